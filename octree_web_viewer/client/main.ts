@@ -31,6 +31,7 @@ class App {
     private octreeIdControl: dat.GUIController;
     private gui: dat.GUI;
     private guiRenderControls: dat.GUI;
+    private guiLabelControls: dat.GUI;
     public octreeId: string;  // octree identifier
     private renderArea: HTMLElement;
 
@@ -84,6 +85,42 @@ class App {
             .onChange(() => {
                 this.needsRender = true;
             });
+        this.guiRenderControls
+            .add(this.viewer, 'compressNodeData')
+            .name('Compress node data');
+    }
+
+    private addLabelControls() {
+        this.viewer.fetchLabelPalette().then((palette) => {
+            if (palette === null || palette.labels.length === 0) {
+                return;
+            }
+            const reload = () => {
+                this.viewer.labelFilterChanged();
+                // Force a reload of the visible nodes.
+                this.lastFrustumUpdateTime = 0;
+                this.needsRender = true;
+            };
+            this.guiLabelControls = this.gui.addFolder(`Labels (${palette.attribute})`);
+            this.guiLabelControls
+                .add(this.viewer, 'colorByLabel')
+                .name('Color by label')
+                .onChange(reload);
+            for (const label of palette.labels) {
+                const control = { visible: true };
+                this.guiLabelControls
+                    .add(control, 'visible')
+                    .name(label.name)
+                    .onChange((visible: boolean) => {
+                        if (visible) {
+                            this.viewer.hiddenLabels.delete(label.id);
+                        } else {
+                            this.viewer.hiddenLabels.add(label.id);
+                        }
+                        reload();
+                    });
+            }
+        });
     }
 
     private getViewPortSize(): [number, number] {
@@ -140,6 +177,10 @@ class App {
         if (this.guiRenderControls) {
             this.gui.removeFolder(this.guiRenderControls);
         }
+        if (this.guiLabelControls) {
+            this.gui.removeFolder(this.guiLabelControls);
+            this.guiLabelControls = undefined;
+        }
     }
 
     private resetOctree() {
@@ -149,6 +190,7 @@ class App {
         this.initRenderer();
         this.initOctreeViewer(this.octreeId);
         this.addControls();
+        this.addLabelControls();
     }
 
     private setOctreeId = (newOctreeId: string) => {
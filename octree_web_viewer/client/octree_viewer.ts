@@ -15,6 +15,7 @@
 'use strict';
 
 import * as THREE from 'three';
+import { decompress } from 'fzstd';
 
 const KEY_L = 'L'.charCodeAt(0);
 
@@ -78,12 +79,25 @@ class NodeRenderData {
     ) { }
 }
 
+export interface Label {
+    id: number;
+    name: string;
+    color: [number, number, number];
+}
+
+export interface LabelPalette {
+    attribute: string;
+    labels: Label[];
+}
+
 class NodeLoader {
     public load(
         scene: THREE.Scene,
         material: THREE.ShaderMaterial,
         nodes: NodeData[],
-        octreeId: string
+        octreeId: string,
+        labelQuery: string,
+        compressed: boolean
     ): Promise<void> {
         let query: string[] = [];
 
@@ -92,7 +106,8 @@ class NodeLoader {
         }
         const headers = new Headers();
         headers.append('Content-Type', 'application/json; charset=UTF-8');
-        const request = new Request(`/nodes_data/${octreeId}/`, {
+        const compression = compressed ? 'zstd' : 'none';
+        const request = new Request(`/nodes_data/${octreeId}/?${labelQuery}&compression=${compression}`, {
             method: 'POST',
             body: '[' + query.join(',') + ']',
             headers: headers,
@@ -103,6 +118,10 @@ class NodeLoader {
             .fetch(request)
             .then((data) => data.arrayBuffer())
             .then((data) => {
+                if (compressed) {
+                    // The typed arrays below need the decompressed data to start a buffer.
+                    data = decompress(new Uint8Array(data)).slice().buffer as ArrayBuffer;
+                }
                 let view = new DataView(data);
                 let currentEntry = 0;
                 let numBytesRead = 0;
@@ -258,12 +277,19 @@ export class OctreeViewer {
     // material.size. If DAT supports callbacks, we can encapsulate this nicer.
     public material: THREE.ShaderMaterial;
     public maxLevelToDisplay: number;
+    public colorByLabel: boolean = false;
+    // Asks the server to compress the node data, for slow connections.
+    public compressNodeData: boolean = false;
+    // Label ids whose points are not shown.
+    public hiddenLabels: Set<number> = new Set();
 
     private loadedData: { [key: string]: NodeData } = {};
     private nodeLoader: NodeLoader;
     private batches: NodeData[][] = [];
     private currentlyLoading: number;
     private useTransparency: boolean;
+    // Lets the server cancel our outdated visible nodes requests.
+    private viewerId: string = Math.random().toString(36).slice(2);
 
 
     constructor(private scene: THREE.Scene, private onNewNodeData: () => void, private octreeId: string) {
@@ -281,6 +307,10 @@ export class OctreeViewer {
 
         this.nodeLoader = new NodeLoader();
         this.currentlyLoading = 0;
+        // Lets the server forget about us once the page is closed.
+        window.addEventListener('pagehide', () => {
+            navigator.sendBeacon(`/disconnect/${this.viewerId}`);
+        });
     }
 
     public alphaChanged() {
@@ -296,10 +326,32 @@ export class OctreeViewer {
         this.useTransparency = newUseTransparency;
     }
 
+    public fetchLabelPalette(): Promise<LabelPalette | null> {
+        const request = new Request(`/label_palette/${this.octreeId}/`, {
+            method: 'GET',
+            credentials: 'same-origin',
+        });
+        return window.fetch(request).then((data) => data.json());
+    }
+
+    // Drops all loaded nodes, so that they are loaded again with the current label settings
+    // on the next frustum update.
+    public labelFilterChanged() {
+        for (const nodeId of Object.keys(this.loadedData)) {
+            const threePoints = this.loadedData[nodeId].threePoints;
+            if (threePoints !== undefined) {
+                this.scene.remove(threePoints);
+                threePoints.geometry.dispose();
+            }
+        }
+        this.loadedData = {};
+        this.batches = [];
+    }
+
     public frustumChanged(matrix: THREE.Matrix4, width: number, height: number) {
         // ThreeJS is column major.
         const request = new Request(
-            `/visible_nodes/${this.octreeId}/?width=${width}&height=${height}&matrix=${matrixToString(
+            `/visible_nodes/${this.octreeId}/?width=${width}&height=${height}&viewer=${this.viewerId}&${this.labelQuery()}&matrix=${matrixToString(
                 matrix
             )}`,
             {
@@ -310,9 +362,17 @@ export class OctreeViewer {
 
         window
             .fetch(request)
-            .then((data) => data.json())
+            .then((data) => {
+                // No content means that a newer request superseded this one.
+                if (data.status === 204) {
+                    return undefined;
+                }
+                return data.json();
+            })
             .then((nodes: any) => {
-                this.nodesUpdate(nodes);
+                if (nodes !== undefined) {
+                    this.nodesUpdate(nodes);
+                }
             });
     }
 
@@ -359,7 +419,14 @@ export class OctreeViewer {
         }
         this.currentlyLoading += 1;
         this.nodeLoader
-            .load(this.scene, this.material, this.batches.shift(), this.octreeId)
+            .load(
+                this.scene,
+                this.material,
+                this.batches.shift(),
+                this.octreeId,
+                this.labelQuery(),
+                this.compressNodeData
+            )
             .then(() => {
                 this.currentlyLoading -= 1;
                 this.onNewNodeData();
@@ -367,6 +434,11 @@ export class OctreeViewer {
             });
     }
 
+    private labelQuery(): string {
+        const hiddenLabels = Array.from(this.hiddenLabels).join(',');
+        return `color_by_label=${this.colorByLabel}&hidden_labels=${hiddenLabels}`;
+    }
+
     private getOrCreate(nodeName: string): NodeData {
         if (this.loadedData[nodeName] === undefined) {
             this.loadedData[nodeName] = new NodeData(nodeName);
@@ -3,24 +3,39 @@ use crate::state::AppState;
 use actix_web::{dev::BodyEncoding, http::ContentEncoding, web, HttpResponse};
 use byteorder::{LittleEndian, WriteBytesExt};
 use nalgebra::Matrix4;
-use point_viewer::octree::{self, Octree};
+use point_viewer::errors::{Error, ErrorKind};
+use point_viewer::labels::LabelPalette;
+use point_viewer::octree::{self, NodeData, Octree};
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
 
 #[derive(Deserialize)]
 pub struct Info {
     matrix: String,
+    /// Identifies the requesting viewer, so that its outdated requests can be cancelled.
+    viewer: Option<String>,
+    /// Comma separated ids of hidden labels. Nodes that only contain those are left out.
+    hidden_labels: Option<String>,
+}
+
+fn parse_label_ids(ids: &Option<String>) -> HashSet<u16> {
+    ids.iter()
+        .flat_map(|ids| ids.split(','))
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
 }
 
 /// Method that returns visible nodes
-pub fn get_visible_nodes(
+pub async fn get_visible_nodes(
     (octree_id, state, matrix_query): (
         web::Path<String>,
         web::Data<Arc<AppState>>,
         web::Query<Info>,
     ),
 ) -> HttpResponse {
-    match get_octree_from_state(&octree_id.into_inner(), &state) {
+    let octree_id = octree_id.into_inner();
+    match get_octree_from_state(&octree_id, &state) {
         Err(err) => HttpResponse::from_error(err.into()),
         Ok(octree) => {
             let matrix = {
@@ -46,7 +61,27 @@ pub fn get_visible_nodes(
                 }
             };
 
-            let visible_nodes = octree.get_visible_nodes(&matrix);
+            let visibility = state.visibility();
+            let query = matrix_query.into_inner();
+            let hidden_labels = parse_label_ids(&query.hidden_labels);
+            let viewer_id = query.viewer;
+            let visible_nodes = match visibility
+                .visible_nodes(octree_id, Arc::clone(&octree), matrix, viewer_id)
+                .await
+            {
+                Ok(Some(visible_nodes)) => visible_nodes,
+                // The viewer has asked for a newer camera in the meantime.
+                Ok(None) => return HttpResponse::NoContent().finish(),
+                Err(err) => return HttpResponse::from_error(err.into()),
+            };
+            let visible_nodes: Vec<octree::NodeId> = visible_nodes
+                .iter()
+                .filter(|id| !octree.all_labels_hidden(id, |label| hidden_labels.contains(&label)))
+                .copied()
+                .collect();
+            // The viewer requests the data of these nodes next, which a remote octree can
+            // already fetch meanwhile.
+            octree.prefetch_node_data(&visible_nodes, octree::ColorSource::Color);
             let mut reply = String::from("[");
             let visible_nodes_string = visible_nodes
                 .iter()
@@ -86,15 +121,196 @@ fn get_octree_from_state(
     })
 }
 
+/// Returns the label palette of the octree as JSON, or null if it has none.
+pub async fn get_label_palette(
+    (octree_id, state): (web::Path<String>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    let octree = match get_octree_from_state(&octree_id.into_inner(), &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let reply = match octree.label_palette() {
+        None => json::JsonValue::Null,
+        Some(palette) => {
+            let mut labels = json::JsonValue::new_array();
+            for (id, label) in &palette.labels {
+                let mut entry = json::JsonValue::new_object();
+                entry["id"] = (*id).into();
+                entry["name"] = label.name.as_str().into();
+                entry["color"] = vec![label.color.red, label.color.green, label.color.blue].into();
+                labels.push(entry).unwrap();
+            }
+            let mut reply = json::JsonValue::new_object();
+            reply["attribute"] = palette.attribute.as_str().into();
+            reply["labels"] = labels;
+            reply
+        }
+    };
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(reply.dump())
+}
+
+/// Forgets a viewer that was closed, see VisibilityService::disconnect.
+pub async fn disconnect(
+    (viewer_id, state): (web::Path<String>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    state.visibility().disconnect(&viewer_id.into_inner());
+    HttpResponse::Ok().finish()
+}
+
+/// Checks the loaded octrees for updates and forgets removed ones, see AppState::reload. Returns
+/// the ids of the updated, unchanged and removed octrees as JSON.
+pub async fn reload(state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let summary = state.reload();
+    let mut reply = json::JsonValue::new_object();
+    reply["updated"] = summary.updated.into();
+    reply["unchanged"] = summary.unchanged.into();
+    reply["removed"] = summary.removed.into();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(reply.dump())
+}
+
+/// Returns the preview images of the octree as a JSON array of objects with the view, the size
+/// and the URL of each.
+pub async fn get_thumbnails(
+    (octree_id, state): (web::Path<String>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    let octree_id = octree_id.into_inner();
+    let octree = match get_octree_from_state(&octree_id, &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let mut reply = json::JsonValue::new_array();
+    for thumbnail in octree.thumbnails() {
+        let mut entry = json::JsonValue::new_object();
+        entry["view"] = thumbnail.view.as_str().into();
+        entry["width"] = thumbnail.width.into();
+        entry["height"] = thumbnail.height.into();
+        entry["url"] = format!("/thumbnail/{}/{}", octree_id, thumbnail.view).into();
+        reply.push(entry).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(reply.dump())
+}
+
+/// Returns the PNG file of one preview image of the octree.
+pub async fn get_thumbnail(
+    (path, state): (web::Path<(String, String)>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    let (octree_id, view) = path.into_inner();
+    let octree = match get_octree_from_state(&octree_id, &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    match octree.read_thumbnail(&view) {
+        Ok(png) => HttpResponse::Ok().content_type("image/png").body(png),
+        Err(Error(ErrorKind::InvalidInput(_), _)) | Err(Error(ErrorKind::NodeNotFound, _)) => {
+            HttpResponse::from_error(
+                PointsViewerError::NotFound(format!(
+                    "Octree {} has no '{}' thumbnail.",
+                    octree_id, view
+                ))
+                .into(),
+            )
+        }
+        // The thumbnail exists, but could not be read.
+        Err(err) => {
+            HttpResponse::from_error(PointsViewerError::InternalServerError(err.to_string()).into())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LabelFilter {
+    /// Replaces the point colors with the label colors of the palette.
+    color_by_label: Option<bool>,
+    /// Comma separated ids of the labels whose points are dropped.
+    hidden_labels: Option<String>,
+}
+
+impl LabelFilter {
+    fn hidden_labels(&self) -> HashSet<u16> {
+        parse_label_ids(&self.hidden_labels)
+    }
+}
+
+/// The zstd level of compressed node data. Higher levels barely shrink positions and colors
+/// further, but take much longer.
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Deserialize)]
+pub struct NodesDataCompression {
+    /// "zstd" compresses the whole reply, for viewers on slow connections. Without it, the reply
+    /// is uncompressed.
+    compression: Option<String>,
+}
+
+/// Recolors and filters the points of 'node_data' by their labels. Nodes without labels are
+/// left untouched.
+fn apply_label_filter(
+    node_data: &mut NodeData,
+    palette: &LabelPalette,
+    color_by_label: bool,
+    hidden_labels: &HashSet<u16>,
+) {
+    let labels = match node_data.labels.take() {
+        Some(labels) => labels,
+        None => return,
+    };
+    let bytes_per_position = node_data.meta.position_encoding.bytes_per_coordinate() * 3;
+    let old_color = node_data.colors(None);
+    let mut position = Vec::with_capacity(node_data.position.len());
+    let mut color = Vec::with_capacity(old_color.len());
+    for (i, label) in labels.iter().enumerate() {
+        if hidden_labels.contains(label) {
+            continue;
+        }
+        position.extend_from_slice(
+            &node_data.position[i * bytes_per_position..(i + 1) * bytes_per_position],
+        );
+        if color_by_label {
+            let label_color = palette.color(*label);
+            color.extend_from_slice(&[label_color.red, label_color.green, label_color.blue]);
+        } else {
+            color.extend_from_slice(&old_color[i * 3..(i + 1) * 3]);
+        }
+    }
+    drop(old_color);
+    node_data.meta.num_points = (color.len() / 3) as i64;
+    node_data.position = position;
+    node_data.color = Some(color);
+}
+
+/// The extractors of 'get_nodes_data': the octree, the ids of the requested nodes and the query.
+type NodesDataRequest = (
+    web::Path<String>,
+    web::Data<Arc<AppState>>,
+    web::Json<Vec<String>>,
+    web::Query<LabelFilter>,
+    web::Query<NodesDataCompression>,
+);
+
 /// Asynchronous Handler to get Node Data
 pub async fn get_nodes_data(
-    (octree_id, state, nodes): (
-        web::Path<String>,
-        web::Data<Arc<AppState>>,
-        web::Json<Vec<String>>,
-    ),
+    (octree_id, state, nodes, label_filter, compression): NodesDataRequest,
 ) -> HttpResponse {
     let start = time::Instant::now();
+    let compress = match compression.compression.as_deref() {
+        None | Some("none") => false,
+        Some("zstd") => true,
+        Some(other) => {
+            return HttpResponse::from_error(
+                PointsViewerError::BadRequest(format!(
+                    "Unsupported compression '{}', expected 'zstd' or 'none'.",
+                    other
+                ))
+                .into(),
+            );
+        }
+    };
     let data: Vec<String> = web::Json::into_inner(nodes);
     let nodes_to_load = data
         .into_iter()
@@ -113,6 +329,10 @@ pub async fn get_nodes_data(
     let mut num_points = 0;
     let octree: Arc<octree::Octree> =
         get_octree_from_state(&octree_id.into_inner(), &state).unwrap();
+    let color_by_label = label_filter.color_by_label.unwrap_or(false);
+    let hidden_labels = label_filter.hidden_labels();
+    // Colorless point clouds are shaded by intensity, within the same range for all nodes.
+    let intensity_range = octree.attribute_range("intensity");
     for node_id in nodes_to_load {
         let mut node_data = match octree.get_node_data(&node_id) {
             Ok(node_data) => node_data,
@@ -126,6 +346,12 @@ pub async fn get_nodes_data(
                 );
             }
         };
+        if node_data.color.is_none() {
+            node_data.color = Some(node_data.colors(intensity_range).into_owned());
+        }
+        if let Some(palette) = octree.label_palette() {
+            apply_label_filter(&mut node_data, palette, color_by_label, &hidden_labels);
+        }
 
         // Write the bounding box information.
         let min = node_data.meta.bounding_cube.min();
@@ -148,23 +374,40 @@ pub async fn get_nodes_data(
             bytes_per_coordinate * node_data.meta.num_points as usize * 3
                 == node_data.position.len()
         );
-        assert!(node_data.meta.num_points as usize * 3 == node_data.color.len());
+        let mut color = node_data.color.take().unwrap();
+        assert!(node_data.meta.num_points as usize * 3 == color.len());
         pad(&mut reply_blob);
 
         reply_blob.append(&mut node_data.position);
         pad(&mut reply_blob);
 
-        reply_blob.append(&mut node_data.color);
+        reply_blob.append(&mut color);
         pad(&mut reply_blob);
 
         num_nodes_fetched += 1;
         num_points += node_data.meta.num_points;
     }
 
+    let num_bytes = reply_blob.len();
+    if compress {
+        reply_blob = match zstd::encode_all(&reply_blob[..], ZSTD_LEVEL) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                return HttpResponse::from_error(
+                    PointsViewerError::InternalServerError(e.to_string()).into(),
+                )
+            }
+        };
+    }
+
     let duration_ms = start.elapsed().as_seconds_f64() * 1_000.;
     eprintln!(
-        "Got {} nodes with {} points ({}ms).",
-        num_nodes_fetched, num_points, duration_ms
+        "Got {} nodes with {} points ({} bytes, {} sent, {}ms).",
+        num_nodes_fetched,
+        num_points,
+        num_bytes,
+        reply_blob.len(),
+        duration_ms
     );
 
     HttpResponse::Ok()
@@ -61,9 +61,8 @@ fn main() {
 
     // initialize app state
     let app_state: Arc<AppState> = Arc::new(state_from(args).unwrap());
-    // The actix-web framework handles requests asynchronously using actors. If we need multi-threaded
-    // write access to the Octree, instead of using an RwLock we should use the actor system.
-    // put octree arc in cache
+    // Octrees never change after they are loaded, so the request handlers share them through an
+    // Arc without locking. Updated octrees are swapped in as new snapshots, see AppState.
 
     let sys = actix::System::new("octree-server");
     let _ = start_octree_server(app_state, &ip_port);
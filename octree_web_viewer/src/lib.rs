@@ -6,3 +6,4 @@ pub mod backend;
 pub mod backend_error;
 pub mod state;
 pub mod utils;
+pub mod visibility;
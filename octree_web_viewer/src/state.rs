@@ -1,9 +1,14 @@
 use crate::backend_error::PointsViewerError;
+use crate::visibility::VisibilityService;
 use point_viewer::data_provider;
 use point_viewer::octree;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// How often the meta of a loaded octree is read again to find out whether it was updated.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 /// path information for the octrees
 #[derive(Clone)]
@@ -24,15 +29,35 @@ impl OctreeKeyParams {
     }
 }
 
+/// The latest snapshot of an octree. Requests keep the snapshot they started with, so they see
+/// a consistent octree even if a newer one is swapped in meanwhile.
+struct LoadedOctree {
+    octree: Arc<octree::Octree>,
+    last_checked: Instant,
+}
+
+/// What 'AppState::reload' did with the octrees that were loaded.
+#[derive(Debug, Default)]
+pub struct ReloadSummary {
+    /// Octrees whose meta was replaced, which were swapped for the new snapshot.
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// Octrees that could not be read anymore, e.g. since they were deleted.
+    pub removed: Vec<String>,
+}
+
 #[derive(Clone)]
 pub struct AppState {
-    /// Hash Map for Octrees
-    octree_map: Arc<RwLock<HashMap<String, Arc<octree::Octree>>>>,
+    /// Hash Map for Octrees. The lock is only held to look up or swap in a snapshot, never while
+    /// nodes are read.
+    octree_map: Arc<RwLock<HashMap<String, LoadedOctree>>>,
     /// information for retieving octree path
     key_params: OctreeKeyParams,
     /// backward compatibility to input arguments
     init_octree_id: String,
     data_provider_factory: data_provider::DataProviderFactory,
+    /// shared visible nodes computation
+    visibility: Arc<VisibilityService>,
 }
 
 impl AppState {
@@ -51,6 +76,7 @@ impl AppState {
             },
             init_octree_id: octree_id.into(),
             data_provider_factory,
+            visibility: Arc::new(VisibilityService::new()),
         }
     }
 
@@ -66,11 +92,13 @@ impl AppState {
             let map = self.octree_map.read().unwrap();
             let octree = map.get(octree_key);
             //some found
-            if let Some(tree) = octree {
-                return Ok(Arc::clone(&tree));
+            if let Some(loaded) = octree {
+                if loaded.last_checked.elapsed() < RELOAD_CHECK_INTERVAL {
+                    return Ok(Arc::clone(&loaded.octree));
+                }
             }
         }
-        // none found
+        // none found or due for a check whether it was updated
         self.insert_octree(octree_key.to_string())
     }
 
@@ -80,19 +108,66 @@ impl AppState {
     ) -> Result<Arc<octree::Octree>, PointsViewerError> {
         let octree_key = octree_id.into();
         let addr = &self.key_params.get_octree_address(&octree_key);
-        let octree: Arc<octree::Octree> = Arc::from(octree::Octree::from_data_provider(
-            self.data_provider_factory
-                .generate_data_provider(addr.to_string_lossy())?,
-        )?);
+        let data_provider = self
+            .data_provider_factory
+            .generate_data_provider(addr.to_string_lossy())?;
+        let current = self
+            .octree_map
+            .read()
+            .unwrap()
+            .get(&octree_key)
+            .map(|loaded| Arc::clone(&loaded.octree));
+        // Only build a new octree if the meta was replaced since the current one was read.
+        let octree = match current {
+            Some(current) if data_provider.meta_proto()?.generation == current.generation() => {
+                current
+            }
+            _ => Arc::new(octree::Octree::from_data_provider(data_provider)?),
+        };
         {
             // write access to state
             let mut wmap = self.octree_map.write().unwrap();
-            wmap.insert(octree_key, Arc::clone(&octree));
+            wmap.insert(
+                octree_key,
+                LoadedOctree {
+                    octree: Arc::clone(&octree),
+                    last_checked: Instant::now(),
+                },
+            );
         }
         Ok(octree)
     }
 
+    /// Checks all loaded octrees for updates right away instead of when they are next requested
+    /// after the reload check interval, and forgets the ones that cannot be read anymore.
+    /// Octrees that were not loaded yet are read when they are first requested anyway.
+    pub fn reload(&self) -> ReloadSummary {
+        let loaded: Vec<(String, u64)> = self
+            .octree_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(octree_id, loaded)| (octree_id.clone(), loaded.octree.generation()))
+            .collect();
+        let mut summary = ReloadSummary::default();
+        for (octree_id, generation) in loaded {
+            match self.insert_octree(octree_id.clone()) {
+                Ok(octree) if octree.generation() != generation => summary.updated.push(octree_id),
+                Ok(_) => summary.unchanged.push(octree_id),
+                Err(_) => {
+                    self.octree_map.write().unwrap().remove(&octree_id);
+                    summary.removed.push(octree_id);
+                }
+            }
+        }
+        summary
+    }
+
     pub fn get_init_id(&self) -> String {
         self.init_octree_id.clone()
     }
+
+    pub fn visibility(&self) -> Arc<VisibilityService> {
+        Arc::clone(&self.visibility)
+    }
 }
@@ -1,4 +1,7 @@
-use crate::backend::{get_nodes_data, get_visible_nodes};
+use crate::backend::{
+    disconnect, get_label_palette, get_nodes_data, get_thumbnail, get_thumbnails,
+    get_visible_nodes, reload,
+};
 use crate::backend_error::PointsViewerError;
 use crate::state::AppState;
 use actix_web::{web, HttpResponse, HttpServer};
@@ -48,6 +51,11 @@ pub fn start_octree_server(
             .service(web::resource("/init_tree").to(get_init_tree))
             .service(web::resource("/visible_nodes/{octree_id}/").to(get_visible_nodes))
             .service(web::resource("/nodes_data/{octree_id}/").to(get_nodes_data))
+            .service(web::resource("/label_palette/{octree_id}/").to(get_label_palette))
+            .service(web::resource("/thumbnails/{octree_id}/").to(get_thumbnails))
+            .service(web::resource("/thumbnail/{octree_id}/{view}").to(get_thumbnail))
+            .service(web::resource("/reload").route(web::post().to(reload)))
+            .service(web::resource("/disconnect/{viewer}").route(web::post().to(disconnect)))
     })
     .bind(&ip_port)
     .unwrap_or_else(|_| panic!("Can not bind to {}", &ip_port))
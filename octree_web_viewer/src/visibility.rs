@@ -0,0 +1,220 @@
+//! Computes visible nodes on a worker thread, so that requests from many viewers share work.
+//!
+//! Requests for the same octree and (quantized) camera matrix are coalesced: only one of them
+//! traverses the octree, the others wait for its result. Results are cached for a short time,
+//! since viewers that stand still or follow the same path keep asking for the same nodes. A
+//! request that carries a viewer id is cancelled as soon as the same viewer asks for a newer
+//! camera, unless someone else is waiting for its result. Viewers are forgotten when they
+//! disconnect or have not asked for anything for a while.
+
+use crate::backend_error::PointsViewerError;
+use actix_web::web;
+use nalgebra::Matrix4;
+use point_viewer::geometry::CachedFrustumIntersector;
+use point_viewer::octree::{NodeId, Octree};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Matrix entries are rounded to this resolution before they are used as cache keys.
+const MATRIX_QUANTIZATION: f64 = 1e-4;
+const CACHE_TTL: Duration = Duration::from_secs(2);
+const MAX_CACHE_ENTRIES: usize = 1024;
+/// Viewers that did not request anything for this long are considered to be disconnected.
+const VIEWER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+type VisibleNodes = Arc<Vec<NodeId>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    octree_id: String,
+    matrix: [i64; 16],
+}
+
+impl Key {
+    fn new(octree_id: &str, matrix: &Matrix4<f64>) -> Self {
+        let mut quantized = [0; 16];
+        for (q, e) in quantized.iter_mut().zip(matrix.iter()) {
+            *q = (e / MATRIX_QUANTIZATION).round() as i64;
+        }
+        Key {
+            octree_id: octree_id.to_string(),
+            matrix: quantized,
+        }
+    }
+}
+
+/// A computation in flight. 'result' is set once it finished, to None if it was cancelled.
+#[derive(Default)]
+struct Pending {
+    result: Mutex<Option<Option<VisibleNodes>>>,
+    finished: Condvar,
+    num_waiting: AtomicUsize,
+}
+
+enum Entry {
+    Pending(Arc<Pending>),
+    Done(VisibleNodes, Instant),
+}
+
+struct Viewer {
+    // The latest request generation, used to detect superseded requests.
+    generation: Arc<AtomicU64>,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+pub struct VisibilityService {
+    entries: Mutex<HashMap<Key, Entry>>,
+    viewers: Mutex<HashMap<String, Viewer>>,
+}
+
+impl VisibilityService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the nodes of 'octree' visible through 'matrix', or None if the request was
+    /// superseded by a newer one from the same 'viewer_id' before it finished.
+    pub async fn visible_nodes(
+        self: Arc<Self>,
+        octree_id: String,
+        octree: Arc<Octree>,
+        matrix: Matrix4<f64>,
+        viewer_id: Option<String>,
+    ) -> Result<Option<VisibleNodes>, PointsViewerError> {
+        let frustum = CachedFrustumIntersector::new(matrix).ok_or_else(|| {
+            PointsViewerError::BadRequest("Matrix is not invertible.".to_string())
+        })?;
+        let cancellation = viewer_id.map(|id| self.next_generation(id));
+        web::block(move || -> Result<_, ()> {
+            Ok(self.compute(
+                &Key::new(&octree_id, &matrix),
+                &octree,
+                &frustum,
+                cancellation,
+            ))
+        })
+        .await
+        .map_err(|_| {
+            PointsViewerError::InternalServerError("Visibility computation failed.".to_string())
+        })
+    }
+
+    fn next_generation(&self, viewer_id: String) -> (Arc<AtomicU64>, u64) {
+        let mut viewers = self.viewers.lock().unwrap();
+        viewers.retain(|_, viewer| viewer.last_seen.elapsed() < VIEWER_TIMEOUT);
+        let viewer = viewers.entry(viewer_id).or_insert_with(|| Viewer {
+            generation: Arc::default(),
+            last_seen: Instant::now(),
+        });
+        viewer.last_seen = Instant::now();
+        let generation = viewer.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        (Arc::clone(&viewer.generation), generation)
+    }
+
+    /// Forgets the viewer with 'viewer_id' and cancels its request in flight, if any.
+    pub fn disconnect(&self, viewer_id: &str) {
+        if let Some(viewer) = self.viewers.lock().unwrap().remove(viewer_id) {
+            viewer.generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn compute(
+        &self,
+        key: &Key,
+        octree: &Octree,
+        frustum: &CachedFrustumIntersector,
+        cancellation: Option<(Arc<AtomicU64>, u64)>,
+    ) -> Option<VisibleNodes> {
+        let is_superseded = || match &cancellation {
+            Some((latest, generation)) => latest.load(Ordering::SeqCst) != *generation,
+            None => false,
+        };
+        loop {
+            let pending = {
+                let mut entries = self.entries.lock().unwrap();
+                match entries.get(key) {
+                    Some(Entry::Done(nodes, created)) if created.elapsed() < CACHE_TTL => {
+                        return Some(Arc::clone(nodes));
+                    }
+                    Some(Entry::Pending(pending)) => {
+                        pending.num_waiting.fetch_add(1, Ordering::SeqCst);
+                        Err(Arc::clone(pending))
+                    }
+                    _ => {
+                        let pending = Arc::new(Pending::default());
+                        entries.insert(key.clone(), Entry::Pending(Arc::clone(&pending)));
+                        Ok(pending)
+                    }
+                }
+            };
+
+            match pending {
+                // Someone else computes this already, wait for their result.
+                Err(pending) => {
+                    let mut result = pending.result.lock().unwrap();
+                    while result.is_none() {
+                        result = pending.finished.wait(result).unwrap();
+                    }
+                    pending.num_waiting.fetch_sub(1, Ordering::SeqCst);
+                    match result.as_ref().unwrap() {
+                        Some(nodes) => return Some(Arc::clone(nodes)),
+                        // The computation was cancelled before we joined, so we try again.
+                        None if is_superseded() => return None,
+                        None => continue,
+                    }
+                }
+                Ok(pending) => {
+                    let nodes = octree
+                        .get_visible_nodes_cancellable(frustum, || {
+                            pending.num_waiting.load(Ordering::SeqCst) == 0 && is_superseded()
+                        })
+                        .map(Arc::new);
+                    {
+                        let mut entries = self.entries.lock().unwrap();
+                        match &nodes {
+                            Some(nodes) => {
+                                if entries.len() >= MAX_CACHE_ENTRIES {
+                                    entries.retain(|_, entry| match entry {
+                                        Entry::Pending(_) => true,
+                                        Entry::Done(_, created) => created.elapsed() < CACHE_TTL,
+                                    });
+                                }
+                                entries.insert(
+                                    key.clone(),
+                                    Entry::Done(Arc::clone(nodes), Instant::now()),
+                                );
+                            }
+                            None => {
+                                entries.remove(key);
+                            }
+                        }
+                    }
+                    *pending.result.lock().unwrap() = Some(nodes.clone());
+                    pending.finished.notify_all();
+                    return nodes;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_disconnected_viewers() {
+        let service = VisibilityService::new();
+        let (generation, first) = service.next_generation("a".to_string());
+        service.next_generation("b".to_string());
+        assert_eq!(service.viewers.lock().unwrap().len(), 2);
+
+        service.disconnect("a");
+        assert_eq!(service.viewers.lock().unwrap().len(), 1);
+        // A request of a disconnected viewer counts as superseded.
+        assert_ne!(generation.load(Ordering::SeqCst), first);
+    }
+}
@@ -0,0 +1,184 @@
+use clap::Clap;
+use nalgebra::{Matrix4, Point3, Vector3};
+use point_cloud_client::{PointCloudClient, PointCloudClientBuilder};
+use point_viewer::errors::*;
+use point_viewer::geometry::{Aabb, Frustum};
+use point_viewer::iterator::{PointLocation, PointQuery};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Runs a mix of queries against point clouds at any location the data providers support and
+/// reports their latencies and throughput, to compare deployments with the real data.
+#[derive(Clap)]
+#[clap(name = "point_cloud_bench")]
+struct CommandlineArguments {
+    /// The locations of the point clouds.
+    #[clap(parse(from_str), required = true)]
+    locations: Vec<String>,
+
+    /// The number of random bounding boxes to query per size.
+    #[clap(long, default_value = "20")]
+    num_queries: usize,
+
+    /// The edge lengths in meters of the random bounding boxes, which are cubes centered at a
+    /// random point within the bounding box of the point clouds.
+    #[clap(long, default_value = "10,100,1000", use_delimiter = true)]
+    aabb_sizes: Vec<f64>,
+
+    /// A file with a recorded camera path to query the frustums of: one frustum per line, given
+    /// as the 16 comma separated entries of its clip_from_world matrix in column-major order,
+    /// like the web viewer sends them.
+    #[clap(long)]
+    camera_path: Option<String>,
+
+    /// The attributes to query.
+    #[clap(long, default_value = "color", use_delimiter = true)]
+    attributes: Vec<String>,
+
+    /// The seed of the random bounding boxes, so that runs can be compared.
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// The maximum number of threads to be running.
+    #[clap(long, default_value = "30")]
+    num_threads: usize,
+
+    /// The maximum number of points sent through batch.
+    #[clap(long, default_value = "500000")]
+    batch_size: usize,
+}
+
+/// The latency and the number of points of each query of one kind.
+#[derive(Default)]
+struct Measurements {
+    latencies: Vec<Duration>,
+    num_points: usize,
+}
+
+impl Measurements {
+    fn add(&mut self, latency: Duration, num_points: usize) {
+        self.latencies.push(latency);
+        self.num_points += num_points;
+    }
+
+    fn percentile(&self, percentile: f64) -> Duration {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let index = (percentile / 100. * (latencies.len() - 1) as f64).round() as usize;
+        latencies[index]
+    }
+
+    fn report(&self, kind: &str) {
+        if self.latencies.is_empty() {
+            return;
+        }
+        let total: Duration = self.latencies.iter().sum();
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.;
+        println!(
+            "{}: {} queries, latency p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms, \
+             {:.0} points/s, {:.2} queries/s",
+            kind,
+            self.latencies.len(),
+            millis(self.percentile(50.)),
+            millis(self.percentile(90.)),
+            millis(self.percentile(99.)),
+            millis(self.percentile(100.)),
+            self.num_points as f64 / total.as_secs_f64(),
+            self.latencies.len() as f64 / total.as_secs_f64(),
+        );
+    }
+}
+
+fn read_camera_path(path: &str) -> Result<Vec<Frustum>> {
+    let content = fs::read_to_string(path).chain_err(|| format!("Could not read {}", path))?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            let invalid = || -> Error {
+                ErrorKind::InvalidInput(format!(
+                    "Line {} of {} is not an invertible 4x4 matrix.",
+                    index + 1,
+                    path
+                ))
+                .into()
+            };
+            let entries = line
+                .split(',')
+                .map(|entry| entry.trim().parse::<f64>())
+                .collect::<std::result::Result<Vec<f64>, _>>()
+                .map_err(|_| invalid())?;
+            if entries.len() != 16 {
+                return Err(invalid());
+            }
+            Frustum::from_matrix4(Matrix4::from_column_slice(&entries)).ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn random_aabb(bounding_box: &Aabb, size: f64, rng: &mut impl Rng) -> Aabb {
+    let (min, max) = (bounding_box.min(), bounding_box.max());
+    let center = Point3::from(Vector3::from_fn(|axis, _| {
+        min[axis] + rng.gen::<f64>() * (max[axis] - min[axis])
+    }));
+    let half_size = Vector3::repeat(size / 2.);
+    Aabb::new(center - half_size, center + half_size)
+}
+
+fn run_query(client: &PointCloudClient, query: &PointQuery) -> Result<(Duration, usize)> {
+    let start = Instant::now();
+    let mut num_points = 0;
+    client.for_each_point_data(query, |batch| {
+        num_points += batch.position.len();
+        Ok(())
+    })?;
+    Ok((start.elapsed(), num_points))
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let client = PointCloudClientBuilder::new(&args.locations)
+        .num_threads(args.num_threads)
+        .num_points_per_batch(args.batch_size)
+        .build()?;
+    let attributes: Vec<&str> = args.attributes.iter().map(String::as_str).collect();
+
+    let mut queries: Vec<(String, PointLocation)> = Vec::new();
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    for size in &args.aabb_sizes {
+        for _ in 0..args.num_queries {
+            let aabb = random_aabb(client.bounding_box(), *size, &mut rng);
+            queries.push((format!("aabb {} m", size), PointLocation::Aabb(aabb)));
+        }
+    }
+    if let Some(camera_path) = &args.camera_path {
+        for frustum in read_camera_path(camera_path)? {
+            queries.push(("camera path".to_string(), PointLocation::Frustum(frustum)));
+        }
+    }
+
+    let mut measurements: BTreeMap<String, Measurements> = BTreeMap::new();
+    let mut all = Measurements::default();
+    for (kind, location) in queries {
+        let query = PointQuery {
+            attributes: attributes.clone(),
+            location,
+            ..Default::default()
+        };
+        let (latency, num_points) = run_query(&client, &query)?;
+        measurements
+            .entry(kind)
+            .or_default()
+            .add(latency, num_points);
+        all.add(latency, num_points);
+    }
+    for (kind, measurements) in &measurements {
+        measurements.report(kind);
+    }
+    all.report("all");
+    Ok(())
+}
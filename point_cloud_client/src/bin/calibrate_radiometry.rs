@@ -0,0 +1,46 @@
+use clap::Clap;
+use point_cloud_client::PointCloudClientBuilder;
+use point_viewer::calibration::write_radiometric_correction;
+use point_viewer::errors::Result;
+
+/// Evens out the colors and intensities of overlapping octrees, e.g. scans of the same area that
+/// were captured in different lighting. Stores a correction in the meta of each octree that is
+/// applied when its points are read.
+#[derive(Clap)]
+#[clap(name = "calibrate_radiometry")]
+struct CommandlineArguments {
+    /// The directories of the octrees.
+    #[clap(parse(from_str), required = true)]
+    locations: Vec<String>,
+
+    /// The attributes to calibrate, "color" and/or "intensity".
+    #[clap(long, default_value = "color")]
+    attributes: Vec<String>,
+
+    /// The edge length of the cubes in which the points of the octrees are compared.
+    #[clap(long, default_value = "0.5")]
+    voxel_size: f64,
+
+    /// Only print the corrections instead of storing them.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let point_cloud_client = PointCloudClientBuilder::new(&args.locations).build()?;
+    let attributes: Vec<&str> = args.attributes.iter().map(String::as_str).collect();
+    let corrections =
+        point_cloud_client.compute_radiometric_corrections(&attributes, args.voxel_size)?;
+    for (location, correction) in args.locations.iter().zip(&corrections) {
+        let offset = correction.color_offset;
+        println!(
+            "{}: color offset ({:.1}, {:.1}, {:.1}), intensity offset {:.3}",
+            location, offset.x, offset.y, offset.z, correction.intensity_offset
+        );
+        if !args.dry_run {
+            write_radiometric_correction(location, correction)?;
+        }
+    }
+    Ok(())
+}
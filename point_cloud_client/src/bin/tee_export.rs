@@ -0,0 +1,106 @@
+use clap::Clap;
+use nalgebra::Point3;
+use point_cloud_client::tee::{BatchSink, DensitySink, LasSink, PlySink, SummarySink};
+use point_cloud_client::PointCloudClientBuilder;
+use point_viewer::errors::{ErrorKind, Result};
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{PointLocation, PointQuery};
+use std::path::PathBuf;
+
+fn point3f64_from_str(s: &str) -> std::result::Result<Point3<f64>, &'static str> {
+    let coords: std::result::Result<Vec<f64>, &'static str> = s
+        .split(|c| c == ' ' || c == ',' || c == ';')
+        .map(|s| s.parse::<f64>().map_err(|_| "Could not parse point."))
+        .collect();
+    let coords = coords?;
+    if coords.len() != 3 {
+        return Err("Wrong number of coordinates.");
+    }
+    Ok(Point3::new(coords[0], coords[1], coords[2]))
+}
+
+/// Exports the points in a bounding box to several outputs while reading them only once.
+#[derive(Clap)]
+#[clap(name = "tee_export")]
+struct CommandlineArguments {
+    /// The locations of the point clouds.
+    #[clap(parse(from_str), required = true)]
+    locations: Vec<String>,
+
+    /// The minimum of the bounding box to export.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    min: Point3<f64>,
+
+    /// The maximum of the bounding box to export.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    max: Point3<f64>,
+
+    /// The attributes to export.
+    #[clap(long, default_value = "color")]
+    attributes: Vec<String>,
+
+    /// Write the points to this PLY file.
+    #[clap(long, parse(from_os_str))]
+    ply: Option<PathBuf>,
+
+    /// Write the points with their colors, intensities and classes to this LAS file, compressed
+    /// if it ends in ".laz".
+    #[clap(long, parse(from_os_str))]
+    las: Option<PathBuf>,
+
+    /// Write the count, minimum, maximum and mean of the coordinates and attributes to this CSV
+    /// file.
+    #[clap(long, parse(from_os_str))]
+    summary: Option<PathBuf>,
+
+    /// Write the number of points per cell of the x-y plane to this 16 bit PNG file.
+    #[clap(long, parse(from_os_str))]
+    density: Option<PathBuf>,
+
+    /// The edge length of the cells of the density raster in meters.
+    #[clap(long, default_value = "1.0")]
+    density_resolution: f64,
+
+    /// The number of batches a slow output may fall behind before reading pauses.
+    #[clap(long, default_value = "4")]
+    queue_size: usize,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let aabb = Aabb::new(args.min, args.max);
+    let mut sinks: Vec<Box<dyn BatchSink>> = Vec::new();
+    if let Some(path) = &args.ply {
+        sinks.push(Box::new(PlySink::new(path)));
+    }
+    if let Some(path) = &args.las {
+        sinks.push(Box::new(LasSink::new(path)));
+    }
+    if let Some(path) = &args.summary {
+        sinks.push(Box::new(SummarySink::new(path)));
+    }
+    if let Some(path) = &args.density {
+        sinks.push(Box::new(DensitySink::new(
+            path,
+            aabb.clone(),
+            args.density_resolution,
+        )?));
+    }
+    if sinks.is_empty() {
+        return Err(ErrorKind::InvalidInput(
+            "Nothing to export, pass at least one of --ply, --las, --summary and --density."
+                .to_string(),
+        )
+        .into());
+    }
+
+    let point_cloud_client = PointCloudClientBuilder::new(&args.locations)
+        .buffer_size(args.queue_size)
+        .build()?;
+    let query = PointQuery {
+        attributes: args.attributes.iter().map(String::as_str).collect(),
+        location: PointLocation::Aabb(aabb),
+        ..Default::default()
+    };
+    point_cloud_client.export(&query, sinks)
+}
@@ -1,6 +1,7 @@
 use clap::Clap;
 use nalgebra::Point3;
-use point_cloud_client::PointCloudClientBuilder;
+use point_cloud_client::spill::SortKey;
+use point_cloud_client::{MissingAttributePolicy, PointCloudClientBuilder};
 use point_viewer::errors::{ErrorKind, Result};
 use point_viewer::geometry::Aabb;
 use point_viewer::iterator::{PointLocation, PointQuery};
@@ -44,10 +45,43 @@ struct CommandlineArguments {
     )]
     max: Point3<f64>,
 
+    /// Tokens of S2 cells to query instead of the bounding box, e.g. "89c25".
+    #[clap(long)]
+    s2_cells: Vec<String>,
+
+    /// Print how the nodes of each point cloud are found for the query instead of running it.
+    #[clap(long)]
+    explain: bool,
+
+    /// Allow an approximate result from coarser levels of detail, where every skipped point is
+    /// at most this many meters from a returned one.
+    #[clap(long)]
+    max_error: Option<f64>,
+
     /// The maximum number of points to return.
     #[clap(long, default_value = "50000000")]
     num_points: usize,
 
+    /// Draw a uniform random sample of --num-points points instead of streaming all of them.
+    #[clap(long)]
+    sample: bool,
+
+    /// Stream the points sorted by "x", "y", "z" or an attribute.
+    #[clap(long)]
+    sort_by: Option<String>,
+
+    /// Stream only one point per voxel of this many meters.
+    #[clap(long)]
+    unique_resolution: Option<f64>,
+
+    /// The number of megabytes that sorting may keep in memory before spilling points to disk.
+    #[clap(long)]
+    memory_budget_mb: Option<usize>,
+
+    /// What to do with point clouds that lack the queried attributes: "error", "skip" or "fill".
+    #[clap(long, default_value = "error", possible_values = &["error", "skip", "fill"])]
+    missing_attributes: String,
+
     /// The maximum number of threads to be running.
     #[clap(long, default_value = "30")]
     num_threads: usize,
@@ -60,17 +94,55 @@ struct CommandlineArguments {
 fn main() {
     let args = CommandlineArguments::parse();
     let num_points = args.num_points;
-    let point_cloud_client = PointCloudClientBuilder::new(&args.locations)
+    let mut builder = PointCloudClientBuilder::new(&args.locations)
         .num_threads(args.num_threads)
         .num_points_per_batch(args.batch_size)
+        .missing_attributes(match args.missing_attributes.as_str() {
+            "skip" => MissingAttributePolicy::SkipCloud,
+            "fill" => MissingAttributePolicy::FillDefault,
+            _ => MissingAttributePolicy::Error,
+        });
+    if let Some(memory_budget_mb) = args.memory_budget_mb {
+        builder = builder.memory_budget(memory_budget_mb * 1024 * 1024);
+    }
+    let point_cloud_client = builder
         .build()
         .expect("Couldn't create point cloud client.");
 
+    let location = if args.s2_cells.is_empty() {
+        PointLocation::Aabb(Aabb::new(args.min, args.max))
+    } else {
+        PointLocation::from_s2_tokens(&args.s2_cells).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    };
     let point_location = PointQuery {
         attributes: vec!["color", "intensity"],
-        location: PointLocation::Aabb(Aabb::new(args.min, args.max)),
+        location,
+        max_error_m: args.max_error,
         ..Default::default()
     };
+    if args.explain {
+        for (location, plan) in args
+            .locations
+            .iter()
+            .zip(point_cloud_client.explain_query(&point_location))
+        {
+            println!("{}: {}", location, plan);
+        }
+        return;
+    }
+    if args.sample {
+        match point_cloud_client.sample(&point_location, num_points) {
+            Ok(sample) => println!("Sampled {} points", sample.position.len()),
+            Err(e) => {
+                eprintln!("Encountered error:\n{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     let mut point_count: usize = 0;
     let mut print_count: usize = 1;
     let callback_func = |points_batch: PointsBatch| -> Result<()> {
@@ -88,7 +160,24 @@ fn main() {
         }
         Ok(())
     };
-    match point_cloud_client.for_each_point_data(&point_location, callback_func) {
+    let result = match (args.sort_by.as_deref(), args.unique_resolution) {
+        (Some(sort_by), _) => {
+            let sort_key = match sort_by {
+                "x" => SortKey::X,
+                "y" => SortKey::Y,
+                "z" => SortKey::Z,
+                attribute => SortKey::Attribute(attribute.to_string()),
+            };
+            point_cloud_client.for_each_point_data_sorted(&point_location, &sort_key, callback_func)
+        }
+        (None, Some(resolution)) => point_cloud_client.for_each_unique_point_data(
+            &point_location,
+            resolution,
+            callback_func,
+        ),
+        (None, None) => point_cloud_client.for_each_point_data(&point_location, callback_func),
+    };
+    match result {
         Ok(_) => (),
         Err(e) => match e.kind() {
             ErrorKind::Io(ref e) if e.kind() == std::io::ErrorKind::Interrupted => (),
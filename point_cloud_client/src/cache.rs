@@ -0,0 +1,338 @@
+//! An in-memory cache of the decoded points of nodes, for interactive applications that issue
+//! many overlapping queries, e.g. while brushing a region. Nodes are cached with all their points
+//! that match the attributes and filter intervals of a query, so queries that only differ in
+//! their location reuse them.
+//!
+//! A cache can be shared by several clients. Entries belong to the location and generation of a
+//! point cloud, so clients only get the entries of their own generation, and the first client
+//! for a newer generation drops the entries of the older ones.
+
+use lru::LruCache;
+use point_viewer::attributes::{AttributeAliases, AttributeDataType};
+use point_viewer::calibration::RadiometricCorrection;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{
+    retain_in_location, PointCloud, PointLocation, PointQuery, QueryPlan,
+};
+use point_viewer::read_write::{Encoding, NodeIterator};
+use point_viewer::PointsBatch;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The point cloud that cache entries belong to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheScope {
+    pub location: String,
+    /// See Octree::generation.
+    pub generation: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to stay within the capacity.
+    pub evictions: u64,
+    /// Entries dropped because their point cloud changed.
+    pub invalidations: u64,
+    pub num_entries: usize,
+    pub num_points: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    location: String,
+    generation: u64,
+    node_id: String,
+    attributes: Vec<String>,
+    // Sorted by attribute, with the bounds as bits so that the key can be hashed.
+    filter_intervals: Vec<(String, u64, u64)>,
+}
+
+impl CacheKey {
+    fn new(scope: &CacheScope, node_id: String, query: &PointQuery) -> Self {
+        let mut filter_intervals: Vec<(String, u64, u64)> = query
+            .filter_intervals
+            .iter()
+            .map(|(attribute, interval)| {
+                (
+                    attribute.to_string(),
+                    interval.lower_bound().to_bits(),
+                    interval.upper_bound().to_bits(),
+                )
+            })
+            .collect();
+        filter_intervals.sort();
+        CacheKey {
+            location: scope.location.clone(),
+            generation: scope.generation,
+            node_id,
+            attributes: query.attributes.iter().map(|a| a.to_string()).collect(),
+            filter_intervals,
+        }
+    }
+}
+
+struct CacheState {
+    entries: LruCache<CacheKey, Arc<Vec<PointsBatch>>>,
+    generations: HashMap<String, u64>,
+    statistics: CacheStatistics,
+}
+
+pub struct BatchCache {
+    capacity_points: usize,
+    state: Mutex<CacheState>,
+}
+
+impl BatchCache {
+    /// A cache that holds at most about 'capacity_points' points. Nodes with more points than
+    /// that are not cached.
+    pub fn new(capacity_points: usize) -> Self {
+        BatchCache {
+            capacity_points,
+            state: Mutex::new(CacheState {
+                entries: LruCache::unbounded(),
+                generations: HashMap::new(),
+                statistics: CacheStatistics::default(),
+            }),
+        }
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.state.lock().unwrap().statistics
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.statistics.num_entries = 0;
+        state.statistics.num_points = 0;
+    }
+
+    /// Drops the entries of older generations of the point cloud at the location of 'scope', if
+    /// it is newer than all generations seen before. Clients that still use an older generation
+    /// keep working with their own entries.
+    pub(crate) fn enter(&self, scope: &CacheScope) {
+        let mut state = self.state.lock().unwrap();
+        let newest = state
+            .generations
+            .entry(scope.location.clone())
+            .or_insert(scope.generation);
+        if *newest >= scope.generation {
+            return;
+        }
+        *newest = scope.generation;
+        let stale: Vec<CacheKey> = state
+            .entries
+            .iter()
+            .filter(|(key, _)| key.location == scope.location && key.generation < scope.generation)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            if let Some(batches) = state.entries.pop(&key) {
+                state.statistics.invalidations += 1;
+                state.statistics.num_entries -= 1;
+                state.statistics.num_points -= num_points(&batches);
+            }
+        }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Arc<Vec<PointsBatch>>> {
+        let mut state = self.state.lock().unwrap();
+        let batches = state.entries.get(key).cloned();
+        match batches {
+            Some(_) => state.statistics.hits += 1,
+            None => state.statistics.misses += 1,
+        }
+        batches
+    }
+
+    fn insert(&self, key: CacheKey, batches: Arc<Vec<PointsBatch>>) {
+        let size = num_points(&batches);
+        if size > self.capacity_points {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(replaced) = state.entries.put(key, batches) {
+            state.statistics.num_entries -= 1;
+            state.statistics.num_points -= num_points(&replaced);
+        }
+        state.statistics.num_entries += 1;
+        state.statistics.num_points += size;
+        while state.statistics.num_points > self.capacity_points {
+            let (_, evicted) = match state.entries.pop_lru() {
+                Some(entry) => entry,
+                None => break,
+            };
+            state.statistics.evictions += 1;
+            state.statistics.num_entries -= 1;
+            state.statistics.num_points -= num_points(&evicted);
+        }
+    }
+}
+
+fn num_points(batches: &[PointsBatch]) -> usize {
+    batches.iter().map(|batch| batch.position.len()).sum()
+}
+
+/// A point cloud whose node queries go through a BatchCache.
+pub(crate) struct CachedPointCloud<'a, C> {
+    pub point_cloud: &'a C,
+    pub scope: &'a CacheScope,
+    pub cache: &'a BatchCache,
+}
+
+impl<'a, C: PointCloud> PointCloud for CachedPointCloud<'a, C> {
+    type Id = C::Id;
+
+    fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id> {
+        self.point_cloud.nodes_in_location(location)
+    }
+
+    fn plan_query(&self, query: &PointQuery) -> QueryPlan<Self::Id> {
+        self.point_cloud.plan_query(query)
+    }
+
+    fn radiometric_correction(&self) -> Option<&RadiometricCorrection> {
+        self.point_cloud.radiometric_correction()
+    }
+
+    fn attribute_aliases(&self) -> Option<&AttributeAliases> {
+        self.point_cloud.attribute_aliases()
+    }
+
+    fn stored_attribute_type(&self, attribute: &str) -> Result<Option<AttributeDataType>> {
+        self.point_cloud.stored_attribute_type(attribute)
+    }
+
+    fn encoding_for_node(&self, id: Self::Id) -> Encoding {
+        self.point_cloud.encoding_for_node(id)
+    }
+
+    fn num_points_in_node(&self, id: Self::Id) -> usize {
+        self.point_cloud.num_points_in_node(id)
+    }
+
+    fn points_in_node(
+        &self,
+        attributes: &[&str],
+        node_id: Self::Id,
+        batch_size: usize,
+    ) -> Result<NodeIterator> {
+        self.point_cloud
+            .points_in_node(attributes, node_id, batch_size)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.point_cloud.bounding_box()
+    }
+
+    fn stream_points_for_query_in_node<F>(
+        &self,
+        query: &PointQuery,
+        node_id: Self::Id,
+        batch_size: usize,
+        mut callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let key = CacheKey::new(self.scope, node_id.to_string(), query);
+        let batches = match self.cache.get(&key) {
+            Some(batches) => batches,
+            None => {
+                // The whole node is cached, with the corrections, aliases and filters applied.
+                let node_query = PointQuery {
+                    location: PointLocation::AllPoints,
+                    ..query.clone()
+                };
+                let mut batches = Vec::new();
+                self.point_cloud.stream_points_for_query_in_node(
+                    &node_query,
+                    node_id,
+                    batch_size,
+                    |batch| {
+                        batches.push(batch);
+                        Ok(())
+                    },
+                )?;
+                let batches = Arc::new(batches);
+                self.cache.insert(key, Arc::clone(&batches));
+                batches
+            }
+        };
+        for batch in batches.iter() {
+            let mut batch = batch.clone();
+            retain_in_location(&query.location, &mut batch);
+            if !batch.position.is_empty() {
+                callback(batch)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+    use std::collections::BTreeMap;
+
+    fn batches(num_points: usize) -> Arc<Vec<PointsBatch>> {
+        Arc::new(vec![PointsBatch {
+            position: vec![Point3::origin(); num_points],
+            attributes: BTreeMap::new(),
+        }])
+    }
+
+    #[test]
+    fn test_batch_cache() {
+        let cache = BatchCache::new(10);
+        let scope = CacheScope {
+            location: "octree".to_string(),
+            generation: 1,
+        };
+        cache.enter(&scope);
+        let query = PointQuery::default();
+        let key = |node: &str| CacheKey::new(&scope, node.to_string(), &query);
+        cache.insert(key("r0"), batches(4));
+        cache.insert(key("r1"), batches(4));
+        assert!(cache.get(&key("r0")).is_some());
+        // Evicts r1, which was used least recently.
+        cache.insert(key("r2"), batches(4));
+        assert!(cache.get(&key("r1")).is_none());
+        // Too large to be cached.
+        cache.insert(key("r3"), batches(11));
+        assert!(cache.get(&key("r3")).is_none());
+
+        let newer_scope = CacheScope {
+            generation: 2,
+            ..scope.clone()
+        };
+        cache.enter(&newer_scope);
+        assert!(cache.get(&key("r0")).is_none());
+        assert_eq!(
+            cache.statistics(),
+            CacheStatistics {
+                hits: 1,
+                misses: 3,
+                evictions: 1,
+                invalidations: 2,
+                num_entries: 0,
+                num_points: 0,
+            }
+        );
+
+        // A client that still uses the older generation neither drops the entries of the newer
+        // one nor shares its own with it.
+        let newer_key = CacheKey::new(&newer_scope, "r0".to_string(), &query);
+        cache.insert(newer_key.clone(), batches(4));
+        cache.enter(&scope);
+        cache.insert(key("r0"), batches(4));
+        assert!(cache.get(&newer_key).is_some());
+        assert!(cache.get(&key("r0")).is_some());
+        cache.enter(&newer_scope);
+        assert_eq!(cache.statistics().num_entries, 2);
+    }
+}
@@ -0,0 +1,246 @@
+//! Computes radiometric corrections for a collection of point clouds from the regions where they
+//! overlap: there, all of them should have the same colors and intensities.
+
+use crate::PointCloudClient;
+use fnv::FnvHashMap;
+use nalgebra::{Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::calibration::RadiometricCorrection;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{PointCloud, PointLocation, PointQuery};
+use point_viewer::octree::Octree;
+
+/// Red, green, blue and intensity.
+const NUM_CHANNELS: usize = 4;
+const NUM_ITERATIONS: usize = 100;
+/// How strongly each offset is pulled towards zero, in voxels. This keeps point clouds without
+/// overlap unchanged and makes the solution unique.
+const REGULARIZATION_WEIGHT: f64 = 1.;
+
+#[derive(Clone, Copy, Default)]
+struct Voxel {
+    sums: [f64; NUM_CHANNELS],
+    counts: [u64; NUM_CHANNELS],
+}
+
+impl Voxel {
+    fn add(&mut self, channel: usize, value: f64) {
+        self.sums[channel] += value;
+        self.counts[channel] += 1;
+    }
+
+    fn mean(&self, channel: usize) -> Option<f64> {
+        if self.counts[channel] == 0 {
+            return None;
+        }
+        Some(self.sums[channel] / self.counts[channel] as f64)
+    }
+}
+
+/// How much brighter the first point cloud of a pair is than the second, summed over the voxels
+/// both have points in, per channel.
+struct Overlap {
+    first: usize,
+    second: usize,
+    differences: [f64; NUM_CHANNELS],
+    num_voxels: [f64; NUM_CHANNELS],
+}
+
+fn intersection(a: &Aabb, b: &Aabb) -> Option<Aabb> {
+    let min = a.min().coords.sup(&b.min().coords);
+    let max = a.max().coords.inf(&b.max().coords);
+    if (0..3).any(|i| min[i] > max[i]) {
+        return None;
+    }
+    Some(Aabb::new(Point3::from(min), Point3::from(max)))
+}
+
+/// Finds offsets such that for every overlap, the corrected first point cloud is as bright as the
+/// corrected second one, in the least squares sense. Gauss-Seidel iterations.
+fn solve(num_point_clouds: usize, overlaps: &[Overlap], channel: usize) -> Vec<f64> {
+    let mut offsets = vec![0.; num_point_clouds];
+    for _ in 0..NUM_ITERATIONS {
+        for index in 0..num_point_clouds {
+            let mut numerator = 0.;
+            let mut denominator = REGULARIZATION_WEIGHT;
+            for overlap in overlaps {
+                let weight = overlap.num_voxels[channel];
+                if weight == 0. {
+                    continue;
+                }
+                let difference = overlap.differences[channel] / weight;
+                if overlap.first == index {
+                    numerator += weight * (offsets[overlap.second] - difference);
+                    denominator += weight;
+                } else if overlap.second == index {
+                    numerator += weight * (offsets[overlap.first] + difference);
+                    denominator += weight;
+                }
+            }
+            offsets[index] = numerator / denominator;
+        }
+    }
+    offsets
+}
+
+impl PointCloudClient {
+    /// The mean colors and intensities of the points of 'octrees[index]' that match 'query', binned
+    /// into cubes with an edge length of 'voxel_size'.
+    fn voxel_means(
+        &self,
+        octrees: &[Octree],
+        index: usize,
+        query: &PointQuery,
+        voxel_size: f64,
+    ) -> Result<FnvHashMap<(i64, i64, i64), Voxel>> {
+        let mut voxels: FnvHashMap<(i64, i64, i64), Voxel> = FnvHashMap::default();
+        let cache_scopes = &self.cache_scopes[index..=index];
+        self.for_each(&octrees[index..=index], cache_scopes, query, |batch| {
+            let color = match batch.attributes.get("color") {
+                Some(AttributeData::U8Vec3(color)) => Some(color),
+                _ => None,
+            };
+            let intensity = match batch.attributes.get("intensity") {
+                Some(AttributeData::F32(intensity)) => Some(intensity),
+                _ => None,
+            };
+            for (index, position) in batch.position.iter().enumerate() {
+                let key = (
+                    (position.x / voxel_size).floor() as i64,
+                    (position.y / voxel_size).floor() as i64,
+                    (position.z / voxel_size).floor() as i64,
+                );
+                let voxel = voxels.entry(key).or_default();
+                if let Some(color) = color {
+                    for (channel, value) in color[index].iter().enumerate() {
+                        voxel.add(channel, f64::from(*value));
+                    }
+                }
+                if let Some(intensity) = intensity {
+                    voxel.add(3, f64::from(intensity[index]));
+                }
+            }
+            Ok(())
+        })?;
+        Ok(voxels)
+    }
+
+    /// Computes a correction for each of the octrees that removes the differences in brightness
+    /// where they overlap, e.g. between scans captured in different lighting. 'attributes' are
+    /// the ones to calibrate, "color" and/or "intensity". Points are compared by the mean of the
+    /// cubes with an edge length of 'voxel_size' they fall into. The returned corrections
+    /// include the ones the octrees already have, and replace them.
+    pub fn compute_radiometric_corrections(
+        &self,
+        attributes: &[&str],
+        voxel_size: f64,
+    ) -> Result<Vec<RadiometricCorrection>> {
+        let octrees = self.octrees().ok_or_else(|| {
+            ErrorKind::InvalidInput("Only octrees can be calibrated.".to_string())
+        })?;
+        if let Some(attribute) = attributes
+            .iter()
+            .find(|attribute| !["color", "intensity"].contains(attribute))
+        {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Cannot calibrate attribute '{}'.",
+                attribute
+            ))
+            .into());
+        }
+        let mut overlaps = Vec::new();
+        for first in 0..octrees.len() {
+            for second in first + 1..octrees.len() {
+                let aabb = match intersection(
+                    octrees[first].bounding_box(),
+                    octrees[second].bounding_box(),
+                ) {
+                    Some(aabb) => aabb,
+                    None => continue,
+                };
+                let query = PointQuery {
+                    attributes: attributes.to_vec(),
+                    location: PointLocation::Aabb(aabb),
+                    ..Default::default()
+                };
+                let first_voxels = self.voxel_means(octrees, first, &query, voxel_size)?;
+                let second_voxels = self.voxel_means(octrees, second, &query, voxel_size)?;
+                let mut overlap = Overlap {
+                    first,
+                    second,
+                    differences: [0.; NUM_CHANNELS],
+                    num_voxels: [0.; NUM_CHANNELS],
+                };
+                for (key, first_voxel) in &first_voxels {
+                    let second_voxel = match second_voxels.get(key) {
+                        Some(second_voxel) => second_voxel,
+                        None => continue,
+                    };
+                    for channel in 0..NUM_CHANNELS {
+                        if let (Some(a), Some(b)) =
+                            (first_voxel.mean(channel), second_voxel.mean(channel))
+                        {
+                            overlap.differences[channel] += a - b;
+                            overlap.num_voxels[channel] += 1.;
+                        }
+                    }
+                }
+                overlaps.push(overlap);
+            }
+        }
+
+        let offsets: Vec<Vec<f64>> = (0..NUM_CHANNELS)
+            .map(|channel| solve(octrees.len(), &overlaps, channel))
+            .collect();
+        Ok(octrees
+            .iter()
+            .enumerate()
+            .map(|(index, octree)| {
+                let delta = RadiometricCorrection {
+                    color_offset: Vector3::new(
+                        offsets[0][index],
+                        offsets[1][index],
+                        offsets[2][index],
+                    ),
+                    intensity_offset: offsets[3][index],
+                };
+                // The queried points were already corrected by the current correction.
+                octree
+                    .radiometric_correction()
+                    .copied()
+                    .unwrap_or_default()
+                    .then(&delta)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_evens_out_brightness() {
+        // The second point cloud is 30 brighter than the first, the third 30 brighter than the
+        // second. The fourth does not overlap with any.
+        let overlaps = vec![
+            Overlap {
+                first: 0,
+                second: 1,
+                differences: [-30_000.; NUM_CHANNELS],
+                num_voxels: [1000.; NUM_CHANNELS],
+            },
+            Overlap {
+                first: 1,
+                second: 2,
+                differences: [-300_000.; NUM_CHANNELS],
+                num_voxels: [10_000.; NUM_CHANNELS],
+            },
+        ];
+        let offsets = solve(4, &overlaps, 0);
+        assert!((offsets[1] - offsets[0] + 30.).abs() < 1.);
+        assert!((offsets[2] - offsets[1] + 30.).abs() < 1.);
+        assert_eq!(offsets[3], 0.);
+    }
+}
@@ -1,10 +1,37 @@
-use point_viewer::data_provider::{DataProvider, DataProviderFactory};
+pub mod cache;
+pub mod calibration;
+pub mod sampling;
+pub mod spill;
+#[cfg(feature = "export")]
+pub mod tee;
+
+use cache::{BatchCache, CacheScope, CachedPointCloud};
+use point_viewer::attributes::AttributeDataType;
+use point_viewer::data_provider::{is_octree_meta, DataProvider, DataProviderFactory};
 use point_viewer::errors::*;
 use point_viewer::geometry::Aabb;
-use point_viewer::iterator::{ParallelIterator, PointCloud, PointQuery};
-use point_viewer::octree::Octree;
+use point_viewer::iterator::{ApproximatedRegion, ParallelIterator, PointCloud, PointQuery};
+use point_viewer::octree::{NodeId, Octree};
 use point_viewer::s2_cells::S2Cells;
+use point_viewer::spatial_join::SpatialJoin;
 use point_viewer::{PointsBatch, NUM_POINTS_PER_BATCH};
+use spill::{ExternalSorter, SortKey};
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(feature = "export")]
+use tee::{BatchSink, Tee};
+
+/// What happens to point clouds that lack some of the queried attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingAttributePolicy {
+    /// The query fails.
+    Error,
+    /// The point clouds that lack any of them are left out.
+    SkipCloud,
+    /// The point clouds are queried without them, and they are filled with zeros of the data
+    /// type that the other point clouds have. Filter intervals on them are checked against zero.
+    FillDefault,
+}
 
 enum PointClouds {
     Octrees(Vec<Octree>),
@@ -17,6 +44,12 @@ pub struct PointCloudClient {
     num_points_per_batch: usize,
     num_threads: usize,
     buffer_size: usize,
+    memory_budget: usize,
+    spill_directory: PathBuf,
+    missing_attributes: MissingAttributePolicy,
+    batch_cache: Option<Arc<BatchCache>>,
+    /// The cache scope of each point cloud.
+    cache_scopes: Vec<CacheScope>,
 }
 
 impl PointCloudClient {
@@ -24,11 +57,71 @@ impl PointCloudClient {
         &self.aabb
     }
 
-    fn for_each<C, F>(&self, point_cloud: &[C], point_query: &PointQuery, mut func: F) -> Result<()>
+    /// The point clouds if they are octrees, None if they are S2 cells.
+    pub fn octrees(&self) -> Option<&[Octree]> {
+        match &self.point_clouds {
+            PointClouds::Octrees(octrees) => Some(octrees),
+            PointClouds::S2Cells(_) => None,
+        }
+    }
+
+    /// The data type that each point cloud stores 'attribute' with, None for those that lack it.
+    pub fn stored_attribute_types(
+        &self,
+        attribute: &str,
+    ) -> Result<Vec<Option<AttributeDataType>>> {
+        match &self.point_clouds {
+            PointClouds::Octrees(octrees) => octrees
+                .iter()
+                .map(|octree| octree.stored_attribute_type(attribute))
+                .collect(),
+            PointClouds::S2Cells(s2_cells) => s2_cells
+                .iter()
+                .map(|s2_cells| s2_cells.stored_attribute_type(attribute))
+                .collect(),
+        }
+    }
+
+    /// The batch cache and its statistics, if this client has one.
+    pub fn batch_cache(&self) -> Option<&BatchCache> {
+        self.batch_cache.as_deref()
+    }
+
+    /// Queries 'point_cloud', whose cache scopes are 'cache_scopes', through the batch cache if
+    /// there is one.
+    fn for_each<C, F>(
+        &self,
+        point_cloud: &[C],
+        cache_scopes: &[CacheScope],
+        point_query: &PointQuery,
+        mut func: F,
+    ) -> Result<()>
     where
         C: PointCloud,
         F: FnMut(PointsBatch) -> Result<()>,
     {
+        if let Some(cache) = &self.batch_cache {
+            let cached: Vec<CachedPointCloud<C>> = point_cloud
+                .iter()
+                .zip(cache_scopes)
+                .map(|(point_cloud, scope)| {
+                    cache.enter(scope);
+                    CachedPointCloud {
+                        point_cloud,
+                        scope,
+                        cache,
+                    }
+                })
+                .collect();
+            let mut parallel_iterator = ParallelIterator::new(
+                &cached,
+                point_query,
+                self.num_points_per_batch,
+                self.num_threads,
+                self.buffer_size,
+            );
+            return parallel_iterator.try_for_each_batch(&mut func);
+        }
         let mut parallel_iterator = ParallelIterator::new(
             point_cloud,
             point_query,
@@ -39,15 +132,291 @@ impl PointCloudClient {
         parallel_iterator.try_for_each_batch(&mut func)
     }
 
-    pub fn for_each_point_data<F>(&self, point_query: &PointQuery, func: F) -> Result<()>
+    /// Like for_each, but point clouds that lack some of the queried attributes are handled
+    /// according to the MissingAttributePolicy. 'func' also gets the names of the attributes that
+    /// were filled in for the batch. All points of a batch come from point clouds that lack the
+    /// same attributes.
+    fn for_each_with_coverage<C, F>(
+        &self,
+        point_clouds: &[C],
+        cache_scopes: &[CacheScope],
+        point_query: &PointQuery,
+        mut func: F,
+    ) -> Result<()>
+    where
+        C: PointCloud,
+        F: FnMut(PointsBatch, &[String]) -> Result<()>,
+    {
+        if self.missing_attributes == MissingAttributePolicy::Error {
+            return self.for_each(point_clouds, cache_scopes, point_query, |batch| {
+                func(batch, &[])
+            });
+        }
+        // The data type of each queried attribute in each point cloud, None where it is missing.
+        let data_types = point_clouds
+            .iter()
+            .map(|point_cloud| {
+                point_query
+                    .attributes
+                    .iter()
+                    .map(|attribute| point_cloud.stored_attribute_type(attribute))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if data_types.iter().flatten().all(Option::is_some) {
+            return self.for_each(point_clouds, cache_scopes, point_query, |batch| {
+                func(batch, &[])
+            });
+        }
+
+        // The point clouds are queried one by one, so that batches do not mix points with real
+        // and filled values.
+        let clouds = point_clouds.iter().zip(&data_types).zip(cache_scopes);
+        for ((point_cloud, cloud_data_types), cache_scope) in clouds {
+            let point_cloud = std::slice::from_ref(point_cloud);
+            let cache_scope = std::slice::from_ref(cache_scope);
+            let missing: Vec<&str> = point_query
+                .attributes
+                .iter()
+                .zip(cloud_data_types)
+                .filter(|(_, data_type)| data_type.is_none())
+                .map(|(attribute, _)| *attribute)
+                .collect();
+            if missing.is_empty() {
+                self.for_each(point_cloud, cache_scope, point_query, |batch| {
+                    func(batch, &[])
+                })?;
+                continue;
+            }
+            if self.missing_attributes == MissingAttributePolicy::SkipCloud {
+                continue;
+            }
+            let fill = missing
+                .iter()
+                .map(|attribute| {
+                    let index = point_query.attributes.iter().position(|a| a == attribute);
+                    data_types
+                        .iter()
+                        .find_map(|types| index.and_then(|index| types[index]))
+                        .map(|data_type| ((*attribute).to_string(), data_type))
+                        .ok_or_else(|| {
+                            ErrorKind::InvalidInput(format!(
+                                "No point cloud has attribute '{}', so it cannot be filled in.",
+                                attribute
+                            ))
+                            .into()
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut query = point_query.clone();
+            query
+                .attributes
+                .retain(|attribute| !missing.contains(attribute));
+            // None of the points match if zero is outside of a filter interval of a filled
+            // attribute.
+            let mut matches_filter = true;
+            query.filter_intervals.retain(|attribute, interval| {
+                if missing.contains(attribute) {
+                    matches_filter &= interval.contains(0.);
+                    false
+                } else {
+                    true
+                }
+            });
+            if !matches_filter {
+                continue;
+            }
+            let filled: Vec<String> = fill.iter().map(|(name, _)| name.clone()).collect();
+            self.for_each(point_cloud, cache_scope, &query, |mut batch| {
+                for (name, data_type) in &fill {
+                    let data = data_type.zeros(batch.position.len());
+                    batch.attributes.insert(name.clone(), data);
+                }
+                func(batch, &filled)
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn for_each_point_data<F>(&self, point_query: &PointQuery, mut func: F) -> Result<()>
     where
         F: FnMut(PointsBatch) -> Result<()>,
+    {
+        self.for_each_point_data_with_coverage(point_query, |batch, _| func(batch))
+    }
+
+    /// Like for_each_point_data, but 'func' also gets the names of the attributes of the batch
+    /// that were filled in because its point cloud lacks them, see
+    /// MissingAttributePolicy::FillDefault.
+    pub fn for_each_point_data_with_coverage<F>(
+        &self,
+        point_query: &PointQuery,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch, &[String]) -> Result<()>,
     {
         match &self.point_clouds {
-            PointClouds::Octrees(octrees) => self.for_each(octrees, point_query, func),
-            PointClouds::S2Cells(s2_cells) => self.for_each(s2_cells, point_query, func),
+            PointClouds::Octrees(octrees) => {
+                self.for_each_with_coverage(octrees, &self.cache_scopes, point_query, func)
+            }
+            PointClouds::S2Cells(s2_cells) => {
+                self.for_each_with_coverage(s2_cells, &self.cache_scopes, point_query, func)
+            }
         }
     }
+
+    /// Streams the points matching the query to all 'sinks' in a single pass. The query is only
+    /// as fast as the slowest sink, which has at most 'buffer_size' batches queued up.
+    #[cfg(feature = "export")]
+    pub fn export(&self, point_query: &PointQuery, sinks: Vec<Box<dyn BatchSink>>) -> Result<()> {
+        let mut tee = Tee::new(sinks, self.buffer_size);
+        let result = self.for_each_point_data(point_query, |batch| tee.write(batch));
+        // A failed sink also fails the query, but its own error is more useful.
+        tee.finish().and(result)
+    }
+
+    /// Like for_each_point_data, but all points are sorted by 'sort_key' first. Where they take
+    /// more than the memory budget, they are sorted in runs that are spilled to disk and merged.
+    pub fn for_each_point_data_sorted<F>(
+        &self,
+        point_query: &PointQuery,
+        sort_key: &SortKey,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let mut sorter = ExternalSorter::new(
+            |batch: &PointsBatch| sort_key.keys(batch),
+            self.memory_budget,
+            &self.spill_directory,
+        );
+        self.for_each_point_data(point_query, |batch| sorter.push(batch))?;
+        sorter.finish(self.num_points_per_batch, func)
+    }
+
+    /// Like for_each_point_data, but only one, arbitrary point is kept per voxel of
+    /// 'resolution_m', e.g. to remove the duplicates where scans overlap. The points are sorted by
+    /// voxel within the memory budget like in for_each_point_data_sorted.
+    pub fn for_each_unique_point_data<F>(
+        &self,
+        point_query: &PointQuery,
+        resolution_m: f64,
+        mut func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let mut sorter = ExternalSorter::new(
+            |batch: &PointsBatch| Ok(spill::voxel_keys(batch, resolution_m)),
+            self.memory_budget,
+            &self.spill_directory,
+        );
+        self.for_each_point_data(point_query, |batch| sorter.push(batch))?;
+        let mut last_voxel = None;
+        sorter.finish(self.num_points_per_batch, |mut batch| {
+            let keep: Vec<bool> = spill::voxel_keys(&batch, resolution_m)
+                .into_iter()
+                .map(|voxel| last_voxel.replace(voxel) != Some(voxel))
+                .collect();
+            batch.retain(&keep);
+            if batch.position.is_empty() {
+                return Ok(());
+            }
+            func(batch)
+        })
+    }
+
+    /// Describes how the nodes matching 'point_query' are found in each of the point clouds,
+    /// with the number of nodes selected and tested, see PointCloud::plan_query.
+    pub fn explain_query(&self, point_query: &PointQuery) -> Vec<String> {
+        match &self.point_clouds {
+            PointClouds::Octrees(octrees) => octrees
+                .iter()
+                .map(|octree| octree.plan_query(point_query).to_string())
+                .collect(),
+            PointClouds::S2Cells(s2_cells) => s2_cells
+                .iter()
+                .map(|s2_cells| s2_cells.plan_query(point_query).to_string())
+                .collect(),
+        }
+    }
+
+    /// A uniform random sample of 'num_points' of the points matching 'point_query', or all of
+    /// them if there are fewer. Only the nodes that points are drawn from are read, see
+    /// sampling::sample.
+    pub fn sample(&self, point_query: &PointQuery, num_points: usize) -> Result<PointsBatch> {
+        let mut rng = rand::thread_rng();
+        match &self.point_clouds {
+            PointClouds::Octrees(octrees) => sampling::sample(
+                octrees,
+                point_query,
+                num_points,
+                self.num_points_per_batch,
+                &mut rng,
+            ),
+            PointClouds::S2Cells(s2_cells) => sampling::sample(
+                s2_cells,
+                point_query,
+                num_points,
+                self.num_points_per_batch,
+                &mut rng,
+            ),
+        }
+    }
+
+    /// The regions of each octree that a query with a maximum error reads at a coarser level
+    /// and which levels they are read at, see PointQuery::max_error_m. Empty for S2 cells, which
+    /// have no levels of detail.
+    pub fn approximated_regions(
+        &self,
+        point_query: &PointQuery,
+    ) -> Vec<Vec<ApproximatedRegion<NodeId>>> {
+        match &self.point_clouds {
+            PointClouds::Octrees(octrees) => octrees
+                .iter()
+                .map(|octree| octree.plan_query(point_query).approximated_regions)
+                .collect(),
+            PointClouds::S2Cells(s2_cells) => vec![Vec::new(); s2_cells.len()],
+        }
+    }
+
+    /// Like for_each_point_data, but the batches are annotated with the polygons of 'join' that
+    /// contain the points and the given numeric 'properties' of those, see SpatialJoin::annotate.
+    pub fn for_each_point_data_joined<F>(
+        &self,
+        point_query: &PointQuery,
+        join: &SpatialJoin,
+        properties: &[&str],
+        mut func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        self.for_each_point_data(point_query, |mut batch| {
+            join.annotate(&mut batch, properties);
+            func(batch)
+        })
+    }
+
+    /// The number of points matching 'point_query' in each of the polygons of 'join'.
+    pub fn count_points_per_polygon(
+        &self,
+        point_query: &PointQuery,
+        join: &SpatialJoin,
+    ) -> Result<Vec<usize>> {
+        let mut counts = vec![0; join.polygons().len()];
+        self.for_each_point_data(point_query, |batch| {
+            for position in &batch.position {
+                if let Some(index) = join.polygon_containing(position) {
+                    counts[index] += 1;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(counts)
+    }
 }
 
 pub struct PointCloudClientBuilder<'a> {
@@ -56,6 +425,10 @@ pub struct PointCloudClientBuilder<'a> {
     num_points_per_batch: usize,
     num_threads: usize,
     buffer_size: usize,
+    memory_budget: usize,
+    spill_directory: PathBuf,
+    missing_attributes: MissingAttributePolicy,
+    batch_cache: Option<Arc<BatchCache>>,
 }
 
 impl<'a> PointCloudClientBuilder<'a> {
@@ -66,6 +439,10 @@ impl<'a> PointCloudClientBuilder<'a> {
             num_points_per_batch: NUM_POINTS_PER_BATCH,
             num_threads: std::cmp::max(1, num_cpus::get() - 1),
             buffer_size: 4,
+            memory_budget: usize::MAX,
+            spill_directory: std::env::temp_dir(),
+            missing_attributes: MissingAttributePolicy::Error,
+            batch_cache: None,
         }
     }
 
@@ -89,6 +466,35 @@ impl<'a> PointCloudClientBuilder<'a> {
         self
     }
 
+    /// The number of bytes that operations which need all matching points at once, like
+    /// for_each_point_data_sorted, may keep in memory before they spill points to disk.
+    /// Unlimited by default.
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Where spilled points are written, the system's temporary directory by default.
+    pub fn spill_directory(mut self, spill_directory: impl Into<PathBuf>) -> Self {
+        self.spill_directory = spill_directory.into();
+        self
+    }
+
+    /// What happens to point clouds that lack some of the queried attributes. By default, the
+    /// query fails.
+    pub fn missing_attributes(mut self, missing_attributes: MissingAttributePolicy) -> Self {
+        self.missing_attributes = missing_attributes;
+        self
+    }
+
+    /// Keeps the decoded points of the queried nodes in 'batch_cache', see cache::BatchCache.
+    /// The cache can be shared with the clients that replace this one when the data changes.
+    /// No cache by default.
+    pub fn batch_cache(mut self, batch_cache: Arc<BatchCache>) -> Self {
+        self.batch_cache = Some(batch_cache);
+        self
+    }
+
     pub fn build(self) -> Result<PointCloudClient> {
         if self.locations.is_empty() {
             return Err("No locations specified for point cloud client.".into());
@@ -104,14 +510,23 @@ impl<'a> PointCloudClientBuilder<'a> {
             b.grow(*bbox.min());
             b.grow(*bbox.max());
         };
+        let mut cache_scopes = Vec::new();
+        let mut add_cache_scope = |location: &String, generation: u64| {
+            cache_scopes.push(CacheScope {
+                location: location.clone(),
+                generation,
+            })
+        };
         let first_meta = data_providers[0].meta_proto()?;
-        let point_clouds = if first_meta.version <= 11 || first_meta.has_octree() {
+        let point_clouds = if is_octree_meta(&first_meta) {
             PointClouds::Octrees(
                 data_providers
                     .into_iter()
-                    .map(|provider| {
+                    .zip(self.locations)
+                    .map(|(provider, location)| {
                         Octree::from_data_provider(provider).map(|octree| {
                             unite(octree.bounding_box(), &mut aabb);
+                            add_cache_scope(location, octree.generation());
                             octree
                         })
                     })
@@ -121,9 +536,12 @@ impl<'a> PointCloudClientBuilder<'a> {
             PointClouds::S2Cells(
                 data_providers
                     .into_iter()
-                    .map(|provider| {
+                    .zip(self.locations)
+                    .map(|(provider, location)| {
+                        let generation = provider.meta_proto()?.generation;
                         S2Cells::from_data_provider(provider).map(|s2_cells| {
                             unite(s2_cells.bounding_box(), &mut aabb);
+                            add_cache_scope(location, generation);
                             s2_cells
                         })
                     })
@@ -137,6 +555,11 @@ impl<'a> PointCloudClientBuilder<'a> {
             num_points_per_batch: self.num_points_per_batch,
             num_threads: self.num_threads,
             buffer_size: self.buffer_size,
+            memory_budget: self.memory_budget,
+            spill_directory: self.spill_directory,
+            missing_attributes: self.missing_attributes,
+            batch_cache: self.batch_cache,
+            cache_scopes,
         })
     }
 }
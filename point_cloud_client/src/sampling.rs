@@ -0,0 +1,238 @@
+//! Uniform random samples of the points matching a query, without reading all of them: the
+//! points to draw are distributed over the nodes by how many points the nodes store, and only
+//! those nodes are read, keeping a random subset of their matching points by reservoir sampling.
+
+use point_viewer::errors::*;
+use point_viewer::iterator::{PointCloud, PointQuery};
+use point_viewer::PointsBatch;
+use rand::seq::index;
+use rand::Rng;
+use std::collections::BTreeMap;
+
+/// When too few of the drawn points matched the query, the next round draws this much more than
+/// the previous one suggests is needed.
+const REDRAW_MARGIN: f64 = 1.2;
+
+fn empty_batch() -> PointsBatch {
+    PointsBatch {
+        position: Vec::new(),
+        attributes: BTreeMap::new(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Slot {
+    /// The point at this index of the sample so far.
+    Kept(usize),
+    /// The point at this index of the pushed batch.
+    Added(usize),
+}
+
+/// A uniform random sample of at most 'capacity' of the points pushed into it, i.e. reservoir
+/// sampling.
+struct Reservoir {
+    capacity: usize,
+    num_seen: usize,
+    sample: PointsBatch,
+}
+
+impl Reservoir {
+    fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            num_seen: 0,
+            sample: empty_batch(),
+        }
+    }
+
+    fn push<R: Rng>(&mut self, mut batch: PointsBatch, rng: &mut R) -> Result<()> {
+        let mut slots: Vec<Slot> = (0..self.sample.position.len()).map(Slot::Kept).collect();
+        for index in 0..batch.position.len() {
+            self.num_seen += 1;
+            if slots.len() < self.capacity {
+                slots.push(Slot::Added(index));
+            } else {
+                let slot = rng.gen_range(0, self.num_seen);
+                if slot < self.capacity {
+                    slots[slot] = Slot::Added(index);
+                }
+            }
+        }
+        let mut keep_kept = vec![false; self.sample.position.len()];
+        let mut keep_added = vec![false; batch.position.len()];
+        for slot in slots {
+            match slot {
+                Slot::Kept(index) => keep_kept[index] = true,
+                Slot::Added(index) => keep_added[index] = true,
+            }
+        }
+        self.sample.retain(&keep_kept);
+        batch.retain(&keep_added);
+        self.sample.append(&mut batch)?;
+        Ok(())
+    }
+}
+
+/// Keeps a uniform random subset of 'num_points' of the points of 'batch'.
+fn retain_random<R: Rng>(batch: &mut PointsBatch, num_points: usize, rng: &mut R) {
+    let len = batch.position.len();
+    if num_points >= len {
+        return;
+    }
+    let mut keep = vec![false; len];
+    for index in index::sample(rng, len, num_points).into_iter() {
+        keep[index] = true;
+    }
+    batch.retain(&keep);
+}
+
+/// How many of 'num_draws' items drawn without replacement from 'population' items are among the
+/// 'num_matching' items that match.
+fn hypergeometric<R: Rng>(
+    population: usize,
+    num_matching: usize,
+    num_draws: usize,
+    rng: &mut R,
+) -> usize {
+    let mut num_drawn_matching = 0;
+    for num_drawn in 0..num_draws {
+        if rng.gen_range(0, population - num_drawn) < num_matching - num_drawn_matching {
+            num_drawn_matching += 1;
+        }
+    }
+    num_drawn_matching
+}
+
+/// A node with the number of points it stores.
+struct Node<'a, C: PointCloud> {
+    point_cloud: &'a C,
+    id: C::Id,
+    num_points: usize,
+}
+
+/// Draws 'num_draws' of the 'total' points stored in 'nodes' uniformly at random and returns
+/// those of them that match 'query'. 'offsets' are the number of points before each node.
+fn draw<C: PointCloud, R: Rng>(
+    nodes: &[Node<C>],
+    offsets: &[usize],
+    total: usize,
+    num_draws: usize,
+    query: &PointQuery,
+    batch_size: usize,
+    rng: &mut R,
+) -> Result<PointsBatch> {
+    let mut quotas = vec![0; nodes.len()];
+    for drawn in index::sample(rng, total, num_draws).into_iter() {
+        // The last node that starts at or before the drawn point, which skips empty nodes.
+        let node = offsets
+            .binary_search_by(|offset| offset.cmp(&drawn).then(std::cmp::Ordering::Less))
+            .unwrap_err()
+            - 1;
+        quotas[node] += 1;
+    }
+
+    let mut sample = empty_batch();
+    for (node, quota) in nodes.iter().zip(quotas) {
+        if quota == 0 {
+            continue;
+        }
+        let mut reservoir = Reservoir::new(quota);
+        node.point_cloud
+            .stream_points_for_query_in_node(query, node.id, batch_size, |batch| {
+                reservoir.push(batch, rng)
+            })?;
+        // Only some of the points drawn from the node match the query, and the reservoir holds a
+        // random subset of the matching ones.
+        let num_drawn_matching = hypergeometric(
+            node.num_points,
+            reservoir.num_seen.min(node.num_points),
+            quota,
+            rng,
+        );
+        let mut node_sample = reservoir.sample;
+        retain_random(&mut node_sample, num_drawn_matching, rng);
+        sample.append(&mut node_sample)?;
+    }
+    Ok(sample)
+}
+
+/// A uniform random sample of 'num_points' of the points matching 'query' in 'point_clouds', or
+/// all of them if there are fewer, in no particular order. Only nodes that points are drawn from
+/// are read. Where nodes only partially overlap the query, more points than requested are drawn
+/// until enough of them match.
+pub fn sample<C: PointCloud, R: Rng>(
+    point_clouds: &[C],
+    query: &PointQuery,
+    num_points: usize,
+    batch_size: usize,
+    rng: &mut R,
+) -> Result<PointsBatch> {
+    let nodes: Vec<Node<C>> = point_clouds
+        .iter()
+        .flat_map(|point_cloud| {
+            point_cloud
+                .nodes_for_query(query)
+                .into_iter()
+                .map(move |id| Node {
+                    point_cloud,
+                    id,
+                    num_points: point_cloud.num_points_in_node(id),
+                })
+        })
+        .collect();
+    let mut offsets = Vec::with_capacity(nodes.len());
+    let mut total = 0;
+    for node in &nodes {
+        offsets.push(total);
+        total += node.num_points;
+    }
+
+    let mut num_draws = num_points.min(total);
+    loop {
+        let mut sample = draw(&nodes, &offsets, total, num_draws, query, batch_size, rng)?;
+        let num_matching = sample.position.len();
+        if num_matching >= num_points || num_draws == total {
+            retain_random(&mut sample, num_points, rng);
+            return Ok(sample);
+        }
+        let factor = REDRAW_MARGIN * num_points as f64 / num_matching.max(1) as f64;
+        num_draws = total.min((num_draws as f64 * factor.max(2.)) as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_reservoir_is_uniform() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = vec![0; 100];
+        for _ in 0..2000 {
+            let mut reservoir = Reservoir::new(10);
+            for start in (0..100).step_by(7) {
+                let batch = PointsBatch {
+                    position: (start..(start + 7).min(100))
+                        .map(|x| Point3::new(f64::from(x), 0., 0.))
+                        .collect(),
+                    attributes: BTreeMap::new(),
+                };
+                reservoir.push(batch, &mut rng).unwrap();
+            }
+            assert_eq!(reservoir.num_seen, 100);
+            assert_eq!(reservoir.sample.position.len(), 10);
+            for position in &reservoir.sample.position {
+                counts[position.x as usize] += 1;
+            }
+        }
+        // Each point is expected in 200 of the samples.
+        assert!(counts.iter().all(|count| (130..270).contains(count)));
+
+        assert_eq!(hypergeometric(10, 10, 4, &mut rng), 4);
+        assert_eq!(hypergeometric(10, 0, 4, &mut rng), 0);
+        assert!(hypergeometric(10, 3, 4, &mut rng) <= 3);
+    }
+}
@@ -0,0 +1,401 @@
+//! Client-side operations that need all points matching a query before they can return the
+//! first one, like sorting and deduplication, within a memory budget: when the buffered points
+//! exceed it, they are sorted and written to a temporary file as a run, and the runs are merged
+//! once all points have been seen. Runs use the plain raw node format.
+
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::read_write::{
+    AttributeReader, Encoding, NodeWriter, OpenMode, RawNodeReader, RawNodeWriter,
+};
+use point_viewer::{attribute_extension, match_1d_attr_data, match_attr_data};
+use point_viewer::{PointsBatch, Schema};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
+use tempdir::TempDir;
+
+/// What the points are sorted by, in ascending order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortKey {
+    X,
+    Y,
+    Z,
+    /// An attribute with one value per point, which must be part of the query.
+    Attribute(String),
+}
+
+/// Orders like the floats, with NaN after everything else.
+fn f64_key(value: f64) -> i64 {
+    let bits = value.to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+impl SortKey {
+    pub fn keys(&self, batch: &PointsBatch) -> Result<Vec<i64>> {
+        let coordinate = |index: usize| -> Vec<i64> {
+            batch.position.iter().map(|p| f64_key(p[index])).collect()
+        };
+        match self {
+            SortKey::X => Ok(coordinate(0)),
+            SortKey::Y => Ok(coordinate(1)),
+            SortKey::Z => Ok(coordinate(2)),
+            SortKey::Attribute(name) => match batch.attributes.get(name) {
+                Some(data) if data.dim() == 1 => {
+                    macro_rules! rhs {
+                        ($dtype:ident, $data:ident) => {
+                            $data.iter().map(|v| f64_key(*v as f64)).collect()
+                        };
+                    }
+                    Ok(match_1d_attr_data!(data, rhs))
+                }
+                Some(data) => Err(ErrorKind::InvalidInput(format!(
+                    "Cannot sort by attribute '{}' with {:?} data.",
+                    name,
+                    data.data_type()
+                ))
+                .into()),
+                None => Err(ErrorKind::InvalidInput(format!(
+                    "Cannot sort by attribute '{}', which is not queried.",
+                    name
+                ))
+                .into()),
+            },
+        }
+    }
+}
+
+/// The voxel of 'resolution_m' that each point is in.
+pub fn voxel_keys(batch: &PointsBatch, resolution_m: f64) -> Vec<(i64, i64, i64)> {
+    batch
+        .position
+        .iter()
+        .map(|p| {
+            (
+                (p.x / resolution_m).floor() as i64,
+                (p.y / resolution_m).floor() as i64,
+                (p.z / resolution_m).floor() as i64,
+            )
+        })
+        .collect()
+}
+
+fn empty_batch() -> PointsBatch {
+    PointsBatch {
+        position: Vec::new(),
+        attributes: BTreeMap::new(),
+    }
+}
+
+/// The points at 'selection', given as indices of the chunk and of the point in it. All chunks
+/// must have the same attributes.
+fn gather(chunks: &[&PointsBatch], selection: &[(usize, usize)]) -> PointsBatch {
+    let position = selection
+        .iter()
+        .map(|&(chunk, index)| chunks[chunk].position[index])
+        .collect();
+    let attributes = chunks[selection[0].0]
+        .attributes
+        .iter()
+        .map(|(name, data)| {
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident, $name:expr) => {
+                    AttributeData::$dtype(
+                        selection
+                            .iter()
+                            .map(|&(chunk, index)| match &chunks[chunk].attributes[$name] {
+                                AttributeData::$dtype(data) => data[index],
+                                _ => unreachable!(),
+                            })
+                            .collect(),
+                    )
+                };
+            }
+            (name.clone(), match_attr_data!(data, rhs, name))
+        })
+        .collect();
+    PointsBatch {
+        position,
+        attributes,
+    }
+}
+
+/// Calls 'func' with 'batch' split into batches of at most 'batch_size' points.
+fn emit(
+    mut batch: PointsBatch,
+    batch_size: usize,
+    func: &mut impl FnMut(PointsBatch) -> Result<()>,
+) -> Result<()> {
+    while !batch.position.is_empty() {
+        let rest = batch.split_off(batch_size.min(batch.position.len()));
+        func(batch)?;
+        batch = rest;
+    }
+    Ok(())
+}
+
+/// A sorted run of points in a temporary file.
+struct Run {
+    stem: PathBuf,
+    num_points: usize,
+}
+
+/// Reads a run in chunks while it is merged.
+struct RunCursor<K> {
+    reader: RawNodeReader,
+    num_unread: usize,
+    chunk: PointsBatch,
+    keys: Vec<K>,
+    next: usize,
+}
+
+impl<K> RunCursor<K> {
+    fn open(run: &Run, schema: &Schema) -> Result<Self> {
+        let open = |attribute: &str| -> Result<Box<dyn Read + Send>> {
+            let path = run.stem.with_extension(attribute_extension(attribute));
+            let file = File::open(&path)
+                .chain_err(|| format!("Could not open spilled points {}.", path.display()))?;
+            Ok(Box::new(file))
+        };
+        let attribute_readers = schema
+            .attributes()
+            .iter()
+            .map(|(name, data_type)| {
+                Ok((
+                    name.clone(),
+                    AttributeReader {
+                        data_type: *data_type,
+                        reader: BufReader::new(open(name)?),
+                    },
+                ))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(RunCursor {
+            reader: RawNodeReader::new(open("position")?, attribute_readers, Encoding::Plain)?,
+            num_unread: run.num_points,
+            chunk: empty_batch(),
+            keys: Vec::new(),
+            next: 0,
+        })
+    }
+
+    /// Replaces the chunk by the next 'chunk_size' points of the run, or fewer at its end.
+    fn load<F>(&mut self, chunk_size: usize, key: &F) -> Result<()>
+    where
+        F: Fn(&PointsBatch) -> Result<Vec<K>>,
+    {
+        let num_points = chunk_size.min(self.num_unread);
+        self.chunk = self.reader.read_batch(num_points)?;
+        self.num_unread -= num_points;
+        self.keys = key(&self.chunk)?;
+        self.next = 0;
+        Ok(())
+    }
+}
+
+/// Sorts any number of points by the keys that 'key' computes for each point of a batch, keeping
+/// at most about 'memory_budget' bytes of points in memory. The sort is stable.
+pub struct ExternalSorter<K, F> {
+    key: F,
+    memory_budget: usize,
+    spill_directory: PathBuf,
+    temp_dir: Option<TempDir>,
+    schema: Option<Schema>,
+    buffer: PointsBatch,
+    runs: Vec<Run>,
+    _key: PhantomData<K>,
+}
+
+impl<K, F> ExternalSorter<K, F>
+where
+    K: Ord + Copy,
+    F: Fn(&PointsBatch) -> Result<Vec<K>>,
+{
+    /// Runs are written into a temporary directory in 'spill_directory', which is removed when
+    /// the sorter is dropped.
+    pub fn new(key: F, memory_budget: usize, spill_directory: impl AsRef<Path>) -> Self {
+        ExternalSorter {
+            key,
+            memory_budget,
+            spill_directory: spill_directory.as_ref().to_path_buf(),
+            temp_dir: None,
+            schema: None,
+            buffer: empty_batch(),
+            runs: Vec::new(),
+            _key: PhantomData,
+        }
+    }
+
+    /// The size of a point with its key.
+    fn bytes_per_point(&self) -> usize {
+        let attributes: usize = self.schema.as_ref().map_or(0, |schema| {
+            schema.attributes().values().map(|t| t.size_of()).sum()
+        });
+        3 * 8 + mem::size_of::<K>() + attributes
+    }
+
+    pub fn push(&mut self, mut batch: PointsBatch) -> Result<()> {
+        if batch.position.is_empty() {
+            return Ok(());
+        }
+        let schema = self
+            .schema
+            .get_or_insert_with(|| Schema::from_batch(&batch));
+        schema
+            .assert_matches(&batch)
+            .map_err(ErrorKind::InvalidInput)?;
+        self.buffer.append(&mut batch)?;
+        if self.buffer.position.len() * self.bytes_per_point() >= self.memory_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) -> Result<PointsBatch> {
+        let buffer = mem::replace(&mut self.buffer, empty_batch());
+        if buffer.position.is_empty() {
+            return Ok(buffer);
+        }
+        let keys = (self.key)(&buffer)?;
+        let mut selection: Vec<(usize, usize)> = (0..keys.len()).map(|i| (0, i)).collect();
+        selection.sort_by_key(|&(_, index)| keys[index]);
+        Ok(gather(&[&buffer], &selection))
+    }
+
+    /// Writes the buffered points as a new run.
+    fn spill(&mut self) -> Result<()> {
+        let sorted = self.sort_buffer()?;
+        if sorted.position.is_empty() {
+            return Ok(());
+        }
+        if self.temp_dir.is_none() {
+            self.temp_dir = Some(
+                TempDir::new_in(&self.spill_directory, "point_cloud_client_spill").chain_err(
+                    || {
+                        format!(
+                            "Could not create a directory for spilled points in {}.",
+                            self.spill_directory.display()
+                        )
+                    },
+                )?,
+            );
+        }
+        let stem = self
+            .temp_dir
+            .as_ref()
+            .unwrap()
+            .path()
+            .join(format!("run_{}", self.runs.len()));
+        let mut writer = RawNodeWriter::new(&stem, Encoding::Plain, OpenMode::Truncate);
+        NodeWriter::<PointsBatch>::write(&mut writer, &sorted)?;
+        self.runs.push(Run {
+            stem,
+            num_points: sorted.position.len(),
+        });
+        Ok(())
+    }
+
+    /// Calls 'func' with all pushed points in sorted order, in batches of at most 'batch_size'
+    /// points.
+    pub fn finish(
+        mut self,
+        batch_size: usize,
+        mut func: impl FnMut(PointsBatch) -> Result<()>,
+    ) -> Result<()> {
+        if self.runs.is_empty() {
+            let sorted = self.sort_buffer()?;
+            return emit(sorted, batch_size, &mut func);
+        }
+        self.spill()?;
+        let schema = self.schema.clone().unwrap();
+        // The chunks of all runs together stay within the budget.
+        let chunk_size =
+            (self.memory_budget / self.bytes_per_point() / (self.runs.len() + 1)).max(1);
+        let mut cursors = self
+            .runs
+            .iter()
+            .map(|run| RunCursor::open(run, &schema))
+            .collect::<Result<Vec<_>>>()?;
+        // Ties are broken by the index of the run, which keeps the sort stable.
+        let mut heap = BinaryHeap::new();
+        for (index, cursor) in cursors.iter_mut().enumerate() {
+            cursor.load(chunk_size, &self.key)?;
+            heap.push(Reverse((cursor.keys[0], index)));
+        }
+
+        let mut selection = Vec::new();
+        while let Some(Reverse((_, run))) = heap.pop() {
+            selection.push((run, cursors[run].next));
+            cursors[run].next += 1;
+            let is_chunk_done = cursors[run].next == cursors[run].keys.len();
+            // The selected points must be copied out before a chunk is replaced.
+            if is_chunk_done || selection.len() == batch_size {
+                let chunks: Vec<&PointsBatch> = cursors.iter().map(|c| &c.chunk).collect();
+                func(gather(&chunks, &selection))?;
+                selection.clear();
+            }
+            if is_chunk_done {
+                cursors[run].load(chunk_size, &self.key)?;
+            }
+            if let Some(key) = cursors[run].keys.get(cursors[run].next) {
+                heap.push(Reverse((*key, run)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    #[test]
+    fn test_external_sort_spills_and_merges() {
+        let tmp_dir = TempDir::new("spill").unwrap();
+        // Room for about 10 points, so the 100 points end up in several runs.
+        let mut sorter = ExternalSorter::new(
+            |batch: &PointsBatch| SortKey::Attribute("intensity".to_string()).keys(batch),
+            10 * (3 * 8 + 8 + 4),
+            tmp_dir.path(),
+        );
+        for start in (0..100).step_by(7) {
+            let values: Vec<i32> = (start..(start + 7).min(100))
+                .map(|i| (i * 37) % 100 - 50)
+                .collect();
+            let batch = PointsBatch {
+                position: values
+                    .iter()
+                    .map(|v| Point3::new(f64::from(*v), 0., 0.))
+                    .collect(),
+                attributes: vec![(
+                    "intensity".to_string(),
+                    AttributeData::F32(values.iter().map(|v| *v as f32).collect()),
+                )]
+                .into_iter()
+                .collect(),
+            };
+            sorter.push(batch).unwrap();
+        }
+        assert!(sorter.runs.len() > 2);
+
+        let mut sorted = Vec::new();
+        sorter
+            .finish(16, |batch| {
+                assert!(batch.position.len() <= 16);
+                let intensities = batch.get_attribute_vec::<f32>("intensity").unwrap();
+                for (position, intensity) in batch.position.iter().zip(intensities) {
+                    assert_eq!(position.x, f64::from(*intensity));
+                    sorted.push(*intensity);
+                }
+                Ok(())
+            })
+            .unwrap();
+        let expected: Vec<f32> = (-50..50).map(|v| v as f32).collect();
+        assert_eq!(sorted, expected);
+    }
+}
@@ -0,0 +1,394 @@
+//! Streams the points of one query to several outputs at once, e.g. a PLY file, a CSV summary and
+//! a density raster, so that large regions only have to be read once.
+//!
+//! Every sink runs on its own thread behind a bounded queue. The query waits whenever one of the
+//! queues is full, so the slowest sink sets the pace and memory stays bounded.
+
+use image::{ImageBuffer, Luma};
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::read_write::{Encoding, LasNodeWriter, NodeWriter, OpenMode, PlyNodeWriter};
+use point_viewer::{match_1d_attr_data, PointsBatch};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// An output of a tee export.
+pub trait BatchSink: Send {
+    fn write(&mut self, batch: &PointsBatch) -> Result<()>;
+
+    /// Called once after the last batch.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes all points into a PLY file.
+pub struct PlySink {
+    writer: PlyNodeWriter,
+}
+
+impl PlySink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        PlySink {
+            writer: PlyNodeWriter::new(path, Encoding::Plain, OpenMode::Truncate),
+        }
+    }
+}
+
+impl BatchSink for PlySink {
+    fn write(&mut self, batch: &PointsBatch) -> Result<()> {
+        self.writer.write(batch).map_err(Into::into)
+    }
+}
+
+/// Writes all points into a LAS file, or a LAZ file if the path ends in ".laz", with their
+/// colors, intensities and classes.
+pub struct LasSink {
+    // Taken when finishing.
+    writer: Option<LasNodeWriter>,
+}
+
+impl LasSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LasSink {
+            writer: Some(LasNodeWriter::new(path, OpenMode::Truncate)),
+        }
+    }
+}
+
+impl BatchSink for LasSink {
+    fn write(&mut self, batch: &PointsBatch) -> Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.write(batch).map_err(Into::into),
+            None => Err("The LAS file was finished already.".into()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finish().map_err(Into::into),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Statistics {
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Statistics {
+            count: 0,
+            min: std::f64::INFINITY,
+            max: std::f64::NEG_INFINITY,
+            sum: 0.,
+        }
+    }
+}
+
+impl Statistics {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+}
+
+/// Writes a CSV file with the number of points and the minimum, maximum and mean of the
+/// coordinates and of every one-dimensional attribute.
+pub struct SummarySink {
+    path: PathBuf,
+    statistics: BTreeMap<String, Statistics>,
+}
+
+impl SummarySink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SummarySink {
+            path: path.into(),
+            statistics: BTreeMap::new(),
+        }
+    }
+}
+
+impl BatchSink for SummarySink {
+    fn write(&mut self, batch: &PointsBatch) -> Result<()> {
+        for (axis, name) in ["x", "y", "z"].iter().enumerate() {
+            let statistics = self.statistics.entry(name.to_string()).or_default();
+            for position in &batch.position {
+                statistics.add(position[axis]);
+            }
+        }
+        for (name, data) in &batch.attributes {
+            if data.dim() != 1 {
+                continue;
+            }
+            let statistics = self.statistics.entry(name.clone()).or_default();
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident, $statistics:ident) => {
+                    for value in $data {
+                        $statistics.add(*value as f64);
+                    }
+                };
+            }
+            match_1d_attr_data!(data, rhs, statistics)
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "name,count,min,max,mean")?;
+        for (name, statistics) in &self.statistics {
+            if statistics.count == 0 {
+                writeln!(writer, "{},0,,,", name)?;
+                continue;
+            }
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                name,
+                statistics.count,
+                statistics.min,
+                statistics.max,
+                statistics.sum / statistics.count as f64
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes a 16 bit grayscale PNG with the number of points in each cell of a grid over the x-y
+/// plane of 'aabb', with the minimum x and maximum y in the top left corner. Points outside of
+/// 'aabb' are ignored, and counts above 65535 are clamped.
+pub struct DensitySink {
+    path: PathBuf,
+    aabb: Aabb,
+    resolution_m: f64,
+    width: u32,
+    height: u32,
+    counts: Vec<u32>,
+}
+
+impl DensitySink {
+    pub fn new(path: impl Into<PathBuf>, aabb: Aabb, resolution_m: f64) -> Result<Self> {
+        if !resolution_m.is_finite() || resolution_m <= 0. {
+            return Err(ErrorKind::InvalidInput(format!(
+                "The resolution must be positive, not {}.",
+                resolution_m
+            ))
+            .into());
+        }
+        let diag = aabb.diag();
+        let width = (diag.x / resolution_m).ceil().max(1.);
+        let height = (diag.y / resolution_m).ceil().max(1.);
+        if width * height > f64::from(std::u32::MAX) {
+            return Err(ErrorKind::InvalidInput(format!(
+                "A density raster of {}x{} cells is too large, use a coarser resolution.",
+                width, height
+            ))
+            .into());
+        }
+        let (width, height) = (width as u32, height as u32);
+        Ok(DensitySink {
+            path: path.into(),
+            aabb,
+            resolution_m,
+            width,
+            height,
+            counts: vec![0; width as usize * height as usize],
+        })
+    }
+}
+
+impl BatchSink for DensitySink {
+    fn write(&mut self, batch: &PointsBatch) -> Result<()> {
+        let min = self.aabb.min();
+        let max = self.aabb.max();
+        for position in &batch.position {
+            if !self.aabb.contains(position) {
+                continue;
+            }
+            let column = ((position.x - min.x) / self.resolution_m) as u32;
+            let row = ((max.y - position.y) / self.resolution_m) as u32;
+            let index = row.min(self.height - 1) as usize * self.width as usize
+                + column.min(self.width - 1) as usize;
+            self.counts[index] = self.counts[index].saturating_add(1);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let pixels = self
+            .counts
+            .iter()
+            .map(|count| (*count).min(u32::from(std::u16::MAX)) as u16)
+            .collect();
+        let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_raw(self.width, self.height, pixels)
+                .expect("The counts have one entry per pixel.");
+        image
+            .save(&self.path)
+            .chain_err(|| format!("Could not write {}.", self.path.display()))
+    }
+}
+
+struct SinkThread {
+    sender: Option<SyncSender<Arc<PointsBatch>>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl SinkThread {
+    fn join(&mut self) -> Result<()> {
+        // Closing the queue lets the sink finish.
+        self.sender = None;
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err("A sink of the export panicked.".into())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hands every batch to all sinks.
+pub struct Tee {
+    sinks: Vec<SinkThread>,
+}
+
+impl Tee {
+    /// Starts a thread for each sink, with room for 'queue_size' batches that it has not yet
+    /// written.
+    pub fn new(sinks: Vec<Box<dyn BatchSink>>, queue_size: usize) -> Self {
+        let sinks = sinks
+            .into_iter()
+            .map(|mut sink| {
+                let (sender, receiver) = mpsc::sync_channel::<Arc<PointsBatch>>(queue_size);
+                let handle = thread::spawn(move || {
+                    for batch in receiver {
+                        sink.write(&batch)?;
+                    }
+                    sink.finish()
+                });
+                SinkThread {
+                    sender: Some(sender),
+                    handle: Some(handle),
+                }
+            })
+            .collect();
+        Tee { sinks }
+    }
+
+    /// Blocks while one of the sinks is behind by a full queue. Fails with the error of a sink
+    /// that failed.
+    pub fn write(&mut self, batch: PointsBatch) -> Result<()> {
+        let batch = Arc::new(batch);
+        for sink in &mut self.sinks {
+            let sent = match &sink.sender {
+                Some(sender) => sender.send(Arc::clone(&batch)).is_ok(),
+                None => true,
+            };
+            if !sent {
+                // The sink stopped receiving, which only happens when it failed.
+                sink.join()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until all sinks have written everything and returns the first error.
+    pub fn finish(mut self) -> Result<()> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let sink_result = sink.join();
+            if result.is_ok() {
+                result = sink_result;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        for sink in &mut self.sinks {
+            let _ = sink.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingSink {
+        num_points: Arc<AtomicUsize>,
+        fail_after: Option<usize>,
+    }
+
+    impl BatchSink for CountingSink {
+        fn write(&mut self, batch: &PointsBatch) -> Result<()> {
+            let num_points = self
+                .num_points
+                .fetch_add(batch.position.len(), Ordering::SeqCst)
+                + batch.position.len();
+            match self.fail_after {
+                Some(limit) if num_points > limit => Err("Disk full.".into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn batch(num_points: usize) -> PointsBatch {
+        PointsBatch {
+            position: vec![Point3::origin(); num_points],
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_tee_writes_to_all_sinks_and_reports_failures() {
+        let counts: Vec<_> = (0..2).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let sinks = counts
+            .iter()
+            .map(|num_points| {
+                Box::new(CountingSink {
+                    num_points: Arc::clone(num_points),
+                    fail_after: None,
+                }) as Box<dyn BatchSink>
+            })
+            .collect();
+        let mut tee = Tee::new(sinks, 1);
+        for _ in 0..10 {
+            tee.write(batch(3)).unwrap();
+        }
+        tee.finish().unwrap();
+        for num_points in &counts {
+            assert_eq!(30, num_points.load(Ordering::SeqCst));
+        }
+
+        let failing = Box::new(CountingSink {
+            num_points: Arc::new(AtomicUsize::new(0)),
+            fail_after: Some(5),
+        });
+        let mut tee = Tee::new(vec![failing], 1);
+        let result = (0..10)
+            .try_for_each(|_| tee.write(batch(3)))
+            .and(tee.finish());
+        assert!(result.unwrap_err().to_string().contains("Disk full."));
+    }
+}
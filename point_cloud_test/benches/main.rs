@@ -1,10 +1,14 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 use point_cloud_client::PointCloudClient;
+use point_cloud_test_lib::io_conditions::{drop_page_cache, IoConditions};
 use point_cloud_test_lib::queries::*;
 use point_cloud_test_lib::{
-    make_octree, make_s2_cells, setup_octree_client, setup_s2_client, Arguments, SyntheticData,
+    get_s2_and_octree_path, make_octree, make_s2_cells, setup_octree_client_with_io_conditions,
+    setup_s2_client_with_io_conditions, Arguments, SyntheticData,
 };
 use point_viewer::iterator::{PointLocation, PointQuery};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tempdir::TempDir;
 
 fn bench_octree_building_multithreaded(c: &mut Criterion) {
@@ -30,68 +34,43 @@ fn bench_s2_building_singlethreaded(c: &mut Criterion) {
 }
 
 fn all_query_octree(b: &mut Criterion) {
-    run_bench(
-        "all_query_octree",
-        setup_octree_client,
-        |_| PointLocation::AllPoints,
-        b,
-    )
+    run_bench("all_query_octree", &OCTREE, |_| PointLocation::AllPoints, b)
 }
 
 fn all_query_s2(b: &mut Criterion) {
-    run_bench(
-        "all_query_s2",
-        setup_s2_client,
-        |_| PointLocation::AllPoints,
-        b,
-    )
+    run_bench("all_query_s2", &S2, |_| PointLocation::AllPoints, b)
 }
 
 fn box_query_octree(b: &mut Criterion) {
-    run_bench("box_query_octree", setup_octree_client, get_aabb_query, b)
+    run_bench("box_query_octree", &OCTREE, get_aabb_query, b)
 }
 
 fn box_query_s2(b: &mut Criterion) {
-    run_bench("box_query_s2", setup_s2_client, get_aabb_query, b)
+    run_bench("box_query_s2", &S2, get_aabb_query, b)
 }
 
 fn frustum_query_octree(b: &mut Criterion) {
-    run_bench(
-        "frustum_query_octree",
-        setup_octree_client,
-        get_frustum_query,
-        b,
-    )
+    run_bench("frustum_query_octree", &OCTREE, get_frustum_query, b)
 }
 
 fn frustum_query_s2(b: &mut Criterion) {
-    run_bench("frustum_query_s2", setup_s2_client, get_frustum_query, b)
+    run_bench("frustum_query_s2", &S2, get_frustum_query, b)
 }
 
 fn obb_query_octree(b: &mut Criterion) {
-    run_bench("obb_query_octree", setup_octree_client, get_obb_query, b)
+    run_bench("obb_query_octree", &OCTREE, get_obb_query, b)
 }
 
 fn obb_query_s2(b: &mut Criterion) {
-    run_bench("obb_query_s2", setup_s2_client, get_obb_query, b)
+    run_bench("obb_query_s2", &S2, get_obb_query, b)
 }
 
 fn cell_union_query_octree(b: &mut Criterion) {
-    run_bench(
-        "cell_union_query_octree",
-        setup_octree_client,
-        get_cell_union_query,
-        b,
-    )
+    run_bench("cell_union_query_octree", &OCTREE, get_cell_union_query, b)
 }
 
 fn cell_union_query_s2(b: &mut Criterion) {
-    run_bench(
-        "cell_union_query_s2",
-        setup_s2_client,
-        get_cell_union_query,
-        b,
-    )
+    run_bench("cell_union_query_s2", &S2, get_cell_union_query, b)
 }
 
 criterion_group!(
@@ -111,26 +90,75 @@ criterion_group!(
 );
 criterion_main!(benches);
 
-type SetupClientFn = fn(&Arguments) -> (PointCloudClient, SyntheticData);
+type SetupClientFn = fn(&Arguments, &IoConditions) -> (PointCloudClient, SyntheticData);
 
-fn run_bench<F>(name: &'static str, gen_client: SetupClientFn, gen_location: F, c: &mut Criterion)
+struct Setup {
+    client: SetupClientFn,
+    directory: fn(&Arguments) -> PathBuf,
+}
+
+fn octree_directory(args: &Arguments) -> PathBuf {
+    get_s2_and_octree_path(args).1
+}
+
+fn s2_directory(args: &Arguments) -> PathBuf {
+    get_s2_and_octree_path(args).0
+}
+
+const OCTREE: Setup = Setup {
+    client: setup_octree_client_with_io_conditions,
+    directory: octree_directory,
+};
+
+const S2: Setup = Setup {
+    client: setup_s2_client_with_io_conditions,
+    directory: s2_directory,
+};
+
+fn run_query(client: &PointCloudClient, query: &PointQuery) -> usize {
+    let mut num_points = 0;
+    let res = client.for_each_point_data(query, |batch| {
+        num_points += batch.position.len();
+        black_box(batch);
+        Ok(())
+    });
+    assert!(res.is_ok());
+    num_points
+}
+
+/// The I/O conditions are taken from the environment, see 'IoConditions::from_env'. Throughput
+/// is reported in points per second.
+fn run_bench<F>(name: &'static str, setup: &Setup, gen_location: F, c: &mut Criterion)
 where
     F: FnOnce(SyntheticData) -> PointLocation,
 {
     let args = Arguments::default();
-    let (client, data) = gen_client(&args);
+    let io_conditions = IoConditions::from_env();
+    let (client, data) = (setup.client)(&args, &io_conditions);
+    let directory = (setup.directory)(&args);
     let query = PointQuery {
         attributes: vec!["color"],
         location: gen_location(data),
         ..Default::default()
     };
-    c.bench_function(name, |b| {
-        b.iter(|| {
-            let res = client.for_each_point_data(&query, |batch| {
-                black_box(batch);
-                Ok(())
-            });
-            assert!(res.is_ok());
-        })
+    let num_points = run_query(&client, &query);
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(num_points as u64));
+    group.bench_function(name, |b| {
+        if io_conditions.drop_page_cache {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+                for _ in 0..iters {
+                    drop_page_cache(&directory).expect("Could not drop the page cache.");
+                    let start = Instant::now();
+                    run_query(&client, &query);
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        } else {
+            b.iter(|| run_query(&client, &query))
+        }
     });
+    group.finish();
 }
@@ -0,0 +1,187 @@
+// Simulation of I/O conditions for benchmarks: a cold page cache and remote storage with latency
+// and limited bandwidth. By default, benchmarks run against a warm local disk.
+use point_viewer::data_provider::{
+    DataProvider, DataProviderFactory, DataProviderFactoryResult, OnDiskDataProvider,
+};
+use point_viewer::errors::*;
+use point_viewer::proto;
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Set to 1 to evict the point cloud from the page cache before every query.
+pub const DROP_PAGE_CACHE_ENV: &str = "POINT_CLOUD_BENCH_DROP_PAGE_CACHE";
+/// Latency in milliseconds added to every request to the data provider.
+pub const LATENCY_MS_ENV: &str = "POINT_CLOUD_BENCH_LATENCY_MS";
+/// Bandwidth in megabytes per second that the data provider is limited to.
+pub const BANDWIDTH_MBPS_ENV: &str = "POINT_CLOUD_BENCH_BANDWIDTH_MBPS";
+
+/// Locations starting with this prefix are served by a DelayedDataProvider, see
+/// 'IoConditions::location'.
+pub const DELAYED_PREFIX: &str = "delayed:";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IoConditions {
+    pub drop_page_cache: bool,
+    pub latency: Duration,
+    pub bandwidth_bytes_per_sec: Option<u64>,
+}
+
+impl IoConditions {
+    /// Reads the conditions from the environment, since benchmarks can't take arguments.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let parse = |name: &str, value: String| -> f64 {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number, got '{}'.", name, value))
+        };
+        IoConditions {
+            drop_page_cache: var(DROP_PAGE_CACHE_ENV).map_or(false, |v| v != "0"),
+            latency: var(LATENCY_MS_ENV).map_or(Duration::default(), |v| {
+                Duration::from_secs_f64(parse(LATENCY_MS_ENV, v) / 1000.)
+            }),
+            bandwidth_bytes_per_sec: var(BANDWIDTH_MBPS_ENV)
+                .map(|v| (parse(BANDWIDTH_MBPS_ENV, v) * 1_000_000.) as u64),
+        }
+    }
+
+    /// Whether data providers need to be wrapped in a DelayedDataProvider.
+    pub fn is_remote(&self) -> bool {
+        self.latency > Duration::default() || self.bandwidth_bytes_per_sec.is_some()
+    }
+
+    /// Returns the location under which the point cloud in 'directory' is served with these
+    /// conditions by the factory returned by 'data_provider_factory'.
+    pub fn location(&self, directory: &Path) -> String {
+        let directory = directory.to_str().unwrap();
+        if !self.is_remote() {
+            return directory.to_string();
+        }
+        format!(
+            "{}{}:{}:{}",
+            DELAYED_PREFIX,
+            self.latency.as_micros(),
+            self.bandwidth_bytes_per_sec.unwrap_or(0),
+            directory
+        )
+    }
+}
+
+/// Wraps a data provider and delays every request as if the data was fetched from a remote
+/// storage with the given latency and bandwidth.
+pub struct DelayedDataProvider {
+    inner: Box<dyn DataProvider>,
+    latency: Duration,
+    bandwidth_bytes_per_sec: Option<u64>,
+}
+
+impl DelayedDataProvider {
+    pub fn new(
+        inner: Box<dyn DataProvider>,
+        latency: Duration,
+        bandwidth_bytes_per_sec: Option<u64>,
+    ) -> Self {
+        DelayedDataProvider {
+            inner,
+            latency,
+            bandwidth_bytes_per_sec,
+        }
+    }
+
+    fn delay(&self, num_bytes: usize) {
+        let transfer = match self.bandwidth_bytes_per_sec {
+            Some(bandwidth) if bandwidth > 0 => {
+                Duration::from_secs_f64(num_bytes as f64 / bandwidth as f64)
+            }
+            _ => Duration::default(),
+        };
+        thread::sleep(self.latency + transfer);
+    }
+}
+
+impl DataProvider for DelayedDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        let meta = self.inner.meta_proto()?;
+        self.delay(protobuf::Message::compute_size(&meta) as usize);
+        Ok(meta)
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let mut num_bytes = 0;
+        let mut buffered = HashMap::<String, Box<dyn Read + Send>>::new();
+        for (attribute, mut reader) in self.inner.data(node_id, node_attributes)? {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            num_bytes += data.len();
+            buffered.insert(attribute, Box::new(Cursor::new(data)));
+        }
+        self.delay(num_bytes);
+        Ok(buffered)
+    }
+}
+
+fn generate_delayed_data_provider(location: &str) -> DataProviderFactoryResult {
+    let invalid = || Error::from(format!("Invalid delayed location '{}'.", location));
+    let mut parts = location
+        .strip_prefix(DELAYED_PREFIX)
+        .ok_or_else(invalid)?
+        .splitn(3, ':');
+    let latency_us: u64 = parts
+        .next()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(invalid)?;
+    let bandwidth: u64 = parts
+        .next()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(invalid)?;
+    let directory = parts.next().ok_or_else(invalid)?;
+    Ok(Box::new(DelayedDataProvider::new(
+        Box::new(OnDiskDataProvider {
+            directory: directory.into(),
+        }),
+        Duration::from_micros(latency_us),
+        Some(bandwidth).filter(|b| *b > 0),
+    )))
+}
+
+/// A factory that understands the locations returned by 'IoConditions::location'.
+pub fn data_provider_factory() -> DataProviderFactory {
+    DataProviderFactory::new().register(DELAYED_PREFIX, generate_delayed_data_provider)
+}
+
+/// Evicts all files in 'directory' from the page cache, so that the next reads hit the disk.
+/// This works without root privileges, but only on Linux.
+#[cfg(target_os = "linux")]
+pub fn drop_page_cache(directory: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file = std::fs::File::open(&path)?;
+        // Dirty pages can't be dropped, so write them out first.
+        file.sync_all()?;
+        let result =
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_page_cache(_directory: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Dropping the page cache is only supported on Linux.",
+    ))
+}
@@ -2,7 +2,7 @@ use point_cloud_client::{PointCloudClient, PointCloudClientBuilder};
 /// This module has functions to generate synthetic point clouds in a temp dir
 /// and provides queries on these synthetic point clouds.
 use point_viewer::data_provider::OnDiskDataProvider;
-use point_viewer::octree::{build_octree, Octree};
+use point_viewer::octree::{Octree, OctreeBuilder};
 use point_viewer::read_write::{Encoding, NodeWriter, OpenMode, RawNodeWriter, S2Splitter};
 use point_viewer::s2_cells::S2Cells;
 use point_viewer::META_FILENAME;
@@ -14,10 +14,13 @@ use std::sync::Once;
 use tempdir::TempDir;
 
 pub mod synthetic_data;
-pub use synthetic_data::{Batched, SyntheticData};
+pub use synthetic_data::{Batched, Class, Scene, SyntheticData};
 
 pub mod queries;
 
+pub mod io_conditions;
+use io_conditions::IoConditions;
+
 pub const S2_LEVEL: u64 = 20;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -57,7 +60,10 @@ pub fn make_octree(args: &Arguments, dir: &Path) {
     let bbox = points_oct.bbox();
     let batches_oct = Batched::new(points_oct, args.batch_size);
 
-    build_octree(dir, args.resolution, bbox, batches_oct, &["color"]);
+    OctreeBuilder::new(args.resolution)
+        .with_attributes(&["color"])
+        .build(dir, bbox, batches_oct)
+        .expect("Could not build octree.");
 }
 
 pub fn make_s2_cells(args: &Arguments, dir: &Path) {
@@ -114,17 +120,33 @@ pub fn setup_pointcloud(args: &Arguments) -> (S2Cells, Octree, SyntheticData) {
 }
 
 pub fn setup_s2_client(args: &Arguments) -> (PointCloudClient, SyntheticData) {
-    let (s2_path_buf, _, data) = get_s2_and_octree_path(args);
-    let s2_locations = &[s2_path_buf.to_str().unwrap().to_owned()];
-    let client = PointCloudClientBuilder::new(s2_locations).build().unwrap();
-    (client, data)
+    setup_s2_client_with_io_conditions(args, &IoConditions::default())
 }
 
 pub fn setup_octree_client(args: &Arguments) -> (PointCloudClient, SyntheticData) {
-    let (_, oct_path_buf, data) = get_s2_and_octree_path(args);
-    let octree_locations = &[oct_path_buf.to_str().unwrap().to_owned()];
-    let client = PointCloudClientBuilder::new(octree_locations)
+    setup_octree_client_with_io_conditions(args, &IoConditions::default())
+}
+
+fn build_client(directory: &Path, io_conditions: &IoConditions) -> PointCloudClient {
+    let locations = &[io_conditions.location(directory)];
+    PointCloudClientBuilder::new(locations)
+        .data_provider_factory(io_conditions::data_provider_factory())
         .build()
-        .unwrap();
-    (client, data)
+        .unwrap()
+}
+
+pub fn setup_s2_client_with_io_conditions(
+    args: &Arguments,
+    io_conditions: &IoConditions,
+) -> (PointCloudClient, SyntheticData) {
+    let (s2_path_buf, _, data) = get_s2_and_octree_path(args);
+    (build_client(&s2_path_buf, io_conditions), data)
+}
+
+pub fn setup_octree_client_with_io_conditions(
+    args: &Arguments,
+    io_conditions: &IoConditions,
+) -> (PointCloudClient, SyntheticData) {
+    let (_, oct_path_buf, data) = get_s2_and_octree_path(args);
+    (build_client(&oct_path_buf, io_conditions), data)
 }
@@ -18,6 +18,17 @@ pub fn get_aabb_query(data: SyntheticData) -> PointLocation {
     PointLocation::Aabb(get_aabb(data))
 }
 
+// An AABB that extends beyond the data by its full size in each direction.
+pub fn get_large_aabb(data: SyntheticData) -> Aabb {
+    let min_corner = data.bbox().min() - data.bbox().diag();
+    let max_corner = data.bbox().max() + data.bbox().diag();
+    Aabb::new(min_corner, max_corner)
+}
+
+pub fn get_large_aabb_query(data: SyntheticData) -> PointLocation {
+    PointLocation::Aabb(get_large_aabb(data))
+}
+
 // An OBB that lies in the center of the point cloud and is aligned with gravity.
 // Its half-extent is half of that of the data.
 pub fn get_obb(data: SyntheticData) -> Obb {
@@ -8,6 +8,46 @@ use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use std::collections::BTreeMap;
 
+// Timestamps start here and increase by this much per point, in nanoseconds.
+const START_TIMESTAMP_NS: u64 = 1_500_000_000_000_000_000;
+const TIMESTAMP_STEP_NS: u64 = 10_000;
+const NUM_BUILDINGS: usize = 8;
+const NUM_TREES: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scene {
+    /// Points uniformly distributed in the bounding box.
+    Uniform,
+    /// A ground plane whose density increases towards -x, box-shaped buildings standing on it and
+    /// clusters of vegetation.
+    Structured,
+}
+
+/// The class of a point in a structured scene. All points of a uniform scene are 'Unclassified'.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Unclassified = 0,
+    Ground = 1,
+    Building = 2,
+    Vegetation = 3,
+}
+
+/// A point with the attributes beyond those of 'Point'.
+#[derive(Clone, Debug)]
+pub struct SyntheticPoint {
+    pub point: Point,
+    pub class: Class,
+    /// Nanoseconds, increasing with every point.
+    pub timestamp: u64,
+}
+
+/// An axis-aligned box standing on the ground, in local coordinates.
+#[derive(Clone, Debug)]
+struct Building {
+    min: Point3<f64>,
+    max: Point3<f64>,
+}
+
 #[derive(Clone)]
 pub struct SyntheticData {
     rng: StdRng,
@@ -16,31 +56,155 @@ pub struct SyntheticData {
     ecef_from_local: Isometry3<f64>,
     size: usize,
     count: usize,
+    scene: Scene,
+    buildings: Vec<Building>,
+    // Centers of the vegetation clusters on the ground, in local coordinates.
+    trees: Vec<Point3<f64>>,
 }
 
 impl SyntheticData {
     pub fn new(width: f64, height: f64, size: usize, seed: u64) -> Self {
+        Self::with_scene(width, height, size, seed, Scene::Uniform)
+    }
+
+    /// Like 'new', but for the given kind of scene. The same seed always gives the same points.
+    pub fn with_scene(width: f64, height: f64, size: usize, seed: u64, scene: Scene) -> Self {
         assert!(size <= 16_777_216, "Only up to 2^24 points can be indexed.");
         let mut rng = StdRng::seed_from_u64(seed);
         let lat = rng.gen_range(-90.0, 90.0);
         let lon = rng.gen_range(-180.0, 180.0);
         let ecef_from_local = local_frame_from_lat_lng(lat, lon).inverse();
+        let (half_width, half_height) = (width * 0.5, height * 0.5);
+        let (mut buildings, mut trees) = (Vec::new(), Vec::new());
+        if scene == Scene::Structured {
+            for _ in 0..NUM_BUILDINGS {
+                let half_size = rng.gen_range(0.05, 0.15) * half_width;
+                let x = rng.gen_range(-half_width + half_size, half_width - half_size);
+                let y = rng.gen_range(-half_width + half_size, half_width - half_size);
+                let z = -half_height + rng.gen_range(0.3, 1.) * 2. * half_height;
+                buildings.push(Building {
+                    min: Point3::new(x - half_size, y - half_size, -half_height),
+                    max: Point3::new(x + half_size, y + half_size, z),
+                });
+            }
+            for _ in 0..NUM_TREES {
+                let x = rng.gen_range(-half_width, half_width);
+                let y = rng.gen_range(-half_width, half_width);
+                trees.push(Point3::new(x, y, -half_height));
+            }
+        }
         SyntheticData {
             rng,
-            half_width: width * 0.5,
-            half_height: height * 0.5,
+            half_width,
+            half_height,
             ecef_from_local,
             size,
             count: 0,
+            scene,
+            buildings,
+            trees,
         }
     }
 
     pub fn next_pos(&mut self) -> Point3<f64> {
+        let pt_local = match self.scene {
+            Scene::Uniform => self.next_uniform_local_pos(),
+            Scene::Structured => self.next_structured_local_pos().0,
+        };
+        self.ecef_from_local.transform_point(&pt_local)
+    }
+
+    fn next_uniform_local_pos(&mut self) -> Point3<f64> {
         let x = self.rng.gen_range(-self.half_width, self.half_width);
         let y = self.rng.gen_range(-self.half_width, self.half_width);
         let z = self.rng.gen_range(-self.half_height, self.half_height);
-        let pt_local = Point3::new(x, y, z);
-        self.ecef_from_local.transform_point(&pt_local)
+        Point3::new(x, y, z)
+    }
+
+    fn next_structured_local_pos(&mut self) -> (Point3<f64>, Class) {
+        let (hw, hh) = (self.half_width, self.half_height);
+        let choice: f64 = self.rng.gen();
+        let (pos, class) = if choice < 0.5 {
+            // Squaring makes the ground denser towards -x.
+            let u: f64 = self.rng.gen();
+            let x = -hw + 2. * hw * u * u;
+            let y = self.rng.gen_range(-hw, hw);
+            let z = -hh + self.rng.gen_range(0., 0.01 * hh);
+            (Point3::new(x, y, z), Class::Ground)
+        } else if choice < 0.8 {
+            let building = &self.buildings[self.rng.gen_range(0, self.buildings.len())];
+            let (min, max) = (building.min, building.max);
+            let mut pos = Point3::new(
+                self.rng.gen_range(min.x, max.x),
+                self.rng.gen_range(min.y, max.y),
+                self.rng.gen_range(min.z, max.z),
+            );
+            // Move the point onto one of the four walls or the roof.
+            match self.rng.gen_range(0, 5) {
+                0 => pos.x = min.x,
+                1 => pos.x = max.x,
+                2 => pos.y = min.y,
+                3 => pos.y = max.y,
+                _ => pos.z = max.z,
+            }
+            (pos, Class::Building)
+        } else {
+            let tree = self.trees[self.rng.gen_range(0, self.trees.len())];
+            let radius = 0.03 * hw;
+            // The sum of uniform samples is denser in the middle, like a crown.
+            let mut offset =
+                || (self.rng.gen_range(-1., 1.) + self.rng.gen_range(-1., 1.)) * 0.5 * radius;
+            let (dx, dy) = (offset(), offset());
+            let z = tree.z + self.rng.gen_range(0., 0.6) * hh;
+            (Point3::new(tree.x + dx, tree.y + dy, z), Class::Vegetation)
+        };
+        let clamped = Point3::new(
+            pos.x.max(-hw).min(hw),
+            pos.y.max(-hw).min(hw),
+            pos.z.max(-hh).min(hh),
+        );
+        (clamped, class)
+    }
+
+    /// Returns the next point with all its attributes. The intensity increases along the local
+    /// x axis, and in structured scenes also depends on the class.
+    pub fn next_synthetic_point(&mut self) -> Option<SyntheticPoint> {
+        if self.count == self.size {
+            return None;
+        }
+        let (pt_local, class) = match self.scene {
+            Scene::Uniform => (self.next_uniform_local_pos(), Class::Unclassified),
+            Scene::Structured => self.next_structured_local_pos(),
+        };
+        let gradient = (pt_local.x + self.half_width) / (2. * self.half_width);
+        let intensity = 100. * gradient as f32 + 50. * class as u8 as f32;
+        let point = Point {
+            position: self.ecef_from_local.transform_point(&pt_local),
+            // Encode index in color, which is preserved in octrees.
+            color: Some(Color::<u8> {
+                red: (self.count >> 16) as u8,
+                green: (self.count >> 8) as u8,
+                blue: self.count as u8,
+                alpha: 0,
+            }),
+            intensity: Some(intensity),
+        };
+        let timestamp = START_TIMESTAMP_NS + self.count as u64 * TIMESTAMP_STEP_NS;
+        self.count += 1;
+        Some(SyntheticPoint {
+            point,
+            class,
+            timestamp,
+        })
+    }
+
+    /// Batches of the points with the attributes color, intensity, class and timestamp, with the
+    /// types of the standard attributes. The timestamp is in seconds.
+    pub fn into_batches_with_attributes(self, batch_size: usize) -> BatchedWithAttributes {
+        BatchedWithAttributes {
+            inner: self,
+            batch_size,
+        }
     }
 
     pub fn bbox(&self) -> Aabb {
@@ -58,23 +222,7 @@ impl Iterator for SyntheticData {
     type Item = Point;
 
     fn next(&mut self) -> Option<Point> {
-        if self.count == self.size {
-            return None;
-        }
-        let position = self.next_pos();
-        let point = Point {
-            position,
-            // Encode index in color, which is preserved in octrees.
-            color: Color::<u8> {
-                red: (self.count >> 16) as u8,
-                green: (self.count >> 8) as u8,
-                blue: self.count as u8,
-                alpha: 0,
-            },
-            intensity: None,
-        };
-        self.count += 1;
-        Some(point)
+        self.next_synthetic_point().map(|p| p.point)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -82,6 +230,53 @@ impl Iterator for SyntheticData {
     }
 }
 
+pub struct BatchedWithAttributes {
+    inner: SyntheticData,
+    batch_size: usize,
+}
+
+impl NumberOfPoints for BatchedWithAttributes {
+    fn num_points(&self) -> usize {
+        self.inner.size_hint().1.unwrap()
+    }
+}
+
+impl Iterator for BatchedWithAttributes {
+    type Item = PointsBatch;
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        let mut position = Vec::with_capacity(self.batch_size);
+        let mut color = Vec::with_capacity(self.batch_size);
+        let mut intensity = Vec::with_capacity(self.batch_size);
+        let mut class = Vec::with_capacity(self.batch_size);
+        let mut timestamp = Vec::with_capacity(self.batch_size);
+        while position.len() < self.batch_size {
+            let p = match self.inner.next_synthetic_point() {
+                Some(p) => p,
+                None => break,
+            };
+            position.push(p.point.position);
+            let c = p.point.color.unwrap();
+            color.push(Vector3::new(c.red, c.green, c.blue));
+            intensity.push(p.point.intensity.unwrap());
+            class.push(p.class as u8);
+            timestamp.push(p.timestamp as f64 * 1e-9);
+        }
+        if position.is_empty() {
+            return None;
+        }
+        let mut attributes = BTreeMap::new();
+        attributes.insert("color".to_owned(), AttributeData::U8Vec3(color));
+        attributes.insert("intensity".to_owned(), AttributeData::F32(intensity));
+        attributes.insert("class".to_owned(), AttributeData::U8(class));
+        attributes.insert("timestamp".to_owned(), AttributeData::F64(timestamp));
+        Some(PointsBatch {
+            position,
+            attributes,
+        })
+    }
+}
+
 pub struct Batched<T>
 where
     T: Iterator<Item = Point>,
@@ -136,7 +331,8 @@ where
         for _ in 0..self.batch_size {
             if let Some(pt) = self.inner.next() {
                 self.batch.position.push(pt.position);
-                let color = Vector3::new(pt.color.red, pt.color.green, pt.color.blue);
+                let c = pt.color.expect("Synthetic points always have a color.");
+                let color = Vector3::new(c.red, c.green, c.blue);
                 self.batch
                     .get_attribute_vec_mut("color")
                     .unwrap()
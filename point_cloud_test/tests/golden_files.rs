@@ -0,0 +1,77 @@
+// Octrees written by older versions of the code, checked in with their binary node data. They
+// must stay readable, and upgrading their meta must give the meta of the current version.
+use nalgebra::{Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::data_provider::{DataProvider, OnDiskDataProvider};
+use point_viewer::iterator::{ParallelIterator, PointQuery};
+use point_viewer::octree::{upgrade_meta_proto_to_current, Octree};
+use point_viewer::CURRENT_VERSION;
+use std::path::PathBuf;
+
+const OLDEST_VERSION: i32 = 9;
+
+fn golden_data_provider(version: i32) -> OnDiskDataProvider {
+    OnDiskDataProvider {
+        directory: PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/golden")
+            .join(format!("octree_v{}", version)),
+    }
+}
+
+fn all_points(octree: Octree) -> Vec<(Point3<f64>, Vector3<u8>)> {
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let octrees = [octree];
+    let mut points = Vec::new();
+    ParallelIterator::new(&octrees, &query, 10, 1, 1)
+        .try_for_each_batch(|batch| {
+            let colors = match batch.attributes.get("color") {
+                Some(AttributeData::U8Vec3(colors)) => colors.clone(),
+                other => panic!("Unexpected color attribute: {:?}", other),
+            };
+            points.extend(batch.position.into_iter().zip(colors));
+            Ok(())
+        })
+        .unwrap();
+    points.sort_by(|a, b| a.0.x.partial_cmp(&b.0.x).unwrap());
+    points
+}
+
+#[test]
+fn golden_octrees_are_readable() {
+    let expected = [
+        (Point3::new(0., 0., 0.), Vector3::new(255, 0, 0)),
+        (Point3::new(0.2, 0.4, 0.6), Vector3::new(0, 255, 0)),
+        (Point3::new(0.9, 0.9, 0.9), Vector3::new(0, 0, 255)),
+    ];
+    for version in OLDEST_VERSION..=CURRENT_VERSION {
+        let octree = Octree::from_data_provider(Box::new(golden_data_provider(version)))
+            .unwrap_or_else(|e| panic!("Could not read version {}: {}", version, e));
+        let points = all_points(octree);
+        assert_eq!(points.len(), expected.len(), "version {}", version);
+        for ((position, color), (expected_position, expected_color)) in
+            points.iter().zip(expected.iter())
+        {
+            assert!(
+                (position - expected_position).norm() < 1e-5,
+                "version {}: {} != {}",
+                version,
+                position,
+                expected_position
+            );
+            assert_eq!(color, expected_color, "version {}", version);
+        }
+    }
+}
+
+#[test]
+fn golden_octrees_upgrade_to_current_meta() {
+    let current = golden_data_provider(CURRENT_VERSION).meta_proto().unwrap();
+    for version in OLDEST_VERSION..CURRENT_VERSION {
+        let meta = golden_data_provider(version).meta_proto().unwrap();
+        let upgraded = upgrade_meta_proto_to_current(meta).unwrap();
+        assert_eq!(upgraded, current, "version {}", version);
+    }
+}
@@ -1,11 +1,19 @@
 use nalgebra::{Point3, Vector3};
 use num_integer::div_ceil;
+use point_cloud_test_lib::io_conditions::IoConditions;
 use point_cloud_test_lib::queries::*;
-use point_cloud_test_lib::{setup_pointcloud, Arguments, SyntheticData};
+use point_cloud_test_lib::{
+    setup_octree_client, setup_octree_client_with_io_conditions, setup_pointcloud, Arguments,
+    Class, Scene, SyntheticData,
+};
+use point_viewer::data_provider::OnDiskDataProvider;
 use point_viewer::iterator::PointCloud;
-use point_viewer::iterator::{PointLocation, PointQuery};
-use point_viewer::math::{sat, ConvexPolyhedron, PointCulling};
+use point_viewer::iterator::{PointLocation, PointQuery, QueryStrategy};
+use point_viewer::math::{sat, ClosedInterval, ConvexPolyhedron, PointCulling};
+use point_viewer::octree::{Octree, OctreeBuilder};
 use std::cmp::Ordering;
+use std::time::Duration;
+use tempdir::TempDir;
 
 #[test]
 fn num_points_in_octree_meta() {
@@ -32,6 +40,129 @@ fn num_points_in_s2_meta() {
     assert_eq!(num_points, Arguments::default().num_points as u64);
 }
 
+#[test]
+fn delayed_data_provider_returns_all_points() {
+    let args = Arguments::default();
+    let io_conditions = IoConditions {
+        latency: Duration::from_micros(10),
+        bandwidth_bytes_per_sec: Some(100_000_000_000),
+        ..Default::default()
+    };
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let count = |client: &point_cloud_client::PointCloudClient| {
+        let mut num_points = 0;
+        client
+            .for_each_point_data(&query, |batch| {
+                num_points += batch.position.len();
+                Ok(())
+            })
+            .unwrap();
+        num_points
+    };
+    let (local, _) = setup_octree_client(&args);
+    let (delayed, _) = setup_octree_client_with_io_conditions(&args, &io_conditions);
+    assert_eq!(count(&delayed), count(&local));
+    assert_eq!(count(&local), args.num_points);
+}
+
+#[test]
+fn structured_scene_is_deterministic_and_has_all_classes() {
+    let args = Arguments::default();
+    let generate = || {
+        SyntheticData::with_scene(
+            args.width,
+            args.height,
+            10_000,
+            args.seed,
+            Scene::Structured,
+        )
+    };
+    let mut classes = Vec::new();
+    let mut data = generate();
+    let mut again = generate();
+    let local_from_ecef = data.ecef_from_local().inverse();
+    let eps = 1e-6;
+    while let Some(p) = data.next_synthetic_point() {
+        let local = local_from_ecef.transform_point(&p.point.position);
+        assert!(local.x.abs() <= data.half_width + eps && local.y.abs() <= data.half_width + eps);
+        assert!(local.z.abs() <= data.half_height + eps);
+        assert_eq!(
+            p.point.position,
+            again.next_synthetic_point().unwrap().point.position
+        );
+        if !classes.contains(&p.class) {
+            classes.push(p.class);
+        }
+    }
+    for class in &[Class::Ground, Class::Building, Class::Vegetation] {
+        assert!(classes.contains(class));
+    }
+}
+
+#[test]
+fn octree_of_structured_scene_filters_by_class() {
+    let args = Arguments::default();
+    let num_points = 20_000;
+    let generate = || {
+        SyntheticData::with_scene(
+            args.width,
+            args.height,
+            num_points,
+            args.seed,
+            Scene::Structured,
+        )
+    };
+    let data = generate();
+    let bbox = data.bbox();
+    let tmp_dir = TempDir::new("octree").unwrap();
+    OctreeBuilder::new(args.resolution)
+        .with_attributes(&["color", "intensity", "class", "timestamp"])
+        .build(
+            tmp_dir.path(),
+            bbox,
+            data.into_batches_with_attributes(args.batch_size),
+        )
+        .unwrap();
+
+    let mut data = generate();
+    let mut num_buildings = 0;
+    while let Some(p) = data.next_synthetic_point() {
+        if p.class == Class::Building {
+            num_buildings += 1;
+        }
+    }
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let building = f64::from(Class::Building as u8);
+    let query = PointQuery {
+        attributes: vec!["class", "timestamp"],
+        filter_intervals: vec![("class", ClosedInterval::new(building, building))]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let mut num_points = 0;
+    for node_id in octree.nodes_in_location(&query.location) {
+        octree
+            .stream_points_for_query_in_node(&query, node_id, args.batch_size, |batch| {
+                let class: &Vec<u8> = batch.get_attribute_vec("class")?;
+                assert!(class.iter().all(|c| *c == Class::Building as u8));
+                let timestamp: &Vec<f64> = batch.get_attribute_vec("timestamp")?;
+                assert!(timestamp.iter().all(|t| *t >= 1.5e9));
+                num_points += batch.position.len();
+                Ok(())
+            })
+            .unwrap();
+    }
+    assert!(num_buildings > 0);
+    assert_eq!(num_points, num_buildings);
+}
+
 #[test]
 fn check_all_query_equality() {
     check_equality(|_| PointLocation::AllPoints)
@@ -42,6 +173,29 @@ fn check_box_query_equality() {
     check_equality(get_aabb_query)
 }
 
+#[test]
+fn s2_covering_finds_all_cells_in_large_box() {
+    let args = Arguments::default();
+    let (s2, _, data) = setup_pointcloud(&args);
+    let num_cells = s2.to_meta_proto().get_s2().get_cells().len();
+    let query = PointQuery {
+        location: get_large_aabb_query(data),
+        ..Default::default()
+    };
+    let plan = s2.plan_query(&query);
+    // Too many cells to scan, so only the cells within the covering are tested.
+    assert!(num_cells > 64);
+    assert_eq!(plan.num_nodes, num_cells);
+    assert_eq!(plan.strategy, QueryStrategy::Covering);
+    // The box contains every point, so every cell must be found.
+    assert_eq!(plan.node_ids.len(), num_cells);
+}
+
+#[test]
+fn check_large_box_query_equality() {
+    check_equality(get_large_aabb_query)
+}
+
 #[test]
 fn check_frustum_query_equality() {
     check_equality(get_frustum_query)
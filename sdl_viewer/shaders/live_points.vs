@@ -0,0 +1,23 @@
+#version 410 core
+
+// inputs
+layout(location = 0) in dvec3 position;
+layout(location = 1) in vec3 color;
+// Seconds since the viewer started listening when the point arrived.
+layout(location = 2) in float arrival;
+
+uniform dmat4 world_to_gl;
+uniform float size;
+uniform float gamma;
+uniform float now;
+uniform float fade_seconds;
+
+// varying outputs
+out vec4 v_color;
+
+void main() {
+  float opacity = clamp(1. - (now - arrival) / fade_seconds, 0., 1.);
+  v_color = vec4(pow(color / 255., vec3(1.0 / gamma)), opacity);
+  gl_PointSize = size;
+  gl_Position = vec4(world_to_gl * dvec4(position, 1.0lf));
+}
@@ -3,20 +3,84 @@
 // inputs
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 color;
+// Opacity in [0, 255]. Constant 255 for nodes without alpha.
+layout(location = 2) in float alpha;
+// Label id in [0, 65535], only set if has_labels.
+layout(location = 3) in float label;
+// Seconds since the start of the recording, only set if has_time_window.
+layout(location = 4) in float timestamp;
 
 uniform dmat4 world_to_gl;
 uniform double edge_length;
 uniform float size;
+// If positive, points at this distance from the camera are 'size' pixels large, closer ones larger
+// and farther ones smaller.
+uniform float attenuation_distance;
 uniform float gamma;
 uniform dvec3 min;
+uniform bool has_labels;
+uniform bool color_by_label;
+// The color of each label id, in rows of 256 ids. Labels with an alpha of 0 are hidden.
+uniform sampler2D label_colors;
+uniform bool has_selection;
+// Maps selected points into the unit cube.
+uniform dmat4 selection_clip_from_world;
+// If set, positions are transformed with the single precision matrices below, which map the unit
+// cube of the node directly into clip space. They are computed in double precision on the CPU.
+uniform bool relative_to_node;
+uniform mat4 gl_from_node;
+uniform mat4 selection_clip_from_node;
+// Only points with timestamps within the window are drawn.
+uniform bool has_time_window;
+uniform vec2 time_window;
+
+// Attenuated points are at least a pixel and at most this many pixels large.
+const float MAX_ATTENUATED_SIZE = 64.;
 
 // varying outputs
 out vec4 v_color;
 
 void main() {
-  vec3 corrected_color = pow(color / 255., vec3(1.0 / gamma));
-  v_color = vec4(corrected_color, 1.);
+  if (has_time_window && (timestamp < time_window.x || timestamp > time_window.y)) {
+    // Outside of the clip volume, so the point is discarded.
+    gl_Position = vec4(2., 2., 2., 1.);
+    return;
+  }
+  vec3 point_color = color;
+  if (has_labels) {
+    int id = int(label);
+    vec4 label_color = texelFetch(label_colors, ivec2(id % 256, id / 256), 0);
+    if (label_color.a == 0.) {
+      // Outside of the clip volume, so the point is discarded.
+      gl_Position = vec4(2., 2., 2., 1.);
+      return;
+    }
+    if (color_by_label) {
+      point_color = label_color.rgb * 255.;
+    }
+  }
+  vec4 node_position = vec4(vec3(position), 1.);
+  dvec4 world_position = dvec4(0.lf);
+  if (!relative_to_node) {
+    world_position = dvec4(dvec3(position) * edge_length + min, 1.0lf);
+  }
+  vec3 corrected_color = pow(point_color / 255., vec3(1.0 / gamma));
+  if (has_selection) {
+    vec4 selection_position = relative_to_node
+        ? selection_clip_from_node * node_position
+        : vec4(selection_clip_from_world * world_position);
+    vec3 ndc = selection_position.xyz / selection_position.w;
+    if (selection_position.w > 0. && all(lessThanEqual(abs(ndc), vec3(1.)))) {
+      corrected_color = mix(corrected_color, vec3(1., 0.5, 0.), 0.6);
+    }
+  }
+  v_color = vec4(corrected_color, alpha / 255.);
+  gl_Position = relative_to_node ? gl_from_node * node_position
+                                 : vec4(world_to_gl * world_position);
   gl_PointSize = size;
-  gl_Position =
-      vec4(world_to_gl * dvec4(dvec3(position) * edge_length + min, 1.0lf));
+  if (attenuation_distance > 0.) {
+    // With a perspective projection, w is the distance along the view direction.
+    float distance = max(gl_Position.w, 1e-3);
+    gl_PointSize = clamp(size * attenuation_distance / distance, 1., MAX_ATTENUATED_SIZE);
+  }
 }
@@ -7,8 +7,14 @@ layout (triangle_strip, max_vertices = 3) out;
 in VS_OUT {
     vec4 color;
     uint quads;
+    uint inner;
 } gs_in[];
 
+// Only the finest clipmap level has one texel per terrain pixel. In the coarser levels, the
+// adjacency bits of neighboring vertices don't refer to the same quads anymore, so a triangle is
+// drawn if all its vertices have any adjacent quad.
+uniform int exact_quads;
+
 out vec4 color;
 
 // Why this stage?
@@ -72,8 +78,17 @@ out vec4 color;
 // will still be semi-visible and it will look bad if one vertex has a nonsense position.
 
 void main() {
-  uint render_quad = gs_in[0].quads & gs_in[1].quads & gs_in[2].quads;
-  if (render_quad > 0) {
+  // Triangles that the next finer level covers are drawn by that level.
+  if ((gs_in[0].inner & gs_in[1].inner & gs_in[2].inner) > 0) {
+    return;
+  }
+  bool render_quad;
+  if (exact_quads != 0) {
+    render_quad = (gs_in[0].quads & gs_in[1].quads & gs_in[2].quads) > 0;
+  } else {
+    render_quad = gs_in[0].quads > 0 && gs_in[1].quads > 0 && gs_in[2].quads > 0;
+  }
+  if (render_quad) {
 	  gl_Position = gl_in[0].gl_Position;
 	  color = gs_in[0].color;
 	  EmitVertex();
@@ -9,7 +9,14 @@ uniform double terrain_res_m;
 // The grid coordinates of the min corner of the currently visible terrain (the window).
 // Essentially, the terrain regular grid with resolution terrain_res_m and origin
 // terrain_origin_m, and the mesh is rendering a rectangle within that grid.
+// It is in units of the level's cell size, i.e. level_scale terrain pixels.
 uniform dvec2 terrain_pos;
+// The clipmap level's cell size in terrain pixels, a power of two.
+uniform double level_scale;
+// The grid coordinates (like terrain_pos) of the region that the next finer level covers.
+// If inner_min > inner_max, nothing is covered.
+uniform dvec2 inner_min;
+uniform dvec2 inner_max;
 // These two are equal and set by the GlMovingWindowTexture.
 // They hold the coordinates (in pixels) of the min corner
 // of the texture within the sampler.
@@ -28,6 +35,8 @@ uniform sampler2D color;
 out VS_OUT {
   vec4 color;
   uint quads;
+  // Whether the vertex is covered by the next finer level.
+  uint inner;
 } vs_out;
 
 void main() {
@@ -42,12 +51,15 @@ void main() {
   vec4 tex = texelFetch(height, texCoordModSize, 0);
   dvec4 local_pos = dvec4(terrain_origin_m, 1.0lf);
   // Calculate the vertex position in the terrain coordinate system.
-  local_pos.xy += terrain_res_m * (dvec2(aPos.xy) + terrain_pos);
+  dvec2 grid_pos = dvec2(aPos.xy) + terrain_pos;
+  local_pos.xy += terrain_res_m * level_scale * grid_pos;
   local_pos.z += double(tex.x);
   // Transform from terrain coordinates to Gl camerae coordinates.
   gl_Position = vec4(world_to_gl * terrain_to_world * local_pos);
   // The second channel contains the quad adjacency list.
   vs_out.quads = uint(tex.y);
+  vs_out.inner = uint(all(greaterThanEqual(grid_pos, inner_min)) &&
+                      all(lessThanEqual(grid_pos, inner_max)));
   // Look up the color.
   vs_out.color = texelFetch(color, texCoordModSize, 0);
   vs_out.color.w = 1.0f;
@@ -127,7 +127,7 @@ impl BoxDrawer {
     }
 
     // Draws the outline of the box where each vertex is transformed with 'transform'.
-    fn draw_outlines_from_transformation(
+    pub fn draw_outlines_from_transformation(
         &self,
         transform: &Matrix4<f64>,
         color: &color::Color<f32>,
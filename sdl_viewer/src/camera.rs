@@ -13,12 +13,12 @@
 // limitations under the License.
 
 use crate::opengl;
-use nalgebra::{Isometry3, Matrix4, Perspective3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, UnitQuaternion, Vector3};
 
 use serde_derive::{Deserialize, Serialize};
 use std::f64;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct RotationAngle {
     /// Horizontal angle in radians
     theta: f64,
@@ -35,8 +35,9 @@ impl RotationAngle {
     }
 }
 
-#[derive(Debug)]
-struct CtMode {
+/// Near and far clipping planes that cut a slab out of the point cloud, like a CT scan.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct CtMode {
     pub enabled: bool,
     near_plane: f32,
     far_plane: f32,
@@ -60,7 +61,19 @@ pub struct Camera {
     movement_speed: f64,
     theta: f64,
     phi: f64,
+    // The direction we currently want to move in from the joystick and remote control, in camera
+    // coordinates. Keyboard input is added to it in 'update'.
     pan: Vector3<f64>,
+    // An absolute value to move by from dragging with the mouse, which the camera follows
+    // directly.
+    drag_pan: Vector3<f64>,
+
+    // How long it takes to reach about 63 % of the speed that the input asks for, or to slow down
+    // by that much after the input stopped. Zero follows the input instantly.
+    smoothing_time: time::Duration,
+    // The smoothed 'pan' and 'rotation_speed'.
+    velocity: Vector3<f64>,
+    angular_velocity: RotationAngle,
 
     // The speed we currently want to rotate at. This is multiplied with the seconds since the last
     // frame to get to an absolute rotation.
@@ -78,13 +91,101 @@ pub struct Camera {
     local_from_global: Isometry3<f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// The pose of the camera. Its rotation is always 'rotation(theta, phi)', i.e. the camera never
+/// rolls.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct State {
     transform: Isometry3<f64>,
     phi: f64,
     theta: f64,
 }
 
+/// Looking down the negative z axis, turned by 'theta' around the z axis and tilted up by 'phi'.
+fn rotation(theta: f64, phi: f64) -> UnitQuaternion<f64> {
+    let rotation_z = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), theta);
+    let rotation_x = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), phi);
+    rotation_z * rotation_x
+}
+
+/// The difference 'to' - 'from' of two angles, the short way around.
+fn angle_difference(from: f64, to: f64) -> f64 {
+    let difference = (to - from) % (2. * f64::consts::PI);
+    if difference > f64::consts::PI {
+        difference - 2. * f64::consts::PI
+    } else if difference < -f64::consts::PI {
+        difference + 2. * f64::consts::PI
+    } else {
+        difference
+    }
+}
+
+impl State {
+    /// The camera at 'position' in local coordinates, see 'rotation' for the angles.
+    pub fn new(position: Point3<f64>, theta: f64, phi: f64) -> Self {
+        State {
+            transform: Isometry3::from_parts(position.coords.into(), rotation(theta, phi)),
+            phi,
+            theta,
+        }
+    }
+
+    /// The camera to local transform.
+    pub fn transform(&self) -> &Isometry3<f64> {
+        &self.transform
+    }
+
+    pub fn position(&self) -> Point3<f64> {
+        Point3::from(self.transform.translation.vector)
+    }
+
+    /// The pose at 't' between this one at 0 and 'other' at 1, moving on a straight line and
+    /// turning the short way around at a constant rate.
+    pub fn interpolate(&self, other: &State, t: f64) -> State {
+        State::new(
+            self.position() + (other.position() - self.position()) * t,
+            self.theta + angle_difference(self.theta, other.theta) * t,
+            self.phi + (other.phi - self.phi) * t,
+        )
+    }
+
+    /// The pose at 't' between 'from' at 0 and 'to' at 1 on a Catmull-Rom spline through
+    /// 'before', 'from', 'to' and 'after'. Consecutive segments of a path of poses join without
+    /// kinks in the motion, unlike with 'interpolate'.
+    pub fn spline(before: &State, from: &State, to: &State, after: &State, t: f64) -> State {
+        let (t2, t3) = (t * t, t * t * t);
+        let weights = [
+            -0.5 * t3 + t2 - 0.5 * t,
+            1.5 * t3 - 2.5 * t2 + 1.,
+            -1.5 * t3 + 2. * t2 + 0.5 * t,
+            0.5 * t3 - 0.5 * t2,
+        ];
+        // The angles are unwrapped relative to 'from', so that the spline turns the short way.
+        let thetas = [
+            from.theta - angle_difference(before.theta, from.theta),
+            from.theta,
+            from.theta + angle_difference(from.theta, to.theta),
+            from.theta
+                + angle_difference(from.theta, to.theta)
+                + angle_difference(to.theta, after.theta),
+        ];
+        let states = [before, from, to, after];
+        let mut position = Vector3::zeros();
+        let (mut theta, mut phi) = (0., 0.);
+        for i in 0..4 {
+            position += states[i].transform.translation.vector * weights[i];
+            theta += thetas[i] * weights[i];
+            phi += states[i].phi * weights[i];
+        }
+        State::new(Point3::from(position), theta, phi)
+    }
+}
+
+/// The default of 'Camera::set_smoothing_time'.
+const SMOOTHING_TIME_MS: i64 = 150;
+
+/// Smoothed speeds below this, relative to the full speed, count as standing still.
+const MIN_RELATIVE_SPEED: f64 = 1e-3;
+
 const FAR_PLANE: f32 = 10000.;
 const NEAR_PLANE: f32 = 0.1;
 
@@ -112,6 +213,10 @@ impl Camera {
             theta: 0.0,
             phi: 0.0,
             pan: nalgebra::zero(),
+            drag_pan: nalgebra::zero(),
+            smoothing_time: time::Duration::milliseconds(SMOOTHING_TIME_MS),
+            velocity: nalgebra::zero(),
+            angular_velocity: RotationAngle::zero(),
             rotation_speed: RotationAngle::zero(),
             delta_rotation: RotationAngle::zero(),
             transform: Isometry3::translation(0., 0., 150.),
@@ -157,9 +262,19 @@ impl Camera {
         self.transform = state.transform;
         self.phi = state.phi;
         self.theta = state.theta;
+        // The camera does not drift away from a pose that was set.
+        self.velocity = nalgebra::zero();
+        self.angular_velocity = RotationAngle::zero();
         self.moved = true;
     }
 
+    /// How long the camera takes to speed up to about 63 % of the speed that the keyboard or
+    /// joystick asks for, and to slow down by as much when they are released. Zero makes it
+    /// follow the input instantly.
+    pub fn set_smoothing_time(&mut self, smoothing_time: time::Duration) {
+        self.smoothing_time = smoothing_time;
+    }
+
     pub fn set_size(&mut self, gl: &opengl::Gl, width: i32, height: i32) {
         self.width = width;
         self.height = height;
@@ -186,11 +301,28 @@ impl Camera {
         self.moved = true;
     }
 
+    pub fn ct_mode(&self) -> CtMode {
+        self.ct_mode
+    }
+
+    pub fn set_ct_mode(&mut self, ct_mode: CtMode, gl: &opengl::Gl) {
+        self.ct_mode = ct_mode;
+        self.update_viewport(gl);
+    }
+
     pub fn toggle_ct_mode(&mut self, gl: &opengl::Gl) {
         self.ct_mode.enabled = !self.ct_mode.enabled;
         self.update_viewport(gl);
     }
 
+    pub fn local_from_global(&self) -> &Isometry3<f64> {
+        &self.local_from_global
+    }
+
+    pub fn local_from_camera(&self) -> &Isometry3<f64> {
+        &self.transform
+    }
+
     pub fn get_camera_to_world(&self) -> Isometry3<f64> {
         self.local_from_global.inverse() * self.transform
     }
@@ -247,18 +379,41 @@ impl Camera {
             self.rotation_speed.phi -= TURNING_SPEED;
         }
 
+        // The speeds approach the ones asked for exponentially, which accelerates and damps the
+        // motion.
+        let smoothing_seconds = self.smoothing_time.as_seconds_f64();
+        let blend = if smoothing_seconds > 0. {
+            1. - (-elapsed_seconds / smoothing_seconds).exp()
+        } else {
+            1.
+        };
+        self.velocity += (self.pan - self.velocity) * blend;
+        self.angular_velocity.theta +=
+            (self.rotation_speed.theta - self.angular_velocity.theta) * blend;
+        self.angular_velocity.phi += (self.rotation_speed.phi - self.angular_velocity.phi) * blend;
+        if self.velocity.norm() < MIN_RELATIVE_SPEED {
+            self.velocity = nalgebra::zero();
+        }
+        if self.angular_velocity.theta.abs() < MIN_RELATIVE_SPEED * TURNING_SPEED {
+            self.angular_velocity.theta = 0.0;
+        }
+        if self.angular_velocity.phi.abs() < MIN_RELATIVE_SPEED * TURNING_SPEED {
+            self.angular_velocity.phi = 0.0;
+        }
+
         // Apply changes
-        if self.pan.norm_squared() > 0. {
+        let pan = self.velocity + self.drag_pan;
+        if pan.norm_squared() > 0. {
             moved = true;
             let translation = self
                 .transform
                 .rotation
-                .transform_vector(&(self.pan * self.movement_speed * elapsed_seconds));
+                .transform_vector(&(pan * self.movement_speed * elapsed_seconds));
             self.transform.append_translation_mut(&translation.into());
         }
 
-        if self.rotation_speed.theta != 0.0
-            || self.rotation_speed.phi != 0.0
+        if self.angular_velocity.theta != 0.0
+            || self.angular_velocity.phi != 0.0
             || self.delta_rotation.theta != 0.0
             || self.delta_rotation.phi != 0.0
         {
@@ -267,15 +422,14 @@ impl Camera {
                 self.theta += self.delta_rotation.theta;
                 self.phi += self.delta_rotation.phi;
             } else {
-                self.theta += self.rotation_speed.theta * elapsed_seconds;
-                self.phi += self.rotation_speed.phi * elapsed_seconds;
+                self.theta += self.angular_velocity.theta * elapsed_seconds;
+                self.phi += self.angular_velocity.phi * elapsed_seconds;
             }
-            let rotation_z = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), self.theta);
-            let rotation_x = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.phi);
-            self.transform.rotation = rotation_z * rotation_x;
+            self.transform.rotation = rotation(self.theta, self.phi);
         }
 
         self.pan = nalgebra::zero();
+        self.drag_pan = nalgebra::zero();
         self.rotation_speed.theta = 0.0;
         self.rotation_speed.phi = 0.0;
         self.delta_rotation.theta = 0.0;
@@ -284,8 +438,8 @@ impl Camera {
     }
 
     pub fn mouse_drag_pan(&mut self, delta_x: i32, delta_y: i32) {
-        self.pan.x -= 100. * f64::from(delta_x) / f64::from(self.width);
-        self.pan.y += 100. * f64::from(delta_y) / f64::from(self.height);
+        self.drag_pan.x -= 100. * f64::from(delta_x) / f64::from(self.width);
+        self.drag_pan.y += 100. * f64::from(delta_y) / f64::from(self.height);
     }
 
     pub fn mouse_drag_rotate(&mut self, delta_x: i32, delta_y: i32) {
@@ -301,6 +455,16 @@ impl Camera {
         self.movement_speed = self.movement_speed.max(0.01);
     }
 
+    /// Moves the camera to the world position ('x', 'y'), keeping its height and orientation.
+    pub fn teleport_to(&mut self, x: f64, y: f64) {
+        let world_position = self.get_camera_to_world().translation.vector;
+        let local_position =
+            self.local_from_global
+                .transform_point(&Point3::new(x, y, world_position.z));
+        self.transform.translation.vector = local_position.coords;
+        self.moved = true;
+    }
+
     pub fn pan(&mut self, x: f64, y: f64, z: f64) {
         self.pan.x += x;
         self.pan.y += y;
@@ -312,3 +476,28 @@ impl Camera {
         self.rotation_speed.theta += around;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation() {
+        let from = State::new(Point3::new(0., 0., 0.), 3., 0.);
+        let to = State::new(Point3::new(10., 0., 2.), -3., 1.);
+        let halfway = from.interpolate(&to, 0.5);
+        assert!((halfway.position() - Point3::new(5., 0., 1.)).norm() < 1e-9);
+        // Turns the short way across +/- pi.
+        assert!((halfway.theta - f64::consts::PI).abs() < 1e-9);
+        assert!((halfway.phi - 0.5).abs() < 1e-9);
+
+        // The spline passes through its control points.
+        let before = State::new(Point3::new(-10., 0., 0.), 2., 0.);
+        let after = State::new(Point3::new(20., 0., 0.), -2., 0.);
+        for (t, expected) in &[(0., &from), (1., &to)] {
+            let state = State::spline(&before, &from, &to, &after, *t);
+            assert!((state.position() - expected.position()).norm() < 1e-9);
+            assert!(angle_difference(state.theta, expected.theta).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-section profiles along a line that is drawn by clicking its ends into the minimap.
+
+use nalgebra::Point2;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::octree::Octree;
+use point_viewer::profile::{CrossSection, Profile};
+use std::path::Path;
+
+/// The size of the exported plots, without the margins for the labels.
+const PROFILE_WIDTH_PX: u32 = 1600;
+const PROFILE_HEIGHT_PX: u32 = 400;
+
+pub struct CrossSectionTool {
+    /// If set, clicks into the minimap set the ends of the line instead of moving the camera.
+    pub enabled: bool,
+    /// The width of the slab around the line.
+    pub width_m: f64,
+    /// The attribute the points of the profile are colored by.
+    pub attribute: Option<String>,
+    start: Option<Point2<f64>>,
+}
+
+impl CrossSectionTool {
+    pub fn new(width_m: f64, attribute: Option<String>) -> Self {
+        CrossSectionTool {
+            enabled: false,
+            width_m,
+            attribute,
+            start: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.start = None;
+    }
+
+    /// Sets the start of the line on the first call, and returns the cross-section through the
+    /// whole height of 'bounding_box' on the second.
+    pub fn add_point(&mut self, point: Point2<f64>, bounding_box: &Aabb) -> Option<CrossSection> {
+        match self.start.take() {
+            None => {
+                self.start = Some(point);
+                None
+            }
+            Some(start) => Some(CrossSection {
+                start,
+                end: point,
+                width_m: self.width_m,
+                min_z: bounding_box.min().z,
+                max_z: bounding_box.max().z,
+            }),
+        }
+    }
+}
+
+/// Writes the profile of 'cross_section' to 'stem' with the extensions "svg" and "png". Returns
+/// the number of points in it.
+pub fn export(
+    octree: &Octree,
+    cross_section: &CrossSection,
+    attribute: Option<&str>,
+    stem: &Path,
+) -> Result<usize> {
+    let profile = Profile::extract(std::slice::from_ref(octree), cross_section, attribute)?;
+    profile.write_svg(
+        stem.with_extension("svg"),
+        PROFILE_WIDTH_PX,
+        PROFILE_HEIGHT_PX,
+    )?;
+    profile.write_png(
+        stem.with_extension("png"),
+        PROFILE_WIDTH_PX,
+        PROFILE_HEIGHT_PX,
+    )?;
+    Ok(profile.points.len())
+}
@@ -0,0 +1,67 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Equalizes the density of the drawn points. Areas that were scanned several times, e.g. where
+//! flight lines overlap, have many more points than areas that were covered once. Drawing all of
+//! them makes those areas blindingly bright and spends the point budget on them.
+
+use fnv::FnvHashMap;
+use point_viewer::octree::{NodeId, NodeMeta, Octree};
+
+pub struct DensityEqualizer {
+    // The median density of the nodes of each level, in points per cubic meter.
+    median_density: FnvHashMap<u8, f64>,
+    pub enabled: bool,
+}
+
+fn density(node_meta: &NodeMeta) -> f64 {
+    node_meta.num_points as f64 / node_meta.bounding_cube.edge_length().powi(3)
+}
+
+impl DensityEqualizer {
+    pub fn new(octree: &Octree) -> Self {
+        let mut densities: FnvHashMap<u8, Vec<f64>> = FnvHashMap::default();
+        for node_id in octree.node_ids_up_to_level(std::u8::MAX) {
+            if let Some(node_meta) = octree.node_meta(&node_id) {
+                densities
+                    .entry(node_id.level())
+                    .or_default()
+                    .push(density(node_meta));
+            }
+        }
+        let median_density = densities
+            .into_iter()
+            .map(|(level, mut densities)| {
+                densities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                (level, densities[densities.len() / 2])
+            })
+            .collect();
+        DensityEqualizer {
+            median_density,
+            enabled: false,
+        }
+    }
+
+    /// Every how many points of the node are drawn. Nodes that are at most as dense as the median
+    /// of their level are drawn completely, denser ones are thinned out to about that density.
+    pub fn level_of_detail(&self, node_id: &NodeId, node_meta: &NodeMeta) -> i32 {
+        if !self.enabled {
+            return 1;
+        }
+        match self.median_density.get(&node_id.level()) {
+            Some(median) if *median > 0. => (density(node_meta) / median).round().max(1.) as i32,
+            _ => 1,
+        }
+    }
+}
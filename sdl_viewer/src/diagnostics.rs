@@ -0,0 +1,178 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Diagnostic bundles for failures that the viewer survives, e.g. nodes that cannot be loaded or
+//! OpenGL errors. A bundle is a JSON file with everything needed to reproduce the failure: the
+//! camera, the visible nodes, the OpenGL implementation and a summary of the point cloud.
+
+use crate::camera::State;
+use crate::opengl;
+use nalgebra::Point3;
+use point_viewer::iterator::PointCloud;
+use point_viewer::octree::{NodeId, Octree};
+use serde_derive::Serialize;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    NodeLoading,
+    OpenGl,
+}
+
+#[derive(Debug, Serialize)]
+struct GlInfo {
+    vendor: String,
+    renderer: String,
+    version: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DatasetSummary {
+    location: String,
+    version: i32,
+    resolution: f64,
+    num_nodes: usize,
+    num_points: i64,
+    bounding_box_min: Point3<f64>,
+    bounding_box_max: Point3<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Bundle<'a> {
+    reason: &'a str,
+    timestamp: i64,
+    camera: State,
+    visible_nodes: Vec<String>,
+    gl_info: &'a GlInfo,
+    dataset: &'a DatasetSummary,
+}
+
+fn gl_string(gl: &opengl::Gl, name: opengl::types::GLenum) -> String {
+    unsafe {
+        let ptr = gl.GetString(name);
+        if ptr.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr(ptr as *const _)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Returns the pending OpenGL errors, None if there are none.
+pub fn gl_errors(gl: &opengl::Gl) -> Option<String> {
+    let mut errors = Vec::new();
+    loop {
+        let error = unsafe { gl.GetError() };
+        let name = match error {
+            opengl::NO_ERROR => break,
+            opengl::INVALID_ENUM => "GL_INVALID_ENUM".to_string(),
+            opengl::INVALID_VALUE => "GL_INVALID_VALUE".to_string(),
+            opengl::INVALID_OPERATION => "GL_INVALID_OPERATION".to_string(),
+            opengl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION".to_string(),
+            opengl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY".to_string(),
+            other => format!("0x{:x}", other),
+        };
+        // A lost context reports the same error forever.
+        if errors.contains(&name) {
+            break;
+        }
+        errors.push(name);
+    }
+    if errors.is_empty() {
+        None
+    } else {
+        Some(errors.join(", "))
+    }
+}
+
+/// Writes diagnostic bundles next to the octree, or into the working directory if the octree is
+/// not a local directory. Only the first failure of each kind gets a bundle, later ones are only
+/// logged, so that a broken node or driver does not flood the disk.
+pub struct Watchdog {
+    directory: PathBuf,
+    gl_info: GlInfo,
+    dataset: DatasetSummary,
+    reported: HashSet<FailureKind>,
+}
+
+impl Watchdog {
+    pub fn new(gl: &opengl::Gl, octree_location: &str, octree: &Octree) -> Self {
+        let directory = if Path::new(octree_location).is_dir() {
+            PathBuf::from(octree_location)
+        } else {
+            PathBuf::from(".")
+        };
+        let meta = octree.to_meta_proto();
+        let nodes = meta.get_octree().get_nodes();
+        let bounding_box = octree.bounding_box();
+        Watchdog {
+            directory,
+            gl_info: GlInfo {
+                vendor: gl_string(gl, opengl::VENDOR),
+                renderer: gl_string(gl, opengl::RENDERER),
+                version: gl_string(gl, opengl::VERSION),
+            },
+            dataset: DatasetSummary {
+                location: octree_location.to_string(),
+                version: meta.get_version(),
+                resolution: meta.get_octree().get_resolution(),
+                num_nodes: nodes.len(),
+                num_points: nodes.iter().map(|node| node.num_points).sum(),
+                bounding_box_min: *bounding_box.min(),
+                bounding_box_max: *bounding_box.max(),
+            },
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Logs the failure and writes a bundle if it is the first of its kind.
+    pub fn report(
+        &mut self,
+        kind: FailureKind,
+        reason: &str,
+        camera: State,
+        visible_nodes: &[NodeId],
+    ) {
+        eprintln!("{}", reason);
+        if !self.reported.insert(kind) {
+            return;
+        }
+        let bundle = Bundle {
+            reason,
+            timestamp: time::OffsetDateTime::now_utc().timestamp(),
+            camera,
+            visible_nodes: visible_nodes.iter().map(NodeId::to_string).collect(),
+            gl_info: &self.gl_info,
+            dataset: &self.dataset,
+        };
+        match self.write(kind, &bundle) {
+            Ok(path) => eprintln!("Wrote diagnostics to {}.", path.display()),
+            Err(e) => eprintln!("Could not write diagnostics: {}", e),
+        }
+    }
+
+    fn write(&self, kind: FailureKind, bundle: &Bundle) -> io::Result<PathBuf> {
+        let path = self.directory.join(format!(
+            "viewer_diagnostics_{}_{:?}.json",
+            bundle.timestamp, kind
+        ));
+        fs::write(&path, serde_json::to_string_pretty(bundle)?)?;
+        Ok(path)
+    }
+}
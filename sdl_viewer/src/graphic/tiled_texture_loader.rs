@@ -1,10 +1,12 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use image::{GenericImage, GenericImageView, ImageBuffer, LumaA, Pixel, Rgba};
+use lru::LruCache;
 use num_integer::Integer;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use point_viewer::data_provider::DataProvider;
+use std::collections::HashSet;
+use std::io::{self, BufReader};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 /// Little trait that just dispatches to the correct read_xyz_into call for the image type
 pub trait ReadLittleEndian {
@@ -25,34 +27,149 @@ impl ReadLittleEndian for ImageBuffer<Rgba<u8>, Vec<u8>> {
 
 pub type TilePos = (i32, i32);
 
+type Tiles<P> = LruCache<TilePos, ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>>;
+
+/// The name under which the data provider serves a tile of the given resolution level, e.g.
+/// "x00000001_y00000002" at level 0 and "l2_x00000001_y00000002" at level 2.
+pub fn tile_name(level: u32, (x, y): TilePos) -> String {
+    if level == 0 {
+        format!("x{:08}_y{:08}", x, y)
+    } else {
+        format!("l{}_x{:08}_y{:08}", level, x, y)
+    }
+}
+
+fn fetch_tile<P>(
+    data_provider: &dyn DataProvider,
+    attribute: &str,
+    tile_size: u32,
+    level: u32,
+    pos: TilePos,
+) -> io::Result<ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P: Pixel + 'static,
+    ImageBuffer<P, Vec<P::Subpixel>>: ReadLittleEndian,
+{
+    let name = tile_name(level, pos);
+    let reader = data_provider
+        .data(&name, &[attribute])
+        .ok()
+        .and_then(|mut readers| readers.remove(attribute))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Could not fetch {} of tile {}.", attribute, name),
+            )
+        })?;
+    let mut tile = ImageBuffer::new(tile_size, tile_size);
+    tile.read_from(&mut BufReader::new(reader))?;
+    Ok(tile)
+}
+
+/// A sparse texture made of square tiles, which are fetched from a data provider when they are
+/// needed. Tiles can be prefetched in the background, so that they are ready when the camera
+/// gets close. Only a bounded number of tiles is kept in memory.
 pub struct TiledTextureLoader<P: Pixel> {
     tile_size: i64,
-    tiles: HashMap<TilePos, ImageBuffer<P, Vec<P::Subpixel>>>,
+    // The resolution level of the tiles, see 'tile_name'.
+    level: u32,
+    // The tiles that exist, all others are empty.
+    tile_positions: Arc<HashSet<TilePos>>,
+    tiles: Arc<Mutex<Tiles<P>>>,
+    data_provider: Arc<dyn DataProvider>,
+    attribute: &'static str,
+    prefetch_tx: mpsc::Sender<Vec<TilePos>>,
 }
 
 impl<P> TiledTextureLoader<P>
 where
-    P: Pixel + 'static,
+    P: Pixel + Send + 'static,
+    P::Subpixel: Send,
     ImageBuffer<P, Vec<P::Subpixel>>: ReadLittleEndian,
 {
-    pub fn new<I, Q>(tile_size: u32, tile_iter: I) -> Result<Self, std::io::Error>
-    where
-        I: Iterator<Item = (TilePos, Q)>,
-        Q: AsRef<Path>,
-    {
-        let mut tiles = HashMap::new();
-        for (xy, file_path) in tile_iter {
-            let mut tile = ImageBuffer::new(tile_size, tile_size);
-            let mut tile_reader = BufReader::new(File::open(file_path)?);
-            tile.read_from(&mut tile_reader)?;
-            let previous_entry = tiles.insert(xy, tile);
-            assert!(previous_entry.is_none());
-        }
+    /// 'attribute' is the data provider attribute of the tiles, e.g. "height", and 'level' their
+    /// resolution level.
+    pub fn new(
+        tile_size: u32,
+        level: u32,
+        tile_positions: impl Iterator<Item = TilePos>,
+        data_provider: Arc<dyn DataProvider>,
+        attribute: &'static str,
+        max_cached_tiles: usize,
+    ) -> Self {
+        let tile_positions: Arc<HashSet<TilePos>> = Arc::new(tile_positions.collect());
+        let tiles = Arc::new(Mutex::new(LruCache::new(max_cached_tiles)));
+
+        // This thread fetches the tiles that were requested for prefetching. If multiple requests
+        // queue up while it is fetching, only the latest one is served.
+        let (prefetch_tx, prefetch_rx) = mpsc::channel::<Vec<TilePos>>();
+        let thread_tiles = Arc::clone(&tiles);
+        let thread_data_provider = Arc::clone(&data_provider);
+        thread::spawn(move || {
+            while let Ok(mut positions) = prefetch_rx.recv() {
+                while let Ok(newer_positions) = prefetch_rx.try_recv() {
+                    positions = newer_positions;
+                }
+                for pos in positions {
+                    if thread_tiles.lock().unwrap().contains(&pos) {
+                        continue;
+                    }
+                    match fetch_tile::<P>(&*thread_data_provider, attribute, tile_size, level, pos)
+                    {
+                        Ok(tile) => {
+                            thread_tiles.lock().unwrap().put(pos, tile);
+                        }
+                        Err(err) => eprintln!("{}", err),
+                    }
+                }
+            }
+        });
 
-        Ok(TiledTextureLoader {
+        TiledTextureLoader {
             tile_size: i64::from(tile_size),
+            level,
+            tile_positions,
             tiles,
-        })
+            data_provider,
+            attribute,
+            prefetch_tx,
+        }
+    }
+
+    /// The positions of the existing tiles that overlap the specified region.
+    fn tile_positions_in(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        width: usize,
+        height: usize,
+    ) -> Vec<TilePos> {
+        let min_tile_x = Integer::div_floor(&min_x, &self.tile_size);
+        let min_tile_y = Integer::div_floor(&min_y, &self.tile_size);
+        let max_tile_x = Integer::div_floor(&(min_x + width as i64), &self.tile_size);
+        let max_tile_y = Integer::div_floor(&(min_y + height as i64), &self.tile_size);
+        let mut positions = Vec::new();
+        for tile_x in min_tile_x..=max_tile_x {
+            for tile_y in min_tile_y..=max_tile_y {
+                let pos = (tile_x as i32, tile_y as i32);
+                if self.tile_positions.contains(&pos) {
+                    positions.push(pos);
+                }
+            }
+        }
+        positions
+    }
+
+    /// Fetches the tiles of the specified region in the background, closest to its center first.
+    pub fn prefetch(&self, min_x: i64, min_y: i64, width: usize, height: usize) {
+        let mut positions = self.tile_positions_in(min_x, min_y, width, height);
+        let center_x = Integer::div_floor(&(min_x + width as i64 / 2), &self.tile_size);
+        let center_y = Integer::div_floor(&(min_y + height as i64 / 2), &self.tile_size);
+        positions.sort_by_key(|(x, y)| {
+            (i64::from(*x) - center_x).abs() + (i64::from(*y) - center_y).abs()
+        });
+        // The thread only stops when this loader is dropped.
+        self.prefetch_tx.send(positions).unwrap();
     }
 
     /// Loads the specified region of the sparse texture into a ImageBuffer
@@ -73,6 +190,8 @@ where
         let (max_tile_x, max_mod_x) = max_x.div_mod_floor(&self.tile_size);
         let (max_tile_y, max_mod_y) = max_y.div_mod_floor(&self.tile_size);
         let mut output_buffer = ImageBuffer::new(width as u32, height as u32);
+        // Tiles that were not prefetched are fetched now.
+        let mut tiles = self.tiles.lock().unwrap();
         for tile_x in min_tile_x..=max_tile_x {
             for tile_y in min_tile_y..=max_tile_y {
                 // At the left border, start with the correct offset
@@ -94,7 +213,25 @@ where
                     self.tile_size - y_off_src
                 };
 
-                if let Some(src) = self.tiles.get(&(tile_x as i32, tile_y as i32)) {
+                let pos = (tile_x as i32, tile_y as i32);
+                if !self.tile_positions.contains(&pos) {
+                    continue;
+                }
+                if !tiles.contains(&pos) {
+                    match fetch_tile::<P>(
+                        &*self.data_provider,
+                        self.attribute,
+                        self.tile_size as u32,
+                        self.level,
+                        pos,
+                    ) {
+                        Ok(tile) => {
+                            tiles.put(pos, tile);
+                        }
+                        Err(err) => eprintln!("{}", err),
+                    }
+                }
+                if let Some(src) = tiles.get(&pos) {
                     let roi = src.view(
                         x_off_src as u32,
                         y_off_src as u32,
@@ -109,4 +246,68 @@ where
         }
         output_buffer
     }
+
+    /// Like 'load', but only every 'step'th pixel of the texture in each direction, i.e. the
+    /// region is given in units of 'step' pixels.
+    pub fn load_subsampled(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        width: usize,
+        height: usize,
+        step: i64,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        if step == 1 {
+            return self.load(min_x, min_y, width, height);
+        }
+        let mut output_buffer = ImageBuffer::new(width as u32, height as u32);
+        if width == 0 || height == 0 {
+            return output_buffer;
+        }
+        // The range of output pixels that fall into a tile, clamped to the output.
+        let output_range = |tile: i64, min: i64, len: usize| {
+            let start = Integer::div_ceil(&(tile * self.tile_size), &step) - min;
+            let end = Integer::div_ceil(&((tile + 1) * self.tile_size), &step) - min;
+            start.max(0)..end.min(len as i64)
+        };
+        let mut tiles = self.tiles.lock().unwrap();
+        // We go tile by tile, so that every tile is only fetched once even if the cache is small.
+        for pos in self.tile_positions_in(
+            min_x * step,
+            min_y * step,
+            width * step as usize,
+            height * step as usize,
+        ) {
+            if !tiles.contains(&pos) {
+                match fetch_tile::<P>(
+                    &*self.data_provider,
+                    self.attribute,
+                    self.tile_size as u32,
+                    self.level,
+                    pos,
+                ) {
+                    Ok(tile) => {
+                        tiles.put(pos, tile);
+                    }
+                    Err(err) => eprintln!("{}", err),
+                }
+            }
+            let tile = match tiles.get(&pos) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            for y in output_range(i64::from(pos.1), min_y, height) {
+                let src_y = ((min_y + y) * step).mod_floor(&self.tile_size);
+                for x in output_range(i64::from(pos.0), min_x, width) {
+                    let src_x = ((min_x + x) * step).mod_floor(&self.tile_size);
+                    output_buffer.put_pixel(
+                        x as u32,
+                        y as u32,
+                        *tile.get_pixel(src_x as u32, src_y as u32),
+                    );
+                }
+            }
+        }
+        output_buffer
+    }
 }
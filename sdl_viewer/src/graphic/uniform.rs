@@ -12,6 +12,12 @@ pub trait Uniform {
     unsafe fn submit(&self, gl: &opengl::Gl, location: GLint);
 }
 
+impl Uniform for i32 {
+    unsafe fn submit(&self, gl: &opengl::Gl, location: GLint) {
+        gl.Uniform1i(location, *self);
+    }
+}
+
 impl Uniform for f32 {
     unsafe fn submit(&self, gl: &opengl::Gl, location: GLint) {
         gl.Uniform1f(location, *self);
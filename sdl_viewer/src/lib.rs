@@ -22,7 +22,8 @@ macro_rules! c_str {
     };
 }
 
-mod camera;
+pub mod camera;
+pub mod cross_section;
 #[allow(
     non_upper_case_globals,
     clippy::missing_safety_doc,
@@ -36,47 +37,130 @@ pub mod opengl {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 pub mod box_drawer;
+mod density;
+mod diagnostics;
 pub mod graphic;
+pub mod live;
+pub mod minimap;
 pub mod node_drawer;
+pub mod occlusion_culler;
+pub mod picking;
+pub mod playback;
+pub mod plugin;
+mod remote;
+pub mod selection;
+mod session;
+pub mod spatial_context;
 pub mod terrain_drawer;
 
 use crate::box_drawer::BoxDrawer;
 use crate::camera::Camera;
-use crate::node_drawer::{NodeDrawer, NodeViewContainer};
+use crate::cross_section::CrossSectionTool;
+use crate::density::DensityEqualizer;
+use crate::diagnostics::{FailureKind, Watchdog};
+use crate::live::LivePoints;
+use crate::minimap::Minimap;
+use crate::node_drawer::{NodeDrawer, NodeViewContainer, NUM_LABEL_IDS};
+use crate::occlusion_culler::OcclusionCuller;
+use crate::picking::PickedPoint;
+use crate::playback::Playback;
+use crate::plugin::Plugin;
+use crate::remote::{Command, Layer};
+use crate::selection::Selection;
+use crate::session::Session;
+use crate::spatial_context::SpatialContext;
 use crate::terrain_drawer::TerrainRenderer;
-use nalgebra::{Isometry3, Matrix4};
-use point_viewer::color::YELLOW;
-use point_viewer::data_provider::DataProviderFactory;
-use point_viewer::octree::{self, Octree};
+use fnv::FnvHashSet;
+use nalgebra::{Isometry3, Matrix4, Point2, Point3};
+use point_viewer::color::{Color, CYAN, MAGENTA, RED, WHITE, YELLOW};
+use point_viewer::data_provider::{
+    DataProvider, DataProviderFactory, DataSource, OverlayDataProvider,
+};
+use point_viewer::geometry::{Aabb, CachedFrustumIntersector, Cube};
+use point_viewer::iterator::PointCloud;
+use point_viewer::labels::{LabelPalette, LABEL_ATTRIBUTES};
+use point_viewer::math::ClosedInterval;
+use point_viewer::octree::{self, ColorSource, Octree};
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::{Mod, Scancode};
+use sdl2::mouse::MouseButton;
 use sdl2::video::{GLProfile, SwapInterval};
 use std::cmp;
+use std::collections::HashMap;
 use std::io;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{mpsc, Arc};
 use std::thread;
 
+/// The distance in meters at which attenuated points have the set point size, and its limits.
+const DEFAULT_ATTENUATION_DISTANCE: f32 = 20.;
+const MIN_ATTENUATION_DISTANCE: f32 = 0.1;
+const MAX_ATTENUATION_DISTANCE: f32 = 10_000.;
+
 struct PointCloudRenderer {
     gl: Rc<opengl::Gl>,
     node_drawer: NodeDrawer,
     last_moving: time::Instant,
     // TODO(sirver): Logging does not fit into this classes responsibilities.
     last_log: time::Instant,
+    octree: Arc<octree::Octree>,
     visible_nodes: Vec<octree::NodeId>,
     get_visible_nodes_params_tx: mpsc::Sender<Matrix4<f64>>,
     get_visible_nodes_result_rx: mpsc::Receiver<Vec<octree::NodeId>>,
     num_frames: u32,
     point_size: f32,
+    // Whether points are drawn larger close to the camera and smaller far from it.
+    attenuate_point_size: bool,
+    // The distance in meters at which attenuated points are 'point_size' pixels large.
+    attenuation_distance: f32,
     gamma: f32,
     needs_drawing: bool,
     max_nodes_in_memory: usize,
     world_to_gl: Matrix4<f64>,
     max_nodes_moving: usize,
     show_octree_nodes: bool,
+    // Outlines the nodes in the colors of their last load latency and source.
+    show_load_latency: bool,
     node_views: NodeViewContainer,
     box_drawer: BoxDrawer,
+    label_palette: Option<LabelPalette>,
+    color_by_label: bool,
+    color_by_intensity: bool,
+    hidden_labels: FnvHashSet<u16>,
+    // The label that the visibility toggle applies to.
+    selected_label: Option<u16>,
+    // Set if nodes hidden behind other points should not count against the node budget.
+    occlusion_culler: Option<OcclusionCuller>,
+    minimap: Minimap,
+    selection: Selection,
+    cross_section_tool: CrossSectionTool,
+    // Points streamed in while the viewer runs, if it listens for them.
+    live_points: Option<LivePoints>,
+    // Set if the points have timestamps.
+    playback: Option<Playback>,
+    density_equalizer: DensityEqualizer,
+}
+
+/// Green for loads that took up to a millisecond, through yellow at about 30 ms to red for loads
+/// that took a second or longer.
+fn latency_color(latency: time::Duration) -> Color<f32> {
+    let milliseconds = latency.as_seconds_f64() * 1000.;
+    let t = (milliseconds.max(1.).log10() / 3.).min(1.) as f32;
+    Color {
+        red: (2. * t).min(1.),
+        green: (2. * (1. - t)).min(1.),
+        blue: 0.,
+        alpha: 1.,
+    }
+}
+
+fn source_color(source: DataSource) -> Color<f32> {
+    match source {
+        DataSource::Cache => CYAN,
+        DataSource::Disk => WHITE,
+        DataSource::Network => MAGENTA,
+    }
 }
 
 #[derive(Debug)]
@@ -99,13 +183,33 @@ impl PointCloudRenderer {
         let (get_visible_nodes_params_tx, rx) = mpsc::channel::<Matrix4<f64>>();
         let (tx, get_visible_nodes_result_rx) = mpsc::channel();
         let octree_clone = octree.clone();
+        let label_palette = octree.label_palette().cloned();
+        let minimap = Minimap::new(
+            octree.bounding_box(),
+            octree.node_ids_up_to_level(minimap::MAX_LEVEL),
+        );
+        let density_equalizer = DensityEqualizer::new(&octree);
+        let mut node_drawer = NodeDrawer::new(&Rc::clone(&gl));
+        let timestamp_range = octree.attribute_range("timestamp");
+        if let Some(timestamp_range) = timestamp_range {
+            node_drawer.set_time_origin(timestamp_range.lower_bound());
+        }
         thread::spawn(move || {
+            // Kept across requests, so that moving without turning does not recompute the
+            // separating axes.
+            let mut frustum: Option<CachedFrustumIntersector> = None;
             while let Ok(mut matrix) = rx.recv() {
                 // Drain the channel, we only ever want to update the latest.
                 while let Ok(newer_matrix) = rx.try_recv() {
                     matrix = newer_matrix;
                 }
-                let visible_nodes = octree_clone.get_visible_nodes(&matrix);
+                if let Some(frustum) = &mut frustum {
+                    frustum.update(&matrix).expect("Invalid projection matrix.");
+                } else {
+                    frustum = CachedFrustumIntersector::new(matrix);
+                }
+                let frustum = frustum.as_ref().expect("Invalid projection matrix.");
+                let visible_nodes = octree_clone.get_visible_nodes_cached(frustum);
                 tx.send(visible_nodes).unwrap();
             }
         });
@@ -114,20 +218,38 @@ impl PointCloudRenderer {
             last_moving: now,
             last_log: now,
             visible_nodes: Vec::new(),
-            node_drawer: NodeDrawer::new(&Rc::clone(&gl)),
+            node_drawer,
             num_frames: 0,
             point_size: 1.,
+            attenuate_point_size: false,
+            attenuation_distance: DEFAULT_ATTENUATION_DISTANCE,
             gamma: 1.,
             get_visible_nodes_params_tx,
             get_visible_nodes_result_rx,
             max_nodes_moving: max_nodes_in_memory,
             needs_drawing: true,
             show_octree_nodes: false,
+            show_load_latency: false,
             max_nodes_in_memory,
-            node_views: NodeViewContainer::new(octree, max_nodes_in_memory),
+            node_views: NodeViewContainer::new(Arc::clone(&octree), max_nodes_in_memory),
+            octree,
             box_drawer: BoxDrawer::new(&Rc::clone(&gl)),
             world_to_gl: Matrix4::identity(),
             gl,
+            selected_label: label_palette
+                .as_ref()
+                .and_then(|p| p.labels.keys().next().copied()),
+            label_palette,
+            color_by_label: false,
+            color_by_intensity: false,
+            hidden_labels: FnvHashSet::default(),
+            occlusion_culler: None,
+            minimap,
+            selection: Selection::default(),
+            cross_section_tool: CrossSectionTool::new(1., Some("color".to_string())),
+            live_points: None,
+            playback: timestamp_range.map(Playback::new),
+            density_equalizer,
         }
     }
 
@@ -140,10 +262,217 @@ impl PointCloudRenderer {
         self.world_to_gl = *world_to_gl;
     }
 
+    pub fn request_redraw(&mut self) {
+        self.needs_drawing = true;
+    }
+
     pub fn toggle_show_octree_nodes(&mut self) {
         self.show_octree_nodes = !self.show_octree_nodes;
     }
 
+    pub fn toggle_show_load_latency(&mut self) {
+        self.show_load_latency = !self.show_load_latency;
+        self.needs_drawing = true;
+    }
+
+    pub fn toggle_occlusion_culling(&mut self) {
+        self.occlusion_culler = match self.occlusion_culler {
+            Some(_) => None,
+            None => Some(OcclusionCuller::new(&self.gl)),
+        };
+        eprintln!(
+            "Occlusion culling is now {}.",
+            if self.occlusion_culler.is_some() {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        self.needs_drawing = true;
+    }
+
+    pub fn toggle_minimap(&mut self) {
+        self.minimap.enabled = !self.minimap.enabled;
+        self.needs_drawing = true;
+    }
+
+    /// Returns the world x and y coordinates under the window pixel ('x', 'y') if the minimap is
+    /// shown there.
+    pub fn minimap_world_xy_at(
+        &self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Option<Point2<f64>> {
+        self.minimap.world_xy_at(x, y, width, height)
+    }
+
+    /// Draws the coarse nodes from above and the footprint of the main camera's frustum. Must be
+    /// called after everything else has been drawn into the frame.
+    pub fn draw_minimap(&mut self, width: i32, height: i32) {
+        if !self.minimap.enabled {
+            return;
+        }
+        self.minimap.begin_drawing(&self.gl, width, height);
+        let minimap_world_to_gl = *self.minimap.world_to_gl();
+        self.node_drawer.update_world_to_gl(&minimap_world_to_gl);
+        for node_id in &self.minimap.node_ids {
+            if let Some(view) = self.node_views.get_or_request(node_id) {
+                self.node_drawer.draw(view, 1, 1., None, self.gamma);
+            }
+        }
+        self.node_drawer.update_world_to_gl(&self.world_to_gl);
+        // The frustum is the unit cube in clip space, so mapping that cube back to the world
+        // with the inverse of the main camera's matrix draws the frustum's outline. Depth
+        // clamping keeps the far away parts of the frustum from being clipped.
+        if let Some(gl_to_world) = self.world_to_gl.try_inverse() {
+            unsafe {
+                self.gl.Disable(opengl::DEPTH_TEST);
+                self.gl.Enable(opengl::DEPTH_CLAMP);
+            }
+            self.box_drawer
+                .draw_outlines_from_transformation(&(minimap_world_to_gl * gl_to_world), &RED);
+            unsafe {
+                self.gl.Disable(opengl::DEPTH_CLAMP);
+                self.gl.Enable(opengl::DEPTH_TEST);
+            }
+        }
+        self.minimap.end_drawing(&self.gl, width, height);
+    }
+
+    pub fn toggle_selection_mode(&mut self) {
+        self.selection.enabled = !self.selection.enabled;
+        eprintln!(
+            "Selection mode is now {}.",
+            if self.selection.enabled { "on" } else { "off" }
+        );
+    }
+
+    /// Starts dragging a selection rectangle at the window pixel ('x', 'y'). Returns false if
+    /// not in selection mode.
+    pub fn start_selection(&mut self, x: i32, y: i32) -> bool {
+        if !self.selection.enabled {
+            return false;
+        }
+        self.selection.start_drag(x, y);
+        true
+    }
+
+    /// Moves the corner of the selection rectangle that is being dragged. Returns false if no
+    /// selection rectangle is being dragged.
+    pub fn update_selection(&mut self, x: i32, y: i32) -> bool {
+        if !self.selection.is_dragging() {
+            return false;
+        }
+        self.selection.update_drag(x, y);
+        self.needs_drawing = true;
+        true
+    }
+
+    pub fn finish_selection(&mut self, width: i32, height: i32) {
+        if !self.selection.is_dragging() {
+            return;
+        }
+        if self.selection.finish_drag(&self.world_to_gl, width, height) {
+            self.node_drawer
+                .set_selection(self.selection.clip_from_world());
+        }
+        self.needs_drawing = true;
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+        self.node_drawer.set_selection(None);
+        self.needs_drawing = true;
+    }
+
+    /// Writes the selected points to a PLY file in the current directory. This happens in the
+    /// background, since it reads every node that intersects the selection.
+    pub fn export_selection(&self) {
+        let clip_from_world = match self.selection.clip_from_world() {
+            Some(clip_from_world) => *clip_from_world,
+            None => {
+                eprintln!("Nothing is selected.");
+                return;
+            }
+        };
+        let octree = Arc::clone(&self.octree);
+        let path = PathBuf::from(format!(
+            "selection_{}.ply",
+            time::OffsetDateTime::now_utc().timestamp()
+        ));
+        eprintln!("Exporting selection to {}.", path.display());
+        thread::spawn(
+            move || match selection::export(&octree, &clip_from_world, &path) {
+                Ok(num_points) => {
+                    eprintln!("Exported {} points to {}.", num_points, path.display())
+                }
+                Err(e) => eprintln!("Could not export selection: {}", e),
+            },
+        );
+    }
+
+    pub fn toggle_cross_section_mode(&mut self) {
+        self.cross_section_tool.toggle();
+        eprintln!(
+            "Cross-section mode is now {}.",
+            if self.cross_section_tool.enabled {
+                "on, click the start and the end of the line into the minimap"
+            } else {
+                "off"
+            }
+        );
+    }
+
+    /// Sets an end of the cross-section line in cross-section mode, and once both are set, writes
+    /// its profile to SVG and PNG files in the current directory in the background. Returns false
+    /// if not in cross-section mode.
+    pub fn add_cross_section_point(&mut self, point: Point2<f64>) -> bool {
+        if !self.cross_section_tool.enabled {
+            return false;
+        }
+        let cross_section = match self
+            .cross_section_tool
+            .add_point(point, self.octree.bounding_box())
+        {
+            Some(cross_section) => cross_section,
+            None => return true,
+        };
+        let octree = Arc::clone(&self.octree);
+        let attribute = self.cross_section_tool.attribute.clone();
+        let stem = PathBuf::from(format!(
+            "profile_{}",
+            time::OffsetDateTime::now_utc().timestamp()
+        ));
+        eprintln!("Exporting the profile to {}.svg and .png.", stem.display());
+        thread::spawn(move || {
+            match cross_section::export(&octree, &cross_section, attribute.as_deref(), &stem) {
+                Ok(num_points) => eprintln!(
+                    "Exported a profile of {} points to {}.svg and .png.",
+                    num_points,
+                    stem.display()
+                ),
+                Err(e) => eprintln!("Could not export the profile: {}", e),
+            }
+        });
+        true
+    }
+
+    /// Draws the outline of the selection rectangle that is being dragged, if any.
+    pub fn draw_selection_outline(&self, width: i32, height: i32) {
+        if let Some(transform) = self.selection.drag_outline(width, height) {
+            unsafe {
+                self.gl.Disable(opengl::DEPTH_TEST);
+            }
+            self.box_drawer
+                .draw_outlines_from_transformation(&transform, &WHITE);
+            unsafe {
+                self.gl.Enable(opengl::DEPTH_TEST);
+            }
+        }
+    }
+
     pub fn adjust_gamma(&mut self, delta: f32) {
         self.gamma += delta;
         self.needs_drawing = true;
@@ -155,6 +484,258 @@ impl PointCloudRenderer {
         self.needs_drawing = true;
     }
 
+    /// Draws points larger close to the camera and smaller far from it, or all points with the
+    /// same size.
+    pub fn toggle_point_size_attenuation(&mut self) {
+        self.attenuate_point_size = !self.attenuate_point_size;
+        self.needs_drawing = true;
+    }
+
+    /// Multiplies the distance at which attenuated points have the set point size with 'factor',
+    /// so that points fall off in size later for factors above 1.
+    pub fn scale_attenuation_distance(&mut self, factor: f32) {
+        self.attenuation_distance = (self.attenuation_distance * factor)
+            .max(MIN_ATTENUATION_DISTANCE)
+            .min(MAX_ATTENUATION_DISTANCE);
+        eprintln!(
+            "Points are {} pixels large at {:.1} m.",
+            self.point_size, self.attenuation_distance
+        );
+        self.needs_drawing = true;
+    }
+
+    /// The attenuation distance that points are drawn with, None for a constant size.
+    fn point_size_attenuation(&self) -> Option<f32> {
+        if self.attenuate_point_size {
+            Some(self.attenuation_distance)
+        } else {
+            None
+        }
+    }
+
+    pub fn toggle_color_by_label(&mut self) {
+        if self.label_palette.is_none() {
+            eprintln!("This point cloud has no labels.");
+            return;
+        }
+        self.color_by_label = !self.color_by_label;
+        self.node_drawer.set_color_by_label(self.color_by_label);
+        self.needs_drawing = true;
+    }
+
+    /// Colors the points by their intensity instead of their colors, or back. Only the colors of
+    /// the nodes are loaded again, not their positions.
+    pub fn toggle_color_by_intensity(&mut self) {
+        if !self.has_intensity() {
+            eprintln!("This point cloud has no intensities.");
+            return;
+        }
+        self.color_by_intensity = !self.color_by_intensity;
+        self.update_color_source();
+    }
+
+    fn has_intensity(&self) -> bool {
+        matches!(self.octree.stored_attribute_type("intensity"), Ok(Some(_)))
+    }
+
+    fn update_color_source(&mut self) {
+        self.node_views
+            .set_color_source(if self.color_by_intensity {
+                ColorSource::Intensity
+            } else {
+                ColorSource::Color
+            });
+        self.needs_drawing = true;
+    }
+
+    /// Selects the next (or with a negative 'delta' the previous) label of the palette for
+    /// toggling its visibility.
+    pub fn select_label(&mut self, delta: i32) {
+        let palette = match &self.label_palette {
+            Some(palette) => palette,
+            None => return,
+        };
+        let ids: Vec<u16> = palette.labels.keys().copied().collect();
+        if ids.is_empty() {
+            return;
+        }
+        let current = self
+            .selected_label
+            .and_then(|id| ids.iter().position(|i| *i == id))
+            .unwrap_or(0) as i32;
+        let next = (current + delta).rem_euclid(ids.len() as i32) as usize;
+        let id = ids[next];
+        self.selected_label = Some(id);
+        eprintln!(
+            "Selected label {} '{}' ({}).",
+            id,
+            palette.labels[&id].name,
+            if self.hidden_labels.contains(&id) {
+                "hidden"
+            } else {
+                "visible"
+            }
+        );
+    }
+
+    pub fn toggle_selected_label_visibility(&mut self) {
+        let (palette, id) = match (&self.label_palette, self.selected_label) {
+            (Some(palette), Some(id)) => (palette, id),
+            _ => return,
+        };
+        let hidden = if self.hidden_labels.remove(&id) {
+            false
+        } else {
+            self.hidden_labels.insert(id);
+            true
+        };
+        eprintln!(
+            "Label {} '{}' is now {}.",
+            id,
+            palette.labels[&id].name,
+            if hidden { "hidden" } else { "visible" }
+        );
+        self.update_label_colors();
+    }
+
+    pub fn toggle_density_equalization(&mut self) {
+        self.density_equalizer.enabled = !self.density_equalizer.enabled;
+        eprintln!(
+            "Density equalization is now {}.",
+            if self.density_equalizer.enabled {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        self.needs_drawing = true;
+    }
+
+    /// Applies 'change' to the playback and updates the shown points. Point clouds without
+    /// timestamps cannot be played back.
+    fn change_playback(&mut self, change: impl FnOnce(&mut Playback)) {
+        let playback = match &mut self.playback {
+            Some(playback) => playback,
+            None => {
+                eprintln!("This point cloud has no timestamps.");
+                return;
+            }
+        };
+        change(playback);
+        self.node_drawer.set_time_window(playback.window());
+        self.needs_drawing = true;
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.change_playback(Playback::toggle_playing);
+    }
+
+    pub fn toggle_time_window(&mut self) {
+        self.change_playback(|playback| playback.enabled = !playback.enabled);
+    }
+
+    pub fn scale_playback_speed(&mut self, factor: f64) {
+        self.change_playback(|playback| playback.scale_speed(factor));
+    }
+
+    pub fn scale_time_window(&mut self, factor: f64) {
+        self.change_playback(|playback| playback.scale_window(factor));
+    }
+
+    /// Moves the time window by its own length, backwards for a negative 'direction'.
+    pub fn step_time_window(&mut self, direction: f64) {
+        self.change_playback(|playback| playback.step(direction));
+    }
+
+    /// Moves the time window while playing.
+    pub fn advance_playback(&mut self, elapsed: time::Duration) {
+        if let Some(playback) = &mut self.playback {
+            if playback.advance(elapsed) {
+                self.node_drawer.set_time_window(playback.window());
+                self.needs_drawing = true;
+            }
+        }
+    }
+
+    /// The state that is kept across restarts. The camera and the spatial context are not owned
+    /// by the renderer, but are part of the session too.
+    fn session(&self, camera: &Camera, spatial_context: &SpatialContext) -> Session {
+        let mut hidden_labels: Vec<u16> = self.hidden_labels.iter().copied().collect();
+        hidden_labels.sort_unstable();
+        Session {
+            camera: camera.state(),
+            ct_mode: camera.ct_mode(),
+            show_octree_nodes: self.show_octree_nodes,
+            occlusion_culling: self.occlusion_culler.is_some(),
+            show_minimap: self.minimap.enabled,
+            equalize_density: self.density_equalizer.enabled,
+            show_gizmo: spatial_context.show_gizmo,
+            show_grid: spatial_context.show_grid,
+            color_by_label: self.color_by_label,
+            color_by_intensity: self.color_by_intensity,
+            hidden_labels,
+            point_size: self.point_size,
+            attenuation_distance: self.point_size_attenuation(),
+            gamma: self.gamma,
+            selection: self.selection.clip_from_world().copied(),
+        }
+    }
+
+    fn restore_session(
+        &mut self,
+        session: &Session,
+        camera: &mut Camera,
+        spatial_context: &mut SpatialContext,
+    ) {
+        camera.set_state(session.camera);
+        camera.set_ct_mode(session.ct_mode, &self.gl);
+        spatial_context.show_gizmo = session.show_gizmo;
+        spatial_context.show_grid = session.show_grid;
+        self.show_octree_nodes = session.show_octree_nodes;
+        if session.occlusion_culling != self.occlusion_culler.is_some() {
+            self.toggle_occlusion_culling();
+        }
+        self.minimap.enabled = session.show_minimap;
+        self.density_equalizer.enabled = session.equalize_density;
+        // The labels might have been removed from the point cloud since.
+        self.color_by_label = session.color_by_label && self.label_palette.is_some();
+        self.node_drawer.set_color_by_label(self.color_by_label);
+        self.color_by_intensity = session.color_by_intensity && self.has_intensity();
+        self.update_color_source();
+        self.hidden_labels = session.hidden_labels.iter().copied().collect();
+        self.point_size = session.point_size.max(1.);
+        self.attenuate_point_size = session.attenuation_distance.is_some();
+        if let Some(attenuation_distance) = session.attenuation_distance {
+            self.attenuation_distance = attenuation_distance;
+        }
+        self.gamma = session.gamma;
+        self.selection.set_clip_from_world(session.selection);
+        self.node_drawer
+            .set_selection(self.selection.clip_from_world());
+        self.update_label_colors();
+        self.needs_drawing = true;
+    }
+
+    fn update_label_colors(&mut self) {
+        let palette = match &self.label_palette {
+            Some(palette) => palette,
+            None => return,
+        };
+        let mut colors = vec![[0; 4]; NUM_LABEL_IDS];
+        for (id, rgba) in colors.iter_mut().enumerate() {
+            let color = palette.color(id as u16);
+            let visible = !self.hidden_labels.contains(&(id as u16));
+            *rgba = [
+                color.red,
+                color.green,
+                color.blue,
+                if visible { 255 } else { 0 },
+            ];
+        }
+        self.node_drawer.set_label_colors(&colors);
+        self.needs_drawing = true;
+    }
+
     pub fn draw(&mut self) -> DrawResult {
         let mut draw_result = DrawResult::NoChange;
         let mut num_points_drawn = 0;
@@ -163,9 +744,15 @@ impl PointCloudRenderer {
         let now = time::Instant::now();
         let moving = now - self.last_moving < time::Duration::milliseconds(150);
         self.needs_drawing |= self.node_views.consume_arrived_nodes(&self.node_drawer);
+        // Fading live points change every frame.
+        if let Some(live_points) = &mut self.live_points {
+            self.needs_drawing |= live_points.update();
+        }
         while let Ok(visible_nodes) = self.get_visible_nodes_result_rx.try_recv() {
             self.visible_nodes.clear();
             self.visible_nodes.extend(visible_nodes);
+            self.node_views
+                .set_wanted(self.visible_nodes.iter().chain(&self.minimap.node_ids));
             self.needs_drawing = true;
         }
 
@@ -183,18 +770,52 @@ impl PointCloudRenderer {
         } else {
             self.max_nodes_in_memory
         };
-        let filtered_visible_nodes = self.visible_nodes.iter().take(max_nodes_to_display);
+        // Nodes that only contain hidden labels are not even requested, and neither are nodes
+        // that were completely occluded the last time we looked or that have no points within
+        // the time window.
+        let octree = &self.octree;
+        let hidden_labels = &self.hidden_labels;
+        let occlusion_culler = &self.occlusion_culler;
+        let time_filter: Option<HashMap<&str, ClosedInterval<f64>>> = self
+            .playback
+            .as_ref()
+            .and_then(Playback::window)
+            .map(|window| std::iter::once(("timestamp", window)).collect());
+        let filtered_visible_nodes = self
+            .visible_nodes
+            .iter()
+            .filter(|id| !octree.all_labels_hidden(id, |label| hidden_labels.contains(&label)))
+            .filter(|id| match &time_filter {
+                Some(time_filter) => octree
+                    .node_meta(id)
+                    .map_or(true, |node_meta| node_meta.may_match(time_filter)),
+                None => true,
+            })
+            .filter(|id| match occlusion_culler {
+                Some(culler) => culler.visible_fraction(id) != Some(0.),
+                None => true,
+            })
+            .take(max_nodes_to_display);
+        let mut estimated_visible_points = 0.;
+        let attenuation_distance = self.point_size_attenuation();
 
         for node_id in filtered_visible_nodes {
+            let load_stats = self.node_views.load_stats(&node_id).copied();
             let view = self.node_views.get_or_request(&node_id);
             if !self.needs_drawing || view.is_none() {
                 continue;
             }
             let view = view.unwrap();
+            estimated_visible_points += view.meta.num_points as f32
+                * occlusion_culler
+                    .as_ref()
+                    .and_then(|culler| culler.visible_fraction(&node_id))
+                    .unwrap_or(1.);
             num_points_drawn += self.node_drawer.draw(
                 view,
-                1, /* level of detail */
+                self.density_equalizer.level_of_detail(&node_id, &view.meta),
                 self.point_size,
+                attenuation_distance,
                 self.gamma,
             );
             num_nodes_drawn += 1;
@@ -206,8 +827,42 @@ impl PointCloudRenderer {
                     &YELLOW,
                 );
             }
+            if self.show_load_latency {
+                if let Some(load_stats) = load_stats {
+                    let aabb = view.meta.bounding_cube.to_aabb();
+                    self.box_drawer.draw_outlines(
+                        &aabb,
+                        &self.world_to_gl,
+                        &latency_color(load_stats.latency),
+                    );
+                    // The source is drawn as a slightly smaller box inside.
+                    let center = aabb.center();
+                    let half_diag = aabb.diag() * 0.45;
+                    self.box_drawer.draw_outlines(
+                        &Aabb::new(center - half_diag, center + half_diag),
+                        &self.world_to_gl,
+                        &source_color(load_stats.source),
+                    );
+                }
+            }
         }
         if self.needs_drawing {
+            // Drawn last, since they are blended over the octree.
+            if let Some(live_points) = &self.live_points {
+                num_points_drawn +=
+                    live_points.draw(&self.world_to_gl, self.point_size, self.gamma) as i64;
+            }
+            if let Some(occlusion_culler) = &mut self.occlusion_culler {
+                // Occluded nodes are queried as well, so that they show up again once they
+                // come into view.
+                let root_cube = Cube::bounding(self.octree.bounding_box());
+                let nodes = self
+                    .visible_nodes
+                    .iter()
+                    .take(self.max_nodes_in_memory)
+                    .map(|id| (*id, id.find_bounding_cube(&root_cube).to_aabb()));
+                occlusion_culler.update(nodes, &self.world_to_gl);
+            }
             draw_result = DrawResult::HasDrawn;
         }
         self.needs_drawing = moving;
@@ -228,10 +883,11 @@ impl PointCloudRenderer {
             self.num_frames = 0;
             self.last_log = now;
             eprintln!(
-                "FPS: {:.2}, Drew {} points from {} loaded nodes. {} nodes \
-                 should be shown, Cache {} MB",
+                "FPS: {:.2}, Drew {} points ({:.0} estimated visible) from {} loaded nodes. \
+                 {} nodes should be shown, Cache {} MB",
                 fps,
                 num_points_drawn,
+                estimated_visible_points,
                 num_nodes_drawn,
                 self.visible_nodes.len(),
                 self.node_views.get_used_memory_bytes() as f32 / 1024. / 1024.,
@@ -299,6 +955,8 @@ pub trait Extension {
     fn local_from_global(matches: &clap::ArgMatches, octree: &Octree) -> Option<Isometry3<f64>>;
     fn camera_changed(&mut self, transform: &Matrix4<f64>);
     fn draw(&mut self);
+    /// Called when the user clicked on a point with the middle mouse button.
+    fn point_picked(&mut self, _point: &PickedPoint) {}
 }
 
 trait Joystick {
@@ -347,6 +1005,70 @@ impl Joystick for SpaceMouseJoystick {
     }
 }
 
+/// Executes a remote control command other than a screenshot, which needs a drawn frame.
+fn execute_remote_command(
+    command: Command,
+    renderer: &mut PointCloudRenderer,
+    camera: &mut Camera,
+    spatial_context: &mut SpatialContext,
+    terrain_renderer: &mut TerrainRenderer,
+    data_provider_factory: &DataProviderFactory,
+) -> Result<Option<serde_json::Value>, String> {
+    match command {
+        Command::GetState => serde_json::to_value(renderer.session(camera, spatial_context))
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        Command::SetCamera { camera: state } => {
+            camera.set_state(state);
+            Ok(None)
+        }
+        Command::Teleport { x, y } => {
+            camera.teleport_to(x, y);
+            Ok(None)
+        }
+        Command::SetLayer { layer, enabled } => {
+            let session = renderer.session(camera, spatial_context);
+            let is_enabled = match layer {
+                Layer::OctreeNodes => session.show_octree_nodes,
+                Layer::OcclusionCulling => session.occlusion_culling,
+                Layer::Minimap => session.show_minimap,
+                Layer::DensityEqualization => session.equalize_density,
+                Layer::Gizmo => session.show_gizmo,
+                Layer::Grid => session.show_grid,
+                Layer::ColorByLabel => session.color_by_label,
+                Layer::ColorByIntensity => session.color_by_intensity,
+            };
+            if is_enabled != enabled {
+                match layer {
+                    Layer::OctreeNodes => renderer.toggle_show_octree_nodes(),
+                    Layer::OcclusionCulling => renderer.toggle_occlusion_culling(),
+                    Layer::Minimap => renderer.toggle_minimap(),
+                    Layer::DensityEqualization => renderer.toggle_density_equalization(),
+                    Layer::Gizmo => spatial_context.show_gizmo = enabled,
+                    Layer::Grid => spatial_context.show_grid = enabled,
+                    Layer::ColorByLabel => renderer.toggle_color_by_label(),
+                    Layer::ColorByIntensity => renderer.toggle_color_by_intensity(),
+                }
+                renderer.request_redraw();
+            }
+            Ok(None)
+        }
+        Command::LoadTerrain { location } => {
+            terrain_renderer
+                .add_layer(&location, data_provider_factory)
+                .map_err(|e| e.to_string())?;
+            terrain_renderer
+                .camera_changed(&camera.get_world_to_gl(), &camera.get_camera_to_world());
+            renderer.request_redraw();
+            Ok(None)
+        }
+        Command::Screenshot { .. } => Err("Screenshots are taken after drawing.".to_string()),
+    }
+}
+
+/// How often the session is saved while the viewer is running.
+const SESSION_SAVE_INTERVAL: time::Duration = time::Duration::seconds(5);
+
 pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     let mut app = clap::App::new("sdl_viewer").args(&[
         clap::Arg::new("octree")
@@ -357,7 +1079,89 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
             .long("terrain")
             .takes_value(true)
             .multiple(true)
-            .about("Terrain directories (multiple possible)."),
+            .about("Terrain directories or other data provider locations (multiple possible)."),
+        clap::Arg::new("terrain_watch_seconds")
+            .long("terrain_watch_seconds")
+            .takes_value(true)
+            .about(
+                "Check the --terrain locations for changes this often and reload the ones that \
+                 another process updated.",
+            ),
+        clap::Arg::new("overlay")
+            .long("overlay")
+            .takes_value(true)
+            .about("Directory with edits that are applied on top of the octree."),
+        clap::Arg::new("label_palette")
+            .long("label_palette")
+            .takes_value(true)
+            .about(
+                "Text file with one 'id,name,red,green,blue' line per label id that replaces the \
+                 label palette of the octree, e.g. for octrees built without one.",
+            ),
+        clap::Arg::new("precision")
+            .long("precision")
+            .takes_value(true)
+            .possible_values(&["double", "relative_to_node"])
+            .default_value("double")
+            .about(
+                "How point positions are transformed. 'relative_to_node' does it in single \
+                 precision on the GPU relative to each node, which avoids jitter and is faster on \
+                 GPUs with few double precision units.",
+            ),
+        clap::Arg::new("full_precision_positions")
+            .long("full_precision_positions")
+            .about(
+                "Upload float positions as they are instead of as 16 bit fixpoint relative to \
+                 their node, which takes more GPU memory.",
+            ),
+        clap::Arg::new("profile_width")
+            .long("profile_width")
+            .takes_value(true)
+            .default_value("1")
+            .about("Width in meters of the slab around the line of a cross-section profile."),
+        clap::Arg::new("profile_attribute")
+            .long("profile_attribute")
+            .takes_value(true)
+            .default_value("color")
+            .about(
+                "Attribute that cross-section profiles are colored by, e.g. 'intensity', or \
+                 'none' for gray points.",
+            ),
+        clap::Arg::new("live")
+            .long("live")
+            .takes_value(true)
+            .about("Address to listen on for streamed points, e.g. 0.0.0.0:5555."),
+        clap::Arg::new("live_fade_seconds")
+            .long("live_fade_seconds")
+            .takes_value(true)
+            .default_value("10")
+            .about("How long streamed points take to fade out."),
+        clap::Arg::new("plugin")
+            .long("plugin")
+            .takes_value(true)
+            .multiple(true)
+            .about(
+                "Dynamic library of a renderer or tool plugin, optionally followed by '=' and an \
+                 argument for it (multiple possible).",
+            ),
+        clap::Arg::new("remote_control")
+            .long("remote_control")
+            .takes_value(true)
+            .about(
+                "Address to listen on for remote control commands, e.g. 127.0.0.1:5556. There is \
+                 no authentication, so only use local addresses.",
+            ),
+        clap::Arg::new("fresh")
+            .long("fresh")
+            .about("Start with the default view instead of restoring the last session."),
+        clap::Arg::new("upload_budget_mb")
+            .long("upload_budget_mb")
+            .takes_value(true)
+            .default_value("32")
+            .about(
+                "How many MB of octree nodes are uploaded to the GPU per frame at most. Lower \
+                 values keep the frame rate smoother, higher ones show arrived nodes sooner.",
+            ),
         clap::Arg::new("cache_size_mb")
             .about(
                 "Maximum cache size in MB for octree nodes in GPU memory. \
@@ -385,12 +1189,32 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     let max_nodes_in_memory = limit_cache_size_mb * 5;
 
     // If no octree was generated create a FromDisk loader
-    let octree: Arc<Octree> = Arc::from(
-        data_provider_factory
-            .generate_data_provider(octree_argument)
-            .and_then(|provider| Octree::from_data_provider(provider))
-            .unwrap_or_else(|_| panic!("Couldn't create octree from path '{}'.", octree_argument)),
-    );
+    let mut octree = data_provider_factory
+        .generate_data_provider(octree_argument)
+        .and_then(|provider| match matches.value_of("overlay") {
+            Some(overlay) => {
+                Ok(Box::new(OverlayDataProvider::new(provider, overlay)?) as Box<dyn DataProvider>)
+            }
+            None => Ok(provider),
+        })
+        .and_then(|provider| Octree::from_data_provider(provider))
+        .unwrap_or_else(|_| panic!("Couldn't create octree from path '{}'.", octree_argument));
+    if let Some(path) = matches.value_of("label_palette") {
+        let (attribute, data_type) = LABEL_ATTRIBUTES
+            .iter()
+            .find_map(|attribute| match octree.stored_attribute_type(attribute) {
+                Ok(Some(data_type)) => Some((*attribute, data_type)),
+                _ => None,
+            })
+            .expect("A label palette requires an octree with a 'label' or 'class' attribute.");
+        let label_palette =
+            LabelPalette::from_csv_file(attribute, path).expect("Could not read label palette.");
+        label_palette
+            .check_ids(data_type)
+            .expect("The label palette does not fit the octree.");
+        octree = octree.with_label_palette(label_palette);
+    }
+    let octree: Arc<Octree> = Arc::from(octree);
 
     let mut pose_path = None;
     let pose_path_buf = PathBuf::from(&octree_argument).join("poses.json");
@@ -444,8 +1268,9 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
 
     const WINDOW_WIDTH: i32 = 800;
     const WINDOW_HEIGHT: i32 = 600;
-    let window = match video_subsystem
-        .window("sdl2_viewer", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
+    const WINDOW_TITLE: &str = "sdl2_viewer";
+    let mut window = match video_subsystem
+        .window(WINDOW_TITLE, WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
         .position_centered()
         .resizable()
         .opengl()
@@ -468,12 +1293,113 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     }));
 
     let mut extension = T::new(&matches, Rc::clone(&gl));
+    let mut plugins: Vec<Plugin> = matches
+        .values_of("plugin")
+        .unwrap_or_default()
+        .map(|spec| {
+            let plugin = Plugin::from_spec(spec, sdl2::sys::SDL_GL_GetProcAddress)
+                .unwrap_or_else(|e| panic!("{}", e));
+            eprintln!("Loaded plugin '{}'.", plugin.name());
+            plugin
+        })
+        .collect();
     let ext_local_from_global = T::local_from_global(&matches, &octree);
+    let mut watchdog = Watchdog::new(&gl, octree_argument, &octree);
     let mut renderer = PointCloudRenderer::new(max_nodes_in_memory, Rc::clone(&gl), octree);
-    let terrain_paths = matches.values_of("terrain").unwrap_or_default();
-    let mut terrain_renderer = TerrainRenderer::new(Rc::clone(&gl), terrain_paths);
+    renderer.node_drawer.set_precision(
+        matches
+            .value_of("precision")
+            .unwrap()
+            .parse()
+            .expect("Could not parse 'precision' option."),
+    );
+    renderer
+        .node_drawer
+        .set_compress_positions(!matches.is_present("full_precision_positions"));
+    let upload_budget_mb: usize = matches
+        .value_of("upload_budget_mb")
+        .unwrap()
+        .parse()
+        .expect("Could not parse 'upload_budget_mb' option.");
+    renderer.node_views.upload_budget.bytes = upload_budget_mb * 1024 * 1024;
+    renderer.cross_section_tool.width_m = matches
+        .value_of("profile_width")
+        .unwrap()
+        .parse()
+        .expect("Could not parse 'profile_width' option.");
+    renderer.cross_section_tool.attribute = matches
+        .value_of("profile_attribute")
+        .filter(|attribute| *attribute != "none")
+        .map(str::to_string);
+    renderer.update_label_colors();
+    if let Some(address) = matches.value_of("live") {
+        let fade_seconds: f64 = matches
+            .value_of("live_fade_seconds")
+            .unwrap()
+            .parse()
+            .expect("Could not parse 'live_fade_seconds' option.");
+        let receiver = live::listen(address).expect("Could not listen for live points.");
+        renderer.live_points = Some(LivePoints::new(
+            &gl,
+            receiver,
+            time::Duration::seconds_f64(fade_seconds),
+        ));
+    }
+    let remote_requests = matches
+        .value_of("remote_control")
+        .map(|address| remote::listen(address).expect("Could not listen for remote control."));
+    let mut pending_screenshots = Vec::new();
+    let mut pending_pick = None;
+    let terrain_locations = matches.values_of("terrain").unwrap_or_default();
+    let mut terrain_renderer =
+        TerrainRenderer::new(Rc::clone(&gl), terrain_locations, &data_provider_factory);
+    let terrain_changes = matches.value_of("terrain_watch_seconds").map(|seconds| {
+        let seconds: f64 = seconds
+            .parse()
+            .expect("Could not parse 'terrain_watch_seconds' option.");
+        terrain_drawer::watch(
+            terrain_renderer.locations().to_vec(),
+            data_provider_factory.clone(),
+            std::time::Duration::from_secs_f64(seconds),
+        )
+    });
     let local_from_global = ext_local_from_global.or_else(|| terrain_renderer.local_from_global());
+    let mut spatial_context = SpatialContext::new(&gl, local_from_global.is_some());
     let mut camera = Camera::new(&gl, WINDOW_WIDTH, WINDOW_HEIGHT, local_from_global);
+    let mut cursor = None;
+    let mut window_title = String::new();
+
+    // The session is saved regularly and not only on exit, so that it survives a crash.
+    let session_path = session::session_path(octree_argument);
+    let mut saved_session = None;
+    if let Some(session_path) = &session_path {
+        if matches.is_present("fresh") {
+            eprintln!("Not restoring the last session.");
+        } else if let Ok(session) = Session::load(session_path) {
+            renderer.restore_session(&session, &mut camera, &mut spatial_context);
+            eprintln!(
+                "Restored the last session from {}. Start with --fresh to ignore it.",
+                session_path.display()
+            );
+            saved_session = Some(session);
+        }
+    }
+    let mut save_session =
+        |renderer: &PointCloudRenderer, camera: &Camera, spatial_context: &SpatialContext| {
+            let session_path = match &session_path {
+                Some(session_path) => session_path,
+                None => return,
+            };
+            let session = renderer.session(camera, spatial_context);
+            if saved_session.as_ref() == Some(&session) {
+                return;
+            }
+            match session.save(session_path) {
+                Ok(()) => saved_session = Some(session),
+                Err(e) => eprintln!("Could not write {}: {}", session_path.display(), e),
+            }
+        };
+    let mut last_session_save = time::Instant::now();
 
     let mut events = ctx.event_pump().unwrap();
     let mut last_frame_time = time::Instant::now();
@@ -505,10 +1431,42 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
                             Scancode::Down => camera.turning_down = true,
                             Scancode::Up => camera.turning_up = true,
                             Scancode::O => renderer.toggle_show_octree_nodes(),
+                            Scancode::Y => renderer.toggle_show_load_latency(),
+                            Scancode::G => renderer.toggle_occlusion_culling(),
+                            Scancode::M => renderer.toggle_minimap(),
+                            Scancode::F => renderer.toggle_density_equalization(),
+                            Scancode::B => renderer.toggle_selection_mode(),
+                            Scancode::C => renderer.clear_selection(),
+                            Scancode::X => {
+                                spatial_context.show_gizmo = !spatial_context.show_gizmo;
+                                renderer.request_redraw();
+                            }
+                            Scancode::N => {
+                                spatial_context.show_grid = !spatial_context.show_grid;
+                                renderer.request_redraw();
+                            }
+                            Scancode::E => renderer.export_selection(),
+                            Scancode::R => renderer.toggle_cross_section_mode(),
+                            Scancode::L => renderer.toggle_color_by_label(),
+                            Scancode::Tab => renderer.toggle_color_by_intensity(),
+                            Scancode::LeftBracket => renderer.select_label(-1),
+                            Scancode::RightBracket => renderer.select_label(1),
+                            Scancode::H => renderer.toggle_selected_label_visibility(),
+                            Scancode::P => renderer.toggle_playing(),
+                            Scancode::V => renderer.toggle_time_window(),
+                            Scancode::Comma => renderer.scale_playback_speed(0.5),
+                            Scancode::Period => renderer.scale_playback_speed(2.),
+                            Scancode::Minus => renderer.scale_time_window(0.5),
+                            Scancode::Equals => renderer.scale_time_window(2.),
+                            Scancode::Semicolon => renderer.step_time_window(-1.),
+                            Scancode::Apostrophe => renderer.step_time_window(1.),
                             Scancode::Num7 => renderer.adjust_gamma(-0.1),
                             Scancode::Num8 => renderer.adjust_gamma(0.1),
                             Scancode::Num9 => renderer.adjust_point_size(-0.1),
                             Scancode::Num0 => renderer.adjust_point_size(0.1),
+                            Scancode::Num6 => renderer.toggle_point_size_attenuation(),
+                            Scancode::Num4 => renderer.scale_attenuation_distance(0.8),
+                            Scancode::Num5 => renderer.scale_attenuation_distance(1.25),
                             _ => (),
                         }
                     } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
@@ -562,17 +1520,58 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
                     _ => (),
                 },
                 Event::MouseMotion {
+                    x,
+                    y,
                     xrel,
                     yrel,
                     mousestate,
                     ..
                 } => {
-                    if mousestate.left() {
+                    cursor = Some((x, y));
+                    if spatial_context.show_gizmo {
+                        // The coordinates under the cursor are read back from a fresh frame.
+                        renderer.request_redraw();
+                    }
+                    if renderer.update_selection(x, y) {
+                        // The drag selects points instead of moving the camera.
+                    } else if mousestate.left() {
                         camera.mouse_drag_rotate(xrel, yrel)
                     } else if mousestate.right() {
                         camera.mouse_drag_pan(xrel, yrel)
                     }
                 }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    if let Some(target) =
+                        renderer.minimap_world_xy_at(x, y, camera.width, camera.height)
+                    {
+                        if !renderer.add_cross_section_point(target) {
+                            camera.teleport_to(target.x, target.y);
+                        }
+                    } else {
+                        renderer.start_selection(x, y);
+                    }
+                }
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => {
+                    renderer.finish_selection(camera.width, camera.height);
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Middle,
+                    x,
+                    y,
+                    ..
+                } => {
+                    // The depth under the cursor is read back from a fresh frame.
+                    pending_pick = Some((x, y));
+                    renderer.request_redraw();
+                }
                 Event::MouseWheel { y, .. } => {
                     camera.mouse_wheel(y);
                 }
@@ -586,26 +1585,151 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
             }
         }
 
+        for request in remote_requests
+            .iter()
+            .flat_map(|requests| requests.try_iter())
+        {
+            match request.command {
+                Command::Screenshot { path } => {
+                    pending_screenshots.push((path, request.reply));
+                    renderer.request_redraw();
+                }
+                command => request.reply.send(execute_remote_command(
+                    command,
+                    &mut renderer,
+                    &mut camera,
+                    &mut spatial_context,
+                    &mut terrain_renderer,
+                    &data_provider_factory,
+                )),
+            }
+        }
+
+        for index in terrain_changes
+            .iter()
+            .flat_map(|changes| changes.try_iter())
+        {
+            let location = terrain_renderer.locations()[index].clone();
+            match terrain_renderer.reload_layer(index, &data_provider_factory) {
+                Ok(()) => {
+                    eprintln!("Reloaded terrain {}.", location);
+                    renderer.request_redraw();
+                }
+                Err(e) => eprintln!("Could not reload terrain {}: {}", location, e),
+            }
+        }
+
         for j in &joysticks {
             j.act(&mut camera);
         }
         let current_time = time::Instant::now();
         let elapsed = current_time - last_frame_time;
         last_frame_time = current_time;
+        renderer.advance_playback(elapsed);
         if camera.update(elapsed) {
             renderer.camera_changed(&camera.get_world_to_gl());
             terrain_renderer
                 .camera_changed(&camera.get_world_to_gl(), &camera.get_camera_to_world());
             extension.camera_changed(&camera.get_world_to_gl());
+            for plugin in &mut plugins {
+                plugin.camera_changed(&camera.get_world_to_gl());
+            }
+        }
+
+        if current_time - last_session_save > SESSION_SAVE_INTERVAL {
+            save_session(&renderer, &camera, &spatial_context);
+            last_session_save = current_time;
         }
 
         match renderer.draw() {
             DrawResult::HasDrawn => {
                 terrain_renderer.draw();
                 extension.draw();
-                window.gl_swap_window()
+                for plugin in &mut plugins {
+                    plugin.draw();
+                }
+                spatial_context.draw_grid(
+                    &camera.get_world_to_gl(),
+                    camera.local_from_global(),
+                    &Point3::from(camera.local_from_camera().translation.vector),
+                );
+                let title = match cursor.filter(|_| spatial_context.show_gizmo) {
+                    Some(cursor) => spatial_context
+                        .point_under_cursor(
+                            &camera.get_world_to_gl(),
+                            camera.local_from_global(),
+                            cursor,
+                            camera.width,
+                            camera.height,
+                        )
+                        .map_or_else(
+                            || WINDOW_TITLE.to_string(),
+                            |point| {
+                                format!(
+                                    "{} - {}",
+                                    WINDOW_TITLE,
+                                    spatial_context.readout(&point, camera.local_from_global())
+                                )
+                            },
+                        ),
+                    None => WINDOW_TITLE.to_string(),
+                };
+                if title != window_title {
+                    window.set_title(&title).unwrap();
+                    window_title = title;
+                }
+                if let Some(clicked) = pending_pick.take() {
+                    let picked = spatial_context
+                        .point_under_cursor(
+                            &camera.get_world_to_gl(),
+                            camera.local_from_global(),
+                            clicked,
+                            camera.width,
+                            camera.height,
+                        )
+                        .map_or(Ok(None), |around| {
+                            picking::pick(&renderer.octree, &around, picking::PICK_RADIUS_M)
+                        });
+                    match picked {
+                        Ok(Some(point)) => {
+                            eprintln!("Picked {}", point);
+                            extension.point_picked(&point);
+                        }
+                        Ok(None) => eprintln!("There is no point under the cursor."),
+                        Err(e) => eprintln!("Could not pick a point: {}", e),
+                    }
+                }
+                spatial_context.draw_gizmo(
+                    &camera.local_from_camera().rotation.inverse(),
+                    camera.width,
+                    camera.height,
+                );
+                renderer.draw_selection_outline(camera.width, camera.height);
+                renderer.draw_minimap(camera.width, camera.height);
+                for (path, reply) in pending_screenshots.drain(..) {
+                    let result = remote::write_screenshot(&gl, camera.width, camera.height, &path);
+                    reply.send(result.map(|()| None));
+                }
+                window.gl_swap_window();
+                if let Some(errors) = diagnostics::gl_errors(&gl) {
+                    watchdog.report(
+                        FailureKind::OpenGl,
+                        &format!("OpenGL error: {}", errors),
+                        camera.state(),
+                        &renderer.visible_nodes,
+                    );
+                }
             }
             DrawResult::NoChange => (),
         }
+        for (node_id, err) in renderer.node_views.take_failures() {
+            watchdog.report(
+                FailureKind::NodeLoading,
+                &format!("Could not load node {}: {}", node_id, err),
+                camera.state(),
+                &renderer.visible_nodes,
+            );
+        }
     }
+    save_session(&renderer, &camera, &spatial_context);
 }
@@ -0,0 +1,498 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Points that are streamed in while the viewer runs, e.g. from an ongoing capture session. They
+//! are kept in a rolling buffer and drawn together with the octree, fading out with their age.
+//!
+//! Sources connect via TCP and send batches, each a little endian u32 point count followed by
+//! that many points of three f64 coordinates in the frame of the octree and three u8 color
+//! channels.
+
+use crate::graphic::{GlBuffer, GlProgram, GlProgramBuilder, GlVertexArray};
+use crate::opengl;
+use crate::opengl::types::{GLboolean, GLint, GLintptr, GLsizeiptr, GLuint};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nalgebra::{Matrix4, Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::PointsBatch;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::ops::Range;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const FRAGMENT_SHADER: &str = include_str!("../shaders/points.fs");
+const VERTEX_SHADER: &str = include_str!("../shaders/live_points.vs");
+
+/// The most points kept in the buffer. The oldest batches are dropped first.
+pub const MAX_LIVE_POINTS: usize = 2_000_000;
+
+/// Batches larger than this are rejected, they are most likely garbage.
+const MAX_POINTS_PER_BATCH: u32 = 10_000_000;
+
+/// The most points that memory is reserved for before they arrived. The point count comes from
+/// the network, so a source that announces a huge batch and then stalls must not get much more.
+const MAX_RESERVED_POINTS: usize = 65_536;
+
+/// Reads the next batch of a live stream, None at the end of the stream. Points without colors
+/// are white.
+pub fn read_batch(reader: &mut impl Read) -> io::Result<Option<PointsBatch>> {
+    let num_points = match reader.read_u32::<LittleEndian>() {
+        Ok(num_points) => num_points,
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if num_points > MAX_POINTS_PER_BATCH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Batch of {} points is too large.", num_points),
+        ));
+    }
+    let capacity = (num_points as usize).min(MAX_RESERVED_POINTS);
+    let mut position = Vec::with_capacity(capacity);
+    let mut color = Vec::with_capacity(capacity);
+    for _ in 0..num_points {
+        let x = reader.read_f64::<LittleEndian>()?;
+        let y = reader.read_f64::<LittleEndian>()?;
+        let z = reader.read_f64::<LittleEndian>()?;
+        position.push(Point3::new(x, y, z));
+        let mut rgb = [0; 3];
+        reader.read_exact(&mut rgb)?;
+        color.push(Vector3::new(rgb[0], rgb[1], rgb[2]));
+    }
+    let mut attributes = BTreeMap::new();
+    attributes.insert("color".to_string(), AttributeData::U8Vec3(color));
+    Ok(Some(PointsBatch {
+        position,
+        attributes,
+    }))
+}
+
+/// Writes a batch in the format that 'read_batch' reads, for sources written in Rust.
+pub fn write_batch(writer: &mut impl Write, batch: &PointsBatch) -> io::Result<()> {
+    let color: Option<&Vec<Vector3<u8>>> = batch.get_attribute_vec("color").ok();
+    writer.write_u32::<LittleEndian>(batch.position.len() as u32)?;
+    for (i, p) in batch.position.iter().enumerate() {
+        writer.write_f64::<LittleEndian>(p.x)?;
+        writer.write_f64::<LittleEndian>(p.y)?;
+        writer.write_f64::<LittleEndian>(p.z)?;
+        let c = color.map_or(Vector3::repeat(255), |color| color[i]);
+        writer.write_all(&[c.x, c.y, c.z])?;
+    }
+    Ok(())
+}
+
+/// Accepts live sources on 'address' in the background. Each source gets its own thread, and
+/// all of their batches end up in the returned receiver.
+pub fn listen(address: impl ToSocketAddrs) -> io::Result<Receiver<PointsBatch>> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Listening for live points on {}.", listener.local_addr()?);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Could not accept live source: {}", e);
+                    continue;
+                }
+            };
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+            eprintln!("Live source {} connected.", peer);
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    match read_batch(&mut reader) {
+                        Ok(Some(batch)) => {
+                            // The viewer is gone.
+                            if sender.send(batch).is_err() {
+                                return;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("Live source {} sent invalid data: {}", peer, e);
+                            break;
+                        }
+                    }
+                }
+                eprintln!("Live source {} disconnected.", peer);
+            });
+        }
+    });
+    Ok(receiver)
+}
+
+/// The up to two ranges of 'len' slots from 'start' on in a ring of 'capacity' slots.
+fn ring_ranges(start: usize, len: usize, capacity: usize) -> Vec<Range<usize>> {
+    let end = start + len;
+    let mut ranges = Vec::with_capacity(2);
+    if end <= capacity {
+        ranges.push(start..end);
+    } else {
+        ranges.push(start..capacity);
+        ranges.push(0..end - capacity);
+    }
+    ranges
+}
+
+/// Where the points are in the OpenGL buffers, which are used as a ring: new points are written
+/// after the newest ones, wrapping around at the end, and the oldest points make room for them.
+struct Ring {
+    capacity: usize,
+    // The slot of the oldest point.
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Ring {
+            capacity,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the slots for 'num_points' new points. There must be room for them.
+    fn push(&mut self, num_points: usize) -> Vec<Range<usize>> {
+        assert!(self.len + num_points <= self.capacity);
+        let end = (self.start + self.len) % self.capacity;
+        self.len += num_points;
+        ring_ranges(end, num_points, self.capacity)
+    }
+
+    /// Frees the slots of the 'num_points' oldest points.
+    fn pop(&mut self, num_points: usize) {
+        self.start = (self.start + num_points) % self.capacity;
+        self.len -= num_points;
+    }
+
+    /// The slots of all points, oldest first.
+    fn occupied(&self) -> Vec<Range<usize>> {
+        ring_ranges(self.start, self.len, self.capacity)
+    }
+}
+
+// The points of a batch, which are in consecutive slots of the ring.
+struct Chunk {
+    arrival: time::Instant,
+    num_points: usize,
+}
+
+/// The rolling buffer of live points and the OpenGL objects to draw it.
+pub struct LivePoints {
+    receiver: Receiver<PointsBatch>,
+    fade: time::Duration,
+    start: time::Instant,
+    chunks: VecDeque<Chunk>,
+    ring: Ring,
+    program: GlProgram,
+    u_world_to_gl: GLint,
+    u_size: GLint,
+    u_gamma: GLint,
+    u_now: GLint,
+    u_fade_seconds: GLint,
+    vertex_array: GlVertexArray,
+    buffer_position: GlBuffer,
+    buffer_color: GlBuffer,
+    buffer_arrival: GlBuffer,
+}
+
+impl LivePoints {
+    /// Points are fully transparent once they are older than 'fade'. The buffers for
+    /// 'MAX_LIVE_POINTS' points are allocated up front.
+    pub fn new(gl: &Rc<opengl::Gl>, receiver: Receiver<PointsBatch>, fade: time::Duration) -> Self {
+        let program = GlProgramBuilder::new_with_vertex_shader(Rc::clone(gl), VERTEX_SHADER)
+            .fragment_shader(FRAGMENT_SHADER)
+            .build();
+        let u_world_to_gl;
+        let u_size;
+        let u_gamma;
+        let u_now;
+        let u_fade_seconds;
+        unsafe {
+            gl.UseProgram(program.id);
+            u_world_to_gl = gl.GetUniformLocation(program.id, c_str!("world_to_gl"));
+            u_size = gl.GetUniformLocation(program.id, c_str!("size"));
+            u_gamma = gl.GetUniformLocation(program.id, c_str!("gamma"));
+            u_now = gl.GetUniformLocation(program.id, c_str!("now"));
+            u_fade_seconds = gl.GetUniformLocation(program.id, c_str!("fade_seconds"));
+        }
+
+        let vertex_array = GlVertexArray::new(Rc::clone(gl));
+        vertex_array.bind();
+        let buffer_position = GlBuffer::new_array_buffer(Rc::clone(gl));
+        let buffer_color = GlBuffer::new_array_buffer(Rc::clone(gl));
+        let buffer_arrival = GlBuffer::new_array_buffer(Rc::clone(gl));
+        // Allocates a buffer for all points without filling it.
+        let allocate = |buffer: &GlBuffer, bytes_per_point: usize| unsafe {
+            buffer.bind();
+            gl.BufferData(
+                opengl::ARRAY_BUFFER,
+                (MAX_LIVE_POINTS * bytes_per_point) as GLsizeiptr,
+                ptr::null(),
+                opengl::DYNAMIC_DRAW,
+            );
+        };
+        allocate(&buffer_position, 3 * mem::size_of::<f64>());
+        allocate(&buffer_color, 3);
+        allocate(&buffer_arrival, mem::size_of::<f32>());
+        unsafe {
+            buffer_position.bind();
+            let pos_attr = gl.GetAttribLocation(program.id, c_str!("position")) as GLuint;
+            gl.EnableVertexAttribArray(pos_attr);
+            gl.VertexAttribLPointer(pos_attr, 3, opengl::DOUBLE, 0, ptr::null());
+
+            buffer_color.bind();
+            let color_attr = gl.GetAttribLocation(program.id, c_str!("color")) as GLuint;
+            gl.EnableVertexAttribArray(color_attr);
+            gl.VertexAttribPointer(
+                color_attr,
+                3,
+                opengl::UNSIGNED_BYTE,
+                opengl::FALSE as GLboolean,
+                0,
+                ptr::null(),
+            );
+
+            buffer_arrival.bind();
+            let arrival_attr = gl.GetAttribLocation(program.id, c_str!("arrival")) as GLuint;
+            gl.EnableVertexAttribArray(arrival_attr);
+            gl.VertexAttribPointer(
+                arrival_attr,
+                1,
+                opengl::FLOAT,
+                opengl::FALSE as GLboolean,
+                0,
+                ptr::null(),
+            );
+        }
+
+        LivePoints {
+            receiver,
+            fade,
+            start: time::Instant::now(),
+            chunks: VecDeque::new(),
+            ring: Ring::new(MAX_LIVE_POINTS),
+            program,
+            u_world_to_gl,
+            u_size,
+            u_gamma,
+            u_now,
+            u_fade_seconds,
+            vertex_array,
+            buffer_position,
+            buffer_color,
+            buffer_arrival,
+        }
+    }
+
+    /// Takes the batches that arrived since the last call and drops the points that have faded
+    /// out. Returns whether there is anything to draw, which changes every frame while points
+    /// are fading.
+    pub fn update(&mut self) -> bool {
+        let now = time::Instant::now();
+        while let Ok(mut batch) = self.receiver.try_recv() {
+            let mut color = match batch.attributes.remove("color") {
+                Some(AttributeData::U8Vec3(color)) => color,
+                _ => vec![Vector3::repeat(255); batch.position.len()],
+            };
+            let mut position = batch.position;
+            // Of a batch that does not fit at all, only the newest points are kept.
+            if position.len() > MAX_LIVE_POINTS {
+                let num_skipped = position.len() - MAX_LIVE_POINTS;
+                position.drain(..num_skipped);
+                color.drain(..num_skipped);
+            }
+            while self.ring.len + position.len() > MAX_LIVE_POINTS {
+                let oldest = self.chunks.pop_front().unwrap();
+                self.ring.pop(oldest.num_points);
+            }
+            let arrival_s = (now - self.start).as_seconds_f32();
+            let mut offset = 0;
+            for slots in self.ring.push(position.len()) {
+                let end = offset + slots.len();
+                self.upload(
+                    slots.start,
+                    &position[offset..end],
+                    &color[offset..end],
+                    arrival_s,
+                );
+                offset = end;
+            }
+            self.chunks.push_back(Chunk {
+                arrival: now,
+                num_points: position.len(),
+            });
+        }
+        while let Some(oldest) = self.chunks.front() {
+            if now - oldest.arrival < self.fade {
+                break;
+            }
+            self.ring.pop(oldest.num_points);
+            self.chunks.pop_front();
+        }
+        self.ring.len > 0
+    }
+
+    /// Writes the points into the buffers from slot 'first_slot' on.
+    fn upload(
+        &self,
+        first_slot: usize,
+        position: &[Point3<f64>],
+        color: &[Vector3<u8>],
+        arrival_s: f32,
+    ) {
+        let mut flat_position = Vec::with_capacity(3 * position.len());
+        for p in position {
+            flat_position.extend_from_slice(&[p.x, p.y, p.z]);
+        }
+        let mut flat_color = Vec::with_capacity(3 * color.len());
+        for c in color {
+            flat_color.extend_from_slice(&[c.x, c.y, c.z]);
+        }
+        let arrival = vec![arrival_s; position.len()];
+        let gl = &self.program.gl;
+        let sub_data = |buffer: &GlBuffer, bytes_per_point: usize, data: *const c_void| unsafe {
+            buffer.bind();
+            gl.BufferSubData(
+                opengl::ARRAY_BUFFER,
+                (first_slot * bytes_per_point) as GLintptr,
+                (arrival.len() * bytes_per_point) as GLsizeiptr,
+                data,
+            );
+        };
+        self.vertex_array.bind();
+        sub_data(
+            &self.buffer_position,
+            3 * mem::size_of::<f64>(),
+            flat_position.as_ptr() as *const c_void,
+        );
+        sub_data(&self.buffer_color, 3, flat_color.as_ptr() as *const c_void);
+        sub_data(
+            &self.buffer_arrival,
+            mem::size_of::<f32>(),
+            arrival.as_ptr() as *const c_void,
+        );
+    }
+
+    /// Draws the live points, blended over what has been drawn before. Returns the number of
+    /// points drawn.
+    pub fn draw(&self, world_to_gl: &Matrix4<f64>, point_size: f32, gamma: f32) -> usize {
+        if self.ring.len == 0 {
+            return 0;
+        }
+        let gl = &self.program.gl;
+        self.vertex_array.bind();
+        unsafe {
+            gl.UseProgram(self.program.id);
+            gl.UniformMatrix4dv(
+                self.u_world_to_gl,
+                1,
+                false as GLboolean,
+                world_to_gl.as_ptr(),
+            );
+            gl.Uniform1f(self.u_size, point_size);
+            gl.Uniform1f(self.u_gamma, gamma);
+            gl.Uniform1f(
+                self.u_now,
+                (time::Instant::now() - self.start).as_seconds_f32(),
+            );
+            gl.Uniform1f(self.u_fade_seconds, self.fade.as_seconds_f32());
+
+            gl.Enable(opengl::PROGRAM_POINT_SIZE);
+            gl.Enable(opengl::DEPTH_TEST);
+            // Like semi-transparent octree points, fading points do not write depth, so that
+            // they never hide the points behind them.
+            gl.Enable(opengl::BLEND);
+            gl.BlendFunc(opengl::SRC_ALPHA, opengl::ONE_MINUS_SRC_ALPHA);
+            gl.DepthMask(opengl::FALSE);
+            for slots in self.ring.occupied() {
+                gl.DrawArrays(opengl::POINTS, slots.start as i32, slots.len() as i32);
+            }
+            gl.DepthMask(opengl::TRUE);
+            gl.Disable(opengl::BLEND);
+            gl.Disable(opengl::PROGRAM_POINT_SIZE);
+        }
+        self.ring.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_batch_round_trip() {
+        let mut attributes = BTreeMap::new();
+        attributes.insert(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(1, 2, 3), Vector3::new(4, 5, 6)]),
+        );
+        let batch = PointsBatch {
+            position: vec![Point3::new(1., 2., 3.), Point3::new(-4.5, 5.5, 1e9)],
+            attributes,
+        };
+        let mut data = Vec::new();
+        write_batch(&mut data, &batch).unwrap();
+        write_batch(&mut data, &batch).unwrap();
+
+        let mut reader = Cursor::new(data);
+        for _ in 0..2 {
+            let read = read_batch(&mut reader).unwrap().unwrap();
+            assert_eq!(read.position, batch.position);
+            let color: &Vec<Vector3<u8>> = read.get_attribute_vec("color").unwrap();
+            assert_eq!(color, &vec![Vector3::new(1, 2, 3), Vector3::new(4, 5, 6)]);
+        }
+        assert!(read_batch(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_truncated_batch() {
+        // A source that announces more points than it sends.
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(MAX_POINTS_PER_BATCH)
+            .unwrap();
+        data.extend_from_slice(&[0; 27]);
+        assert!(read_batch(&mut Cursor::new(data)).is_err());
+
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(MAX_POINTS_PER_BATCH + 1)
+            .unwrap();
+        assert!(read_batch(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut ring = Ring::new(10);
+        assert_eq!(ring.push(6), [0..6]);
+        ring.pop(4);
+        assert_eq!(ring.push(7), [6..10, 0..3]);
+        assert_eq!(ring.occupied(), [4..10, 0..3]);
+        ring.pop(6);
+        assert_eq!(ring.occupied(), [0..3]);
+        assert_eq!(ring.push(7), [3..10]);
+        assert_eq!(ring.len, 10);
+    }
+}
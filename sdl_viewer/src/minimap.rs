@@ -0,0 +1,122 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A top-down overview of the whole point cloud in a corner of the window.
+
+use crate::opengl;
+use nalgebra::{Matrix4, Orthographic3, Point2, Vector3};
+use point_viewer::geometry::Aabb;
+use point_viewer::octree::NodeId;
+
+/// Nodes up to this level are drawn in the minimap. They are few and cover the whole cloud.
+pub const MAX_LEVEL: u8 = 3;
+
+/// Fraction of the smaller window dimension that the minimap takes up.
+const RELATIVE_SIZE: f64 = 0.3;
+
+/// A square viewport in window pixels, with the origin in the lower left corner like OpenGL.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub size: i32,
+}
+
+pub struct Minimap {
+    pub enabled: bool,
+    world_to_gl: Matrix4<f64>,
+    center: Point2<f64>,
+    half_extent: f64,
+    /// The nodes that are drawn, i.e. all nodes up to 'MAX_LEVEL'.
+    pub node_ids: Vec<NodeId>,
+}
+
+impl Minimap {
+    pub fn new(bounding_box: &Aabb, node_ids: Vec<NodeId>) -> Self {
+        let (min, max) = (bounding_box.min(), bounding_box.max());
+        let center = Point2::new((min.x + max.x) / 2., (min.y + max.y) / 2.);
+        let half_extent = ((max.x - min.x).max(max.y - min.y) / 2.).max(1.);
+        // We look down from just above the highest point, so OpenGL's view direction -z is
+        // the world's down.
+        let eye_from_world =
+            Matrix4::new_translation(&Vector3::new(-center.x, -center.y, -max.z - 1.));
+        let projection = Orthographic3::new(
+            -half_extent,
+            half_extent,
+            -half_extent,
+            half_extent,
+            0.,
+            max.z - min.z + 2.,
+        );
+        Minimap {
+            enabled: false,
+            world_to_gl: projection.to_homogeneous() * eye_from_world,
+            center,
+            half_extent,
+            node_ids,
+        }
+    }
+
+    pub fn world_to_gl(&self) -> &Matrix4<f64> {
+        &self.world_to_gl
+    }
+
+    /// The minimap sits in the lower right corner of a window of the given size.
+    pub fn viewport(&self, width: i32, height: i32) -> Viewport {
+        let size = (f64::from(width.min(height)) * RELATIVE_SIZE) as i32;
+        Viewport {
+            x: width - size,
+            y: 0,
+            size,
+        }
+    }
+
+    /// Returns the world x and y coordinates under the window pixel ('x', 'y'), with the origin in
+    /// the upper left corner like SDL mouse events, or None if the pixel is not in the minimap.
+    pub fn world_xy_at(&self, x: i32, y: i32, width: i32, height: i32) -> Option<Point2<f64>> {
+        if !self.enabled {
+            return None;
+        }
+        let viewport = self.viewport(width, height);
+        let (x, y) = (x - viewport.x, height - y - viewport.y);
+        if x < 0 || y < 0 || x >= viewport.size || y >= viewport.size {
+            return None;
+        }
+        let to_ndc = |v: i32| 2. * (f64::from(v) + 0.5) / f64::from(viewport.size) - 1.;
+        Some(Point2::new(
+            self.center.x + to_ndc(x) * self.half_extent,
+            self.center.y + to_ndc(y) * self.half_extent,
+        ))
+    }
+
+    /// Restricts drawing to the minimap and clears it.
+    pub fn begin_drawing(&self, gl: &opengl::Gl, width: i32, height: i32) {
+        let viewport = self.viewport(width, height);
+        unsafe {
+            gl.Viewport(viewport.x, viewport.y, viewport.size, viewport.size);
+            gl.Enable(opengl::SCISSOR_TEST);
+            gl.Scissor(viewport.x, viewport.y, viewport.size, viewport.size);
+            gl.ClearColor(0.15, 0.15, 0.15, 1.);
+            gl.Clear(opengl::COLOR_BUFFER_BIT | opengl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// Restores drawing to the whole window.
+    pub fn end_drawing(&self, gl: &opengl::Gl, width: i32, height: i32) {
+        unsafe {
+            gl.Disable(opengl::SCISSOR_TEST);
+            gl.Viewport(0, 0, width, height);
+        }
+    }
+}
@@ -14,23 +14,47 @@
 
 use crate::graphic::{GlBuffer, GlProgram, GlProgramBuilder, GlVertexArray};
 use crate::opengl;
-use crate::opengl::types::{GLboolean, GLint, GLsizeiptr, GLuint};
-use fnv::FnvHashSet;
+use crate::opengl::types::{GLboolean, GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::channel::mpsc::{unbounded, TryRecvError, UnboundedSender};
+use futures::future::{abortable, AbortHandle, Future};
+use futures::stream::FuturesUnordered;
+use futures::{select, FutureExt, StreamExt};
 use lru::LruCache;
 use nalgebra::Matrix4;
-use point_viewer::octree;
-use point_viewer::read_write::PositionEncoding;
-use rand::{prelude::SliceRandom, thread_rng};
+use point_viewer::data_provider::{AsyncDataProvider, BlockingDataProvider, DataSource};
+use point_viewer::math::ClosedInterval;
+use point_viewer::octree::{self, ColorSource};
+use point_viewer::read_write::{fixpoint_encode, PositionEncoding};
+use rand::rngs::StdRng;
+use rand::{prelude::SliceRandom, thread_rng, Rng, SeedableRng};
+use std::collections::VecDeque;
 use std::os::raw::c_void;
+use std::panic::AssertUnwindSafe;
 use std::ptr;
 use std::rc::Rc;
-use std::str;
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::str::{self, FromStr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
 use std::sync::Arc;
 
+/// The number of nodes that are loaded at the same time, so that a slow node does not hold up the
+/// ones behind it. This is also the number of threads that read the data.
+const MAX_CONCURRENT_LOADS: usize = 4;
+
 const FRAGMENT_SHADER: &str = include_str!("../shaders/points.fs");
 const VERTEX_SHADER: &str = include_str!("../shaders/points.vs");
 
+/// The order in which the points of a node are drawn. It is random, which allows us to only draw
+/// the first N if we want to draw less, but derived from 'seed', so that attributes that are
+/// uploaded later can be put into the same order.
+fn draw_order(num_points: usize, seed: u64) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..num_points).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    indices
+}
+
 fn reshuffle(new_order: &[usize], old_data: &[u8], bytes_per_vertex: usize) -> Vec<u8> {
     assert_eq!(new_order.len() * bytes_per_vertex, old_data.len());
     let mut new_data = Vec::with_capacity(old_data.len());
@@ -42,6 +66,25 @@ fn reshuffle(new_order: &[usize], old_data: &[u8], bytes_per_vertex: usize) -> V
     new_data
 }
 
+/// Converts float positions, which are relative to the node's bounding cube, to 16 bit fixpoint.
+fn compress_positions(position: Vec<u8>, encoding: &PositionEncoding) -> Vec<u8> {
+    let to_fixpoint = |value: f64| fixpoint_encode::<u16>(value, 0., 1.);
+    let values: Vec<u16> = match encoding {
+        PositionEncoding::Uint8 | PositionEncoding::Uint16 => return position,
+        PositionEncoding::Float32 => position
+            .chunks_exact(4)
+            .map(|bytes| to_fixpoint(f64::from(LittleEndian::read_f32(bytes))))
+            .collect(),
+        PositionEncoding::Float64 => position
+            .chunks_exact(8)
+            .map(|bytes| to_fixpoint(LittleEndian::read_f64(bytes)))
+            .collect(),
+    };
+    let mut compressed = vec![0; 2 * values.len()];
+    LittleEndian::write_u16_into(&values, &mut compressed);
+    compressed
+}
+
 pub struct NodeProgram {
     program: GlProgram,
 
@@ -49,13 +92,126 @@ pub struct NodeProgram {
     u_world_to_gl: GLint,
     u_edge_length: GLint,
     u_size: GLint,
+    u_attenuation_distance: GLint,
     u_gamma: GLint,
     u_min: GLint,
+    u_has_labels: GLint,
+    u_color_by_label: GLint,
+    u_label_colors: GLint,
+    u_has_selection: GLint,
+    u_selection_clip_from_world: GLint,
+    u_relative_to_node: GLint,
+    u_gl_from_node: GLint,
+    u_selection_clip_from_node: GLint,
+    u_has_time_window: GLint,
+    u_time_window: GLint,
+
+    // Attribute locations.
+    a_alpha: GLuint,
+    a_label: GLuint,
+    a_timestamp: GLuint,
+}
+
+/// The number of possible label ids.
+pub const NUM_LABEL_IDS: usize = 1 << 16;
+
+/// The width of the label colors texture. 1D textures cannot hold all label ids on every GPU, so
+/// the colors are laid out in rows of this many ids.
+const LABEL_COLORS_WIDTH: usize = 256;
+
+/// A 2D texture with the RGBA color of each possible label id, row by row.
+struct LabelColorsTexture {
+    gl: Rc<opengl::Gl>,
+    id: GLuint,
+}
+
+impl LabelColorsTexture {
+    fn new(gl: Rc<opengl::Gl>) -> Self {
+        let mut id = 0;
+        unsafe {
+            gl.GenTextures(1, &mut id);
+            gl.BindTexture(opengl::TEXTURE_2D, id);
+            // Label ids are fetched exactly, no interpolation needed.
+            gl.TexParameteri(
+                opengl::TEXTURE_2D,
+                opengl::TEXTURE_MIN_FILTER,
+                opengl::NEAREST as i32,
+            );
+            gl.TexParameteri(
+                opengl::TEXTURE_2D,
+                opengl::TEXTURE_MAG_FILTER,
+                opengl::NEAREST as i32,
+            );
+        }
+        let texture = LabelColorsTexture { gl, id };
+        texture.update(&vec![[255; 4]; NUM_LABEL_IDS]);
+        texture
+    }
+
+    fn update(&self, colors: &[[u8; 4]]) {
+        assert_eq!(colors.len(), NUM_LABEL_IDS);
+        unsafe {
+            self.gl.BindTexture(opengl::TEXTURE_2D, self.id);
+            self.gl.TexImage2D(
+                opengl::TEXTURE_2D,
+                0, // level
+                opengl::RGBA8 as GLint,
+                LABEL_COLORS_WIDTH as GLsizei,
+                (NUM_LABEL_IDS / LABEL_COLORS_WIDTH) as GLsizei,
+                0, // border
+                opengl::RGBA,
+                opengl::UNSIGNED_BYTE,
+                colors.as_ptr() as *const c_void,
+            );
+        }
+    }
+}
+
+impl Drop for LabelColorsTexture {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+/// How the positions of points are transformed into clip space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// In double precision on the GPU, which is slow on GPUs with few f64 units.
+    Double,
+    /// The transformation from the unit cube of each node into clip space is computed in double
+    /// precision on the CPU and applied in single precision on the GPU. Since it only sees
+    /// positions relative to the node, there is no jitter far from the origin, e.g. in ECEF.
+    RelativeToNode,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "double" => Ok(Precision::Double),
+            "relative_to_node" => Ok(Precision::RelativeToNode),
+            _ => Err(format!("Unknown precision '{}'.", s)),
+        }
+    }
 }
 
 pub struct NodeDrawer {
     program_f32: NodeProgram,
     program_f64: NodeProgram,
+    label_colors: LabelColorsTexture,
+    color_by_label: bool,
+    precision: Precision,
+    compress_positions: bool,
+    // Kept for computing the per-node matrices in 'Precision::RelativeToNode'.
+    world_to_gl: Matrix4<f64>,
+    selection_clip_from_world: Option<Matrix4<f64>>,
+    // Timestamps are uploaded in single precision relative to this origin, usually the start of
+    // the recording.
+    time_origin: f64,
+    time_window: Option<ClosedInterval<f64>>,
 }
 
 impl NodeDrawer {
@@ -67,24 +223,69 @@ impl NodeDrawer {
             let u_world_to_gl;
             let u_edge_length;
             let u_size;
+            let u_attenuation_distance;
             let u_gamma;
             let u_min;
+            let u_has_labels;
+            let u_color_by_label;
+            let u_label_colors;
+            let u_has_selection;
+            let u_selection_clip_from_world;
+            let u_relative_to_node;
+            let u_gl_from_node;
+            let u_selection_clip_from_node;
+            let u_has_time_window;
+            let u_time_window;
+            let a_alpha;
+            let a_label;
+            let a_timestamp;
             unsafe {
                 gl.UseProgram(program.id);
 
                 u_world_to_gl = gl.GetUniformLocation(program.id, c_str!("world_to_gl"));
                 u_edge_length = gl.GetUniformLocation(program.id, c_str!("edge_length"));
                 u_size = gl.GetUniformLocation(program.id, c_str!("size"));
+                u_attenuation_distance =
+                    gl.GetUniformLocation(program.id, c_str!("attenuation_distance"));
                 u_gamma = gl.GetUniformLocation(program.id, c_str!("gamma"));
                 u_min = gl.GetUniformLocation(program.id, c_str!("min"));
+                u_has_labels = gl.GetUniformLocation(program.id, c_str!("has_labels"));
+                u_color_by_label = gl.GetUniformLocation(program.id, c_str!("color_by_label"));
+                u_label_colors = gl.GetUniformLocation(program.id, c_str!("label_colors"));
+                u_has_selection = gl.GetUniformLocation(program.id, c_str!("has_selection"));
+                u_selection_clip_from_world =
+                    gl.GetUniformLocation(program.id, c_str!("selection_clip_from_world"));
+                u_relative_to_node = gl.GetUniformLocation(program.id, c_str!("relative_to_node"));
+                u_gl_from_node = gl.GetUniformLocation(program.id, c_str!("gl_from_node"));
+                u_selection_clip_from_node =
+                    gl.GetUniformLocation(program.id, c_str!("selection_clip_from_node"));
+                u_has_time_window = gl.GetUniformLocation(program.id, c_str!("has_time_window"));
+                u_time_window = gl.GetUniformLocation(program.id, c_str!("time_window"));
+                a_alpha = gl.GetAttribLocation(program.id, c_str!("alpha")) as GLuint;
+                a_label = gl.GetAttribLocation(program.id, c_str!("label")) as GLuint;
+                a_timestamp = gl.GetAttribLocation(program.id, c_str!("timestamp")) as GLuint;
             }
             NodeProgram {
                 program,
                 u_world_to_gl,
                 u_edge_length,
                 u_size,
+                u_attenuation_distance,
                 u_gamma,
                 u_min,
+                u_has_labels,
+                u_color_by_label,
+                u_label_colors,
+                u_has_selection,
+                u_selection_clip_from_world,
+                u_relative_to_node,
+                u_gl_from_node,
+                u_selection_clip_from_node,
+                u_has_time_window,
+                u_time_window,
+                a_alpha,
+                a_label,
+                a_timestamp,
             }
         };
         let program_f32 = create_program(VERTEX_SHADER);
@@ -96,9 +297,60 @@ impl NodeDrawer {
         NodeDrawer {
             program_f32,
             program_f64,
+            label_colors: LabelColorsTexture::new(Rc::clone(gl)),
+            color_by_label: false,
+            precision: Precision::Double,
+            compress_positions: true,
+            world_to_gl: Matrix4::identity(),
+            selection_clip_from_world: None,
+            time_origin: 0.,
+            time_window: None,
         }
     }
 
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    /// Whether nodes with float positions are uploaded as 16 bit fixpoint relative to their
+    /// bounding cube, which halves their GPU memory (or quarters it for f64). Must be set
+    /// before the first node view is created.
+    pub fn set_compress_positions(&mut self, compress_positions: bool) {
+        self.compress_positions = compress_positions;
+    }
+
+    /// The encoding in which positions of 'encoding' are uploaded to the GPU.
+    fn upload_encoding(&self, encoding: &PositionEncoding) -> PositionEncoding {
+        match encoding {
+            PositionEncoding::Float32 | PositionEncoding::Float64 if self.compress_positions => {
+                PositionEncoding::Uint16
+            }
+            _ => encoding.clone(),
+        }
+    }
+
+    /// Sets the RGBA color of each of the 'NUM_LABEL_IDS' label ids. Points with labels that have
+    /// an alpha of 0 are not drawn.
+    pub fn set_label_colors(&mut self, colors: &[[u8; 4]]) {
+        self.label_colors.update(colors);
+    }
+
+    /// Whether points with labels are drawn in the color of their label instead of their own.
+    pub fn set_color_by_label(&mut self, color_by_label: bool) {
+        self.color_by_label = color_by_label;
+    }
+
+    /// Must be set before the first node view is created, since it is baked into them.
+    pub fn set_time_origin(&mut self, time_origin: f64) {
+        self.time_origin = time_origin;
+    }
+
+    /// Only draws the points of nodes with timestamps whose timestamps lie within 'window', or
+    /// all points.
+    pub fn set_time_window(&mut self, window: Option<ClosedInterval<f64>>) {
+        self.time_window = window;
+    }
+
     pub fn program(&self, position_encoding: &PositionEncoding) -> &NodeProgram {
         if let PositionEncoding::Float64 = position_encoding {
             &self.program_f64
@@ -119,20 +371,48 @@ impl NodeDrawer {
         };
         update_matrix(&mut self.program_f32);
         update_matrix(&mut self.program_f64);
+        self.world_to_gl = *matrix;
     }
 
+    /// Highlights the points that 'clip_from_world' maps into the unit cube, or none.
+    pub fn set_selection(&mut self, clip_from_world: Option<&Matrix4<f64>>) {
+        let update_selection = |node_program: &mut NodeProgram| unsafe {
+            let gl = &node_program.program.gl;
+            gl.UseProgram(node_program.program.id);
+            gl.Uniform1i(
+                node_program.u_has_selection,
+                clip_from_world.is_some() as GLint,
+            );
+            if let Some(matrix) = clip_from_world {
+                gl.UniformMatrix4dv(
+                    node_program.u_selection_clip_from_world,
+                    1,
+                    false as GLboolean,
+                    matrix.as_ptr(),
+                );
+            }
+        };
+        update_selection(&mut self.program_f32);
+        update_selection(&mut self.program_f64);
+        self.selection_clip_from_world = clip_from_world.copied();
+    }
+
+    /// Draws the points of 'node_view' with 'point_size' pixels. With an 'attenuation_distance',
+    /// this is the size of points at that distance from the camera, and closer points are drawn
+    /// larger and farther ones smaller.
     pub fn draw(
         &self,
         node_view: &NodeView,
         level_of_detail: i32,
         point_size: f32,
+        attenuation_distance: Option<f32>,
         gamma: f32,
     ) -> i64 {
         node_view.vertex_array.bind();
         let num_points = node_view
             .meta
             .num_points_for_level_of_detail(level_of_detail);
-        let node_program = self.program(&node_view.meta.position_encoding);
+        let node_program = self.program(&node_view.position_encoding);
         let program = &node_program.program;
         unsafe {
             program.gl.UseProgram(program.id);
@@ -144,6 +424,10 @@ impl NodeDrawer {
                 node_view.meta.bounding_cube.edge_length(),
             );
             program.gl.Uniform1f(node_program.u_size, point_size);
+            program.gl.Uniform1f(
+                node_program.u_attenuation_distance,
+                attenuation_distance.unwrap_or(0.),
+            );
             program.gl.Uniform1f(node_program.u_gamma, gamma);
 
             program.gl.Uniform3dv(
@@ -152,8 +436,79 @@ impl NodeDrawer {
                 node_view.meta.bounding_cube.min().coords.as_ptr(),
             );
 
+            let relative_to_node = self.precision == Precision::RelativeToNode;
+            program
+                .gl
+                .Uniform1i(node_program.u_relative_to_node, relative_to_node as GLint);
+            if relative_to_node {
+                let bounding_cube = &node_view.meta.bounding_cube;
+                let world_from_node = Matrix4::new_translation(&bounding_cube.min().coords)
+                    * Matrix4::new_scaling(bounding_cube.edge_length());
+                let gl_from_node: Matrix4<f32> =
+                    nalgebra::convert(self.world_to_gl * world_from_node);
+                program.gl.UniformMatrix4fv(
+                    node_program.u_gl_from_node,
+                    1,
+                    false as GLboolean,
+                    gl_from_node.as_ptr(),
+                );
+                if let Some(selection_clip_from_world) = &self.selection_clip_from_world {
+                    let selection_clip_from_node: Matrix4<f32> =
+                        nalgebra::convert(selection_clip_from_world * world_from_node);
+                    program.gl.UniformMatrix4fv(
+                        node_program.u_selection_clip_from_node,
+                        1,
+                        false as GLboolean,
+                        selection_clip_from_node.as_ptr(),
+                    );
+                }
+            }
+
+            program
+                .gl
+                .Uniform1i(node_program.u_has_labels, node_view.has_labels as GLint);
+            if node_view.has_labels {
+                program
+                    .gl
+                    .Uniform1i(node_program.u_color_by_label, self.color_by_label as GLint);
+                program.gl.ActiveTexture(opengl::TEXTURE0);
+                program
+                    .gl
+                    .BindTexture(opengl::TEXTURE_2D, self.label_colors.id);
+                program.gl.Uniform1i(node_program.u_label_colors, 0);
+            }
+
+            match &self.time_window {
+                Some(window) if node_view.has_timestamps => {
+                    program.gl.Uniform1i(node_program.u_has_time_window, 1);
+                    program.gl.Uniform2f(
+                        node_program.u_time_window,
+                        (window.lower_bound() - self.time_origin) as f32,
+                        (window.upper_bound() - self.time_origin) as f32,
+                    );
+                }
+                _ => program.gl.Uniform1i(node_program.u_has_time_window, 0),
+            }
+
+            // Semi-transparent points are blended over what is behind them. They do not write
+            // depth, so that they never hide opaque points that are drawn later.
+            if node_view.has_alpha {
+                program.gl.Enable(opengl::BLEND);
+                program
+                    .gl
+                    .BlendFunc(opengl::SRC_ALPHA, opengl::ONE_MINUS_SRC_ALPHA);
+                program.gl.DepthMask(opengl::FALSE);
+            } else {
+                // The alpha attribute array is disabled, so the shader sees this constant instead.
+                program.gl.VertexAttrib1f(node_program.a_alpha, 255.);
+            }
+
             program.gl.DrawArrays(opengl::POINTS, 0, num_points as i32);
 
+            if node_view.has_alpha {
+                program.gl.DepthMask(opengl::TRUE);
+                program.gl.Disable(opengl::BLEND);
+            }
             program.gl.Disable(opengl::PROGRAM_POINT_SIZE);
         }
         num_points
@@ -162,18 +517,35 @@ impl NodeDrawer {
 
 pub struct NodeView {
     pub meta: octree::NodeMeta,
+    // The encoding of the uploaded positions, which can differ from the one in 'meta'.
+    position_encoding: PositionEncoding,
 
     // The buffers are bound by 'vertex_array', so we never refer to them. But they must outlive
     // this 'NodeView'.
     vertex_array: GlVertexArray,
     _buffer_position: GlBuffer,
-    _buffer_color: GlBuffer,
+    // Holds the colors of 'color_source'. It is refilled when the source changes, while the
+    // positions and other attributes stay as they are.
+    buffer_color: GlBuffer,
+    color_source: ColorSource,
+    draw_order_seed: u64,
+    _buffer_alpha: Option<GlBuffer>,
+    _buffer_labels: Option<GlBuffer>,
+    has_alpha: bool,
+    has_labels: bool,
+    _buffer_timestamps: Option<GlBuffer>,
+    has_timestamps: bool,
     used_memory_bytes: usize,
 }
 
 impl NodeView {
-    fn new(node_drawer: &NodeDrawer, node_data: octree::NodeData) -> Self {
-        let node_program = node_drawer.program(&node_data.meta.position_encoding);
+    fn new(
+        node_drawer: &NodeDrawer,
+        node_data: octree::NodeData,
+        color_source: ColorSource,
+    ) -> Self {
+        let position_encoding = node_drawer.upload_encoding(&node_data.meta.position_encoding);
+        let node_program = node_drawer.program(&position_encoding);
         let program = &node_program.program;
         unsafe {
             program.gl.UseProgram(program.id);
@@ -182,13 +554,10 @@ impl NodeView {
         let vertex_array = GlVertexArray::new(Rc::clone(&program.gl));
         vertex_array.bind();
 
-        // We draw the points in random order. This allows us to only draw the first N if we want
-        // to draw less.
-        let mut indices: Vec<usize> = (0..node_data.meta.num_points as usize).collect();
-        let mut rng = thread_rng();
-        indices.shuffle(&mut rng);
+        let draw_order_seed = thread_rng().gen();
+        let indices = draw_order(node_data.meta.num_points as usize, draw_order_seed);
 
-        let position = reshuffle(
+        let mut position = reshuffle(
             &indices,
             &node_data.position,
             match node_data.meta.position_encoding {
@@ -198,14 +567,34 @@ impl NodeView {
                 PositionEncoding::Float64 => 24,
             },
         );
-        let color = reshuffle(&indices, &node_data.color, 3);
+        if position_encoding != node_data.meta.position_encoding {
+            position = compress_positions(position, &node_data.meta.position_encoding);
+        }
+        let color = reshuffle(&indices, &node_data.colors(None), 3);
+        let alpha = node_data
+            .alpha
+            .as_ref()
+            .map(|alpha| reshuffle(&indices, alpha, 1));
+        let labels = node_data.labels.as_ref().map(|labels| {
+            let mut bytes = vec![0; 2 * labels.len()];
+            LittleEndian::write_u16_into(labels, &mut bytes);
+            reshuffle(&indices, &bytes, 2)
+        });
+        let timestamps: Option<Vec<f32>> = node_data.timestamp.as_ref().map(|timestamp| {
+            reshuffle(&indices, timestamp, 8)
+                .chunks_exact(8)
+                .map(|bytes| (LittleEndian::read_f64(bytes) - node_drawer.time_origin) as f32)
+                .collect()
+        });
 
         let buffer_position = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
         let buffer_color = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
 
+        // The buffers of nodes that all points were deleted from are empty, 'as_ptr' is still
+        // valid for them.
         unsafe {
             buffer_position.bind();
-            let (normalize, data_type) = match node_data.meta.position_encoding {
+            let (normalize, data_type) = match position_encoding {
                 PositionEncoding::Uint8 => (opengl::TRUE, opengl::UNSIGNED_BYTE),
                 PositionEncoding::Uint16 => (opengl::TRUE, opengl::UNSIGNED_SHORT),
                 PositionEncoding::Float32 => (opengl::FALSE, opengl::FLOAT),
@@ -214,14 +603,14 @@ impl NodeView {
             program.gl.BufferData(
                 opengl::ARRAY_BUFFER,
                 position.len() as GLsizeiptr,
-                &position[0] as *const u8 as *const c_void,
+                position.as_ptr() as *const c_void,
                 opengl::STATIC_DRAW,
             );
 
             // Specify the layout of the vertex data.
             let pos_attr = program.gl.GetAttribLocation(program.id, c_str!("position")) as GLuint;
             program.gl.EnableVertexAttribArray(pos_attr);
-            if node_data.meta.position_encoding == PositionEncoding::Float64 {
+            if position_encoding == PositionEncoding::Float64 {
                 program
                     .gl
                     .VertexAttribLPointer(pos_attr, 3, data_type, 0, ptr::null());
@@ -235,7 +624,7 @@ impl NodeView {
             program.gl.BufferData(
                 opengl::ARRAY_BUFFER,
                 color.len() as GLsizeiptr,
-                &color[0] as *const u8 as *const c_void,
+                color.as_ptr() as *const c_void,
                 opengl::STATIC_DRAW,
             );
             let color_attr = program.gl.GetAttribLocation(program.id, c_str!("color"));
@@ -249,81 +638,532 @@ impl NodeView {
                 ptr::null(),
             );
         }
+
+        // Creates a buffer for a per-point integer attribute of 'data_type'.
+        let integer_buffer = |data: &Vec<u8>, attribute: GLuint, data_type: GLenum| {
+            let buffer = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
+            unsafe {
+                buffer.bind();
+                program.gl.BufferData(
+                    opengl::ARRAY_BUFFER,
+                    data.len() as GLsizeiptr,
+                    data.as_ptr() as *const c_void,
+                    opengl::STATIC_DRAW,
+                );
+                program.gl.EnableVertexAttribArray(attribute);
+                program.gl.VertexAttribPointer(
+                    attribute,
+                    1,
+                    data_type,
+                    opengl::FALSE as GLboolean,
+                    0,
+                    ptr::null(),
+                );
+            }
+            buffer
+        };
+        let buffer_alpha = alpha
+            .as_ref()
+            .map(|alpha| integer_buffer(alpha, node_program.a_alpha, opengl::UNSIGNED_BYTE));
+        let buffer_labels = labels
+            .as_ref()
+            .map(|labels| integer_buffer(labels, node_program.a_label, opengl::UNSIGNED_SHORT));
+        let buffer_timestamps = timestamps.as_ref().map(|timestamps| {
+            let buffer = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
+            unsafe {
+                buffer.bind();
+                program.gl.BufferData(
+                    opengl::ARRAY_BUFFER,
+                    (timestamps.len() * std::mem::size_of::<f32>()) as GLsizeiptr,
+                    timestamps.as_ptr() as *const c_void,
+                    opengl::STATIC_DRAW,
+                );
+                program.gl.EnableVertexAttribArray(node_program.a_timestamp);
+                program.gl.VertexAttribPointer(
+                    node_program.a_timestamp,
+                    1,
+                    opengl::FLOAT,
+                    opengl::FALSE as GLboolean,
+                    0,
+                    ptr::null(),
+                );
+            }
+            buffer
+        });
+        let alpha_len = alpha.as_ref().map_or(0, Vec::len);
+        let labels_len = labels.as_ref().map_or(0, Vec::len);
+        let timestamps_len = timestamps
+            .as_ref()
+            .map_or(0, |t| t.len() * std::mem::size_of::<f32>());
+
         NodeView {
             vertex_array,
             _buffer_position: buffer_position,
-            _buffer_color: buffer_color,
+            buffer_color,
+            color_source,
+            draw_order_seed,
+            _buffer_alpha: buffer_alpha,
+            _buffer_labels: buffer_labels,
+            has_alpha: alpha.is_some(),
+            has_labels: labels.is_some(),
+            _buffer_timestamps: buffer_timestamps,
+            has_timestamps: timestamps.is_some(),
             meta: node_data.meta,
-            used_memory_bytes: position.len() + color.len(),
+            position_encoding,
+            used_memory_bytes: position.len()
+                + color.len()
+                + alpha_len
+                + labels_len
+                + timestamps_len,
+        }
+    }
+
+    /// Replaces the colors of the points with 'color' from 'color_source', three bytes per point.
+    /// The color buffer is refilled in place, so this takes no additional GPU memory.
+    fn set_colors(&mut self, node_drawer: &NodeDrawer, color: &[u8], color_source: ColorSource) {
+        let indices = draw_order(self.meta.num_points as usize, self.draw_order_seed);
+        let color = reshuffle(&indices, color, 3);
+        let gl = &node_drawer.program(&self.position_encoding).program.gl;
+        self.buffer_color.bind();
+        unsafe {
+            gl.BufferData(
+                opengl::ARRAY_BUFFER,
+                color.len() as GLsizeiptr,
+                color.as_ptr() as *const c_void,
+                opengl::STATIC_DRAW,
+            );
+        }
+        self.color_source = color_source;
+    }
+}
+
+fn panic_message(cause: &(dyn std::any::Any + Send)) -> String {
+    let message = cause
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| cause.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("Panicked while loading: {}", message)
+}
+
+/// What the I/O thread loads for a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Load {
+    /// All attributes that are drawn, with the colors of the source, for a node that is not
+    /// uploaded yet.
+    Node(ColorSource),
+    /// Only the colors of the source, for a node that is uploaded with other colors.
+    Colors(ColorSource),
+}
+
+/// What the I/O thread loaded for a node.
+enum Loaded {
+    Node(octree::NodeData, ColorSource),
+    Colors(Vec<u8>, ColorSource),
+}
+
+async fn read_node(
+    octree: &octree::Octree,
+    data_provider: &dyn AsyncDataProvider,
+    node_id: &octree::NodeId,
+    load: Load,
+    intensity_range: Option<ClosedInterval<f64>>,
+) -> Result<Loaded, String> {
+    match load {
+        Load::Node(color_source) => {
+            let mut node_data = octree
+                .fetch_node_data_colored_by(data_provider, node_id, color_source)
+                .await
+                .map_err(|err| err.to_string())?;
+            if node_data.color.is_none() {
+                node_data.color = Some(node_data.colors(intensity_range).into_owned());
+            }
+            Ok(Loaded::Node(node_data, color_source))
+        }
+        Load::Colors(color_source) => {
+            let color = octree
+                .fetch_node_colors(data_provider, node_id, color_source, intensity_range)
+                .await
+                .map_err(|err| err.to_string())?;
+            // Colors that do not match the uploaded points cannot be drawn.
+            let num_points = octree.node_meta(node_id).map_or(0, |meta| meta.num_points);
+            if color.len() != 3 * num_points as usize {
+                return Err(format!(
+                    "Read {} bytes of colors for {} points.",
+                    color.len(),
+                    num_points
+                ));
+            }
+            Ok(Loaded::Colors(color, color_source))
+        }
+    }
+}
+
+/// Loads the data of a node through 'data_provider'. A node that fails to load, even by panicking
+/// while decoding, must not take the loader and with it all other nodes down.
+fn load_node(
+    octree: Arc<octree::Octree>,
+    data_provider: Arc<dyn AsyncDataProvider>,
+    node_id: octree::NodeId,
+    load: Load,
+    intensity_range: Option<ClosedInterval<f64>>,
+) -> impl Future<Output = (Result<Loaded, String>, LoadStats)> {
+    let source = octree.data_source(&node_id);
+    let start = time::Instant::now();
+    async move {
+        let loaded = AssertUnwindSafe(read_node(
+            &octree,
+            &*data_provider,
+            &node_id,
+            load,
+            intensity_range,
+        ))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|cause| Err(panic_message(&*cause)));
+        let load_stats = LoadStats {
+            latency: time::Instant::now() - start,
+            source,
+        };
+        (loaded, load_stats)
+    }
+}
+
+/// Takes a request of the container: a load that is running or waiting already is renewed with
+/// the request's generation, other loads are queued.
+fn add_request(
+    (node_id, load, generation): (octree::NodeId, Load, u64),
+    loading: &mut FnvHashMap<(octree::NodeId, Load), (u64, AbortHandle)>,
+    pending: &mut VecDeque<(octree::NodeId, Load, u64)>,
+) {
+    if let Some((loading_generation, _)) = loading.get_mut(&(node_id, load)) {
+        *loading_generation = generation;
+        return;
+    }
+    match pending
+        .iter_mut()
+        .find(|(id, pending_load, _)| *id == node_id && *pending_load == load)
+    {
+        Some((_, _, pending_generation)) => *pending_generation = generation,
+        None => pending.push_back((node_id, load, generation)),
+    }
+}
+
+/// How much node data is uploaded to the GPU per frame at most. Uploading all nodes that arrived
+/// at once, e.g. right after the camera stopped, would stall that frame. At least one node is
+/// uploaded per frame, however large.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadBudget {
+    pub bytes: usize,
+    pub duration: time::Duration,
+}
+
+impl Default for UploadBudget {
+    fn default() -> Self {
+        UploadBudget {
+            bytes: 32 * 1024 * 1024,
+            duration: time::Duration::milliseconds(4),
         }
     }
 }
 
-// Keeps track of the nodes that were requested in-order and loads then one by one on request.
+/// How the last load of a node went, for the load latency overlay.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadStats {
+    pub latency: time::Duration,
+    pub source: DataSource,
+}
+
+// Keeps track of the nodes that were requested in-order and loads them, several at a time, on
+// request.
 pub struct NodeViewContainer {
     node_views: LruCache<octree::NodeId, NodeView>,
-    // The node_ids that the I/O thread is currently loading.
-    requested: FnvHashSet<octree::NodeId>,
+    // The attribute that the points are colored by. Nodes that were uploaded with other colors
+    // are drawn with them until the new ones arrived.
+    color_source: ColorSource,
+    // The node_ids that the I/O thread is currently loading, with what is loaded and the
+    // generation they were last requested in.
+    requested: FnvHashMap<octree::NodeId, (Load, u64)>,
+    // The nodes that should be shown. Nodes that arrive after they dropped out of this set are
+    // not uploaded to the GPU.
+    wanted: FnvHashSet<octree::NodeId>,
+    // Incremented whenever 'wanted' is replaced. The I/O thread skips requests from older
+    // generations, since they might be stale.
+    generation: Arc<AtomicU64>,
+    // The node_ids that could not be loaded. They are not requested again.
+    failed: FnvHashSet<octree::NodeId>,
+    // Failures that have not been taken by 'take_failures' yet.
+    new_failures: Vec<(octree::NodeId, String)>,
+    // Loaded nodes that wait for their upload to the GPU, see 'UploadBudget'.
+    arrived: VecDeque<(octree::NodeId, Loaded)>,
+    // The last load of every node that is loaded, waits for its upload or failed to load.
+    load_stats: FnvHashMap<octree::NodeId, LoadStats>,
+    pub upload_budget: UploadBudget,
+    // To hint the data provider at the nodes that are about to be requested.
+    octree: Arc<octree::Octree>,
     // Communication with the I/O thread.
-    node_id_sender: Sender<octree::NodeId>,
-    node_data_receiver: Receiver<(octree::NodeId, octree::NodeData)>,
+    node_id_sender: UnboundedSender<(octree::NodeId, Load, u64)>,
+    node_data_receiver: Receiver<(octree::NodeId, Result<Loaded, String>, LoadStats)>,
 }
 
 impl NodeViewContainer {
     pub fn new(octree: Arc<octree::Octree>, max_nodes_in_memory: usize) -> Self {
         // We perform I/O in a separate thread in order to not block the main thread while loading.
-        // Data sharing is done through channels.
-        let (node_id_sender, node_id_receiver) = mpsc::channel();
+        // Data sharing is done through channels. The thread overlaps the loads of several nodes
+        // and cancels the ones that are no longer wanted.
+        let (node_id_sender, mut node_id_receiver) = unbounded::<(octree::NodeId, Load, u64)>();
         let (node_data_sender, node_data_receiver) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current_generation = Arc::clone(&generation);
+        let loader_octree = Arc::clone(&octree);
+        // The reads of the loads run on a bounded pool of threads. Loads that are cancelled before
+        // their reads started never occupy one.
+        let data_provider: Arc<dyn AsyncDataProvider> = Arc::new(BlockingDataProvider::new(
+            octree.data_provider(),
+            MAX_CONCURRENT_LOADS,
+        ));
         std::thread::spawn(move || {
-            // Loads the next node data in the receiver queue.
-            for node_id in node_id_receiver {
-                let node_data = octree.get_node_data(&node_id).unwrap();
-                // TODO(hrapp): reshuffle
-                node_data_sender.send((node_id, node_data)).unwrap();
-            }
+            let octree = loader_octree;
+            // Colorless point clouds are shaded by intensity. The range is the same for all nodes,
+            // so that neighboring nodes match.
+            let intensity_range = octree.attribute_range("intensity");
+            let mut pending = VecDeque::new();
+            let mut loads = FuturesUnordered::new();
+            // The loads that are running, with the generation they were last requested in.
+            let mut loading: FnvHashMap<(octree::NodeId, Load), (u64, AbortHandle)> =
+                FnvHashMap::default();
+            futures::executor::block_on(async {
+                'requests: loop {
+                    // The container sends the renewed requests before it publishes a new
+                    // generation. Taking all waiting requests first means that only the loads of
+                    // nodes that are no longer wanted are stale.
+                    let current = current_generation.load(Ordering::SeqCst);
+                    loop {
+                        match node_id_receiver.try_recv() {
+                            Ok(request) => add_request(request, &mut loading, &mut pending),
+                            Err(TryRecvError::Empty) => break,
+                            // The container was dropped.
+                            Err(TryRecvError::Closed) => break 'requests,
+                        }
+                    }
+                    loading.retain(|_, (generation, abort_handle)| {
+                        if *generation < current {
+                            abort_handle.abort();
+                        }
+                        *generation >= current
+                    });
+                    pending.retain(|(_, _, generation)| *generation >= current);
+                    while loads.len() < MAX_CONCURRENT_LOADS {
+                        let (node_id, load, generation) = match pending.pop_front() {
+                            Some(request) => request,
+                            None => break,
+                        };
+                        let future = load_node(
+                            Arc::clone(&octree),
+                            Arc::clone(&data_provider),
+                            node_id,
+                            load,
+                            intensity_range,
+                        );
+                        let (future, abort_handle) = abortable(future);
+                        loading.insert((node_id, load), (generation, abort_handle));
+                        loads.push(future.map(move |result| (node_id, load, result)));
+                    }
+                    select! {
+                        request = node_id_receiver.next() => match request {
+                            Some(request) => add_request(request, &mut loading, &mut pending),
+                            // The container was dropped.
+                            None => break,
+                        },
+                        (node_id, load, result) = loads.select_next_some() => {
+                            // Cancelled loads were removed from 'loading' already.
+                            if let Ok((loaded, load_stats)) = result {
+                                loading.remove(&(node_id, load));
+                                // TODO(hrapp): reshuffle
+                                if node_data_sender.send((node_id, loaded, load_stats)).is_err() {
+                                    break;
+                                }
+                            }
+                        },
+                    }
+                }
+            });
         });
         NodeViewContainer {
             node_views: LruCache::new(max_nodes_in_memory),
-            requested: FnvHashSet::default(),
+            color_source: ColorSource::Color,
+            requested: FnvHashMap::default(),
+            wanted: FnvHashSet::default(),
+            generation,
+            failed: FnvHashSet::default(),
+            new_failures: Vec::new(),
+            arrived: VecDeque::new(),
+            load_stats: FnvHashMap::default(),
+            upload_budget: UploadBudget::default(),
+            octree,
             node_id_sender,
             node_data_receiver,
         }
     }
 
+    /// Colors the points by 'color_source' from now on. The nodes that were uploaded already only
+    /// load the colors of the new source when they are drawn next, not their positions, and keep
+    /// their old colors meanwhile.
+    pub fn set_color_source(&mut self, color_source: ColorSource) {
+        self.color_source = color_source;
+    }
+
+    /// Replaces the nodes that should be shown, e.g. after the camera moved. Pending requests for
+    /// other nodes are dropped, so that the I/O thread does not spend time on them. Remote data
+    /// providers are hinted at the nodes that are not loaded yet, since they are requested soon.
+    pub fn set_wanted<'a>(&mut self, node_ids: impl IntoIterator<Item = &'a octree::NodeId>) {
+        self.wanted.clear();
+        self.wanted.extend(node_ids);
+        let upcoming: Vec<octree::NodeId> = self
+            .wanted
+            .iter()
+            .filter(|&node_id| !self.node_views.contains(node_id) && !self.failed.contains(node_id))
+            .copied()
+            .collect();
+        self.octree.prefetch_node_data(&upcoming, self.color_source);
+        // The requests that stay wanted are renewed before the new generation is published, so
+        // that the I/O thread never sees the new generation without them and cancels their loads.
+        let generation = self.generation.load(Ordering::SeqCst) + 1;
+        let wanted = &self.wanted;
+        self.requested.retain(|node_id, _| wanted.contains(node_id));
+        for (node_id, (load, node_generation)) in &mut self.requested {
+            *node_generation = generation;
+            self.node_id_sender
+                .unbounded_send((*node_id, *load, generation))
+                .unwrap();
+        }
+        self.generation.store(generation, Ordering::SeqCst);
+    }
+
+    fn request(&mut self, node_id: octree::NodeId, load: Load) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        self.wanted.insert(node_id);
+        self.requested.insert(node_id, (load, generation));
+        self.node_id_sender
+            .unbounded_send((node_id, load, generation))
+            .unwrap();
+    }
+
+    /// What has to be loaded to draw the node with the current colors, None if nothing or if it
+    /// is being loaded already.
+    fn needed_load(&self, node_id: &octree::NodeId) -> Option<Load> {
+        if self.requested.contains_key(node_id)
+            || self.failed.contains(node_id)
+            || self.arrived.iter().any(|(id, _)| id == node_id)
+        {
+            return None;
+        }
+        match self.node_views.peek(node_id) {
+            None => Some(Load::Node(self.color_source)),
+            Some(node_view) if node_view.color_source != self.color_source => {
+                Some(Load::Colors(self.color_source))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Uploads nodes that arrived from the I/O thread to the GPU, as many as the upload budget
+    /// allows. Returns whether any were uploaded.
     pub fn consume_arrived_nodes(&mut self, node_drawer: &NodeDrawer) -> bool {
-        let mut consumed_any = false;
-        while let Ok((node_id, node_data)) = self.node_data_receiver.try_recv() {
-            // Put loaded node into hash map.
+        let mut received_any = false;
+        while let Ok((node_id, loaded, load_stats)) = self.node_data_receiver.try_recv() {
+            received_any = true;
             self.requested.remove(&node_id);
-            self.node_views
-                .put(node_id, NodeView::new(node_drawer, node_data));
+            self.load_stats.insert(node_id, load_stats);
+            match loaded {
+                Ok(loaded) => self.arrived.push_back((node_id, loaded)),
+                Err(err) => {
+                    self.failed.insert(node_id);
+                    self.new_failures.push((node_id, err));
+                }
+            }
+        }
+
+        let start = time::Instant::now();
+        let mut uploaded_bytes = 0;
+        let mut consumed_any = false;
+        while let Some((node_id, loaded)) = self.arrived.pop_front() {
+            match loaded {
+                Loaded::Node(node_data, color_source) => {
+                    // A node can arrive twice if it was loading while its request was renewed.
+                    // Nodes that are no longer wanted, possibly since they arrived, would only be
+                    // evicted again soon.
+                    if self.node_views.contains(&node_id) || !self.wanted.contains(&node_id) {
+                        continue;
+                    }
+                    // Put loaded node into hash map.
+                    let node_view = NodeView::new(node_drawer, node_data, color_source);
+                    uploaded_bytes += node_view.used_memory_bytes;
+                    self.node_views.put(node_id, node_view);
+                }
+                Loaded::Colors(color, color_source) => {
+                    // Colors of a source that was switched away from meanwhile are stale.
+                    match self.node_views.get_mut(&node_id) {
+                        Some(node_view) if color_source == self.color_source => {
+                            node_view.set_colors(node_drawer, &color, color_source);
+                            uploaded_bytes += color.len();
+                        }
+                        _ => continue,
+                    }
+                }
+            }
             consumed_any = true;
+            if uploaded_bytes >= self.upload_budget.bytes
+                || time::Instant::now() - start >= self.upload_budget.duration
+            {
+                break;
+            }
+        }
+        if received_any || consumed_any {
+            self.forget_evicted_load_stats();
         }
         consumed_any
     }
 
+    /// Drops the load stats of nodes that were evicted, or that arrived when they were no longer
+    /// wanted, so they do not pile up while the camera moves through a large point cloud.
+    fn forget_evicted_load_stats(&mut self) {
+        let node_views = &self.node_views;
+        let failed = &self.failed;
+        let arrived: FnvHashSet<octree::NodeId> = self.arrived.iter().map(|(id, _)| *id).collect();
+        self.load_stats.retain(|node_id, _| {
+            node_views.contains(node_id) || failed.contains(node_id) || arrived.contains(node_id)
+        });
+    }
+
+    /// How the last load of the node went, None if it was never loaded.
+    pub fn load_stats(&self, node_id: &octree::NodeId) -> Option<&LoadStats> {
+        self.load_stats.get(node_id)
+    }
+
+    /// Returns the nodes that failed to load since the last call, with the reason.
+    pub fn take_failures(&mut self) -> Vec<(octree::NodeId, String)> {
+        std::mem::take(&mut self.new_failures)
+    }
+
     // Returns the 'NodeView' for 'node_id' if it is already loaded, otherwise returns None, but
-    // requested the node for loading in the I/O thread
+    // requested the node for loading in the I/O thread. A 'NodeView' with the colors of another
+    // source is returned as well, but its new colors are requested.
     pub fn get_or_request(&mut self, node_id: &octree::NodeId) -> Option<&NodeView> {
-        if self.node_views.contains(node_id) {
-            return self.node_views.get_mut(node_id).map(|f| f as &NodeView);
-        }
-
         // Limit the number of requested nodes because after a camera move
         // requested nodes might not be in the frustum anymore.
-        if !self.requested.contains(node_id) && self.requested.len() < 10 {
-            self.requested.insert(*node_id);
-            self.node_id_sender.send(*node_id).unwrap();
+        if self.requested.len() < 10 {
+            if let Some(load) = self.needed_load(node_id) {
+                self.request(*node_id, load);
+            }
         }
-        None
+        self.node_views.get_mut(node_id).map(|f| f as &NodeView)
     }
 
     pub fn request_all(&mut self, node_ids: &[octree::NodeId]) {
-        for &node_id in node_ids {
-            if !self.node_views.contains(&node_id) && !self.requested.contains(&node_id) {
-                self.requested.insert(node_id);
-                self.node_id_sender.send(node_id).unwrap();
+        for node_id in node_ids {
+            if let Some(load) = self.needed_load(node_id) {
+                self.request(*node_id, load);
             }
         }
     }
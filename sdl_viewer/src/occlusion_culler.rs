@@ -0,0 +1,248 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Estimates which part of each node is visible by drawing its bounding box against the depth
+//! buffer of the points drawn so far, using occlusion queries. Frustum culling alone counts nodes
+//! that are hidden behind other points, so they use up the node budget for nothing.
+
+use crate::graphic::{GlBuffer, GlProgram, GlProgramBuilder, GlVertexArray};
+use crate::opengl;
+use crate::opengl::types::{GLboolean, GLint, GLsizeiptr, GLuint};
+use fnv::{FnvHashMap, FnvHashSet};
+use nalgebra::Matrix4;
+use point_viewer::geometry::Aabb;
+use point_viewer::octree::NodeId;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+
+// The proxy boxes are never visible, so we reuse the box drawer shaders.
+const FRAGMENT_SHADER: &str = include_str!("../shaders/box_drawer_outline.fs");
+const VERTEX_SHADER: &str = include_str!("../shaders/box_drawer_outline.vs");
+
+/// The queries of one node: samples of the box front faces that pass the depth test and all
+/// samples of the box front faces.
+struct NodeQueries {
+    gl: Rc<opengl::Gl>,
+    ids: [GLuint; 2],
+    pending: bool,
+    visible_fraction: Option<f32>,
+}
+
+impl NodeQueries {
+    fn new(gl: Rc<opengl::Gl>) -> Self {
+        let mut ids = [0; 2];
+        unsafe {
+            gl.GenQueries(2, ids.as_mut_ptr());
+        }
+        NodeQueries {
+            gl,
+            ids,
+            pending: false,
+            visible_fraction: None,
+        }
+    }
+
+    /// Collects the result of the pending queries without waiting for the GPU.
+    fn poll(&mut self) {
+        if !self.pending {
+            return;
+        }
+        let mut available = 0;
+        let mut samples = [0; 2];
+        unsafe {
+            // Results become available in order, so the second query finishes last.
+            self.gl
+                .GetQueryObjectuiv(self.ids[1], opengl::QUERY_RESULT_AVAILABLE, &mut available);
+            if available == 0 {
+                return;
+            }
+            for (id, samples) in self.ids.iter().zip(samples.iter_mut()) {
+                self.gl
+                    .GetQueryObjectuiv(*id, opengl::QUERY_RESULT, samples);
+            }
+        }
+        self.pending = false;
+        // No samples at all means that the camera is inside the box, so the node is visible.
+        self.visible_fraction = Some(if samples[1] == 0 {
+            1.
+        } else {
+            (samples[0] as f32 / samples[1] as f32).min(1.)
+        });
+    }
+}
+
+impl Drop for NodeQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteQueries(2, self.ids.as_ptr());
+        }
+    }
+}
+
+pub struct OcclusionCuller {
+    program: GlProgram,
+    u_transform: GLint,
+    vertex_array: GlVertexArray,
+    _buffer_position: GlBuffer,
+    _buffer_indices: GlBuffer,
+    queries: FnvHashMap<NodeId, NodeQueries>,
+}
+
+impl OcclusionCuller {
+    pub fn new(gl: &Rc<opengl::Gl>) -> Self {
+        let program = GlProgramBuilder::new_with_vertex_shader(Rc::clone(gl), VERTEX_SHADER)
+            .fragment_shader(FRAGMENT_SHADER)
+            .build();
+        let u_transform;
+        unsafe {
+            gl.UseProgram(program.id);
+            u_transform = gl.GetUniformLocation(program.id, c_str!("transform"));
+        }
+
+        let vertex_array = GlVertexArray::new(Rc::clone(gl));
+        vertex_array.bind();
+
+        let _buffer_position = GlBuffer::new_array_buffer(Rc::clone(gl));
+        _buffer_position.bind();
+        let vertices: [[f64; 3]; 8] = [
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [-1.0, 1.0, 1.0],
+            [-1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, 1.0, -1.0],
+            [-1.0, 1.0, -1.0],
+        ];
+        unsafe {
+            gl.BufferData(
+                opengl::ARRAY_BUFFER,
+                (vertices.len() * 3 * mem::size_of::<f64>()) as GLsizeiptr,
+                &vertices[0] as *const [f64; 3] as *const c_void,
+                opengl::STATIC_DRAW,
+            );
+        }
+
+        // Two counter-clockwise triangles per face, seen from outside the box.
+        let _buffer_indices = GlBuffer::new_element_array_buffer(Rc::clone(gl));
+        _buffer_indices.bind();
+        let triangle_indices: [[i32; 3]; 12] = [
+            [0, 1, 2],
+            [0, 2, 3], // front
+            [4, 7, 6],
+            [4, 6, 5], // back
+            [1, 5, 6],
+            [1, 6, 2], // right
+            [4, 0, 3],
+            [4, 3, 7], // left
+            [3, 2, 6],
+            [3, 6, 7], // top
+            [4, 5, 1],
+            [4, 1, 0], // bottom
+        ];
+        unsafe {
+            gl.BufferData(
+                opengl::ELEMENT_ARRAY_BUFFER,
+                (triangle_indices.len() * 3 * mem::size_of::<i32>()) as GLsizeiptr,
+                &triangle_indices[0] as *const [i32; 3] as *const c_void,
+                opengl::STATIC_DRAW,
+            );
+        }
+
+        unsafe {
+            let pos_attr = gl.GetAttribLocation(program.id, c_str!("position"));
+            gl.EnableVertexAttribArray(pos_attr as GLuint);
+            gl.VertexAttribLPointer(
+                pos_attr as GLuint,
+                3,
+                opengl::DOUBLE,
+                3 * mem::size_of::<f64>() as i32,
+                ptr::null(),
+            );
+        }
+        OcclusionCuller {
+            program,
+            u_transform,
+            vertex_array,
+            _buffer_position,
+            _buffer_indices,
+            queries: FnvHashMap::default(),
+        }
+    }
+
+    /// The fraction of the node's bounding box that was visible when it was last queried, None
+    /// if no result is known yet.
+    pub fn visible_fraction(&self, node_id: &NodeId) -> Option<f32> {
+        self.queries
+            .get(node_id)
+            .and_then(|queries| queries.visible_fraction)
+    }
+
+    /// Collects finished queries and issues new ones for 'nodes' against the current depth
+    /// buffer, so it must be called after the points have been drawn. Results arrive a few
+    /// frames later. Queries of nodes that are not in 'nodes' are dropped.
+    pub fn update(
+        &mut self,
+        nodes: impl Iterator<Item = (NodeId, Aabb)>,
+        world_to_gl: &Matrix4<f64>,
+    ) {
+        let gl = Rc::clone(&self.program.gl);
+        let mut current = FnvHashSet::default();
+        self.vertex_array.bind();
+        unsafe {
+            gl.UseProgram(self.program.id);
+            gl.ColorMask(opengl::FALSE, opengl::FALSE, opengl::FALSE, opengl::FALSE);
+            gl.DepthMask(opengl::FALSE);
+            gl.Enable(opengl::CULL_FACE);
+            gl.CullFace(opengl::BACK);
+        }
+        for (node_id, aabb) in nodes {
+            current.insert(node_id);
+            let queries = self
+                .queries
+                .entry(node_id)
+                .or_insert_with(|| NodeQueries::new(Rc::clone(&gl)));
+            queries.poll();
+            if queries.pending {
+                continue;
+            }
+            let transform = world_to_gl
+                * Matrix4::new_translation(&aabb.center().coords)
+                * Matrix4::new_nonuniform_scaling(&(aabb.diag() / 2.0));
+            unsafe {
+                gl.UniformMatrix4dv(self.u_transform, 1, false as GLboolean, transform.as_ptr());
+                for (i, id) in queries.ids.iter().enumerate() {
+                    if i == 0 {
+                        gl.Enable(opengl::DEPTH_TEST);
+                    } else {
+                        gl.Disable(opengl::DEPTH_TEST);
+                    }
+                    gl.BeginQuery(opengl::SAMPLES_PASSED, *id);
+                    gl.DrawElements(opengl::TRIANGLES, 36, opengl::UNSIGNED_INT, ptr::null());
+                    gl.EndQuery(opengl::SAMPLES_PASSED);
+                }
+            }
+            queries.pending = true;
+        }
+        unsafe {
+            gl.Disable(opengl::CULL_FACE);
+            gl.Enable(opengl::DEPTH_TEST);
+            gl.DepthMask(opengl::TRUE);
+            gl.ColorMask(opengl::TRUE, opengl::TRUE, opengl::TRUE, opengl::TRUE);
+        }
+        self.queries.retain(|node_id, _| current.contains(node_id));
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Picking single points with the mouse. The depth buffer tells where the clicked pixel is in the
+//! world, and the octree is queried around there for the nearest point and its attributes.
+
+use nalgebra::{Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{ParallelIterator, PointCloud, PointLocation, PointQuery};
+use point_viewer::octree::Octree;
+use point_viewer::{match_attr_data, NUM_POINTS_PER_BATCH};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How far from the clicked position a point may be to be picked. The depth buffer is only
+/// accurate to a fraction of the point size, so this should be about the size of a point.
+pub const PICK_RADIUS_M: f64 = 0.25;
+
+/// A point of the octree with all attributes that it stores.
+#[derive(Debug, Clone)]
+pub struct PickedPoint {
+    pub position: Point3<f64>,
+    /// Each attribute holds the value of this point only.
+    pub attributes: BTreeMap<String, AttributeData>,
+}
+
+impl fmt::Display for PickedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:.3}, {:.3}, {:.3})",
+            self.position.x, self.position.y, self.position.z
+        )?;
+        for (name, data) in &self.attributes {
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident, $f:ident) => {
+                    write!($f, ", {}: {:?}", name, $data[0])?
+                };
+            }
+            match_attr_data!(data, rhs, f)
+        }
+        Ok(())
+    }
+}
+
+/// Returns the point of 'octree' nearest to 'around', if there is one within 'radius' meters.
+pub fn pick(octree: &Octree, around: &Point3<f64>, radius: f64) -> Result<Option<PickedPoint>> {
+    let mut attributes = Vec::new();
+    for name in octree.attribute_names() {
+        if octree.stored_attribute_type(name)?.is_some() {
+            attributes.push(name);
+        }
+    }
+    let half_size = Vector3::repeat(radius);
+    let query = PointQuery {
+        attributes,
+        location: PointLocation::Aabb(Aabb::new(around - half_size, around + half_size)),
+        ..Default::default()
+    };
+    let mut nearest: Option<(f64, PickedPoint)> = None;
+    let mut parallel_iterator = ParallelIterator::new(
+        std::slice::from_ref(octree),
+        &query,
+        NUM_POINTS_PER_BATCH,
+        2, /* num_threads */
+        4, /* buffer_size */
+    );
+    parallel_iterator.try_for_each_batch(|batch| {
+        for (i, position) in batch.position.iter().enumerate() {
+            let distance = (position - around).norm();
+            if distance > radius || nearest.as_ref().map_or(false, |(d, _)| *d <= distance) {
+                continue;
+            }
+            let attributes = batch
+                .attributes
+                .iter()
+                .map(|(name, data)| (name.clone(), data.get(i)))
+                .collect();
+            nearest = Some((
+                distance,
+                PickedPoint {
+                    position: *position,
+                    attributes,
+                },
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(nearest.map(|(_, point)| point))
+}
@@ -0,0 +1,114 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replaying point clouds with timestamps, e.g. mobile mapping runs, by only showing the points
+//! within a time window that slides over the recording.
+
+use point_viewer::math::ClosedInterval;
+
+/// The window that is shown first, relative to the length of the recording.
+const INITIAL_WINDOW_FRACTION: f64 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct Playback {
+    /// The timestamps of all points lie within this range.
+    range: ClosedInterval<f64>,
+    /// Whether the window is shown at all. If not, all points are shown.
+    pub enabled: bool,
+    playing: bool,
+    /// Recorded seconds per second.
+    speed: f64,
+    /// Length of the window in recorded seconds.
+    window_length: f64,
+    /// Timestamp of the end of the window.
+    end: f64,
+}
+
+impl Playback {
+    pub fn new(range: ClosedInterval<f64>) -> Self {
+        let duration = range.upper_bound() - range.lower_bound();
+        let window_length = duration * INITIAL_WINDOW_FRACTION;
+        Playback {
+            range,
+            enabled: false,
+            playing: false,
+            speed: 1.,
+            window_length,
+            end: range.lower_bound() + window_length,
+        }
+    }
+
+    /// The timestamps that are shown, None if all are.
+    pub fn window(&self) -> Option<ClosedInterval<f64>> {
+        if !self.enabled {
+            return None;
+        }
+        Some(ClosedInterval::new(self.end - self.window_length, self.end))
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.enabled = true;
+        self.playing = !self.playing;
+        self.log();
+    }
+
+    /// Multiplies the playback speed by 'factor'.
+    pub fn scale_speed(&mut self, factor: f64) {
+        self.speed *= factor;
+        self.log();
+    }
+
+    /// Multiplies the length of the window by 'factor'. It stays at least a millisecond long.
+    pub fn scale_window(&mut self, factor: f64) {
+        let duration = self.range.upper_bound() - self.range.lower_bound();
+        self.window_length = (self.window_length * factor)
+            .max(1e-3)
+            .min(duration.max(1e-3));
+        self.enabled = true;
+        self.log();
+    }
+
+    /// Moves the window by its own length, backwards for a negative 'direction', e.g. to step
+    /// through the recording while paused.
+    pub fn step(&mut self, direction: f64) {
+        self.enabled = true;
+        self.end = (self.end + direction * self.window_length)
+            .max(self.range.lower_bound())
+            .min(self.range.upper_bound() + self.window_length);
+        self.log();
+    }
+
+    /// Advances the window while playing. At the end of the recording, it starts over. Returns
+    /// whether the window moved.
+    pub fn advance(&mut self, elapsed: time::Duration) -> bool {
+        if !self.enabled || !self.playing {
+            return false;
+        }
+        self.end += elapsed.as_seconds_f64() * self.speed;
+        if self.end - self.window_length > self.range.upper_bound() {
+            self.end = self.range.lower_bound();
+        }
+        true
+    }
+
+    fn log(&self) {
+        eprintln!(
+            "Playback {} at {:.1}x, window of {:.2} s ending {:.2} s into the recording.",
+            if self.playing { "playing" } else { "paused" },
+            self.speed,
+            self.window_length,
+            self.end - self.range.lower_bound(),
+        );
+    }
+}
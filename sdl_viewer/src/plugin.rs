@@ -0,0 +1,149 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Renderer and tool plugins that are loaded from dynamic libraries at runtime, so that they can
+//! be distributed without the viewer's sources. A plugin library exports a function named
+//! `point_viewer_plugin` that returns a pointer to a static `PluginVTable`. Only C types cross the
+//! library boundary, so plugins can be built with any compiler and in any language.
+//!
+//! The plugin draws with the OpenGL context of the viewer, which is current during all calls.
+//! It must restore any OpenGL state it changes.
+
+use nalgebra::Matrix4;
+use point_viewer::errors::*;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+
+/// Plugins that report another version are rejected. It is increased whenever 'PluginVTable'
+/// changes.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// The name of the function every plugin library exports, see 'PluginEntryPoint'.
+pub const PLUGIN_ENTRY_POINT: &[u8] = b"point_viewer_plugin\0";
+
+/// Looks up an OpenGL function by its NUL-terminated name.
+pub type GetProcAddress = unsafe extern "C" fn(name: *const c_char) -> *mut c_void;
+
+pub type PluginEntryPoint = unsafe extern "C" fn() -> *const PluginVTable;
+
+/// What a plugin implements. 'state' is the pointer that 'create' returned.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    /// Must be the first field and equal to PLUGIN_ABI_VERSION.
+    pub abi_version: u32,
+    /// A NUL-terminated name for messages.
+    pub name: *const c_char,
+    /// Called once after the OpenGL context was created. 'argument' is the NUL-terminated part
+    /// of the --plugin option after the '=', or empty. Returns null on failure.
+    pub create: unsafe extern "C" fn(
+        get_proc_address: GetProcAddress,
+        argument: *const c_char,
+    ) -> *mut c_void,
+    /// Called with the 16 values of the world to GL matrix in column-major order whenever the
+    /// camera moved.
+    pub camera_changed: unsafe extern "C" fn(state: *mut c_void, world_to_gl: *const f64),
+    /// Called every time the point cloud was drawn, after the terrain.
+    pub draw: unsafe extern "C" fn(state: *mut c_void),
+    /// Called once before the library is unloaded.
+    pub destroy: unsafe extern "C" fn(state: *mut c_void),
+}
+
+pub struct Plugin {
+    name: String,
+    vtable: PluginVTable,
+    state: *mut c_void,
+    // Unloaded after 'destroy' was called in 'drop'.
+    _library: libloading::Library,
+}
+
+impl Plugin {
+    /// Loads a plugin given as "<library path>" or "<library path>=<argument>" on the command
+    /// line.
+    pub fn from_spec(spec: &str, get_proc_address: GetProcAddress) -> Result<Self> {
+        let mut parts = spec.splitn(2, '=');
+        let path = parts.next().unwrap_or_default();
+        let argument = parts.next().unwrap_or_default();
+        Self::load(Path::new(path), argument, get_proc_address)
+    }
+
+    pub fn load(path: &Path, argument: &str, get_proc_address: GetProcAddress) -> Result<Self> {
+        let context = || format!("Could not load plugin {}.", path.display());
+        let library = unsafe { libloading::Library::new(path) }.chain_err(context)?;
+        let vtable = unsafe {
+            let entry_point = library
+                .get::<PluginEntryPoint>(PLUGIN_ENTRY_POINT)
+                .chain_err(context)?;
+            let vtable = entry_point();
+            if vtable.is_null() {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Plugin {} returned no functions.",
+                    path.display()
+                ))
+                .into());
+            }
+            // Only the version is read until it is known that the layout matches.
+            let abi_version = (*vtable).abi_version;
+            if abi_version != PLUGIN_ABI_VERSION {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Plugin {} was built for plugin ABI version {}, but the viewer has version {}.",
+                    path.display(),
+                    abi_version,
+                    PLUGIN_ABI_VERSION
+                ))
+                .into());
+            }
+            *vtable
+        };
+        let name = if vtable.name.is_null() {
+            path.display().to_string()
+        } else {
+            unsafe { CStr::from_ptr(vtable.name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        let argument = CString::new(argument).chain_err(context)?;
+        let state = unsafe { (vtable.create)(get_proc_address, argument.as_ptr()) };
+        if state.is_null() {
+            return Err(
+                ErrorKind::InvalidInput(format!("Plugin '{}' failed to start.", name)).into(),
+            );
+        }
+        Ok(Plugin {
+            name,
+            vtable,
+            state,
+            _library: library,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn camera_changed(&mut self, world_to_gl: &Matrix4<f64>) {
+        unsafe { (self.vtable.camera_changed)(self.state, world_to_gl.as_slice().as_ptr()) }
+    }
+
+    pub fn draw(&mut self) {
+        unsafe { (self.vtable.draw)(self.state) }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.destroy)(self.state) }
+    }
+}
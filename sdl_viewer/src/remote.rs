@@ -0,0 +1,208 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Remote control of the viewer, e.g. for test automation and demos. Clients connect via TCP and
+//! send one JSON command per line, like `{"command": "teleport", "x": 10, "y": 20}`. Every
+//! command is answered with one line of JSON: `{"ok": true}`, with a "result" for commands that
+//! return something, or `{"ok": false, "error": "..."}`.
+//!
+//! There is no authentication, so the server should only listen on localhost.
+
+use crate::camera;
+use crate::opengl;
+use image::RgbaImage;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::ffi::c_void;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    OctreeNodes,
+    OcclusionCulling,
+    Minimap,
+    DensityEqualization,
+    Gizmo,
+    Grid,
+    ColorByLabel,
+    ColorByIntensity,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Returns the camera and the shown layers, in the format of the saved sessions.
+    GetState,
+    /// Sets the camera to a state that "get_state" returned.
+    SetCamera {
+        camera: camera::State,
+    },
+    /// Moves the camera above the point (x, y), like clicking into the minimap.
+    Teleport {
+        x: f64,
+        y: f64,
+    },
+    SetLayer {
+        layer: Layer,
+        enabled: bool,
+    },
+    /// Adds a terrain layer from a directory or another data provider location.
+    LoadTerrain {
+        location: String,
+    },
+    /// Writes the next frame as a PNG file.
+    Screenshot {
+        path: PathBuf,
+    },
+}
+
+/// A command of a client, which waits for the reply.
+pub struct Request {
+    pub command: Command,
+    pub reply: Reply,
+}
+
+pub struct Reply {
+    sender: Sender<Result<Option<Value>, String>>,
+}
+
+impl Reply {
+    pub fn send(self, result: Result<Option<Value>, String>) {
+        // The client might be gone.
+        let _ = self.sender.send(result);
+    }
+}
+
+fn response(result: Result<Option<Value>, String>) -> Value {
+    match result {
+        Ok(None) => serde_json::json!({ "ok": true }),
+        Ok(Some(result)) => serde_json::json!({ "ok": true, "result": result }),
+        Err(error) => serde_json::json!({ "ok": false, "error": error }),
+    }
+}
+
+fn serve(stream: TcpStream, sender: &Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<Command>(&line) {
+            Ok(command) => {
+                let (reply_sender, reply_receiver) = mpsc::channel();
+                let request = Request {
+                    command,
+                    reply: Reply {
+                        sender: reply_sender,
+                    },
+                };
+                if sender.send(request).is_err() {
+                    // The viewer is gone.
+                    return Ok(());
+                }
+                reply_receiver
+                    .recv()
+                    .unwrap_or_else(|_| Err("The viewer exited.".to_string()))
+            }
+            Err(e) => Err(format!("Invalid command: {}", e)),
+        };
+        writeln!(writer, "{}", response(result))?;
+    }
+    Ok(())
+}
+
+/// Accepts clients on 'address' in the background. Their commands end up in the returned
+/// receiver and are executed by the viewer between frames.
+pub fn listen(address: impl ToSocketAddrs) -> io::Result<Receiver<Request>> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!(
+        "Listening for remote control on {}.",
+        listener.local_addr()?
+    );
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Could not accept remote control client: {}", e);
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream, &sender) {
+                    eprintln!("Remote control client failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(receiver)
+}
+
+/// Writes what was drawn into the current frame to 'path'. Must be called after the scene has
+/// been drawn and before the buffers are swapped.
+pub fn write_screenshot(
+    gl: &opengl::Gl,
+    width: i32,
+    height: i32,
+    path: &Path,
+) -> Result<(), String> {
+    let mut pixels = vec![0u8; 4 * width.max(0) as usize * height.max(0) as usize];
+    unsafe {
+        gl.ReadPixels(
+            0,
+            0,
+            width,
+            height,
+            opengl::RGBA,
+            opengl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut c_void,
+        );
+    }
+    let image = RgbaImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or_else(|| "The window has no pixels.".to_string())?;
+    // OpenGL rows start at the bottom.
+    image::imageops::flip_vertical(&image)
+        .save(path)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        match serde_json::from_str(r#"{"command": "set_layer", "layer": "grid", "enabled": true}"#)
+        {
+            Ok(Command::SetLayer {
+                layer: Layer::Grid,
+                enabled: true,
+            }) => (),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(serde_json::from_str::<Command>(r#"{"command": "fly_away"}"#).is_err());
+        assert_eq!(
+            response(Err("No.".to_string())).to_string(),
+            r#"{"error":"No.","ok":false}"#
+        );
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Selecting points by dragging a rectangle on the screen. The rectangle and the camera define a
+//! frustum, which is used both to highlight the selected points and to query them for export.
+
+use nalgebra::{Matrix4, Vector3};
+use point_viewer::errors::*;
+use point_viewer::geometry::Frustum;
+use point_viewer::iterator::{ParallelIterator, PointLocation, PointQuery};
+use point_viewer::octree::Octree;
+use point_viewer::read_write::{Encoding, NodeWriter, OpenMode, PlyNodeWriter};
+use point_viewer::NUM_POINTS_PER_BATCH;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Rectangles smaller than this many pixels in either direction are ignored, so that a click does
+/// not select a sliver of the cloud.
+const MIN_SIZE_PX: i32 = 3;
+
+#[derive(Default)]
+pub struct Selection {
+    /// If set, dragging with the left mouse button selects instead of rotating the camera.
+    pub enabled: bool,
+    /// The window pixels where the current drag started and where it is now, with the origin in
+    /// the upper left corner like SDL mouse events.
+    drag: Option<((i32, i32), (i32, i32))>,
+    /// Maps world coordinates into the unit cube if they are selected.
+    clip_from_world: Option<Matrix4<f64>>,
+}
+
+/// Returns the matrix that maps the rectangle between the window pixels 'a' and 'b' to the x and y
+/// range [-1, 1] of clip space, leaving z untouched.
+fn ndc_from_rectangle(a: (i32, i32), b: (i32, i32), width: i32, height: i32) -> Matrix4<f64> {
+    let to_ndc = |(x, y): (i32, i32)| {
+        (
+            2. * f64::from(x) / f64::from(width) - 1.,
+            1. - 2. * f64::from(y) / f64::from(height),
+        )
+    };
+    let (a, b) = (to_ndc(a), to_ndc(b));
+    let center = Vector3::new((a.0 + b.0) / 2., (a.1 + b.1) / 2., 0.);
+    let half_size = Vector3::new((a.0 - b.0).abs() / 2., (a.1 - b.1).abs() / 2., 1.);
+    Matrix4::new_nonuniform_scaling(&half_size.map(|v| 1. / v)) * Matrix4::new_translation(&-center)
+}
+
+impl Selection {
+    pub fn clip_from_world(&self) -> Option<&Matrix4<f64>> {
+        self.clip_from_world.as_ref()
+    }
+
+    /// Selects what 'clip_from_world' maps into the unit cube, e.g. a restored selection.
+    pub fn set_clip_from_world(&mut self, clip_from_world: Option<Matrix4<f64>>) {
+        self.drag = None;
+        self.clip_from_world = clip_from_world;
+    }
+
+    pub fn clear(&mut self) {
+        self.drag = None;
+        self.clip_from_world = None;
+    }
+
+    pub fn start_drag(&mut self, x: i32, y: i32) {
+        self.drag = Some(((x, y), (x, y)));
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn update_drag(&mut self, x: i32, y: i32) {
+        if let Some((_, end)) = &mut self.drag {
+            *end = (x, y);
+        }
+    }
+
+    /// Finishes the drag and selects everything inside the rectangle, as seen through
+    /// 'world_to_gl'. Returns false if the rectangle was too small to select anything.
+    pub fn finish_drag(&mut self, world_to_gl: &Matrix4<f64>, width: i32, height: i32) -> bool {
+        let (start, end) = match self.drag.take() {
+            Some(drag) => drag,
+            None => return false,
+        };
+        if (start.0 - end.0).abs() < MIN_SIZE_PX || (start.1 - end.1).abs() < MIN_SIZE_PX {
+            return false;
+        }
+        self.clip_from_world = Some(ndc_from_rectangle(start, end, width, height) * world_to_gl);
+        true
+    }
+
+    /// The transformation that maps the unit cube to the rectangle that is currently being
+    /// dragged, in clip space.
+    pub fn drag_outline(&self, width: i32, height: i32) -> Option<Matrix4<f64>> {
+        let (start, end) = self.drag?;
+        if start == end {
+            return None;
+        }
+        ndc_from_rectangle(start, end, width, height).try_inverse()
+    }
+}
+
+/// Writes all points of 'octree' that 'clip_from_world' maps into the unit cube to a PLY file.
+/// Returns the number of points written.
+pub fn export(octree: &Octree, clip_from_world: &Matrix4<f64>, path: &Path) -> Result<usize> {
+    let frustum = Frustum::from_matrix4(*clip_from_world)
+        .ok_or_else(|| ErrorKind::InvalidInput("Invalid selection.".to_string()))?;
+    let query = PointQuery {
+        attributes: vec!["color"],
+        location: PointLocation::Frustum(frustum),
+        filter_intervals: HashMap::new(),
+        max_error_m: None,
+    };
+    let mut writer = PlyNodeWriter::new(path, Encoding::Plain, OpenMode::Truncate);
+    let mut num_points = 0;
+    let mut parallel_iterator = ParallelIterator::new(
+        std::slice::from_ref(octree),
+        &query,
+        NUM_POINTS_PER_BATCH,
+        2, /* num_threads */
+        4, /* buffer_size */
+    );
+    parallel_iterator.try_for_each_batch(|batch| {
+        num_points += batch.position.len();
+        writer.write(&batch)?;
+        Ok(())
+    })?;
+    Ok(num_points)
+}
@@ -0,0 +1,129 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The state of the viewer that is kept across restarts: camera, shown layers, rendering
+//! settings and clipping volumes. It is stored per point cloud in the user's home directory,
+//! since the point cloud itself might not be writable or not even local.
+
+use crate::camera::{CtMode, State};
+use fnv::FnvHasher;
+use nalgebra::Matrix4;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory below the home directory that holds the sessions of all point clouds.
+const SESSIONS_DIRECTORY: &str = ".point_viewer/sessions";
+
+/// Session file names keep at most this many characters of the location, well below the limit
+/// of file systems on the length of file names.
+const MAX_READABLE_NAME_LEN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub camera: State,
+    pub ct_mode: CtMode,
+    pub show_octree_nodes: bool,
+    pub occlusion_culling: bool,
+    pub show_minimap: bool,
+    /// Missing in sessions saved before density equalization existed.
+    #[serde(default)]
+    pub equalize_density: bool,
+    pub show_gizmo: bool,
+    pub show_grid: bool,
+    pub color_by_label: bool,
+    /// Missing in sessions saved before points could be colored by intensity.
+    #[serde(default)]
+    pub color_by_intensity: bool,
+    pub hidden_labels: Vec<u16>,
+    pub point_size: f32,
+    /// The distance at which points have 'point_size' if their size is attenuated by distance.
+    /// Missing in sessions saved before point sizes could be attenuated.
+    #[serde(default)]
+    pub attenuation_distance: Option<f32>,
+    pub gamma: f32,
+    /// Maps world coordinates into the unit cube if they are selected.
+    pub selection: Option<Matrix4<f64>>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // Written to a temporary file first, so that a crash while saving does not lose the
+        // previous session.
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// The file that the session of the point cloud at 'location' is stored in. None if there is no
+/// home directory.
+pub fn session_path(location: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").filter(|home| !home.is_empty())?;
+    // Relative paths name the same point cloud independent of the working directory.
+    let location = fs::canonicalize(location)
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| location.to_string());
+    Some(
+        PathBuf::from(home)
+            .join(SESSIONS_DIRECTORY)
+            .join(session_file_name(&location)),
+    )
+}
+
+/// A file name that shows the end of 'location', which tells point clouds apart best, and ends
+/// in a hash of all of it, so that locations that only differ in characters that are replaced or
+/// cut off do not share a session.
+fn session_file_name(location: &str) -> String {
+    let readable: Vec<char> = location
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let start = readable.len().saturating_sub(MAX_READABLE_NAME_LEN);
+    let readable: String = readable[start..].iter().collect();
+    let mut hasher = FnvHasher::default();
+    hasher.write(location.as_bytes());
+    format!("{}_{:016x}.json", readable, hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_file_name() {
+        let name = session_file_name("/data/a-b");
+        assert!(name.starts_with("_data_a_b_"));
+        assert!(name.ends_with(".json"));
+        assert_ne!(name, session_file_name("/data/a_b"));
+        assert_ne!(name, session_file_name("/data/a.b"));
+        let long_location = format!("/data/{}", "a".repeat(1000));
+        assert!(session_file_name(&long_location).len() < 255);
+        assert_ne!(
+            session_file_name(&long_location),
+            session_file_name(&format!("/other{}", long_location))
+        );
+    }
+}
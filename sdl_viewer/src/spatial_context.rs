@@ -0,0 +1,196 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Orientation aids: an axis gizmo showing the camera's orientation relative to the local frame,
+//! a grid on the local frame's ground plane z = 0 and the coordinates under the mouse cursor.
+
+use crate::box_drawer::BoxDrawer;
+use crate::opengl;
+use nalgebra::{Isometry3, Matrix4, Point3, UnitQuaternion, Vector3};
+use nav_types::{ECEF, WGS84};
+use point_viewer::color::{Color, BLUE, GREEN, RED};
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+/// Distance between two grid lines in meters.
+const GRID_SPACING_M: f64 = 10.;
+/// Number of grid lines on each side of the camera.
+const GRID_HALF_LINES: i32 = 10;
+const GRID_COLOR: Color<f32> = Color {
+    red: 0.4,
+    green: 0.4,
+    blue: 0.4,
+    alpha: 1.,
+};
+/// Edge length of the square in the lower left corner of the window that shows the gizmo.
+const GIZMO_SIZE_PX: i32 = 80;
+
+/// The transformation of the box drawer's unit cube to the line segment from 'start' to 'end'.
+fn line_transform(start: &Point3<f64>, end: &Point3<f64>) -> Matrix4<f64> {
+    let center = nalgebra::center(start, end);
+    Matrix4::new_translation(&center.coords)
+        * Matrix4::new_nonuniform_scaling(&((end - start) / 2.))
+}
+
+pub struct SpatialContext {
+    gl: Rc<opengl::Gl>,
+    box_drawer: BoxDrawer,
+    /// Shows the axis gizmo and the coordinates under the cursor.
+    pub show_gizmo: bool,
+    pub show_grid: bool,
+    /// Whether the global frame is ECEF, so that coordinates can also be shown in WGS84.
+    georeferenced: bool,
+}
+
+impl SpatialContext {
+    pub fn new(gl: &Rc<opengl::Gl>, georeferenced: bool) -> Self {
+        SpatialContext {
+            gl: Rc::clone(gl),
+            box_drawer: BoxDrawer::new(gl),
+            show_gizmo: false,
+            show_grid: false,
+            georeferenced,
+        }
+    }
+
+    /// Draws the x (red), y (green) and z (blue) axes of the local frame as seen from the camera
+    /// into the lower left corner of the window.
+    pub fn draw_gizmo(&self, camera_from_local: &UnitQuaternion<f64>, width: i32, height: i32) {
+        if !self.show_gizmo {
+            return;
+        }
+        // Without a projection, the gizmo is seen along the camera's view direction -z.
+        let gl_from_local = Matrix4::new_scaling(0.8) * camera_from_local.to_homogeneous();
+        unsafe {
+            self.gl.Viewport(0, 0, GIZMO_SIZE_PX, GIZMO_SIZE_PX);
+            self.gl.Disable(opengl::DEPTH_TEST);
+        }
+        for (axis, color) in [
+            (Vector3::x(), &RED),
+            (Vector3::y(), &GREEN),
+            (Vector3::z(), &BLUE),
+        ]
+        .iter()
+        {
+            let transform = gl_from_local * line_transform(&Point3::origin(), &Point3::from(*axis));
+            self.box_drawer
+                .draw_outlines_from_transformation(&transform, color);
+        }
+        unsafe {
+            self.gl.Enable(opengl::DEPTH_TEST);
+            self.gl.Viewport(0, 0, width, height);
+        }
+    }
+
+    /// Draws grid lines on the ground plane of the local frame around the camera's position.
+    pub fn draw_grid(
+        &self,
+        world_to_gl: &Matrix4<f64>,
+        local_from_global: &Isometry3<f64>,
+        camera_position_local: &Point3<f64>,
+    ) {
+        if !self.show_grid {
+            return;
+        }
+        let snap = |v: f64| (v / GRID_SPACING_M).round() * GRID_SPACING_M;
+        let (center_x, center_y) = (snap(camera_position_local.x), snap(camera_position_local.y));
+        let half_extent = f64::from(GRID_HALF_LINES) * GRID_SPACING_M;
+        let gl_from_local = world_to_gl * local_from_global.inverse().to_homogeneous();
+        for i in -GRID_HALF_LINES..=GRID_HALF_LINES {
+            let offset = f64::from(i) * GRID_SPACING_M;
+            let lines = [
+                (
+                    Point3::new(center_x + offset, center_y - half_extent, 0.),
+                    Point3::new(center_x + offset, center_y + half_extent, 0.),
+                ),
+                (
+                    Point3::new(center_x - half_extent, center_y + offset, 0.),
+                    Point3::new(center_x + half_extent, center_y + offset, 0.),
+                ),
+            ];
+            for (start, end) in lines.iter() {
+                self.box_drawer.draw_outlines_from_transformation(
+                    &(gl_from_local * line_transform(start, end)),
+                    &GRID_COLOR,
+                );
+            }
+        }
+    }
+
+    /// Returns the global coordinates of what is drawn at the window pixel ('x', 'y'), with the
+    /// origin in the upper left corner like SDL mouse events. If nothing is drawn there, this is
+    /// where the ray through the pixel hits the ground plane of the local frame. Must be called
+    /// after the scene has been drawn and before the buffers are swapped.
+    pub fn point_under_cursor(
+        &self,
+        world_to_gl: &Matrix4<f64>,
+        local_from_global: &Isometry3<f64>,
+        (x, y): (i32, i32),
+        width: i32,
+        height: i32,
+    ) -> Option<Point3<f64>> {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None;
+        }
+        let gl_to_world = world_to_gl.try_inverse()?;
+        let mut depth: f32 = 1.;
+        unsafe {
+            self.gl.ReadPixels(
+                x,
+                height - 1 - y,
+                1,
+                1,
+                opengl::DEPTH_COMPONENT,
+                opengl::FLOAT,
+                &mut depth as *mut f32 as *mut c_void,
+            );
+        }
+        let ndc_x = 2. * (f64::from(x) + 0.5) / f64::from(width) - 1.;
+        let ndc_y = 1. - 2. * (f64::from(y) + 0.5) / f64::from(height);
+        if depth < 1. {
+            let ndc_z = 2. * f64::from(depth) - 1.;
+            return Some(gl_to_world.transform_point(&Point3::new(ndc_x, ndc_y, ndc_z)));
+        }
+
+        let near = local_from_global
+            .transform_point(&gl_to_world.transform_point(&Point3::new(ndc_x, ndc_y, -1.)));
+        let far = local_from_global
+            .transform_point(&gl_to_world.transform_point(&Point3::new(ndc_x, ndc_y, 1.)));
+        if (near.z - far.z).abs() < std::f64::EPSILON {
+            return None;
+        }
+        let t = near.z / (near.z - far.z);
+        if t < 0. {
+            return None;
+        }
+        let ground = near + (far - near) * t;
+        Some(local_from_global.inverse_transform_point(&ground))
+    }
+
+    /// Describes the global point in the local frame and, if georeferenced, in WGS84.
+    pub fn readout(&self, global: &Point3<f64>, local_from_global: &Isometry3<f64>) -> String {
+        let local = local_from_global.transform_point(global);
+        let mut readout = format!("x: {:.2}, y: {:.2}, z: {:.2}", local.x, local.y, local.z);
+        if self.georeferenced {
+            let wgs84 = WGS84::from(ECEF::new(global.x, global.y, global.z));
+            readout += &format!(
+                " | lat: {:.7}, lon: {:.7}, alt: {:.2} m",
+                wgs84.latitude_degrees(),
+                wgs84.longitude_degrees(),
+                wgs84.altitude()
+            );
+        }
+        readout
+    }
+}
@@ -1,11 +1,13 @@
-use crate::graphic::tiled_texture_loader::TiledTextureLoader;
 use crate::graphic::{GlMovingWindowTexture, GlProgram, GlUniform};
-use crate::terrain_drawer::read_write::Metadata;
+use crate::terrain_drawer::read_write::{Metadata, TextureLoaders};
 use image::{ImageBuffer, LumaA, Rgba};
 use nalgebra::{Isometry3, Matrix4, Point3, Vector2, Vector3};
+use num_integer::Integer;
+use point_viewer::data_provider::DataProvider;
 use std::convert::TryInto;
 use std::io;
 use std::rc::Rc;
+use std::sync::Arc;
 
 // The last integer that can be exactly represented in a f64
 const F64_MAX_SAFE_INT: i64 = 9_007_199_254_740_992;
@@ -16,47 +18,70 @@ struct HeightAndColor {
     color: ImageBuffer<Rgba<u8>, Vec<u8>>,
 }
 
-pub struct TerrainLayer {
-    grid_coordinates: GridCoordinateFrame,
-    // The terrain pos is the coordinate of the lower corner of the terrain.
+/// The loaders of the terrain's tiles at each of their resolution levels, shared by all clipmap
+/// levels.
+struct Tiles {
+    // The finest level comes first.
+    levels: Vec<TextureLoaders>,
+}
+
+impl Tiles {
+    /// Loads a region given in units of 'step' terrain pixels, which must be a power of two. It
+    /// is read from the coarsest level of tiles that has at least this resolution.
+    fn load(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        width: usize,
+        height: usize,
+        step: i64,
+    ) -> HeightAndColor {
+        let level = (step.trailing_zeros() as usize).min(self.levels.len() - 1);
+        let (height_tiles, color_tiles) = &self.levels[level];
+        let step = step >> level;
+        HeightAndColor {
+            height: height_tiles.load_subsampled(min_x, min_y, width, height, step),
+            color: color_tiles.load_subsampled(min_x, min_y, width, height, step),
+        }
+    }
+}
+
+/// One ring of the clipmap: a window into the terrain with 2^level times the terrain's
+/// resolution, centered on the camera. The part that is covered by the next finer level is not
+/// drawn.
+struct ClipmapLevel {
+    // The edge length of a grid cell of this level in terrain pixels.
+    step: i64,
+    // The terrain pos is the coordinate of the lower corner of the window, in units of 'step'.
     // It converted to f64 at the latest possible moment, so that all
     // calculations have 64-bit integer precision.
     terrain_pos: Vector2<i64>,
     u_terrain_pos: GlUniform<Vector2<f64>>,
-    height_tiles: TiledTextureLoader<LumaA<f32>>,
-    color_tiles: TiledTextureLoader<Rgba<u8>>,
+    u_level_scale: GlUniform<f64>,
+    // Vertices in this region, in the same units as 'terrain_pos', are covered by the finer level.
+    u_inner_min: GlUniform<Vector2<f64>>,
+    u_inner_max: GlUniform<Vector2<f64>>,
+    // Whether the quad adjacency of the vertices is exact, which is only true at full resolution.
+    u_exact_quads: GlUniform<i32>,
     heightmap: GlMovingWindowTexture<LumaA<f32>>,
     colormap: GlMovingWindowTexture<Rgba<u8>>,
-    // The texture size shouldn't be negative or larger than u32::MAX, but it's
-    // more convenient for our calculations to store it as an i64.
-    texture_size: i64,
 }
 
-#[allow(dead_code)]
-impl TerrainLayer {
-    pub fn new<P: AsRef<std::path::Path>>(
+impl ClipmapLevel {
+    fn new(
         program: &GlProgram,
-        path: P,
+        level: u32,
+        terrain_pos: Vector2<i64>,
+        tiles: &Tiles,
         texture_size: u32,
-    ) -> io::Result<Self> {
-        assert!(texture_size % 2 == 0 && texture_size > 0);
-        let metadata = Metadata::from_dir(&path)?;
-        let (height_tiles, color_tiles) = metadata.read_tiles(&path)?;
-
-        let grid_coordinates = GridCoordinateFrame::new(program, metadata, texture_size);
-
-        // Initial terrain pos
-        // TODO(nnmm): Do not hardcode 0, 0, 0
-        let terrain_pos: Vector2<i64> =
-            grid_coordinates.terrain_pos_for_camera_pos(&Point3::new(0.0, 0.0, 0.0));
-        let float_terrain_pos = Self::convert_terrain_pos_to_float(&terrain_pos);
-        let u_terrain_pos = GlUniform::new(&program, "terrain_pos", float_terrain_pos);
-
-        let height_initial = height_tiles.load(
+    ) -> Self {
+        let step = 1 << level;
+        let initial = tiles.load(
             terrain_pos.x,
             terrain_pos.y,
             texture_size.try_into().unwrap(),
             texture_size.try_into().unwrap(),
+            step,
         );
         let heightmap = GlMovingWindowTexture::new(
             &program,
@@ -64,14 +89,7 @@ impl TerrainLayer {
             "height",
             texture_size,
             0, // texture_unit
-            height_initial,
-        );
-
-        let color_initial = color_tiles.load(
-            terrain_pos.x,
-            terrain_pos.y,
-            texture_size.try_into().unwrap(),
-            texture_size.try_into().unwrap(),
+            initial.height,
         );
         let colormap = GlMovingWindowTexture::new(
             &program,
@@ -79,60 +97,73 @@ impl TerrainLayer {
             "color",
             texture_size,
             1, // texture_unit
-            color_initial,
+            initial.color,
         );
-        let texture_size = i64::from(texture_size);
-
-        Ok(TerrainLayer {
-            grid_coordinates,
+        // Until the finer level is known, nothing is covered.
+        let no_inner_region = (Vector2::new(1., 1.), Vector2::new(0., 0.));
+        ClipmapLevel {
+            step,
             terrain_pos,
-            u_terrain_pos,
-            height_tiles,
-            color_tiles,
+            u_terrain_pos: GlUniform::new(
+                &program,
+                "terrain_pos",
+                convert_terrain_pos_to_float(&terrain_pos),
+            ),
+            u_level_scale: GlUniform::new(&program, "level_scale", step as f64),
+            u_inner_min: GlUniform::new(&program, "inner_min", no_inner_region.0),
+            u_inner_max: GlUniform::new(&program, "inner_max", no_inner_region.1),
+            u_exact_quads: GlUniform::new(&program, "exact_quads", (level == 0) as i32),
             heightmap,
             colormap,
-            texture_size,
-        })
+        }
     }
 
     // We already have the data between self.terrain_pos and self.terrain_pos + texture_size
     // Only fetch the "L" shape that is needed, as separate horizontal and vertical strips.
     // Don't get confused, the horizontal strip is determined by the movement in y direction and
     // the vertical strip is determined by the movement in x direction.
-    pub fn update(&mut self, cur_world_pos: Point3<f64>) {
-        let cur_pos = self
-            .grid_coordinates
-            .terrain_pos_for_camera_pos(&cur_world_pos);
+    fn update(&mut self, cur_pos: Vector2<i64>, tiles: &Tiles, texture_size: i64) {
         let moved = cur_pos - self.terrain_pos;
+        if moved.x == 0 && moved.y == 0 {
+            return;
+        }
+        let full: usize = texture_size.try_into().unwrap();
+        // When moving by more than a full window, e.g. because the camera jumped, none of the old
+        // data can be reused. That is handled as a strip spanning the whole window.
+        let moved = moved.map(|v| v.max(-texture_size).min(texture_size));
 
         let hori_strip = if moved.y > 0 {
-            self.load(
+            tiles.load(
                 cur_pos.x,
-                self.terrain_pos.y + self.texture_size,
-                self.texture_size.try_into().unwrap(),
+                cur_pos.y + texture_size - moved.y,
+                full,
                 moved.y.try_into().unwrap(),
+                self.step,
             )
         } else {
-            self.load(
+            tiles.load(
                 cur_pos.x,
                 cur_pos.y,
-                self.texture_size.try_into().unwrap(),
+                full,
                 moved.y.abs().try_into().unwrap(),
+                self.step,
             )
         };
         let vert_strip = if moved.x > 0 {
-            self.load(
-                self.terrain_pos.x + self.texture_size,
+            tiles.load(
+                cur_pos.x + texture_size - moved.x,
                 cur_pos.y,
                 moved.x.try_into().unwrap(),
-                self.texture_size.try_into().unwrap(),
+                full,
+                self.step,
             )
         } else {
-            self.load(
+            tiles.load(
                 cur_pos.x,
                 cur_pos.y,
                 moved.x.abs().try_into().unwrap(),
-                self.texture_size.try_into().unwrap(),
+                full,
+                self.step,
             )
         };
 
@@ -150,39 +181,157 @@ impl TerrainLayer {
         );
 
         self.terrain_pos = cur_pos;
-        self.u_terrain_pos.value = Self::convert_terrain_pos_to_float(&cur_pos)
+        self.u_terrain_pos.value = convert_terrain_pos_to_float(&cur_pos);
     }
 
-    pub fn terrain_from_world(&self) -> &Isometry3<f64> {
-        &self.grid_coordinates.terrain_from_world
+    /// Hides the part of this level that the next finer level with the window at 'finer_pos'
+    /// covers. That level's grid has 'grid_size' cells and half the cell size.
+    fn set_finer_level(&mut self, finer_pos: &Vector2<i64>, grid_size: i64) {
+        let floor = |v: i64| Integer::div_floor(&v, &2) as f64;
+        let ceil = |v: i64| Integer::div_ceil(&v, &2) as f64;
+        self.u_inner_min.value = Vector2::new(ceil(finer_pos.x), ceil(finer_pos.y));
+        self.u_inner_max.value = Vector2::new(
+            floor(finer_pos.x + grid_size),
+            floor(finer_pos.y + grid_size),
+        );
     }
 
-    pub fn submit(&self) {
-        self.grid_coordinates.submit();
+    fn submit(&self) {
         self.u_terrain_pos.submit();
+        self.u_level_scale.submit();
+        self.u_inner_min.submit();
+        self.u_inner_max.submit();
+        self.u_exact_quads.submit();
         self.heightmap.submit();
         self.colormap.submit();
     }
+}
 
-    fn load(&self, min_x: i64, min_y: i64, width: usize, height: usize) -> HeightAndColor {
-        HeightAndColor {
-            height: self.height_tiles.load(min_x, min_y, width, height),
-            color: self.color_tiles.load(min_x, min_y, width, height),
+pub struct TerrainLayer {
+    grid_coordinates: GridCoordinateFrame,
+    tiles: Tiles,
+    // The finest level comes first.
+    levels: Vec<ClipmapLevel>,
+    // The texture size shouldn't be negative or larger than u32::MAX, but it's
+    // more convenient for our calculations to store it as an i64.
+    texture_size: i64,
+}
+
+impl TerrainLayer {
+    pub fn new(
+        program: &GlProgram,
+        data_provider: Box<dyn DataProvider>,
+        texture_size: u32,
+        num_levels: u32,
+    ) -> io::Result<Self> {
+        assert!(texture_size % 2 == 0 && texture_size > 0);
+        assert!(num_levels > 0);
+        let metadata = Metadata::from_data_provider(&*data_provider)?;
+        let data_provider: Arc<dyn DataProvider> = Arc::from(data_provider);
+        let num_tile_levels = metadata.num_levels.max(1).min(num_levels);
+        let levels = (0..num_tile_levels)
+            .map(|tile_level| {
+                // The coarsest level of tiles also serves all coarser clipmap levels.
+                let max_level = if tile_level + 1 == num_tile_levels {
+                    num_levels - 1
+                } else {
+                    tile_level
+                };
+                // Enough tiles for the coarsest clipmap level that reads them, with some slack
+                // for tiles that are only partially covered. Finer levels and the prefetched
+                // region lie within it.
+                let tiles_per_side =
+                    ((texture_size << (max_level - tile_level)) / metadata.tile_size + 2) as usize;
+                metadata.tile_loaders(
+                    tile_level,
+                    Arc::clone(&data_provider),
+                    2 * tiles_per_side * tiles_per_side,
+                )
+            })
+            .collect();
+        let tiles = Tiles { levels };
+
+        let grid_coordinates = GridCoordinateFrame::new(program, metadata, texture_size);
+
+        // Initial terrain pos
+        // TODO(nnmm): Do not hardcode 0, 0, 0
+        let camera_pos = Point3::new(0.0, 0.0, 0.0);
+        let levels = (0..num_levels)
+            .map(|level| {
+                let terrain_pos =
+                    grid_coordinates.terrain_pos_for_camera_pos(&camera_pos, 1 << level);
+                ClipmapLevel::new(program, level, terrain_pos, &tiles, texture_size)
+            })
+            .collect();
+
+        let mut layer = TerrainLayer {
+            grid_coordinates,
+            tiles,
+            levels,
+            texture_size: i64::from(texture_size),
+        };
+        layer.update_inner_regions();
+        layer.prefetch();
+        Ok(layer)
+    }
+
+    pub fn update(&mut self, cur_world_pos: Point3<f64>) {
+        for level in &mut self.levels {
+            let cur_pos = self
+                .grid_coordinates
+                .terrain_pos_for_camera_pos(&cur_world_pos, level.step);
+            level.update(cur_pos, &self.tiles, self.texture_size);
         }
+        self.update_inner_regions();
+        self.prefetch();
     }
 
-    // Helper function because OpenGL doesn't like i64
-    fn convert_terrain_pos_to_float(v: &Vector2<i64>) -> Vector2<f64> {
-        assert!(
-            v.x < F64_MAX_SAFE_INT && v.x > F64_MIN_SAFE_INT,
-            "Terrain location not representable."
-        );
-        assert!(
-            v.y < F64_MAX_SAFE_INT && v.y > F64_MIN_SAFE_INT,
-            "Terrain location not representable."
-        );
-        Vector2::new(v.x as f64, v.y as f64)
+    fn update_inner_regions(&mut self) {
+        let grid_size = self.texture_size - 1;
+        for i in 1..self.levels.len() {
+            let finer_pos = self.levels[i - 1].terrain_pos;
+            self.levels[i].set_finer_level(&finer_pos, grid_size);
+        }
+    }
+
+    /// Fetches the tiles around the finest level in the background, so that they are ready when
+    /// the camera moves there.
+    fn prefetch(&self) {
+        let margin = self.texture_size / 2;
+        let size = (self.texture_size + 2 * margin).try_into().unwrap();
+        let terrain_pos = self.levels[0].terrain_pos;
+        let (min_x, min_y) = (terrain_pos.x - margin, terrain_pos.y - margin);
+        let (height_tiles, color_tiles) = &self.tiles.levels[0];
+        height_tiles.prefetch(min_x, min_y, size, size);
+        color_tiles.prefetch(min_x, min_y, size, size);
+    }
+
+    pub fn terrain_from_world(&self) -> &Isometry3<f64> {
+        &self.grid_coordinates.terrain_from_world
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
     }
+
+    /// Sets up drawing the level with the given index, the finest level being 0.
+    pub fn submit(&self, level: usize) {
+        self.grid_coordinates.submit();
+        self.levels[level].submit();
+    }
+}
+
+// Helper function because OpenGL doesn't like i64
+fn convert_terrain_pos_to_float(v: &Vector2<i64>) -> Vector2<f64> {
+    assert!(
+        v.x < F64_MAX_SAFE_INT && v.x > F64_MIN_SAFE_INT,
+        "Terrain location not representable."
+    );
+    assert!(
+        v.y < F64_MAX_SAFE_INT && v.y > F64_MIN_SAFE_INT,
+        "Terrain location not representable."
+    );
+    Vector2::new(v.x as f64, v.y as f64)
 }
 
 /// This struct's job is to convert from continuous world positions to discrete
@@ -218,11 +367,12 @@ impl GridCoordinateFrame {
     }
 
     /// Returns the terrain pos (i.e. the coordinate of the lower corner of the terrain) for
-    /// a given camera position (in the world coordinate system).
-    fn terrain_pos_for_camera_pos(&self, world_pos: &Point3<f64>) -> Vector2<i64> {
+    /// a given camera position (in the world coordinate system), in units of 'step' pixels.
+    fn terrain_pos_for_camera_pos(&self, world_pos: &Point3<f64>, step: i64) -> Vector2<i64> {
         let local_pos = self.terrain_from_world * world_pos;
-        let x = ((local_pos.x - self.u_origin.value.x) / self.u_resolution_m.value).floor();
-        let y = ((local_pos.y - self.u_origin.value.y) / self.u_resolution_m.value).floor();
+        let cell_size_m = self.u_resolution_m.value * step as f64;
+        let x = ((local_pos.x - self.u_origin.value.x) / cell_size_m).floor();
+        let y = ((local_pos.y - self.u_origin.value.y) / cell_size_m).floor();
         assert!(
             x <= std::i64::MAX as f64 && x >= std::i64::MIN as f64,
             "Terrain location not representable."
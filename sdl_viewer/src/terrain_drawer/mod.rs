@@ -2,6 +2,8 @@ use crate::c_str;
 use crate::graphic::{GlBuffer, GlProgram, GlProgramBuilder, GlUniform, GlVertexArray};
 use crate::opengl;
 use nalgebra::{Isometry3, Matrix4, Point3};
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::{ErrorKind, Result};
 
 use opengl::types::{GLsizeiptr, GLuint};
 
@@ -11,15 +13,21 @@ use std::rc::Rc;
 
 mod layer;
 mod read_write;
+mod watch;
 
 pub use layer::TerrainLayer;
 pub use read_write::Metadata;
+pub use watch::watch;
 
 const TERRAIN_FRAGMENT_SHADER: &str = include_str!("../../shaders/terrain.fs");
 const TERRAIN_VERTEX_SHADER: &str = include_str!("../../shaders/terrain.vs");
 const TERRAIN_GEOMETRY_SHADER: &str = include_str!("../../shaders/terrain.gs");
 
-const GRID_SIZE: u32 = 1023;
+// The number of cells along each side of the grid of a clipmap level.
+const GRID_SIZE: u32 = 511;
+// Each clipmap level covers twice the area of the previous one at half the resolution. Since
+// the grid is the same for all levels, the rendering cost doesn't depend on the terrain's size.
+const NUM_LEVELS: u32 = 4;
 
 pub struct TerrainRenderer {
     program: GlProgram,
@@ -31,13 +39,22 @@ pub struct TerrainRenderer {
     buffer_indices: GlBuffer,
     num_indices: usize,
     terrain_layers: Vec<TerrainLayer>,
+    // The location of each layer, for reloading it.
+    terrain_locations: Vec<String>,
+    camera_pos: Point3<f64>,
 }
 
 impl TerrainRenderer {
-    pub fn new<I>(gl: Rc<opengl::Gl>, terrain_paths: I) -> Self
+    /// The terrain locations are directories or anything else the factory can create a data
+    /// provider for.
+    pub fn new<I>(
+        gl: Rc<opengl::Gl>,
+        terrain_locations: I,
+        data_provider_factory: &DataProviderFactory,
+    ) -> Self
     where
         I: Iterator,
-        I::Item: AsRef<std::path::Path>,
+        I::Item: AsRef<str>,
     {
         let program =
             GlProgramBuilder::new_with_vertex_shader(Rc::clone(&gl), TERRAIN_VERTEX_SHADER)
@@ -54,8 +71,17 @@ impl TerrainRenderer {
         let (buffer_position, buffer_indices, num_indices) =
             Self::create_mesh(&program, &vertex_array, Rc::clone(&gl));
 
-        let terrain_layers = terrain_paths
-            .map(|p| TerrainLayer::new(&program, p, GRID_SIZE + 1).unwrap())
+        let terrain_locations: Vec<String> = terrain_locations
+            .map(|location| location.as_ref().to_string())
+            .collect();
+        let terrain_layers = terrain_locations
+            .iter()
+            .map(|location| {
+                let data_provider = data_provider_factory
+                    .generate_data_provider(location)
+                    .unwrap_or_else(|_| panic!("Couldn't open terrain at '{}'.", location));
+                TerrainLayer::new(&program, data_provider, GRID_SIZE + 1, NUM_LEVELS).unwrap()
+            })
             .collect();
 
         Self {
@@ -66,6 +92,8 @@ impl TerrainRenderer {
             buffer_indices,
             num_indices,
             terrain_layers,
+            terrain_locations,
+            camera_pos: Point3::origin(),
         }
     }
 
@@ -141,7 +169,8 @@ impl TerrainRenderer {
     }
 
     pub fn camera_changed(&mut self, world_to_gl: &Matrix4<f64>, camera_to_world: &Isometry3<f64>) {
-        let camera_pos = Point3::from(camera_to_world.translation.vector);
+        self.camera_pos = Point3::from(camera_to_world.translation.vector);
+        let camera_pos = self.camera_pos;
         self.terrain_layers
             .iter_mut()
             .for_each(|layer| layer.update(camera_pos));
@@ -173,19 +202,56 @@ impl TerrainRenderer {
             // And after:
             // self.program.gl.Disable(opengl::BLEND);
             for layer in self.terrain_layers.iter() {
-                // Set the terrain to be used with the next draw call
-                layer.submit();
-                // Draw the mesh using the current terrain data
-                self.program.gl.DrawElements(
-                    opengl::TRIANGLES,
-                    self.num_indices as i32,
-                    opengl::UNSIGNED_INT,
-                    std::ptr::null(), // no offset
-                );
+                for level in 0..layer.num_levels() {
+                    // Set the terrain to be used with the next draw call
+                    layer.submit(level);
+                    // Draw the mesh using the current terrain data
+                    self.program.gl.DrawElements(
+                        opengl::TRIANGLES,
+                        self.num_indices as i32,
+                        opengl::UNSIGNED_INT,
+                        std::ptr::null(), // no offset
+                    );
+                }
             }
         }
     }
 
+    /// Adds a layer for the terrain at 'location' while the viewer is running.
+    pub fn add_layer(
+        &mut self,
+        location: &str,
+        data_provider_factory: &DataProviderFactory,
+    ) -> Result<()> {
+        let data_provider = data_provider_factory.generate_data_provider(location)?;
+        let layer = TerrainLayer::new(&self.program, data_provider, GRID_SIZE + 1, NUM_LEVELS)?;
+        self.terrain_layers.push(layer);
+        self.terrain_locations.push(location.to_string());
+        Ok(())
+    }
+
+    /// The locations of the layers, in the order in which they were added.
+    pub fn locations(&self) -> &[String] {
+        &self.terrain_locations
+    }
+
+    /// Reads the layer with the given index again from its location, e.g. after another process
+    /// updated its tiles. The old layer stays if the new one cannot be read.
+    pub fn reload_layer(
+        &mut self,
+        index: usize,
+        data_provider_factory: &DataProviderFactory,
+    ) -> Result<()> {
+        let location = self.terrain_locations.get(index).ok_or_else(|| {
+            ErrorKind::InvalidInput(format!("There is no terrain layer {}.", index))
+        })?;
+        let data_provider = data_provider_factory.generate_data_provider(location)?;
+        let mut layer = TerrainLayer::new(&self.program, data_provider, GRID_SIZE + 1, NUM_LEVELS)?;
+        layer.update(self.camera_pos);
+        self.terrain_layers[index] = layer;
+        Ok(())
+    }
+
     pub fn local_from_global(&self) -> Option<Isometry3<f64>> {
         self.terrain_layers
             .first()
@@ -1,9 +1,20 @@
-use crate::graphic::tiled_texture_loader::{TilePos, TiledTextureLoader};
-use image::{LumaA, Rgba};
+use crate::graphic::tiled_texture_loader::{tile_name, TilePos, TiledTextureLoader};
+use byteorder::{LittleEndian, WriteBytesExt};
+use image::{ImageBuffer, LumaA, Rgba};
 use nalgebra::{Isometry3, Vector3};
+use num_integer::Integer;
+use point_viewer::attribute_extension;
+use point_viewer::data_provider::{DataProvider, OnDiskDataProvider};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::io::{self, ErrorKind};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::sync::Arc;
+
+/// The data provider attributes of the tiles, which are stored in files like
+/// "x00000001_y00000002.height" and "x00000001_y00000002.color".
+pub const HEIGHT_ATTRIBUTE: &str = "height";
+pub const COLOR_ATTRIBUTE: &str = "terrain_color";
 
 #[derive(Serialize, Deserialize)]
 pub struct Metadata {
@@ -11,33 +22,79 @@ pub struct Metadata {
     pub world_from_terrain: Isometry3<f64>,
     pub origin: Vector3<f64>,
     pub resolution_m: f64,
+    // The positions of the tiles at level 0.
     pub tile_positions: Vec<TilePos>,
+    // The number of resolution levels of the tiles. Each level has half the resolution of the
+    // previous one, and level 0 has the full resolution.
+    #[serde(default = "default_num_levels")]
+    pub num_levels: u32,
+}
+
+fn default_num_levels() -> u32 {
+    1
 }
 
 pub type TextureLoaders = (TiledTextureLoader<LumaA<f32>>, TiledTextureLoader<Rgba<u8>>);
 
 impl Metadata {
     pub fn from_dir<P: AsRef<std::path::Path>>(dir: P) -> io::Result<Self> {
-        let meta_path = dir.as_ref().join("meta.json");
-        let reader = File::open(meta_path)?;
+        Self::from_data_provider(&OnDiskDataProvider {
+            directory: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Reads the metadata that the data provider serves as the "json" attribute of "meta", i.e.
+    /// "meta.json" in a local directory.
+    pub fn from_data_provider(data_provider: &dyn DataProvider) -> io::Result<Self> {
+        let reader = data_provider
+            .data("meta", &["json"])
+            .ok()
+            .and_then(|mut readers| readers.remove("json"))
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Could not read meta.json"))?;
         serde_json::from_reader(reader).map_err(|e| {
             let msg = format!("Could not parse meta.json: {}", e.to_string());
             io::Error::new(ErrorKind::InvalidData, msg)
         })
     }
 
-    pub fn read_tiles<P: AsRef<std::path::Path>>(&self, dir: P) -> io::Result<TextureLoaders> {
-        let height_tiles = self.tile_positions.iter().map(|&(x, y)| {
-            let height_file_path = dir.as_ref().join(format!("x{:08}_y{:08}.height", x, y));
-            ((x, y), height_file_path)
-        });
-        let color_tiles = self.tile_positions.iter().map(|&(x, y)| {
-            let color_file_path = dir.as_ref().join(format!("x{:08}_y{:08}.color", x, y));
-            ((x, y), color_file_path)
-        });
-        let height_tiles = TiledTextureLoader::new(self.tile_size, height_tiles)?;
-        let color_tiles = TiledTextureLoader::new(self.tile_size, color_tiles)?;
-        Ok((height_tiles, color_tiles))
+    /// The positions of the tiles at the given level, each of which covers 2^level times
+    /// 2^level tiles of level 0.
+    pub fn tile_positions_at(&self, level: u32) -> Vec<TilePos> {
+        let scale = 1 << level;
+        let positions: BTreeSet<TilePos> = self
+            .tile_positions
+            .iter()
+            .map(|(x, y)| (x.div_floor(&scale), y.div_floor(&scale)))
+            .collect();
+        positions.into_iter().collect()
+    }
+
+    /// Returns loaders for the height and color tiles of the given level, which are served by
+    /// the data provider like the files in a local directory, e.g. "x00000001_y00000002.height".
+    pub fn tile_loaders(
+        &self,
+        level: u32,
+        data_provider: Arc<dyn DataProvider>,
+        max_cached_tiles: usize,
+    ) -> TextureLoaders {
+        let tile_positions = self.tile_positions_at(level);
+        let height_tiles = TiledTextureLoader::new(
+            self.tile_size,
+            level,
+            tile_positions.iter().copied(),
+            Arc::clone(&data_provider),
+            HEIGHT_ATTRIBUTE,
+            max_cached_tiles,
+        );
+        let color_tiles = TiledTextureLoader::new(
+            self.tile_size,
+            level,
+            tile_positions.into_iter(),
+            data_provider,
+            COLOR_ATTRIBUTE,
+            max_cached_tiles,
+        );
+        (height_tiles, color_tiles)
     }
 
     pub fn write<P: AsRef<std::path::Path>>(&self, dir: P) -> io::Result<()> {
@@ -47,4 +104,146 @@ impl Metadata {
             io::Error::new(ErrorKind::InvalidData, msg)
         })
     }
+
+    /// Writes the height and color of the tile at 'pos' of the given level into 'dir', where
+    /// 'tile_loaders' finds them. Both must have the tile size of this terrain.
+    pub fn write_tile<P: AsRef<std::path::Path>>(
+        &self,
+        dir: P,
+        level: u32,
+        pos: TilePos,
+        height: &ImageBuffer<LumaA<f32>, Vec<f32>>,
+        color: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> io::Result<()> {
+        for (width, tile_height) in &[height.dimensions(), color.dimensions()] {
+            if *width != self.tile_size || *tile_height != self.tile_size {
+                let msg = format!("Tiles must have a size of {}.", self.tile_size);
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+        }
+        let stem = dir.as_ref().join(tile_name(level, pos));
+        let path = stem.with_extension(attribute_extension(HEIGHT_ATTRIBUTE));
+        let mut writer = BufWriter::new(File::create(path)?);
+        for value in height.iter() {
+            writer.write_f32::<LittleEndian>(*value)?;
+        }
+        writer.flush()?;
+        let path = stem.with_extension(attribute_extension(COLOR_ATTRIBUTE));
+        File::create(path)?.write_all(color)
+    }
+
+    /// Writes the levels 1 to 'num_levels' - 1 of the tiles in 'dir', which are subsampled from
+    /// level 0, and records them in "meta.json". The viewer reads coarse parts of the terrain
+    /// from them instead of from the full resolution tiles.
+    pub fn write_levels<P: AsRef<std::path::Path>>(
+        &mut self,
+        dir: P,
+        num_levels: u32,
+    ) -> io::Result<()> {
+        if num_levels == 0 {
+            let msg = "There must be at least one level.";
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+        let data_provider = Arc::new(OnDiskDataProvider {
+            directory: dir.as_ref().to_path_buf(),
+        });
+        // Level 0 is read tile by tile, so a small cache suffices.
+        let (height_tiles, color_tiles) = self.tile_loaders(0, data_provider, 4);
+        let tile_size = i64::from(self.tile_size);
+        for level in 1..num_levels {
+            let step = 1 << level;
+            for (x, y) in self.tile_positions_at(level) {
+                let (min_x, min_y) = (i64::from(x) * tile_size, i64::from(y) * tile_size);
+                let size = self.tile_size as usize;
+                let height = height_tiles.load_subsampled(min_x, min_y, size, size, step);
+                let color = color_tiles.load_subsampled(min_x, min_y, size, size, step);
+                self.write_tile(&dir, level, (x, y), &height, &color)?;
+            }
+        }
+        self.num_levels = num_levels;
+        self.write(dir)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// Writes a terrain of two tiles with the given red value into 'dir'.
+    pub fn write_terrain(dir: &std::path::Path, value: u8) -> Metadata {
+        let metadata = Metadata {
+            tile_size: 4,
+            world_from_terrain: Isometry3::identity(),
+            origin: Vector3::zeros(),
+            resolution_m: 1.,
+            tile_positions: vec![(0, 0), (1, 0)],
+            num_levels: 1,
+        };
+        metadata.write(dir).unwrap();
+        for &(x, y) in &metadata.tile_positions {
+            let height = ImageBuffer::from_fn(4, 4, |i, j| LumaA([(i + 4 * j) as f32, 1.]));
+            let color = ImageBuffer::from_pixel(4, 4, Rgba([value, x as u8, y as u8, 255]));
+            metadata
+                .write_tile(dir, 0, (x, y), &height, &color)
+                .unwrap();
+        }
+        metadata
+    }
+
+    #[test]
+    fn test_written_tiles_are_loaded() {
+        let tmp_dir = TempDir::new("terrain").unwrap();
+        write_terrain(tmp_dir.path(), 7);
+        let metadata = Metadata::from_dir(tmp_dir.path()).unwrap();
+        let data_provider = Arc::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        });
+        let (height, color) = metadata.tile_loaders(0, data_provider, 4);
+        let heights = height.load(2, 1, 4, 2);
+        assert_eq!(heights.get_pixel(0, 0), &LumaA([6., 1.]));
+        assert_eq!(heights.get_pixel(3, 1), &LumaA([9., 1.]));
+        let colors = color.load(2, 1, 4, 2);
+        assert_eq!(colors.get_pixel(0, 0), &Rgba([7, 0, 0, 255]));
+        assert_eq!(colors.get_pixel(3, 1), &Rgba([7, 1, 0, 255]));
+    }
+
+    #[test]
+    fn test_write_tile_checks_size() {
+        let tmp_dir = TempDir::new("terrain").unwrap();
+        let metadata = write_terrain(tmp_dir.path(), 0);
+        let height = ImageBuffer::new(2, 2);
+        let color = ImageBuffer::new(4, 4);
+        assert!(metadata
+            .write_tile(tmp_dir.path(), 0, (0, 0), &height, &color)
+            .is_err());
+    }
+
+    #[test]
+    fn test_coarse_levels_are_subsampled() {
+        let tmp_dir = TempDir::new("terrain").unwrap();
+        let mut metadata = write_terrain(tmp_dir.path(), 7);
+        metadata.write_levels(tmp_dir.path(), 2).unwrap();
+        let metadata = Metadata::from_dir(tmp_dir.path()).unwrap();
+        assert_eq!(metadata.num_levels, 2);
+        assert_eq!(metadata.tile_positions_at(1), vec![(0, 0)]);
+
+        let data_provider: Arc<dyn DataProvider> = Arc::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        });
+        let (height, color) = metadata.tile_loaders(0, Arc::clone(&data_provider), 4);
+        let (coarse_height, coarse_color) = metadata.tile_loaders(1, data_provider, 4);
+        assert_eq!(
+            coarse_height.load(-1, 0, 5, 3),
+            height.load_subsampled(-1, 0, 5, 3, 2)
+        );
+        assert_eq!(
+            coarse_color.load(-1, 0, 5, 3),
+            color.load_subsampled(-1, 0, 5, 3, 2)
+        );
+        assert_eq!(
+            coarse_color.load(3, 0, 1, 1).get_pixel(0, 0),
+            &Rgba([7, 1, 0, 255])
+        );
+    }
 }
@@ -0,0 +1,138 @@
+//! Notices when another process rewrites a terrain, so that the viewer can reload it.
+
+use point_viewer::data_provider::DataProviderFactory;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What is compared between two polls of a terrain location.
+#[derive(PartialEq)]
+struct Signature {
+    meta_hash: u64,
+    // For local directories, where tiles can change without the meta: a hash of the names, sizes
+    // and modification times of all files. Not only the newest time is compared, since copies
+    // can keep the time of the file they were copied from.
+    files_hash: Option<u64>,
+}
+
+fn files_hash(dir: &Path) -> Option<u64> {
+    let mut files = fs::read_dir(dir)
+        .ok()?
+        .map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            Some((entry.file_name(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect::<Option<Vec<(OsString, u64, SystemTime)>>>()?;
+    files.sort();
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn signature(location: &str, data_provider_factory: &DataProviderFactory) -> Option<Signature> {
+    let data_provider = data_provider_factory
+        .generate_data_provider(location)
+        .ok()?;
+    let mut meta = Vec::new();
+    data_provider
+        .data("meta", &["json"])
+        .ok()?
+        .remove("json")?
+        .read_to_end(&mut meta)
+        .ok()?;
+    let mut hasher = DefaultHasher::new();
+    meta.hash(&mut hasher);
+    let files_hash = if Path::new(location).is_dir() {
+        Some(files_hash(Path::new(location))?)
+    } else {
+        None
+    };
+    Some(Signature {
+        meta_hash: hasher.finish(),
+        files_hash,
+    })
+}
+
+/// Polls the terrain 'locations' every 'interval' in the background and sends the index of each
+/// one that changed since the last poll. Locations that cannot be read, e.g. because they are
+/// being written, are compared again in the next poll.
+pub fn watch(
+    locations: Vec<String>,
+    data_provider_factory: DataProviderFactory,
+    interval: Duration,
+) -> Receiver<usize> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut signatures: Vec<Option<Signature>> = locations
+            .iter()
+            .map(|location| signature(location, &data_provider_factory))
+            .collect();
+        loop {
+            thread::sleep(interval);
+            for (index, location) in locations.iter().enumerate() {
+                let current = match signature(location, &data_provider_factory) {
+                    Some(current) => current,
+                    None => continue,
+                };
+                if signatures[index].as_ref() == Some(&current) {
+                    continue;
+                }
+                let changed = signatures[index].is_some();
+                signatures[index] = Some(current);
+                if changed && sender.send(index).is_err() {
+                    // The viewer is gone.
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_drawer::read_write::tests::write_terrain;
+    use crate::terrain_drawer::Metadata;
+    use image::Rgba;
+    use point_viewer::data_provider::DataProvider;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_reload_picks_up_rewritten_tile() {
+        let tmp_dir = TempDir::new("terrain").unwrap();
+        write_terrain(tmp_dir.path(), 1);
+        let location = tmp_dir.path().to_str().unwrap().to_string();
+        let data_provider_factory = DataProviderFactory::new();
+        let changes = watch(
+            vec![location.clone()],
+            data_provider_factory.clone(),
+            Duration::from_millis(10),
+        );
+        // Let the watcher record the terrain before it is rewritten.
+        thread::sleep(Duration::from_millis(200));
+        write_terrain(tmp_dir.path(), 2);
+        assert_eq!(changes.recv_timeout(Duration::from_secs(10)), Ok(0));
+
+        // A reload reads the terrain from its location again, like this.
+        let data_provider: Arc<dyn DataProvider> = Arc::from(
+            data_provider_factory
+                .generate_data_provider(&location)
+                .unwrap(),
+        );
+        let metadata = Metadata::from_data_provider(&*data_provider).unwrap();
+        let (_, color) = metadata.tile_loaders(0, data_provider, 4);
+        assert_eq!(
+            color.load(0, 0, 1, 1).get_pixel(0, 0),
+            &Rgba([2, 0, 0, 255])
+        );
+    }
+}
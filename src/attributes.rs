@@ -1,6 +1,10 @@
+use crate::data_provider::OnDiskDataProvider;
 use crate::errors::{ErrorKind, Result};
+use crate::PointsBatch;
 use nalgebra::Vector3;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::path::Path;
 
 pub use point_viewer_proto_rust::proto;
 
@@ -17,6 +21,9 @@ pub enum AttributeDataType {
     F32,
     F64,
     U8Vec3,
+    U32Vec3,
+    U64Vec3,
+    I32Vec3,
     F64Vec3,
 }
 
@@ -34,6 +41,9 @@ impl AttributeDataType {
             AttributeDataType::F32 => proto::AttributeDataType::F32,
             AttributeDataType::F64 => proto::AttributeDataType::F64,
             AttributeDataType::U8Vec3 => proto::AttributeDataType::U8Vec3,
+            AttributeDataType::U32Vec3 => proto::AttributeDataType::U32Vec3,
+            AttributeDataType::U64Vec3 => proto::AttributeDataType::U64Vec3,
+            AttributeDataType::I32Vec3 => proto::AttributeDataType::I32Vec3,
             AttributeDataType::F64Vec3 => proto::AttributeDataType::F64Vec3,
         }
     }
@@ -51,6 +61,9 @@ impl AttributeDataType {
             proto::AttributeDataType::F32 => AttributeDataType::F32,
             proto::AttributeDataType::F64 => AttributeDataType::F64,
             proto::AttributeDataType::U8Vec3 => AttributeDataType::U8Vec3,
+            proto::AttributeDataType::U32Vec3 => AttributeDataType::U32Vec3,
+            proto::AttributeDataType::U64Vec3 => AttributeDataType::U64Vec3,
+            proto::AttributeDataType::I32Vec3 => AttributeDataType::I32Vec3,
             proto::AttributeDataType::F64Vec3 => AttributeDataType::F64Vec3,
             proto::AttributeDataType::INVALID_DATA_TYPE => {
                 return Err(
@@ -68,7 +81,41 @@ impl AttributeDataType {
             AttributeDataType::U32 | AttributeDataType::I32 | AttributeDataType::F32 => 4,
             AttributeDataType::U64 | AttributeDataType::I64 | AttributeDataType::F64 => 8,
             AttributeDataType::U8Vec3 => 3,
-            AttributeDataType::F64Vec3 => 3 * 8,
+            AttributeDataType::U32Vec3 | AttributeDataType::I32Vec3 => 3 * 4,
+            AttributeDataType::U64Vec3 | AttributeDataType::F64Vec3 => 3 * 8,
+        }
+    }
+
+    /// 'len' values of this type that are all zero.
+    pub fn zeros(self, len: usize) -> AttributeData {
+        match self {
+            AttributeDataType::U8 => AttributeData::U8(vec![0; len]),
+            AttributeDataType::U16 => AttributeData::U16(vec![0; len]),
+            AttributeDataType::U32 => AttributeData::U32(vec![0; len]),
+            AttributeDataType::U64 => AttributeData::U64(vec![0; len]),
+            AttributeDataType::I8 => AttributeData::I8(vec![0; len]),
+            AttributeDataType::I16 => AttributeData::I16(vec![0; len]),
+            AttributeDataType::I32 => AttributeData::I32(vec![0; len]),
+            AttributeDataType::I64 => AttributeData::I64(vec![0; len]),
+            AttributeDataType::F32 => AttributeData::F32(vec![0.; len]),
+            AttributeDataType::F64 => AttributeData::F64(vec![0.; len]),
+            AttributeDataType::U8Vec3 => AttributeData::U8Vec3(vec![Vector3::zeros(); len]),
+            AttributeDataType::U32Vec3 => AttributeData::U32Vec3(vec![Vector3::zeros(); len]),
+            AttributeDataType::U64Vec3 => AttributeData::U64Vec3(vec![Vector3::zeros(); len]),
+            AttributeDataType::I32Vec3 => AttributeData::I32Vec3(vec![Vector3::zeros(); len]),
+            AttributeDataType::F64Vec3 => AttributeData::F64Vec3(vec![Vector3::zeros(); len]),
+        }
+    }
+
+    /// The number of values per point.
+    pub fn dim(self) -> usize {
+        match self {
+            AttributeDataType::U8Vec3
+            | AttributeDataType::U32Vec3
+            | AttributeDataType::U64Vec3
+            | AttributeDataType::I32Vec3
+            | AttributeDataType::F64Vec3 => 3,
+            _ => 1,
         }
     }
 }
@@ -87,6 +134,9 @@ pub enum AttributeData {
     F32(Vec<f32>),
     F64(Vec<f64>),
     U8Vec3(Vec<Vector3<u8>>),
+    U32Vec3(Vec<Vector3<u32>>),
+    U64Vec3(Vec<Vector3<u64>>),
+    I32Vec3(Vec<Vector3<i32>>),
     F64Vec3(Vec<Vector3<f64>>),
 }
 
@@ -106,6 +156,9 @@ macro_rules! match_attr_data {
             AttributeData::F32(_d) => $match_rhs!(F32, _d $(, $arg )* ),
             AttributeData::F64(_d) => $match_rhs!(F64, _d $(, $arg )* ),
             AttributeData::U8Vec3(_d) => $match_rhs!(U8Vec3, _d $(, $arg )* ),
+            AttributeData::U32Vec3(_d) => $match_rhs!(U32Vec3, _d $(, $arg )* ),
+            AttributeData::U64Vec3(_d) => $match_rhs!(U64Vec3, _d $(, $arg )* ),
+            AttributeData::I32Vec3(_d) => $match_rhs!(I32Vec3, _d $(, $arg )* ),
             AttributeData::F64Vec3(_d) => $match_rhs!(F64Vec3, _d $(, $arg )* ),
         }
     };
@@ -125,8 +178,11 @@ macro_rules! match_1d_attr_data {
             AttributeData::I64(_d) => $match_rhs!(I64, _d $(, $arg )* ),
             AttributeData::F32(_d) => $match_rhs!(F32, _d $(, $arg )* ),
             AttributeData::F64(_d) => $match_rhs!(F64, _d $(, $arg )* ),
-            AttributeData::U8Vec3(_d) => unimplemented!(),
-            AttributeData::F64Vec3(_d) => unimplemented!(),
+            AttributeData::U8Vec3(_)
+            | AttributeData::U32Vec3(_)
+            | AttributeData::U64Vec3(_)
+            | AttributeData::I32Vec3(_)
+            | AttributeData::F64Vec3(_) => unimplemented!(),
         }
     };
 }
@@ -146,19 +202,7 @@ impl AttributeData {
     }
 
     pub fn dim(&self) -> usize {
-        match self {
-            AttributeData::U8(_)
-            | AttributeData::U16(_)
-            | AttributeData::U32(_)
-            | AttributeData::U64(_)
-            | AttributeData::I8(_)
-            | AttributeData::I16(_)
-            | AttributeData::I32(_)
-            | AttributeData::I64(_)
-            | AttributeData::F32(_)
-            | AttributeData::F64(_) => 1,
-            AttributeData::U8Vec3(_) | AttributeData::F64Vec3(_) => 3,
-        }
+        self.data_type().dim()
     }
 
     pub fn data_type(&self) -> AttributeDataType {
@@ -183,6 +227,9 @@ impl AttributeData {
             (AttributeData::F32(s), AttributeData::F32(o)) => s.append(o),
             (AttributeData::F64(s), AttributeData::F64(o)) => s.append(o),
             (AttributeData::U8Vec3(s), AttributeData::U8Vec3(o)) => s.append(o),
+            (AttributeData::U32Vec3(s), AttributeData::U32Vec3(o)) => s.append(o),
+            (AttributeData::U64Vec3(s), AttributeData::U64Vec3(o)) => s.append(o),
+            (AttributeData::I32Vec3(s), AttributeData::I32Vec3(o)) => s.append(o),
             (AttributeData::F64Vec3(s), AttributeData::F64Vec3(o)) => s.append(o),
             (s, o) => {
                 return Err(format!(
@@ -269,4 +316,92 @@ try_from_attribute_data!(I64, i64);
 try_from_attribute_data!(F32, f32);
 try_from_attribute_data!(F64, f64);
 try_from_attribute_data!(U8Vec3, Vector3<u8>);
+try_from_attribute_data!(U32Vec3, Vector3<u32>);
+try_from_attribute_data!(U64Vec3, Vector3<u64>);
+try_from_attribute_data!(I32Vec3, Vector3<i32>);
 try_from_attribute_data!(F64Vec3, Vector3<f64>);
+
+/// Other names under which queries can request the stored attributes of a point cloud, so that
+/// collections whose point clouds name the same attribute differently, e.g. "intensity" and
+/// "intensities", can be queried with one list of attributes. Query results use the requested
+/// names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttributeAliases {
+    // Maps the alias to the stored attribute.
+    aliases: BTreeMap<String, String>,
+}
+
+impl AttributeAliases {
+    pub fn new(aliases: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            aliases: aliases.into_iter().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The stored attribute that 'name' refers to, which is 'name' itself unless it is an alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// Renames the attributes of 'batch', which were read under the resolved names of
+    /// 'requested', to the requested names.
+    pub fn rename_to_requested(&self, batch: &mut PointsBatch, requested: &[&str]) {
+        if self.is_empty() {
+            return;
+        }
+        let mut stored = std::mem::take(&mut batch.attributes);
+        for (index, name) in requested.iter().enumerate() {
+            let resolved = self.resolve(name);
+            // An attribute that is requested under several names is copied for all but the last.
+            let is_requested_again = requested[index + 1..]
+                .iter()
+                .any(|other| self.resolve(other) == resolved);
+            let data = if is_requested_again {
+                stored.get(resolved).cloned()
+            } else {
+                stored.remove(resolved)
+            };
+            if let Some(data) = data {
+                batch.attributes.insert((*name).to_string(), data);
+            }
+        }
+    }
+
+    pub fn from_meta_proto(meta: &proto::Meta) -> Self {
+        Self::new(
+            meta.get_attribute_aliases()
+                .iter()
+                .map(|alias| (alias.alias.clone(), alias.attribute.clone())),
+        )
+    }
+
+    pub fn to_proto(&self) -> Vec<proto::AttributeAlias> {
+        self.aliases
+            .iter()
+            .map(|(alias, attribute)| {
+                let mut proto = proto::AttributeAlias::new();
+                proto.set_alias(alias.clone());
+                proto.set_attribute(attribute.clone());
+                proto
+            })
+            .collect()
+    }
+}
+
+/// Stores 'aliases' in the meta file of the point cloud in 'directory', replacing the previous
+/// ones.
+pub fn write_attribute_aliases(
+    directory: impl AsRef<Path>,
+    aliases: &AttributeAliases,
+) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    data_provider.update_meta_proto(|meta| {
+        meta.set_attribute_aliases(::protobuf::RepeatedField::from_vec(aliases.to_proto()))
+    })
+}
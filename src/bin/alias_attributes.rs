@@ -0,0 +1,48 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::attributes::{write_attribute_aliases, AttributeAliases};
+use point_viewer::errors::*;
+use std::path::PathBuf;
+
+fn alias_from_str(s: &str) -> std::result::Result<(String, String), &'static str> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(alias), Some(attribute)) if !alias.is_empty() && !attribute.is_empty() => {
+            Ok((alias.to_string(), attribute.to_string()))
+        }
+        _ => Err("Expected <alias>=<attribute>."),
+    }
+}
+
+/// Sets the other names under which queries can request the attributes of a point cloud, e.g.
+/// `--alias intensity=intensities` for a point cloud that stores "intensities".
+#[derive(Clap, Debug)]
+#[clap(name = "alias_attributes")]
+struct CommandlineArguments {
+    /// Directory of the octree or S2 point cloud.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+
+    /// An alias as <alias>=<stored attribute>. Replaces all previous aliases, without any the
+    /// aliases are removed.
+    #[clap(long, parse(try_from_str = alias_from_str))]
+    alias: Vec<(String, String)>,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    write_attribute_aliases(&args.directory, &AttributeAliases::new(args.alias))
+}
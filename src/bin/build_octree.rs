@@ -13,17 +13,28 @@
 // limitations under the License.
 
 use clap::Clap;
-use point_viewer::octree::build_octree_from_file;
+use point_viewer::errors::*;
+use point_viewer::labels::{write_label_palette, LabelPalette, LABEL_ATTRIBUTES};
+use point_viewer::octree::{InputFile, OctreeBuilder};
+use point_viewer::provenance::{write_provenance, Provenance};
+use point_viewer::read_write::{AsciiColumns, NonFinitePolicy};
+use point_viewer::utils::ProgressMode;
 use rayon::ThreadPoolBuilder;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Clap, Debug)]
 #[clap(name = "build_octree")]
 struct CommandlineArguments {
-    /// PLY/PTS file to parse for the points.
+    /// PLY file or ASCII file (.pts, .xyz, .txt or .csv), optionally gzipped, to parse for the
+    /// points. Can also be a zip or tar archive, see '--member'.
     #[clap(parse(from_os_str))]
     input: PathBuf,
 
+    /// Glob selecting the file to read from the input archive, e.g. 'scans/*.ply'.
+    #[clap(long)]
+    member: Option<String>,
+
     /// Output directory to write the octree into.
     #[clap(long, parse(from_os_str))]
     output_directory: PathBuf,
@@ -36,18 +47,131 @@ struct CommandlineArguments {
     /// The number of threads used to shard octree building. Set this as high as possible for SSDs.
     #[clap(long, default_value = "10")]
     num_threads: usize,
+
+    /// The column layout of ASCII input files, e.g. 'x,y,z,i,r,g,b,class'. Use '_' for columns
+    /// to ignore.
+    #[clap(long, default_value = "x,y,z,i,r,g,b")]
+    columns: AsciiColumns,
+
+    /// Also keep the per-point opacity of the input, e.g. the alpha channel of a PLY file.
+    #[clap(long)]
+    with_alpha: bool,
+
+    /// What to do with NaN or infinite positions and attribute values: drop, error or clamp.
+    #[clap(long, default_value = "drop")]
+    non_finite: NonFinitePolicy,
+
+    /// Keep only the first point within cubes of this edge length in meters, e.g. to not count
+    /// overlapping scans twice.
+    #[clap(long)]
+    duplicate_tolerance: Option<f64>,
+
+    /// Text file with one 'id,name,red,green,blue' line per value of the 'label' attribute, or of
+    /// 'class' for inputs without labels. It is stored with the octree and used by the viewers to
+    /// color and filter points by label.
+    #[clap(long, parse(from_os_str))]
+    label_palette: Option<PathBuf>,
+
+    /// Record the value ranges and label counts of every node, so that filtered queries and the
+    /// viewers can skip nodes. This reads the octree once more after it was built.
+    #[clap(long)]
+    node_statistics: bool,
+
+    /// The attribute with the label ids that nodes count and the label palette is of, by default
+    /// 'label' or else 'class'.
+    #[clap(long)]
+    label_attribute: Option<String>,
+
+    /// How to report progress: bar, quiet or json.
+    #[clap(long, default_value = "bar")]
+    progress: ProgressMode,
 }
 
-fn main() {
+fn main() -> Result<()> {
     let args = CommandlineArguments::parse();
-    ThreadPoolBuilder::new()
+    let thread_pool = ThreadPoolBuilder::new()
         .num_threads(args.num_threads)
-        .build_global()
-        .expect("Could not create thread pool.");
-    build_octree_from_file(
-        args.output_directory,
-        args.resolution,
-        args.input,
-        &["color", "intensity"],
+        .build()
+        .chain_err(|| "Could not create thread pool.")?;
+    let mut provenance = Provenance::new("build_octree")
+        .with_source_file(&args.input)
+        .chain_err(|| "Could not hash input file.")?
+        .with_parameter("resolution", args.resolution)
+        .with_parameter(
+            "non_finite",
+            format!("{:?}", args.non_finite).to_lowercase(),
+        )
+        .with_parameter("with_alpha", args.with_alpha);
+    if let Some(duplicate_tolerance) = args.duplicate_tolerance {
+        provenance = provenance.with_parameter("duplicate_tolerance", duplicate_tolerance);
+    }
+    if let Some(member) = &args.member {
+        provenance = provenance.with_parameter("member", member);
+    }
+    let input = match &args.member {
+        Some(member) => InputFile::from_archive(args.input, member, args.columns),
+        None => InputFile::from_path(args.input, args.columns),
+    }
+    .chain_err(|| "Unsupported input file.")?;
+    if let InputFile::Ascii(_, columns) = &input {
+        provenance = provenance.with_parameter("columns", columns);
+    }
+    let mut attributes = input
+        .attributes()
+        .chain_err(|| "Could not read the attributes of the input.")?;
+    let label_attribute = args.label_attribute.as_deref().or_else(|| {
+        LABEL_ATTRIBUTES
+            .iter()
+            .find(|attribute| attributes.contains(*attribute))
+            .copied()
+    });
+    let label_palette = match &args.label_palette {
+        Some(path) => {
+            let attribute = label_attribute.ok_or_else(|| {
+                ErrorKind::InvalidInput(
+                    "A label palette requires a 'label' or 'class' column in the input."
+                        .to_string(),
+                )
+            })?;
+            Some(
+                LabelPalette::from_csv_file(attribute, path)
+                    .chain_err(|| "Could not read label palette.")?,
+            )
+        }
+        None => None,
+    };
+    if args.with_alpha {
+        attributes.push("alpha");
+    }
+    let mut builder = OctreeBuilder::new(args.resolution)
+        .with_attributes(&attributes)
+        .with_non_finite_policy(args.non_finite)
+        .with_thread_pool(Arc::new(thread_pool))
+        .with_progress_mode(args.progress);
+    if let Some(duplicate_tolerance) = args.duplicate_tolerance {
+        builder = builder.with_duplicate_suppression(duplicate_tolerance);
+    }
+    if args.node_statistics {
+        builder = builder.with_node_statistics();
+    }
+    if let Some(label_attribute) = label_attribute {
+        builder = builder.with_label_attribute(label_attribute);
+    }
+    let report = builder
+        .build_from_file(&args.output_directory, &input)
+        .chain_err(|| "Could not build octree.")?;
+    eprintln!(
+        "Built {} nodes with {} points.",
+        report.num_nodes, report.num_points
     );
+    if args.duplicate_tolerance.is_some() {
+        eprintln!("Dropped {} duplicate points.", report.num_duplicates);
+    }
+    write_provenance(&args.output_directory, &provenance)
+        .chain_err(|| "Could not write provenance.")?;
+    if let Some(label_palette) = &label_palette {
+        write_label_palette(&args.output_directory, label_palette)
+            .chain_err(|| "Could not write label palette.")?;
+    }
+    Ok(())
 }
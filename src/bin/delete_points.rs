@@ -0,0 +1,87 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use nalgebra::Point3;
+use point_viewer::data_provider::{OnDiskDataProvider, OverlayDataProvider};
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::PointLocation;
+use point_viewer::octree::{delete_points_in_location, Octree};
+use point_viewer::utils::parse_point3;
+use std::fs;
+use std::path::PathBuf;
+
+/// Deletes all points in a region of an octree, e.g. scan artifacts like moving vehicles.
+#[derive(Clap, Debug)]
+#[clap(name = "delete_points")]
+struct CommandlineArguments {
+    /// Directory of the octree to delete points from.
+    #[clap(parse(from_os_str))]
+    octree_directory: PathBuf,
+
+    /// Minimum corner of the box to delete as 'x,y,z'.
+    #[clap(long, parse(try_from_str = parse_point3), requires = "max")]
+    min: Option<Point3<f64>>,
+
+    /// Maximum corner of the box to delete as 'x,y,z'.
+    #[clap(long, parse(try_from_str = parse_point3), requires = "min")]
+    max: Option<Point3<f64>>,
+
+    /// A JSON file with the serialized PointLocation to delete, e.g. an oriented box.
+    #[clap(long, parse(from_os_str), conflicts_with = "min")]
+    location: Option<PathBuf>,
+
+    /// Record the deletions in this overlay directory instead of rewriting the octree, see
+    /// `octree_overlay`.
+    #[clap(long, parse(from_os_str))]
+    overlay_directory: Option<PathBuf>,
+}
+
+fn read_location(args: &CommandlineArguments) -> Result<PointLocation> {
+    match (&args.location, args.min, args.max) {
+        (Some(path), _, _) => {
+            let json = fs::read_to_string(path)
+                .chain_err(|| format!("Could not read {}", path.display()))?;
+            serde_json::from_str(&json)
+                .chain_err(|| format!("{} does not contain a PointLocation", path.display()))
+        }
+        (None, Some(min), Some(max)) => Ok(PointLocation::Aabb(Aabb::new(min, max))),
+        _ => Err(ErrorKind::InvalidInput(
+            "Give the region to delete with --min and --max or with --location.".to_string(),
+        )
+        .into()),
+    }
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let location = read_location(&args)?;
+    if let PointLocation::AllPoints = location {
+        return Err(ErrorKind::InvalidInput("Refusing to delete all points.".to_string()).into());
+    }
+    let num_deleted_points = match &args.overlay_directory {
+        Some(overlay_directory) => {
+            let on_disk = || OnDiskDataProvider {
+                directory: args.octree_directory.clone(),
+            };
+            let octree = Octree::from_data_provider(Box::new(on_disk()))?;
+            let overlay = OverlayDataProvider::new(Box::new(on_disk()), overlay_directory)?;
+            octree.delete_points_in(&location, &overlay)?
+        }
+        None => delete_points_in_location(&args.octree_directory, &location)?,
+    };
+    eprintln!("Deleted {} points.", num_deleted_points);
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::*;
+use point_viewer::maintenance::{dataset_size, dataset_stats};
+use point_viewer::provenance::Provenance;
+
+/// Prints the meta data of a point cloud, including where it came from.
+#[derive(Clap, Debug)]
+#[clap(name = "describe_point_cloud")]
+struct CommandlineArguments {
+    /// Location of the octree or S2 point cloud.
+    location: String,
+
+    /// Also print how many bytes the point cloud takes up per attribute, level and region.
+    #[clap(long)]
+    sizes: bool,
+
+    /// Level of the octree nodes or S2 cells that the sizes are summed up in as regions.
+    #[clap(long, default_value = "3")]
+    region_level: u8,
+
+    /// Also print statistics: density percentiles, nodes per level, attribute coverage and a
+    /// coarse grid of where the points are.
+    #[clap(long)]
+    stats: bool,
+
+    /// Print only the statistics, as JSON.
+    #[clap(long)]
+    json: bool,
+
+    /// How many nodes are read to compute the attribute coverage.
+    #[clap(long, default_value = "100")]
+    sampled_nodes: usize,
+
+    /// Number of cells of the density grid along x and y.
+    #[clap(long, default_value = "8")]
+    grid_size: usize,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let data_provider = DataProviderFactory::new()
+        .generate_data_provider(&args.location)
+        .chain_err(|| format!("Couldn't open '{}'.", args.location))?;
+    let compute_stats = || {
+        dataset_stats(&*data_provider, args.sampled_nodes, args.grid_size)
+            .chain_err(|| "Couldn't compute the statistics.")
+    };
+    if args.json {
+        let stats = serde_json::to_string_pretty(&compute_stats()?)
+            .chain_err(|| "Couldn't serialize the statistics.")?;
+        println!("{}", stats);
+        return Ok(());
+    }
+    let meta = data_provider
+        .meta_proto()
+        .chain_err(|| format!("Couldn't read meta data from '{}'.", args.location))?;
+
+    println!("Version: {}", meta.get_version());
+    let bounding_box = meta.get_bounding_box();
+    let (min, max) = (bounding_box.get_min(), bounding_box.get_max());
+    println!(
+        "Bounding box: ({}, {}, {}) - ({}, {}, {})",
+        min.x, min.y, min.z, max.x, max.y, max.z
+    );
+    if meta.has_octree() {
+        let octree = meta.get_octree();
+        let num_points: i64 = octree.get_nodes().iter().map(|n| n.num_points).sum();
+        println!("Octree with resolution {}", octree.get_resolution());
+        println!("{} nodes, {} points", octree.get_nodes().len(), num_points);
+    } else if meta.has_s2() {
+        let s2 = meta.get_s2();
+        let num_points: u64 = s2.get_cells().iter().map(|c| c.num_points).sum();
+        println!("S2 point cloud");
+        println!("{} cells, {} points", s2.get_cells().len(), num_points);
+        let attributes: Vec<_> = s2.get_attributes().iter().map(|a| a.get_name()).collect();
+        println!("Attributes: {}", attributes.join(", "));
+    }
+    println!();
+    match Provenance::from_meta_proto(&meta) {
+        Some(provenance) => print!("{}", provenance),
+        None => println!("No provenance recorded."),
+    }
+    if args.sizes {
+        println!();
+        let report = dataset_size(&*data_provider, args.region_level)
+            .chain_err(|| "Couldn't compute the sizes.")?;
+        print!("{}", report);
+    }
+    if args.stats {
+        println!();
+        print!("{}", compute_stats()?);
+    }
+    Ok(())
+}
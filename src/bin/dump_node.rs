@@ -0,0 +1,224 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::attributes::AttributeData;
+use point_viewer::data_provider::{is_octree_meta, DataProviderFactory};
+use point_viewer::errors::*;
+use point_viewer::geometry::cell_id_from_token;
+use point_viewer::iterator::PointCloud;
+use point_viewer::match_attr_data;
+use point_viewer::octree::{NodeId, Octree};
+use point_viewer::read_write::{Encoding, NodeWriter, OpenMode, PlyNodeWriter};
+use point_viewer::s2_cells::{cell_id_from_proto, S2Cells};
+use point_viewer::PointsBatch;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const NUM_POINTS_PER_BATCH: usize = 100_000;
+
+/// Decodes a single node of an octree or cell of an S2 point cloud and writes its points with all
+/// stored attributes, together with its entry in the meta, to debug encoding or culling issues.
+#[derive(Clap, Debug)]
+#[clap(name = "dump_node")]
+struct CommandlineArguments {
+    /// Location of the octree or S2 point cloud.
+    location: String,
+
+    /// The id of the octree node, e.g. "r0413", or the token of the S2 cell, e.g. "89c25".
+    node_id: String,
+
+    /// "text" for one point per line, with the meta entry in comments, "json" for an object with
+    /// the meta entry and the points, or "ply", which needs '--output' and prints the meta entry.
+    #[clap(long, default_value = "text", possible_values = &["text", "json", "ply"])]
+    format: String,
+
+    /// The file to write to instead of stdout.
+    #[clap(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+/// The entry of a node in the meta and its points, with the stored values of all attributes,
+/// i.e. without radiometric correction.
+struct NodeDump {
+    meta: Value,
+    points: PointsBatch,
+}
+
+fn read_points<C: PointCloud>(
+    point_cloud: &C,
+    node_id: C::Id,
+    attributes: &[&str],
+) -> Result<PointsBatch> {
+    let mut points = PointsBatch {
+        position: Vec::new(),
+        attributes: Default::default(),
+    };
+    for mut batch in point_cloud.points_in_node(attributes, node_id, NUM_POINTS_PER_BATCH)? {
+        points.append(&mut batch).map_err(ErrorKind::InvalidInput)?;
+    }
+    Ok(points)
+}
+
+fn dump_node(location: &str, node_id: &str) -> Result<NodeDump> {
+    let data_provider = DataProviderFactory::new().generate_data_provider(location)?;
+    let meta = data_provider.meta_proto()?;
+    if is_octree_meta(&meta) {
+        let octree = Octree::from_data_provider(data_provider)?;
+        let node_id = NodeId::from_str(node_id)?;
+        let node_meta = octree.node_meta(&node_id).ok_or_else(|| {
+            ErrorKind::InvalidInput(format!("The octree has no node {}.", node_id))
+        })?;
+        let meta = json!({
+            "id": node_id.to_string(),
+            "level": node_id.level(),
+            "num_points": node_meta.num_points,
+            "position_encoding": format!("{:?}", node_meta.position_encoding),
+            "bounding_cube": {
+                "min": node_meta.bounding_cube.min(),
+                "edge_length": node_meta.bounding_cube.edge_length(),
+            },
+            "label_counts": node_meta.label_counts,
+            "attribute_ranges": node_meta.attribute_ranges,
+        });
+        let points = read_points(&octree, node_id, &octree.stored_attribute_names()?)?;
+        Ok(NodeDump { meta, points })
+    } else {
+        let cell_id = cell_id_from_token(node_id)?;
+        let cell = meta
+            .get_s2()
+            .get_cells()
+            .iter()
+            .find(|cell| cell_id_from_proto(cell).ok() == Some(cell_id))
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput(format!("The point cloud has no cell {}.", node_id))
+            })?;
+        let meta_entry = json!({
+            "id": cell_id.0,
+            "token": cell_id.to_token(),
+            "level": cell_id.level(),
+            "num_points": cell.num_points,
+        });
+        let attributes: Vec<String> = meta
+            .get_s2()
+            .get_attributes()
+            .iter()
+            .map(|attribute| attribute.get_name().to_string())
+            .collect();
+        let attributes: Vec<&str> = attributes.iter().map(String::as_str).collect();
+        let s2_cells = S2Cells::from_data_provider(data_provider)?;
+        let points = read_points(&s2_cells, cell_id, &attributes)?;
+        Ok(NodeDump {
+            meta: meta_entry,
+            points,
+        })
+    }
+}
+
+/// The value of point 'index' in 'data', an array for vector attributes.
+fn value_at(data: &AttributeData, index: usize) -> Value {
+    macro_rules! rhs {
+        ($dtype:ident, $data:ident, $index:ident) => {
+            json!($data[$index])
+        };
+    }
+    match_attr_data!(data, rhs, index)
+}
+
+/// The components of a value, separated by spaces.
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Array(components) => components
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" "),
+        value => value.to_string(),
+    }
+}
+
+fn write_text(dump: &NodeDump, writer: &mut dyn Write) -> io::Result<()> {
+    if let Value::Object(entries) = &dump.meta {
+        for (key, value) in entries {
+            writeln!(writer, "# {}: {}", key, value)?;
+        }
+    }
+    let names: Vec<&str> = dump.points.attributes.keys().map(String::as_str).collect();
+    writeln!(writer, "# x y z {}", names.join(" "))?;
+    for (i, position) in dump.points.position.iter().enumerate() {
+        write!(writer, "{} {} {}", position.x, position.y, position.z)?;
+        for data in dump.points.attributes.values() {
+            write!(writer, " {}", to_text(&value_at(data, i)))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+fn write_json(dump: &NodeDump, writer: &mut dyn Write) -> io::Result<()> {
+    let points: Vec<Value> = dump
+        .points
+        .position
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let mut point = serde_json::Map::new();
+            point.insert("position".to_string(), json!(position));
+            for (name, data) in &dump.points.attributes {
+                point.insert(name.clone(), value_at(data, i));
+            }
+            Value::Object(point)
+        })
+        .collect();
+    let dump = json!({ "meta": dump.meta, "points": points });
+    serde_json::to_writer_pretty(&mut *writer, &dump)?;
+    writeln!(writer)
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let dump = dump_node(&args.location, &args.node_id)
+        .chain_err(|| format!("Could not read {} of {}.", args.node_id, args.location))?;
+    if args.format == "ply" {
+        let output = args
+            .output
+            .as_ref()
+            .ok_or_else(|| ErrorKind::InvalidInput("Writing PLY needs '--output'.".to_string()))?;
+        let mut writer = PlyNodeWriter::new(output, Encoding::Plain, OpenMode::Truncate);
+        writer
+            .write(&dump.points)
+            .chain_err(|| format!("Could not write {}", output.display()))?;
+        println!("{}", serde_json::to_string_pretty(&dump.meta).unwrap());
+        return Ok(());
+    }
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(output) => {
+            Box::new(BufWriter::new(File::create(output).chain_err(|| {
+                format!("Could not create {}", output.display())
+            })?))
+        }
+        None => Box::new(io::stdout()),
+    };
+    let written = if args.format == "json" {
+        write_json(&dump, &mut *writer)
+    } else {
+        write_text(&dump, &mut *writer)
+    };
+    written
+        .and_then(|_| writer.flush())
+        .chain_err(|| "Could not write the points")
+}
@@ -0,0 +1,101 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use nalgebra::Point3;
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::PointLocation;
+use point_viewer::octree::{export_bundle, export_bundle_archive, Octree};
+use point_viewer::provenance::{write_provenance, Provenance};
+use point_viewer::utils::{parse_point3, set_progress_mode, ProgressMode};
+use std::path::PathBuf;
+
+/// Extracts a region of an octree into a self-contained octree directory for offline use.
+#[derive(Clap, Debug)]
+#[clap(name = "export_octree_bundle")]
+struct CommandlineArguments {
+    /// Location of the octree to export from.
+    octree_location: String,
+
+    /// Output directory to write the smaller octree into, or with --archive, the tar file.
+    #[clap(long, parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// Write a single tar file ordered for progressive download instead of a directory.
+    #[clap(long)]
+    archive: bool,
+
+    /// Minimum corner of the region to export as 'x,y,z'. Exports everything if not given.
+    #[clap(long, parse(try_from_str = parse_point3), requires = "max")]
+    min: Option<Point3<f64>>,
+
+    /// Maximum corner of the region to export as 'x,y,z'.
+    #[clap(long, parse(try_from_str = parse_point3), requires = "min")]
+    max: Option<Point3<f64>>,
+
+    /// The deepest octree level to export. Limiting this trades detail for a smaller bundle.
+    #[clap(long)]
+    max_level: Option<u8>,
+
+    /// How to report progress: bar, quiet or json.
+    #[clap(long, default_value = "bar")]
+    progress: ProgressMode,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    set_progress_mode(args.progress);
+    let octree = DataProviderFactory::new()
+        .generate_data_provider(&args.octree_location)
+        .and_then(Octree::from_data_provider)
+        .chain_err(|| format!("Couldn't create octree from '{}'.", args.octree_location))?;
+    let mut provenance =
+        Provenance::new("export_octree_bundle").with_source_location(&args.octree_location);
+    let location = match (args.min, args.max) {
+        (Some(min), Some(max)) => {
+            provenance = provenance
+                .with_parameter("min", format!("{},{},{}", min.x, min.y, min.z))
+                .with_parameter("max", format!("{},{},{}", max.x, max.y, max.z));
+            PointLocation::Aabb(Aabb::new(min, max))
+        }
+        _ => PointLocation::AllPoints,
+    };
+    if let Some(max_level) = args.max_level {
+        provenance = provenance.with_parameter("max_level", max_level);
+    }
+    let num_nodes = if args.archive {
+        export_bundle_archive(
+            &octree,
+            &location,
+            args.max_level,
+            &provenance,
+            &args.output_directory,
+        )
+        .chain_err(|| "Could not export bundle.")?
+    } else {
+        let num_nodes = export_bundle(&octree, &location, args.max_level, &args.output_directory)
+            .chain_err(|| "Could not export bundle.")?;
+        write_provenance(&args.output_directory, &provenance)
+            .chain_err(|| "Could not write provenance.")?;
+        num_nodes
+    };
+    eprintln!(
+        "Exported {} nodes to {}.",
+        num_nodes,
+        args.output_directory.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,59 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::errors::*;
+use point_viewer::geometry::Wireframe;
+use point_viewer::iterator::PointLocation;
+use std::fs;
+use std::path::PathBuf;
+
+/// Writes the region that a query covers as a wireframe to a binary glTF file, to look at it
+/// together with the point cloud in external 3D tools.
+#[derive(Clap, Debug)]
+#[clap(name = "export_query_gltf")]
+struct CommandlineArguments {
+    /// A JSON file with a serialized PointQuery or PointLocation.
+    #[clap(parse(from_os_str))]
+    query: PathBuf,
+
+    /// The glTF file to write, usually ending in ".glb".
+    #[clap(parse(from_os_str))]
+    output: PathBuf,
+}
+
+fn read_location(path: &PathBuf) -> Result<PointLocation> {
+    let json =
+        fs::read_to_string(path).chain_err(|| format!("Could not read {}", path.display()))?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&json).chain_err(|| format!("{} is not JSON", path.display()))?;
+    // A whole query has its location in a field of its own.
+    if let Some(location) = value.get_mut("location") {
+        value = location.take();
+    }
+    serde_json::from_value(value).chain_err(|| {
+        format!(
+            "{} contains neither a PointQuery nor a PointLocation",
+            path.display()
+        )
+    })
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let wireframe = Wireframe::from_location(&read_location(&args.query)?)?;
+    fs::write(&args.output, wireframe.to_glb())
+        .chain_err(|| format!("Could not write {}", args.output.display()))?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::*;
+use point_viewer::octree::Octree;
+use point_viewer::training_tiles::{export_training_tiles, TrainingTileOptions};
+use point_viewer::utils::{set_progress_mode, ProgressMode};
+use std::path::PathBuf;
+
+/// Cuts a point cloud into square tiles with at most a given number of points each, e.g. as
+/// training data for semantic segmentation, and writes them as .npz files with a manifest.
+#[derive(Clap, Debug)]
+#[clap(name = "export_training_tiles")]
+struct CommandlineArguments {
+    /// Location of the octree to export from.
+    octree_location: String,
+
+    /// Output directory for the tiles and the manifest.
+    #[clap(long, parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// The edge length of the tiles in meters.
+    #[clap(long, default_value = "50")]
+    tile_size: f64,
+
+    /// Tiles with more points are subsampled to this many.
+    #[clap(long, default_value = "65536")]
+    max_points: usize,
+
+    /// Tiles with fewer points are skipped.
+    #[clap(long, default_value = "1024")]
+    min_points: usize,
+
+    /// The attributes to export besides the positions, e.g. color or intensity.
+    #[clap(long)]
+    attributes: Vec<String>,
+
+    /// The attribute with the class of each point, exported as "labels".
+    #[clap(long)]
+    class_attribute: Option<String>,
+
+    /// Keep equally many points of each class when subsampling, where possible.
+    #[clap(long, requires = "class-attribute")]
+    balance_classes: bool,
+
+    /// Scale the positions so that each tile spans [-1, 1] in x and y.
+    #[clap(long)]
+    normalize: bool,
+
+    /// How to report progress: bar, quiet or json.
+    #[clap(long, default_value = "bar")]
+    progress: ProgressMode,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    set_progress_mode(args.progress);
+    let octree = DataProviderFactory::new()
+        .generate_data_provider(&args.octree_location)
+        .and_then(Octree::from_data_provider)?;
+    let options = TrainingTileOptions {
+        tile_size_m: args.tile_size,
+        max_points_per_tile: args.max_points,
+        min_points_per_tile: args.min_points,
+        attributes: args.attributes,
+        class_attribute: args.class_attribute,
+        balance_classes: args.balance_classes,
+        normalize: args.normalize,
+    };
+    let manifest = export_training_tiles(&octree, &options, &args.output_directory)?;
+    eprintln!(
+        "Exported {} tiles to {}.",
+        manifest.tiles.len(),
+        args.output_directory.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,71 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::{is_octree_meta, DataProviderFactory};
+use point_viewer::errors::*;
+use point_viewer::fingerprint::{fingerprint, Fingerprint};
+use point_viewer::octree::Octree;
+use point_viewer::s2_cells::S2Cells;
+
+/// Prints a content hash of each point cloud that does not depend on the order of the points, and
+/// fails if they differ.
+#[derive(Clap, Debug)]
+#[clap(name = "fingerprint_point_cloud")]
+struct CommandlineArguments {
+    /// Locations of the octrees or S2 point clouds.
+    #[clap(required = true)]
+    locations: Vec<String>,
+
+    /// The attributes to include besides the positions, e.g. color or intensity.
+    #[clap(long)]
+    attributes: Vec<String>,
+
+    /// Positions are compared after rounding them to multiples of this many meters.
+    #[clap(long, default_value = "0.001")]
+    tolerance: f64,
+}
+
+fn fingerprint_location(
+    location: &str,
+    attributes: &[&str],
+    tolerance: f64,
+) -> Result<Fingerprint> {
+    let data_provider = DataProviderFactory::new().generate_data_provider(location)?;
+    let meta = data_provider.meta_proto()?;
+    if is_octree_meta(&meta) {
+        let octree = Octree::from_data_provider(data_provider)?;
+        fingerprint(&[octree], attributes, tolerance)
+    } else {
+        let s2_cells = S2Cells::from_data_provider(data_provider)?;
+        fingerprint(&[s2_cells], attributes, tolerance)
+    }
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let attributes: Vec<&str> = args.attributes.iter().map(String::as_str).collect();
+    let mut fingerprints = Vec::new();
+    for location in &args.locations {
+        let fingerprint = fingerprint_location(location, &attributes, args.tolerance)
+            .chain_err(|| format!("Could not fingerprint {}.", location))?;
+        println!("{}  {}", fingerprint, location);
+        fingerprints.push(fingerprint);
+    }
+    if fingerprints.windows(2).any(|pair| pair[0] != pair[1]) {
+        eprintln!("The point clouds differ.");
+        std::process::exit(1);
+    }
+    Ok(())
+}
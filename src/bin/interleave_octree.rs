@@ -0,0 +1,46 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::errors::*;
+use point_viewer::maintenance::set_interleaved_attributes;
+use std::path::PathBuf;
+
+/// Stores the positions and the given attributes of each node of an octree interleaved in a
+/// single file, so that the node can be fetched with one request, or splits them up again.
+#[derive(Clap, Debug)]
+#[clap(name = "interleave_octree")]
+struct CommandlineArguments {
+    /// Directory of the octree.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+
+    /// The attributes to interleave with the positions. All nodes must have them.
+    #[clap(long, default_value = "color")]
+    attributes: Vec<String>,
+
+    /// Give every attribute its own file again.
+    #[clap(long)]
+    separate: bool,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let attributes: Vec<&str> = if args.separate {
+        Vec::new()
+    } else {
+        args.attributes.iter().map(String::as_str).collect()
+    };
+    set_interleaved_attributes(&args.directory, &attributes)
+}
@@ -0,0 +1,133 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::*;
+use point_viewer::iterator::PointCloud;
+use point_viewer::octree::{Octree, OctreeBuilder};
+use point_viewer::provenance::{write_provenance, Provenance};
+use point_viewer::utils::ProgressMode;
+use rayon::ThreadPoolBuilder;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Merges octrees with the same resolution, e.g. of neighbouring regions, into a single octree.
+#[derive(Clap, Debug)]
+#[clap(name = "merge_octrees")]
+struct CommandlineArguments {
+    /// Locations of the octrees to merge.
+    #[clap(required = true)]
+    locations: Vec<String>,
+
+    /// Output directory to write the merged octree into. It must not exist yet or be empty.
+    #[clap(long, parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// The attributes to keep. By default, all attributes that every octree stores are kept.
+    #[clap(long)]
+    attributes: Vec<String>,
+
+    /// Keep only the first point within cubes of this edge length in meters, e.g. where the
+    /// acquisitions of the octrees overlap.
+    #[clap(long)]
+    duplicate_tolerance: Option<f64>,
+
+    /// The number of threads used to shard octree building.
+    #[clap(long, default_value = "10")]
+    num_threads: usize,
+
+    /// How to report progress: bar, quiet or json.
+    #[clap(long, default_value = "bar")]
+    progress: ProgressMode,
+}
+
+/// The attributes that all of 'octrees' store.
+fn common_attributes(octrees: &[Octree]) -> Result<Vec<String>> {
+    let mut attributes = Vec::new();
+    for attribute in octrees[0].attribute_names() {
+        let mut stored_by_all = true;
+        for octree in octrees {
+            stored_by_all &= octree.stored_attribute_type(attribute)?.is_some();
+        }
+        if stored_by_all {
+            attributes.push(attribute.to_string());
+        }
+    }
+    Ok(attributes)
+}
+
+fn is_same_directory(location: &str, directory: &Path) -> bool {
+    match (Path::new(location).canonicalize(), directory.canonicalize()) {
+        (Ok(location), Ok(directory)) => location == directory,
+        _ => false,
+    }
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    if let Some(location) = args
+        .locations
+        .iter()
+        .find(|location| is_same_directory(location, &args.output_directory))
+    {
+        return Err(ErrorKind::InvalidInput(format!(
+            "The output directory is the input {}.",
+            location
+        ))
+        .into());
+    }
+    let mut octrees = Vec::new();
+    let mut provenance = Provenance::new("merge_octrees");
+    for location in &args.locations {
+        let octree = DataProviderFactory::new()
+            .generate_data_provider(location)
+            .and_then(Octree::from_data_provider)
+            .chain_err(|| format!("Could not open octree {}.", location))?;
+        octrees.push(octree);
+        provenance = provenance.with_source_location(location);
+    }
+    let attributes = if args.attributes.is_empty() {
+        common_attributes(&octrees)?
+    } else {
+        args.attributes.clone()
+    };
+    let attributes: Vec<&str> = attributes.iter().map(String::as_str).collect();
+    provenance = provenance.with_parameter("attributes", attributes.join(","));
+
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build()
+        .chain_err(|| "Could not create thread pool.")?;
+    let mut builder = OctreeBuilder::new(octrees[0].resolution())
+        .with_attributes(&attributes)
+        .with_thread_pool(Arc::new(thread_pool))
+        .with_progress_mode(args.progress);
+    if let Some(duplicate_tolerance) = args.duplicate_tolerance {
+        builder = builder.with_duplicate_suppression(duplicate_tolerance);
+        provenance = provenance.with_parameter("duplicate_tolerance", duplicate_tolerance);
+    }
+    let report = builder.merge(&args.output_directory, &octrees)?;
+    eprintln!(
+        "Merged {} octrees into {} nodes with {} points.",
+        octrees.len(),
+        report.num_nodes,
+        report.num_points
+    );
+    if args.duplicate_tolerance.is_some() {
+        eprintln!("Dropped {} duplicate points.", report.num_duplicates);
+    }
+    write_provenance(&args.output_directory, &provenance)?;
+    Ok(())
+}
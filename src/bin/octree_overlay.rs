@@ -0,0 +1,67 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::{commit_overlay, OnDiskDataProvider, OverlayDataProvider};
+use point_viewer::errors::*;
+use std::path::PathBuf;
+
+/// Manages the edits that are stored in an overlay directory next to an octree.
+#[derive(Clap, Debug)]
+#[clap(name = "octree_overlay")]
+struct CommandlineArguments {
+    /// Directory of the unmodified octree.
+    #[clap(parse(from_os_str))]
+    octree_directory: PathBuf,
+
+    /// Directory holding the edits.
+    #[clap(parse(from_os_str))]
+    overlay_directory: PathBuf,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap, Debug)]
+enum Command {
+    /// Lists the edited nodes and their number of deleted points.
+    Status,
+    /// Rewrites the octree with the edits applied and removes the overlay.
+    Commit,
+    /// Removes the overlay, leaving the octree as it was.
+    Discard,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let octree = OnDiskDataProvider {
+        directory: args.octree_directory,
+    };
+    match args.command {
+        Command::Status => {
+            let overlay = OverlayDataProvider::new(Box::new(octree), &args.overlay_directory)?;
+            for node_id in overlay.patched_node_ids()? {
+                let num_deleted_points = overlay
+                    .patch(&node_id)?
+                    .map_or(0, |patch| patch.num_deleted_points());
+                println!("{}: {} deleted points", node_id, num_deleted_points);
+            }
+        }
+        Command::Commit => commit_overlay(&octree, &args.overlay_directory)?,
+        Command::Discard => {
+            OverlayDataProvider::new(Box::new(octree), &args.overlay_directory)?.discard()?
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::errors::*;
+use point_viewer::maintenance::{
+    add_layout_headers, delete_orphaned_files, find_inconsistencies, remove_incomplete_nodes,
+};
+use std::path::PathBuf;
+
+/// Finds node files that are not referenced by the meta of an octree or S2 point cloud, nodes in
+/// the meta whose files are missing, octree nodes whose ancestors are missing, and node files
+/// whose layout header does not match the meta.
+#[derive(Clap, Debug)]
+#[clap(name = "point_cloud_gc")]
+struct CommandlineArguments {
+    /// Directory of the octree or S2 point cloud.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+
+    /// Delete the orphaned node files.
+    #[clap(long)]
+    delete_orphans: bool,
+
+    /// Remove the nodes with missing files and the unreachable ones from the meta.
+    #[clap(long)]
+    repair: bool,
+
+    /// Add the layout header to node files written before there were headers.
+    #[clap(long)]
+    add_layout_headers: bool,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    let report = find_inconsistencies(&args.directory)?;
+    for (path, size) in &report.orphaned_files {
+        println!("Orphaned: {} ({} bytes)", path.display(), size);
+    }
+    for (node, paths) in &report.missing_files {
+        for path in paths {
+            println!("Missing for {}: {}", node, path.display());
+        }
+    }
+    for node in &report.unreachable_nodes {
+        println!("Unreachable: {}", node);
+    }
+    for (path, mismatch) in &report.layout_mismatches {
+        println!("Wrong layout: {}: {}", path.display(), mismatch);
+    }
+    if !report.files_without_layout.is_empty() {
+        println!(
+            "{} node files have no layout header.",
+            report.files_without_layout.len()
+        );
+        if args.add_layout_headers {
+            add_layout_headers(&report)?;
+            println!("Added the layout header to them.");
+        }
+    }
+    if report.is_clean() {
+        println!("No inconsistencies found.");
+        return Ok(());
+    }
+    println!(
+        "{} orphaned files with {} bytes, {} nodes with missing files, {} unreachable nodes, {} \
+         files with the wrong layout.",
+        report.orphaned_files.len(),
+        report.orphaned_bytes(),
+        report.missing_files.len(),
+        report.unreachable_nodes.len(),
+        report.layout_mismatches.len()
+    );
+
+    let num_removed = report.missing_files.len() + report.unreachable_nodes.len();
+    if args.repair && num_removed > 0 {
+        remove_incomplete_nodes(&args.directory, &report)?;
+        println!("Removed {} nodes from the meta.", num_removed);
+    }
+    if args.delete_orphans {
+        // Repairing turns the files of incomplete nodes into orphans, so look again.
+        let report = find_inconsistencies(&args.directory)?;
+        delete_orphaned_files(&report)?;
+        println!(
+            "Deleted {} orphaned files with {} bytes.",
+            report.orphaned_files.len(),
+            report.orphaned_bytes()
+        );
+    }
+    Ok(())
+}
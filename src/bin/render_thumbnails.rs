@@ -0,0 +1,43 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::errors::*;
+use point_viewer::thumbnails::write_thumbnails;
+use std::path::PathBuf;
+
+/// Renders preview images of an octree from the top and from an oblique angle, stores them in
+/// its directory and lists them in its meta, e.g. as a last step after build_octree.
+#[derive(Clap, Debug)]
+#[clap(name = "render_thumbnails")]
+struct CommandlineArguments {
+    /// Directory of the octree.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+
+    /// The length of the longer side of the images in pixels.
+    #[clap(long, default_value = "256")]
+    size: u32,
+}
+
+fn main() -> Result<()> {
+    let args = CommandlineArguments::parse();
+    for thumbnail in write_thumbnails(&args.directory, args.size)? {
+        println!(
+            "Wrote the {} thumbnail, {}x{} pixels.",
+            thumbnail.view, thumbnail.width, thumbnail.height
+        );
+    }
+    Ok(())
+}
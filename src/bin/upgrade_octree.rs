@@ -14,13 +14,8 @@
 
 use clap::Clap;
 use point_viewer::data_provider::{DataProvider, OnDiskDataProvider};
-use point_viewer::octree::NodeId;
-use point_viewer::proto;
-use point_viewer::META_FILENAME;
-use protobuf::Message;
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::{Path, PathBuf};
+use point_viewer::octree::upgrade_meta_proto;
+use std::path::PathBuf;
 
 #[derive(Clap, Debug)]
 #[clap(name = "upgrade_octree")]
@@ -30,82 +25,31 @@ struct CommandlineArguments {
     directory: PathBuf,
 }
 
-fn write_meta(directory: &Path, mut meta: proto::Meta, version: i32) {
-    meta.version = version;
-    let mut buf_writer = BufWriter::new(File::create(&directory.join(META_FILENAME)).unwrap());
-    meta.write_to_writer(&mut buf_writer).unwrap();
-}
-
-fn upgrade_version9(directory: &Path, mut meta: proto::Meta) {
-    eprintln!("Upgrading version 9 => 10.");
-    for node_proto in &mut meta.deprecated_nodes.iter_mut() {
-        let mut id = node_proto.id.as_mut().unwrap();
-        let node_id = NodeId::from_proto(id);
-        id.deprecated_level = 0;
-        id.deprecated_index = 0;
-        *id = node_id.to_proto();
-    }
-    write_meta(directory, meta, 10);
-}
-
-fn upgrade_version10(directory: &Path, mut meta: proto::Meta) {
-    eprintln!("Upgrading version 10 => 11.");
-    let bbox = meta.bounding_box.as_mut().unwrap();
-    let deprecated_min = bbox.take_deprecated_min();
-    bbox.set_min(point_viewer::proto::Vector3d::from(deprecated_min));
-    let deprecated_max = bbox.take_deprecated_max();
-    bbox.set_max(point_viewer::proto::Vector3d::from(deprecated_max));
-    write_meta(directory, meta, 11);
-}
-
-fn upgrade_version11(directory: &Path, mut meta: proto::Meta) {
-    eprintln!("Upgrading version 11 => 12.");
-    let mut octree = proto::OctreeMeta::new();
-
-    octree.set_resolution(meta.deprecated_resolution);
-    meta.deprecated_resolution = 0.0;
-
-    octree.set_nodes(meta.take_deprecated_nodes());
-
-    meta.set_octree(octree);
-    write_meta(directory, meta, 12);
-}
-
-fn upgrade_version12(directory: &Path, mut meta: proto::Meta) {
-    eprintln!("Upgrading version 12 => 13.");
-    if meta.has_octree() {
-        let bounding_box = meta.mut_octree().take_deprecated_bounding_box();
-        meta.set_bounding_box(bounding_box);
-    }
-    write_meta(directory, meta, 13);
-}
-
 fn main() {
     let args = CommandlineArguments::parse();
     let data_provider = OnDiskDataProvider {
-        directory: args.directory.clone(),
+        directory: args.directory,
     };
 
-    loop {
-        let meta = data_provider
-            .meta_proto()
-            .expect("Could not read meta proto.");
-        match meta.version {
-            9 => upgrade_version9(&args.directory, meta),
-            10 => upgrade_version10(&args.directory, meta),
-            11 => upgrade_version11(&args.directory, meta),
-            12 => upgrade_version12(&args.directory, meta),
-            other if other == point_viewer::CURRENT_VERSION => {
-                eprintln!(
-                    "Point cloud at current version {}",
-                    point_viewer::CURRENT_VERSION
-                );
-                break;
-            }
-            other => {
-                eprintln!("Do not know how to upgrade version {}", other);
+    let mut meta = data_provider
+        .meta_proto()
+        .expect("Could not read meta proto.");
+    while meta.version != point_viewer::CURRENT_VERSION {
+        let version = meta.version;
+        meta = match upgrade_meta_proto(meta) {
+            Ok(meta) => meta,
+            Err(_) => {
+                eprintln!("Do not know how to upgrade version {}", version);
                 std::process::exit(1);
             }
-        }
+        };
+        eprintln!("Upgrading version {} => {}.", version, meta.version);
+        data_provider
+            .write_meta_proto(&meta)
+            .expect("Could not write meta proto.");
     }
+    eprintln!(
+        "Point cloud at current version {}",
+        point_viewer::CURRENT_VERSION
+    );
 }
@@ -0,0 +1,141 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Radiometric corrections that even out the colors and intensities of point clouds of the same
+//! area that were captured in different lighting, so that no seams show where they meet.
+
+use crate::attributes::AttributeData;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::proto;
+use crate::PointsBatch;
+use byteorder::{ByteOrder, LittleEndian};
+use nalgebra::Vector3;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RadiometricCorrection {
+    /// Added to the red, green and blue channels, which are in [0, 255].
+    pub color_offset: Vector3<f64>,
+    pub intensity_offset: f64,
+}
+
+fn correct_channel(value: u8, offset: f64) -> u8 {
+    (f64::from(value) + offset).round().max(0.).min(255.) as u8
+}
+
+impl RadiometricCorrection {
+    /// The correction that applies 'other' after this one.
+    pub fn then(&self, other: &RadiometricCorrection) -> Self {
+        RadiometricCorrection {
+            color_offset: self.color_offset + other.color_offset,
+            intensity_offset: self.intensity_offset + other.intensity_offset,
+        }
+    }
+
+    /// Corrects colors with three bytes per point in place.
+    pub fn apply_to_colors(&self, color: &mut [u8]) {
+        for rgb in color.chunks_exact_mut(3) {
+            for (value, offset) in rgb.iter_mut().zip(self.color_offset.iter()) {
+                *value = correct_channel(*value, *offset);
+            }
+        }
+    }
+
+    /// Corrects intensities with one little endian f32 per point in place.
+    pub fn apply_to_intensities(&self, intensity: &mut [u8]) {
+        for bytes in intensity.chunks_exact_mut(4) {
+            let value = f64::from(LittleEndian::read_f32(bytes)) + self.intensity_offset;
+            LittleEndian::write_f32(bytes, value as f32);
+        }
+    }
+
+    /// Corrects the "color" and "intensity" attributes of 'batch', if it has them.
+    pub fn apply_to_batch(&self, batch: &mut PointsBatch) {
+        if let Some(AttributeData::U8Vec3(color)) = batch.attributes.get_mut("color") {
+            for rgb in color.iter_mut() {
+                for (value, offset) in rgb.iter_mut().zip(self.color_offset.iter()) {
+                    *value = correct_channel(*value, *offset);
+                }
+            }
+        }
+        if let Some(AttributeData::F32(intensity)) = batch.attributes.get_mut("intensity") {
+            for value in intensity.iter_mut() {
+                *value = (f64::from(*value) + self.intensity_offset) as f32;
+            }
+        }
+    }
+
+    pub fn from_proto(proto: &proto::RadiometricCorrection) -> Self {
+        RadiometricCorrection {
+            color_offset: Vector3::new(proto.red_offset, proto.green_offset, proto.blue_offset),
+            intensity_offset: proto.intensity_offset,
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::RadiometricCorrection {
+        let mut proto = proto::RadiometricCorrection::new();
+        proto.set_red_offset(self.color_offset.x);
+        proto.set_green_offset(self.color_offset.y);
+        proto.set_blue_offset(self.color_offset.z);
+        proto.set_intensity_offset(self.intensity_offset);
+        proto
+    }
+
+    /// Returns the correction stored in 'meta', if any.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Option<Self> {
+        if meta.has_radiometric_correction() {
+            Some(Self::from_proto(meta.get_radiometric_correction()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Stores 'correction' in the meta file of the point cloud in 'directory', which must exist.
+pub fn write_radiometric_correction(
+    directory: impl AsRef<Path>,
+    correction: &RadiometricCorrection,
+) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    data_provider.update_meta_proto(|meta| meta.set_radiometric_correction(correction.to_proto()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correction_saturates_colors() {
+        let correction = RadiometricCorrection {
+            color_offset: Vector3::new(10., -10., 0.4),
+            intensity_offset: -0.5,
+        };
+        let mut color = vec![250, 5, 100, 0, 20, 255];
+        correction.apply_to_colors(&mut color);
+        assert_eq!(color, vec![255, 0, 100, 10, 10, 255]);
+
+        let mut intensity = vec![0; 4];
+        LittleEndian::write_f32(&mut intensity, 2.);
+        correction.apply_to_intensities(&mut intensity);
+        assert_eq!(LittleEndian::read_f32(&intensity), 1.5);
+
+        assert_eq!(
+            RadiometricCorrection::from_proto(&correction.to_proto()),
+            correction
+        );
+    }
+}
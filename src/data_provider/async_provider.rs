@@ -0,0 +1,183 @@
+use crate::data_provider::{DataProvider, DataSource};
+use crate::errors::*;
+use crate::proto;
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// Like DataProvider, but the data is fetched in the background, so that callers can overlap
+/// fetches and cancel them by dropping their futures.
+pub trait AsyncDataProvider: Send + Sync {
+    fn meta_proto(&self) -> BoxFuture<'static, Result<proto::Meta>>;
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> BoxFuture<'static, Result<HashMap<String, Box<dyn Read + Send>>>>;
+    /// See DataProvider::data_source.
+    fn data_source(&self, _node_id: &str) -> DataSource {
+        DataSource::Network
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads that run blocking tasks, e.g. file reads, for futures. Tasks whose
+/// futures were dropped before they started are skipped, so cancelled work does not pile up.
+pub struct BlockingPool {
+    job_sender: crossbeam::channel::Sender<Job>,
+}
+
+impl BlockingPool {
+    pub fn new(num_threads: usize) -> Self {
+        let (job_sender, job_receiver) = crossbeam::channel::unbounded::<Job>();
+        for _ in 0..num_threads.max(1) {
+            let job_receiver = job_receiver.clone();
+            // The threads end when the pool is dropped.
+            std::thread::spawn(move || {
+                for job in job_receiver {
+                    // A panicking task fails its future, but must not take the thread down.
+                    let _res = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+            });
+        }
+        BlockingPool { job_sender }
+    }
+
+    /// Runs the blocking 'f' on one of the threads. Dropping the future before 'f' started
+    /// skips it, dropping it later discards its result.
+    pub fn spawn<T, F>(&self, f: F) -> BoxFuture<'static, Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job = Box::new(move || {
+            if !sender.is_canceled() {
+                // Fails if the future was dropped in the meantime.
+                let _res = sender.send(f());
+            }
+        });
+        if self.job_sender.send(job).is_err() {
+            return future::ready(Err("The blocking pool has no threads.".into())).boxed();
+        }
+        receiver
+            .map(|result| result.unwrap_or_else(|_| Err("The blocking task panicked.".into())))
+            .boxed()
+    }
+}
+
+/// Makes any DataProvider, e.g. an OnDiskDataProvider, usable as an AsyncDataProvider by running
+/// its calls on a 'BlockingPool'. The data is read into memory there, so the futures never block.
+pub struct BlockingDataProvider {
+    provider: Arc<dyn DataProvider>,
+    pool: BlockingPool,
+}
+
+impl BlockingDataProvider {
+    /// Runs at most 'num_threads' calls to 'provider' at once.
+    pub fn new(provider: Arc<dyn DataProvider>, num_threads: usize) -> Self {
+        BlockingDataProvider {
+            provider,
+            pool: BlockingPool::new(num_threads),
+        }
+    }
+}
+
+impl AsyncDataProvider for BlockingDataProvider {
+    fn meta_proto(&self) -> BoxFuture<'static, Result<proto::Meta>> {
+        let provider = Arc::clone(&self.provider);
+        self.pool.spawn(move || provider.meta_proto())
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> BoxFuture<'static, Result<HashMap<String, Box<dyn Read + Send>>>> {
+        let provider = Arc::clone(&self.provider);
+        let node_id = node_id.to_string();
+        let node_attributes: Vec<String> = node_attributes.iter().map(|a| a.to_string()).collect();
+        self.pool.spawn(move || {
+            let node_attributes: Vec<&str> = node_attributes.iter().map(String::as_str).collect();
+            let mut buffered = HashMap::new();
+            for (name, mut reader) in provider.data(&node_id, &node_attributes)? {
+                let mut data = Vec::new();
+                reader
+                    .read_to_end(&mut data)
+                    .chain_err(|| format!("Could not read {} of node {}", name, node_id))?;
+                buffered.insert(name, Box::new(Cursor::new(data)) as Box<dyn Read + Send>);
+            }
+            Ok(buffered)
+        })
+    }
+
+    fn data_source(&self, node_id: &str) -> DataSource {
+        self.provider.data_source(node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_provider::OnDiskDataProvider;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempdir::TempDir;
+
+    #[test]
+    fn blocking_provider_reads_data() {
+        let directory = TempDir::new("blocking_provider").unwrap();
+        fs::write(directory.path().join("r0.rgb"), &[1u8, 2, 3]).unwrap();
+        let provider = BlockingDataProvider::new(
+            Arc::new(OnDiskDataProvider {
+                directory: directory.path().to_path_buf(),
+            }),
+            2,
+        );
+        assert_eq!(provider.data_source("r0"), DataSource::Disk);
+
+        let mut readers = futures::executor::block_on(provider.data("r0", &["color"])).unwrap();
+        let mut data = Vec::new();
+        readers
+            .get_mut("color")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(futures::executor::block_on(provider.data("r1", &["color"])).is_err());
+    }
+
+    #[test]
+    fn pool_skips_cancelled_tasks() {
+        let pool = BlockingPool::new(1);
+        let (release_sender, release_receiver) = std::sync::mpsc::channel::<()>();
+        let blocking = pool.spawn(move || {
+            release_receiver.recv().unwrap();
+            Ok(1)
+        });
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran_clone = Arc::clone(&ran);
+        let cancelled = pool.spawn(move || {
+            ran_clone.store(true, Ordering::SeqCst);
+            Ok(2)
+        });
+        drop(cancelled);
+        release_sender.send(()).unwrap();
+        assert_eq!(futures::executor::block_on(blocking).unwrap(), 1);
+        assert_eq!(
+            futures::executor::block_on(pool.spawn(|| Ok(3))).unwrap(),
+            3
+        );
+        assert!(!ran.load(Ordering::SeqCst));
+        let panicking = pool.spawn(|| -> Result<()> { panic!("Oops") });
+        assert!(futures::executor::block_on(panicking).is_err());
+        assert_eq!(
+            futures::executor::block_on(pool.spawn(|| Ok(4))).unwrap(),
+            4
+        );
+    }
+}
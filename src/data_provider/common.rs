@@ -3,6 +3,15 @@ use crate::proto;
 use std::collections::HashMap;
 use std::io::Read;
 
+/// Where a data provider reads the data of a node from, for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataSource {
+    /// A local copy of remote data.
+    Cache,
+    Disk,
+    Network,
+}
+
 pub trait DataProvider: Send + Sync {
     fn meta_proto(&self) -> Result<proto::Meta>;
     fn data(
@@ -10,4 +19,14 @@ pub trait DataProvider: Send + Sync {
         node_id: &str,
         node_attributes: &[&str],
     ) -> Result<HashMap<String, Box<dyn Read + Send>>>;
+    /// Where 'data' would read the node from right now. Providers that do not say are assumed to
+    /// be remote.
+    fn data_source(&self, _node_id: &str) -> DataSource {
+        DataSource::Network
+    }
+    /// Hints that 'node_attributes' of 'node_ids' will be requested soon, e.g. for the nodes that
+    /// are about to become visible or that a query will read. Remote providers can start fetching
+    /// them in the background to hide their latency, 'data' returns the same either way. Does
+    /// nothing by default.
+    fn prefetch(&self, _node_ids: &[String], _node_attributes: &[&str]) {}
 }
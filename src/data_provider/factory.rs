@@ -1,23 +1,67 @@
-use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::data_provider::{
+    DataProvider, HttpDataProvider, HttpOptions, OnDiskDataProvider, TieredDataProvider,
+    HTTP_PREFIXES, S3_PREFIX,
+};
 use crate::errors::*;
-use fnv::FnvHashMap;
-use std::path::Path;
+use crate::proto;
+use fnv::{FnvHashMap, FnvHasher};
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
 
 pub type DataProviderFactoryResult = Result<Box<dyn DataProvider>>;
 pub type DataProviderFactoryFunction = fn(&str) -> DataProviderFactoryResult;
 
+/// Locations starting with this prefix are served through a local cache, e.g.
+/// "cache+https://host/octree" or "cache+s3://bucket/octree". The rest of the location can be
+/// anything the factory can generate a data provider for.
+pub const TIERED_CACHE_PREFIX: &str = "cache+";
+
+/// The environment variable that can point to the directory of the local cache.
+pub const TIERED_CACHE_DIR_ENV: &str = "POINT_VIEWER_CACHE_DIR";
+
+const DEFAULT_TIERED_CACHE_SIZE_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+/// Whether 'meta' belongs to an octree rather than an S2 point cloud. S2 point clouds exist since
+/// version 12, older metas are always octrees.
+pub fn is_octree_meta(meta: &proto::Meta) -> bool {
+    meta.version <= 11 || meta.has_octree()
+}
+
 #[derive(Default, Clone)]
 pub struct DataProviderFactory {
     data_provider_fn_map: FnvHashMap<String, DataProviderFactoryFunction>,
+    tiered_cache: Option<(PathBuf, u64)>,
+    http_options: HttpOptions,
 }
 
 impl DataProviderFactory {
     pub fn new() -> Self {
         Self {
             data_provider_fn_map: FnvHashMap::default(),
+            tiered_cache: None,
+            http_options: HttpOptions::default(),
         }
     }
 
+    /// Sets the directory and maximum size of the local cache used for locations prefixed with
+    /// TIERED_CACHE_PREFIX. Without this, the cache lives in $POINT_VIEWER_CACHE_DIR or the
+    /// temporary directory and is bounded to 10 GB.
+    pub fn tiered_cache(
+        mut self,
+        directory: impl Into<PathBuf>,
+        max_size_bytes: u64,
+    ) -> DataProviderFactory {
+        self.tiered_cache = Some((directory.into(), max_size_bytes));
+        self
+    }
+
+    /// Sets the concurrency, retries and S3 endpoint of the data providers for "http://",
+    /// "https://" and "s3://" locations.
+    pub fn http_options(mut self, http_options: HttpOptions) -> DataProviderFactory {
+        self.http_options = http_options;
+        self
+    }
+
     pub fn register(
         mut self,
         prefix: impl Into<String>,
@@ -32,6 +76,9 @@ impl DataProviderFactory {
         data_provider_argument: impl AsRef<str>,
     ) -> DataProviderFactoryResult {
         let data_provider_argument = data_provider_argument.as_ref();
+        if let Some(remote_argument) = data_provider_argument.strip_prefix(TIERED_CACHE_PREFIX) {
+            return self.generate_tiered_data_provider(remote_argument);
+        }
         for (prefix, data_provider_factory_function) in &self.data_provider_fn_map {
             if !data_provider_argument.starts_with(prefix) {
                 continue;
@@ -39,6 +86,17 @@ impl DataProviderFactory {
             return data_provider_factory_function(data_provider_argument);
         }
 
+        if data_provider_argument.starts_with(S3_PREFIX)
+            || HTTP_PREFIXES
+                .iter()
+                .any(|prefix| data_provider_argument.starts_with(prefix))
+        {
+            return Ok(Box::new(HttpDataProvider::new(
+                data_provider_argument,
+                self.http_options.clone(),
+            )?));
+        }
+
         // If no data provider was generated, create it from disk
         if Path::new(data_provider_argument).exists() {
             Ok(Box::new(OnDiskDataProvider {
@@ -52,4 +110,23 @@ impl DataProviderFactory {
             .into())
         }
     }
+
+    fn generate_tiered_data_provider(&self, remote_argument: &str) -> DataProviderFactoryResult {
+        let remote = self.generate_data_provider(remote_argument)?;
+        let (base_directory, max_size_bytes) = self.tiered_cache.clone().unwrap_or_else(|| {
+            let directory = std::env::var_os(TIERED_CACHE_DIR_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("point_viewer_cache"));
+            (directory, DEFAULT_TIERED_CACHE_SIZE_BYTES)
+        });
+        // Every remote location gets its own cache directory, so that node names do not clash.
+        let mut hasher = FnvHasher::default();
+        hasher.write(remote_argument.as_bytes());
+        let directory = base_directory.join(format!("{:016x}", hasher.finish()));
+        Ok(Box::new(TieredDataProvider::new(
+            remote,
+            directory,
+            max_size_bytes,
+        )?))
+    }
 }
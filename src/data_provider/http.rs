@@ -0,0 +1,508 @@
+use crate::attribute_extension;
+use crate::data_provider::DataProvider;
+use crate::errors::*;
+use crate::proto;
+use crate::META_FILENAME;
+use lru::LruCache;
+use protobuf::Message;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Locations starting with these prefixes are read over HTTP. The files of the point cloud are
+/// expected below the URL with the same names as on disk, e.g. "<url>/meta.pb" and
+/// "<url>/r0.xyz".
+pub const HTTP_PREFIXES: [&str; 2] = ["http://", "https://"];
+
+/// Locations starting with this prefix, like "s3://bucket/prefix", are read from an
+/// S3-compatible object store, see HttpOptions::s3_endpoint.
+pub const S3_PREFIX: &str = "s3://";
+
+/// The environment variable that can point to the endpoint of the S3-compatible object store.
+pub const S3_ENDPOINT_ENV: &str = "POINT_VIEWER_S3_ENDPOINT";
+
+const DEFAULT_S3_ENDPOINT: &str = "https://s3.amazonaws.com";
+
+/// The most prefetched files that are kept until they are read. The least recently prefetched
+/// ones are dropped beyond that.
+const MAX_PREFETCHED_FILES: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// The maximum number of requests that each data provider has in flight.
+    pub max_concurrent_requests: usize,
+    /// How often a failed request is retried. Requests that the server rejects as invalid, e.g.
+    /// for files that do not exist, are not retried.
+    pub max_retries: usize,
+    /// The delay before the first retry, which doubles with every further one.
+    pub retry_delay: Duration,
+    pub timeout: Duration,
+    /// Where "s3://bucket/prefix" is requested from, as "<endpoint>/bucket/prefix". Defaults to
+    /// $POINT_VIEWER_S3_ENDPOINT or else Amazon S3. Requests are not signed, so the objects must
+    /// be readable anonymously.
+    pub s3_endpoint: Option<String>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            max_concurrent_requests: 16,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(100),
+            timeout: Duration::from_secs(30),
+            s3_endpoint: None,
+        }
+    }
+}
+
+/// Limits the number of requests in flight.
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+enum Prefetched {
+    /// Waiting for a prefetching thread.
+    Queued,
+    InFlight,
+    Fetched(Vec<u8>),
+}
+
+/// Files that were fetched before they were read, see 'DataProvider::prefetch'.
+struct PrefetchedFiles {
+    files: Mutex<PrefetchState>,
+    fetched: Condvar,
+}
+
+struct PrefetchState {
+    files: LruCache<String, Prefetched>,
+    /// Counts how often the files were cleared, so that fetches which were started before do
+    /// not keep outdated data.
+    epoch: u64,
+}
+
+impl PrefetchedFiles {
+    fn new() -> Self {
+        PrefetchedFiles {
+            files: Mutex::new(PrefetchState {
+                files: LruCache::new(MAX_PREFETCHED_FILES),
+                epoch: 0,
+            }),
+            fetched: Condvar::new(),
+        }
+    }
+
+    /// Queues the file for prefetching. False if it already is queued, being fetched or fetched.
+    fn queue(&self, file_name: &str) -> bool {
+        let file_name = file_name.to_string();
+        let mut state = self.files.lock().unwrap();
+        if state.files.contains(&file_name) {
+            return false;
+        }
+        state.files.put(file_name, Prefetched::Queued);
+        true
+    }
+
+    /// Marks the queued file as being fetched by the calling thread and returns the epoch to
+    /// finish it with. None if the file is no longer queued, e.g. because it was read meanwhile.
+    fn start(&self, file_name: &str) -> Option<u64> {
+        let mut state = self.files.lock().unwrap();
+        match state.files.peek_mut(&file_name.to_string()) {
+            Some(prefetched @ Prefetched::Queued) => *prefetched = Prefetched::InFlight,
+            _ => return None,
+        }
+        Some(state.epoch)
+    }
+
+    /// Keeps the fetched file until it is read, unless the files were cleared since the fetch
+    /// started. Failures are forgotten, so that reading the file fetches it again and reports
+    /// the error.
+    fn finish(&self, file_name: &str, epoch: u64, data: Result<Vec<u8>>) {
+        let file_name = file_name.to_string();
+        let mut state = self.files.lock().unwrap();
+        if state.epoch == epoch {
+            match data {
+                Ok(data) => {
+                    state.files.put(file_name, Prefetched::Fetched(data));
+                }
+                Err(_) => {
+                    state.files.pop(&file_name);
+                }
+            }
+        }
+        self.fetched.notify_all();
+    }
+
+    /// Takes the prefetched file, waiting for it if it is being fetched. None if it was not
+    /// prefetched or is still queued, then it is not prefetched anymore.
+    fn take(&self, file_name: &str) -> Option<Vec<u8>> {
+        let file_name = file_name.to_string();
+        let mut state = self.files.lock().unwrap();
+        loop {
+            match state.files.pop(&file_name) {
+                Some(Prefetched::Fetched(data)) => return Some(data),
+                Some(Prefetched::InFlight) => {
+                    state.files.put(file_name.clone(), Prefetched::InFlight);
+                    state = self.fetched.wait(state).unwrap();
+                }
+                Some(Prefetched::Queued) | None => return None,
+            }
+        }
+    }
+
+    /// Drops all files, e.g. because the point cloud was updated.
+    fn clear(&self) {
+        let mut state = self.files.lock().unwrap();
+        state.files.clear();
+        state.epoch += 1;
+        self.fetched.notify_all();
+    }
+}
+
+/// What the requests of a provider share, also with the threads that prefetch files.
+struct Remote {
+    base_url: String,
+    options: HttpOptions,
+    agent: ureq::Agent,
+    in_flight: Semaphore,
+}
+
+pub struct HttpDataProvider {
+    remote: Arc<Remote>,
+    prefetched: Arc<PrefetchedFiles>,
+    /// The queue of the threads that prefetch files, which are started by the first prefetch.
+    prefetch_queue: Mutex<Option<crossbeam::channel::Sender<String>>>,
+    /// The generation of the meta that the prefetched files belong to.
+    generation: Mutex<Option<u64>>,
+}
+
+impl HttpDataProvider {
+    /// 'location' is an "http://", "https://" or "s3://" URL.
+    pub fn new(location: &str, options: HttpOptions) -> Result<Self> {
+        let base_url = if let Some(path) = location.strip_prefix(S3_PREFIX) {
+            let endpoint = options
+                .s3_endpoint
+                .clone()
+                .or_else(|| std::env::var(S3_ENDPOINT_ENV).ok())
+                .unwrap_or_else(|| DEFAULT_S3_ENDPOINT.to_string());
+            format!("{}/{}", endpoint.trim_end_matches('/'), path)
+        } else if HTTP_PREFIXES
+            .iter()
+            .any(|prefix| location.starts_with(prefix))
+        {
+            location.to_string()
+        } else {
+            return Err(ErrorKind::InvalidInput(format!("'{}' is not a URL.", location)).into());
+        };
+        Ok(HttpDataProvider {
+            remote: Arc::new(Remote {
+                base_url: base_url.trim_end_matches('/').to_string(),
+                in_flight: Semaphore {
+                    available: Mutex::new(options.max_concurrent_requests.max(1)),
+                    released: Condvar::new(),
+                },
+                options,
+                agent: ureq::agent(),
+            }),
+            prefetched: Arc::new(PrefetchedFiles::new()),
+            prefetch_queue: Mutex::new(None),
+            generation: Mutex::new(None),
+        })
+    }
+
+    /// Starts the threads that prefetch files and returns their queue. They end when the
+    /// provider is dropped.
+    fn start_prefetching(&self) -> crossbeam::channel::Sender<String> {
+        // Prefetching uses at most half of the requests in flight, so that reads that cannot wait
+        // do not queue up behind it.
+        let num_threads = (self.remote.options.max_concurrent_requests / 2).max(1);
+        let (sender, receiver) = crossbeam::channel::unbounded::<String>();
+        for _ in 0..num_threads {
+            let remote = Arc::clone(&self.remote);
+            let prefetched = Arc::clone(&self.prefetched);
+            let receiver = receiver.clone();
+            std::thread::spawn(move || {
+                for file_name in receiver {
+                    if let Some(epoch) = prefetched.start(&file_name) {
+                        prefetched.finish(&file_name, epoch, remote.fetch(&file_name));
+                    }
+                }
+            });
+        }
+        sender
+    }
+
+    /// The file with the given name, prefetched or else downloaded now.
+    fn read(&self, file_name: &str) -> Result<Vec<u8>> {
+        match self.prefetched.take(file_name) {
+            Some(data) => Ok(data),
+            None => self.remote.fetch(file_name),
+        }
+    }
+}
+
+impl Remote {
+    /// Downloads the file with the given name, retrying on server and network errors.
+    fn fetch(&self, file_name: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/{}", self.base_url, file_name);
+        let _permit = self.in_flight.acquire();
+        let mut delay = self.options.retry_delay;
+        let mut num_retries = 0;
+        loop {
+            let response = self.agent.get(&url).timeout(self.options.timeout).call();
+            let status = response.status();
+            let transport_error = response.synthetic_error().as_ref().map(|e| e.to_string());
+            let error = match transport_error {
+                Some(error) => error,
+                None if response.ok() => {
+                    let mut data = Vec::new();
+                    match response.into_reader().read_to_end(&mut data) {
+                        Ok(_) => return Ok(data),
+                        Err(err) => err.to_string(),
+                    }
+                }
+                None if status == 404 => return Err(ErrorKind::NodeNotFound.into()),
+                // Too Many Requests is worth retrying, other client errors are not.
+                None if (400..500).contains(&status) && status != 429 => {
+                    return Err(format!("{} returned status {}.", url, status).into());
+                }
+                None => format!("status {}", status),
+            };
+            if num_retries >= self.options.max_retries {
+                return Err(format!("Could not fetch {}: {}", url, error).into());
+            }
+            num_retries += 1;
+            std::thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+}
+
+impl DataProvider for HttpDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        // The meta is never prefetched, it changes when the point cloud is updated.
+        let data = self
+            .remote
+            .fetch(META_FILENAME)
+            .chain_err(|| format!("Could not read {}", META_FILENAME))?;
+        let meta = proto::Meta::parse_from_bytes(&data)
+            .chain_err(|| format!("Could not parse {}", META_FILENAME))?;
+        // Files that were prefetched for another generation may have been rewritten since.
+        let mut generation = self.generation.lock().unwrap();
+        if *generation != Some(meta.generation) {
+            self.prefetched.clear();
+            *generation = Some(meta.generation);
+        }
+        Ok(meta)
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        // The attributes are fetched in parallel, within the limit of requests in flight.
+        let fetched = crossbeam::scope(|s| {
+            let handles: Vec<_> = node_attributes
+                .iter()
+                .map(|attribute| {
+                    let file_name = format!("{}.{}", node_id, attribute_extension(attribute));
+                    s.spawn(move |_| self.read(&file_name))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Fetching thread panicked."))
+                .collect::<Vec<_>>()
+        })
+        .expect("Fetching thread panicked.");
+        let mut readers = HashMap::<String, Box<dyn Read + Send>>::new();
+        for (attribute, data) in node_attributes.iter().zip(fetched) {
+            readers.insert((*attribute).to_string(), Box::new(Cursor::new(data?)));
+        }
+        Ok(readers)
+    }
+
+    fn prefetch(&self, node_ids: &[String], node_attributes: &[&str]) {
+        let file_names: Vec<String> = node_ids
+            .iter()
+            .flat_map(|node_id| {
+                node_attributes
+                    .iter()
+                    .map(move |attribute| format!("{}.{}", node_id, attribute_extension(attribute)))
+            })
+            // Files further down the list would only push the first ones out before they are read.
+            .take(MAX_PREFETCHED_FILES)
+            .filter(|file_name| self.prefetched.queue(file_name))
+            .collect();
+        if file_names.is_empty() {
+            return;
+        }
+        let mut prefetch_queue = self.prefetch_queue.lock().unwrap();
+        let sender = prefetch_queue.get_or_insert_with(|| self.start_prefetching());
+        for file_name in file_names {
+            // The threads only end with the provider.
+            sender.send(file_name).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serves the given responses to the next requests, one each, and returns the base URL.
+    fn serve(responses: Vec<(u16, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/octree", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                // Skips the request up to the empty line after the headers.
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let url = serve(vec![
+            (503, Vec::new()),
+            (200, vec![1, 2, 3]),
+            (404, Vec::new()),
+        ]);
+        let options = HttpOptions {
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let provider = HttpDataProvider::new(&url, options).unwrap();
+        let mut data = Vec::new();
+        provider
+            .data("r0", &["color"])
+            .unwrap()
+            .get_mut("color")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(provider.data("r1", &["color"]).is_err());
+    }
+
+    #[test]
+    fn reads_prefetched_files() {
+        // The second request for the prefetched file would fail.
+        let url = serve(vec![(200, vec![1, 2, 3]), (404, Vec::new())]);
+        let provider = HttpDataProvider::new(&url, HttpOptions::default()).unwrap();
+        provider.prefetch(&["r0".to_string()], &["color"]);
+        provider.prefetch(&["r0".to_string()], &["color"]);
+        let mut data = Vec::new();
+        provider
+            .data("r0", &["color"])
+            .unwrap()
+            .get_mut("color")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(provider.data("r0", &["color"]).is_err());
+    }
+
+    #[test]
+    fn reads_queued_files_directly() {
+        let prefetched = PrefetchedFiles::new();
+        assert!(prefetched.queue("r0.rgb"));
+        assert!(!prefetched.queue("r0.rgb"));
+        // The file is not waited for, and no thread fetches it anymore.
+        assert_eq!(prefetched.take("r0.rgb"), None);
+        assert_eq!(prefetched.start("r0.rgb"), None);
+    }
+
+    #[test]
+    fn drops_prefetched_files_of_other_generations() {
+        let meta = |generation| {
+            let mut meta = proto::Meta::new();
+            meta.generation = generation;
+            meta.write_to_bytes().unwrap()
+        };
+        let url = serve(vec![
+            (200, meta(1)),
+            (200, vec![1, 2, 3]),
+            (200, meta(2)),
+            (200, vec![4, 5, 6]),
+        ]);
+        let provider = HttpDataProvider::new(&url, HttpOptions::default()).unwrap();
+        provider.meta_proto().unwrap();
+        provider.prefetch(&["r0".to_string()], &["color"]);
+        while provider
+            .prefetched
+            .files
+            .lock()
+            .unwrap()
+            .files
+            .iter()
+            .any(|(_, prefetched)| !matches!(prefetched, Prefetched::Fetched(_)))
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        provider.meta_proto().unwrap();
+        let mut data = Vec::new();
+        provider
+            .data("r0", &["color"])
+            .unwrap()
+            .get_mut("color")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn maps_s3_locations_to_the_endpoint() {
+        let options = HttpOptions {
+            s3_endpoint: Some("http://localhost:9000/".to_string()),
+            ..Default::default()
+        };
+        let provider = HttpDataProvider::new("s3://bucket/octree/", options).unwrap();
+        assert_eq!(
+            provider.remote.base_url,
+            "http://localhost:9000/bucket/octree"
+        );
+        assert!(HttpDataProvider::new("/tmp/octree", HttpOptions::default()).is_err());
+    }
+}
@@ -1,7 +1,20 @@
+mod async_provider;
 mod common;
 mod factory;
+mod http;
 mod on_disk;
+mod overlay;
+mod reencoding;
+mod tiered;
 
-pub use common::DataProvider;
-pub use factory::{DataProviderFactory, DataProviderFactoryResult};
+pub use async_provider::{AsyncDataProvider, BlockingDataProvider, BlockingPool};
+pub use common::{DataProvider, DataSource};
+pub use factory::{
+    is_octree_meta, DataProviderFactory, DataProviderFactoryResult, TIERED_CACHE_DIR_ENV,
+    TIERED_CACHE_PREFIX,
+};
+pub use http::{HttpDataProvider, HttpOptions, HTTP_PREFIXES, S3_ENDPOINT_ENV, S3_PREFIX};
 pub use on_disk::OnDiskDataProvider;
+pub use overlay::{commit_overlay, NodePatch, OverlayDataProvider};
+pub use reencoding::ReencodingDataProvider;
+pub use tiered::TieredDataProvider;
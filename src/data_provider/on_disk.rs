@@ -1,11 +1,13 @@
 use crate::attribute_extension;
-use crate::data_provider::DataProvider;
+use crate::data_provider::{DataProvider, DataSource};
 use crate::errors::*;
 use crate::proto;
+use crate::read_write::{read_layout_header_of_file, Encoding, LAYOUT_HEADER_LEN};
 use crate::META_FILENAME;
+use protobuf::Message;
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Cursor, Read};
+use std::io::{BufWriter, Cursor, Read, Write};
 use std::path::PathBuf;
 
 pub struct OnDiskDataProvider {
@@ -18,18 +20,48 @@ impl OnDiskDataProvider {
         self.directory.join(node_id)
     }
 
-    // Get number of points from the file size of the color data.
-    // Color data is required and always present.
-    pub fn number_of_points(&self, node_id: &str) -> Result<i64> {
-        let stem = self.stem(node_id);
-        let file_meta_data_opt = fs::metadata(stem.with_extension(attribute_extension("color")));
+    // Get number of points from the file size of the position data, which is the only attribute
+    // that every node has.
+    pub fn number_of_points(&self, node_id: &str, encoding: &Encoding) -> Result<i64> {
+        let path = self
+            .stem(node_id)
+            .with_extension(attribute_extension("position"));
+        let file_meta_data_opt = fs::metadata(&path);
         if file_meta_data_opt.is_err() {
             return Err(ErrorKind::NodeNotFound.into());
         }
 
-        let file_size_bytes = file_meta_data_opt.unwrap().len();
-        // color has 3 bytes per point
-        Ok((file_size_bytes / 3) as i64)
+        let mut file_size_bytes = file_meta_data_opt.unwrap().len();
+        if read_layout_header_of_file(&path)?.is_some() {
+            file_size_bytes -= LAYOUT_HEADER_LEN as u64;
+        }
+        Ok((file_size_bytes / encoding.bytes_per_position() as u64) as i64)
+    }
+
+    /// Reads the meta proto, lets 'update' modify it and writes it back with the next
+    /// generation number.
+    pub fn update_meta_proto(&self, update: impl FnOnce(&mut proto::Meta)) -> Result<()> {
+        let mut meta = self.meta_proto()?;
+        let generation = meta.generation;
+        update(&mut meta);
+        meta.set_generation(generation + 1);
+        self.write_meta_proto(&meta)
+    }
+
+    /// Replaces the meta proto atomically: it is written to a temporary file that is then renamed,
+    /// so concurrent readers see either the old or the new meta, never a partial one. Writers that
+    /// modify a point cloud that is being served must write the files of new nodes before and
+    /// must not change the files of existing nodes in place.
+    pub fn write_meta_proto(&self, meta: &proto::Meta) -> Result<()> {
+        let path = self.directory.join(META_FILENAME);
+        let tmp_path = path.with_extension("pb.tmp");
+        {
+            let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
+            meta.write_to_writer(&mut buf_writer)
+                .chain_err(|| format!("Could not write {}", META_FILENAME))?;
+            buf_writer.flush()?;
+        }
+        fs::rename(&tmp_path, &path).chain_err(|| format!("Could not replace {}", META_FILENAME))
     }
 }
 
@@ -66,4 +98,8 @@ impl DataProvider for OnDiskDataProvider {
         }
         Ok(readers)
     }
+
+    fn data_source(&self, _node_id: &str) -> DataSource {
+        DataSource::Disk
+    }
 }
@@ -0,0 +1,437 @@
+use crate::data_provider::{DataProvider, DataSource, OnDiskDataProvider};
+use crate::errors::*;
+use crate::octree::{NodeId, Occupancy};
+use crate::proto;
+use crate::read_write::{split_layout_header, LAYOUT_HEADER_LEN};
+use protobuf::Message;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// Extension of the files holding the edits of one node.
+const PATCH_EXTENSION: &str = "patch";
+
+/// The edits of one node: deleted points and new attribute values. Point indices always refer to
+/// the unpatched node.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodePatch {
+    deleted_points: BTreeSet<usize>,
+    /// The new encoded value of each changed point, per attribute.
+    attributes: BTreeMap<String, BTreeMap<usize, Vec<u8>>>,
+}
+
+impl NodePatch {
+    pub fn is_empty(&self) -> bool {
+        self.deleted_points.is_empty() && self.attributes.is_empty()
+    }
+
+    pub fn num_deleted_points(&self) -> usize {
+        self.deleted_points.len()
+    }
+
+    pub fn delete_point(&mut self, index: usize) {
+        self.deleted_points.insert(index);
+    }
+
+    /// Sets the value of 'attribute' of the point at 'index'. The value must be little endian
+    /// encoded like the attribute data of the node, e.g. three bytes for a color.
+    pub fn set_attribute(&mut self, attribute: &str, index: usize, value: Vec<u8>) {
+        self.attributes
+            .entry(attribute.to_string())
+            .or_default()
+            .insert(index, value);
+    }
+
+    /// Applies the edits to 'data', the encoded values of 'attribute' for all 'num_points' points
+    /// of the unpatched node.
+    pub fn apply(&self, attribute: &str, data: &[u8], num_points: usize) -> Result<Vec<u8>> {
+        if num_points == 0 || data.is_empty() {
+            return Ok(data.to_vec());
+        }
+        if data.len() % num_points != 0 {
+            return Err(ErrorKind::InvalidInput(format!(
+                "{} bytes of {} do not divide into {} points",
+                data.len(),
+                attribute,
+                num_points
+            ))
+            .into());
+        }
+        let size = data.len() / num_points;
+        let changed = self.attributes.get(attribute);
+        let mut patched = Vec::with_capacity(data.len());
+        for (index, value) in data.chunks_exact(size).enumerate() {
+            if self.deleted_points.contains(&index) {
+                continue;
+            }
+            match changed.and_then(|values| values.get(&index)) {
+                Some(new_value) if new_value.len() == size => patched.extend(new_value),
+                Some(new_value) => {
+                    return Err(ErrorKind::InvalidInput(format!(
+                        "Value of {} for point {} has {} bytes, expected {}",
+                        attribute,
+                        index,
+                        new_value.len(),
+                        size
+                    ))
+                    .into())
+                }
+                None => patched.extend(value),
+            }
+        }
+        Ok(patched)
+    }
+
+    pub fn from_proto(proto: &proto::NodePatch) -> Result<Self> {
+        let mut patch = NodePatch::default();
+        for index in proto.get_deleted_points() {
+            patch.delete_point(*index as usize);
+        }
+        for attribute_patch in proto.get_attribute_patches() {
+            let points = attribute_patch.get_points();
+            let values = attribute_patch.get_values();
+            if points.is_empty() {
+                continue;
+            }
+            if values.len() % points.len() != 0 {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Patch of {} has an invalid number of bytes",
+                    attribute_patch.get_attribute()
+                ))
+                .into());
+            }
+            let size = values.len() / points.len();
+            for (index, value) in points.iter().zip(values.chunks_exact(size)) {
+                patch.set_attribute(
+                    attribute_patch.get_attribute(),
+                    *index as usize,
+                    value.to_vec(),
+                );
+            }
+        }
+        Ok(patch)
+    }
+
+    pub fn to_proto(&self) -> proto::NodePatch {
+        let mut proto = proto::NodePatch::new();
+        proto.set_deleted_points(self.deleted_points.iter().map(|i| *i as u64).collect());
+        for (attribute, values) in &self.attributes {
+            let mut attribute_patch = proto::AttributePatch::new();
+            attribute_patch.set_attribute(attribute.clone());
+            attribute_patch.set_points(values.keys().map(|i| *i as u64).collect());
+            attribute_patch.set_values(values.values().flatten().copied().collect());
+            proto.mut_attribute_patches().push(attribute_patch);
+        }
+        proto
+    }
+}
+
+/// The inverse of 'attribute_extension'.
+fn attribute_for_extension(extension: &str) -> &str {
+    match extension {
+        "xyz" => "position",
+        "rgb" => "color",
+        _ => extension,
+    }
+}
+
+/// Serves the octree of 'base' with the edits stored in a separate directory applied, so that
+/// read-only data can be edited non-destructively. Deleted points are also removed from the point
+/// counts in the meta. Use 'commit_overlay' to make the edits permanent and 'discard' to drop
+/// them.
+pub struct OverlayDataProvider {
+    base: Box<dyn DataProvider>,
+    directory: PathBuf,
+    /// The number of points of each node of the unpatched octree.
+    num_points: HashMap<String, usize>,
+}
+
+impl OverlayDataProvider {
+    pub fn new(base: Box<dyn DataProvider>, directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        let meta = base.meta_proto()?;
+        if meta.has_octree() && !meta.get_octree().get_interleaved_attributes().is_empty() {
+            return Err(ErrorKind::InvalidInput(
+                "Octrees with interleaved attributes cannot be edited. Run `interleave_octree \
+                 --separate` on it first."
+                    .to_string(),
+            )
+            .into());
+        }
+        let num_points = octree_nodes(&meta)
+            .iter()
+            .map(|node| {
+                (
+                    NodeId::from_proto(node.get_id()).to_string(),
+                    node.num_points as usize,
+                )
+            })
+            .collect();
+        Ok(Self {
+            base,
+            directory,
+            num_points,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn patch_path(&self, node_id: &str) -> PathBuf {
+        self.directory.join(node_id).with_extension(PATCH_EXTENSION)
+    }
+
+    /// Returns the edits of the node, None if it has not been edited.
+    pub fn patch(&self, node_id: &str) -> Result<Option<NodePatch>> {
+        let mut data = Vec::new();
+        match File::open(self.patch_path(node_id)) {
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            file => file?.read_to_end(&mut data)?,
+        };
+        let proto = protobuf::parse_from_reader::<proto::NodePatch>(&mut Cursor::new(data))
+            .chain_err(|| format!("Could not parse patch of {}", node_id))?;
+        Ok(Some(NodePatch::from_proto(&proto)?))
+    }
+
+    /// Replaces the edits of the node. An empty patch removes them.
+    pub fn set_patch(&self, node_id: &str, patch: &NodePatch) -> Result<()> {
+        let path = self.patch_path(node_id);
+        if patch.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result.map_err(Error::from),
+            };
+        }
+        if !self.num_points.contains_key(node_id) {
+            return Err(ErrorKind::NodeNotFound.into());
+        }
+        let mut buf_writer = BufWriter::new(File::create(&path)?);
+        patch
+            .to_proto()
+            .write_to_writer(&mut buf_writer)
+            .chain_err(|| format!("Could not write patch of {}", node_id))
+    }
+
+    /// The ids of all edited nodes.
+    pub fn patched_node_ids(&self) -> Result<Vec<String>> {
+        let mut node_ids = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PATCH_EXTENSION) {
+                continue;
+            }
+            if let Some(node_id) = path.file_stem().and_then(|s| s.to_str()) {
+                node_ids.push(node_id.to_string());
+            }
+        }
+        node_ids.sort();
+        Ok(node_ids)
+    }
+
+    /// Drops all edits.
+    pub fn discard(self) -> Result<()> {
+        fs::remove_dir_all(&self.directory)
+            .chain_err(|| format!("Could not remove {}", self.directory.display()))
+    }
+}
+
+fn octree_nodes(meta: &proto::Meta) -> &[proto::OctreeNode] {
+    if meta.has_octree() {
+        meta.get_octree().get_nodes()
+    } else {
+        meta.get_deprecated_nodes()
+    }
+}
+
+impl DataProvider for OverlayDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        let mut meta = self.base.meta_proto()?;
+        let nodes = if meta.has_octree() {
+            meta.mut_octree().mut_nodes()
+        } else {
+            &mut meta.deprecated_nodes
+        };
+        let mut points_deleted = false;
+        for node in nodes.iter_mut() {
+            let node_id = NodeId::from_proto(node.get_id()).to_string();
+            if let Some(patch) = self.patch(&node_id)? {
+                node.num_points -= patch.num_deleted_points() as i64;
+                points_deleted |= patch.num_deleted_points() > 0;
+                // The statistics no longer hold, so they must not be used to skip the node.
+                node.clear_label_counts();
+                node.clear_label_attribute();
+                node.clear_attribute_ranges();
+            }
+        }
+        if points_deleted && meta.has_octree() {
+            meta.mut_octree().points_deleted = true;
+        }
+        Ok(meta)
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let readers = self.base.data(node_id, node_attributes)?;
+        let patch = match self.patch(node_id)? {
+            Some(patch) => patch,
+            None => return Ok(readers),
+        };
+        let num_points = *self
+            .num_points
+            .get(node_id)
+            .ok_or(ErrorKind::NodeNotFound)?;
+        let mut patched = HashMap::<String, Box<dyn Read + Send>>::new();
+        for (attribute, mut reader) in readers {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            // The patched data has no layout header, like files from before there were headers.
+            let (_, data) = split_layout_header(&data)?;
+            let data = patch.apply(&attribute, data, num_points)?;
+            patched.insert(attribute, Box::new(Cursor::new(data)));
+        }
+        Ok(patched)
+    }
+
+    fn data_source(&self, node_id: &str) -> DataSource {
+        self.base.data_source(node_id)
+    }
+
+    fn prefetch(&self, node_ids: &[String], node_attributes: &[&str]) {
+        self.base.prefetch(node_ids, node_attributes)
+    }
+}
+
+/// The files of 'node_id' in 'directory' with their extensions.
+fn node_files(directory: &Path, node_id: &str) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.file_stem().and_then(|s| s.to_str()) != Some(node_id) {
+            continue;
+        }
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            let extension = extension.to_string();
+            files.push((path, extension));
+        }
+    }
+    Ok(files)
+}
+
+/// Removes the nodes of an octree meta that are left without points and without children, so
+/// that readers never see them. The root is kept. Returns the names of the removed nodes.
+fn remove_empty_nodes(meta: &mut proto::Meta) -> Vec<String> {
+    if !meta.has_octree() {
+        return Vec::new();
+    }
+    let octree = meta.mut_octree();
+    let mut nodes: Vec<(NodeId, i64)> = octree
+        .get_nodes()
+        .iter()
+        .map(|node| (NodeId::from_proto(node.get_id()), node.num_points))
+        .collect();
+    // Children come first, so that parents which only had empty children are removed too.
+    nodes.sort_by_key(|(node_id, _)| Reverse(node_id.level()));
+    let mut remaining: HashSet<NodeId> = nodes.iter().map(|(node_id, _)| *node_id).collect();
+    let mut removed = Vec::new();
+    for (node_id, num_points) in nodes {
+        if num_points == 0
+            && node_id.level() > 0
+            && !node_id.children().any(|child| remaining.contains(&child))
+        {
+            remaining.remove(&node_id);
+            removed.push(node_id.to_string());
+        }
+    }
+    if removed.is_empty() {
+        return removed;
+    }
+    let kept_nodes = octree
+        .take_nodes()
+        .into_iter()
+        .filter(|node| remaining.contains(&NodeId::from_proto(node.get_id())))
+        .collect();
+    octree.set_nodes(kept_nodes);
+    // Octrees built before the occupancy was stored in the meta have none.
+    if !octree.get_occupancy().is_empty() {
+        octree.set_occupancy(::protobuf::RepeatedField::from_vec(
+            Occupancy::from_node_ids(&remaining).to_proto(),
+        ));
+    }
+    removed
+}
+
+/// Makes the edits in 'overlay_directory' permanent by rewriting the edited nodes and the meta of
+/// the octree in 'octree', then removes the overlay. Nodes that are left without points and
+/// without children are removed with their files. This is not atomic, an interrupted commit
+/// leaves the octree in an inconsistent state.
+pub fn commit_overlay(octree: &OnDiskDataProvider, overlay_directory: &Path) -> Result<()> {
+    let base = OnDiskDataProvider {
+        directory: octree.directory.clone(),
+    };
+    let overlay = OverlayDataProvider::new(Box::new(base), overlay_directory)?;
+    let mut meta = overlay.meta_proto()?;
+    let removed_nodes = remove_empty_nodes(&mut meta);
+    for node_id in overlay.patched_node_ids()? {
+        if removed_nodes.contains(&node_id) {
+            continue;
+        }
+        let patch = overlay
+            .patch(&node_id)?
+            .ok_or_else(|| format!("Patch of {} disappeared", node_id))?;
+        let num_points = *overlay
+            .num_points
+            .get(&node_id)
+            .ok_or(ErrorKind::NodeNotFound)?;
+        for (path, extension) in node_files(&octree.directory, &node_id)? {
+            let attribute = attribute_for_extension(&extension);
+            let mut data = fs::read(&path)?;
+            // Keeps the layout header, if the file has one.
+            let header_len = match split_layout_header(&data)? {
+                (Some(_), _) => LAYOUT_HEADER_LEN,
+                (None, _) => 0,
+            };
+            let patched = patch.apply(attribute, &data[header_len..], num_points)?;
+            data.truncate(header_len);
+            data.extend(patched);
+            fs::write(&path, data)?;
+        }
+    }
+    octree.update_meta_proto(|m| *m = meta)?;
+    // Only once the meta no longer refers to them.
+    for node_id in &removed_nodes {
+        for (path, _) in node_files(&octree.directory, node_id)? {
+            fs::remove_file(&path)?;
+        }
+    }
+    overlay.discard()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_patch() {
+        let mut patch = NodePatch::default();
+        patch.delete_point(1);
+        patch.set_attribute("color", 2, vec![7, 8, 9]);
+        patch.set_attribute("class", 0, vec![5]);
+        let color = [0, 0, 0, 1, 1, 1, 2, 2, 2];
+        assert_eq!(
+            patch.apply("color", &color, 3).unwrap(),
+            vec![0, 0, 0, 7, 8, 9]
+        );
+        assert_eq!(patch.apply("class", &[1, 2, 3], 3).unwrap(), vec![5, 3]);
+        assert!(patch.apply("class", &[1, 2], 3).is_err());
+
+        let roundtrip = NodePatch::from_proto(&patch.to_proto()).unwrap();
+        assert_eq!(patch, roundtrip);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::data_provider::{DataProvider, DataSource};
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::octree::{upgrade_meta_proto_to_current, NodeId};
+use crate::proto;
+use crate::read_write::{
+    vec3_encode, vec3_fixpoint_encode, Encoding, PositionEncoding, RawNodeReader,
+};
+use byteorder::{LittleEndian, WriteBytesExt};
+use nalgebra::Point3;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// How the positions of a node are stored by the wrapped provider.
+struct NodeEncoding {
+    encoding: Encoding,
+    num_points: usize,
+}
+
+/// Serves the octree of 'inner' with all node positions re-encoded to 'position_encoding', e.g.
+/// Float32 for clients that can't do fixpoint decoding. Only the requested attributes are
+/// returned. The conversion happens where the data is local, instead of on the client.
+pub struct ReencodingDataProvider {
+    inner: Box<dyn DataProvider>,
+    position_encoding: PositionEncoding,
+    nodes: HashMap<String, NodeEncoding>,
+}
+
+impl ReencodingDataProvider {
+    pub fn new(inner: Box<dyn DataProvider>, position_encoding: PositionEncoding) -> Result<Self> {
+        let meta = upgrade_meta_proto_to_current(inner.meta_proto()?)?;
+        if !meta.has_octree() {
+            return Err(
+                ErrorKind::InvalidInput("Only octrees can be re-encoded".to_string()).into(),
+            );
+        }
+        if !meta.get_octree().get_interleaved_attributes().is_empty() {
+            return Err(ErrorKind::InvalidInput(
+                "Octrees with interleaved attributes cannot be re-encoded".to_string(),
+            )
+            .into());
+        }
+        let root_cube = Cube::bounding(&Aabb::from(meta.get_bounding_box()));
+        let mut nodes = HashMap::new();
+        for node in meta.get_octree().get_nodes() {
+            let node_id = NodeId::from_proto(node.get_id());
+            let bounding_cube = node_id.find_bounding_cube(&root_cube);
+            let encoding = Encoding::ScaledToCube(
+                bounding_cube.min(),
+                bounding_cube.edge_length(),
+                PositionEncoding::from_proto(node.position_encoding)?,
+            );
+            nodes.insert(
+                node_id.to_string(),
+                NodeEncoding {
+                    encoding,
+                    num_points: node.num_points as usize,
+                },
+            );
+        }
+        Ok(Self {
+            inner,
+            position_encoding,
+            nodes,
+        })
+    }
+
+    fn reencode(&self, node: &NodeEncoding, reader: Box<dyn Read + Send>) -> Result<Vec<u8>> {
+        let positions = RawNodeReader::new(reader, HashMap::new(), node.encoding.clone())?
+            .read_batch(node.num_points)?
+            .position;
+        let (min, edge_length) = match node.encoding {
+            Encoding::ScaledToCube(min, edge_length, _) => (min, edge_length),
+            Encoding::Plain => (Point3::origin(), 1.),
+        };
+        let mut data =
+            Vec::with_capacity(3 * self.position_encoding.bytes_per_coordinate() * positions.len());
+        for position in &positions {
+            match self.position_encoding {
+                PositionEncoding::Uint8 => {
+                    data.extend(vec3_fixpoint_encode::<u8>(position, &min, edge_length).iter());
+                }
+                PositionEncoding::Uint16 => {
+                    for v in vec3_fixpoint_encode::<u16>(position, &min, edge_length).iter() {
+                        data.write_u16::<LittleEndian>(*v)?;
+                    }
+                }
+                PositionEncoding::Float32 => {
+                    for v in vec3_encode::<f32>(position, &min, edge_length).iter() {
+                        data.write_f32::<LittleEndian>(*v)?;
+                    }
+                }
+                PositionEncoding::Float64 => {
+                    for v in vec3_encode::<f64>(position, &min, edge_length).iter() {
+                        data.write_f64::<LittleEndian>(*v)?;
+                    }
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl DataProvider for ReencodingDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        let mut meta = self.inner.meta_proto()?;
+        let nodes = if meta.has_octree() {
+            meta.mut_octree().mut_nodes()
+        } else {
+            &mut meta.deprecated_nodes
+        };
+        for node in nodes.iter_mut() {
+            node.set_position_encoding(self.position_encoding.to_proto());
+        }
+        Ok(meta)
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let mut readers = self.inner.data(node_id, node_attributes)?;
+        readers.retain(|attribute, _| node_attributes.contains(&attribute.as_str()));
+        let node = self.nodes.get(node_id).ok_or(ErrorKind::NodeNotFound)?;
+        let needs_reencoding = match node.encoding {
+            Encoding::ScaledToCube(_, _, ref position_encoding) => {
+                *position_encoding != self.position_encoding
+            }
+            Encoding::Plain => true,
+        };
+        if needs_reencoding {
+            if let Some(reader) = readers.remove("position") {
+                let data = self.reencode(node, reader)?;
+                readers.insert("position".to_string(), Box::new(Cursor::new(data)));
+            }
+        }
+        Ok(readers)
+    }
+
+    fn data_source(&self, node_id: &str) -> DataSource {
+        self.inner.data_source(node_id)
+    }
+
+    fn prefetch(&self, node_ids: &[String], node_attributes: &[&str]) {
+        self.inner.prefetch(node_ids, node_attributes)
+    }
+}
+
+#[cfg(all(test, feature = "build"))]
+mod tests {
+    use super::*;
+    use crate::data_provider::OnDiskDataProvider;
+    use crate::iterator::{ParallelIterator, PointQuery};
+    use crate::octree::{Octree, OctreeBuilder};
+    use crate::{AttributeData, PointsBatch};
+    use nalgebra::Vector3;
+    use tempdir::TempDir;
+
+    fn all_positions(octree: &Octree) -> Vec<Point3<f64>> {
+        let query = PointQuery::default();
+        let mut positions = Vec::new();
+        ParallelIterator::new(std::slice::from_ref(octree), &query, 1000, 1, 1)
+            .try_for_each_batch(|batch| {
+                positions.extend(batch.position);
+                Ok(())
+            })
+            .unwrap();
+        positions.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        positions
+    }
+
+    #[test]
+    fn test_reencoding() {
+        let num_points = 100;
+        let batch = PointsBatch {
+            position: (0..num_points)
+                .map(|i| Point3::new(i as f64, (i % 7) as f64, (i % 3) as f64))
+                .collect(),
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let bounding_box = Aabb::new(Point3::origin(), Point3::new(99., 99., 99.));
+        let tmp_dir = TempDir::new("octree").unwrap();
+        OctreeBuilder::new(1.0)
+            .build(&tmp_dir, bounding_box, vec![batch].into_iter())
+            .unwrap();
+        let on_disk = || {
+            Box::new(OnDiskDataProvider {
+                directory: tmp_dir.path().to_path_buf(),
+            })
+        };
+
+        let reencoding = ReencodingDataProvider::new(on_disk(), PositionEncoding::Float64).unwrap();
+        let meta = reencoding.meta_proto().unwrap();
+        let nodes = meta.get_octree().get_nodes();
+        assert!(nodes
+            .iter()
+            .all(|node| node.position_encoding == proto::PositionEncoding::Float64));
+        let node_id = NodeId::from_proto(nodes[0].get_id()).to_string();
+        let readers = reencoding.data(&node_id, &["position"]).unwrap();
+        assert_eq!(readers.keys().collect::<Vec<_>>(), vec!["position"]);
+
+        let original = Octree::from_data_provider(on_disk()).unwrap();
+        let reencoded = Octree::from_data_provider(Box::new(reencoding)).unwrap();
+        let original_positions = all_positions(&original);
+        let reencoded_positions = all_positions(&reencoded);
+        assert_eq!(original_positions.len(), num_points);
+        for (a, b) in original_positions.iter().zip(&reencoded_positions) {
+            assert!((a - b).norm() < 1e-9);
+        }
+    }
+}
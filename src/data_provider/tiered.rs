@@ -0,0 +1,465 @@
+use crate::attribute_extension;
+use crate::data_provider::{DataProvider, DataSource};
+use crate::errors::*;
+use crate::proto;
+use crate::read_write::INTERLEAVED;
+use crate::META_FILENAME;
+use fnv::FnvHasher;
+use lru::LruCache;
+use protobuf::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Extension of the sidecar file holding the checksum of a cached file.
+const CHECKSUM_EXTENSION: &str = "fnv";
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(data);
+    hasher.finish()
+}
+
+// Appends 'extension' to the file name of 'path', i.e. "r0.rgb" becomes "r0.rgb.fnv".
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    sibling_path(path, CHECKSUM_EXTENSION)
+}
+
+/// Keeps track of the files in the cache directory in least-recently-used order.
+struct CacheIndex {
+    files: LruCache<PathBuf, u64>,
+    size_bytes: u64,
+    /// The generation of the meta that the cached node files belong to, None if it is unknown.
+    generation: Option<u64>,
+}
+
+/// A read-through cache in front of a (slow) remote data provider. Everything that is fetched from
+/// the remote is persisted in a local directory, which is bounded in size and evicts the least
+/// recently used files first. Cached files are checked against a checksum on every read and are
+/// fetched again from the remote if they are corrupted. The cached node files are dropped whenever
+/// the remote serves a meta of another generation, since an update can rewrite any node.
+pub struct TieredDataProvider {
+    remote: Box<dyn DataProvider>,
+    cache_directory: PathBuf,
+    max_cache_size_bytes: u64,
+    index: Mutex<CacheIndex>,
+}
+
+impl TieredDataProvider {
+    pub fn new(
+        remote: Box<dyn DataProvider>,
+        cache_directory: impl Into<PathBuf>,
+        max_cache_size_bytes: u64,
+    ) -> Result<Self> {
+        let cache_directory = cache_directory.into();
+        fs::create_dir_all(&cache_directory)?;
+
+        // Files that are already in the cache from earlier sessions are ordered by their
+        // modification time, so that the oldest ones are evicted first.
+        let mut existing = Vec::new();
+        for entry in fs::read_dir(&cache_directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(CHECKSUM_EXTENSION) => continue,
+                Some("tmp") => {
+                    // Left over from an interrupted write.
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                _ => (),
+            }
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            existing.push((metadata.modified()?, path, metadata.len()));
+        }
+        existing.sort();
+
+        // The node files left by earlier sessions belong to the cached meta, if there is one.
+        let generation = fs::read(cache_directory.join(META_FILENAME))
+            .ok()
+            .and_then(|data| proto::Meta::parse_from_bytes(&data).ok())
+            .map(|meta| meta.generation);
+        let mut index = CacheIndex {
+            files: LruCache::unbounded(),
+            size_bytes: 0,
+            generation,
+        };
+        for (_, path, len) in existing {
+            index.size_bytes += len;
+            index.files.put(path, len);
+        }
+
+        let provider = Self {
+            remote,
+            cache_directory,
+            max_cache_size_bytes,
+            index: Mutex::new(index),
+        };
+        provider.evict(&mut provider.index.lock().unwrap());
+        Ok(provider)
+    }
+
+    pub fn cache_directory(&self) -> &Path {
+        &self.cache_directory
+    }
+
+    /// Returns the number of bytes currently used by the cache.
+    pub fn cache_size_bytes(&self) -> u64 {
+        self.index.lock().unwrap().size_bytes
+    }
+
+    fn evict(&self, index: &mut CacheIndex) {
+        while index.size_bytes > self.max_cache_size_bytes {
+            match index.files.pop_lru() {
+                Some((path, len)) => {
+                    index.size_bytes -= len;
+                    let _ = fs::remove_file(checksum_path(&path));
+                    let _ = fs::remove_file(&path);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Drops the cached node files if they do not belong to 'generation' of the meta.
+    fn switch_generation(&self, generation: u64) {
+        let mut index = self.index.lock().unwrap();
+        if index.generation == Some(generation) {
+            return;
+        }
+        let meta_path = self.cache_directory.join(META_FILENAME);
+        let node_files: Vec<PathBuf> = index
+            .files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .filter(|path| *path != meta_path)
+            .collect();
+        for path in node_files {
+            if let Some(len) = index.files.pop(&path) {
+                index.size_bytes -= len;
+            }
+            let _ = fs::remove_file(checksum_path(&path));
+            let _ = fs::remove_file(&path);
+        }
+        index.generation = Some(generation);
+    }
+
+    fn forget(&self, path: &Path) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(len) = index.files.pop(&path.to_path_buf()) {
+            index.size_bytes -= len;
+        }
+        let _ = fs::remove_file(checksum_path(path));
+        let _ = fs::remove_file(path);
+    }
+
+    /// Returns the cached content of 'path' if it is present and not corrupted.
+    fn read_cached(&self, path: &Path) -> Option<Vec<u8>> {
+        let data = fs::read(path).ok()?;
+        let expected = fs::read(checksum_path(path)).ok()?;
+        if expected.len() != 8 || expected[..] != checksum(&data).to_le_bytes()[..] {
+            eprintln!("Discarding corrupted cache file {}.", path.display());
+            self.forget(path);
+            return None;
+        }
+        self.index.lock().unwrap().files.get(&path.to_path_buf());
+        Some(data)
+    }
+
+    /// Caches 'data' as 'path', unless the cached files moved on from 'generation' since the
+    /// data was fetched.
+    fn write_cached(&self, path: &Path, data: &[u8], generation: Option<u64>) -> Result<()> {
+        let len = data.len() as u64;
+        if len > self.max_cache_size_bytes || self.index.lock().unwrap().generation != generation {
+            return Ok(());
+        }
+        // Write to a temporary file first, so that readers never see partially written data.
+        let tmp_path = sibling_path(path, "tmp");
+        File::create(&tmp_path)?.write_all(data)?;
+        fs::write(checksum_path(path), &checksum(data).to_le_bytes())?;
+        fs::rename(&tmp_path, path)?;
+
+        let mut index = self.index.lock().unwrap();
+        if index.generation != generation {
+            // The generation changed while writing, the file may be outdated.
+            drop(index);
+            self.forget(path);
+            return Ok(());
+        }
+        if let Some(old_len) = index.files.put(path.to_path_buf(), len) {
+            index.size_bytes -= old_len;
+        }
+        index.size_bytes += len;
+        self.evict(&mut index);
+        Ok(())
+    }
+
+    fn read_through(
+        &self,
+        path: &Path,
+        fetch: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        if let Some(data) = self.read_cached(path) {
+            return Ok(data);
+        }
+        let generation = self.index.lock().unwrap().generation;
+        let data = fetch()?;
+        if let Err(err) = self.write_cached(path, &data, generation) {
+            eprintln!("Could not cache {}: {}", path.display(), err);
+        }
+        Ok(data)
+    }
+}
+
+impl DataProvider for TieredDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        // Unlike the files of nodes, the meta is replaced whenever the point cloud is updated, so
+        // it is fetched from the remote every time. The cached copy is only used while the remote
+        // cannot be reached.
+        let path = self.cache_directory.join(META_FILENAME);
+        let remote_error = match self.remote.meta_proto() {
+            Ok(meta) => {
+                self.switch_generation(meta.generation);
+                let cached = meta
+                    .write_to_bytes()
+                    .chain_err(|| format!("Could not serialize {}", META_FILENAME))
+                    .and_then(|data| self.write_cached(&path, &data, Some(meta.generation)));
+                if let Err(err) = cached {
+                    eprintln!("Could not cache {}: {}", path.display(), err);
+                }
+                return Ok(meta);
+            }
+            Err(err) => err,
+        };
+        match self.read_cached(&path) {
+            Some(data) => protobuf::parse_from_reader::<proto::Meta>(&mut Cursor::new(data))
+                .chain_err(|| format!("Could not parse {}", META_FILENAME)),
+            None => Err(remote_error),
+        }
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let stem = self.cache_directory.join(node_id);
+        let mut readers = HashMap::<String, Box<dyn Read + Send>>::new();
+        for node_attribute in node_attributes {
+            let path = stem.with_extension(attribute_extension(node_attribute));
+            let data = self.read_through(&path, || {
+                let mut remote_readers = self.remote.data(node_id, &[*node_attribute])?;
+                let mut data = Vec::new();
+                remote_readers
+                    .get_mut(*node_attribute)
+                    .ok_or(ErrorKind::NodeNotFound)?
+                    .read_to_end(&mut data)?;
+                Ok(data)
+            })?;
+            readers.insert((*node_attribute).to_string(), Box::new(Cursor::new(data)));
+        }
+        Ok(readers)
+    }
+
+    fn data_source(&self, node_id: &str) -> DataSource {
+        // Every node has either a position or an interleaved file.
+        let stem = self.cache_directory.join(node_id);
+        let index = self.index.lock().unwrap();
+        let is_cached = ["position", INTERLEAVED].iter().any(|attribute| {
+            index
+                .files
+                .contains(&stem.with_extension(attribute_extension(attribute)))
+        });
+        if is_cached {
+            DataSource::Cache
+        } else {
+            self.remote.data_source(node_id)
+        }
+    }
+
+    fn prefetch(&self, node_ids: &[String], node_attributes: &[&str]) {
+        // Only the files that are not cached yet are worth fetching. Nodes missing the same files
+        // are passed on together.
+        let mut missing = BTreeMap::<Vec<&str>, Vec<String>>::new();
+        {
+            let index = self.index.lock().unwrap();
+            for node_id in node_ids {
+                let stem = self.cache_directory.join(node_id);
+                let attributes: Vec<&str> = node_attributes
+                    .iter()
+                    .filter(|attribute| {
+                        !index
+                            .files
+                            .contains(&stem.with_extension(attribute_extension(attribute)))
+                    })
+                    .copied()
+                    .collect();
+                if !attributes.is_empty() {
+                    missing.entry(attributes).or_default().push(node_id.clone());
+                }
+            }
+        }
+        for (attributes, node_ids) in missing {
+            self.remote.prefetch(&node_ids, &attributes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_provider::OnDiskDataProvider;
+    use tempdir::TempDir;
+
+    fn read_attribute(provider: &dyn DataProvider, node_id: &str, attribute: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        provider
+            .data(node_id, &[attribute])
+            .unwrap()
+            .get_mut(attribute)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn serves_from_cache_once_fetched() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        fs::write(remote_dir.path().join("r0.rgb"), &[1u8, 2, 3]).unwrap();
+
+        let remote = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 1024).unwrap();
+        assert_eq!(read_attribute(&tiered, "r0", "color"), vec![1, 2, 3]);
+
+        // The remote is gone now, the data has to come from the cache.
+        fs::remove_file(remote_dir.path().join("r0.rgb")).unwrap();
+        assert_eq!(read_attribute(&tiered, "r0", "color"), vec![1, 2, 3]);
+        assert_eq!(tiered.cache_size_bytes(), 3);
+    }
+
+    #[test]
+    fn serves_the_latest_meta() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        let writer = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        writer.write_meta_proto(&proto::Meta::new()).unwrap();
+
+        let remote = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 1024).unwrap();
+        assert_eq!(tiered.meta_proto().unwrap().generation, 0);
+        writer.update_meta_proto(|_| ()).unwrap();
+        assert_eq!(tiered.meta_proto().unwrap().generation, 1);
+
+        // The remote is gone now, the meta has to come from the cache.
+        fs::remove_file(remote_dir.path().join(META_FILENAME)).unwrap();
+        assert_eq!(tiered.meta_proto().unwrap().generation, 1);
+    }
+
+    #[test]
+    fn drops_nodes_of_other_generations() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        let writer = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        writer.write_meta_proto(&proto::Meta::new()).unwrap();
+        fs::write(remote_dir.path().join("r0.rgb"), &[1u8, 2, 3]).unwrap();
+
+        let open = || {
+            let remote = OnDiskDataProvider {
+                directory: remote_dir.path().to_path_buf(),
+            };
+            let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 1024).unwrap();
+            tiered.meta_proto().unwrap();
+            tiered
+        };
+        let tiered = open();
+        read_attribute(&tiered, "r0", "color");
+
+        // The node stays cached for later sessions that see the same generation.
+        fs::write(remote_dir.path().join("r0.rgb"), &[4u8, 5, 6]).unwrap();
+        assert_eq!(read_attribute(&open(), "r0", "color"), vec![1, 2, 3]);
+
+        writer.update_meta_proto(|_| ()).unwrap();
+        tiered.meta_proto().unwrap();
+        assert_eq!(read_attribute(&tiered, "r0", "color"), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn reports_cached_nodes_as_cache() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        fs::write(remote_dir.path().join("r0.xyz"), &[0u8; 24]).unwrap();
+
+        let remote = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 1024).unwrap();
+        assert_eq!(tiered.data_source("r0"), DataSource::Disk);
+        read_attribute(&tiered, "r0", "position");
+        assert_eq!(tiered.data_source("r0"), DataSource::Cache);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        for node_id in &["r0", "r1", "r2"] {
+            fs::write(
+                remote_dir.path().join(node_id).with_extension("rgb"),
+                &[0u8; 6],
+            )
+            .unwrap();
+        }
+
+        let remote = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 12).unwrap();
+        read_attribute(&tiered, "r0", "color");
+        read_attribute(&tiered, "r1", "color");
+        read_attribute(&tiered, "r0", "color");
+        read_attribute(&tiered, "r2", "color");
+
+        assert_eq!(tiered.cache_size_bytes(), 12);
+        assert!(cache_dir.path().join("r0.rgb").exists());
+        assert!(!cache_dir.path().join("r1.rgb").exists());
+        assert!(cache_dir.path().join("r2.rgb").exists());
+    }
+
+    #[test]
+    fn refetches_corrupted_files() {
+        let remote_dir = TempDir::new("remote").unwrap();
+        let cache_dir = TempDir::new("cache").unwrap();
+        fs::write(remote_dir.path().join("r0.rgb"), &[1u8, 2, 3]).unwrap();
+
+        let remote = OnDiskDataProvider {
+            directory: remote_dir.path().to_path_buf(),
+        };
+        let tiered = TieredDataProvider::new(Box::new(remote), cache_dir.path(), 1024).unwrap();
+        read_attribute(&tiered, "r0", "color");
+        fs::write(cache_dir.path().join("r0.rgb"), &[4u8, 5, 6]).unwrap();
+        assert_eq!(read_attribute(&tiered, "r0", "color"), vec![1, 2, 3]);
+    }
+}
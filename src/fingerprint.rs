@@ -0,0 +1,173 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Content hashes of point clouds that only depend on the points, not on their order, the node
+//! structure or the position encoding, to check that two copies or two export paths produced the
+//! same data.
+
+use crate::errors::*;
+use crate::iterator::{ParallelIterator, PointCloud, PointQuery};
+use crate::{match_attr_data, AttributeData, Schema, NUM_POINTS_PER_BATCH};
+use fnv::FnvHasher;
+use nalgebra::{Scalar, Vector3};
+use std::fmt;
+use std::hash::Hasher;
+
+/// The digest is made up of two FNV-1a hashes that start from different offset bases. They share
+/// the multiplier, so they are not independent and the digest is weaker than its 128 bits
+/// suggest. It is only meant to catch accidental differences, not deliberate collisions.
+const KEYS: [u64; 2] = [0xcbf2_9ce4_8422_2325, 0x6c62_272e_07bb_0142];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub num_points: u64,
+    pub digest: [u64; 2],
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}{:016x} ({} points)",
+            self.digest[0], self.digest[1], self.num_points
+        )
+    }
+}
+
+struct PointHasher([FnvHasher; 2]);
+
+impl PointHasher {
+    fn new() -> Self {
+        PointHasher([FnvHasher::with_key(KEYS[0]), FnvHasher::with_key(KEYS[1])])
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for hasher in &mut self.0 {
+            hasher.write(bytes);
+        }
+    }
+
+    fn finish(&self) -> [u64; 2] {
+        [self.0[0].finish(), self.0[1].finish()]
+    }
+}
+
+trait WriteValue {
+    fn write_to(&self, hasher: &mut PointHasher);
+}
+
+macro_rules! impl_write_value {
+    ($($type:ty),*) => {
+        $(
+            impl WriteValue for $type {
+                fn write_to(&self, hasher: &mut PointHasher) {
+                    hasher.write(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_write_value!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl<T: WriteValue + Scalar> WriteValue for Vector3<T> {
+    fn write_to(&self, hasher: &mut PointHasher) {
+        self.x.write_to(hasher);
+        self.y.write_to(hasher);
+        self.z.write_to(hasher);
+    }
+}
+
+/// Computes the fingerprint of all points of 'point_clouds' with the given 'attributes'.
+/// Positions are rounded to multiples of 'tolerance_m', so that the error of lossy position
+/// encodings does not change the fingerprint. Two positions that are closer than that can still
+/// be rounded apart if they lie on both sides of a multiple, so the tolerance should be well above
+/// the expected error. Attributes are compared exactly. Every point contributes a hash that is
+/// summed up, so the fingerprint does not depend on the order of the points, but on how often
+/// each point occurs.
+pub fn fingerprint<C: PointCloud>(
+    point_clouds: &[C],
+    attributes: &[&str],
+    tolerance_m: f64,
+) -> Result<Fingerprint> {
+    if !tolerance_m.is_finite() || tolerance_m <= 0. {
+        return Err(ErrorKind::InvalidInput(format!(
+            "The tolerance must be positive, not {}.",
+            tolerance_m
+        ))
+        .into());
+    }
+    let query = PointQuery {
+        attributes: attributes.to_vec(),
+        ..Default::default()
+    };
+    let mut num_points = 0;
+    let mut sums = [0u64; 2];
+    let mut schema = None;
+    let mut parallel_iterator = ParallelIterator::new(
+        point_clouds,
+        &query,
+        NUM_POINTS_PER_BATCH,
+        num_cpus::get(),
+        4, /* buffer_size */
+    );
+    parallel_iterator.try_for_each_batch(|batch| {
+        schema.get_or_insert_with(|| Schema::from_batch(&batch));
+        let mut hashers: Vec<PointHasher> = batch
+            .position
+            .iter()
+            .map(|position| {
+                let mut hasher = PointHasher::new();
+                for coordinate in position.coords.iter() {
+                    ((coordinate / tolerance_m).round() as i64).write_to(&mut hasher);
+                }
+                hasher
+            })
+            .collect();
+        // The attributes of a batch are sorted by name.
+        for data in batch.attributes.values() {
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident, $hashers:expr) => {
+                    for (hasher, value) in $hashers.iter_mut().zip($data) {
+                        value.write_to(hasher);
+                    }
+                };
+            }
+            match_attr_data!(data, rhs, hashers)
+        }
+        for hasher in &hashers {
+            let hash = hasher.finish();
+            sums[0] = sums[0].wrapping_add(hash[0]);
+            sums[1] = sums[1].wrapping_add(hash[1]);
+        }
+        num_points += batch.position.len() as u64;
+        Ok(())
+    })?;
+
+    // Clouds with the same points but different attribute names or types differ.
+    let mut hasher = PointHasher::new();
+    if let Some(schema) = schema {
+        for (name, data_type) in schema.attributes() {
+            hasher.write(name.as_bytes());
+            hasher.write(format!("{:?}", data_type).as_bytes());
+        }
+    }
+    num_points.write_to(&mut hasher);
+    sums[0].write_to(&mut hasher);
+    sums[1].write_to(&mut hasher);
+    Ok(Fingerprint {
+        num_points,
+        digest: hasher.finish(),
+    })
+}
@@ -55,6 +55,20 @@ impl Aabb {
         self.maxs - self.mins
     }
 
+    /// The Euclidean distance from 'p' to the closest point of this box, 0 if it is inside.
+    pub fn distance_to_point(&self, p: &Point3<f64>) -> f64 {
+        let closest = p.sup(&self.mins).inf(&self.maxs);
+        nalgebra::distance(p, &closest)
+    }
+
+    /// The Euclidean distance between the closest points of both boxes, 0 if they intersect.
+    pub fn distance_to_aabb(&self, other: &Aabb) -> f64 {
+        let gap = (self.mins - other.maxs)
+            .sup(&(other.mins - self.maxs))
+            .sup(&Vector3::zeros());
+        gap.norm()
+    }
+
     pub fn transform(&self, transform: &Isometry3<f64>) -> Aabb {
         let corners = self.compute_corners();
         let transformed_first = transform.transform_point(&corners[0]);
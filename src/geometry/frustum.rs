@@ -168,6 +168,53 @@ impl ConvexPolyhedron for Frustum {
 
 has_aabb_intersector_for_convex_polyhedron!(Frustum);
 
+/// An AABB intersector for a frustum that is meant to be kept across frames.
+///
+/// The separating axes only depend on the orientation and the projection of the frustum, so when
+/// the camera merely moves without turning or zooming, updating only recomputes the corners.
+pub struct CachedFrustumIntersector {
+    clip_from_query: Matrix4<f64>,
+    intersector: CachedAxesIntersector,
+}
+
+impl CachedFrustumIntersector {
+    /// Fails if the matrix is not invertible.
+    pub fn new(clip_from_query: Matrix4<f64>) -> Option<Self> {
+        let intersector = Frustum::from_matrix4(clip_from_query)?.aabb_intersector();
+        Some(Self {
+            clip_from_query,
+            intersector,
+        })
+    }
+
+    /// Updates the frustum to 'clip_from_query'. Returns whether the separating axes had to be
+    /// recomputed, or None if the matrix is not invertible, in which case nothing changes.
+    pub fn update(&mut self, clip_from_query: &Matrix4<f64>) -> Option<bool> {
+        if *clip_from_query == self.clip_from_query {
+            return Some(false);
+        }
+        let frustum = Frustum::from_matrix4(*clip_from_query)?;
+        // A translation of the camera only changes the last column of the matrix.
+        let only_translated = clip_from_query.fixed_columns::<nalgebra::U3>(0)
+            == self.clip_from_query.fixed_columns::<nalgebra::U3>(0);
+        if only_translated {
+            self.intersector.corners = frustum.compute_corners();
+        } else {
+            self.intersector = frustum.aabb_intersector();
+        }
+        self.clip_from_query = *clip_from_query;
+        Some(!only_translated)
+    }
+
+    pub fn clip_from_query(&self) -> &Matrix4<f64> {
+        &self.clip_from_query
+    }
+
+    pub fn intersector(&self) -> &CachedAxesIntersector {
+        &self.intersector
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -203,4 +250,44 @@ mod tests {
             assert_eq!(el_a, el_b);
         }
     }
+
+    #[test]
+    fn test_cached_frustum_intersector_update() {
+        use crate::geometry::Aabb;
+        use crate::math::sat::Relation;
+        use nalgebra::{Translation3, UnitQuaternion, Vector3};
+
+        let perspective = Perspective::new(-1.0, 1.0, -1.0, 1.0, 1.0, 100.0);
+        let clip_from_query = |x: f64, yaw: f64| {
+            let query_from_eye = Isometry3::from_parts(
+                Translation3::new(x, 0.0, 0.0),
+                UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw),
+            );
+            perspective.as_matrix() * query_from_eye.inverse().to_homogeneous()
+        };
+        // The camera looks along -z.
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -11.0), Point3::new(1.0, 1.0, -9.0));
+
+        let mut cached = CachedFrustumIntersector::new(clip_from_query(0.0, 0.0)).unwrap();
+        assert_eq!(
+            cached.intersector().intersect(&aabb.compute_corners()),
+            Relation::In
+        );
+        assert_eq!(cached.update(&clip_from_query(0.0, 0.0)), Some(false));
+        assert_eq!(cached.update(&clip_from_query(50.0, 0.0)), Some(false));
+        assert_eq!(
+            cached.intersector().intersect(&aabb.compute_corners()),
+            Relation::Out
+        );
+        assert_eq!(cached.update(&clip_from_query(0.0, 0.1)), Some(true));
+        let fresh = Frustum::from_matrix4(clip_from_query(0.0, 0.1))
+            .unwrap()
+            .aabb_intersector();
+        assert_eq!(cached.intersector().axes, fresh.axes);
+        assert_eq!(
+            cached.intersector().intersect(&aabb.compute_corners()),
+            fresh.intersect(&aabb.compute_corners())
+        );
+        assert_eq!(cached.update(&Matrix4::zeros()), None);
+    }
 }
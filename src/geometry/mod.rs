@@ -4,9 +4,11 @@ mod frustum;
 mod obb;
 mod s2_cell_union;
 mod web_mercator_rect;
+mod wireframe;
 
 pub use aabb::*;
 pub use frustum::*;
 pub use obb::*;
 pub use s2_cell_union::*;
 pub use web_mercator_rect::*;
+pub use wireframe::*;
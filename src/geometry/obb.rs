@@ -43,6 +43,13 @@ impl Obb {
     pub fn transformed(&self, global_from_query: &Isometry3<f64>) -> Self {
         Self::new(global_from_query * self.query_from_obb, self.half_extent)
     }
+
+    /// The Euclidean distance from 'p' to the closest point of this box, 0 if it is inside.
+    pub fn distance_to_point(&self, p: &Point3<f64>) -> f64 {
+        // Distances are invariant under the isometry, so this is the AABB case in box frame.
+        let p = (self.obb_from_query * p).coords.abs();
+        (p - self.half_extent).sup(&Vector3::zeros()).norm()
+    }
 }
 
 impl ConvexPolyhedron for Obb {
@@ -138,4 +145,18 @@ mod tests {
         let arbitrary_obb_isec = arbitrary_obb.intersector().cache_separating_axes_for_aabb();
         assert_eq!(arbitrary_obb_isec.axes.len(), 15);
     }
+
+    #[test]
+    fn test_obb_distance_to_point() {
+        let rot: UnitQuaternion<f64> =
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f64::consts::PI / 2.0);
+        let obb = Obb::new(
+            Isometry3::from_parts(Vector3::new(10.0, 0.0, 0.0).into(), rot),
+            Vector3::new(1.0, 2.0, 3.0),
+        );
+        assert_eq!(obb.distance_to_point(&Point3::new(10.5, 0.5, 0.0)), 0.0);
+        // The box is rotated, so its long side of 2 is along the global x axis.
+        assert!((obb.distance_to_point(&Point3::new(13.0, 0.0, 0.0)) - 1.0).abs() < 1e-10);
+        assert!((obb.distance_to_point(&Point3::new(10.0, 4.0, 7.0)) - 5.0).abs() < 1e-10);
+    }
 }
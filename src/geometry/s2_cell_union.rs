@@ -1,12 +1,71 @@
 //! A cell union, re-exported from the s2 crate.
 pub use s2::cellunion::CellUnion;
 
+use crate::errors::*;
 use crate::geometry::Aabb;
 use crate::math::base::{HasAabbIntersector, IntersectAabb, PointCulling};
 use crate::math::sat::ConvexPolyhedron;
 use crate::math::FromPoint3;
 use nalgebra::Point3;
 use s2::{cell::Cell, cellid::CellID, region::Region};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Parses an S2 cell token, i.e. the hex encoded cell id without trailing zeros, as used by other
+/// S2 libraries to exchange cell ids.
+pub fn cell_id_from_token(token: &str) -> Result<CellID> {
+    let cell_id = CellID::from_token(token);
+    if token.len() > 16 || !cell_id.is_valid() {
+        return Err(ErrorKind::InvalidInput(format!("Invalid S2 cell token '{}'.", token)).into());
+    }
+    Ok(cell_id)
+}
+
+/// The normalized union of the cells with the given tokens.
+pub fn cell_union_from_tokens(tokens: &[impl AsRef<str>]) -> Result<CellUnion> {
+    let cell_ids = tokens
+        .iter()
+        .map(|token| cell_id_from_token(token.as_ref()))
+        .collect::<Result<_>>()?;
+    let mut cell_union = CellUnion(cell_ids);
+    cell_union.normalize();
+    Ok(cell_union)
+}
+
+/// Serializes cell unions as lists of tokens. Lists of raw cell ids are accepted as well, which
+/// is how cell unions were serialized before.
+pub mod cell_union_tokens {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum SerializedCellId {
+        Token(String),
+        Id(u64),
+    }
+
+    pub fn serialize<S: Serializer>(
+        cell_union: &CellUnion,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let tokens: Vec<String> = cell_union.0.iter().map(CellID::to_token).collect();
+        tokens.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<CellUnion, D::Error> {
+        let cell_ids = Vec::<SerializedCellId>::deserialize(deserializer)?
+            .into_iter()
+            .map(|cell_id| match cell_id {
+                SerializedCellId::Token(token) => {
+                    cell_id_from_token(&token).map_err(serde::de::Error::custom)
+                }
+                SerializedCellId::Id(id) => Ok(CellID(id)),
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(CellUnion(cell_ids))
+    }
+}
 
 /// Checks for an intersection between a list of cells and a polyhedron.
 ///
@@ -42,3 +101,27 @@ impl<'a> HasAabbIntersector<'a> for CellUnion {
         self.0.iter().map(Cell::from).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_tokens() {
+        let cell_id = CellID::from_point(&Point3::new(6_378_137., 1000., 2000.)).parent(10);
+        assert_eq!(cell_id_from_token(&cell_id.to_token()).unwrap(), cell_id);
+        for token in &["", "X", "zz", "89c28", "89c259000000000000"] {
+            assert!(cell_id_from_token(token).is_err(), "{}", token);
+        }
+
+        let cell_union = cell_union_from_tokens(&[cell_id.to_token()]).unwrap();
+        let json = serde_json::to_string(&SerializedCellUnion(cell_union.clone())).unwrap();
+        assert_eq!(json, format!("[\"{}\"]", cell_id.to_token()));
+        let from_ids: SerializedCellUnion =
+            serde_json::from_str(&format!("[{}]", cell_id.0)).unwrap();
+        assert_eq!(from_ids.0, cell_union);
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedCellUnion(#[serde(with = "cell_union_tokens")] CellUnion);
+}
@@ -9,7 +9,7 @@ use nav_types::{ECEF, WGS84};
 use serde::{Deserialize, Serialize};
 
 /// The dead sea is at -413m, but we use a more generous minimum
-const MIN_ELEVATION_M: f64 = -500.0;
+pub(super) const MIN_ELEVATION_M: f64 = -500.0;
 
 /// Mt. Everest is at 8,848m, plus we need to take into account the
 /// [sagitta](https://en.wikipedia.org/wiki/Sagitta_(geometry)) of the section
@@ -23,7 +23,7 @@ const MIN_ELEVATION_M: f64 = -500.0;
 /// approximately the direction of the four corners, so it's fair to say we're
 /// adding it directly to its height), and then some to get a nice round number
 /// and to insure against calculation errors.
-const MAX_ELEVATION_M: f64 = 10000.0;
+pub(super) const MAX_ELEVATION_M: f64 = 10000.0;
 
 /// A rectangle on a Web Mercator map, not rotated.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -0,0 +1,257 @@
+//! Wireframes of the regions that queries cover, to inspect them in external 3D tools next to the
+//! point cloud, e.g. when a query unexpectedly returns no points.
+
+use super::web_mercator_rect::{MAX_ELEVATION_M, MIN_ELEVATION_M};
+use crate::errors::*;
+use crate::iterator::PointLocation;
+use crate::math::sat::ConvexPolyhedron;
+use byteorder::{LittleEndian, WriteBytesExt};
+use nalgebra::{Point3, Vector3};
+use nav_types::{ECEF, WGS84};
+use s2::cell::Cell;
+use s2::latlng::LatLng;
+use serde_json::json;
+
+/// The edges of boxes whose corners are numbered by the bits of their coordinates, like the
+/// corners of Aabb, Obb and Frustum.
+const BOX_EDGES: [[u32; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// The edges of prisms whose corners are a ring at the bottom followed by the same ring at the
+/// top, like the corners of WebMercatorRect.
+const PRISM_EDGES: [[u32; 2]; 12] = [
+    [0, 1],
+    [1, 2],
+    [2, 3],
+    [3, 0],
+    [4, 5],
+    [5, 6],
+    [6, 7],
+    [7, 4],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+// Constants of the glTF 2.0 specification.
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const GLB_CHUNK_JSON: &[u8; 4] = b"JSON";
+const GLB_CHUNK_BIN: &[u8; 4] = b"BIN\0";
+const MODE_LINES: u32 = 1;
+const COMPONENT_TYPE_FLOAT: u32 = 5126;
+const COMPONENT_TYPE_UNSIGNED_INT: u32 = 5125;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Line segments between points in the coordinates of the query.
+#[derive(Debug, Default, Clone)]
+pub struct Wireframe {
+    pub vertices: Vec<Point3<f64>>,
+    /// Indices into 'vertices'.
+    pub edges: Vec<[u32; 2]>,
+}
+
+/// The corners of the S2 cell, extruded from below to above the surface like a WebMercatorRect.
+fn s2_cell_corners(cell: &Cell) -> Vec<Point3<f64>> {
+    let vertices = cell.vertices();
+    [MIN_ELEVATION_M, MAX_ELEVATION_M]
+        .iter()
+        .flat_map(|elevation| {
+            vertices.iter().map(move |vertex| {
+                let lat_lng = LatLng::from(vertex);
+                let ecef = ECEF::from(WGS84::from_radians_and_meters(
+                    lat_lng.lat.rad(),
+                    lat_lng.lng.rad(),
+                    *elevation,
+                ));
+                Point3::new(ecef.x(), ecef.y(), ecef.z())
+            })
+        })
+        .collect()
+}
+
+impl Wireframe {
+    /// The outline of the region. S2 cells are approximated by one box per cell, like Web Mercator
+    /// rectangles, from below to above any terrain. All points cover no region.
+    pub fn from_location(location: &PointLocation) -> Result<Self> {
+        let mut wireframe = Wireframe::default();
+        match location {
+            PointLocation::AllPoints => {
+                return Err(ErrorKind::InvalidInput(
+                    "Querying all points has no region to show.".to_string(),
+                )
+                .into());
+            }
+            PointLocation::Aabb(aabb) => wireframe.add(&aabb.compute_corners(), &BOX_EDGES),
+            PointLocation::Frustum(frustum) => {
+                wireframe.add(&frustum.compute_corners(), &BOX_EDGES)
+            }
+            PointLocation::Obb(obb) => wireframe.add(&obb.compute_corners(), &BOX_EDGES),
+            PointLocation::S2Cells(cell_union) => {
+                for cell_id in cell_union.0.iter() {
+                    wireframe.add(&s2_cell_corners(&Cell::from(cell_id)), &PRISM_EDGES);
+                }
+            }
+            PointLocation::WebMercatorRect(rect) => {
+                wireframe.add(&rect.compute_corners(), &PRISM_EDGES)
+            }
+        }
+        if wireframe.vertices.is_empty() {
+            return Err(ErrorKind::InvalidInput("The region is empty.".to_string()).into());
+        }
+        Ok(wireframe)
+    }
+
+    /// Adds a shape, with 'edges' indexing into 'corners'.
+    pub fn add(&mut self, corners: &[Point3<f64>], edges: &[[u32; 2]]) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(corners);
+        self.edges
+            .extend(edges.iter().map(|[a, b]| [offset + a, offset + b]));
+    }
+
+    /// A binary glTF 2.0 file with the wireframe as a mesh of lines. glTF positions are only
+    /// single precision, so the vertices are stored relative to their centroid, which is the
+    /// translation of the node. glTF is y-up, the node turns the z-up coordinates accordingly.
+    pub fn to_glb(&self) -> Vec<u8> {
+        let origin = self
+            .vertices
+            .iter()
+            .fold(Vector3::zeros(), |sum, vertex| sum + vertex.coords)
+            / (self.vertices.len().max(1) as f64);
+        let mut min = Vector3::repeat(std::f32::MAX);
+        let mut max = Vector3::repeat(std::f32::MIN);
+        let mut buffer = Vec::new();
+        for vertex in &self.vertices {
+            let relative = (vertex.coords - origin).map(|c| c as f32);
+            min = min.inf(&relative);
+            max = max.sup(&relative);
+            for coordinate in relative.iter() {
+                buffer.write_f32::<LittleEndian>(*coordinate).unwrap();
+            }
+        }
+        let positions_length = buffer.len();
+        for index in self.edges.iter().flatten() {
+            buffer.write_u32::<LittleEndian>(*index).unwrap();
+        }
+        let indices_length = buffer.len() - positions_length;
+
+        let quarter_turn = std::f64::consts::FRAC_1_SQRT_2;
+        let gltf = json!({
+            "asset": { "version": "2.0", "generator": "point_viewer" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{
+                "mesh": 0,
+                // The translation is in the y-up frame of the scene.
+                "translation": [origin.x, origin.z, -origin.y],
+                // -90 degrees around the x axis.
+                "rotation": [-quarter_turn, 0., 0., quarter_turn],
+            }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0 },
+                    "indices": 1,
+                    "mode": MODE_LINES,
+                }],
+            }],
+            "buffers": [{ "byteLength": buffer.len() }],
+            "bufferViews": [
+                {
+                    "buffer": 0,
+                    "byteOffset": 0,
+                    "byteLength": positions_length,
+                    "target": TARGET_ARRAY_BUFFER,
+                },
+                {
+                    "buffer": 0,
+                    "byteOffset": positions_length,
+                    "byteLength": indices_length,
+                    "target": TARGET_ELEMENT_ARRAY_BUFFER,
+                },
+            ],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": COMPONENT_TYPE_FLOAT,
+                    "count": self.vertices.len(),
+                    "type": "VEC3",
+                    "min": [min.x, min.y, min.z],
+                    "max": [max.x, max.y, max.z],
+                },
+                {
+                    "bufferView": 1,
+                    "componentType": COMPONENT_TYPE_UNSIGNED_INT,
+                    "count": 2 * self.edges.len(),
+                    "type": "SCALAR",
+                },
+            ],
+        });
+
+        // Chunks are padded to multiples of 4 bytes, the JSON with spaces.
+        let mut json = gltf.to_string().into_bytes();
+        json.resize((json.len() + 3) / 4 * 4, b' ');
+        buffer.resize((buffer.len() + 3) / 4 * 4, 0);
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.write_u32::<LittleEndian>(GLB_VERSION).unwrap();
+        glb.write_u32::<LittleEndian>(length as u32).unwrap();
+        for (chunk_type, chunk) in &[(GLB_CHUNK_JSON, json), (GLB_CHUNK_BIN, buffer)] {
+            glb.write_u32::<LittleEndian>(chunk.len() as u32).unwrap();
+            glb.extend_from_slice(*chunk_type);
+            glb.extend_from_slice(chunk);
+        }
+        glb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Aabb;
+    use crate::math::FromPoint3;
+    use byteorder::ByteOrder;
+    use s2::cellid::CellID;
+    use s2::cellunion::CellUnion;
+
+    #[test]
+    fn test_wireframe() {
+        let aabb = Aabb::new(Point3::new(0., 0., 0.), Point3::new(1., 2., 3.));
+        let wireframe = Wireframe::from_location(&PointLocation::Aabb(aabb)).unwrap();
+        assert_eq!(wireframe.vertices.len(), 8);
+        // Every edge of the box is parallel to an axis.
+        for [a, b] in &wireframe.edges {
+            let edge = wireframe.vertices[*b as usize] - wireframe.vertices[*a as usize];
+            assert_eq!(edge.iter().filter(|c| **c != 0.).count(), 1);
+        }
+
+        let glb = wireframe.to_glb();
+        assert_eq!(&glb[0..4], GLB_MAGIC);
+        assert_eq!(LittleEndian::read_u32(&glb[8..12]) as usize, glb.len());
+        let json_length = LittleEndian::read_u32(&glb[12..16]) as usize;
+        let gltf: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(gltf["accessors"][1]["count"], 24);
+
+        let cell_id = CellID::from_point(&Point3::new(6_378_137., 1000., 2000.)).parent(10);
+        let cells = PointLocation::S2Cells(CellUnion(vec![cell_id, cell_id.next()]));
+        let wireframe = Wireframe::from_location(&cells).unwrap();
+        assert_eq!(wireframe.vertices.len(), 16);
+        assert_eq!(wireframe.edges.len(), 24);
+        assert!(Wireframe::from_location(&PointLocation::AllPoints).is_err());
+    }
+}
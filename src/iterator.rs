@@ -1,12 +1,15 @@
+use crate::attributes::AttributeAliases;
+use crate::calibration::RadiometricCorrection;
 use crate::errors::*;
-use crate::geometry::{Aabb, CellUnion, Frustum, Obb, WebMercatorRect};
+use crate::geometry::{cell_union_from_tokens, Aabb, CellUnion, Frustum, Obb, WebMercatorRect};
 use crate::math::{AllPoints, ClosedInterval, PointCulling};
 use crate::read_write::{Encoding, NodeIterator};
-use crate::{match_1d_attr_data, AttributeData, PointsBatch};
+use crate::{match_1d_attr_data, AttributeData, AttributeDataType, PointsBatch};
 use crossbeam::deque::{Injector, Steal, Worker};
 use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +18,8 @@ pub enum PointLocation {
     Aabb(Aabb),
     Frustum(Frustum),
     Obb(Obb),
+    /// Serialized as a list of cell tokens.
+    #[serde(with = "crate::geometry::cell_union_tokens")]
     S2Cells(CellUnion),
     WebMercatorRect(WebMercatorRect),
 }
@@ -26,6 +31,11 @@ impl Default for PointLocation {
 }
 
 impl PointLocation {
+    /// The points in the S2 cells with the given tokens, e.g. "89c25".
+    pub fn from_s2_tokens(tokens: &[impl AsRef<str>]) -> Result<Self> {
+        Ok(PointLocation::S2Cells(cell_union_from_tokens(tokens)?))
+    }
+
     pub fn get_point_culling(&self) -> Box<dyn PointCulling> {
         match &self {
             PointLocation::AllPoints => Box::new(AllPoints {}),
@@ -69,6 +79,73 @@ pub struct PointQuery<'a> {
     pub location: PointLocation,
     #[serde(borrow)]
     pub filter_intervals: HashMap<&'a str, ClosedInterval<f64>>,
+    /// If set, point clouds with levels of detail may answer the query with the points of
+    /// coarser nodes only, as long as every skipped point is at most this far from a point of
+    /// the node that replaces it. This is much faster for queries that only need an overview,
+    /// e.g. for visualization. The query plan lists the approximated regions.
+    #[serde(default)]
+    pub max_error_m: Option<f64>,
+}
+
+/// How a point cloud finds the nodes that might contain the points of a query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryStrategy {
+    /// All nodes without any tests, for queries that cover the whole point cloud.
+    AllNodes,
+    /// Descends the tree from the root, testing each node on the way.
+    Traversal,
+    /// Only tests the nodes within a covering of the query, e.g. the S2 cells within a cell
+    /// covering, or the octree nodes below the smallest node containing the query.
+    Covering,
+    /// Tests every node, which is cheapest for point clouds with few nodes.
+    Scan,
+}
+
+/// A region whose points are approximated by the points of a coarser node in a query with a
+/// maximum error, see 'PointQuery::max_error_m'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApproximatedRegion<Id> {
+    /// The node covering the region. Its descendants are skipped.
+    pub node_id: Id,
+    pub level: u8,
+    /// The largest possible distance of a skipped point to the nearest point of the node.
+    pub max_error_m: f64,
+}
+
+/// The nodes for a query and how they were found, see 'PointCloud::plan_query'.
+#[derive(Clone, Debug)]
+pub struct QueryPlan<Id> {
+    pub strategy: QueryStrategy,
+    pub node_ids: Vec<Id>,
+    /// The number of nodes of the point cloud.
+    pub num_nodes: usize,
+    /// The number of nodes that were tested against the query location.
+    pub num_tested_nodes: usize,
+    /// The regions that are read at a coarser level for queries with a maximum error. Everything
+    /// else is read at full resolution.
+    pub approximated_regions: Vec<ApproximatedRegion<Id>>,
+}
+
+impl<Id> fmt::Display for QueryPlan<Id> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: selected {} of {} nodes, tested {}",
+            self.strategy,
+            self.node_ids.len(),
+            self.num_nodes,
+            self.num_tested_nodes
+        )?;
+        if let Some(coarsest) = self.approximated_regions.iter().map(|r| r.level).min() {
+            write!(
+                f,
+                ", approximated {} regions from level {}",
+                self.approximated_regions.len(),
+                coarsest
+            )?;
+        }
+        Ok(())
+    }
 }
 
 /// Iterator over the points of a point cloud node within the specified PointCulling
@@ -169,7 +246,27 @@ where
 pub trait PointCloud: Sync {
     type Id: ToString + Send + Copy;
     fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id>;
+    /// Chooses how to find the nodes that might contain points matching 'query', depending on
+    /// its extent and the number of nodes, and finds them. Point clouds that keep per-node
+    /// attribute statistics can leave out nodes that cannot match the filter intervals.
+    fn plan_query(&self, query: &PointQuery) -> QueryPlan<Self::Id>;
+    /// The nodes that might contain points matching 'query'.
+    fn nodes_for_query(&self, query: &PointQuery) -> Vec<Self::Id> {
+        self.plan_query(query).node_ids
+    }
+    /// The offsets that are added to the colors and intensities of the queried points, if any.
+    fn radiometric_correction(&self) -> Option<&RadiometricCorrection> {
+        None
+    }
+    /// Other names under which queries can request the attributes, if any.
+    fn attribute_aliases(&self) -> Option<&AttributeAliases> {
+        None
+    }
+    /// The data type of 'attribute', which may be an alias, if this point cloud stores it.
+    fn stored_attribute_type(&self, attribute: &str) -> Result<Option<AttributeDataType>>;
     fn encoding_for_node(&self, id: Self::Id) -> Encoding;
+    /// The number of points stored in the node, whether or not they match a query.
+    fn num_points_in_node(&self, id: Self::Id) -> usize;
     /// Return all points in the selected node.
     fn points_in_node(
         &self,
@@ -177,6 +274,9 @@ pub trait PointCloud: Sync {
         node_id: Self::Id,
         batch_size: usize,
     ) -> Result<NodeIterator>;
+    /// Hints that the points of 'node_ids' with 'attributes' will be read soon, so that remote
+    /// data providers can fetch them ahead, see 'DataProvider::prefetch'. Does nothing by default.
+    fn prefetch(&self, _node_ids: &[Self::Id], _attributes: &[&str]) {}
     fn bounding_box(&self) -> &Aabb;
 
     /// Return the points matching the query in the selected node.
@@ -192,19 +292,46 @@ pub trait PointCloud: Sync {
     where
         F: FnMut(PointsBatch) -> Result<()>,
     {
-        let filter_intervals = &query.filter_intervals;
-        let node_iterator = self.points_in_node(&query.attributes, node_id, batch_size)?;
+        // Aliases are read under the names of the stored attributes and renamed back afterwards.
+        let aliases = self.attribute_aliases();
+        let attributes: Vec<&str> = query
+            .attributes
+            .iter()
+            .map(|a| resolve_alias(aliases, a))
+            .collect();
+        let filter_intervals: HashMap<&str, ClosedInterval<f64>> = query
+            .filter_intervals
+            .iter()
+            .map(|(attribute, interval)| (resolve_alias(aliases, attribute), *interval))
+            .collect();
+        let node_iterator = self.points_in_node(&attributes, node_id, batch_size)?;
+        // The filter intervals apply to the stored, uncorrected values.
+        let correction = self.radiometric_correction();
+        let mut callback = callback;
+        let callback = |mut batch: PointsBatch| {
+            if let Some(correction) = correction {
+                correction.apply_to_batch(&mut batch);
+            }
+            if let Some(aliases) = aliases {
+                aliases.rename_to_requested(&mut batch, &query.attributes);
+            }
+            callback(batch)
+        };
 
         dispatch_point_location!(
             stream,
             &query.location,
-            filter_intervals,
+            &filter_intervals,
             node_iterator,
             callback
         )
     }
 }
 
+fn resolve_alias<'a>(aliases: Option<&'a AttributeAliases>, name: &'a str) -> &'a str {
+    aliases.map_or(name, |aliases| aliases.resolve(name))
+}
+
 // TODO(nnmm): Instead of having this helper function, make stream_points_for_query_in_node
 // accept a T: PointCulling, so we can dispatch to this function directly
 fn stream<'a, T: PointCulling + Clone, F: FnMut(PointsBatch) -> Result<()>>(
@@ -222,6 +349,24 @@ fn stream<'a, T: PointCulling + Clone, F: FnMut(PointsBatch) -> Result<()>>(
     .try_for_each(callback)
 }
 
+fn retain_culled<T: PointCulling>(batch: &mut PointsBatch, culling: &T) {
+    let keep: Vec<bool> = batch
+        .position
+        .iter()
+        .map(|pos| culling.contains(pos))
+        .collect();
+    batch.retain(&keep);
+}
+
+/// Removes the points of 'batch' outside of 'location', e.g. to answer a query from the points of
+/// a node that were read for a larger one.
+pub fn retain_in_location(location: &PointLocation, batch: &mut PointsBatch) {
+    if let PointLocation::AllPoints = location {
+        return;
+    }
+    dispatch_point_location!(retain_culled, location, batch)
+}
+
 /// Iterator on point batches
 pub struct ParallelIterator<'a, C> {
     point_clouds: &'a [C],
@@ -259,16 +404,15 @@ where
         // get thread safe fifo
         let jobs = Injector::<(&C, C::Id)>::new();
         let mut number_of_jobs = 0;
-        self.point_clouds
-            .iter()
-            .flat_map(|point_cloud| {
-                std::iter::repeat(point_cloud)
-                    .zip(point_cloud.nodes_in_location(&self.point_query.location))
-            })
-            .for_each(|(node_id, point_cloud)| {
-                jobs.push((node_id, point_cloud));
+        for point_cloud in self.point_clouds {
+            let node_ids = point_cloud.nodes_for_query(&self.point_query);
+            // Remote point clouds can fetch the nodes while the first ones are processed.
+            point_cloud.prefetch(&node_ids, &self.point_query.attributes);
+            for node_id in node_ids {
+                jobs.push((point_cloud, node_id));
                 number_of_jobs += 1;
-            });
+            }
+        }
 
         // operate on nodes with limited number of threads
         crossbeam::scope(|s| {
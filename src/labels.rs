@@ -0,0 +1,266 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Names and colors of per-point labels, e.g. the classes of a semantic segmentation.
+
+use crate::color::Color;
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::octree::{upgrade_meta_proto_to_current, OctreeMeta};
+use crate::proto;
+use crate::{AttributeDataType, PointCloudMeta};
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// The attributes that hold label ids, in order of preference: "label" with u16 ids, e.g. the
+/// classes of a semantic segmentation, and "class" with u8 ids, e.g. the classification of LAS
+/// files.
+pub const LABEL_ATTRIBUTES: [&str; 2] = ["label", "class"];
+
+/// The color of labels that are not in the palette.
+const UNKNOWN_LABEL_COLOR: Color<u8> = Color {
+    red: 128,
+    green: 128,
+    blue: 128,
+    alpha: 255,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub color: Color<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelPalette {
+    /// The U8 or U16 attribute that holds the label ids.
+    pub attribute: String,
+    pub labels: BTreeMap<u16, Label>,
+}
+
+impl LabelPalette {
+    pub fn new(attribute: impl Into<String>) -> Self {
+        LabelPalette {
+            attribute: attribute.into(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Reads a palette from a text file with one 'id,name,red,green,blue' line per label, with
+    /// colors in [0, 255]. Empty lines and lines starting with '#' are ignored.
+    pub fn from_csv_file(attribute: impl Into<String>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut palette = Self::new(attribute);
+        let file = File::open(path).chain_err(|| format!("Could not open {}.", path.display()))?;
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                ErrorKind::InvalidInput(format!(
+                    "{}:{}: Expected 'id,name,red,green,blue', got '{}'.",
+                    path.display(),
+                    line_number + 1,
+                    line
+                ))
+            };
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            if fields.len() != 5 {
+                return Err(invalid().into());
+            }
+            let parse = |s: &str| s.parse::<u8>().map_err(|_| invalid());
+            let color = Color {
+                red: parse(fields[2])?,
+                green: parse(fields[3])?,
+                blue: parse(fields[4])?,
+                alpha: 255,
+            };
+            palette.labels.insert(
+                fields[0].parse::<u16>().map_err(|_| invalid())?,
+                Label {
+                    name: fields[1].to_string(),
+                    color,
+                },
+            );
+        }
+        Ok(palette)
+    }
+
+    pub fn color(&self, id: u16) -> Color<u8> {
+        self.labels
+            .get(&id)
+            .map_or(UNKNOWN_LABEL_COLOR, |label| label.color)
+    }
+
+    pub fn from_proto(proto: &proto::LabelPalette) -> Result<Self> {
+        let mut labels = BTreeMap::new();
+        for label in proto.get_labels() {
+            let id = u16::try_from(label.id).map_err(|_| {
+                ErrorKind::InvalidInput(format!(
+                    "Label id {} of '{}' is out of range.",
+                    label.id,
+                    label.get_name()
+                ))
+            })?;
+            let color = label.get_color();
+            // Rounding makes sure that colors survive the roundtrip through floats.
+            let to_u8 = |c: f32| (c * 255.).round() as u8;
+            labels.insert(
+                id,
+                Label {
+                    name: label.get_name().to_string(),
+                    color: Color {
+                        red: to_u8(color.red),
+                        green: to_u8(color.green),
+                        blue: to_u8(color.blue),
+                        alpha: to_u8(color.alpha),
+                    },
+                },
+            );
+        }
+        Ok(LabelPalette {
+            attribute: proto.get_attribute().to_string(),
+            labels,
+        })
+    }
+
+    /// Fails if some of the ids cannot be stored in an attribute of 'data_type', e.g. ids above
+    /// 255 for the U8 ids of "class".
+    pub fn check_ids(&self, data_type: AttributeDataType) -> Result<()> {
+        let out_of_range: Vec<String> = match data_type {
+            AttributeDataType::U8 => self
+                .labels
+                .keys()
+                .filter(|id| u8::try_from(**id).is_err())
+                .map(u16::to_string)
+                .collect(),
+            AttributeDataType::U16 => Vec::new(),
+            other => {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Label ids must be stored as U8 or U16, not {:?}.",
+                    other
+                ))
+                .into())
+            }
+        };
+        if !out_of_range.is_empty() {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Label ids {} do not fit the {:?} attribute '{}'.",
+                out_of_range.join(", "),
+                data_type,
+                self.attribute
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn to_proto(&self) -> proto::LabelPalette {
+        let mut proto = proto::LabelPalette::new();
+        proto.set_attribute(self.attribute.clone());
+        for (id, label) in &self.labels {
+            let color = label.color.to_f32();
+            let mut color_proto = proto::Color::new();
+            color_proto.set_red(color.red);
+            color_proto.set_green(color.green);
+            color_proto.set_blue(color.blue);
+            color_proto.set_alpha(color.alpha);
+            let mut label_proto = proto::Label::new();
+            label_proto.set_id(u32::from(*id));
+            label_proto.set_name(label.name.clone());
+            label_proto.set_color(color_proto);
+            proto.mut_labels().push(label_proto);
+        }
+        proto
+    }
+
+    /// Returns the palette stored in 'meta', if any.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Result<Option<Self>> {
+        if meta.has_label_palette() {
+            Ok(Some(Self::from_proto(meta.get_label_palette())?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Decodes the label ids of a node from the raw 'data' of an attribute of 'data_type'. U8 ids
+/// are widened.
+pub fn decode_label_ids(data: &[u8], data_type: AttributeDataType) -> Result<Vec<u16>> {
+    match data_type {
+        AttributeDataType::U8 => Ok(data.iter().map(|id| u16::from(*id)).collect()),
+        AttributeDataType::U16 => Ok(data.chunks_exact(2).map(LittleEndian::read_u16).collect()),
+        other => Err(ErrorKind::InvalidInput(format!(
+            "Label ids must be stored as U8 or U16, not {:?}.",
+            other
+        ))
+        .into()),
+    }
+}
+
+/// Stores 'palette' in the meta file of the point cloud in 'directory', which must exist. Fails
+/// if its ids do not fit the attribute of the palette.
+pub fn write_label_palette(directory: impl AsRef<Path>, palette: &LabelPalette) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    let data_type = if meta.has_octree() {
+        OctreeMeta::from_proto(&meta)?
+            .attribute_data_types()
+            .get(&palette.attribute)
+            .copied()
+    } else {
+        meta.get_s2()
+            .get_attributes()
+            .iter()
+            .find(|attribute| attribute.name == palette.attribute)
+            .map(|attribute| AttributeDataType::from_proto(attribute.data_type))
+            .transpose()?
+    };
+    if let Some(data_type) = data_type {
+        palette.check_ids(data_type)?;
+    }
+    data_provider.update_meta_proto(|meta| meta.set_label_palette(palette.to_proto()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_ids_out_of_range() {
+        let mut palette = LabelPalette::new("class");
+        let label = Label {
+            name: "building".to_string(),
+            color: UNKNOWN_LABEL_COLOR,
+        };
+        palette.labels.insert(6, label.clone());
+        assert!(palette.check_ids(AttributeDataType::U8).is_ok());
+        palette.labels.insert(300, label);
+        assert!(palette.check_ids(AttributeDataType::U8).is_err());
+        assert!(palette.check_ids(AttributeDataType::U16).is_ok());
+
+        let mut proto = palette.to_proto();
+        assert_eq!(LabelPalette::from_proto(&proto).unwrap(), palette);
+        proto.mut_labels()[0].set_id(70_000);
+        assert!(LabelPalette::from_proto(&proto).is_err());
+    }
+}
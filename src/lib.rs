@@ -19,17 +19,28 @@ pub mod math;
 
 #[macro_use]
 pub mod attributes;
+pub mod calibration;
 pub mod color;
 pub mod data_provider;
 // Workaround for https://github.com/rust-lang-nursery/error-chain/issues/254
 #[allow(deprecated)]
 pub mod errors;
+pub mod fingerprint;
 pub mod geometry;
 #[macro_use]
 pub mod iterator;
+pub mod labels;
+pub mod maintenance;
 pub mod octree;
+pub mod profile;
+pub mod provenance;
 pub mod read_write;
 pub mod s2_cells;
+pub mod spatial_join;
+pub mod thumbnails;
+#[cfg(feature = "build")]
+pub mod training_tiles;
+#[cfg(feature = "build")]
 pub mod utils;
 
 use errors::Result;
@@ -62,8 +73,8 @@ use attributes::{AttributeData, AttributeDataType};
 #[derive(Debug, Clone)]
 pub struct Point {
     pub position: Point3<f64>,
-    // TODO(sirver): Make color optional, we might not always have it.
-    pub color: color::Color<u8>,
+    // Not all point clouds have colors, e.g. those from lidars that only measure intensity.
+    pub color: Option<color::Color<u8>>,
 
     // The intensity of the point if it exists. This value is usually handed through directly by a
     // sensor and has therefore no defined range - or even meaning.
@@ -75,6 +86,8 @@ pub fn attribute_extension(attribute: &str) -> &str {
     match attribute {
         "position" => "xyz",
         "color" => "rgb",
+        // The color tiles of the sdl_viewer's terrain.
+        "terrain_color" => "color",
         _ => attribute,
     }
 }
@@ -150,6 +163,35 @@ impl PointsBatch {
         }
     }
 
+    /// Checks that all attributes have as many entries as there are positions and, if
+    /// 'require_finite_positions' is set, that no position has a NaN or infinite coordinate.
+    pub fn validate(&self, require_finite_positions: bool) -> std::result::Result<(), String> {
+        let num_points = self.position.len();
+        for (name, data) in &self.attributes {
+            if data.len() != num_points {
+                return Err(format!(
+                    "Attribute '{}' has {} entries, but the batch has {} positions.",
+                    name,
+                    data.len(),
+                    num_points
+                ));
+            }
+        }
+        if require_finite_positions {
+            if let Some(i) = self
+                .position
+                .iter()
+                .position(|p| !p.coords.iter().all(|c| c.is_finite()))
+            {
+                return Err(format!(
+                    "Position {} is not finite: {:?}.",
+                    i, self.position[i]
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_attribute_vec<'a, T>(
         &'a self,
         key: impl AsRef<str>,
@@ -190,4 +232,106 @@ impl PointsBatch {
     }
 }
 
+/// The attribute names and data types that the 'PointsBatch'es of a stream are expected to have.
+/// Writers check incoming batches against it, so that malformed batches are reported where they
+/// enter instead of failing somewhere inside the writer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    attributes: BTreeMap<String, AttributeDataType>,
+}
+
+impl Schema {
+    pub fn new(attributes: impl IntoIterator<Item = (String, AttributeDataType)>) -> Self {
+        Self {
+            attributes: attributes.into_iter().collect(),
+        }
+    }
+
+    pub fn from_batch(batch: &PointsBatch) -> Self {
+        Self::new(
+            batch
+                .attributes
+                .iter()
+                .map(|(name, data)| (name.clone(), data.data_type())),
+        )
+    }
+
+    pub fn attributes(&self) -> &BTreeMap<String, AttributeDataType> {
+        &self.attributes
+    }
+
+    /// Returns an error describing the first difference if 'batch' does not have exactly the
+    /// attributes of this schema, or if it is not valid on its own.
+    pub fn assert_matches(&self, batch: &PointsBatch) -> std::result::Result<(), String> {
+        for (name, data_type) in &self.attributes {
+            match batch.attributes.get(name) {
+                Some(data) if data.data_type() == *data_type => {}
+                Some(data) => {
+                    return Err(format!(
+                        "Attribute '{}' has data type '{:?}', expected '{:?}'.",
+                        name,
+                        data.data_type(),
+                        data_type
+                    ))
+                }
+                None => return Err(format!("Attribute '{}' is missing.", name)),
+            }
+        }
+        if let Some(name) = batch
+            .attributes
+            .keys()
+            .find(|name| !self.attributes.contains_key(*name))
+        {
+            return Err(format!("Attribute '{}' is not part of the schema.", name));
+        }
+        batch.validate(false)
+    }
+}
+
 pub use point_viewer_proto_rust::proto;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    fn batch(num_points: usize, num_colors: usize) -> PointsBatch {
+        PointsBatch {
+            position: vec![Point3::new(1., 2., 3.); num_points],
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_colors]),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(batch(3, 3).validate(true).is_ok());
+        assert!(batch(3, 2).validate(false).is_err());
+        let mut non_finite = batch(3, 3);
+        non_finite.position[1].y = std::f64::NAN;
+        assert!(non_finite.validate(false).is_ok());
+        assert!(non_finite.validate(true).is_err());
+    }
+
+    #[test]
+    fn test_schema_assert_matches() {
+        let schema = Schema::new(vec![("color".to_string(), AttributeDataType::U8Vec3)]);
+        assert_eq!(schema, Schema::from_batch(&batch(3, 3)));
+        assert!(schema.assert_matches(&batch(3, 3)).is_ok());
+        assert!(schema.assert_matches(&batch(3, 1)).is_err());
+
+        let mut extra = batch(3, 3);
+        extra
+            .attributes
+            .insert("intensity".to_string(), AttributeData::F32(vec![1.; 3]));
+        assert!(schema.assert_matches(&extra).is_err());
+
+        let wrong_type = Schema::new(vec![("color".to_string(), AttributeDataType::F64Vec3)]);
+        assert!(wrong_type.assert_matches(&batch(3, 3)).is_err());
+        assert!(Schema::new(vec![]).assert_matches(&batch(3, 3)).is_err());
+    }
+}
@@ -0,0 +1,982 @@
+//! Storage maintenance: finds and cleans up inconsistencies between the meta of a point cloud on
+//! disk and its node files, as left behind by interrupted generations or manual copies, and
+//! reports how the storage and the points are distributed.
+
+use crate::attribute_extension;
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::octree::{
+    find_unreachable_nodes, upgrade_meta_proto_to_current, NodeId, Occupancy, OctreeMeta,
+};
+use crate::proto;
+use crate::read_write::{
+    check_layout, deinterleave, interleave, read_layout_header_of_file, split_layout_header,
+    write_layout_header, NodeLayout, PositionEncoding, INTERLEAVED, LAYOUT_HEADER_LEN,
+};
+use crate::s2_cells::cell_id_from_proto;
+use crate::{AttributeDataType, PointCloudMeta};
+use nalgebra::Point3;
+use nav_types::{ECEF, WGS84};
+use s2::cell::Cell;
+use s2::latlng::LatLng;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The attributes every node of an octree has files for. Color, intensity, alpha and class are
+/// optional.
+const REQUIRED_OCTREE_ATTRIBUTES: [&str; 1] = ["position"];
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Node files that no node in the meta refers to, with their size in bytes.
+    pub orphaned_files: BTreeMap<PathBuf, u64>,
+    /// Nodes in the meta with the files that are missing for them.
+    pub missing_files: BTreeMap<String, Vec<PathBuf>>,
+    /// Octree nodes that cannot be reached from the root once the nodes with missing files are
+    /// removed, because one of their ancestors is missing.
+    pub unreachable_nodes: BTreeSet<String>,
+    /// Node files whose layout header contradicts the meta, with what is wrong.
+    pub layout_mismatches: BTreeMap<PathBuf, String>,
+    /// Node files written before there were layout headers, with the layout that the meta
+    /// implies for them. They are read fine, 'add_layout_headers' adds the header.
+    pub files_without_layout: BTreeMap<PathBuf, NodeLayout>,
+}
+
+impl GcReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_files.is_empty()
+            && self.missing_files.is_empty()
+            && self.unreachable_nodes.is_empty()
+            && self.layout_mismatches.is_empty()
+    }
+
+    pub fn orphaned_bytes(&self) -> u64 {
+        self.orphaned_files.values().sum()
+    }
+}
+
+/// Whether 'stem' looks like the name of an octree node or an S2 cell. Other files, like the
+/// meta, are never touched.
+fn is_node_stem(stem: &str, is_s2: bool) -> bool {
+    if is_s2 {
+        !stem.is_empty() && stem.chars().all(|c| c.is_ascii_hexdigit())
+    } else {
+        stem.starts_with('r') && stem[1..].chars().all(|c| ('0'..='7').contains(&c))
+    }
+}
+
+/// The node names and the attributes each of them must have files for.
+fn required_files(meta: &proto::Meta) -> Result<(bool, BTreeMap<String, Vec<String>>)> {
+    if meta.has_octree() {
+        // With interleaved attributes, the positions are in the interleaved file.
+        let attributes: Vec<String> = if meta.get_octree().get_interleaved_attributes().is_empty() {
+            REQUIRED_OCTREE_ATTRIBUTES
+                .iter()
+                .map(|a| a.to_string())
+                .collect()
+        } else {
+            vec![INTERLEAVED.to_string()]
+        };
+        let nodes = meta
+            .get_octree()
+            .get_nodes()
+            .iter()
+            .map(|node| {
+                (
+                    NodeId::from_proto(node.get_id()).to_string(),
+                    attributes.clone(),
+                )
+            })
+            .collect();
+        Ok((false, nodes))
+    } else if meta.has_s2() {
+        let s2 = meta.get_s2();
+        let attributes: Vec<String> = std::iter::once("position".to_string())
+            .chain(s2.get_attributes().iter().map(|a| a.name.clone()))
+            .collect();
+        let nodes = s2
+            .get_cells()
+            .iter()
+            .map(|cell| Ok((cell_id_from_proto(cell)?.to_token(), attributes.clone())))
+            .collect::<Result<_>>()?;
+        Ok((true, nodes))
+    } else {
+        Err(
+            ErrorKind::InvalidInput("Meta describes neither an octree nor S2 cells".to_string())
+                .into(),
+        )
+    }
+}
+
+/// The layouts that the files of each node must have according to the meta, for all attributes
+/// that the node might have files for.
+fn expected_layouts(meta: &proto::Meta) -> Result<BTreeMap<String, BTreeMap<String, NodeLayout>>> {
+    let mut layouts = BTreeMap::new();
+    if meta.has_octree() {
+        // The attributes listed in the meta, or the standard ones for octrees that list none.
+        let octree_meta = OctreeMeta::from_proto(meta)?;
+        // Interleaved files have no header.
+        let interleaved_attributes = octree_meta.interleaved_attributes();
+        for node in meta.get_octree().get_nodes() {
+            let mut node_layouts = BTreeMap::new();
+            if interleaved_attributes.is_empty() {
+                let position_encoding = PositionEncoding::from_proto(node.position_encoding)?;
+                node_layouts.insert(
+                    "position".to_string(),
+                    NodeLayout::Position(Some(position_encoding)),
+                );
+            }
+            for (attribute, data_type) in octree_meta.attribute_data_types() {
+                if !interleaved_attributes.contains(attribute) {
+                    node_layouts.insert(attribute.clone(), NodeLayout::Attribute(*data_type));
+                }
+            }
+            layouts.insert(NodeId::from_proto(node.get_id()).to_string(), node_layouts);
+        }
+    } else if meta.has_s2() {
+        let s2 = meta.get_s2();
+        let mut cell_layouts = BTreeMap::new();
+        cell_layouts.insert("position".to_string(), NodeLayout::Position(None));
+        for attribute in s2.get_attributes() {
+            let data_type = AttributeDataType::from_proto(attribute.data_type)?;
+            cell_layouts.insert(attribute.name.clone(), NodeLayout::Attribute(data_type));
+        }
+        for cell in s2.get_cells() {
+            layouts.insert(cell_id_from_proto(cell)?.to_token(), cell_layouts.clone());
+        }
+    }
+    Ok(layouts)
+}
+
+/// Compares the node files in 'directory' with the nodes in its meta.
+pub fn find_inconsistencies(directory: &Path) -> Result<GcReport> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    };
+    let meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    let (is_s2, nodes) = required_files(&meta)?;
+
+    let mut report = GcReport::default();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => stem,
+            None => continue,
+        };
+        if is_node_stem(stem, is_s2) && !nodes.contains_key(stem) {
+            report
+                .orphaned_files
+                .insert(path.clone(), entry.metadata()?.len());
+        }
+    }
+    for (node, attributes) in &nodes {
+        let missing: Vec<PathBuf> = attributes
+            .iter()
+            .map(|attribute| {
+                directory
+                    .join(node)
+                    .with_extension(attribute_extension(attribute))
+            })
+            .filter(|path| !path.exists())
+            .collect();
+        if !missing.is_empty() {
+            report.missing_files.insert(node.clone(), missing);
+        }
+    }
+    for (node, layouts) in expected_layouts(&meta)? {
+        for (attribute, expected) in layouts {
+            let path = directory
+                .join(&node)
+                .with_extension(attribute_extension(&attribute));
+            if !path.exists() {
+                continue;
+            }
+            match read_layout_header_of_file(&path)
+                .and_then(|layout| check_layout(&layout, &expected).map(|_| layout))
+            {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    report.files_without_layout.insert(path, expected);
+                }
+                Err(err) => {
+                    report.layout_mismatches.insert(path, err.to_string());
+                }
+            }
+        }
+    }
+    if !is_s2 {
+        let complete_nodes: Vec<NodeId> = nodes
+            .keys()
+            .filter(|node| !report.missing_files.contains_key(*node))
+            .map(|node| node.parse().unwrap())
+            .collect();
+        report.unreachable_nodes = find_unreachable_nodes(&complete_nodes)
+            .iter()
+            .map(NodeId::to_string)
+            .collect();
+    }
+    Ok(report)
+}
+
+/// Adds the layout header to the files of the report that were written before there were
+/// headers. Each file is replaced atomically, so the point cloud can be read meanwhile.
+pub fn add_layout_headers(report: &GcReport) -> Result<()> {
+    for (path, layout) in &report.files_without_layout {
+        let data = fs::read(path).chain_err(|| format!("Could not read {}", path.display()))?;
+        // Checked again in case the file was replaced since the report.
+        if split_layout_header(&data)?.0.is_some() {
+            continue;
+        }
+        let mut with_header = Vec::with_capacity(LAYOUT_HEADER_LEN + data.len());
+        write_layout_header(&mut with_header, layout)?;
+        with_header.extend_from_slice(&data);
+        let temporary = path.with_extension("layout_tmp");
+        fs::write(&temporary, &with_header)
+            .and_then(|_| fs::rename(&temporary, path))
+            .chain_err(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Deletes the orphaned files of the report.
+pub fn delete_orphaned_files(report: &GcReport) -> Result<()> {
+    for path in report.orphaned_files.keys() {
+        fs::remove_file(path).chain_err(|| format!("Could not delete {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Removes the nodes with missing files and the unreachable ones from the meta in 'directory', so
+/// that the point cloud can be read again. Their remaining files become orphans. The meta is
+/// written at the current version.
+pub fn remove_incomplete_nodes(directory: &Path, report: &GcReport) -> Result<()> {
+    let incomplete: BTreeSet<&str> = report
+        .missing_files
+        .keys()
+        .chain(&report.unreachable_nodes)
+        .map(String::as_str)
+        .collect();
+    let data_provider = OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    };
+    let mut meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    if meta.has_octree() {
+        let nodes: ::protobuf::RepeatedField<proto::OctreeNode> = meta
+            .mut_octree()
+            .take_nodes()
+            .into_iter()
+            .filter(|node| {
+                !incomplete.contains(NodeId::from_proto(node.get_id()).to_string().as_str())
+            })
+            .collect();
+        let node_ids: Vec<NodeId> = nodes
+            .iter()
+            .map(|node| NodeId::from_proto(node.get_id()))
+            .collect();
+        meta.mut_octree()
+            .set_occupancy(::protobuf::RepeatedField::from_vec(
+                Occupancy::from_node_ids(&node_ids).to_proto(),
+            ));
+        meta.mut_octree().set_nodes(nodes);
+    } else if meta.has_s2() {
+        let mut cells = Vec::new();
+        for cell in meta.mut_s2().take_cells() {
+            if !incomplete.contains(cell_id_from_proto(&cell)?.to_token().as_str()) {
+                cells.push(cell);
+            }
+        }
+        meta.mut_s2()
+            .set_cells(::protobuf::RepeatedField::from_vec(cells));
+    }
+    data_provider.update_meta_proto(|m| *m = meta)
+}
+
+/// Where the bytes of a point cloud are, see 'dataset_size'.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SizeReport {
+    pub total_bytes: u64,
+    pub bytes_per_attribute: BTreeMap<String, u64>,
+    pub bytes_per_level: BTreeMap<u8, u64>,
+    /// Keyed by the name of the node or cell at the region level containing the data.
+    pub bytes_per_region: BTreeMap<String, u64>,
+}
+
+impl SizeReport {
+    fn add(&mut self, attribute: &str, level: u8, region: &str, num_bytes: u64) {
+        self.total_bytes += num_bytes;
+        *self
+            .bytes_per_attribute
+            .entry(attribute.to_string())
+            .or_default() += num_bytes;
+        *self.bytes_per_level.entry(level).or_default() += num_bytes;
+        *self.bytes_per_region.entry(region.to_string()).or_default() += num_bytes;
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Total: {} bytes", self.total_bytes)?;
+        writeln!(f, "Per attribute:")?;
+        for (attribute, num_bytes) in &self.bytes_per_attribute {
+            writeln!(f, "  {}: {} bytes", attribute, num_bytes)?;
+        }
+        writeln!(f, "Per level:")?;
+        for (level, num_bytes) in &self.bytes_per_level {
+            writeln!(f, "  {}: {} bytes", level, num_bytes)?;
+        }
+        writeln!(f, "Per region:")?;
+        for (region, num_bytes) in &self.bytes_per_region {
+            writeln!(f, "  {}: {} bytes", region, num_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// The attributes an octree has, besides the position. Separately stored ones are assumed to be
+/// present in all nodes if the first one has them.
+fn octree_attributes(
+    data_provider: &dyn DataProvider,
+    meta: &proto::Meta,
+) -> Result<Vec<(String, AttributeDataType)>> {
+    let octree_meta = OctreeMeta::from_proto(meta)?;
+    let first_node = match meta.get_octree().get_nodes().first() {
+        Some(node) => NodeId::from_proto(node.get_id()).to_string(),
+        None => return Ok(Vec::new()),
+    };
+    let interleaved_attributes = meta.get_octree().get_interleaved_attributes();
+    let mut attributes = Vec::new();
+    for (attribute, data_type) in octree_meta.attribute_data_types() {
+        if interleaved_attributes.contains(attribute) {
+            attributes.push((attribute.clone(), *data_type));
+            continue;
+        }
+        match data_provider.data(&first_node, &[attribute.as_str()]) {
+            Ok(_) => attributes.push((attribute.clone(), *data_type)),
+            Err(ref err) if matches!(err.kind(), ErrorKind::NodeNotFound) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(attributes)
+}
+
+/// Summarizes how many bytes the point cloud takes up per attribute, per level and per region,
+/// where the regions are the nodes or cells at 'region_level' (or above for shallower data).
+/// The sizes are computed from the meta, so no node data is read.
+pub fn dataset_size(data_provider: &dyn DataProvider, region_level: u8) -> Result<SizeReport> {
+    let meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    let mut report = SizeReport::default();
+    if meta.has_octree() {
+        let attributes = octree_attributes(data_provider, &meta)?;
+        for node in meta.get_octree().get_nodes() {
+            let node_id = NodeId::from_proto(node.get_id());
+            let level = node_id.level();
+            let region = if level > region_level {
+                let index = node_id.index() >> (3 * u32::from(level - region_level));
+                NodeId::from_level_index(region_level, index)
+            } else {
+                node_id
+            }
+            .to_string();
+            let num_points = node.num_points as u64;
+            let position_encoding = PositionEncoding::from_proto(node.position_encoding)?;
+            let position_bytes = 3 * position_encoding.bytes_per_coordinate() as u64;
+            report.add("position", level, &region, num_points * position_bytes);
+            for (attribute, data_type) in &attributes {
+                let num_bytes = num_points * data_type.size_of() as u64;
+                report.add(attribute, level, &region, num_bytes);
+            }
+        }
+    } else if meta.has_s2() {
+        let s2 = meta.get_s2();
+        let mut attributes = Vec::new();
+        for attribute in s2.get_attributes() {
+            let data_type = AttributeDataType::from_proto(attribute.get_data_type())?;
+            attributes.push((attribute.get_name(), data_type));
+        }
+        for cell in s2.get_cells() {
+            let cell_id = cell_id_from_proto(cell)?;
+            let level = cell_id.level();
+            let region = if level > u64::from(region_level) {
+                cell_id.parent(u64::from(region_level))
+            } else {
+                cell_id
+            }
+            .to_token();
+            // Positions of S2 cells are stored as plain f64.
+            let position_bytes = 3 * std::mem::size_of::<f64>() as u64;
+            report.add(
+                "position",
+                level as u8,
+                &region,
+                cell.num_points * position_bytes,
+            );
+            for (attribute, data_type) in &attributes {
+                let num_bytes = cell.num_points * data_type.size_of() as u64;
+                report.add(attribute, level as u8, &region, num_bytes);
+            }
+        }
+    } else {
+        return Err(ErrorKind::InvalidInput(
+            "Meta describes neither an octree nor S2 cells".to_string(),
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+/// The percentiles of the node densities that 'dataset_stats' reports.
+const DENSITY_PERCENTILES: [f64; 5] = [5., 25., 50., 75., 95.];
+
+/// S2 cells are on the unit sphere, their areas are scaled with this.
+const EARTH_MEAN_RADIUS_M: f64 = 6_371_008.8;
+
+/// A quick characterization of a point cloud, see 'dataset_stats'.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DatasetStats {
+    pub num_points: u64,
+    pub num_nodes: usize,
+    pub nodes_per_level: BTreeMap<u8, usize>,
+    /// "points/m³" for octrees, where the density of the leaf nodes is computed from their
+    /// volume, and "points/m²" for S2 point clouds, where it is computed from the cell area.
+    pub density_unit: String,
+    /// Pairs of percentile and density.
+    pub density_percentiles: Vec<(f64, f64)>,
+    /// How many nodes were read to find out which attributes they have.
+    pub num_sampled_nodes: usize,
+    /// For each attribute, in how many of the sampled nodes it is present.
+    pub attribute_coverage: BTreeMap<String, usize>,
+    /// The number of points in each cell of a grid over the x and y extent of the bounding box,
+    /// row by row starting with the largest y. Nodes are counted in the cell of their center.
+    /// The axes are those of the point cloud's frame, so for a point cloud in ECEF, the grid is
+    /// not a map view.
+    pub density_grid: Vec<Vec<u64>>,
+}
+
+impl fmt::Display for DatasetStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Points: {} in {} nodes", self.num_points, self.num_nodes)?;
+        writeln!(f, "Nodes per level:")?;
+        for (level, num_nodes) in &self.nodes_per_level {
+            writeln!(f, "  {}: {}", level, num_nodes)?;
+        }
+        writeln!(f, "Density percentiles ({}):", self.density_unit)?;
+        for (percentile, density) in &self.density_percentiles {
+            writeln!(f, "  {}%: {:.3}", percentile, density)?;
+        }
+        writeln!(
+            f,
+            "Attribute coverage in {} sampled nodes:",
+            self.num_sampled_nodes
+        )?;
+        for (attribute, num_nodes) in &self.attribute_coverage {
+            writeln!(f, "  {}: {}", attribute, num_nodes)?;
+        }
+        writeln!(f, "Points per grid cell over x and y, largest y first:")?;
+        for row in &self.density_grid {
+            let row: Vec<String> = row.iter().map(u64::to_string).collect();
+            writeln!(f, "  {}", row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// What 'dataset_stats' needs to know about a node or cell.
+struct NodeSummary {
+    name: String,
+    level: u8,
+    num_points: u64,
+    center: Point3<f64>,
+    /// The volume or area for nodes whose density is reported.
+    extent: Option<f64>,
+}
+
+fn octree_node_summaries(meta: &proto::Meta) -> Vec<NodeSummary> {
+    let root_cube = Cube::bounding(&Aabb::from(meta.get_bounding_box()));
+    let nodes = meta.get_octree().get_nodes();
+    let node_ids: HashSet<NodeId> = nodes
+        .iter()
+        .map(|node| NodeId::from_proto(node.get_id()))
+        .collect();
+    nodes
+        .iter()
+        .map(|node| {
+            let node_id = NodeId::from_proto(node.get_id());
+            let cube = node_id.find_bounding_cube(&root_cube);
+            let is_leaf = node_id.children().all(|child| !node_ids.contains(&child));
+            NodeSummary {
+                name: node_id.to_string(),
+                level: node_id.level(),
+                num_points: node.num_points as u64,
+                center: Point3::from(cube.center()),
+                extent: Some(cube.edge_length().powi(3)).filter(|_| is_leaf),
+            }
+        })
+        .collect()
+}
+
+fn s2_cell_summaries(meta: &proto::Meta) -> Result<Vec<NodeSummary>> {
+    meta.get_s2()
+        .get_cells()
+        .iter()
+        .map(|cell| {
+            let cell_id = cell_id_from_proto(cell)?;
+            let lat_lng = LatLng::from(cell_id);
+            let ecef = ECEF::from(WGS84::from_radians_and_meters(
+                lat_lng.lat.rad(),
+                lat_lng.lng.rad(),
+                0.,
+            ));
+            let area = Cell::from(cell_id).exact_area() * EARTH_MEAN_RADIUS_M.powi(2);
+            Ok(NodeSummary {
+                name: cell_id.to_token(),
+                level: cell_id.level() as u8,
+                num_points: cell.num_points,
+                center: Point3::new(ecef.x(), ecef.y(), ecef.z()),
+                extent: Some(area),
+            })
+        })
+        .collect()
+}
+
+/// Characterizes a point cloud: how many points and nodes it has, how dense the nodes are, which
+/// attributes they have and where the points are. Everything but the attribute coverage comes
+/// from the meta. For that, up to 'num_sampled_nodes' nodes spread over the whole point cloud
+/// are checked for attribute files. The density grid has 'grid_size' x 'grid_size' cells.
+pub fn dataset_stats(
+    data_provider: &dyn DataProvider,
+    num_sampled_nodes: usize,
+    grid_size: usize,
+) -> Result<DatasetStats> {
+    let meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    let (nodes, density_unit, attributes) = if meta.has_octree() {
+        let octree_meta = OctreeMeta::from_proto(&meta)?;
+        let attributes: Vec<String> = octree_meta.attribute_data_types().keys().cloned().collect();
+        (octree_node_summaries(&meta), "points/m³", attributes)
+    } else if meta.has_s2() {
+        let attributes = meta
+            .get_s2()
+            .get_attributes()
+            .iter()
+            .map(|a| a.name.clone())
+            .collect();
+        (s2_cell_summaries(&meta)?, "points/m²", attributes)
+    } else {
+        return Err(ErrorKind::InvalidInput(
+            "Meta describes neither an octree nor S2 cells".to_string(),
+        )
+        .into());
+    };
+
+    let mut stats = DatasetStats {
+        num_nodes: nodes.len(),
+        density_unit: density_unit.to_string(),
+        density_grid: vec![vec![0; grid_size]; grid_size],
+        ..Default::default()
+    };
+    let bounding_box = Aabb::from(meta.get_bounding_box());
+    let diag = bounding_box.diag();
+    let grid_index = |value: f64, min: f64, extent: f64| {
+        // NaN for a flat bounding box, which casts to 0.
+        let index = ((value - min) / extent * grid_size as f64).floor() as usize;
+        index.min(grid_size.saturating_sub(1))
+    };
+    let mut densities = Vec::new();
+    for node in &nodes {
+        stats.num_points += node.num_points;
+        *stats.nodes_per_level.entry(node.level).or_default() += 1;
+        if let Some(extent) = node.extent {
+            densities.push(node.num_points as f64 / extent);
+        }
+        if grid_size > 0 {
+            let column = grid_index(node.center.x, bounding_box.min().x, diag.x);
+            let row = grid_index(node.center.y, bounding_box.min().y, diag.y);
+            stats.density_grid[grid_size - 1 - row][column] += node.num_points;
+        }
+    }
+    densities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    if !densities.is_empty() {
+        stats.density_percentiles = DENSITY_PERCENTILES
+            .iter()
+            .map(|percentile| {
+                let index = (percentile / 100. * (densities.len() - 1) as f64).round() as usize;
+                (*percentile, densities[index])
+            })
+            .collect();
+    }
+
+    let interleaved_attributes: &[String] = if meta.has_octree() {
+        meta.get_octree().get_interleaved_attributes()
+    } else {
+        &[]
+    };
+    let step = (nodes.len() / num_sampled_nodes.max(1)).max(1);
+    for node in nodes.iter().step_by(step).take(num_sampled_nodes) {
+        stats.num_sampled_nodes += 1;
+        for attribute in &attributes {
+            let file = if interleaved_attributes.contains(attribute) {
+                INTERLEAVED
+            } else {
+                attribute.as_str()
+            };
+            match data_provider.data(&node.name, &[file]) {
+                Ok(_) => {
+                    *stats
+                        .attribute_coverage
+                        .entry(attribute.clone())
+                        .or_default() += 1
+                }
+                Err(ref err) if matches!(err.kind(), ErrorKind::NodeNotFound) => {
+                    stats
+                        .attribute_coverage
+                        .entry(attribute.clone())
+                        .or_default();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// The octree meta of 'meta' with 'interleaved_attributes'.
+fn octree_meta_with_interleaved(
+    meta: &proto::Meta,
+    interleaved_attributes: &[&str],
+) -> Result<OctreeMeta> {
+    Ok(OctreeMeta::from_proto(meta)?.with_interleaved_attributes(
+        interleaved_attributes
+            .iter()
+            .map(|a| a.to_string())
+            .collect(),
+    ))
+}
+
+/// The file stems of the nodes of the octree in 'meta' with the encodings of their positions.
+fn octree_node_stems(
+    data_provider: &OnDiskDataProvider,
+    meta: &proto::Meta,
+) -> Result<Vec<(PathBuf, PositionEncoding)>> {
+    meta.get_octree()
+        .get_nodes()
+        .iter()
+        .map(|node| {
+            let node_id = NodeId::from_proto(node.get_id()).to_string();
+            let position_encoding = PositionEncoding::from_proto(node.position_encoding)?;
+            Ok((data_provider.stem(&node_id), position_encoding))
+        })
+        .collect()
+}
+
+/// Reads the data of a node file without its layout header.
+fn read_node_file(path: &Path) -> Result<Vec<u8>> {
+    let mut data = fs::read(path).chain_err(|| format!("Could not read {}", path.display()))?;
+    let header_len = data.len() - split_layout_header(&data)?.1.len();
+    data.drain(..header_len);
+    Ok(data)
+}
+
+/// Changes how the nodes of the octree in 'directory' store their data: the positions and
+/// 'attributes' are interleaved point by point into a single file per node, so that a data
+/// provider can serve them with one request instead of one per attribute. This pays off for
+/// providers with a high overhead per request, like HTTP servers and object stores. Attributes
+/// that are not listed keep their own files. Without attributes, every attribute gets its own file
+/// again. The old files are only deleted after the meta with the new layout replaced the old one,
+/// so the octree can be read during the conversion.
+pub fn set_interleaved_attributes(directory: &Path, attributes: &[&str]) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    };
+    let meta = upgrade_meta_proto_to_current(data_provider.meta_proto()?)?;
+    if !meta.has_octree() {
+        return Err(ErrorKind::InvalidInput(
+            "Only octrees can have interleaved attributes".to_string(),
+        )
+        .into());
+    }
+    let current = meta.get_octree().get_interleaved_attributes().to_vec();
+    if current == attributes {
+        return Ok(());
+    }
+    if !current.is_empty() {
+        // Changing the interleaved attributes goes through separate files, so that no file is
+        // read and written at the same time.
+        separate_interleaved_attributes(&data_provider, meta)?;
+        if attributes.is_empty() {
+            return Ok(());
+        }
+        return set_interleaved_attributes(directory, attributes);
+    }
+    let unique: BTreeSet<&str> = attributes.iter().cloned().collect();
+    if unique.len() != attributes.len() || unique.contains("position") {
+        return Err(ErrorKind::InvalidInput(format!(
+            "Cannot interleave {:?}: the position is always interleaved and every attribute \
+             can only be interleaved once.",
+            attributes
+        ))
+        .into());
+    }
+    interleave_attributes(&data_provider, meta, attributes)
+}
+
+fn interleave_attributes(
+    data_provider: &OnDiskDataProvider,
+    mut meta: proto::Meta,
+    attributes: &[&str],
+) -> Result<()> {
+    let names: Vec<&str> = std::iter::once("position")
+        .chain(attributes.iter().cloned())
+        .collect();
+    let octree_meta = octree_meta_with_interleaved(&meta, attributes)?;
+    let stems = octree_node_stems(data_provider, &meta)?;
+    for (stem, position_encoding) in &stems {
+        let bytes_per_point = octree_meta.interleaved_bytes_per_point(position_encoding)?;
+        let columns = names
+            .iter()
+            .map(|name| read_node_file(&stem.with_extension(attribute_extension(name))))
+            .collect::<Result<Vec<_>>>()?;
+        let columns: Vec<(&[u8], usize)> = columns
+            .iter()
+            .map(Vec::as_slice)
+            .zip(bytes_per_point)
+            .collect();
+        fs::write(stem.with_extension(INTERLEAVED), interleave(&columns)?)?;
+    }
+    meta.mut_octree()
+        .set_interleaved_attributes(::protobuf::RepeatedField::from_vec(
+            attributes.iter().map(|a| a.to_string()).collect(),
+        ));
+    data_provider.update_meta_proto(|m| *m = meta)?;
+    for (stem, _) in &stems {
+        for name in &names {
+            fs::remove_file(stem.with_extension(attribute_extension(name)))?;
+        }
+    }
+    Ok(())
+}
+
+fn separate_interleaved_attributes(
+    data_provider: &OnDiskDataProvider,
+    mut meta: proto::Meta,
+) -> Result<()> {
+    let interleaved_attributes = meta.get_octree().get_interleaved_attributes().to_vec();
+    let names: Vec<&str> = std::iter::once("position")
+        .chain(interleaved_attributes.iter().map(String::as_str))
+        .collect();
+    let octree_meta = octree_meta_with_interleaved(&meta, &names[1..])?;
+    let stems = octree_node_stems(data_provider, &meta)?;
+    for (stem, position_encoding) in &stems {
+        let bytes_per_point = octree_meta.interleaved_bytes_per_point(position_encoding)?;
+        let data = read_node_file(&stem.with_extension(INTERLEAVED))?;
+        for (name, column) in names.iter().zip(deinterleave(&data, &bytes_per_point)?) {
+            fs::write(stem.with_extension(attribute_extension(name)), column)?;
+        }
+    }
+    meta.mut_octree().clear_interleaved_attributes();
+    data_provider.update_meta_proto(|m| *m = meta)?;
+    for (stem, _) in &stems {
+        fs::remove_file(stem.with_extension(INTERLEAVED))?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "build"))]
+mod tests {
+    use super::*;
+    use crate::geometry::Aabb;
+    use crate::iterator::PointCloud;
+    use crate::octree::{Octree, OctreeBuilder};
+    use crate::{AttributeData, PointsBatch, NUM_POINTS_PER_BATCH};
+    use nalgebra::{Point3, Vector3};
+    use tempdir::TempDir;
+
+    fn build_colored_octree(num_points: usize) -> TempDir {
+        let batch = PointsBatch {
+            position: (0..num_points)
+                .map(|i| Point3::new(i as f64, i as f64, i as f64))
+                .collect(),
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let max = (num_points - 1) as f64;
+        let bounding_box = Aabb::new(Point3::origin(), Point3::new(max, max, max));
+        let tmp_dir = TempDir::new("octree").unwrap();
+        OctreeBuilder::new(1.0)
+            .build(&tmp_dir, bounding_box, vec![batch].into_iter())
+            .unwrap();
+        tmp_dir
+    }
+
+    #[test]
+    fn test_find_and_fix_inconsistencies() {
+        let num_points = 100;
+        let tmp_dir = build_colored_octree(num_points);
+        let directory = tmp_dir.path();
+        assert!(find_inconsistencies(directory).unwrap().is_clean());
+
+        let data_provider = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        };
+        let sizes = dataset_size(&data_provider, 1).unwrap();
+        // Uint8 positions and colors are three bytes per point.
+        assert_eq!(sizes.bytes_per_attribute["position"], 3 * num_points as u64);
+        assert_eq!(sizes.bytes_per_attribute["color"], 3 * num_points as u64);
+        assert_eq!(
+            sizes.total_bytes,
+            sizes.bytes_per_level.values().sum::<u64>()
+        );
+        assert_eq!(
+            sizes.total_bytes,
+            sizes.bytes_per_region.values().sum::<u64>()
+        );
+        let stats = dataset_stats(&data_provider, 4, 2).unwrap();
+        assert_eq!(stats.num_points, sizes.bytes_per_attribute["position"] / 3);
+        assert_eq!(stats.nodes_per_level[&0], 1);
+        assert_eq!(stats.num_sampled_nodes, 4.min(stats.num_nodes));
+        assert_eq!(stats.attribute_coverage["color"], stats.num_sampled_nodes);
+        // The octree was built with colors only, so it has no intensity.
+        assert!(!stats.attribute_coverage.contains_key("intensity"));
+        assert_eq!(stats.density_percentiles.len(), DENSITY_PERCENTILES.len());
+        let grid_points: u64 = stats.density_grid.iter().flatten().sum();
+        assert_eq!(grid_points, stats.num_points);
+
+        fs::write(directory.join("r7777777.xyz"), [0; 3]).unwrap();
+        fs::remove_file(directory.join("r.xyz")).unwrap();
+        let report = find_inconsistencies(directory).unwrap();
+        assert_eq!(
+            report.orphaned_files.keys().collect::<Vec<_>>(),
+            vec![&directory.join("r7777777.xyz")]
+        );
+        assert_eq!(report.orphaned_bytes(), 3);
+        assert_eq!(report.missing_files.keys().collect::<Vec<_>>(), vec!["r"]);
+
+        remove_incomplete_nodes(directory, &report).unwrap();
+        delete_orphaned_files(&find_inconsistencies(directory).unwrap()).unwrap();
+        assert!(find_inconsistencies(directory).unwrap().is_clean());
+        assert!(directory.join("meta.pb").exists());
+        assert!(!directory.join("r.xyz").exists());
+    }
+
+    #[test]
+    fn test_layout_headers() {
+        let tmp_dir = build_colored_octree(100);
+        let directory = tmp_dir.path();
+        let report = find_inconsistencies(directory).unwrap();
+        assert!(report.is_clean());
+        assert!(report.files_without_layout.is_empty());
+        let read_root = || {
+            let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+                directory: directory.to_path_buf(),
+            }))
+            .unwrap();
+            octree.get_node_data(&NodeId::from_level_index(0, 0))
+        };
+        let root = read_root().unwrap();
+
+        // Files from before there were headers are read the same and can be migrated.
+        let color_path = directory.join("r.rgb");
+        let data = fs::read(&color_path).unwrap();
+        fs::write(&color_path, split_layout_header(&data).unwrap().1).unwrap();
+        let report = find_inconsistencies(directory).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(
+            report.files_without_layout.keys().collect::<Vec<_>>(),
+            vec![&color_path]
+        );
+        assert_eq!(read_root().unwrap().color, root.color);
+        add_layout_headers(&report).unwrap();
+        assert_eq!(fs::read(&color_path).unwrap(), data);
+
+        // A header that contradicts the meta is an error instead of garbage.
+        let mut wrong = Vec::new();
+        write_layout_header(&mut wrong, &NodeLayout::Attribute(AttributeDataType::F32)).unwrap();
+        wrong.extend_from_slice(split_layout_header(&data).unwrap().1);
+        fs::write(&color_path, wrong).unwrap();
+        let report = find_inconsistencies(directory).unwrap();
+        assert!(!report.is_clean());
+        assert!(report.layout_mismatches.contains_key(&color_path));
+        assert!(read_root().is_err());
+    }
+
+    #[test]
+    fn test_layout_headers_of_listed_attributes() {
+        let tmp_dir = build_colored_octree(100);
+        let directory = tmp_dir.path();
+        let data_provider = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        };
+        // An attribute beyond the standard ones, e.g. added by a tool after the build.
+        data_provider
+            .update_meta_proto(|meta| {
+                let mut attribute = proto::Attribute::new();
+                attribute.set_name("point_source_id".to_string());
+                attribute.set_data_type(AttributeDataType::U16.to_proto());
+                meta.mut_octree().mut_attributes().push(attribute);
+            })
+            .unwrap();
+        let path = directory.join("r.point_source_id");
+        fs::write(&path, vec![0u8; 2 * 100]).unwrap();
+        let report = find_inconsistencies(directory).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(
+            report.files_without_layout.get(&path),
+            Some(&NodeLayout::Attribute(AttributeDataType::U16))
+        );
+    }
+
+    #[test]
+    fn test_interleaved_attributes() {
+        let tmp_dir = build_colored_octree(1000);
+        let directory = tmp_dir.path();
+        // The points, colors and node data of each node.
+        let read_nodes = || {
+            let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+                directory: directory.to_path_buf(),
+            }))
+            .unwrap();
+            let mut nodes = Vec::new();
+            for node_id in octree.node_ids_up_to_level(std::u8::MAX) {
+                let mut positions = Vec::new();
+                let mut colors = Vec::new();
+                for batch in octree
+                    .points_in_node(&["color"], node_id, NUM_POINTS_PER_BATCH)
+                    .unwrap()
+                {
+                    positions.extend(batch.position);
+                    match &batch.attributes["color"] {
+                        AttributeData::U8Vec3(color) => colors.extend(color.iter().cloned()),
+                        other => panic!("Unexpected color {:?}", other),
+                    }
+                }
+                let node_data = octree.get_node_data(&node_id).unwrap();
+                nodes.push((positions, colors, node_data.position, node_data.color));
+            }
+            nodes
+        };
+        let separate = read_nodes();
+
+        set_interleaved_attributes(directory, &["color"]).unwrap();
+        assert!(directory.join("r.interleaved").exists());
+        assert!(!directory.join("r.xyz").exists());
+        assert!(!directory.join("r.rgb").exists());
+        assert!(find_inconsistencies(directory).unwrap().is_clean());
+        assert_eq!(read_nodes(), separate);
+        assert!(set_interleaved_attributes(directory, &["color", "color"]).is_err());
+
+        set_interleaved_attributes(directory, &[]).unwrap();
+        assert!(!directory.join("r.interleaved").exists());
+        assert!(directory.join("r.rgb").exists());
+        assert_eq!(read_nodes(), separate);
+    }
+}
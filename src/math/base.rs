@@ -12,6 +12,12 @@ pub trait IntersectAabb {
     fn intersect_aabb(&self, aabb: &Aabb) -> bool;
 }
 
+/// Something that can compute its distance to an AABB, e.g. to visit nodes closest first.
+pub trait DistanceToAabb {
+    /// A lower bound of the Euclidean distance to 'aabb', which is 0 if they intersect.
+    fn distance_to_aabb(&self, aabb: &Aabb) -> f64;
+}
+
 /// We use this trait to allow an indirection: The geometry itself does not need to be able to
 /// efficiently do intersection tests with AABBs, because the geometry (e.g. an OBB) should not need
 /// to store data to support intersection tests (like separating axes).
@@ -32,6 +38,18 @@ impl IntersectAabb for CachedAxesIntersector {
     }
 }
 
+impl DistanceToAabb for CachedAxesIntersector {
+    fn distance_to_aabb(&self, aabb: &Aabb) -> f64 {
+        self.separation(&aabb.compute_corners())
+    }
+}
+
+impl DistanceToAabb for Aabb {
+    fn distance_to_aabb(&self, aabb: &Aabb) -> f64 {
+        Aabb::distance_to_aabb(self, aabb)
+    }
+}
+
 /// Use this macro as a crutch for the missing
 /// `impl<'a, S, T: ConvexPolyhedron> HasAabbIntersector<'a, S> for T`.
 macro_rules! has_aabb_intersector_for_convex_polyhedron {
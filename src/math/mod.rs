@@ -87,6 +87,19 @@ where
     pub fn contains(self, value: T) -> bool {
         self.lower_bound <= value && value <= self.upper_bound
     }
+
+    pub fn lower_bound(self) -> T {
+        self.lower_bound
+    }
+
+    pub fn upper_bound(self) -> T {
+        self.upper_bound
+    }
+
+    /// Whether both intervals have at least one value in common.
+    pub fn intersects(self, other: Self) -> bool {
+        self.lower_bound <= other.upper_bound && other.lower_bound <= self.upper_bound
+    }
 }
 
 impl<T> FromStr for ClosedInterval<T>
@@ -152,6 +152,17 @@ impl Intersector {
             &other.corners,
         )
     }
+
+    /// The largest gap between the two objects along any of the separating axes, or 0 if they
+    /// intersect. This is a lower bound of their distance, not the distance itself, see
+    /// [`separation`](fn.separation.html).
+    pub fn separation(&self, other: &Self) -> f64 {
+        separation(
+            self.separating_axes_iter(&other.edges, &other.face_normals),
+            &self.corners,
+            &other.corners,
+        )
+    }
 }
 
 /// Stores pre-computed separating axes for intersection tests.
@@ -167,6 +178,12 @@ impl CachedAxesIntersector {
     pub fn intersect(&self, corners: &[Point3<f64>]) -> Relation {
         sat(self.axes.iter().cloned(), &self.corners, corners)
     }
+
+    /// Like [`Intersector::separation`](struct.Intersector.html#method.separation), but using the
+    /// cached axes and the specified corner points. Also only a lower bound of the distance.
+    pub fn separation(&self, corners: &[Point3<f64>]) -> f64 {
+        separation(self.axes.iter().cloned(), &self.corners, corners)
+    }
 }
 
 /// See https://www.gamedev.net/forums/topic/694911-separating-axis-theorem-3d-polygons/ for more detail
@@ -193,6 +210,33 @@ where
     rel
 }
 
+/// Returns the largest gap between the projections of A and B onto any of the separating axes,
+/// or 0 if the projections overlap on all of them, i.e. if A and B intersect.
+///
+/// Since the axes are unit vectors, this is a lower bound of the Euclidean distance between A and
+/// B, which makes it suitable for ordering a traversal by distance. It is exact if the closest
+/// features of A and B are a face and a corner or two edges, which is the usual case for a query
+/// volume and a node that is not too close to it, but can underestimate the distance between
+/// nearby corners, e.g. of two diagonally offset boxes.
+pub fn separation<I>(
+    separating_axes: I,
+    corners_a: &[Point3<f64>],
+    corners_b: &[Point3<f64>],
+) -> f64
+where
+    I: IntoIterator<Item = Unit<Vector3<f64>>>,
+{
+    let mut max_gap = 0.0f64;
+    for sep_axis in separating_axes {
+        let (a_min_proj, a_max_proj) = project_on_axis(corners_a, sep_axis);
+        let (b_min_proj, b_max_proj) = project_on_axis(corners_b, sep_axis);
+        max_gap = max_gap
+            .max(b_min_proj - a_max_proj)
+            .max(a_min_proj - b_max_proj);
+    }
+    max_gap
+}
+
 fn project_on_axis(corners: &[Point3<f64>], sep_axis: Unit<Vector3<f64>>) -> (f64, f64) {
     let mut min_proj = std::f64::MAX;
     let mut max_proj = std::f64::MIN;
@@ -207,6 +251,7 @@ fn project_on_axis(corners: &[Point3<f64>], sep_axis: Unit<Vector3<f64>>) -> (f6
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::geometry::Aabb;
     use arrayvec::ArrayVec;
     use nalgebra::{Point3, Vector3};
     use std::iter::FromIterator;
@@ -265,5 +310,34 @@ mod tests {
         assert_eq!(cube_isec_2.intersect(&cube_isec_3), Relation::Out);
         assert_eq!(cube_isec_1.intersect(&cube_isec_3), Relation::In);
         assert_eq!(cube_isec_3.intersect(&cube_isec_1), Relation::Cross);
+
+        assert_eq!(cube_isec_1.separation(&cube_isec_2), 0.0);
+        assert_eq!(cube_isec_1.separation(&cube_isec_3), 0.0);
+        // Cube 3 ends at 0.7 below cube 2 on every axis.
+        assert!((cube_isec_2.separation(&cube_isec_3) - 0.2).abs() < 1e-10);
+        assert!((cube_isec_3.separation(&cube_isec_2) - 0.2).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_separation_is_a_lower_bound_of_the_distance() {
+        let unit_vectors =
+            ArrayVec::from([Vector3::x_axis(), Vector3::y_axis(), Vector3::z_axis()]);
+        let intersector = |aabb: &Aabb| Intersector {
+            corners: aabb.compute_corners(),
+            edges: ArrayVec::from_iter(unit_vectors.clone()),
+            face_normals: ArrayVec::from_iter(unit_vectors.clone()),
+        };
+        let cube = Aabb::new(Point3::origin(), Point3::new(1., 1., 1.));
+        let beside = Aabb::new(Point3::new(2., 0., 0.), Point3::new(3., 1., 1.));
+        let diagonal = Aabb::new(Point3::new(2., 2., 2.), Point3::new(3., 3., 3.));
+
+        // Facing each other, the gap along the x axis is the distance.
+        let separation = intersector(&cube).separation(&intersector(&beside));
+        assert!((separation - cube.distance_to_aabb(&beside)).abs() < 1e-10);
+
+        // Closest at their corners, no axis sees more than one unit of the √3 between them.
+        let separation = intersector(&cube).separation(&intersector(&diagonal));
+        assert!((separation - 1.).abs() < 1e-10);
+        assert!(separation < cube.distance_to_aabb(&diagonal));
     }
 }
@@ -0,0 +1,251 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extraction of a self-contained subset of an octree, e.g. for offline use in the field.
+
+use crate::errors::*;
+use crate::iterator::{PointCloud, PointLocation};
+use crate::octree::{to_meta_proto, to_node_proto, NodeId, Octree};
+use crate::proto;
+use crate::provenance::Provenance;
+use crate::read_write::INTERLEAVED;
+use crate::utils::create_progress_bar;
+use crate::{attribute_extension, META_FILENAME};
+use protobuf::Message;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
+use std::path::Path;
+
+/// The nodes of 'octree' that intersect 'location' and are not deeper than 'max_level'.
+fn bundle_node_ids(
+    octree: &Octree,
+    location: &PointLocation,
+    max_level: Option<u8>,
+) -> Vec<NodeId> {
+    let mut node_ids = octree.nodes_in_location(location);
+    if let Some(max_level) = max_level {
+        node_ids.retain(|id| id.level() <= max_level);
+    }
+    node_ids
+}
+
+fn bundle_meta(octree: &Octree, node_ids: &[NodeId]) -> proto::Meta {
+    let nodes = node_ids
+        .iter()
+        .map(|node_id| to_node_proto(node_id, &octree.nodes[node_id]))
+        .collect();
+    to_meta_proto(&octree.meta, nodes)
+}
+
+/// Calls 'write' with the file name and contents of each attribute file of each of 'node_ids',
+/// in that order.
+fn for_each_node_file(
+    octree: &Octree,
+    node_ids: &[NodeId],
+    mut write: impl FnMut(String, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    // Interleaved attributes are copied with the interleaved file, which has the positions too.
+    let interleaved_attributes = octree.meta.interleaved_attributes();
+    let mut attributes = if interleaved_attributes.is_empty() {
+        vec!["position"]
+    } else {
+        vec![INTERLEAVED]
+    };
+    attributes.extend(
+        octree
+            .meta
+            .attribute_data_types
+            .keys()
+            .filter(|attribute| !interleaved_attributes.contains(attribute))
+            .map(String::as_str),
+    );
+
+    let mut progress_bar = create_progress_bar(node_ids.len(), "Exporting nodes");
+    for node_id in node_ids {
+        let node_name = node_id.to_string();
+        for attribute in &attributes {
+            let mut reader = match octree.data_provider.data(&node_name, &[*attribute]) {
+                Ok(mut readers) => readers.remove(*attribute).unwrap(),
+                // Optional attributes like color or intensity might not exist for this octree, only
+                // the positions or the interleaved file are required.
+                Err(ref err)
+                    if matches!(err.kind(), ErrorKind::NodeNotFound)
+                        && *attribute != attributes[0] =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            let file_name = format!("{}.{}", node_name, attribute_extension(attribute));
+            write(file_name, &mut reader)?;
+        }
+        progress_bar.inc();
+    }
+    progress_bar.finish_println("");
+    Ok(())
+}
+
+/// Copies all nodes of 'octree' that intersect 'location' and are not deeper than 'max_level'
+/// into 'output_directory', together with a meta that only lists these nodes. The bounding box
+/// of the original octree is kept, so node ids and georeference stay valid and the result can be
+/// opened like any other octree. Returns the number of exported nodes.
+pub fn export_bundle(
+    octree: &Octree,
+    location: &PointLocation,
+    max_level: Option<u8>,
+    output_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let output_directory = output_directory.as_ref();
+    fs::create_dir_all(output_directory)?;
+
+    let node_ids = bundle_node_ids(octree, location, max_level);
+    for_each_node_file(octree, &node_ids, |file_name, reader| {
+        let mut writer = BufWriter::new(File::create(output_directory.join(file_name))?);
+        io::copy(reader, &mut writer)?;
+        Ok(())
+    })?;
+
+    let mut meta_writer = BufWriter::new(File::create(output_directory.join(META_FILENAME))?);
+    bundle_meta(octree, &node_ids)
+        .write_to_writer(&mut meta_writer)
+        .chain_err(|| format!("Could not write {}", META_FILENAME))?;
+    Ok(node_ids.len())
+}
+
+/// Like 'export_bundle', but writes a single uncompressed tar file that is ordered for
+/// progressive download: the meta comes first, then the nodes level by level, and within a level
+/// along a Hilbert curve, so that neighboring nodes arrive close together. A viewer that reads
+/// the file sequentially, e.g. from a plain HTTP server, can draw a coarse version of the whole
+/// point cloud early on and refine it as the download proceeds.
+pub fn export_bundle_archive(
+    octree: &Octree,
+    location: &PointLocation,
+    max_level: Option<u8>,
+    provenance: &Provenance,
+    output_file: impl AsRef<Path>,
+) -> Result<usize> {
+    let mut node_ids = bundle_node_ids(octree, location, max_level);
+    node_ids.sort_by_key(|node_id| (node_id.level(), hilbert_index(node_id)));
+
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(output_file)?));
+    let mut append = |file_name: &str, data: &[u8]| -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, file_name, data)?;
+        Ok(())
+    };
+
+    let mut meta = bundle_meta(octree, &node_ids);
+    meta.set_provenance(provenance.to_proto());
+    let meta_bytes = meta
+        .write_to_bytes()
+        .chain_err(|| format!("Could not write {}", META_FILENAME))?;
+    append(META_FILENAME, &meta_bytes)?;
+
+    for_each_node_file(octree, &node_ids, |file_name, reader| {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        append(&file_name, &data)
+    })?;
+    builder.into_inner()?;
+    Ok(node_ids.len())
+}
+
+/// The position of the node along a Hilbert curve through all nodes of its level, following
+/// John Skilling, "Programming the Hilbert curve", 2004.
+fn hilbert_index(node_id: &NodeId) -> u128 {
+    let level = node_id.level();
+    if level == 0 {
+        return 0;
+    }
+    // The child indices along the path from the root are the bits of the grid coordinates of
+    // the node, see 'NodeId::find_bounding_cube'.
+    let mut coordinates = [0u64; 3];
+    for depth in (0..level).rev() {
+        let child_index = (node_id.index() >> (3 * depth)) as u64 & 7;
+        for (axis, coordinate) in coordinates.iter_mut().enumerate() {
+            *coordinate = (*coordinate << 1) | ((child_index >> (2 - axis)) & 1);
+        }
+    }
+
+    // Inverse undo excess work.
+    let most_significant_bit = 1u64 << (level - 1);
+    let mut q = most_significant_bit;
+    while q > 1 {
+        let p = q - 1;
+        for axis in 0..3 {
+            if coordinates[axis] & q != 0 {
+                coordinates[0] ^= p;
+            } else {
+                let t = (coordinates[0] ^ coordinates[axis]) & p;
+                coordinates[0] ^= t;
+                coordinates[axis] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    // Gray encode.
+    coordinates[1] ^= coordinates[0];
+    coordinates[2] ^= coordinates[1];
+    let mut t = 0;
+    let mut q = most_significant_bit;
+    while q > 1 {
+        if coordinates[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for coordinate in &mut coordinates {
+        *coordinate ^= t;
+    }
+
+    // Interleave the bits of the transposed index.
+    let mut index = 0u128;
+    for bit in (0..level).rev() {
+        for coordinate in &coordinates {
+            index = (index << 1) | u128::from((coordinate >> bit) & 1);
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cube;
+    use nalgebra::Point3;
+
+    #[test]
+    fn test_hilbert_order_visits_neighbors() {
+        // At level 2, the nodes form a 4x4x4 grid. Consecutive nodes along the curve must be
+        // adjacent cells.
+        let mut node_ids: Vec<NodeId> = (0..64).map(|i| NodeId::from_level_index(2, i)).collect();
+        node_ids.sort_by_key(hilbert_index);
+        let indices: Vec<u128> = node_ids.iter().map(hilbert_index).collect();
+        assert_eq!(indices, (0..64).collect::<Vec<u128>>());
+        let cell = |node_id: &NodeId| {
+            let min = node_id
+                .find_bounding_cube(&Cube::new(Point3::origin(), 4.))
+                .min();
+            [min.x, min.y, min.z]
+        };
+        for pair in node_ids.windows(2) {
+            let (a, b) = (cell(&pair[0]), cell(&pair[1]));
+            let distance: f64 = (0..3).map(|i| (a[i] - b[i]).abs()).sum();
+            assert_eq!(distance, 1.);
+        }
+    }
+}
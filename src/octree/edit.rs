@@ -0,0 +1,85 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deleting points from an existing octree, e.g. scan artifacts like moving vehicles.
+
+use crate::data_provider::{commit_overlay, OnDiskDataProvider, OverlayDataProvider};
+use crate::errors::*;
+use crate::iterator::{PointCloud, PointLocation};
+use crate::octree::Octree;
+use crate::NUM_POINTS_PER_BATCH;
+use std::fs;
+use std::path::Path;
+
+/// The overlay that 'delete_points_in_location' collects the deletions in before committing them.
+const DELETE_OVERLAY_DIRECTORY: &str = ".delete_points";
+
+impl Octree {
+    /// Records the deletion of all points inside 'location' in 'overlay', in addition to the
+    /// edits it already has. The edits of an overlay refer to the points of the unedited nodes,
+    /// so this octree must be read from the base of 'overlay', not through it. Only the nodes that
+    /// intersect 'location' are read. Returns the number of newly deleted points.
+    pub fn delete_points_in(
+        &self,
+        location: &PointLocation,
+        overlay: &OverlayDataProvider,
+    ) -> Result<usize> {
+        let culling = location.get_point_culling();
+        let mut num_deleted_points = 0;
+        for node_id in self.nodes_in_location(location) {
+            let node_name = node_id.to_string();
+            let mut patch = overlay.patch(&node_name)?.unwrap_or_default();
+            let num_deleted_before = patch.num_deleted_points();
+            let mut index = 0;
+            for batch in self.points_in_node(&[], node_id, NUM_POINTS_PER_BATCH)? {
+                for position in &batch.position {
+                    if culling.contains(position) {
+                        patch.delete_point(index);
+                    }
+                    index += 1;
+                }
+            }
+            if patch.num_deleted_points() > num_deleted_before {
+                num_deleted_points += patch.num_deleted_points() - num_deleted_before;
+                overlay.set_patch(&node_name, &patch)?;
+            }
+        }
+        Ok(num_deleted_points)
+    }
+}
+
+/// Removes all points inside 'location' from the octree in 'directory', rewriting only the nodes
+/// that had any and reducing their number of points in the meta. Nodes that are left without
+/// points and without children are removed. Like 'commit_overlay', this is not atomic.
+/// Returns the number of deleted points.
+pub fn delete_points_in_location(directory: &Path, location: &PointLocation) -> Result<usize> {
+    let on_disk = || OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    };
+    let octree = Octree::from_data_provider(Box::new(on_disk()))?;
+    let overlay_directory = directory.join(DELETE_OVERLAY_DIRECTORY);
+    // Left behind by an interrupted deletion, whose edits were never committed.
+    if overlay_directory.exists() {
+        fs::remove_dir_all(&overlay_directory)
+            .chain_err(|| format!("Could not remove {}", overlay_directory.display()))?;
+    }
+    let overlay = OverlayDataProvider::new(Box::new(on_disk()), &overlay_directory)?;
+    let num_deleted_points = octree.delete_points_in(location, &overlay)?;
+    if num_deleted_points == 0 {
+        overlay.discard()?;
+    } else {
+        commit_overlay(&on_disk(), &overlay_directory)?;
+    }
+    Ok(num_deleted_points)
+}
@@ -15,27 +15,564 @@
 use crate::data_provider::OnDiskDataProvider;
 use crate::errors::*;
 use crate::geometry::{Aabb, Cube};
-use crate::octree::{self, to_meta_proto, to_node_proto, ChildIndex, NodeId, OctreeMeta};
+use crate::labels::LABEL_ATTRIBUTES;
+use crate::math::ClosedInterval;
+use crate::octree::{self, to_meta_proto, to_node_proto, ChildIndex, NodeId, NodeMeta, OctreeMeta};
 use crate::proto;
 use crate::read_write::{
-    attempt_increasing_rlimit_to_max, Encoding, NodeIterator, NodeWriter, OpenMode, PlyIterator,
-    PositionEncoding, RawNodeWriter,
+    attempt_increasing_rlimit_to_max, AsciiColumns, Encoding, InputSource, NodeIterator,
+    NodeWriter, NonFiniteFilter, NonFinitePolicy, OpenMode, PlyIterator, PositionEncoding,
+    PtsIterator, RawNodeWriter, StreamError,
+};
+use crate::utils::{progress_mode, ProgressMode, ProgressReporter};
+use crate::{
+    match_1d_attr_data, AttributeData, AttributeDataType, NumberOfPoints, PointCloudMeta,
+    PointsBatch, NUM_POINTS_PER_BATCH,
 };
-use crate::utils::create_progress_bar;
-use crate::META_FILENAME;
-use crate::{AttributeDataType, NumberOfPoints, PointCloudMeta, PointsBatch, NUM_POINTS_PER_BATCH};
 use fnv::{FnvHashMap, FnvHashSet};
-use protobuf::Message;
+use num_traits::ToPrimitive;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rayon::Scope;
+use rayon::{Scope, ThreadPool};
 use std::cmp;
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::BufWriter;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 const MAX_POINTS_PER_NODE: i64 = 100_000;
 
+/// A file of points to build an octree from.
+#[derive(Clone, Debug)]
+pub enum InputFile {
+    Ply(InputSource),
+    /// PTS, XYZ, TXT or CSV files with the given column layout.
+    Ascii(InputSource, AsciiColumns),
+}
+
+impl InputFile {
+    /// Determines the format from the file extension, ignoring a trailing '.gz'. 'columns' is
+    /// only used for ASCII files.
+    pub fn from_path(path: impl Into<PathBuf>, columns: AsciiColumns) -> Result<Self> {
+        Self::from_source(InputSource::file(path), columns)
+    }
+
+    /// Like 'from_path', but reads the member of a zip or tar archive matching 'member_glob'.
+    pub fn from_archive(
+        path: impl Into<PathBuf>,
+        member_glob: &str,
+        columns: AsciiColumns,
+    ) -> Result<Self> {
+        Self::from_source(InputSource::archive_member(path, member_glob)?, columns)
+    }
+
+    pub fn from_source(source: InputSource, columns: AsciiColumns) -> Result<Self> {
+        let name = source.name().to_lowercase();
+        let name = name.trim_end_matches(".gz");
+        match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("ply") => Ok(InputFile::Ply(source)),
+            Some("pts") | Some("xyz") | Some("txt") | Some("csv") => {
+                Ok(InputFile::Ascii(source, columns))
+            }
+            _ => Err(ErrorKind::InvalidInput(format!("Unknown input format of {}.", name)).into()),
+        }
+    }
+
+    pub fn source(&self) -> &InputSource {
+        match self {
+            InputFile::Ply(source) | InputFile::Ascii(source, _) => source,
+        }
+    }
+
+    /// The attributes that the points read from this file have. PLY files are opened to read
+    /// them from the header.
+    pub fn attributes(&self) -> Result<Vec<&'static str>> {
+        match self {
+            InputFile::Ply(source) => Ok(PlyIterator::from_source(source, 1)?.attributes()),
+            InputFile::Ascii(_, columns) => Ok(columns.attributes()),
+        }
+    }
+}
+
+/// The stream of points of an 'InputFile'.
+pub enum InputStream {
+    Ply(PlyIterator),
+    Ascii(PtsIterator),
+}
+
+impl Iterator for InputStream {
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            InputStream::Ply(stream) => stream.size_hint(),
+            InputStream::Ascii(stream) => stream.size_hint(),
+        }
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        match self {
+            InputStream::Ply(stream) => stream.next(),
+            InputStream::Ascii(stream) => stream.next(),
+        }
+    }
+}
+
+impl InputStream {
+    /// The error that ended the stream early, see 'PlyIterator::error' and 'PtsIterator::error'.
+    pub fn error(&self) -> StreamError {
+        match self {
+            InputStream::Ply(stream) => stream.error(),
+            InputStream::Ascii(stream) => stream.error(),
+        }
+    }
+}
+
+impl NumberOfPoints for InputStream {
+    fn num_points(&self) -> usize {
+        match self {
+            InputStream::Ply(stream) => stream.num_points(),
+            InputStream::Ascii(stream) => stream.num_points(),
+        }
+    }
+}
+
+pub fn make_stream(input: &InputFile) -> Result<InputStream> {
+    Ok(match input {
+        InputFile::Ply(source) => {
+            InputStream::Ply(PlyIterator::from_source(source, NUM_POINTS_PER_BATCH)?)
+        }
+        InputFile::Ascii(source, columns) => InputStream::Ascii(PtsIterator::from_source(
+            source,
+            columns.clone(),
+            NUM_POINTS_PER_BATCH,
+        )?),
+    })
+}
+
+/// What building an octree did.
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    pub num_nodes: usize,
+    /// The number of points in the octree.
+    pub num_points: i64,
+    /// Points that were dropped because an earlier point was in the same cell of the duplicate
+    /// tolerance, see OctreeBuilder::with_duplicate_suppression.
+    pub num_duplicates: i64,
+}
+
+/// Nodes that are in the output directory before an octree is built into it, e.g. those that
+/// merging copies from the octrees it merges. They hang below roots whose points are part of the
+/// input, which the build writes like leaves that it never splits.
+#[derive(Default)]
+pub(super) struct ExistingSubtrees {
+    pub roots: FnvHashSet<NodeId>,
+    /// The nodes below the roots with their number of points.
+    pub nodes: FnvHashMap<NodeId, i64>,
+}
+
+/// Configuration for building an octree out of a stream of points or an input file.
+#[derive(Clone)]
+pub struct OctreeBuilder {
+    resolution: f64,
+    attributes: Vec<String>,
+    max_depth: Option<u8>,
+    max_points_per_node: i64,
+    position_encoding: Option<PositionEncoding>,
+    non_finite_policy: NonFinitePolicy,
+    duplicate_tolerance: Option<f64>,
+    label_attribute: Option<String>,
+    node_statistics: bool,
+    thread_pool: Option<Arc<ThreadPool>>,
+    progress_mode: Option<ProgressMode>,
+}
+
+impl OctreeBuilder {
+    /// 'resolution' is the minimal precision that the point cloud should have. It decides on the
+    /// number of bits used to encode the positions of each node.
+    pub fn new(resolution: f64) -> Self {
+        OctreeBuilder {
+            resolution,
+            attributes: vec!["color".to_string()],
+            max_depth: None,
+            max_points_per_node: MAX_POINTS_PER_NODE,
+            position_encoding: None,
+            non_finite_policy: NonFinitePolicy::Drop,
+            duplicate_tolerance: None,
+            label_attribute: None,
+            node_statistics: false,
+            thread_pool: None,
+            progress_mode: None,
+        }
+    }
+
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    pub fn max_depth(&self) -> Option<u8> {
+        self.max_depth
+    }
+
+    /// The attributes to keep, the default is only color.
+    pub fn with_attributes(mut self, attributes: &[&str]) -> Self {
+        self.attributes = attributes.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    /// Nodes on this level are not split, no matter how many points they have.
+    pub fn with_max_depth(mut self, max_depth: u8) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Nodes with more points are split unless they are already as small as the resolution.
+    pub fn with_max_points_per_node(mut self, max_points_per_node: usize) -> Self {
+        self.max_points_per_node = max_points_per_node as i64;
+        self
+    }
+
+    /// Encodes the positions of all nodes like this instead of choosing the smallest encoding
+    /// that keeps the resolution.
+    pub fn with_position_encoding(mut self, position_encoding: PositionEncoding) -> Self {
+        self.position_encoding = Some(position_encoding);
+        self
+    }
+
+    /// What to do with non-finite values when building from a file, the default is to drop them.
+    pub fn with_non_finite_policy(mut self, non_finite_policy: NonFinitePolicy) -> Self {
+        self.non_finite_policy = non_finite_policy;
+        self
+    }
+
+    /// Keeps only the first point in each cube of 'tolerance' edge length, so that overlapping
+    /// scans or data that is ingested twice do not add points. The cubes are aligned to the leaf
+    /// nodes and only points in the same leaf node are compared, which bounds the memory needed.
+    /// Building fails unless 'tolerance' is positive and finite. The number of dropped points is
+    /// returned in the 'BuildReport'.
+    pub fn with_duplicate_suppression(mut self, tolerance: f64) -> Self {
+        self.duplicate_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Records the value ranges of the one-dimensional attributes and the label counts of every
+    /// node in the meta, which lets filtered queries and viewers skip nodes. This reads all
+    /// nodes back once they are finished, so it is off by default.
+    pub fn with_node_statistics(mut self) -> Self {
+        self.node_statistics = true;
+        self
+    }
+
+    /// Counts the points per value of this U8 or U16 attribute in every node if node statistics
+    /// are recorded, see 'NodeMeta::label_counts'. The default is the first of
+    /// 'LABEL_ATTRIBUTES' that is built.
+    pub fn with_label_attribute(mut self, label_attribute: &str) -> Self {
+        self.label_attribute = Some(label_attribute.to_string());
+        self
+    }
+
+    /// Builds on this pool instead of the global rayon pool.
+    pub fn with_thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Reports progress like this instead of following the global progress mode.
+    pub fn with_progress_mode(mut self, progress_mode: ProgressMode) -> Self {
+        self.progress_mode = Some(progress_mode);
+        self
+    }
+
+    fn progress_bar(&self, total: usize, message: &str) -> ProgressReporter {
+        ProgressReporter::with_mode(
+            total,
+            message,
+            self.progress_mode.unwrap_or_else(progress_mode),
+        )
+    }
+
+    pub(super) fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Builds the octree of the points in 'input' into 'output_directory'. 'bounding_box' must
+    /// contain all points.
+    pub fn build(
+        &self,
+        output_directory: impl AsRef<Path>,
+        bounding_box: Aabb,
+        input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+    ) -> Result<BuildReport> {
+        self.build_with_existing_subtrees(
+            output_directory,
+            bounding_box,
+            input,
+            &ExistingSubtrees::default(),
+            || Ok(()),
+        )
+    }
+
+    /// Like 'build', but adds 'existing_subtrees' from the output directory to the octree, and
+    /// fails before the meta is written if 'check_input' reports that 'input' was cut short.
+    pub(super) fn build_with_existing_subtrees(
+        &self,
+        output_directory: impl AsRef<Path>,
+        bounding_box: Aabb,
+        input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+        existing_subtrees: &ExistingSubtrees,
+        check_input: impl Fn() -> Result<()> + Send,
+    ) -> Result<BuildReport> {
+        let output_directory = output_directory.as_ref();
+        self.install(|| {
+            self.build_in_pool(
+                output_directory,
+                bounding_box,
+                input,
+                existing_subtrees,
+                check_input,
+            )
+        })
+    }
+
+    /// The meta of the octrees this builder builds with 'bounding_box', which has no nodes yet.
+    pub(super) fn octree_meta(&self, bounding_box: Aabb) -> Result<OctreeMeta> {
+        // The input files have the standard attributes, the octree stores the ones that are built.
+        let attributes: Vec<&str> = self.attributes.iter().map(String::as_str).collect();
+        let attribute_data_types =
+            octree::OctreeMeta::new_with_standard_attributes(self.resolution, bounding_box.clone())
+                .attribute_data_types_for(&attributes)?;
+        let mut octree_meta =
+            octree::OctreeMeta::new(self.resolution, bounding_box, attribute_data_types);
+        if let Some(position_encoding) = &self.position_encoding {
+            octree_meta = octree_meta.with_position_encoding(position_encoding.clone());
+        }
+        Ok(octree_meta)
+    }
+
+    /// Reads 'input' twice, first to determine the bounding box and then to build the octree.
+    /// Fails without writing the meta if the input cannot be read to the end or has non-finite
+    /// points that the 'NonFinitePolicy' rejects.
+    pub fn build_from_file(
+        &self,
+        output_directory: impl AsRef<Path>,
+        input: &InputFile,
+    ) -> Result<BuildReport> {
+        let bounding_box = self.find_bounding_box(input)?;
+        let stream = make_stream(input)?;
+        let input_error = stream.error();
+        let stream = NonFiniteFilter::new(stream, self.non_finite_policy);
+        let non_finite_error = stream.error();
+        self.build_with_existing_subtrees(
+            output_directory,
+            bounding_box,
+            stream,
+            &ExistingSubtrees::default(),
+            || {
+                input_error.check()?;
+                non_finite_error.check()
+            },
+        )
+    }
+
+    /// Returns the bounding box containing all points
+    fn find_bounding_box(&self, input: &InputFile) -> Result<Aabb> {
+        let mut bounding_box = None;
+        let stream = make_stream(input)?;
+        let input_error = stream.error();
+        let mut progress_bar = self.progress_bar(stream.num_points(), "Determining bounding box");
+
+        stream.for_each(|batch| {
+            progress_bar.add(batch.position.len() as u64);
+            // Non-finite positions are handled by the 'NonFinitePolicy' when the points are read
+            // for real, they must not spoil the bounding box.
+            for pos in batch
+                .position
+                .into_iter()
+                .filter(|p| p.coords.iter().all(|c| c.is_finite()))
+            {
+                let b = bounding_box.get_or_insert(Aabb::new(pos, pos));
+                b.grow(pos);
+            }
+        });
+        progress_bar.finish();
+        input_error.check()?;
+        Ok(bounding_box.unwrap_or_else(Aabb::zero))
+    }
+
+    fn build_in_pool(
+        &self,
+        output_directory: &Path,
+        bounding_box: Aabb,
+        input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+        existing_subtrees: &ExistingSubtrees,
+        check_input: impl Fn() -> Result<()>,
+    ) -> Result<BuildReport> {
+        if let Some(tolerance) = self.duplicate_tolerance {
+            if !(tolerance.is_finite() && tolerance > 0.) {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The duplicate tolerance must be positive and finite, not {}.",
+                    tolerance
+                ))
+                .into());
+            }
+        }
+        attempt_increasing_rlimit_to_max();
+
+        let octree_meta = self.octree_meta(bounding_box.clone())?;
+        let attribute_data_types = octree_meta.attribute_data_types().clone();
+        let label_attribute = match &self.label_attribute {
+            Some(label_attribute) => {
+                match attribute_data_types.get(label_attribute) {
+                    Some(AttributeDataType::U8) | Some(AttributeDataType::U16) => (),
+                    _ => {
+                        return Err(ErrorKind::InvalidInput(format!(
+                            "The label attribute {} must be built and be U8 or U16.",
+                            label_attribute
+                        ))
+                        .into())
+                    }
+                }
+                Some(label_attribute.clone())
+            }
+            None => LABEL_ATTRIBUTES
+                .iter()
+                .find(|attribute| attribute_data_types.contains_key(**attribute))
+                .map(|attribute| attribute.to_string()),
+        };
+        let ctx = &BuildContext {
+            attribute_data_types,
+            label_attribute,
+            octree_meta,
+            existing_roots: &existing_subtrees.roots,
+            above_existing_roots: existing_subtrees
+                .roots
+                .iter()
+                .flat_map(NodeId::ancestors)
+                .collect(),
+            data_provider: OnDiskDataProvider {
+                directory: output_directory.to_path_buf(),
+            },
+            builder: self,
+        };
+
+        // Ignore errors, maybe directory is already there.
+        let _ = fs::create_dir(output_directory);
+
+        eprintln!("Creating octree structure.");
+
+        let (leaf_nodes_sender, leaf_nodes_receiver) = crossbeam::channel::unbounded();
+        rayon::scope(move |scope| {
+            let root_node = octree::Node::root_with_bounding_cube(Cube::bounding(&bounding_box));
+            split_node(scope, ctx, &root_node.id, input, &leaf_nodes_sender);
+        });
+        check_input()?;
+
+        let mut nodes_to_subsample = Vec::new();
+        let mut deepest_level = 0u8;
+        for id in leaf_nodes_receiver {
+            deepest_level = cmp::max(deepest_level, id.level());
+            nodes_to_subsample.push(id);
+        }
+        let mut report = BuildReport::default();
+        if let Some(tolerance) = self.duplicate_tolerance {
+            report.num_duplicates = nodes_to_subsample
+                .par_iter()
+                .map(|id| suppress_duplicates(ctx, id, tolerance))
+                .collect::<Result<Vec<i64>>>()?
+                .into_iter()
+                .sum();
+        }
+        let mut finished_nodes = existing_subtrees.nodes.clone();
+
+        // sub sampling returns the list of finished nodes including all meta data
+        // We start on the deepest level and work our way up the tree.
+        for current_level in (1..=deepest_level).rev() {
+            // All nodes on the same level can be subsampled in parallel.
+            let res = nodes_to_subsample
+                .into_iter()
+                .partition(|n| n.level() == current_level);
+            nodes_to_subsample = res.1;
+
+            // Unwrap is safe, since we stop at current_level = 1, so the root can never appear.
+            let parent_ids: FnvHashSet<_> = res
+                .0
+                .into_iter()
+                .map(|id| id.parent_id().unwrap())
+                .collect();
+            let mut progress_bar = self.progress_bar(
+                parent_ids.len(),
+                &format!("Building level {}", current_level - 1),
+            );
+
+            let (finished_nodes_sender, finished_nodes_receiver) = crossbeam::channel::unbounded();
+            let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
+            rayon::scope(|scope| {
+                scope.spawn(|_| {
+                    for (id, num_points) in finished_nodes_receiver {
+                        finished_nodes.insert(id, num_points);
+                    }
+                });
+
+                scope.spawn(|_| {
+                    for _ in progress_rx {
+                        progress_bar.inc();
+                    }
+                });
+
+                parent_ids.par_iter().for_each(|id| {
+                    subsample_children_into(ctx, id, &finished_nodes_sender).unwrap();
+                    progress_tx.send(()).unwrap();
+                });
+                drop(finished_nodes_sender);
+                drop(progress_tx);
+            });
+            progress_bar.finish();
+
+            // The nodes that were just now created through sub-sampling will be required to create
+            // their parents.
+            nodes_to_subsample.extend(parent_ids.into_iter());
+        }
+
+        // Add all non-zero node meta data to meta file, including the statistics that let queries
+        // skip nodes if they were asked for.
+        let nodes = finished_nodes
+            .par_iter()
+            .map(|(id, num_points)| {
+                let node_meta = if self.node_statistics {
+                    node_meta_with_statistics(ctx, *id, *num_points)?
+                } else {
+                    node_meta_without_statistics(ctx, *id, *num_points)
+                };
+                Ok(to_node_proto(&id, &node_meta))
+            })
+            .collect::<Result<Vec<proto::OctreeNode>>>()?;
+        let meta = to_meta_proto(&ctx.octree_meta, nodes);
+        ctx.data_provider.write_meta_proto(&meta)?;
+        report.num_nodes = finished_nodes.len();
+        report.num_points = finished_nodes.values().sum();
+        Ok(report)
+    }
+}
+
+/// Everything that the steps of building an octree share.
+struct BuildContext<'a> {
+    data_provider: OnDiskDataProvider,
+    octree_meta: OctreeMeta,
+    attribute_data_types: HashMap<String, AttributeDataType>,
+    // The attribute that the label counts of the nodes are of.
+    label_attribute: Option<String>,
+    // The roots of the existing subtrees, which are not split, and their ancestors, which are.
+    existing_roots: &'a FnvHashSet<NodeId>,
+    above_existing_roots: FnvHashSet<NodeId>,
+    builder: &'a OctreeBuilder,
+}
+
 impl RawNodeWriter {
     fn from_data_provider(
         octree_data_provider: &OnDiskDataProvider,
@@ -44,7 +581,7 @@ impl RawNodeWriter {
     ) -> Self {
         let path = octree_data_provider.stem(&node_id.to_string());
         let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
-        let position_encoding = PositionEncoding::new(&bounding_cube, octree_meta.resolution);
+        let position_encoding = octree_meta.position_encoding(&bounding_cube);
         let min = bounding_cube.min();
         RawNodeWriter::new(
             path,
@@ -56,8 +593,7 @@ impl RawNodeWriter {
 
 // Return a list of leaf nodes and a list of nodes to be split further.
 fn split<P>(
-    octree_data_provider: &OnDiskDataProvider,
-    octree_meta: &octree::OctreeMeta,
+    ctx: &BuildContext,
     node_id: &octree::NodeId,
     stream: P,
 ) -> (Vec<octree::NodeId>, Vec<octree::NodeId>)
@@ -67,15 +603,26 @@ where
     let mut children: Vec<Option<RawNodeWriter>> =
         vec![None, None, None, None, None, None, None, None];
     let size = stream.num_points();
+    let max_points_per_node = ctx.builder.max_points_per_node;
     eprintln!(
-        "Splitting {} which has {} points ({:.2}x MAX_POINTS_PER_NODE).",
+        "Splitting {} which has {} points ({:.2}x the maximum per node).",
         node_id,
         size,
-        size as f64 / MAX_POINTS_PER_NODE as f64
+        size as f64 / max_points_per_node as f64
     );
 
-    let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
+    // Only the root is split on a single thread, all other nodes are split in parallel and would
+    // garble each other's progress output.
+    let mut progress_bar = if node_id.level() == 0 {
+        Some(ctx.builder.progress_bar(size, "Splitting root"))
+    } else {
+        None
+    };
+    let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&ctx.octree_meta.bounding_box));
     stream.for_each(|batch| {
+        if let Some(progress_bar) = &mut progress_bar {
+            progress_bar.add(batch.position.len() as u64);
+        }
         let child_indices: Vec<_> = batch
             .position
             .iter()
@@ -91,8 +638,8 @@ where
             if !child_batch.position.is_empty() {
                 if child_writer.is_none() {
                     *child_writer = Some(RawNodeWriter::from_data_provider(
-                        octree_data_provider,
-                        octree_meta,
+                        &ctx.data_provider,
+                        &ctx.octree_meta,
                         &node_id.get_child_id(ChildIndex::from_u8(array_index as u8)),
                     ));
                 }
@@ -100,12 +647,15 @@ where
             }
         }
     });
+    if let Some(progress_bar) = &mut progress_bar {
+        progress_bar.finish();
+    }
 
     // Remove the node file on disk by reopening the node and immediately dropping it again without
     // writing a point. This only saves some disk space during processing - all nodes will be
     // rewritten by subsampling the children in the second step anyways. We also ignore file
     // removing error. For example, we never write out the root, so it cannot be removed.
-    RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, node_id);
+    RawNodeWriter::from_data_provider(&ctx.data_provider, &ctx.octree_meta, node_id);
 
     let mut leaf_nodes = Vec::new();
     let mut split_nodes = Vec::new();
@@ -116,7 +666,7 @@ where
         let c = c.unwrap();
         let child_id = node_id.get_child_id(octree::ChildIndex::from_u8(child_index as u8));
 
-        if should_split_node(&child_id, c.num_written(), octree_meta) {
+        if should_split_node(ctx, &child_id, c.num_written()) {
             split_nodes.push(child_id);
         } else {
             leaf_nodes.push(child_id);
@@ -125,24 +675,34 @@ where
     (leaf_nodes, split_nodes)
 }
 
-fn should_split_node(
-    id: &octree::NodeId,
-    num_points: i64,
-    octree_meta: &octree::OctreeMeta,
-) -> bool {
-    if num_points <= MAX_POINTS_PER_NODE {
+fn should_split_node(ctx: &BuildContext, id: &octree::NodeId, num_points: i64) -> bool {
+    if ctx.existing_roots.contains(id) {
+        return false;
+    }
+    if ctx.above_existing_roots.contains(id) {
+        return true;
+    }
+    let max_points_per_node = ctx.builder.max_points_per_node;
+    if num_points <= max_points_per_node {
+        return false;
+    }
+    if ctx
+        .builder
+        .max_depth
+        .map_or(false, |max_depth| id.level() >= max_depth)
+    {
         return false;
     }
-    let bounding_cube = id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
-    if bounding_cube.edge_length() <= octree_meta.resolution {
+    let bounding_cube = id.find_bounding_cube(&Cube::bounding(&ctx.octree_meta.bounding_box));
+    if bounding_cube.edge_length() <= ctx.octree_meta.resolution {
         // TODO(hrapp): If the data has billion of points in this small spot, performance will
         // greatly suffer if we display it. Drop points?
         eprintln!(
-            "Node {} which has {} points ({:.2}x MAX_POINTS_PER_NODE) \
+            "Node {} which has {} points ({:.2}x the maximum per node) \
              is too small to be split, keeping all points.",
             id,
             num_points,
-            num_points as f64 / MAX_POINTS_PER_NODE as f64
+            num_points as f64 / max_points_per_node as f64
         );
         return false;
     }
@@ -151,39 +711,32 @@ fn should_split_node(
 
 fn split_node<'a, P>(
     scope: &Scope<'a>,
-    octree_data_provider: &'a OnDiskDataProvider,
-    octree_meta: &'a octree::OctreeMeta,
-    attribute_data_types: &'a HashMap<String, AttributeDataType>,
+    ctx: &'a BuildContext,
     node_id: &octree::NodeId,
     stream: P,
     leaf_nodes_sender: &crossbeam::channel::Sender<octree::NodeId>,
 ) where
     P: Iterator<Item = PointsBatch> + NumberOfPoints,
 {
-    let (leaf_nodes, split_nodes) = split(octree_data_provider, octree_meta, node_id, stream);
+    let (leaf_nodes, split_nodes) = split(ctx, node_id, stream);
     for child_id in split_nodes {
         let leaf_nodes_sender_clone = leaf_nodes_sender.clone();
         scope.spawn(move |scope| {
+            let encoding = ctx.octree_meta.encoding_for_node(child_id);
+            let num_points = ctx
+                .data_provider
+                .number_of_points(&child_id.to_string(), &encoding)
+                .unwrap() as usize;
             let stream = NodeIterator::from_data_provider(
-                octree_data_provider,
-                attribute_data_types,
-                octree_meta.encoding_for_node(child_id),
+                &ctx.data_provider,
+                &ctx.attribute_data_types,
+                encoding,
                 &child_id,
-                octree_data_provider
-                    .number_of_points(&child_id.to_string())
-                    .unwrap() as usize,
+                num_points,
                 NUM_POINTS_PER_BATCH,
             )
             .unwrap();
-            split_node(
-                scope,
-                octree_data_provider,
-                octree_meta,
-                attribute_data_types,
-                &child_id,
-                stream,
-                &leaf_nodes_sender_clone,
-            );
+            split_node(scope, ctx, &child_id, stream, &leaf_nodes_sender_clone);
         });
     }
 
@@ -193,25 +746,27 @@ fn split_node<'a, P>(
 }
 
 fn subsample_children_into(
-    octree_data_provider: &OnDiskDataProvider,
-    octree_meta: &octree::OctreeMeta,
-    attribute_data_types: &HashMap<String, AttributeDataType>,
+    ctx: &BuildContext,
     node_id: &octree::NodeId,
     nodes_sender: &crossbeam::channel::Sender<(octree::NodeId, i64)>,
 ) -> Result<()> {
     let mut parent_writer =
-        RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, node_id);
+        RawNodeWriter::from_data_provider(&ctx.data_provider, &ctx.octree_meta, node_id);
     for i in 0..8 {
         let child_id = node_id.get_child_id(octree::ChildIndex::from_u8(i));
-        let num_points = match octree_data_provider.number_of_points(&child_id.to_string()) {
+        let encoding = ctx.octree_meta.encoding_for_node(child_id);
+        let num_points = match ctx
+            .data_provider
+            .number_of_points(&child_id.to_string(), &encoding)
+        {
             Ok(num_points) => num_points,
             Err(Error(ErrorKind::NodeNotFound, _)) => continue,
             Err(err) => return Err(err),
         };
         let mut node_iterator = NodeIterator::from_data_provider(
-            octree_data_provider,
-            attribute_data_types,
-            octree_meta.encoding_for_node(child_id),
+            &ctx.data_provider,
+            &ctx.attribute_data_types,
+            encoding,
             &child_id,
             num_points as usize,
             NUM_POINTS_PER_BATCH,
@@ -233,7 +788,7 @@ fn subsample_children_into(
         child_batch.retain(&keep_child);
 
         let mut child_writer =
-            RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, &child_id);
+            RawNodeWriter::from_data_provider(&ctx.data_provider, &ctx.octree_meta, &child_id);
         parent_writer.write(&parent_batch)?;
         child_writer.write(&child_batch)?;
 
@@ -252,152 +807,139 @@ fn subsample_children_into(
     Ok(())
 }
 
-/// Returns the bounding box containing all points
-fn find_bounding_box(filename: impl AsRef<Path>) -> Aabb {
-    let mut bounding_box = None;
-    let stream = PlyIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap();
-    let mut progress_bar = create_progress_bar(stream.num_points(), "Determining bounding box");
-
-    stream.for_each(|batch| {
-        for pos in batch.position {
-            let b = bounding_box.get_or_insert(Aabb::new(pos, pos));
-            b.grow(pos);
-            progress_bar.inc();
-        }
-    });
-    progress_bar.finish();
-    bounding_box.unwrap_or_else(Aabb::zero)
+/// Rewrites the leaf node 'node_id' with only the first point in each cube of 'tolerance' edge
+/// length, counted from the min corner of the node. Returns the number of dropped points.
+fn suppress_duplicates(ctx: &BuildContext, node_id: &NodeId, tolerance: f64) -> Result<i64> {
+    let encoding = ctx.octree_meta.encoding_for_node(*node_id);
+    let num_points = ctx
+        .data_provider
+        .number_of_points(&node_id.to_string(), &encoding)?;
+    // We read all points into memory, because the node writer rewrites the node's file(s).
+    let mut batches: Vec<PointsBatch> = NodeIterator::from_data_provider(
+        &ctx.data_provider,
+        &ctx.attribute_data_types,
+        encoding,
+        node_id,
+        num_points as usize,
+        NUM_POINTS_PER_BATCH,
+    )?
+    .collect();
+    let min = node_id
+        .find_bounding_cube(&Cube::bounding(&ctx.octree_meta.bounding_box))
+        .min();
+    let mut occupied_cells = FnvHashSet::default();
+    let mut num_kept = 0;
+    for batch in &mut batches {
+        let keep: Vec<bool> = batch
+            .position
+            .iter()
+            .map(|p| {
+                let cell = (p - min) / tolerance;
+                occupied_cells.insert((
+                    cell.x.floor() as i64,
+                    cell.y.floor() as i64,
+                    cell.z.floor() as i64,
+                ))
+            })
+            .collect();
+        batch.retain(&keep);
+        num_kept += batch.position.len() as i64;
+    }
+    if num_kept == num_points {
+        return Ok(0);
+    }
+    let mut writer =
+        RawNodeWriter::from_data_provider(&ctx.data_provider, &ctx.octree_meta, node_id);
+    for batch in &batches {
+        writer.write(batch)?;
+    }
+    Ok(num_points - num_kept)
 }
 
-pub fn build_octree_from_file(
-    output_directory: impl AsRef<Path>,
-    resolution: f64,
-    filename: impl AsRef<Path>,
-    attributes: &[&str],
-) {
-    let bounding_box = find_bounding_box(filename.as_ref());
-    let stream = PlyIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap();
-    build_octree(
-        output_directory,
-        resolution,
-        bounding_box,
-        stream,
-        attributes,
-    )
+/// Returns the smallest and largest finite value in 'data'.
+fn value_range<T: ToPrimitive>(data: &[T]) -> Option<(f64, f64)> {
+    data.iter()
+        .filter_map(ToPrimitive::to_f64)
+        .filter(|v| v.is_finite())
+        .fold(None, |range, v| match range {
+            None => Some((v, v)),
+            Some((min, max)) => Some((min.min(v), max.max(v))),
+        })
 }
 
-pub fn build_octree(
-    output_directory: impl AsRef<Path>,
-    resolution: f64,
-    bounding_box: Aabb,
-    input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
-    attributes: &[&str],
-) {
-    attempt_increasing_rlimit_to_max();
-
-    let octree_meta =
-        &octree::OctreeMeta::new_with_standard_attributes(resolution, bounding_box.clone());
-    let attribute_data_types = &octree_meta.attribute_data_types_for(attributes).unwrap();
-    let octree_data_provider = OnDiskDataProvider {
-        directory: output_directory.as_ref().to_path_buf(),
-    };
-    let octree_data_provider = &octree_data_provider;
-
-    // Ignore errors, maybe directory is already there.
-    let _ = fs::create_dir(output_directory.as_ref());
-
-    eprintln!("Creating octree structure.");
-
-    let (leaf_nodes_sender, leaf_nodes_receiver) = crossbeam::channel::unbounded();
-    rayon::scope(move |scope| {
-        let root_node = octree::Node::root_with_bounding_cube(Cube::bounding(&bounding_box));
-        split_node(
-            scope,
-            octree_data_provider,
-            octree_meta,
-            attribute_data_types,
-            &root_node.id,
-            input,
-            &leaf_nodes_sender,
-        );
-    });
-
-    let mut nodes_to_subsample = Vec::new();
-    let mut deepest_level = 0u8;
-    for id in leaf_nodes_receiver {
-        deepest_level = cmp::max(deepest_level, id.level());
-        nodes_to_subsample.push(id);
-    }
-    let mut finished_nodes = FnvHashMap::default();
-
-    // sub sampling returns the list of finished nodes including all meta data
-    // We start on the deepest level and work our way up the tree.
-    for current_level in (1..=deepest_level).rev() {
-        // All nodes on the same level can be subsampled in parallel.
-        let res = nodes_to_subsample
-            .into_iter()
-            .partition(|n| n.level() == current_level);
-        nodes_to_subsample = res.1;
-
-        // Unwrap is safe, since we stop at current_level = 1, so the root can never appear.
-        let parent_ids: FnvHashSet<_> = res
-            .0
-            .into_iter()
-            .map(|id| id.parent_id().unwrap())
-            .collect();
-        let mut progress_bar = create_progress_bar(
-            parent_ids.len(),
-            &format!("Building level {}", current_level - 1),
-        );
-
-        let (finished_nodes_sender, finished_nodes_receiver) = crossbeam::channel::unbounded();
-        let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
-        rayon::scope(|scope| {
-            scope.spawn(|_| {
-                for (id, num_points) in finished_nodes_receiver {
-                    finished_nodes.insert(id, num_points);
-                }
-            });
-
-            scope.spawn(|_| {
-                for _ in progress_rx {
-                    progress_bar.inc();
-                }
-            });
-
-            parent_ids.par_iter().for_each(|id| {
-                subsample_children_into(
-                    octree_data_provider,
-                    octree_meta,
-                    attribute_data_types,
-                    id,
-                    &finished_nodes_sender,
-                )
-                .unwrap();
-                progress_tx.send(()).unwrap();
-            });
-            drop(finished_nodes_sender);
-            drop(progress_tx);
-        });
-        progress_bar.finish();
-
-        // The nodes that were just now created through sub-sampling will be required to create
-        // their parents.
-        nodes_to_subsample.extend(parent_ids.into_iter());
+fn node_meta_without_statistics(ctx: &BuildContext, node_id: NodeId, num_points: i64) -> NodeMeta {
+    let octree_meta = &ctx.octree_meta;
+    let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
+    NodeMeta {
+        num_points,
+        position_encoding: octree_meta.position_encoding(&bounding_cube),
+        bounding_cube,
+        label_counts: BTreeMap::new(),
+        label_attribute: None,
+        attribute_ranges: BTreeMap::new(),
     }
+}
 
-    // Add all non-zero node meta data to meta file
-    let nodes: Vec<proto::OctreeNode> = finished_nodes
+/// Reads a finished node back to record the value ranges of its one-dimensional attributes and
+/// the number of points per label, which lets queries and viewers skip nodes without matching
+/// points.
+fn node_meta_with_statistics(
+    ctx: &BuildContext,
+    node_id: NodeId,
+    num_points: i64,
+) -> Result<NodeMeta> {
+    let octree_meta = &ctx.octree_meta;
+    let mut node_meta = node_meta_without_statistics(ctx, node_id, num_points);
+    let scalar_data_types: HashMap<String, AttributeDataType> = ctx
+        .attribute_data_types
         .iter()
-        .map(|(id, num_points)| {
-            let bounding_cube = id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
-            let position_encoding = PositionEncoding::new(&bounding_cube, octree_meta.resolution);
-            to_node_proto(&id, *num_points, &position_encoding)
-        })
+        .filter(|(_, data_type)| data_type.dim() == 1)
+        .map(|(name, data_type)| (name.clone(), *data_type))
         .collect();
-    let meta = to_meta_proto(&octree_meta, nodes);
-
-    let mut buf_writer =
-        BufWriter::new(File::create(&output_directory.as_ref().join(META_FILENAME)).unwrap());
-    meta.write_to_writer(&mut buf_writer).unwrap();
+    if scalar_data_types.is_empty() {
+        return Ok(node_meta);
+    }
+    let node_iterator = NodeIterator::from_data_provider(
+        &ctx.data_provider,
+        &scalar_data_types,
+        octree_meta.encoding_for_node(node_id),
+        &node_id,
+        num_points as usize,
+        NUM_POINTS_PER_BATCH,
+    )?;
+    let mut ranges: HashMap<String, (f64, f64)> = HashMap::new();
+    for batch in node_iterator {
+        for (name, data) in &batch.attributes {
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident) => {
+                    value_range($data)
+                };
+            }
+            if let Some((min, max)) = match_1d_attr_data!(data, rhs) {
+                let range = ranges.entry(name.clone()).or_insert((min, max));
+                *range = (range.0.min(min), range.1.max(max));
+            }
+        }
+        let label_counts = &mut node_meta.label_counts;
+        let mut count = |label: u16| *label_counts.entry(label).or_insert(0) += 1;
+        match ctx
+            .label_attribute
+            .as_ref()
+            .and_then(|attribute| batch.attributes.get(attribute))
+        {
+            Some(AttributeData::U16(labels)) => labels.iter().for_each(|label| count(*label)),
+            Some(AttributeData::U8(labels)) => {
+                labels.iter().for_each(|label| count(u16::from(*label)))
+            }
+            _ => (),
+        }
+    }
+    if !node_meta.label_counts.is_empty() {
+        node_meta.label_attribute = ctx.label_attribute.clone();
+    }
+    node_meta.attribute_ranges = ranges
+        .into_iter()
+        .map(|(name, (min, max))| (name, ClosedInterval::new(min, max)))
+        .collect();
+    Ok(node_meta)
 }
@@ -0,0 +1,440 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merging octrees, e.g. of regions that were acquired and built separately, into one octree.
+
+use crate::attribute_extension;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::iterator::{PointCloud, PointQuery};
+use crate::labels::{write_label_palette, LabelPalette};
+use crate::octree::generation::ExistingSubtrees;
+use crate::octree::node_id::MAX_LEVEL;
+use crate::octree::{BuildReport, ChildIndex, NodeId, Octree, OctreeBuilder, OctreeMeta};
+use crate::{NumberOfPoints, PointCloudMeta, PointsBatch, NUM_POINTS_PER_BATCH};
+use nalgebra::Point3;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::vec;
+
+/// Resolutions that differ by less than this are the same.
+const RESOLUTION_TOLERANCE: f64 = 1e-9;
+
+/// Cubes whose corners and edge lengths differ by less than this fraction of the edge length of
+/// the merged octree are the same.
+const CUBE_TOLERANCE: f64 = 1e-9;
+
+/// The points of all nodes of several octrees, corrected like queries correct them. Reading
+/// stops at the first error, which is kept in 'error'.
+struct MergeStream<'a> {
+    octrees: &'a [Octree],
+    query: PointQuery<'a>,
+    /// The index of the octree and the node to read next.
+    node_ids: vec::IntoIter<(usize, NodeId)>,
+    batches: vec::IntoIter<PointsBatch>,
+    num_points: usize,
+    error: &'a Mutex<Option<Error>>,
+}
+
+impl<'a> Iterator for MergeStream<'a> {
+    type Item = PointsBatch;
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        loop {
+            if let Some(batch) = self.batches.next() {
+                return Some(batch);
+            }
+            let (index, node_id) = self.node_ids.next()?;
+            let mut batches = Vec::new();
+            let result = self.octrees[index].stream_points_for_query_in_node(
+                &self.query,
+                node_id,
+                NUM_POINTS_PER_BATCH,
+                |batch| {
+                    batches.push(batch);
+                    Ok(())
+                },
+            );
+            if let Err(err) = result {
+                *self.error.lock().unwrap() = Some(err);
+                return None;
+            }
+            self.batches = batches.into_iter();
+        }
+    }
+}
+
+impl<'a> NumberOfPoints for MergeStream<'a> {
+    fn num_points(&self) -> usize {
+        self.num_points
+    }
+}
+
+/// Whether the interiors of 'a' and 'b' intersect. Boxes that only touch do not overlap.
+fn overlaps(a: &Aabb, b: &Aabb) -> bool {
+    (0..3).all(|i| a.min()[i] < b.max()[i] && b.min()[i] < a.max()[i])
+}
+
+/// The node of an octree with the bounding cube 'root_cube' whose cube is 'cube', if there is one.
+fn node_with_cube(root_cube: &Cube, cube: &Cube) -> Option<NodeId> {
+    let tolerance = CUBE_TOLERANCE * root_cube.edge_length();
+    let mut node_id = NodeId::root();
+    let mut node_cube = root_cube.clone();
+    while node_cube.edge_length() > 1.5 * cube.edge_length() && node_id.level() < MAX_LEVEL {
+        let child_index = ChildIndex::from_bounding_cube(&node_cube, &Point3::from(cube.center()));
+        node_id = node_id.get_child_id(child_index);
+        node_cube = node_id.find_bounding_cube(root_cube);
+    }
+    let is_same = (node_cube.edge_length() - cube.edge_length()).abs() <= tolerance
+        && (node_cube.min() - cube.min()).amax() <= tolerance;
+    if is_same {
+        Some(node_id)
+    } else {
+        None
+    }
+}
+
+/// The id that 'node_id' of an octree whose root is 'root_id' of the merged octree has there.
+fn merged_node_id(root_id: NodeId, node_id: NodeId) -> Option<NodeId> {
+    let level = root_id.level().checked_add(node_id.level())?;
+    if level > MAX_LEVEL {
+        return None;
+    }
+    Some(NodeId::from_level_index(
+        level,
+        root_id.index() << (3 * u32::from(node_id.level())) | node_id.index(),
+    ))
+}
+
+/// 'node_id' and all nodes below it in 'octree'.
+fn subtree(octree: &Octree, node_id: NodeId) -> Vec<NodeId> {
+    let mut subtree = Vec::new();
+    let mut to_visit = vec![node_id];
+    while let Some(node_id) = to_visit.pop() {
+        if octree.nodes.contains_key(&node_id) {
+            subtree.push(node_id);
+            to_visit.extend(node_id.children());
+        }
+    }
+    subtree
+}
+
+/// A node of an octree to merge that is copied into the merged octree with its files.
+struct CopiedNode {
+    index: usize,
+    node_id: NodeId,
+    merged_id: NodeId,
+}
+
+/// Which nodes of the octrees to merge are read and distributed to the nodes of the merged
+/// octree, and which are copied.
+#[derive(Default)]
+struct MergePlan {
+    /// The index of the octree and the node.
+    streamed_nodes: Vec<(usize, NodeId)>,
+    copied_nodes: Vec<CopiedNode>,
+    existing_subtrees: ExistingSubtrees,
+}
+
+/// The label palette of the octrees that have one, which must all be the same.
+fn merged_label_palette(octrees: &[Octree]) -> Result<Option<LabelPalette>> {
+    let mut palettes = octrees.iter().filter_map(Octree::label_palette);
+    let first = palettes.next();
+    if palettes.any(|palette| Some(palette) != first) {
+        return Err(ErrorKind::InvalidInput(
+            "The octrees to merge have different label palettes.".to_string(),
+        )
+        .into());
+    }
+    Ok(first.cloned())
+}
+
+impl OctreeBuilder {
+    /// Whether the files of the nodes of 'octree' can be copied into an octree with
+    /// 'merged_meta' as they are, i.e. they are stored like this builder would store them and
+    /// read without corrections.
+    fn can_copy_nodes(&self, octree: &Octree, merged_meta: &OctreeMeta) -> Result<bool> {
+        if self.max_depth().is_some()
+            || !octree.meta.interleaved_attributes().is_empty()
+            || octree.radiometric_correction.is_some()
+        {
+            return Ok(false);
+        }
+        for attribute in self.attributes() {
+            if octree.attribute_aliases.resolve(attribute) != attribute
+                || octree.stored_attribute_type(attribute)?
+                    != merged_meta.attribute_data_types().get(attribute).copied()
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Plans to copy the subtrees of the octrees that do not overlap any other octree, which
+    /// is possible if the bounding cube of their octree is a node of 'merged_meta'. The roots of
+    /// these subtrees are still read, so that their points move up to the new nodes above them.
+    /// All other nodes are read.
+    fn plan_merge(&self, octrees: &[Octree], merged_meta: &OctreeMeta) -> Result<MergePlan> {
+        let merged_cube = Cube::bounding(&merged_meta.bounding_box);
+        let mut plan = MergePlan::default();
+        for (index, octree) in octrees.iter().enumerate() {
+            let root_id =
+                match node_with_cube(&merged_cube, &Cube::bounding(&octree.meta.bounding_box)) {
+                    Some(root_id) if self.can_copy_nodes(octree, merged_meta)? => root_id,
+                    _ => {
+                        plan.streamed_nodes
+                            .extend(octree.nodes.keys().map(|node_id| (index, *node_id)));
+                        continue;
+                    }
+                };
+            let others: Vec<&Aabb> = octrees
+                .iter()
+                .enumerate()
+                .filter(|(other_index, _)| *other_index != index)
+                .map(|(_, other)| &other.meta.bounding_box)
+                .collect();
+            // The subtree below a node can be copied if it is stored with the encodings the
+            // merged octree would choose.
+            let is_copyable = |node_id: NodeId| match merged_node_id(root_id, node_id) {
+                Some(merged_id) => {
+                    let cube = merged_id.find_bounding_cube(&merged_cube);
+                    octree.nodes[&node_id].position_encoding == merged_meta.position_encoding(&cube)
+                }
+                None => false,
+            };
+            let mut to_visit = vec![NodeId::root()];
+            while let Some(node_id) = to_visit.pop() {
+                let node = match octree.nodes.get(&node_id) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                plan.streamed_nodes.push((index, node_id));
+                let subtree = subtree(octree, node_id);
+                let copy = node.num_points > 0
+                    && root_id.level() + node_id.level() > 0
+                    && !others
+                        .iter()
+                        .any(|other| overlaps(&node.bounding_cube.to_aabb(), other))
+                    && subtree.iter().all(|id| is_copyable(*id));
+                // Checked by 'is_copyable'.
+                let merged_id = |id| merged_node_id(root_id, id).unwrap();
+                // Subtrees of octrees with flat bounding boxes could otherwise end up in each other.
+                let roots = &plan.existing_subtrees.roots;
+                if !copy
+                    || merged_id(node_id).ancestors().any(|id| roots.contains(&id))
+                    || roots
+                        .iter()
+                        .any(|root| root.ancestors().any(|id| id == merged_id(node_id)))
+                {
+                    to_visit.extend(node_id.children());
+                    continue;
+                }
+                plan.existing_subtrees.roots.insert(merged_id(node_id));
+                for id in subtree.into_iter().filter(|id| *id != node_id) {
+                    plan.existing_subtrees
+                        .nodes
+                        .insert(merged_id(id), octree.nodes[&id].num_points);
+                    plan.copied_nodes.push(CopiedNode {
+                        index,
+                        node_id: id,
+                        merged_id: merged_id(id),
+                    });
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Copies the files of the positions and the attributes of 'node' into 'directory'.
+    fn copy_node_files(
+        &self,
+        octrees: &[Octree],
+        node: &CopiedNode,
+        directory: &Path,
+    ) -> Result<()> {
+        let octree = &octrees[node.index];
+        if octree.nodes[&node.node_id].num_points == 0 {
+            return Ok(());
+        }
+        let files: Vec<&str> = std::iter::once("position")
+            .chain(self.attributes().iter().map(String::as_str))
+            .collect();
+        let stem = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        }
+        .stem(&node.merged_id.to_string());
+        for (attribute, mut reader) in octree
+            .data_provider
+            .data(&node.node_id.to_string(), &files)?
+        {
+            let path = stem.with_extension(attribute_extension(&attribute));
+            io::copy(&mut reader, &mut File::create(&path)?)
+                .chain_err(|| format!("Could not copy {} of node {}", attribute, node.node_id))?;
+        }
+        Ok(())
+    }
+
+    /// Builds one octree out of all points of 'octrees' into 'output_directory', which must not
+    /// exist yet or be empty. The octrees must have been built with the resolution of this
+    /// builder and store all of its attributes. Points where the octrees overlap are distributed
+    /// to the nodes of the merged octree like any other points, use 'with_duplicate_suppression'
+    /// to drop those that were captured twice. Where an octree does not overlap the others, its
+    /// nodes are copied instead, but only if its bounding cube is one of the nodes of the merged
+    /// octree, e.g. for regions that were built with the cells of a common grid as their bounding
+    /// boxes. Radiometric corrections are applied to the merged points, and the label palette is
+    /// kept if the octrees agree on it. Thumbnails are dropped, since they only show one of the
+    /// octrees. The octree is built next to 'output_directory' and only moved there once it is
+    /// complete.
+    pub fn merge(
+        &self,
+        output_directory: impl AsRef<Path>,
+        octrees: &[Octree],
+    ) -> Result<BuildReport> {
+        let output_directory = output_directory.as_ref();
+        let first = octrees.first().ok_or_else(|| {
+            Error::from(ErrorKind::InvalidInput(
+                "There are no octrees to merge.".to_string(),
+            ))
+        })?;
+        for octree in octrees {
+            if (octree.meta.resolution - self.resolution()).abs() > RESOLUTION_TOLERANCE {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "An octree to merge has a resolution of {}, but the merged octree has {}.",
+                    octree.meta.resolution,
+                    self.resolution()
+                ))
+                .into());
+            }
+            for attribute in self.attributes() {
+                if octree.stored_attribute_type(attribute)?.is_none() {
+                    return Err(ErrorKind::InvalidInput(format!(
+                        "An octree to merge has no '{}' attribute.",
+                        attribute
+                    ))
+                    .into());
+                }
+            }
+        }
+        let label_palette = merged_label_palette(octrees)?.filter(|palette| {
+            self.attributes()
+                .iter()
+                .any(|attribute| *attribute == palette.attribute)
+        });
+        if let Ok(mut entries) = fs::read_dir(output_directory) {
+            if entries.next().is_some() {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The output directory {} is not empty.",
+                    output_directory.display()
+                ))
+                .into());
+            }
+        }
+        let building_directory = building_directory(output_directory)?;
+        // Left over from an interrupted merge.
+        let _ = fs::remove_dir_all(&building_directory);
+        fs::create_dir_all(&building_directory)?;
+
+        let mut bounding_box = first.meta.bounding_box.clone();
+        for octree in &octrees[1..] {
+            bounding_box.grow(*octree.meta.bounding_box.min());
+            bounding_box.grow(*octree.meta.bounding_box.max());
+        }
+        let result = self.merge_into(&building_directory, bounding_box, octrees, &label_palette);
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&building_directory);
+            return result;
+        }
+        if output_directory.exists() {
+            fs::remove_dir(output_directory)?;
+        }
+        fs::rename(&building_directory, output_directory).chain_err(|| {
+            format!(
+                "Could not move the merged octree to {}",
+                output_directory.display()
+            )
+        })?;
+        result
+    }
+
+    fn merge_into(
+        &self,
+        directory: &Path,
+        bounding_box: Aabb,
+        octrees: &[Octree],
+        label_palette: &Option<LabelPalette>,
+    ) -> Result<BuildReport> {
+        let merged_meta = self.octree_meta(bounding_box.clone())?;
+        let plan = self.plan_merge(octrees, &merged_meta)?;
+        self.install(|| {
+            plan.copied_nodes
+                .par_iter()
+                .try_for_each(|node| self.copy_node_files(octrees, node, directory))
+        })?;
+
+        let num_points = plan
+            .streamed_nodes
+            .iter()
+            .map(|(index, id)| octrees[*index].nodes[id].num_points as usize)
+            .sum();
+        let error = Mutex::new(None);
+        let stream = MergeStream {
+            octrees,
+            query: PointQuery {
+                attributes: self.attributes().iter().map(String::as_str).collect(),
+                ..Default::default()
+            },
+            node_ids: plan.streamed_nodes.into_iter(),
+            batches: Vec::new().into_iter(),
+            num_points,
+            error: &error,
+        };
+        // A read error ends the stream early, the octree of the points before it must not be
+        // completed.
+        let check_input = || match error.lock().unwrap().take() {
+            Some(err) => Err(err).chain_err(|| "Could not read the octrees to merge."),
+            None => Ok(()),
+        };
+        let report = self.build_with_existing_subtrees(
+            directory,
+            bounding_box,
+            stream,
+            &plan.existing_subtrees,
+            check_input,
+        )?;
+        if let Some(label_palette) = label_palette {
+            write_label_palette(directory, label_palette)?;
+        }
+        Ok(report)
+    }
+}
+
+/// The directory next to 'output_directory' that the merged octree is built in.
+fn building_directory(output_directory: &Path) -> Result<PathBuf> {
+    let mut file_name = output_directory
+        .file_name()
+        .ok_or_else(|| {
+            ErrorKind::InvalidInput(format!(
+                "Invalid output directory {}.",
+                output_directory.display()
+            ))
+        })?
+        .to_os_string();
+    file_name.push(".merging");
+    Ok(output_directory.with_file_name(file_name))
+}
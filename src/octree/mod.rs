@@ -11,33 +11,68 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::data_provider::DataProvider;
+use crate::attributes::AttributeAliases;
+use crate::calibration::RadiometricCorrection;
+use crate::data_provider::{AsyncDataProvider, DataProvider, DataSource};
 use crate::errors::*;
-use crate::geometry::{Aabb, Cube, Frustum};
-use crate::iterator::{PointCloud, PointLocation};
+use crate::geometry::{Aabb, CachedFrustumIntersector, Cube};
+use crate::iterator::{
+    ApproximatedRegion, PointCloud, PointLocation, PointQuery, QueryPlan, QueryStrategy,
+};
+use crate::labels::{decode_label_ids, LabelPalette};
 use crate::math::base::{HasAabbIntersector, IntersectAabb};
 use crate::math::sat::{ConvexPolyhedron, Relation};
-use crate::math::AllPoints;
+use crate::math::{AllPoints, ClosedInterval};
 use crate::proto;
-use crate::read_write::{Encoding, NodeIterator, PositionEncoding};
+use crate::read_write::{
+    check_layout, deinterleave, read_layout_header, Encoding, NodeIterator, NodeLayout,
+    PositionEncoding, INTERLEAVED,
+};
+use crate::thumbnails::Thumbnail;
 use crate::{AttributeDataType, PointCloudMeta, CURRENT_VERSION};
-use fnv::FnvHashMap;
-use nalgebra::{Matrix4, Point3};
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::{FnvHashMap, FnvHashSet};
+use nalgebra::{Matrix4, Point3, Vector3};
 use num::clamp;
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
-use std::io::{BufReader, Read};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::io::{BufReader, Cursor, Read};
+use std::sync::Arc;
 
+#[cfg(feature = "build")]
+mod bundle;
+#[cfg(feature = "build")]
+pub use self::bundle::{export_bundle, export_bundle_archive};
+
+#[cfg(feature = "build")]
 mod generation;
-pub use self::generation::{build_octree, build_octree_from_file};
+#[cfg(feature = "build")]
+pub use self::generation::{make_stream, BuildReport, InputFile, InputStream, OctreeBuilder};
+
+#[cfg(feature = "build")]
+mod merge;
+
+mod edit;
+pub use self::edit::delete_points_in_location;
 
 mod node;
-pub use self::node::{to_node_proto, ChildIndex, Node, NodeId, NodeMeta};
+pub use self::node::{to_node_proto, Node, NodeMeta};
+
+pub mod node_id;
+pub use self::node_id::{ChildIndex, NodeId};
+
+mod occupancy;
+pub use self::occupancy::{Occupancy, MAX_OCCUPANCY_LEVEL};
 
 mod octree_iterator;
 pub use self::octree_iterator::NodeIdsIterator;
 
-#[cfg(test)]
+mod upgrade;
+pub use self::upgrade::{upgrade_meta_proto, upgrade_meta_proto_to_current};
+
+#[cfg(all(test, feature = "build"))]
 mod tests;
 
 #[derive(Clone, Debug)]
@@ -45,6 +80,13 @@ pub struct OctreeMeta {
     pub resolution: f64,
     pub bounding_box: Aabb,
     attribute_data_types: HashMap<String, AttributeDataType>,
+    position_encoding: Option<PositionEncoding>,
+    interleaved_attributes: Vec<String>,
+    /// Whether the attributes were listed in the meta proto. Then every node stores all of them,
+    /// otherwise they are the implied standard attributes.
+    attributes_listed: bool,
+    /// Whether points were deleted after the octree was built, see the meta proto.
+    pub points_deleted: bool,
 }
 
 impl PointCloudMeta for OctreeMeta {
@@ -54,28 +96,114 @@ impl PointCloudMeta for OctreeMeta {
 }
 
 impl OctreeMeta {
-    /// An octree currently does not store its data types, instead, color and
-    /// intensity are implied. We already do have attributes as part of the
-    /// meta data structure, but not its serialized form. So the data structure
-    /// is initialized with color and intensity hardcoded until attributes are
-    /// in the meta proto.
+    pub fn new(
+        resolution: f64,
+        bounding_box: Aabb,
+        attribute_data_types: HashMap<String, AttributeDataType>,
+    ) -> Self {
+        Self {
+            resolution,
+            bounding_box,
+            attribute_data_types,
+            position_encoding: None,
+            interleaved_attributes: Vec::new(),
+            attributes_listed: false,
+            points_deleted: false,
+        }
+    }
+
+    /// Octrees written before their attributes were stored in the meta imply color, intensity,
+    /// alpha, class, label and timestamp. Only positions are required, nodes of octrees built
+    /// without any of these attributes have no files for them.
     pub fn new_with_standard_attributes(resolution: f64, bounding_box: Aabb) -> Self {
         let attribute_data_types = vec![
             ("color".to_string(), AttributeDataType::U8Vec3),
             ("intensity".to_string(), AttributeDataType::F32),
+            ("alpha".to_string(), AttributeDataType::U8),
+            ("class".to_string(), AttributeDataType::U8),
+            ("label".to_string(), AttributeDataType::U16),
+            ("timestamp".to_string(), AttributeDataType::F64),
         ]
         .into_iter()
         .collect();
-        Self {
-            resolution,
-            bounding_box,
-            attribute_data_types,
+        Self::new(resolution, bounding_box, attribute_data_types)
+    }
+
+    /// The octree meta of 'meta', which must be of the current version. Its attributes are the
+    /// ones listed in the meta, or the standard ones if it lists none.
+    pub fn from_proto(meta: &proto::Meta) -> Result<Self> {
+        if !meta.has_octree() {
+            return Err(ErrorKind::InvalidInput("No octree meta found".to_string()).into());
         }
+        let octree_meta = meta.get_octree();
+        let bounding_box = Aabb::from(meta.get_bounding_box());
+        let octree_meta_with_attributes = if octree_meta.get_attributes().is_empty() {
+            Self::new_with_standard_attributes(octree_meta.resolution, bounding_box)
+        } else {
+            let mut attribute_data_types = HashMap::new();
+            for attribute in octree_meta.get_attributes() {
+                attribute_data_types.insert(
+                    attribute.name.clone(),
+                    AttributeDataType::from_proto(attribute.data_type)?,
+                );
+            }
+            let mut octree_meta_with_attributes =
+                Self::new(octree_meta.resolution, bounding_box, attribute_data_types);
+            octree_meta_with_attributes.attributes_listed = true;
+            octree_meta_with_attributes
+        };
+        let mut octree_meta_with_attributes = octree_meta_with_attributes
+            .with_interleaved_attributes(octree_meta.get_interleaved_attributes().to_vec());
+        octree_meta_with_attributes.points_deleted = octree_meta.points_deleted;
+        Ok(octree_meta_with_attributes)
+    }
+
+    /// Stores the positions and 'interleaved_attributes' of each node interleaved in a single
+    /// file, see 'interleaved_attributes' in the meta proto.
+    pub fn with_interleaved_attributes(mut self, interleaved_attributes: Vec<String>) -> Self {
+        self.interleaved_attributes = interleaved_attributes;
+        self
+    }
+
+    /// The attributes that are interleaved with the positions, empty if each attribute has its
+    /// own file.
+    pub fn interleaved_attributes(&self) -> &[String] {
+        &self.interleaved_attributes
+    }
+
+    /// The sizes of the position and of each interleaved attribute of a point in the interleaved
+    /// file of a node, in this order.
+    pub fn interleaved_bytes_per_point(
+        &self,
+        position_encoding: &PositionEncoding,
+    ) -> Result<Vec<usize>> {
+        let mut bytes_per_point = vec![3 * position_encoding.bytes_per_coordinate()];
+        for attribute in &self.interleaved_attributes {
+            let data_type = self.attribute_data_types.get(attribute).ok_or_else(|| {
+                ErrorKind::InvalidInput(format!("Unknown interleaved attribute {}.", attribute))
+            })?;
+            bytes_per_point.push(data_type.size_of());
+        }
+        Ok(bytes_per_point)
+    }
+
+    /// Encodes the positions of all nodes with 'position_encoding' instead of the smallest
+    /// encoding that keeps the resolution.
+    pub fn with_position_encoding(mut self, position_encoding: PositionEncoding) -> Self {
+        self.position_encoding = Some(position_encoding);
+        self
+    }
+
+    /// The encoding of the positions of a node with the given bounding cube.
+    pub fn position_encoding(&self, bounding_cube: &Cube) -> PositionEncoding {
+        self.position_encoding
+            .clone()
+            .unwrap_or_else(|| PositionEncoding::new(bounding_cube, self.resolution))
     }
 
     pub fn encoding_for_node(&self, id: NodeId) -> Encoding {
         let bounding_cube = id.find_bounding_cube(&Cube::bounding(&self.bounding_box));
-        let position_encoding = PositionEncoding::new(&bounding_cube, self.resolution);
+        let position_encoding = self.position_encoding(&bounding_cube);
         Encoding::ScaledToCube(
             bounding_cube.min(),
             bounding_cube.edge_length(),
@@ -87,9 +215,32 @@ impl OctreeMeta {
 pub fn to_meta_proto(octree_meta: &OctreeMeta, nodes: Vec<proto::OctreeNode>) -> proto::Meta {
     let mut octree_proto = proto::OctreeMeta::new();
     octree_proto.set_resolution(octree_meta.resolution);
+    let node_ids: Vec<NodeId> = nodes
+        .iter()
+        .map(|node| NodeId::from_proto(node.get_id()))
+        .collect();
+    octree_proto.set_occupancy(::protobuf::RepeatedField::from_vec(
+        Occupancy::from_node_ids(&node_ids).to_proto(),
+    ));
 
     let octree_nodes = ::protobuf::RepeatedField::<proto::OctreeNode>::from_vec(nodes);
     octree_proto.set_nodes(octree_nodes);
+    octree_proto.set_interleaved_attributes(::protobuf::RepeatedField::from_vec(
+        octree_meta.interleaved_attributes.clone(),
+    ));
+    let mut attributes: Vec<proto::Attribute> = octree_meta
+        .attribute_data_types
+        .iter()
+        .map(|(name, data_type)| {
+            let mut attribute = proto::Attribute::new();
+            attribute.set_name(name.to_string());
+            attribute.set_data_type(data_type.to_proto());
+            attribute
+        })
+        .collect();
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+    octree_proto.set_attributes(::protobuf::RepeatedField::from_vec(attributes));
+    octree_proto.set_points_deleted(octree_meta.points_deleted);
 
     let mut meta = proto::Meta::new();
     meta.set_version(CURRENT_VERSION);
@@ -139,16 +290,167 @@ fn relative_size_on_screen(bounding_cube: &Cube, matrix: &Matrix4<f64>) -> f64 {
 }
 
 pub struct Octree {
-    data_provider: Box<dyn DataProvider>,
+    data_provider: Arc<dyn DataProvider>,
     meta: OctreeMeta,
     nodes: FnvHashMap<NodeId, NodeMeta>,
+    label_palette: Option<LabelPalette>,
+    radiometric_correction: Option<RadiometricCorrection>,
+    attribute_aliases: AttributeAliases,
+    thumbnails: Vec<Thumbnail>,
+    occupancy: Occupancy,
+    generation: u64,
+}
+
+/// Which attribute 'NodeData' gets the colors of its points from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSource {
+    /// The colors, or the intensity for octrees without colors.
+    Color,
+    /// The intensity, also for octrees with colors.
+    Intensity,
 }
 
 #[derive(Debug)]
 pub struct NodeData {
     pub meta: NodeMeta,
     pub position: Vec<u8>,
-    pub color: Vec<u8>,
+    // Three bytes per point, if the octree was built with colors.
+    pub color: Option<Vec<u8>>,
+    // One little endian f32 per point. Only read for octrees without colors, see 'colors'.
+    pub intensity: Option<Vec<u8>>,
+    // One opacity byte per point, if the octree was built with alpha.
+    pub alpha: Option<Vec<u8>>,
+    // One label id per point, if the octree has a label palette.
+    pub labels: Option<Vec<u16>>,
+    // One little endian f64 per point, if the octree was built with timestamps.
+    pub timestamp: Option<Vec<u8>>,
+}
+
+impl NodeData {
+    /// The colors of the points, three bytes per point. Points without colors are shaded in gray
+    /// by their intensity within 'intensity_range', or within the intensity range of this node if
+    /// it is None. Points without intensity either are white.
+    pub fn colors(&self, intensity_range: Option<ClosedInterval<f64>>) -> Cow<'_, [u8]> {
+        match &self.color {
+            Some(color) => Cow::Borrowed(color),
+            None => Cow::Owned(shade_by_intensity(
+                &self.meta,
+                self.intensity.as_deref(),
+                intensity_range,
+            )),
+        }
+    }
+}
+
+/// Gray colors for the 'intensity' of the points of a node, see 'NodeData::colors'.
+fn shade_by_intensity(
+    meta: &NodeMeta,
+    intensity: Option<&[u8]>,
+    intensity_range: Option<ClosedInterval<f64>>,
+) -> Vec<u8> {
+    let num_points = meta.num_points as usize;
+    let intensity = match intensity {
+        Some(intensity) => intensity,
+        None => return vec![255; 3 * num_points],
+    };
+    let range = intensity_range
+        .or_else(|| meta.attribute_ranges.get("intensity").copied())
+        .unwrap_or_else(|| ClosedInterval::new(0., 1.));
+    let min = range.lower_bound();
+    let extent = range.upper_bound() - min;
+    let mut color = Vec::with_capacity(3 * num_points);
+    for bytes in intensity.chunks_exact(4) {
+        let value = f64::from(LittleEndian::read_f32(bytes));
+        let gray = if extent > 0. {
+            (clamp((value - min) / extent, 0., 1.) * 255.).round() as u8
+        } else {
+            255
+        };
+        color.extend_from_slice(&[gray, gray, gray]);
+    }
+    color
+}
+
+/// How far the number of points of a parent may be off from what subsampling its children yields,
+/// relative to that.
+const POINT_COUNT_TOLERANCE: f64 = 0.1;
+
+/// The nodes that cannot be reached from the root because one of their ancestors is missing.
+/// Traversals never visit them.
+pub fn find_unreachable_nodes<'a>(node_ids: impl IntoIterator<Item = &'a NodeId>) -> Vec<NodeId> {
+    let mut node_ids: Vec<NodeId> = node_ids.into_iter().copied().collect();
+    node_ids.sort_by_key(NodeId::level);
+    let mut reachable = FnvHashSet::default();
+    let mut unreachable = Vec::new();
+    for node_id in node_ids {
+        match node_id.parent_id() {
+            Some(parent_id) if !reachable.contains(&parent_id) => unreachable.push(node_id),
+            _ => {
+                reachable.insert(node_id);
+            }
+        }
+    }
+    unreachable
+}
+
+/// The nodes whose number of points does not match the ones of their children. When building,
+/// every parent takes every 8th point of each child, so it ends up with about a seventh of the
+/// points left in its children, plus at most one per child from rounding.
+fn find_inconsistent_point_counts(nodes: &FnvHashMap<NodeId, NodeMeta>) -> Vec<NodeId> {
+    let mut children_points: FnvHashMap<NodeId, (i64, i64)> = FnvHashMap::default();
+    for (node_id, node_meta) in nodes {
+        if let Some(parent_id) = node_id.parent_id() {
+            let entry = children_points.entry(parent_id).or_default();
+            entry.0 += node_meta.num_points;
+            entry.1 += 1;
+        }
+    }
+    let mut inconsistent: Vec<NodeId> = children_points
+        .into_iter()
+        .filter(|(parent_id, (num_children_points, num_children))| {
+            let num_points = match nodes.get(parent_id) {
+                Some(parent_meta) => parent_meta.num_points as f64,
+                None => return false,
+            };
+            let expected = *num_children_points as f64 / 7.;
+            let slack = POINT_COUNT_TOLERANCE * expected;
+            num_points < expected - slack || num_points > expected + slack + *num_children as f64
+        })
+        .map(|(parent_id, _)| parent_id)
+        .collect();
+    inconsistent.sort_by_key(|node_id| (node_id.level(), node_id.index()));
+    inconsistent
+}
+
+/// Fails for octrees with nodes that would never be shown and hints at nodes with unexpected
+/// numbers of points, suggesting how to fix them. Octrees that points were deleted from have
+/// those by design, so their point counts are not checked.
+fn check_consistency(nodes: &FnvHashMap<NodeId, NodeMeta>, points_deleted: bool) -> Result<()> {
+    let unreachable = find_unreachable_nodes(nodes.keys());
+    if let Some(node_id) = unreachable.first() {
+        return Err(ErrorKind::InvalidInput(format!(
+            "{} nodes of the octree, e.g. {}, have ancestors that are missing from the meta, so \
+             their points can never be read. Run `point_cloud_gc --repair` on the octree to \
+             remove them.",
+            unreachable.len(),
+            node_id
+        ))
+        .into());
+    }
+    if points_deleted {
+        return Ok(());
+    }
+    let inconsistent = find_inconsistent_point_counts(nodes);
+    if let Some(node_id) = inconsistent.first() {
+        eprintln!(
+            "Hint: {} nodes of the octree, e.g. {}, do not have about a seventh of the points of \
+             their children, so the octree was probably modified after it was built. If levels \
+             of detail look uneven, rebuild it with `build_octree`.",
+            inconsistent.len(),
+            node_id
+        );
+    }
+    Ok(())
 }
 
 impl Octree {
@@ -162,7 +464,7 @@ impl Octree {
                 meta_proto.version, CURRENT_VERSION
             );
         }
-        let (bounding_box, meta, nodes_proto) = match meta_proto.version {
+        let (bounding_box, meta, nodes_proto, occupancy_proto) = match meta_proto.version {
             9 | 10 | 11 => {
                 let bounding_box = Aabb::from(meta_proto.get_bounding_box());
                 (
@@ -172,22 +474,32 @@ impl Octree {
                         bounding_box,
                     ),
                     meta_proto.get_deprecated_nodes(),
+                    &[][..],
                 )
             }
-            12 | CURRENT_VERSION => {
+            12 => {
                 if !meta_proto.has_octree() {
                     return Err(ErrorKind::InvalidInput("No octree meta found".to_string()).into());
                 }
                 let octree_meta = meta_proto.get_octree();
-                let bounding_box = Aabb::from(if meta_proto.version == 12 {
-                    octree_meta.get_deprecated_bounding_box()
-                } else {
-                    meta_proto.get_bounding_box()
-                });
+                let bounding_box = Aabb::from(octree_meta.get_deprecated_bounding_box());
                 (
                     bounding_box.clone(),
-                    OctreeMeta::new_with_standard_attributes(octree_meta.resolution, bounding_box),
+                    OctreeMeta::new_with_standard_attributes(octree_meta.resolution, bounding_box)
+                        .with_interleaved_attributes(
+                            octree_meta.get_interleaved_attributes().to_vec(),
+                        ),
                     octree_meta.get_nodes(),
+                    octree_meta.get_occupancy(),
+                )
+            }
+            CURRENT_VERSION => {
+                let octree_meta = OctreeMeta::from_proto(&meta_proto)?;
+                (
+                    octree_meta.bounding_box.clone(),
+                    octree_meta,
+                    meta_proto.get_octree().get_nodes(),
+                    meta_proto.get_octree().get_occupancy(),
                 )
             }
             _ => return Err(ErrorKind::InvalidVersion(meta_proto.version).into()),
@@ -199,18 +511,37 @@ impl Octree {
             let node_id = NodeId::from_proto(node_proto.id.as_ref().unwrap());
             nodes.insert(
                 node_id,
-                NodeMeta {
-                    num_points: node_proto.num_points,
-                    position_encoding: PositionEncoding::from_proto(node_proto.position_encoding)?,
-                    bounding_cube: node_id.find_bounding_cube(&Cube::bounding(&bounding_box)),
-                },
+                NodeMeta::from_proto(
+                    node_proto,
+                    node_id.find_bounding_cube(&Cube::bounding(&bounding_box)),
+                )?,
             );
         }
+        check_consistency(&nodes, meta.points_deleted)?;
+        // Octrees built before the occupancy was stored in the meta have none.
+        let occupancy = if occupancy_proto.is_empty() {
+            Occupancy::from_node_ids(nodes.keys())
+        } else {
+            Occupancy::from_proto(occupancy_proto)?
+        };
+
+        let label_palette = LabelPalette::from_meta_proto(&meta_proto)?;
+        if let Some(label_palette) = &label_palette {
+            if let Some(data_type) = meta.attribute_data_types().get(&label_palette.attribute) {
+                label_palette.check_ids(*data_type)?;
+            }
+        }
 
         Ok(Octree {
             meta,
             nodes,
-            data_provider,
+            data_provider: Arc::from(data_provider),
+            label_palette,
+            radiometric_correction: RadiometricCorrection::from_meta_proto(&meta_proto),
+            attribute_aliases: AttributeAliases::from_meta_proto(&meta_proto),
+            thumbnails: Thumbnail::from_meta_proto(&meta_proto),
+            occupancy,
+            generation: meta_proto.generation,
         })
     }
 
@@ -218,17 +549,196 @@ impl Octree {
         let nodes: Vec<proto::OctreeNode> = self
             .nodes
             .iter()
-            .map(|(id, node_meta)| {
-                to_node_proto(&id, node_meta.num_points, &node_meta.position_encoding)
-            })
+            .map(|(id, node_meta)| to_node_proto(&id, node_meta))
             .collect();
-        to_meta_proto(&self.meta, nodes)
+        let mut meta = to_meta_proto(&self.meta, nodes);
+        if let Some(label_palette) = &self.label_palette {
+            meta.set_label_palette(label_palette.to_proto());
+        }
+        if let Some(radiometric_correction) = &self.radiometric_correction {
+            meta.set_radiometric_correction(radiometric_correction.to_proto());
+        }
+        meta.set_attribute_aliases(::protobuf::RepeatedField::from_vec(
+            self.attribute_aliases.to_proto(),
+        ));
+        meta.set_thumbnails(::protobuf::RepeatedField::from_vec(
+            self.thumbnails.iter().map(Thumbnail::to_proto).collect(),
+        ));
+        meta.set_generation(self.generation);
+        meta
+    }
+
+    /// The attributes that are stored interleaved with the positions, see
+    /// 'OctreeMeta::interleaved_attributes'. Tools that rewrite the files of single attributes
+    /// cannot modify them.
+    pub fn interleaved_attributes(&self) -> &[String] {
+        self.meta.interleaved_attributes()
+    }
+
+    /// The minimal precision the octree was built with.
+    pub fn resolution(&self) -> f64 {
+        self.meta.resolution
+    }
+
+    /// The names of the attributes the octree may store, sorted. Nodes of octrees built without
+    /// some of them have no data for these, see 'PointCloud::stored_attribute_type'.
+    pub fn attribute_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .meta
+            .attribute_data_types()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The names of the attributes the nodes have data for, sorted, i.e. 'attribute_names'
+    /// without those the octree was built without.
+    pub fn stored_attribute_names(&self) -> Result<Vec<&str>> {
+        let mut names = Vec::new();
+        for name in self.attribute_names() {
+            if self.stored_attribute_type(name)?.is_some() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// The generation of the meta this octree was read from. It grows whenever the meta on disk
+    /// is replaced, see 'OnDiskDataProvider::write_meta_proto'. The octree itself never changes,
+    /// so it stays a consistent snapshot while the data is updated.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The names and colors of the per-point labels, if this octree has any.
+    pub fn label_palette(&self) -> Option<&LabelPalette> {
+        self.label_palette.as_ref()
+    }
+
+    /// Uses 'label_palette' instead of the one stored with the octree, e.g. to try other colors
+    /// or to show the labels of an octree that was built without a palette.
+    pub fn with_label_palette(mut self, label_palette: LabelPalette) -> Self {
+        self.label_palette = Some(label_palette);
+        self
+    }
+
+    /// The preview images of this octree, see 'write_thumbnails'.
+    pub fn thumbnails(&self) -> &[Thumbnail] {
+        &self.thumbnails
+    }
+
+    /// Which nodes exist on the coarse levels, see 'Occupancy'.
+    pub fn occupancy(&self) -> &Occupancy {
+        &self.occupancy
+    }
+
+    /// Whether there may be points within 'radius' meters of 'point'. Only looks at the
+    /// occupancy, so it is instant, but it may answer true for points up to a cell of
+    /// 'MAX_OCCUPANCY_LEVEL' away.
+    pub fn may_have_points_near(&self, point: &Point3<f64>, radius: f64) -> bool {
+        let radius = Vector3::repeat(radius);
+        self.occupancy.may_contain_points(
+            &Cube::bounding(&self.meta.bounding_box),
+            &Aabb::new(point - radius, point + radius),
+        )
+    }
+
+    /// Reads the PNG file of the thumbnail with the given view.
+    pub fn read_thumbnail(&self, view: &str) -> Result<Vec<u8>> {
+        if !self
+            .thumbnails
+            .iter()
+            .any(|thumbnail| thumbnail.view == view)
+        {
+            return Err(
+                ErrorKind::InvalidInput(format!("There is no '{}' thumbnail.", view)).into(),
+            );
+        }
+        let mut readers = self
+            .data_provider
+            .data(&Thumbnail::node_id(view), &["png"])?;
+        let mut data = Vec::new();
+        readers
+            .remove("png")
+            .ok_or(ErrorKind::NodeNotFound)?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Whether 'is_hidden' returns true for the labels of all points in the node, so that it
+    /// need not be fetched. False for unknown nodes and octrees without per-node label counts.
+    pub fn all_labels_hidden(&self, node_id: &NodeId, is_hidden: impl Fn(u16) -> bool) -> bool {
+        self.nodes
+            .get(node_id)
+            .map_or(false, |node_meta| node_meta.all_labels_hidden(is_hidden))
+    }
+
+    /// The range of the values of 'attribute' over all nodes. None if the nodes have no
+    /// statistics for it, e.g. because the octree was built before they were recorded.
+    pub fn attribute_range(&self, attribute: &str) -> Option<ClosedInterval<f64>> {
+        self.nodes
+            .values()
+            .filter_map(|node_meta| node_meta.attribute_ranges.get(attribute))
+            .fold(None, |range: Option<ClosedInterval<f64>>, node_range| {
+                Some(match range {
+                    Some(range) => ClosedInterval::new(
+                        range.lower_bound().min(node_range.lower_bound()),
+                        range.upper_bound().max(node_range.upper_bound()),
+                    ),
+                    None => *node_range,
+                })
+            })
+    }
+
+    /// The meta of the node, None if the octree has no such node.
+    pub fn node_meta(&self, node_id: &NodeId) -> Option<&NodeMeta> {
+        self.nodes.get(node_id)
+    }
+
+    /// The ids of all nodes with a level of at most 'max_level', coarsest first.
+    pub fn node_ids_up_to_level(&self, max_level: u8) -> Vec<NodeId> {
+        let root = Node::root_with_bounding_cube(Cube::bounding(&self.meta.bounding_box));
+        let mut node_ids = Vec::new();
+        let mut open = VecDeque::new();
+        open.push_back(root.id);
+        while let Some(node_id) = open.pop_front() {
+            if !self.nodes.contains_key(&node_id) {
+                continue;
+            }
+            node_ids.push(node_id);
+            if node_id.level() < max_level {
+                for child_index in 0..8 {
+                    open.push_back(node_id.get_child_id(ChildIndex::from_u8(child_index)));
+                }
+            }
+        }
+        node_ids
     }
 
     pub fn get_visible_nodes(&self, projection_matrix: &Matrix4<f64>) -> Vec<NodeId> {
         let frustum =
-            Frustum::from_matrix4(*projection_matrix).expect("Invalid projection matrix.");
-        let frustum_isec = frustum.intersector().cache_separating_axes_for_aabb();
+            CachedFrustumIntersector::new(*projection_matrix).expect("Invalid projection matrix.");
+        self.get_visible_nodes_cached(&frustum)
+    }
+
+    /// Like 'get_visible_nodes', but with a frustum intersector that callers can keep and update
+    /// between frames.
+    pub fn get_visible_nodes_cached(&self, frustum: &CachedFrustumIntersector) -> Vec<NodeId> {
+        self.get_visible_nodes_cancellable(frustum, || false)
+            .expect("Traversal is never cancelled.")
+    }
+
+    /// Like 'get_visible_nodes_cached', but calls 'is_cancelled' before visiting each node and
+    /// stops with None as soon as it returns true, e.g. because the camera has moved on.
+    pub fn get_visible_nodes_cancellable(
+        &self,
+        frustum: &CachedFrustumIntersector,
+        is_cancelled: impl Fn() -> bool,
+    ) -> Option<Vec<NodeId>> {
+        let projection_matrix = frustum.clip_from_query();
+        let frustum_isec = frustum.intersector();
         let mut open = BinaryHeap::new();
         maybe_push_node(
             &mut open,
@@ -240,6 +750,9 @@ impl Octree {
 
         let mut visible = Vec::new();
         while let Some(current) = open.pop() {
+            if is_cancelled() {
+                return None;
+            }
             match current.relation {
                 Relation::Cross => {
                     for child_index in 0..8 {
@@ -279,35 +792,424 @@ impl Octree {
                 visible.push(current.node.id);
             }
         }
-        visible
+        Some(visible)
+    }
+
+    /// Where the data of the node would be read from right now, e.g. to tell slow loads from a
+    /// remote apart from loads from a local cache.
+    pub fn data_source(&self, node_id: &NodeId) -> DataSource {
+        self.data_provider.data_source(&node_id.to_string())
+    }
+
+    /// The data provider the octree reads its nodes from, e.g. to wrap it into an
+    /// 'AsyncDataProvider' for 'fetch_node_data_colored_by'.
+    pub fn data_provider(&self) -> Arc<dyn DataProvider> {
+        Arc::clone(&self.data_provider)
     }
 
     pub fn get_node_data(&self, node_id: &NodeId) -> Result<NodeData> {
+        self.get_node_data_colored_by(node_id, ColorSource::Color)
+    }
+
+    /// Like 'get_node_data', but only reads the attribute that 'source' colors the points by.
+    pub fn get_node_data_colored_by(
+        &self,
+        node_id: &NodeId,
+        source: ColorSource,
+    ) -> Result<NodeData> {
         // TODO(hrapp): If we'd randomize the points while writing, we could just read the
         // first N points instead of reading everything and skipping over a few.
-        let mut position_color_reads = self
-            .data_provider
-            .data(&node_id.to_string(), &["position", "color"])?;
-
-        let mut get_data = |node_attribute: &str, err: &str| -> Result<Vec<u8>> {
-            let mut reader =
-                BufReader::new(position_color_reads.remove(node_attribute).ok_or(err)?);
-            let mut all_data = Vec::new();
-            reader.read_to_end(&mut all_data).chain_err(|| err)?;
-            Ok(all_data)
+        // With interleaved attributes, this also returns all of them.
+        let readers = self.node_readers(node_id, &[])?;
+        self.node_data_from_readers(node_id, source, readers, |attribute| {
+            self.get_optional_data(node_id, attribute)
+        })
+    }
+
+    /// Like 'get_node_data_colored_by', but fetches the files through 'data_provider', which must
+    /// serve this octree. Several nodes can then be fetched at once, and a fetch is cancelled by
+    /// dropping its future.
+    pub async fn fetch_node_data_colored_by(
+        &self,
+        data_provider: &dyn AsyncDataProvider,
+        node_id: &NodeId,
+        source: ColorSource,
+    ) -> Result<NodeData> {
+        let readers = data_provider
+            .data(&node_id.to_string(), &self.node_files(&[]))
+            .await?;
+        let readers = self.deinterleave_readers(node_id, readers)?;
+        let mut optional_data = HashMap::new();
+        for attribute in self.optional_node_attributes(node_id, source) {
+            let has_color = source == ColorSource::Color
+                && (readers.contains_key("color")
+                    || matches!(optional_data.get("color"), Some(Some(_))));
+            if readers.contains_key(attribute) || (attribute == "intensity" && has_color) {
+                continue;
+            }
+            let data = self
+                .fetch_node_attribute(data_provider, node_id, attribute)
+                .await?;
+            optional_data.insert(attribute.to_string(), data);
+        }
+        self.node_data_from_readers(node_id, source, readers, |attribute| {
+            Ok(optional_data.remove(attribute).flatten())
+        })
+    }
+
+    /// The attributes besides the positions that 'node_data_from_readers' may read for 'source',
+    /// in this order. Intensities are only read for nodes without colors.
+    fn optional_node_attributes(&self, node_id: &NodeId, source: ColorSource) -> Vec<&str> {
+        let mut attributes = Vec::new();
+        if source == ColorSource::Color {
+            attributes.push("color");
+        }
+        attributes.extend_from_slice(&["intensity", "alpha"]);
+        if let Some(label_palette) = &self.label_palette {
+            attributes.push(label_palette.attribute.as_str());
+        }
+        if self.nodes[node_id]
+            .attribute_ranges
+            .contains_key("timestamp")
+        {
+            attributes.push("timestamp");
+        }
+        attributes
+    }
+
+    /// Decodes the data of a node from 'readers', which hold its positions and possibly more
+    /// attributes. The attributes that 'readers' lacks are read with 'read_optional_data'.
+    fn node_data_from_readers(
+        &self,
+        node_id: &NodeId,
+        source: ColorSource,
+        mut readers: HashMap<String, Box<dyn Read + Send>>,
+        mut read_optional_data: impl FnMut(&str) -> Result<Option<Vec<u8>>>,
+    ) -> Result<NodeData> {
+        let err = "Could not read position";
+        let mut position = Vec::new();
+        let position_reader = readers.remove("position").ok_or(err)?;
+        BufReader::new(self.strip_layout_header(node_id, "position", position_reader)?)
+            .read_to_end(&mut position)
+            .chain_err(|| err)?;
+        let mut get_optional_data = |attribute: &str| -> Result<Option<Vec<u8>>> {
+            match readers.remove(attribute) {
+                Some(reader) => {
+                    let mut all_data = Vec::new();
+                    self.strip_layout_header(node_id, attribute, reader)?
+                        .read_to_end(&mut all_data)
+                        .chain_err(|| format!("Could not read {}", attribute))?;
+                    Ok(Some(all_data))
+                }
+                None => read_optional_data(attribute),
+            }
+        };
+        let (color, intensity) = self.read_color_attributes(source, &mut get_optional_data)?;
+        let alpha = get_optional_data("alpha")?;
+        let labels = match &self.label_palette {
+            Some(label_palette) => match get_optional_data(&label_palette.attribute)? {
+                Some(data) => {
+                    let data_type = self
+                        .meta
+                        .attribute_data_types()
+                        .get(&label_palette.attribute)
+                        .ok_or("Unknown label attribute")?;
+                    Some(decode_label_ids(&data, *data_type)?)
+                }
+                None => None,
+            },
+            None => None,
+        };
+        // Nodes with timestamps have their range recorded, which saves the lookup for all others.
+        let timestamp = if self.nodes[node_id]
+            .attribute_ranges
+            .contains_key("timestamp")
+        {
+            get_optional_data("timestamp")?
+        } else {
+            None
         };
-        let position = get_data("position", "Could not read position")?;
-        let color = get_data("color", "Could not read color")?;
 
         Ok(NodeData {
             position,
             color,
+            intensity,
+            alpha,
+            labels,
+            timestamp,
             meta: self.nodes[node_id].clone(),
         })
     }
 
+    /// The colors of a node like 'NodeData::colors', three bytes per point. Only reads the
+    /// attribute that 'source' colors the points by, so viewers can recolor the nodes they
+    /// uploaded already without reading their positions again.
+    pub fn get_node_colors(
+        &self,
+        node_id: &NodeId,
+        source: ColorSource,
+        intensity_range: Option<ClosedInterval<f64>>,
+    ) -> Result<Vec<u8>> {
+        self.node_colors_from(node_id, source, intensity_range, |attribute| {
+            self.read_node_attribute(node_id, attribute)
+        })
+    }
+
+    /// Like 'get_node_colors', but fetches the files through 'data_provider', see
+    /// 'fetch_node_data_colored_by'.
+    pub async fn fetch_node_colors(
+        &self,
+        data_provider: &dyn AsyncDataProvider,
+        node_id: &NodeId,
+        source: ColorSource,
+        intensity_range: Option<ClosedInterval<f64>>,
+    ) -> Result<Vec<u8>> {
+        let mut color = match source {
+            ColorSource::Color => {
+                self.fetch_node_attribute(data_provider, node_id, "color")
+                    .await?
+            }
+            ColorSource::Intensity => None,
+        };
+        let mut intensity = match color {
+            Some(_) => None,
+            None => {
+                self.fetch_node_attribute(data_provider, node_id, "intensity")
+                    .await?
+            }
+        };
+        self.node_colors_from(node_id, source, intensity_range, |attribute| {
+            Ok(match attribute {
+                "color" => color.take(),
+                "intensity" => intensity.take(),
+                _ => None,
+            })
+        })
+    }
+
+    /// The colors of a node whose attributes are read with 'read'.
+    fn node_colors_from(
+        &self,
+        node_id: &NodeId,
+        source: ColorSource,
+        intensity_range: Option<ClosedInterval<f64>>,
+        read: impl FnMut(&str) -> Result<Option<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        let (color, intensity) = self.read_color_attributes(source, read)?;
+        Ok(match color {
+            Some(color) => color,
+            None => shade_by_intensity(&self.nodes[node_id], intensity.as_deref(), intensity_range),
+        })
+    }
+
+    /// Reads the colors and intensities that 'source' asks for with 'read', with the radiometric
+    /// correction applied.
+    fn read_color_attributes(
+        &self,
+        source: ColorSource,
+        mut read: impl FnMut(&str) -> Result<Option<Vec<u8>>>,
+    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let mut color = match source {
+            ColorSource::Color => read("color")?,
+            ColorSource::Intensity => None,
+        };
+        // Intensity is only needed to shade points that have no colors.
+        let mut intensity = match color {
+            Some(_) => None,
+            None => read("intensity")?,
+        };
+        if let Some(correction) = &self.radiometric_correction {
+            color.iter_mut().for_each(|c| correction.apply_to_colors(c));
+            intensity
+                .iter_mut()
+                .for_each(|i| correction.apply_to_intensities(i));
+        }
+        Ok((color, intensity))
+    }
+
+    /// Reads a single attribute of a node, None if the node does not have it. Attributes that are
+    /// interleaved with the positions can only be read together with them.
+    fn read_node_attribute(&self, node_id: &NodeId, attribute: &str) -> Result<Option<Vec<u8>>> {
+        if !self
+            .meta
+            .interleaved_attributes()
+            .iter()
+            .any(|a| a == attribute)
+        {
+            return self.get_optional_data(node_id, attribute);
+        }
+        let reader = match self.node_readers(node_id, &[])?.remove(attribute) {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        let mut data = Vec::new();
+        self.strip_layout_header(node_id, attribute, reader)?
+            .read_to_end(&mut data)
+            .chain_err(|| format!("Could not read {}", attribute))?;
+        Ok(Some(data))
+    }
+
+    /// Like 'read_node_attribute', but fetches the files through 'data_provider'.
+    async fn fetch_node_attribute(
+        &self,
+        data_provider: &dyn AsyncDataProvider,
+        node_id: &NodeId,
+        attribute: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let is_interleaved = self
+            .meta
+            .interleaved_attributes()
+            .iter()
+            .any(|a| a == attribute);
+        let files = if is_interleaved {
+            self.node_files(&[])
+        } else {
+            vec![attribute]
+        };
+        let readers = match data_provider.data(&node_id.to_string(), &files).await {
+            Ok(readers) => readers,
+            Err(Error(ErrorKind::NodeNotFound, _)) if !is_interleaved => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut readers = if is_interleaved {
+            self.deinterleave_readers(node_id, readers)?
+        } else {
+            readers
+        };
+        let reader = match readers.remove(attribute) {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        let mut data = Vec::new();
+        self.strip_layout_header(node_id, attribute, reader)?
+            .read_to_end(&mut data)
+            .chain_err(|| format!("Could not read {}", attribute))?;
+        Ok(Some(data))
+    }
+
+    /// The files that hold the position and 'attributes' of a node, as the data provider names
+    /// them.
+    fn node_files<'a>(&'a self, attributes: &[&'a str]) -> Vec<&'a str> {
+        let interleaved_attributes = self.meta.interleaved_attributes();
+        if interleaved_attributes.is_empty() {
+            return [&["position"], attributes].concat();
+        }
+        let mut files = vec![INTERLEAVED];
+        files.extend(
+            attributes
+                .iter()
+                .filter(|attribute| !interleaved_attributes.iter().any(|a| a == *attribute)),
+        );
+        files
+    }
+
+    /// Hints the data provider that 'get_node_data_colored_by' will soon be called for 'node_ids'
+    /// and 'source', e.g. for the nodes that are about to become visible, so that remote data can
+    /// be fetched ahead.
+    pub fn prefetch_node_data(&self, node_ids: &[NodeId], source: ColorSource) {
+        let mut attributes = match source {
+            ColorSource::Color if self.meta.attribute_data_types().contains_key("color") => {
+                vec!["color"]
+            }
+            _ => vec!["intensity"],
+        };
+        if let Some(label_palette) = &self.label_palette {
+            attributes.push(&label_palette.attribute);
+        }
+        let node_names: Vec<String> = node_ids.iter().map(NodeId::to_string).collect();
+        self.data_provider
+            .prefetch(&node_names, &self.node_files(&attributes));
+    }
+
+    /// Requests the position and 'attributes' of a node. If the node has interleaved attributes,
+    /// this is a single request for its interleaved file and the attributes that are not in it,
+    /// and the result has the other interleaved attributes as well.
+    fn node_readers(
+        &self,
+        node_id: &NodeId,
+        attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let readers = self
+            .data_provider
+            .data(&node_id.to_string(), &self.node_files(attributes))?;
+        self.deinterleave_readers(node_id, readers)
+    }
+
+    /// Replaces the interleaved file among the 'readers' of a node, if it has one, by the
+    /// position and attributes in it.
+    fn deinterleave_readers(
+        &self,
+        node_id: &NodeId,
+        mut readers: HashMap<String, Box<dyn Read + Send>>,
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let interleaved_attributes = self.meta.interleaved_attributes();
+        if interleaved_attributes.is_empty() {
+            return Ok(readers);
+        }
+        let err = "Could not read interleaved data";
+        let mut data = Vec::new();
+        BufReader::new(readers.remove(INTERLEAVED).ok_or(err)?)
+            .read_to_end(&mut data)
+            .chain_err(|| err)?;
+        let bytes_per_point = self
+            .meta
+            .interleaved_bytes_per_point(&self.nodes[node_id].position_encoding)?;
+        let names =
+            std::iter::once("position").chain(interleaved_attributes.iter().map(String::as_str));
+        for (name, column) in names.zip(deinterleave(&data, &bytes_per_point)?) {
+            readers.insert(name.to_string(), Box::new(Cursor::new(column)));
+        }
+        Ok(readers)
+    }
+
+    /// Removes the layout header in front of the raw data of 'attribute' of the node, if it has
+    /// one, and checks it against the meta. Attributes that the meta does not know the data type
+    /// of are not checked.
+    fn strip_layout_header(
+        &self,
+        node_id: &NodeId,
+        attribute: &str,
+        reader: Box<dyn Read + Send>,
+    ) -> Result<Box<dyn Read + Send>> {
+        let expected = if attribute == "position" {
+            Some(NodeLayout::for_position(&self.encoding_for_node(*node_id)))
+        } else {
+            self.meta
+                .attribute_data_types()
+                .get(attribute)
+                .map(|data_type| NodeLayout::Attribute(*data_type))
+        };
+        let (layout, reader) = read_layout_header(reader)?;
+        if let Some(expected) = expected {
+            check_layout(&layout, &expected)
+                .chain_err(|| format!("Could not read {} of node {}", attribute, node_id))?;
+        }
+        Ok(reader)
+    }
+
+    /// Reads the raw data of an attribute that not all octrees have, None if this one has not.
+    fn get_optional_data(&self, node_id: &NodeId, attribute: &str) -> Result<Option<Vec<u8>>> {
+        match self.data_provider.data(&node_id.to_string(), &[attribute]) {
+            Ok(mut readers) => {
+                let err = || format!("Could not read {}", attribute);
+                let mut all_data = Vec::new();
+                let reader = readers.remove(attribute).ok_or_else(err)?;
+                BufReader::new(self.strip_layout_header(node_id, attribute, reader)?)
+                    .read_to_end(&mut all_data)
+                    .chain_err(err)?;
+                Ok(Some(all_data))
+            }
+            Err(Error(ErrorKind::NodeNotFound, _)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The nodes in the subtree at 'start' that intersect 'location'. Counts the intersection
+    /// tests in 'num_tested'.
     fn nodes_in_location_impl<'a, T: HasAabbIntersector<'a>>(
         &self,
+        start: NodeId,
+        num_tested: &Cell<usize>,
         location: &'a T,
     ) -> Vec<NodeId> {
         // TODO(nnmm): Once intersection tests use Relation, this function can traverse the octree
@@ -315,23 +1217,212 @@ impl Octree {
         // it's a generalized version of get_visible_nodes(), and get_visible_nodes() can use this
         // function instead.
         let isec = location.aabb_intersector();
-        NodeIdsIterator::new(&self, |node_id, octree| {
+        NodeIdsIterator::starting_at(&self, start, |node_id, octree| {
+            num_tested.set(num_tested.get() + 1);
             let aabb = octree.nodes[&node_id].bounding_cube.to_aabb();
             isec.intersect_aabb(&aabb)
         })
         .collect()
     }
+
+    /// The deepest node whose cube contains all of 'corners', or None if the root does not.
+    fn deepest_node_containing(&self, corners: &[Point3<f64>]) -> Option<NodeId> {
+        let contains_all = |node_id: &NodeId| {
+            self.nodes.get(node_id).map_or(false, |node_meta| {
+                let aabb = node_meta.bounding_cube.to_aabb();
+                corners.iter().all(|corner| aabb.contains(corner))
+            })
+        };
+        let mut node_id = NodeId::root();
+        if !contains_all(&node_id) {
+            return None;
+        }
+        loop {
+            let child_index =
+                ChildIndex::from_bounding_cube(&self.nodes[&node_id].bounding_cube, &corners[0]);
+            let child_id = node_id.get_child_id(child_index);
+            if !contains_all(&child_id) {
+                return Some(node_id);
+            }
+            node_id = child_id;
+        }
+    }
+
+    /// Small queries only traverse the subtree of the smallest node containing them, after its
+    /// ancestors, which intersect the query as well. Queries containing the whole octree take
+    /// all nodes without tests.
+    fn plan_location(&self, location: &PointLocation) -> QueryPlan<NodeId> {
+        let num_nodes = self.nodes.len();
+        let bounding_box = &self.meta.bounding_box;
+        let covers_all = match location {
+            PointLocation::AllPoints => true,
+            PointLocation::Aabb(aabb) => {
+                aabb.contains(bounding_box.min()) && aabb.contains(bounding_box.max())
+            }
+            _ => false,
+        };
+        if covers_all {
+            return QueryPlan {
+                strategy: QueryStrategy::AllNodes,
+                node_ids: NodeIdsIterator::new(self, |_, _| true).collect(),
+                num_nodes,
+                num_tested_nodes: 0,
+                approximated_regions: Vec::new(),
+            };
+        }
+
+        let corners = match location {
+            PointLocation::Aabb(aabb) => Some(aabb.compute_corners()),
+            PointLocation::Obb(obb) => Some(obb.compute_corners()),
+            PointLocation::Frustum(frustum) => Some(frustum.compute_corners()),
+            PointLocation::WebMercatorRect(wmr) => Some(wmr.compute_corners()),
+            PointLocation::AllPoints | PointLocation::S2Cells(_) => None,
+        };
+        if let Some(corners) = &corners {
+            // Small queries in empty regions are answered without looking at the nodes.
+            let mut aabb = Aabb::new(corners[0], corners[0]);
+            for corner in &corners[1..] {
+                aabb.grow(*corner);
+            }
+            if !self
+                .occupancy
+                .may_contain_points(&Cube::bounding(bounding_box), &aabb)
+            {
+                return QueryPlan {
+                    strategy: QueryStrategy::Covering,
+                    node_ids: Vec::new(),
+                    num_nodes,
+                    num_tested_nodes: 0,
+                    approximated_regions: Vec::new(),
+                };
+            }
+        }
+        let start = corners
+            .and_then(|corners| self.deepest_node_containing(&corners))
+            .unwrap_or_else(NodeId::root);
+        let num_tested = Cell::new(0);
+        let mut node_ids: Vec<NodeId> = start.ancestors().collect();
+        node_ids.reverse();
+        node_ids.extend(dispatch_point_location!(
+            Octree::nodes_in_location_impl,
+            location,
+            &self,
+            start,
+            &num_tested
+        ));
+        QueryPlan {
+            strategy: if start.level() == 0 {
+                QueryStrategy::Traversal
+            } else {
+                QueryStrategy::Covering
+            },
+            node_ids,
+            num_nodes,
+            num_tested_nodes: num_tested.get(),
+            approximated_regions: Vec::new(),
+        }
+    }
+}
+
+impl Octree {
+    /// Leaves out the nodes of 'plan' below the coarsest level whose nodes are small enough:
+    /// all points of the descendants of a node are inside its cube, so they are at most its
+    /// diagonal away from any of its points, which is what 'max_error_m' bounds.
+    fn approximate(&self, plan: &mut QueryPlan<NodeId>, max_error_m: f64) {
+        let root_diagonal = Cube::bounding(&self.meta.bounding_box).edge_length() * 3f64.sqrt();
+        let diagonal = |level: u8| root_diagonal / 2f64.powi(i32::from(level));
+        let mut level = 0;
+        while level < node_id::MAX_LEVEL && diagonal(level) > max_error_m {
+            level += 1;
+        }
+        plan.node_ids.retain(|id| id.level() <= level);
+        plan.approximated_regions = plan
+            .node_ids
+            .iter()
+            .filter(|id| {
+                id.level() == level && id.children().any(|child| self.nodes.contains_key(&child))
+            })
+            .map(|id| ApproximatedRegion {
+                node_id: *id,
+                level,
+                max_error_m: diagonal(level),
+            })
+            .collect();
+    }
 }
 
 impl PointCloud for Octree {
     type Id = NodeId;
 
     fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id> {
-        dispatch_point_location!(Octree::nodes_in_location_impl, location, &self)
+        self.plan_location(location).node_ids
+    }
+
+    fn plan_query(&self, query: &PointQuery) -> QueryPlan<Self::Id> {
+        let mut plan = self.plan_location(&query.location);
+        if !query.filter_intervals.is_empty() {
+            // The node statistics are kept under the names of the stored attributes.
+            let filter_intervals: HashMap<&str, ClosedInterval<f64>> = query
+                .filter_intervals
+                .iter()
+                .map(|(attribute, interval)| (self.attribute_aliases.resolve(attribute), *interval))
+                .collect();
+            plan.node_ids
+                .retain(|id| self.nodes[id].may_match(&filter_intervals));
+        }
+        if let Some(max_error_m) = query.max_error_m {
+            self.approximate(&mut plan, max_error_m);
+        }
+        plan
+    }
+
+    fn radiometric_correction(&self) -> Option<&RadiometricCorrection> {
+        self.radiometric_correction.as_ref()
+    }
+
+    fn attribute_aliases(&self) -> Option<&AttributeAliases> {
+        Some(&self.attribute_aliases)
+    }
+
+    fn stored_attribute_type(&self, attribute: &str) -> Result<Option<AttributeDataType>> {
+        let attribute = self.attribute_aliases.resolve(attribute);
+        let data_type = match self.meta.attribute_data_types().get(attribute) {
+            Some(data_type) => *data_type,
+            None => return Ok(None),
+        };
+        let root = NodeId::root();
+        if self.meta.attributes_listed
+            || !self.nodes.contains_key(&root)
+            || self
+                .meta
+                .interleaved_attributes()
+                .iter()
+                .any(|a| a == attribute)
+        {
+            return Ok(Some(data_type));
+        }
+        // Octrees built without one of the implied standard attributes have no files for it in
+        // any node. Opening the file of the root tells, without reading it.
+        match self.data_provider.data(&root.to_string(), &[attribute]) {
+            Ok(_) => Ok(Some(data_type)),
+            Err(Error(ErrorKind::NodeNotFound, _)) => Ok(None),
+            Err(err) => Err(err),
+        }
     }
 
     fn encoding_for_node(&self, id: Self::Id) -> Encoding {
-        self.meta.encoding_for_node(id)
+        // The encoding stored with the node, which is not necessarily the one implied by the
+        // resolution, e.g. when served by a ReencodingDataProvider.
+        let node_meta = &self.nodes[&id];
+        Encoding::ScaledToCube(
+            node_meta.bounding_cube.min(),
+            node_meta.bounding_cube.edge_length(),
+            node_meta.position_encoding.clone(),
+        )
+    }
+
+    fn num_points_in_node(&self, id: Self::Id) -> usize {
+        self.nodes[&id].num_points as usize
     }
 
     fn points_in_node(
@@ -340,17 +1431,30 @@ impl PointCloud for Octree {
         node_id: Self::Id,
         batch_size: usize,
     ) -> Result<NodeIterator> {
-        let node_iterator = NodeIterator::from_data_provider(
-            &*self.data_provider,
+        // Nodes that all points were moved out of have no files.
+        if self.nodes[&node_id].num_points == 0 {
+            return Ok(NodeIterator::default());
+        }
+        let node_iterator = NodeIterator::from_readers(
+            self.node_readers(&node_id, attributes)?,
             &self.meta.attribute_data_types_for(&attributes)?,
-            self.meta.encoding_for_node(node_id),
-            &node_id,
+            self.encoding_for_node(node_id),
             self.nodes[&node_id].num_points as usize,
             batch_size,
         )?;
         Ok(node_iterator)
     }
 
+    fn prefetch(&self, node_ids: &[Self::Id], attributes: &[&str]) {
+        let attributes: Vec<&str> = attributes
+            .iter()
+            .map(|attribute| self.attribute_aliases.resolve(attribute))
+            .collect();
+        let node_names: Vec<String> = node_ids.iter().map(NodeId::to_string).collect();
+        self.data_provider
+            .prefetch(&node_names, &self.node_files(&attributes));
+    }
+
     /// return the bounding box saved in meta
     fn bounding_box(&self) -> &Aabb {
         &self.meta.bounding_box
@@ -12,165 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::node_id::{ChildIndex, NodeId};
+use crate::errors::*;
 use crate::geometry::Cube;
+use crate::math::ClosedInterval;
 use crate::proto;
 use crate::read_write::PositionEncoding;
-use nalgebra::Point3;
-use std::num::ParseIntError;
-use std::str::FromStr;
-use std::{fmt, result};
-
-/// Represents a child of an octree Node.
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub struct ChildIndex(u8);
-
-impl ChildIndex {
-    pub fn from_u8(index: u8) -> Self {
-        assert!(index < 8);
-        ChildIndex(index)
-    }
-
-    /// Returns the ChildId of the child containing 'v'.
-    pub fn from_bounding_cube(bounding_cube: &Cube, v: &Point3<f64>) -> ChildIndex {
-        // This is a bit flawed: it is not guaranteed that 'child_bounding_box.contains(&v)' is true
-        // using this calculated index due to floating point precision.
-        let center = bounding_cube.center();
-        let gt_x = v.x > center.x;
-        let gt_y = v.y > center.y;
-        let gt_z = v.z > center.z;
-        ChildIndex((gt_x as u8) << 2 | (gt_y as u8) << 1 | gt_z as u8)
-    }
-
-    pub fn as_u8(self) -> u8 {
-        self.0
-    }
-}
-
-/// A unique identifier to a node. Currently this is implemented as 'r' being the root and r[0-7]
-/// being the children, r[0-7][0-7] being the grand children and so on. The actual representation
-/// might change though.
-// Top 8 bits of the value are level, the rest is the index.
-// The root has level = 0, its children 1 and so on. Multiple nodes can have the same index,
-// but none can have the same index and level.
-#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
-pub struct NodeId(u128);
-
-impl FromStr for NodeId {
-    type Err = ParseIntError;
-
-    /// Construct a NodeId. No checking is done if this is a valid Id.
-    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
-        let level = (name.len() - 1) as u8;
-        let index = if level > 0 {
-            u128::from_str_radix(&name[1..], 8)?
-        } else {
-            0
-        };
-        Ok(NodeId::from_level_index(level, index))
-    }
-}
-
-impl fmt::Display for NodeId {
-    fn fmt(&self, formatter: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
-        if self.level() == 0 {
-            "r".fmt(formatter)
-        } else {
-            write!(
-                formatter,
-                "r{index:0width$o}",
-                index = self.index(),
-                width = self.level() as usize
-            )
-        }
-    }
-}
-
-impl NodeId {
-    pub fn from_proto(proto: &proto::NodeId) -> Self {
-        let deprecated_level = proto.deprecated_level as u8;
-        let deprecated_index = proto.deprecated_index;
-        let high = proto.high;
-        let low = proto.low;
-        if deprecated_level != 0 || deprecated_index != 0 {
-            NodeId::from_level_index(deprecated_level, deprecated_index as u128)
-        } else {
-            NodeId((u128::from(high) << 64) | u128::from(low))
-        }
-    }
-
-    pub fn to_proto(&self) -> proto::NodeId {
-        let mut proto = proto::NodeId::new();
-        proto.set_high((self.0 >> 64) as u64);
-        proto.set_low(self.0 as u64);
-        proto
-    }
-
-    pub fn from_level_index(level: u8, index: u128) -> Self {
-        let value = (u128::from(level) << 120) | index;
-        NodeId(value)
-    }
-
-    /// Returns the root node of the octree.
-    fn root() -> Self {
-        NodeId(0)
-    }
-
-    /// Returns the NodeId for the corresponding 'child_index'.
-    #[inline]
-    pub fn get_child_id(&self, child_index: ChildIndex) -> Self {
-        NodeId::from_level_index(
-            self.level() + 1,
-            (self.index() << 3) + u128::from(child_index.0),
-        )
-    }
-
-    /// The child index of this node in its parent.
-    fn child_index(&self) -> Option<ChildIndex> {
-        if self.level() == 0 {
-            return None;
-        }
-        Some(ChildIndex(self.index() as u8 & 7))
-    }
-
-    /// Returns the parents id or None if this is the root.
-    pub fn parent_id(&self) -> Option<NodeId> {
-        if self.level() == 0 {
-            return None;
-        }
-        Some(NodeId::from_level_index(
-            self.level() - 1,
-            self.index() >> 3,
-        ))
-    }
-
-    /// Returns the level of this node in the octree, with 0 being the root.
-    pub fn level(&self) -> u8 {
-        (self.0 >> 120) as u8
-    }
-
-    /// Returns the index of this node at the current level.
-    pub fn index(&self) -> u128 {
-        self.0 & 0x00ff_ffff_ffff_ffff_ffff_ffff_ffff_ffff
-    }
-
-    /// Computes the bounding cube from a NodeID.
-    pub fn find_bounding_cube(&self, root_bounding_cube: &Cube) -> Cube {
-        let mut edge_length = root_bounding_cube.edge_length();
-        let mut min = root_bounding_cube.min();
-        for level in (0..self.level()).rev() {
-            edge_length /= 2.;
-            // Reverse order: process from root to leaf nodes.
-            let child_index = (self.0 >> (3 * level)) & 7;
-            let z = child_index & 1;
-            let y = (child_index >> 1) & 1;
-            let x = (child_index >> 2) & 1;
-            min.x += x as f64 * edge_length;
-            min.y += y as f64 * edge_length;
-            min.z += z as f64 * edge_length;
-        }
-        Cube::new(min, edge_length)
-    }
-}
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug)]
 pub struct Node {
@@ -191,15 +39,15 @@ impl Node {
         let child_bounding_cube = {
             let half_edge_length = self.bounding_cube.edge_length() / 2.;
             let mut min = self.bounding_cube.min();
-            if (child_index.0 & 0b001) != 0 {
+            if (child_index.as_u8() & 0b001) != 0 {
                 min.z += half_edge_length;
             }
 
-            if (child_index.0 & 0b010) != 0 {
+            if (child_index.as_u8() & 0b010) != 0 {
                 min.y += half_edge_length;
             }
 
-            if (child_index.0 & 0b100) != 0 {
+            if (child_index.as_u8() & 0b100) != 0 {
                 min.x += half_edge_length;
             }
             Cube::new(min, half_edge_length)
@@ -216,7 +64,7 @@ impl Node {
         maybe_parent_id?;
 
         let parent_cube = {
-            let child_index = self.id.child_index().unwrap().0;
+            let child_index = self.id.child_index().unwrap().as_u8();
             let mut min = self.bounding_cube.min();
             let edge_length = self.bounding_cube.edge_length();
             if (child_index & 0b001) != 0 {
@@ -249,23 +97,94 @@ pub struct NodeMeta {
     pub num_points: i64,
     pub position_encoding: PositionEncoding,
     pub bounding_cube: Cube,
+    /// Number of points per label id, empty if the octree has no labels.
+    pub label_counts: BTreeMap<u16, i64>,
+    /// The attribute whose values 'label_counts' counts, None if there are no counts.
+    pub label_attribute: Option<String>,
+    /// Value ranges of the one-dimensional attributes, empty if they were not recorded.
+    pub attribute_ranges: BTreeMap<String, ClosedInterval<f64>>,
 }
 
 impl NodeMeta {
+    pub fn from_proto(proto: &proto::OctreeNode, bounding_cube: Cube) -> Result<Self> {
+        Ok(NodeMeta {
+            num_points: proto.num_points,
+            position_encoding: PositionEncoding::from_proto(proto.position_encoding)?,
+            bounding_cube,
+            label_counts: proto
+                .get_label_counts()
+                .iter()
+                .map(|label_count| (label_count.label as u16, label_count.num_points))
+                .collect(),
+            label_attribute: if proto.get_label_counts().is_empty() {
+                None
+            } else if proto.get_label_attribute().is_empty() {
+                Some("class".to_string())
+            } else {
+                Some(proto.get_label_attribute().to_string())
+            },
+            attribute_ranges: proto
+                .get_attribute_ranges()
+                .iter()
+                .map(|range| {
+                    (
+                        range.attribute.clone(),
+                        ClosedInterval::new(range.min, range.max),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Whether 'is_hidden' returns true for the labels of all points in this node. Always false
+    /// if the label counts are unknown.
+    pub fn all_labels_hidden(&self, is_hidden: impl Fn(u16) -> bool) -> bool {
+        !self.label_counts.is_empty() && self.label_counts.keys().all(|label| is_hidden(*label))
+    }
+
+    /// Whether this node might contain points whose attributes lie within 'filter_intervals'.
+    /// False means that the node can be skipped, attributes without recorded ranges never
+    /// rule out a node.
+    pub fn may_match(&self, filter_intervals: &HashMap<&str, ClosedInterval<f64>>) -> bool {
+        filter_intervals.iter().all(|(attribute, interval)| {
+            if self.label_attribute.as_deref() == Some(*attribute) {
+                return self
+                    .label_counts
+                    .keys()
+                    .any(|label| interval.contains(f64::from(*label)));
+            }
+            self.attribute_ranges
+                .get(*attribute)
+                .map_or(true, |range| range.intersects(*interval))
+        })
+    }
+
     pub fn num_points_for_level_of_detail(&self, level_of_detail: i32) -> i64 {
         (self.num_points as f32 / level_of_detail as f32).ceil() as i64
     }
 }
 
-pub fn to_node_proto(
-    node_id: &NodeId,
-    num_points: i64,
-    position_encoding: &PositionEncoding,
-) -> proto::OctreeNode {
+pub fn to_node_proto(node_id: &NodeId, node_meta: &NodeMeta) -> proto::OctreeNode {
     let mut proto = proto::OctreeNode::new();
     *proto.mut_id() = node_id.to_proto();
-    proto.set_num_points(num_points);
-    proto.set_position_encoding(position_encoding.to_proto());
+    proto.set_num_points(node_meta.num_points);
+    proto.set_position_encoding(node_meta.position_encoding.to_proto());
+    for (label, num_points) in &node_meta.label_counts {
+        let mut label_count = proto::LabelCount::new();
+        label_count.set_label(u32::from(*label));
+        label_count.set_num_points(*num_points);
+        proto.mut_label_counts().push(label_count);
+    }
+    if let Some(label_attribute) = &node_meta.label_attribute {
+        proto.set_label_attribute(label_attribute.clone());
+    }
+    for (attribute, range) in &node_meta.attribute_ranges {
+        let mut range_proto = proto::AttributeRange::new();
+        range_proto.set_attribute(attribute.clone());
+        range_proto.set_min(range.lower_bound());
+        range_proto.set_max(range.upper_bound());
+        proto.mut_attribute_ranges().push(range_proto);
+    }
     proto
 }
 
@@ -275,44 +194,35 @@ mod tests {
     use nalgebra::Point3;
 
     #[test]
-    fn test_parent_node_name() {
-        assert_eq!(
-            Some(NodeId::from_str("r12345").unwrap()),
-            NodeId::from_str("r123456").unwrap().parent_id()
-        );
-    }
-
-    #[test]
-    fn test_child_index() {
-        assert_eq!(
-            Some(ChildIndex(1)),
-            NodeId::from_str("r123451").unwrap().child_index()
-        );
-        assert_eq!(
-            Some(ChildIndex(7)),
-            NodeId::from_str("r123457").unwrap().child_index()
-        );
-        assert_eq!(None, NodeId::from_str("r").unwrap().child_index());
-    }
-
-    #[test]
-    fn test_bounding_box() {
-        let root_bounding_cube = Cube::new(Point3::new(-5., -5., -5.), 10.);
-
-        let bounding_cube = NodeId::from_str("r0")
-            .unwrap()
-            .find_bounding_cube(&root_bounding_cube);
-        assert_eq!(-5., bounding_cube.min().x);
-        assert_eq!(-5., bounding_cube.min().y);
-        assert_eq!(-5., bounding_cube.min().z);
-        assert_eq!(5., bounding_cube.edge_length());
-
-        let bounding_cube = NodeId::from_str("r13")
-            .unwrap()
-            .find_bounding_cube(&root_bounding_cube);
-        assert_eq!(-5., bounding_cube.min().x);
-        assert_eq!(-2.5, bounding_cube.min().y);
-        assert_eq!(2.5, bounding_cube.min().z);
-        assert_eq!(2.5, bounding_cube.edge_length());
+    fn test_may_match() {
+        let bounding_cube = Cube::new(Point3::new(0., 0., 0.), 1.);
+        let node_meta = NodeMeta {
+            num_points: 3,
+            position_encoding: PositionEncoding::new(&bounding_cube, 0.001),
+            bounding_cube,
+            label_counts: vec![(2, 1), (5, 2)].into_iter().collect(),
+            label_attribute: Some("class".to_string()),
+            attribute_ranges: vec![("intensity".to_string(), ClosedInterval::new(10., 20.))]
+                .into_iter()
+                .collect(),
+        };
+        let filter = |attribute, min, max| {
+            let mut filter_intervals = HashMap::new();
+            filter_intervals.insert(attribute, ClosedInterval::new(min, max));
+            filter_intervals
+        };
+        assert!(node_meta.may_match(&filter("intensity", 15., 30.)));
+        assert!(!node_meta.may_match(&filter("intensity", 20.5, 30.)));
+        assert!(node_meta.may_match(&filter("class", 4., 6.)));
+        assert!(!node_meta.may_match(&filter("class", 3., 4.)));
+        // The counts are of the recorded attribute only.
+        let mut node_meta = node_meta;
+        node_meta.label_attribute = Some("label".to_string());
+        node_meta.label_counts = vec![(2, 1), (300, 2)].into_iter().collect();
+        assert!(node_meta.may_match(&filter("label", 299., 301.)));
+        assert!(!node_meta.may_match(&filter("label", 3., 4.)));
+        assert!(node_meta.may_match(&filter("class", 3., 4.)));
+        // Unknown ranges never rule out a node.
+        assert!(node_meta.may_match(&filter("timestamp", 0., 1.)));
     }
 }
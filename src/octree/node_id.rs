@@ -0,0 +1,285 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ids of octree nodes and the cubes they cover, without needing an octree.
+//!
+//! The root node covers the bounding cube of the octree, i.e. the smallest cube around its
+//! bounding box whose min corner is the min corner of the bounding box. Every node is split into
+//! eight children with half its edge length. The child index of a child is
+//! `x << 2 | y << 1 | z`, where each of `x`, `y` and `z` is 1 if the child is in the upper half
+//! of its parent along that axis and 0 otherwise.
+//!
+//! The name of a node, which is also the stem of its files, is "r" followed by the child indices
+//! on the path from the root in octal, e.g. "r" is the root and "r13" the child 3 of the child 1
+//! of the root. The level of a node is the number of digits. In memory and in protos, the level is
+//! kept in the top 8 bits of a u128 and the digits read as an octal number in the remaining ones,
+//! which allows for up to 40 levels.
+
+use crate::errors::*;
+use crate::geometry::Cube;
+use crate::proto;
+use nalgebra::Point3;
+use std::str::FromStr;
+use std::{fmt, result};
+
+/// The deepest level a NodeId can represent.
+pub const MAX_LEVEL: u8 = 40;
+
+/// Represents a child of an octree Node.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ChildIndex(u8);
+
+impl ChildIndex {
+    pub fn from_u8(index: u8) -> Self {
+        assert!(index < 8);
+        ChildIndex(index)
+    }
+
+    /// Returns the ChildId of the child containing 'v'.
+    pub fn from_bounding_cube(bounding_cube: &Cube, v: &Point3<f64>) -> ChildIndex {
+        // This is a bit flawed: it is not guaranteed that 'child_bounding_box.contains(&v)' is true
+        // using this calculated index due to floating point precision.
+        let center = bounding_cube.center();
+        let gt_x = v.x > center.x;
+        let gt_y = v.y > center.y;
+        let gt_z = v.z > center.z;
+        ChildIndex((gt_x as u8) << 2 | (gt_y as u8) << 1 | gt_z as u8)
+    }
+
+    pub fn as_u8(self) -> u8 {
+        self.0
+    }
+}
+
+/// A unique identifier to a node, see the module documentation for the scheme.
+// Top 8 bits of the value are level, the rest is the index.
+// The root has level = 0, its children 1 and so on. Multiple nodes can have the same index,
+// but none can have the same index and level.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(u128);
+
+impl FromStr for NodeId {
+    type Err = Error;
+
+    /// Parses the name of a node, e.g. "r1027".
+    fn from_str(name: &str) -> Result<Self> {
+        let invalid = || ErrorKind::InvalidInput(format!("Invalid node id '{}'.", name));
+        let digits = name.strip_prefix('r').ok_or_else(invalid)?;
+        if digits.len() > usize::from(MAX_LEVEL)
+            || !digits.chars().all(|c| ('0'..='7').contains(&c))
+        {
+            return Err(invalid().into());
+        }
+        let index = if digits.is_empty() {
+            0
+        } else {
+            u128::from_str_radix(digits, 8).map_err(|_| invalid())?
+        };
+        Ok(NodeId::from_level_index(digits.len() as u8, index))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        if self.level() == 0 {
+            "r".fmt(formatter)
+        } else {
+            write!(
+                formatter,
+                "r{index:0width$o}",
+                index = self.index(),
+                width = self.level() as usize
+            )
+        }
+    }
+}
+
+impl NodeId {
+    pub fn from_proto(proto: &proto::NodeId) -> Self {
+        let deprecated_level = proto.deprecated_level as u8;
+        let deprecated_index = proto.deprecated_index;
+        let high = proto.high;
+        let low = proto.low;
+        if deprecated_level != 0 || deprecated_index != 0 {
+            NodeId::from_level_index(deprecated_level, deprecated_index as u128)
+        } else {
+            NodeId((u128::from(high) << 64) | u128::from(low))
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::NodeId {
+        let mut proto = proto::NodeId::new();
+        proto.set_high((self.0 >> 64) as u64);
+        proto.set_low(self.0 as u64);
+        proto
+    }
+
+    pub fn from_level_index(level: u8, index: u128) -> Self {
+        let value = (u128::from(level) << 120) | index;
+        NodeId(value)
+    }
+
+    /// Returns the root node of the octree.
+    pub fn root() -> Self {
+        NodeId(0)
+    }
+
+    /// Returns the NodeId for the corresponding 'child_index'.
+    #[inline]
+    pub fn get_child_id(&self, child_index: ChildIndex) -> Self {
+        NodeId::from_level_index(
+            self.level() + 1,
+            (self.index() << 3) + u128::from(child_index.0),
+        )
+    }
+
+    /// The ids of the eight children, in the order of their child indices.
+    pub fn children(&self) -> impl Iterator<Item = NodeId> {
+        let node_id = *self;
+        (0..8).map(move |index| node_id.get_child_id(ChildIndex(index)))
+    }
+
+    /// The child index of this node in its parent.
+    pub fn child_index(&self) -> Option<ChildIndex> {
+        if self.level() == 0 {
+            return None;
+        }
+        Some(ChildIndex(self.index() as u8 & 7))
+    }
+
+    /// Returns the parents id or None if this is the root.
+    pub fn parent_id(&self) -> Option<NodeId> {
+        if self.level() == 0 {
+            return None;
+        }
+        Some(NodeId::from_level_index(
+            self.level() - 1,
+            self.index() >> 3,
+        ))
+    }
+
+    /// The parent, its parent and so on up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = NodeId> {
+        std::iter::successors(self.parent_id(), NodeId::parent_id)
+    }
+
+    /// The ids of all descendants at 'level', which must not be above this node's level. There
+    /// are 8^(level - self.level()) of them, in the order of their indices.
+    pub fn descendants_at_level(&self, level: u8) -> impl Iterator<Item = NodeId> {
+        assert!(self.level() <= level && level <= MAX_LEVEL);
+        let shift = 3 * u32::from(level - self.level());
+        let first = self.index() << shift;
+        (first..first + (1 << shift)).map(move |index| NodeId::from_level_index(level, index))
+    }
+
+    /// Returns the level of this node in the octree, with 0 being the root.
+    pub fn level(&self) -> u8 {
+        (self.0 >> 120) as u8
+    }
+
+    /// Returns the index of this node at the current level.
+    pub fn index(&self) -> u128 {
+        self.0 & 0x00ff_ffff_ffff_ffff_ffff_ffff_ffff_ffff
+    }
+
+    /// Computes the bounding cube from a NodeID.
+    pub fn find_bounding_cube(&self, root_bounding_cube: &Cube) -> Cube {
+        let mut edge_length = root_bounding_cube.edge_length();
+        let mut min = root_bounding_cube.min();
+        for level in (0..self.level()).rev() {
+            edge_length /= 2.;
+            // Reverse order: process from root to leaf nodes.
+            let child_index = (self.0 >> (3 * level)) & 7;
+            let z = child_index & 1;
+            let y = (child_index >> 1) & 1;
+            let x = (child_index >> 2) & 1;
+            min.x += x as f64 * edge_length;
+            min.y += y as f64 * edge_length;
+            min.z += z as f64 * edge_length;
+        }
+        Cube::new(min, edge_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_node_name() {
+        assert_eq!(
+            Some(NodeId::from_str("r12345").unwrap()),
+            NodeId::from_str("r123456").unwrap().parent_id()
+        );
+    }
+
+    #[test]
+    fn test_child_index() {
+        assert_eq!(
+            Some(ChildIndex(1)),
+            NodeId::from_str("r123451").unwrap().child_index()
+        );
+        assert_eq!(
+            Some(ChildIndex(7)),
+            NodeId::from_str("r123457").unwrap().child_index()
+        );
+        assert_eq!(None, NodeId::from_str("r").unwrap().child_index());
+    }
+
+    #[test]
+    fn test_parse_and_iterate() {
+        let too_deep = format!("r{}", "1".repeat(41));
+        for name in &["", "x1", "r8", "r1-", too_deep.as_str()] {
+            assert!(NodeId::from_str(name).is_err());
+        }
+        let node_id = NodeId::from_str("r0070").unwrap();
+        assert_eq!(node_id.to_string(), "r0070");
+        let names = |ids: Vec<NodeId>| ids.iter().map(NodeId::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            names(node_id.ancestors().collect()),
+            vec!["r007", "r00", "r0", "r"]
+        );
+        assert_eq!(
+            names(node_id.children().skip(6).collect()),
+            vec!["r00706", "r00707"]
+        );
+        let descendants: Vec<NodeId> = NodeId::root().descendants_at_level(2).collect();
+        assert_eq!(descendants.len(), 64);
+        assert_eq!(names(descendants[9..11].to_vec()), vec!["r11", "r12"]);
+        assert!(descendants
+            .iter()
+            .all(|descendant| descendant.ancestors().last() == Some(NodeId::root())));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let root_bounding_cube = Cube::new(Point3::new(-5., -5., -5.), 10.);
+
+        let bounding_cube = NodeId::from_str("r0")
+            .unwrap()
+            .find_bounding_cube(&root_bounding_cube);
+        assert_eq!(-5., bounding_cube.min().x);
+        assert_eq!(-5., bounding_cube.min().y);
+        assert_eq!(-5., bounding_cube.min().z);
+        assert_eq!(5., bounding_cube.edge_length());
+
+        let bounding_cube = NodeId::from_str("r13")
+            .unwrap()
+            .find_bounding_cube(&root_bounding_cube);
+        assert_eq!(-5., bounding_cube.min().x);
+        assert_eq!(-2.5, bounding_cube.min().y);
+        assert_eq!(2.5, bounding_cube.min().z);
+        assert_eq!(2.5, bounding_cube.edge_length());
+    }
+}
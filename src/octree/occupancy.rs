@@ -0,0 +1,195 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which nodes exist on the coarse levels of an octree, as one bitmask per level over the node
+//! indices. Since every point is inside the cube of its node and of all its ancestors, a cell
+//! whose bit is unset has no points, which answers "is there data near here" without looking at
+//! the nodes.
+
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::octree::NodeId;
+use crate::proto;
+
+/// The deepest level that is stored. Its bitmask has 8^6 bits, i.e. 32 KiB before run-length
+/// encoding, and cells of 1/64 of the edge length of the root.
+pub const MAX_OCCUPANCY_LEVEL: u8 = 6;
+
+/// Queries are answered at the deepest level at which they touch at most this many cells.
+const MAX_CELLS_PER_QUERY: u64 = 4096;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Occupancy {
+    /// The bitmask of level 'i' in 64 bit words, for the levels up to the deepest stored one.
+    levels: Vec<Vec<u64>>,
+}
+
+fn num_bits(level: u8) -> u64 {
+    1 << (3 * u32::from(level))
+}
+
+fn is_set(bits: &[u64], index: u64) -> bool {
+    bits[(index / 64) as usize] & (1 << (index % 64)) != 0
+}
+
+/// The index of the node at 'level' whose cell in the grid of that level is ('x', 'y', 'z'), see
+/// the module documentation of 'node_id'.
+fn node_index(level: u8, x: u64, y: u64, z: u64) -> u64 {
+    (0..level).rev().fold(0, |index, bit| {
+        index << 3 | (x >> bit & 1) << 2 | (y >> bit & 1) << 1 | (z >> bit & 1)
+    })
+}
+
+impl Occupancy {
+    /// The occupancy of an octree with the given nodes, which must include all ancestors of each
+    /// node.
+    pub fn from_node_ids<'a>(node_ids: impl IntoIterator<Item = &'a NodeId>) -> Self {
+        let mut occupancy = Occupancy::default();
+        for node_id in node_ids {
+            let level = node_id.level().min(MAX_OCCUPANCY_LEVEL);
+            while occupancy.levels.len() <= usize::from(level) {
+                let words = (num_bits(occupancy.levels.len() as u8) + 63) / 64;
+                occupancy.levels.push(vec![0; words as usize]);
+            }
+            // Nodes below the deepest stored level mark their ancestor on it.
+            let index = (node_id.index() >> (3 * u32::from(node_id.level() - level))) as u64;
+            occupancy.levels[usize::from(level)][(index / 64) as usize] |= 1 << (index % 64);
+        }
+        occupancy
+    }
+
+    pub fn from_proto(levels: &[proto::OccupancyLevel]) -> Result<Self> {
+        let mut occupancy = Occupancy::default();
+        for (expected_level, level_proto) in levels.iter().enumerate() {
+            if level_proto.level as usize != expected_level
+                || expected_level > usize::from(MAX_OCCUPANCY_LEVEL)
+            {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Unexpected occupancy level {}.",
+                    level_proto.level
+                ))
+                .into());
+            }
+            let num_bits = num_bits(expected_level as u8);
+            let mut bits = vec![0u64; ((num_bits + 63) / 64) as usize];
+            let mut index = 0u64;
+            for (i, run) in level_proto.get_runs().iter().enumerate() {
+                let end = index.saturating_add(*run);
+                if end > num_bits {
+                    break;
+                }
+                if i % 2 == 1 {
+                    for set in index..end {
+                        bits[(set / 64) as usize] |= 1 << (set % 64);
+                    }
+                }
+                index = end;
+            }
+            if index != num_bits {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The occupancy of level {} does not have {} bits.",
+                    expected_level, num_bits
+                ))
+                .into());
+            }
+            occupancy.levels.push(bits);
+        }
+        Ok(occupancy)
+    }
+
+    pub fn to_proto(&self) -> Vec<proto::OccupancyLevel> {
+        self.levels
+            .iter()
+            .enumerate()
+            .map(|(level, bits)| {
+                let mut runs = Vec::new();
+                let mut run = 0;
+                let mut current = false;
+                for index in 0..num_bits(level as u8) {
+                    if is_set(bits, index) != current {
+                        runs.push(run);
+                        run = 0;
+                        current = !current;
+                    }
+                    run += 1;
+                }
+                runs.push(run);
+                let mut level_proto = proto::OccupancyLevel::new();
+                level_proto.set_level(level as u32);
+                level_proto.set_runs(runs);
+                level_proto
+            })
+            .collect()
+    }
+
+    /// The deepest level that is stored, None for empty octrees.
+    pub fn max_level(&self) -> Option<u8> {
+        self.levels.len().checked_sub(1).map(|level| level as u8)
+    }
+
+    /// Whether the node exists. None if its level is not stored.
+    pub fn is_occupied(&self, node_id: &NodeId) -> Option<bool> {
+        let bits = self.levels.get(usize::from(node_id.level()))?;
+        Some(is_set(bits, node_id.index() as u64))
+    }
+
+    /// The nodes on 'level' that exist, e.g. to show the coverage of the octree.
+    pub fn occupied_nodes(&self, level: u8) -> Vec<NodeId> {
+        match self.levels.get(usize::from(level)) {
+            Some(bits) => (0..num_bits(level))
+                .filter(|index| is_set(bits, *index))
+                .map(|index| NodeId::from_level_index(level, u128::from(index)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// False if there are certainly no points in 'aabb', for an octree with the given bounding
+    /// cube. True does not mean that there are points, only that a cell around them is occupied.
+    pub fn may_contain_points(&self, bounding_cube: &Cube, aabb: &Aabb) -> bool {
+        let max_level = match self.max_level() {
+            Some(max_level) => max_level,
+            None => return false,
+        };
+        let (min, max) = (bounding_cube.min(), bounding_cube.max());
+        if (0..3).any(|axis| aabb.max()[axis] < min[axis] || aabb.min()[axis] > max[axis]) {
+            return false;
+        }
+        let mut level = max_level;
+        loop {
+            let num_cells = 1u64 << level;
+            let cell_size = bounding_cube.edge_length() / num_cells as f64;
+            let cell = |value: f64, axis: usize| {
+                (((value - min[axis]) / cell_size).floor().max(0.) as u64).min(num_cells - 1)
+            };
+            let ranges: Vec<(u64, u64)> = (0..3)
+                .map(|axis| (cell(aabb.min()[axis], axis), cell(aabb.max()[axis], axis)))
+                .collect();
+            let touched: u64 = ranges
+                .iter()
+                .map(|(first, last)| last - first + 1)
+                .product();
+            if touched > MAX_CELLS_PER_QUERY && level > 0 {
+                level -= 1;
+                continue;
+            }
+            let bits = &self.levels[usize::from(level)];
+            return (ranges[0].0..=ranges[0].1).any(|x| {
+                (ranges[1].0..=ranges[1].1).any(|y| {
+                    (ranges[2].0..=ranges[2].1).any(|z| is_set(bits, node_index(level, x, y, z)))
+                })
+            });
+        }
+    }
+}
@@ -13,9 +13,18 @@ where
     F: Fn(&NodeId, &Octree) -> bool,
 {
     pub fn new(octree: &'a Octree, filter_func: F) -> NodeIdsIterator<'a, F> {
+        Self::starting_at(octree, NodeId::from_level_index(0, 0), filter_func)
+    }
+
+    /// Only visits 'node_id' and its descendants.
+    pub fn starting_at(
+        octree: &'a Octree,
+        node_id: NodeId,
+        filter_func: F,
+    ) -> NodeIdsIterator<'a, F> {
         NodeIdsIterator {
             octree,
-            node_ids: vec![NodeId::from_level_index(0, 0)].into(),
+            node_ids: vec![node_id].into(),
             filter_func,
         }
     }
@@ -1,10 +1,25 @@
-use crate::data_provider::OnDiskDataProvider;
+use crate::attributes::{write_attribute_aliases, AttributeAliases};
+use crate::color::Color;
+use crate::data_provider::{BlockingDataProvider, OnDiskDataProvider};
 use crate::errors::Result;
+use crate::fingerprint::fingerprint;
 use crate::geometry::Aabb;
-use crate::iterator::{ParallelIterator, PointQuery};
-use crate::octree::{build_octree, Octree};
-use crate::{AttributeData, NumberOfPoints, PointsBatch};
+use crate::iterator::{ParallelIterator, PointCloud, PointLocation, PointQuery, QueryStrategy};
+use crate::labels::{write_label_palette, Label, LabelPalette};
+use crate::maintenance::{find_inconsistencies, set_interleaved_attributes};
+use crate::math::base::{HasAabbIntersector, IntersectAabb};
+use crate::math::ClosedInterval;
+use crate::octree::{
+    check_consistency, delete_points_in_location, find_inconsistent_point_counts,
+    find_unreachable_nodes, BuildReport, ColorSource, InputFile, NodeId, NodeIdsIterator,
+    Occupancy, Octree, OctreeBuilder,
+};
+use crate::read_write::{AsciiColumns, NonFinitePolicy, PositionEncoding};
+use crate::thumbnails::write_thumbnails;
+use crate::{AttributeData, AttributeDataType, NumberOfPoints, PointsBatch};
 use nalgebra::{Point3, Vector3};
+use std::collections::BTreeSet;
+use std::path::Path;
 use tempdir::TempDir;
 
 const NUM_POINTS: usize = 100_001;
@@ -32,15 +47,47 @@ fn build_test_octree() -> Octree {
 
     let tmp_dir = TempDir::new("octree").unwrap();
 
-    build_octree(
-        &tmp_dir,
-        1.0,
-        bounding_box,
-        vec![batch].into_iter(),
-        &["color"],
-    );
+    OctreeBuilder::new(1.0)
+        .build(&tmp_dir, bounding_box, vec![batch].into_iter())
+        .unwrap();
+    open_octree(&tmp_dir.into_path())
+}
+
+/// The positions of the small test octrees: 'num_points' points along the x axis, one per meter,
+/// starting at 'offset'.
+fn points_along_x(offset: f64, num_points: usize) -> Vec<Point3<f64>> {
+    (0..num_points)
+        .map(|i| Point3::new(offset + i as f64, (i % 10) as f64, (i % 7) as f64))
+        .collect()
+}
+
+/// Builds an octree of the points with the given attributes into 'directory', within the
+/// bounding box of the points.
+fn build_small_octree(
+    builder: OctreeBuilder,
+    directory: &Path,
+    position: Vec<Point3<f64>>,
+    attributes: Vec<(&str, AttributeData)>,
+) -> BuildReport {
+    let mut bounding_box = Aabb::new(position[0], position[0]);
+    for p in &position {
+        bounding_box.grow(*p);
+    }
+    let batch = PointsBatch {
+        position,
+        attributes: attributes
+            .into_iter()
+            .map(|(name, data)| (name.to_string(), data))
+            .collect(),
+    };
+    builder
+        .build(directory, bounding_box, vec![batch].into_iter())
+        .unwrap()
+}
+
+fn open_octree(directory: &Path) -> Octree {
     Octree::from_data_provider(Box::new(OnDiskDataProvider {
-        directory: tmp_dir.into_path(),
+        directory: directory.to_path_buf(),
     }))
     .unwrap()
 }
@@ -134,3 +181,688 @@ fn test_batch_iterator_more_points() {
         .expect("Iterator errored even though callback should not have errored.");
     assert_eq!(c.num_received_points, NUM_POINTS);
 }
+
+#[test]
+fn test_builder_limits_depth() {
+    let num_points = 1000;
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_max_points_per_node(10)
+            .with_max_depth(2)
+            .with_position_encoding(PositionEncoding::Float64),
+        tmp_dir.path(),
+        points_along_x(0., num_points),
+        vec![(
+            "color",
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        )],
+    );
+    let octree = open_octree(tmp_dir.path());
+    let node_ids = octree.node_ids_up_to_level(u8::max_value());
+    assert_eq!(2, node_ids.iter().map(|id| id.level()).max().unwrap());
+    let num_stored_points: i64 = node_ids
+        .iter()
+        .map(|id| {
+            let node_meta = octree.node_meta(id).unwrap();
+            assert_eq!(node_meta.position_encoding, PositionEncoding::Float64);
+            node_meta.num_points
+        })
+        .sum();
+    assert_eq!(num_points as i64, num_stored_points);
+}
+
+#[test]
+fn test_builder_suppresses_duplicates() {
+    // Every point is ingested twice, the second time slightly moved.
+    let num_points = 1000;
+    let position: Vec<Point3<f64>> = (0..2 * num_points)
+        .map(|i| {
+            let offset = if i < num_points { 0.3 } else { 0.301 };
+            let i = i % num_points;
+            Point3::new(i as f64 + offset, (i % 10) as f64, (i % 7) as f64)
+        })
+        .collect();
+    let batch = PointsBatch {
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); position.len()]),
+        )]
+        .into_iter()
+        .collect(),
+        position,
+    };
+    // The cells of the duplicate tolerance are aligned to the bounding cube.
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(1000., 9., 6.));
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let report = OctreeBuilder::new(0.01)
+        .with_max_points_per_node(10)
+        .with_position_encoding(PositionEncoding::Float64)
+        .with_duplicate_suppression(0.5)
+        .build(&tmp_dir, bounding_box, vec![batch].into_iter())
+        .unwrap();
+    assert_eq!(report.num_duplicates, num_points as i64);
+    assert_eq!(report.num_points, num_points as i64);
+    let octree = open_octree(tmp_dir.path());
+    let num_stored_points: i64 = octree
+        .node_ids_up_to_level(u8::max_value())
+        .iter()
+        .map(|id| octree.node_meta(id).unwrap().num_points)
+        .sum();
+    assert_eq!(num_points as i64, num_stored_points);
+}
+
+#[test]
+fn test_builder_rejects_invalid_duplicate_tolerance() {
+    for tolerance in &[0., -0.5, f64::NAN, f64::INFINITY] {
+        let tmp_dir = TempDir::new("octree").unwrap();
+        let batch = PointsBatch {
+            position: points_along_x(0., 10),
+            attributes: Default::default(),
+        };
+        let bounding_box = Aabb::new(Point3::origin(), Point3::new(10., 1., 1.));
+        let result = OctreeBuilder::new(0.01)
+            .with_duplicate_suppression(*tolerance)
+            .build(&tmp_dir, bounding_box, vec![batch].into_iter());
+        assert!(result.is_err(), "Tolerance {} was accepted.", tolerance);
+    }
+}
+
+#[test]
+fn test_fingerprint_ignores_point_order() {
+    let positions = |moved: f64| -> Vec<Point3<f64>> {
+        (0..1000)
+            .map(|i| {
+                let x = if i == 500 { i as f64 + moved } else { i as f64 };
+                Point3::new(x + 0.3, (i % 10) as f64, (i % 7) as f64)
+            })
+            .collect()
+    };
+    let build = |mut position: Vec<Point3<f64>>, reverse: bool, max_points_per_node: usize| {
+        if reverse {
+            position.reverse();
+        }
+        let intensity = AttributeData::F32(position.iter().map(|p| p.y as f32).collect());
+        let tmp_dir = TempDir::new("octree").unwrap();
+        build_small_octree(
+            OctreeBuilder::new(0.01)
+                .with_attributes(&["intensity"])
+                .with_max_points_per_node(max_points_per_node)
+                .with_position_encoding(PositionEncoding::Float64),
+            tmp_dir.path(),
+            position,
+            vec![("intensity", intensity)],
+        );
+        fingerprint(&[open_octree(tmp_dir.path())], &["intensity"], 0.01).unwrap()
+    };
+    let original = build(positions(0.), false, 10);
+    assert_eq!(original.num_points, 1000);
+    assert_eq!(original, build(positions(0.), true, 100));
+    assert_ne!(original, build(positions(0.5), false, 10));
+}
+
+#[test]
+fn test_meta_stores_built_attributes() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01).with_attributes(&["intensity"]),
+        tmp_dir.path(),
+        points_along_x(0., 2),
+        vec![("intensity", AttributeData::F32(vec![1., 2.]))],
+    );
+    let octree = open_octree(tmp_dir.path());
+    assert_eq!(octree.attribute_names(), vec!["intensity"]);
+    assert_eq!(
+        octree.stored_attribute_type("intensity").unwrap(),
+        Some(AttributeDataType::F32)
+    );
+    assert_eq!(octree.stored_attribute_type("color").unwrap(), None);
+
+    // Octrees written before the attributes were stored imply the standard ones.
+    let data_provider = OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    };
+    data_provider
+        .update_meta_proto(|meta| meta.mut_octree().clear_attributes())
+        .unwrap();
+    let octree = Octree::from_data_provider(Box::new(data_provider)).unwrap();
+    assert_eq!(
+        octree.attribute_names(),
+        vec!["alpha", "class", "color", "intensity", "label", "timestamp"]
+    );
+    // Only the attributes with files are stored.
+    assert_eq!(octree.stored_attribute_names().unwrap(), vec!["intensity"]);
+    assert_eq!(
+        octree.stored_attribute_type("intensity").unwrap(),
+        Some(AttributeDataType::F32)
+    );
+    assert_eq!(octree.stored_attribute_type("color").unwrap(), None);
+}
+
+#[test]
+fn test_query_attribute_alias() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_attributes(&["intensity"])
+            .with_max_points_per_node(100),
+        tmp_dir.path(),
+        points_along_x(0., 1000),
+        vec![(
+            "intensity",
+            AttributeData::F32((0..1000).map(|i| i as f32).collect()),
+        )],
+    );
+    let aliases = AttributeAliases::new(vec![("intensities".to_string(), "intensity".to_string())]);
+    write_attribute_aliases(tmp_dir.path(), &aliases).unwrap();
+    let octree = open_octree(tmp_dir.path());
+
+    let query = PointQuery {
+        attributes: vec!["intensities"],
+        filter_intervals: vec![("intensities", ClosedInterval::new(0., 99.))]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let mut intensities = Vec::new();
+    ParallelIterator::new(std::slice::from_ref(&octree), &query, 100, 2, 4)
+        .try_for_each_batch(|batch| {
+            assert_eq!(
+                batch.attributes.keys().collect::<Vec<_>>(),
+                vec!["intensities"]
+            );
+            intensities.extend(
+                batch
+                    .get_attribute_vec::<f32>("intensities")?
+                    .iter()
+                    .copied(),
+            );
+            Ok(())
+        })
+        .unwrap();
+    intensities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(intensities, (0..100).map(|i| i as f32).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_thumbnails() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01).with_max_points_per_node(100),
+        tmp_dir.path(),
+        points_along_x(0., 1000),
+        vec![(
+            "color",
+            AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0); 1000]),
+        )],
+    );
+    let thumbnails = write_thumbnails(tmp_dir.path(), 64).unwrap();
+    let octree = open_octree(tmp_dir.path());
+    assert_eq!(octree.thumbnails(), &thumbnails[..]);
+    // The top view keeps the aspect ratio of the point cloud.
+    assert_eq!((thumbnails[0].width, thumbnails[0].height), (64, 1));
+    let image = image::load_from_memory(&octree.read_thumbnail("top").unwrap())
+        .unwrap()
+        .to_rgba();
+    assert_eq!(image.get_pixel(0, 0).0, [0, 255, 0, 255]);
+    assert!(octree.read_thumbnail("side").is_err());
+}
+
+#[test]
+fn test_consistency_check() {
+    let octree = build_test_octree();
+    assert!(find_inconsistent_point_counts(&octree.nodes).is_empty());
+    assert!(find_unreachable_nodes(octree.nodes.keys()).is_empty());
+
+    let root = NodeId::from_level_index(0, 0);
+    let mut nodes = octree.nodes.clone();
+    nodes.remove(&root);
+    assert_eq!(find_unreachable_nodes(nodes.keys()).len(), nodes.len());
+    assert!(check_consistency(&nodes, false).is_err());
+
+    let mut nodes = octree.nodes.clone();
+    nodes.get_mut(&root).unwrap().num_points *= 2;
+    assert_eq!(find_inconsistent_point_counts(&nodes), vec![root]);
+}
+
+#[test]
+fn test_query_plans() {
+    let octree = build_test_octree();
+    let plan = octree.plan_query(&PointQuery::default());
+    assert_eq!(plan.strategy, QueryStrategy::AllNodes);
+    assert_eq!(plan.node_ids.len(), octree.nodes.len());
+
+    // Within the child of the root that contains the points at the origin.
+    let aabb = Aabb::new(Point3::new(-1., -1., 0.5), Point3::new(-0.5, -0.5, 1.));
+    let query = PointQuery {
+        location: PointLocation::Aabb(aabb.clone()),
+        ..Default::default()
+    };
+    let plan = octree.plan_query(&query);
+    assert_eq!(plan.strategy, QueryStrategy::Covering);
+    assert!(plan.num_tested_nodes < octree.nodes.len());
+    let intersector = aabb.aabb_intersector();
+    let mut traversed: Vec<NodeId> = NodeIdsIterator::new(&octree, |node_id, octree| {
+        intersector.intersect_aabb(&octree.nodes[node_id].bounding_cube.to_aabb())
+    })
+    .collect();
+    let mut planned = plan.node_ids;
+    let key = |node_id: &NodeId| (node_id.level(), node_id.index());
+    traversed.sort_by_key(key);
+    planned.sort_by_key(key);
+    assert_eq!(planned, traversed);
+}
+
+#[test]
+fn test_approximate_query_plans() {
+    let octree = build_test_octree();
+    let coarse = PointQuery {
+        max_error_m: Some(std::f64::MAX),
+        ..Default::default()
+    };
+    let plan = octree.plan_query(&coarse);
+    assert_eq!(plan.node_ids, vec![NodeId::root()]);
+    assert_eq!(plan.approximated_regions.len(), 1);
+    assert_eq!(plan.approximated_regions[0].node_id, NodeId::root());
+    assert_eq!(plan.approximated_regions[0].level, 0);
+    let root_diagonal = octree.nodes[&NodeId::root()].bounding_cube.edge_length() * 3f64.sqrt();
+    assert_eq!(plan.approximated_regions[0].max_error_m, root_diagonal);
+
+    let exact = PointQuery {
+        max_error_m: Some(0.),
+        ..Default::default()
+    };
+    let plan = octree.plan_query(&exact);
+    assert_eq!(plan.node_ids.len(), octree.nodes.len());
+    assert!(plan.approximated_regions.is_empty());
+}
+
+#[test]
+fn test_occupancy() {
+    let octree = build_test_octree();
+    let meta = octree.to_meta_proto();
+    let occupancy = Occupancy::from_proto(meta.get_octree().get_occupancy()).unwrap();
+    assert_eq!(&occupancy, octree.occupancy());
+    assert_eq!(occupancy.is_occupied(&NodeId::root()), Some(true));
+    for node_id in occupancy.occupied_nodes(occupancy.max_level().unwrap()) {
+        assert!(octree.nodes.contains_key(&node_id));
+    }
+
+    assert!(octree.may_have_points_near(&Point3::new(0., 0., 0.), 0.1));
+    assert!(octree.may_have_points_near(&Point3::new(-200., -40., 30.), 0.1));
+    // The points are in two of the eight level 1 cells of the bounding cube, this is in another.
+    let empty = Point3::new(-50., 120., 150.);
+    assert!(!octree.may_have_points_near(&empty, 1.));
+    let query = PointQuery {
+        location: PointLocation::Aabb(Aabb::new(
+            empty - Vector3::repeat(1.),
+            empty + Vector3::repeat(1.),
+        )),
+        ..Default::default()
+    };
+    assert!(octree.plan_query(&query).node_ids.is_empty());
+}
+
+#[test]
+fn test_merge() {
+    let build = |offset: f64, tmp_dir: &TempDir| {
+        let position = points_along_x(offset, 1000);
+        let intensity = AttributeData::F32(position.iter().map(|p| p.x as f32).collect());
+        build_small_octree(
+            OctreeBuilder::new(0.01)
+                .with_attributes(&["intensity"])
+                .with_max_points_per_node(100),
+            tmp_dir.path(),
+            position,
+            vec![("intensity", intensity)],
+        );
+        open_octree(tmp_dir.path())
+    };
+    let tmp_dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new("octree").unwrap()).collect();
+    // The second octree overlaps the first one by half.
+    let octrees = vec![build(0., &tmp_dirs[0]), build(500., &tmp_dirs[1])];
+    let report = OctreeBuilder::new(0.01)
+        .with_attributes(&["intensity"])
+        .with_max_points_per_node(100)
+        .merge(&tmp_dirs[2], &octrees)
+        .unwrap();
+    assert_eq!(report.num_points, 2000);
+    let merged = open_octree(tmp_dirs[2].path());
+    assert_eq!(merged.bounding_box().max().x, 1499.);
+    assert_eq!(
+        fingerprint(&[merged], &["intensity"], 0.1).unwrap(),
+        fingerprint(&octrees, &["intensity"], 0.1).unwrap()
+    );
+
+    assert!(OctreeBuilder::new(0.1)
+        .with_attributes(&["intensity"])
+        .merge(&tmp_dirs[2], &octrees)
+        .is_err());
+    assert!(OctreeBuilder::new(0.01)
+        .with_attributes(&["color"])
+        .merge(&tmp_dirs[2], &octrees)
+        .is_err());
+    // The merged octree is only moved to the output directory if all points could be read.
+    std::fs::remove_file(tmp_dirs[1].path().join("r.intensity")).unwrap();
+    let output_directory = tmp_dirs[2].path().join("merged");
+    assert!(OctreeBuilder::new(0.01)
+        .with_attributes(&["intensity"])
+        .with_max_points_per_node(100)
+        .merge(&output_directory, &octrees)
+        .is_err());
+    assert!(!output_directory.exists());
+    assert!(!tmp_dirs[2].path().join("merged.merging").exists());
+}
+
+#[test]
+fn test_merge_copies_nodes_of_aligned_octrees() {
+    // Two octrees in neighbouring cells of a grid, whose bounding cubes are children of the
+    // bounding cube of the merged octree.
+    let build = |offset: f64, tmp_dir: &TempDir| {
+        let mut position = vec![
+            Point3::new(offset, 0., 0.),
+            Point3::new(offset + 8., 8., 8.),
+        ];
+        position.extend((0..998).map(|i| {
+            Point3::new(
+                offset + (i * 37 % 800) as f64 / 100.,
+                (i * 73 % 800) as f64 / 100.,
+                (i * 19 % 800) as f64 / 100.,
+            )
+        }));
+        let intensity = AttributeData::F32(position.iter().map(|p| p.x as f32).collect());
+        build_small_octree(
+            OctreeBuilder::new(0.01)
+                .with_attributes(&["intensity"])
+                .with_max_points_per_node(100),
+            tmp_dir.path(),
+            position,
+            vec![("intensity", intensity)],
+        );
+        open_octree(tmp_dir.path())
+    };
+    let tmp_dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new("octree").unwrap()).collect();
+    let octrees = vec![build(0., &tmp_dirs[0]), build(8., &tmp_dirs[1])];
+    let report = OctreeBuilder::new(0.01)
+        .with_attributes(&["intensity"])
+        .with_max_points_per_node(100)
+        .merge(&tmp_dirs[2], &octrees)
+        .unwrap();
+    assert_eq!(report.num_points, 2000);
+    let merged = open_octree(tmp_dirs[2].path());
+    assert_eq!(
+        fingerprint(&[merged], &["intensity"], 0.1).unwrap(),
+        fingerprint(&octrees, &["intensity"], 0.1).unwrap()
+    );
+    // Only the roots of the octrees are read, the nodes below them are copied as they are.
+    for (index, prefix) in [(0, "r0"), (1, "r4")].iter() {
+        for node_id in octrees[*index].nodes.keys().filter(|id| id.level() > 0) {
+            let name = node_id.to_string();
+            let merged_name = format!("{}{}", prefix, &name[1..]);
+            for extension in &["xyz", "intensity"] {
+                let read = |directory: &Path, name: &str| {
+                    std::fs::read(directory.join(name).with_extension(extension)).unwrap()
+                };
+                assert_eq!(
+                    read(tmp_dirs[*index].path(), &name),
+                    read(tmp_dirs[2].path(), &merged_name)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_delete_points_in_location() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_attributes(&["intensity"])
+            .with_max_points_per_node(100),
+        tmp_dir.path(),
+        points_along_x(0., 1000),
+        vec![(
+            "intensity",
+            AttributeData::F32((0..1000).map(|i| i as f32).collect()),
+        )],
+    );
+    let location = PointLocation::Aabb(Aabb::new(
+        Point3::new(99.5, -1., -1.),
+        Point3::new(199.5, 10., 10.),
+    ));
+    assert_eq!(
+        delete_points_in_location(tmp_dir.path(), &location).unwrap(),
+        100
+    );
+    assert_eq!(
+        delete_points_in_location(tmp_dir.path(), &location).unwrap(),
+        0
+    );
+
+    let octree = open_octree(tmp_dir.path());
+    // The parents keep the deleted points of their children.
+    assert!(octree.to_meta_proto().get_octree().points_deleted);
+    let num_points: i64 = octree.nodes.values().map(|node| node.num_points).sum();
+    assert_eq!(num_points, 900);
+    let query = PointQuery {
+        attributes: vec!["intensity"],
+        ..Default::default()
+    };
+    let mut intensities = Vec::new();
+    ParallelIterator::new(std::slice::from_ref(&octree), &query, 100, 2, 4)
+        .try_for_each_batch(|batch| {
+            intensities.extend(batch.get_attribute_vec::<f32>("intensity")?.iter().copied());
+            Ok(())
+        })
+        .unwrap();
+    intensities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let expected: Vec<f32> = (0..1000)
+        .filter(|i| *i < 100 || *i >= 200)
+        .map(|i| i as f32)
+        .collect();
+    assert_eq!(intensities, expected);
+
+    // Nodes that are left without points and children are removed with their files.
+    let location = PointLocation::Aabb(Aabb::new(
+        Point3::new(499.5, -1., -1.),
+        Point3::new(1000., 10., 10.),
+    ));
+    assert_eq!(
+        delete_points_in_location(tmp_dir.path(), &location).unwrap(),
+        500
+    );
+    let edited = open_octree(tmp_dir.path());
+    assert!(edited.nodes.len() < octree.nodes.len());
+    assert!(edited
+        .nodes
+        .iter()
+        .all(|(node_id, node)| node.num_points > 0
+            || node_id
+                .children()
+                .any(|child| edited.nodes.contains_key(&child))));
+    assert!(find_inconsistencies(tmp_dir.path()).unwrap().is_clean());
+}
+
+#[test]
+fn test_node_colors() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_attributes(&["color", "intensity"])
+            .with_max_points_per_node(100),
+        tmp_dir.path(),
+        points_along_x(0., 1000),
+        vec![
+            (
+                "color",
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); 1000]),
+            ),
+            (
+                "intensity",
+                AttributeData::F32((0..1000).map(|i| i as f32).collect()),
+            ),
+        ],
+    );
+    let octree = open_octree(tmp_dir.path());
+    let range = Some(ClosedInterval::new(0., 999.));
+    for node_id in octree.nodes.keys() {
+        let by_color = octree
+            .get_node_data_colored_by(node_id, ColorSource::Color)
+            .unwrap();
+        assert!(by_color.intensity.is_none());
+        let colors = octree
+            .get_node_colors(node_id, ColorSource::Color, range)
+            .unwrap();
+        assert_eq!(colors, by_color.colors(range).into_owned());
+
+        // Colored by intensity, the colors are not read at all.
+        let by_intensity = octree
+            .get_node_data_colored_by(node_id, ColorSource::Intensity)
+            .unwrap();
+        assert!(by_intensity.color.is_none());
+        let grays = octree
+            .get_node_colors(node_id, ColorSource::Intensity, range)
+            .unwrap();
+        assert_eq!(grays, by_intensity.colors(range).into_owned());
+        assert!(grays
+            .chunks_exact(3)
+            .all(|rgb| rgb[0] == rgb[1] && rgb[1] == rgb[2]));
+    }
+}
+
+#[test]
+fn test_u16_labels() {
+    // Points left of x = 500 have label 1000, the others 40000.
+    let labels = (0..1000)
+        .map(|i| if i < 500 { 1000 } else { 40000 })
+        .collect();
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_attributes(&["label"])
+            .with_node_statistics()
+            .with_max_points_per_node(100),
+        tmp_dir.path(),
+        points_along_x(0., 1000),
+        vec![("label", AttributeData::U16(labels))],
+    );
+    let mut palette = LabelPalette::new("label");
+    palette.labels.insert(
+        40000,
+        Label {
+            name: "vegetation".to_string(),
+            color: Color {
+                red: 0,
+                green: 255,
+                blue: 0,
+                alpha: 255,
+            },
+        },
+    );
+    write_label_palette(&tmp_dir, &palette).unwrap();
+    let octree = open_octree(tmp_dir.path());
+    assert_eq!(octree.label_palette(), Some(&palette));
+    let mut all_labels = BTreeSet::new();
+    for (node_id, node_meta) in &octree.nodes {
+        let node_data = octree.get_node_data(node_id).unwrap();
+        let labels = node_data.labels.unwrap();
+        assert_eq!(labels.len(), node_meta.num_points as usize);
+        assert!(labels
+            .iter()
+            .all(|label| node_meta.label_counts.contains_key(label)));
+        assert_eq!(node_meta.label_attribute.as_deref(), Some("label"));
+        all_labels.extend(labels);
+        assert!(octree.all_labels_hidden(node_id, |label| label >= 1000));
+        assert_eq!(
+            octree.all_labels_hidden(node_id, |label| label == 40000),
+            !node_meta.label_counts.contains_key(&1000)
+        );
+    }
+    assert_eq!(all_labels, vec![1000, 40000].into_iter().collect());
+
+    // Only U8 and U16 attributes hold label ids.
+    let tmp_dir = TempDir::new("octree").unwrap();
+    assert!(OctreeBuilder::new(0.01)
+        .with_attributes(&["intensity"])
+        .with_label_attribute("intensity")
+        .build(
+            &tmp_dir,
+            Aabb::new(Point3::origin(), Point3::new(1., 1., 1.)),
+            Vec::<PointsBatch>::new().into_iter(),
+        )
+        .is_err());
+}
+
+#[test]
+fn test_fetched_node_data_matches_read_node_data() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let num_points = 100;
+    build_small_octree(
+        OctreeBuilder::new(0.01)
+            .with_attributes(&["color", "intensity"])
+            .with_max_points_per_node(40),
+        tmp_dir.path(),
+        points_along_x(0., num_points),
+        vec![
+            (
+                "color",
+                AttributeData::U8Vec3(vec![Vector3::new(1, 2, 3); num_points]),
+            ),
+            ("intensity", AttributeData::F32(vec![7.; num_points])),
+        ],
+    );
+    let check = |octree: &Octree| {
+        let data_provider = BlockingDataProvider::new(octree.data_provider(), 2);
+        for node_id in octree.node_ids_up_to_level(u8::MAX) {
+            for source in &[ColorSource::Color, ColorSource::Intensity] {
+                let read = octree.get_node_data_colored_by(&node_id, *source).unwrap();
+                let fetched = futures::executor::block_on(octree.fetch_node_data_colored_by(
+                    &data_provider,
+                    &node_id,
+                    *source,
+                ))
+                .unwrap();
+                assert_eq!(read.position, fetched.position);
+                assert_eq!(read.color, fetched.color);
+                assert_eq!(read.intensity, fetched.intensity);
+                assert_eq!(read.alpha, fetched.alpha);
+                let colors = futures::executor::block_on(octree.fetch_node_colors(
+                    &data_provider,
+                    &node_id,
+                    *source,
+                    None,
+                ))
+                .unwrap();
+                assert_eq!(
+                    octree.get_node_colors(&node_id, *source, None).unwrap(),
+                    colors
+                );
+            }
+        }
+    };
+    check(&open_octree(tmp_dir.path()));
+    set_interleaved_attributes(tmp_dir.path(), &["color"]).unwrap();
+    check(&open_octree(tmp_dir.path()));
+}
+
+#[test]
+fn test_build_from_file_fails_before_the_meta_on_non_finite_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let input_path = tmp_dir.path().join("points.pts");
+    std::fs::write(
+        &input_path,
+        "0 0 0 1 0 0 0\n1 1 1 1 0 0 0\nnan 0 0 1 0 0 0\n2 2 2 1 0 0 0\n",
+    )
+    .unwrap();
+    let input = InputFile::from_path(&input_path, AsciiColumns::default()).unwrap();
+    let output_directory = tmp_dir.path().join("octree");
+    let builder = OctreeBuilder::new(0.01).with_non_finite_policy(NonFinitePolicy::Error);
+    assert!(builder.build_from_file(&output_directory, &input).is_err());
+    assert!(!output_directory.join(crate::META_FILENAME).exists());
+
+    let builder = OctreeBuilder::new(0.01).with_non_finite_policy(NonFinitePolicy::Drop);
+    let report = builder
+        .build_from_file(tmp_dir.path().join("dropped"), &input)
+        .unwrap();
+    assert_eq!(report.num_points, 3);
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upgrading the meta of an octree from older versions. The node data did not change between
+//! the versions, only the meta.
+
+use crate::errors::*;
+use crate::octree::NodeId;
+use crate::proto;
+use crate::CURRENT_VERSION;
+
+fn upgrade_version9(mut meta: proto::Meta) -> proto::Meta {
+    for node_proto in &mut meta.deprecated_nodes.iter_mut() {
+        let id = node_proto.id.as_mut().unwrap();
+        *id = NodeId::from_proto(id).to_proto();
+    }
+    meta
+}
+
+fn upgrade_version10(mut meta: proto::Meta) -> proto::Meta {
+    let bbox = meta.bounding_box.as_mut().unwrap();
+    let deprecated_min = bbox.take_deprecated_min();
+    bbox.set_min(proto::Vector3d::from(deprecated_min));
+    let deprecated_max = bbox.take_deprecated_max();
+    bbox.set_max(proto::Vector3d::from(deprecated_max));
+    meta
+}
+
+fn upgrade_version11(mut meta: proto::Meta) -> proto::Meta {
+    let mut octree = proto::OctreeMeta::new();
+
+    octree.set_resolution(meta.deprecated_resolution);
+    meta.deprecated_resolution = 0.0;
+
+    octree.set_nodes(meta.take_deprecated_nodes());
+    // Version 12 kept the bounding box in the octree meta.
+    octree.set_deprecated_bounding_box(meta.take_bounding_box());
+
+    meta.set_octree(octree);
+    meta
+}
+
+fn upgrade_version12(mut meta: proto::Meta) -> proto::Meta {
+    if meta.has_octree() {
+        let bounding_box = meta.mut_octree().take_deprecated_bounding_box();
+        meta.set_bounding_box(bounding_box);
+    }
+    meta
+}
+
+/// Upgrades the meta by one version. Metas at the current version are an error.
+pub fn upgrade_meta_proto(meta: proto::Meta) -> Result<proto::Meta> {
+    let version = meta.version;
+    let mut upgraded = match version {
+        9 => upgrade_version9(meta),
+        10 => upgrade_version10(meta),
+        11 => upgrade_version11(meta),
+        12 => upgrade_version12(meta),
+        _ => return Err(ErrorKind::InvalidVersion(version).into()),
+    };
+    upgraded.version = version + 1;
+    Ok(upgraded)
+}
+
+/// Upgrades the meta to the current version.
+pub fn upgrade_meta_proto_to_current(mut meta: proto::Meta) -> Result<proto::Meta> {
+    while meta.version != CURRENT_VERSION {
+        meta = upgrade_meta_proto(meta)?;
+    }
+    Ok(meta)
+}
@@ -0,0 +1,346 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-section profiles: the points in a thin vertical slab along a line, seen from the side,
+//! with the distance along the line as x and the height as y. They can be written as SVG or PNG.
+
+use crate::color::Color;
+use crate::errors::*;
+use crate::geometry::Obb;
+use crate::iterator::{ParallelIterator, PointCloud, PointLocation, PointQuery};
+use crate::{match_1d_attr_data, AttributeData, NUM_POINTS_PER_BATCH};
+use image::{Rgb, RgbImage};
+use nalgebra::{Isometry3, Point2, Point3, Translation3, UnitQuaternion, Vector3};
+use num::ToPrimitive;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Space around the plot for the axis labels.
+const MARGIN_PX: u32 = 48;
+
+const GRAY: Color<u8> = Color {
+    red: 128,
+    green: 128,
+    blue: 128,
+    alpha: 255,
+};
+
+/// A vertical slab of 'width_m' around the line from 'start' to 'end' in the x-y plane, between
+/// 'min_z' and 'max_z'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossSection {
+    pub start: Point2<f64>,
+    pub end: Point2<f64>,
+    pub width_m: f64,
+    pub min_z: f64,
+    pub max_z: f64,
+}
+
+impl CrossSection {
+    pub fn length(&self) -> f64 {
+        (self.end - self.start).norm()
+    }
+
+    /// The slab, to query the points in it.
+    pub fn obb(&self) -> Obb {
+        let direction = self.end - self.start;
+        let center = nalgebra::center(&self.start, &self.end);
+        let rotation =
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), direction.y.atan2(direction.x));
+        let translation = Translation3::new(center.x, center.y, (self.min_z + self.max_z) / 2.);
+        let half_extent = Vector3::new(
+            self.length() / 2.,
+            self.width_m / 2.,
+            (self.max_z - self.min_z) / 2.,
+        );
+        Obb::new(Isometry3::from_parts(translation, rotation), half_extent)
+    }
+
+    /// The distance along the line and the height of 'point', or None if it is outside of the
+    /// slab.
+    pub fn project(&self, point: &Point3<f64>) -> Option<Point2<f64>> {
+        let length = self.length();
+        if length == 0. || point.z < self.min_z || point.z > self.max_z {
+            return None;
+        }
+        let direction = (self.end - self.start) / length;
+        let offset = Point2::new(point.x, point.y) - self.start;
+        let distance = offset.dot(&direction);
+        let across = direction.perp(&offset);
+        if distance < 0. || distance > length || across.abs() > self.width_m / 2. {
+            return None;
+        }
+        Some(Point2::new(distance, point.z))
+    }
+}
+
+/// Blue for 0 via green to red for 1.
+fn ramp(t: f64) -> Color<u8> {
+    let t = t.max(0.).min(1.);
+    Color {
+        red: (255. * (2. * t - 1.).max(0.)) as u8,
+        green: (255. * (1. - (2. * t - 1.).abs())) as u8,
+        blue: (255. * (1. - 2. * t).max(0.)) as u8,
+        alpha: 255,
+    }
+}
+
+fn to_f64<T: ToPrimitive>(data: &[T]) -> Vec<f64> {
+    data.iter()
+        .map(|v| v.to_f64().unwrap_or(std::f64::NAN))
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// The distance along the line and the height of each point.
+    pub points: Vec<Point2<f64>>,
+    pub colors: Vec<Color<u8>>,
+    pub length_m: f64,
+    pub min_z: f64,
+    pub max_z: f64,
+}
+
+impl Profile {
+    /// Queries the points in 'cross_section'. They are colored by 'attribute': "color" is used
+    /// as is, other attributes with a single value per point are mapped from blue for their
+    /// smallest to red for their largest value. Without an attribute, all points are gray.
+    pub fn extract<C: PointCloud>(
+        point_clouds: &[C],
+        cross_section: &CrossSection,
+        attribute: Option<&str>,
+    ) -> Result<Self> {
+        let query = PointQuery {
+            attributes: attribute.into_iter().collect(),
+            location: PointLocation::Obb(cross_section.obb()),
+            filter_intervals: HashMap::new(),
+            max_error_m: None,
+        };
+        let mut points = Vec::new();
+        let mut colors = Vec::new();
+        let mut values = Vec::new();
+        let mut parallel_iterator = ParallelIterator::new(
+            point_clouds,
+            &query,
+            NUM_POINTS_PER_BATCH,
+            2, /* num_threads */
+            4, /* buffer_size */
+        );
+        parallel_iterator.try_for_each_batch(|batch| {
+            let batch_values = match attribute.map(|a| &batch.attributes[a]) {
+                None | Some(AttributeData::U8Vec3(_)) => None,
+                Some(data) if data.dim() == 1 => {
+                    macro_rules! rhs {
+                        ($dtype:ident, $data:ident) => {
+                            to_f64($data)
+                        };
+                    }
+                    Some(match_1d_attr_data!(data, rhs))
+                }
+                Some(data) => {
+                    return Err(ErrorKind::InvalidInput(format!(
+                        "Cannot color by {:?} data.",
+                        data.data_type()
+                    ))
+                    .into())
+                }
+            };
+            for (index, position) in batch.position.iter().enumerate() {
+                let point = match cross_section.project(position) {
+                    Some(point) => point,
+                    None => continue,
+                };
+                points.push(point);
+                match (&batch_values, attribute.map(|a| &batch.attributes[a])) {
+                    (Some(batch_values), _) => values.push(batch_values[index]),
+                    (None, Some(AttributeData::U8Vec3(color))) => colors.push(Color {
+                        red: color[index].x,
+                        green: color[index].y,
+                        blue: color[index].z,
+                        alpha: 255,
+                    }),
+                    _ => colors.push(GRAY),
+                }
+            }
+            Ok(())
+        })?;
+
+        if !values.is_empty() {
+            let finite = values.iter().filter(|v| v.is_finite());
+            let min = finite.clone().cloned().fold(std::f64::INFINITY, f64::min);
+            let max = finite.cloned().fold(std::f64::NEG_INFINITY, f64::max);
+            let range = if max > min { max - min } else { 1. };
+            colors = values
+                .iter()
+                .map(|v| {
+                    if v.is_finite() {
+                        ramp((v - min) / range)
+                    } else {
+                        GRAY
+                    }
+                })
+                .collect();
+        }
+        // The heights of the points, or of the slab if it is empty.
+        let (min_z, max_z) = points.iter().fold(
+            (cross_section.max_z, cross_section.min_z),
+            |(min_z, max_z), p| (min_z.min(p.y), max_z.max(p.y)),
+        );
+        let (min_z, max_z) = if points.is_empty() {
+            (cross_section.min_z, cross_section.max_z)
+        } else {
+            (min_z, max_z)
+        };
+        Ok(Profile {
+            points,
+            colors,
+            length_m: cross_section.length(),
+            min_z,
+            max_z,
+        })
+    }
+
+    /// The color of each pixel of a 'width' x 'height' plot, row by row from the top, or None
+    /// where there are no points. Where several points fall into a pixel, the last one wins.
+    fn rasterize(&self, width: u32, height: u32) -> Vec<Option<Color<u8>>> {
+        let mut pixels = vec![None; (width * height) as usize];
+        let length = if self.length_m > 0. {
+            self.length_m
+        } else {
+            1.
+        };
+        let z_range = if self.max_z > self.min_z {
+            self.max_z - self.min_z
+        } else {
+            1.
+        };
+        for (point, color) in self.points.iter().zip(&self.colors) {
+            let x = (point.x / length * f64::from(width)) as u32;
+            let y = ((self.max_z - point.y) / z_range * f64::from(height)) as u32;
+            pixels[(y.min(height - 1) * width + x.min(width - 1)) as usize] = Some(*color);
+        }
+        pixels
+    }
+
+    /// Writes the plot with axis labels, with one square per pixel that has points, so the size
+    /// of the file does not grow with the number of points.
+    pub fn write_svg(&self, path: impl AsRef<Path>, width: u32, height: u32) -> Result<()> {
+        let path = path.as_ref();
+        let (plot_width, plot_height) = (width.max(1), height.max(1));
+        let mut svg = BufWriter::new(File::create(path)?);
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
+            plot_width + 2 * MARGIN_PX,
+            plot_height + 2 * MARGIN_PX
+        )?;
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        let pixels = self.rasterize(plot_width, plot_height);
+        for (index, color) in pixels.iter().enumerate() {
+            if let Some(color) = color {
+                writeln!(
+                    svg,
+                    r#"<rect x="{}" y="{}" width="1" height="1" fill="rgb({},{},{})"/>"#,
+                    MARGIN_PX + index as u32 % plot_width,
+                    MARGIN_PX + index as u32 / plot_width,
+                    color.red,
+                    color.green,
+                    color.blue
+                )?;
+            }
+        }
+        writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="black"/>"#,
+            MARGIN_PX, MARGIN_PX, plot_width, plot_height
+        )?;
+        let label = |svg: &mut BufWriter<File>, x: u32, y: u32, anchor: &str, text: String| {
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="{}" font-size="12">{}</text>"#,
+                x, y, anchor, text
+            )
+        };
+        let bottom = MARGIN_PX + plot_height + 16;
+        label(&mut svg, MARGIN_PX, bottom, "start", "0 m".to_string())?;
+        label(
+            &mut svg,
+            MARGIN_PX + plot_width,
+            bottom,
+            "end",
+            format!("{:.1} m", self.length_m),
+        )?;
+        label(
+            &mut svg,
+            MARGIN_PX - 4,
+            MARGIN_PX + 12,
+            "end",
+            format!("{:.1}", self.max_z),
+        )?;
+        label(
+            &mut svg,
+            MARGIN_PX - 4,
+            MARGIN_PX + plot_height,
+            "end",
+            format!("{:.1}", self.min_z),
+        )?;
+        writeln!(svg, "</svg>")?;
+        svg.flush()
+            .chain_err(|| format!("Could not write {}.", path.display()))
+    }
+
+    /// Writes the plot without labels, on a white background.
+    pub fn write_png(&self, path: impl AsRef<Path>, width: u32, height: u32) -> Result<()> {
+        let path = path.as_ref();
+        let (width, height) = (width.max(1), height.max(1));
+        let pixels = self.rasterize(width, height);
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            match pixels[(y * width + x) as usize] {
+                Some(color) => Rgb([color.red, color.green, color.blue]),
+                None => Rgb([255, 255, 255]),
+            }
+        });
+        image
+            .save(path)
+            .chain_err(|| format!("Could not write {}.", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_into_cross_section() {
+        let cross_section = CrossSection {
+            start: Point2::new(0., 0.),
+            end: Point2::new(10., 10.),
+            width_m: 1.,
+            min_z: -5.,
+            max_z: 5.,
+        };
+        let projected = cross_section.project(&Point3::new(5., 5.2, 3.)).unwrap();
+        assert!((projected.x - 10.2 / 2f64.sqrt()).abs() < 1e-9);
+        assert_eq!(projected.y, 3.);
+        assert_eq!(cross_section.project(&Point3::new(5., 6., 3.)), None);
+        assert_eq!(cross_section.project(&Point3::new(-1., -1., 0.)), None);
+        assert_eq!(cross_section.project(&Point3::new(5., 5., 6.)), None);
+
+        let obb = cross_section.obb();
+        assert_eq!(obb.distance_to_point(&Point3::new(5., 5.2, 3.)), 0.);
+        assert!(obb.distance_to_point(&Point3::new(5., 6., 3.)) > 0.);
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Records which raw data and which settings produced a point cloud.
+
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::proto;
+#[cfg(feature = "build")]
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "build")]
+use std::fs::File;
+#[cfg(feature = "build")]
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: String,
+    pub size_bytes: u64,
+    /// Hex encoded, None if the source is not a local file.
+    pub sha256: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    pub source_files: Vec<SourceFile>,
+    pub tool: String,
+    pub tool_version: String,
+    pub parameters: BTreeMap<String, String>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+}
+
+impl Provenance {
+    /// Starts the provenance of a point cloud that 'tool' generates right now.
+    pub fn new(tool: impl Into<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Provenance {
+            source_files: Vec::new(),
+            tool: tool.into(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            parameters: BTreeMap::new(),
+            timestamp,
+        }
+    }
+
+    /// Adds a local file, which is hashed for that.
+    #[cfg(feature = "build")]
+    pub fn with_source_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).chain_err(|| format!("Could not open {}.", path.display()))?;
+        let mut hasher = Sha256::new();
+        let size_bytes = io::copy(&mut BufReader::new(file), &mut hasher)?;
+        let sha256 = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.source_files.push(SourceFile {
+            path: path.to_string_lossy().into_owned(),
+            size_bytes,
+            sha256: Some(sha256),
+        });
+        Ok(self)
+    }
+
+    /// Adds a source that cannot be hashed, e.g. a remote point cloud.
+    pub fn with_source_location(mut self, location: impl Into<String>) -> Self {
+        self.source_files.push(SourceFile {
+            path: location.into(),
+            size_bytes: 0,
+            sha256: None,
+        });
+        self
+    }
+
+    pub fn with_parameter(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.parameters.insert(name.into(), value.to_string());
+        self
+    }
+
+    pub fn from_proto(proto: &proto::Provenance) -> Self {
+        Provenance {
+            source_files: proto
+                .get_source_files()
+                .iter()
+                .map(|s| SourceFile {
+                    path: s.get_path().to_string(),
+                    size_bytes: s.get_size_bytes(),
+                    sha256: Some(s.get_sha256().to_string()).filter(|h| !h.is_empty()),
+                })
+                .collect(),
+            tool: proto.get_tool().to_string(),
+            tool_version: proto.get_tool_version().to_string(),
+            parameters: proto
+                .get_parameters()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            timestamp: proto.get_timestamp(),
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::Provenance {
+        let mut proto = proto::Provenance::new();
+        for source in &self.source_files {
+            let mut source_proto = proto::SourceFile::new();
+            source_proto.set_path(source.path.clone());
+            source_proto.set_size_bytes(source.size_bytes);
+            source_proto.set_sha256(source.sha256.clone().unwrap_or_default());
+            proto.mut_source_files().push(source_proto);
+        }
+        proto.set_tool(self.tool.clone());
+        proto.set_tool_version(self.tool_version.clone());
+        proto.set_parameters(self.parameters.clone().into_iter().collect());
+        proto.set_timestamp(self.timestamp);
+        proto
+    }
+
+    /// Returns the provenance stored in 'meta', if any.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Option<Self> {
+        if meta.has_provenance() {
+            Some(Self::from_proto(meta.get_provenance()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Generated by {} {}", self.tool, self.tool_version)?;
+        writeln!(f, "Generated at {} (seconds since epoch)", self.timestamp)?;
+        writeln!(f, "Parameters:")?;
+        for (name, value) in &self.parameters {
+            writeln!(f, "  {}: {}", name, value)?;
+        }
+        writeln!(f, "Source files:")?;
+        for source in &self.source_files {
+            match &source.sha256 {
+                Some(sha256) => writeln!(
+                    f,
+                    "  {} ({} bytes, sha256 {})",
+                    source.path, source.size_bytes, sha256
+                )?,
+                None => writeln!(f, "  {}", source.path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stores 'provenance' in the meta file of the point cloud in 'directory', which must exist.
+pub fn write_provenance(directory: impl AsRef<Path>, provenance: &Provenance) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    data_provider.update_meta_proto(|meta| meta.set_provenance(provenance.to_proto()))
+}
+
+#[cfg(all(test, feature = "build"))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_proto_roundtrip() {
+        let tmp_dir = TempDir::new("provenance").unwrap();
+        let path = tmp_dir.path().join("points.xyz");
+        File::create(&path).unwrap().write_all(b"abc").unwrap();
+        let provenance = Provenance::new("test")
+            .with_source_file(&path)
+            .unwrap()
+            .with_source_location("s3://bucket/octree")
+            .with_parameter("resolution", 0.001);
+        assert_eq!(
+            provenance.source_files[0].sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(provenance.source_files[0].size_bytes, 3);
+        assert_eq!(Provenance::from_proto(&provenance.to_proto()), provenance);
+    }
+}
@@ -81,6 +81,16 @@ pub enum Encoding {
     ScaledToCube(Point3<f64>, f64, PositionEncoding),
 }
 
+impl Encoding {
+    /// The size of one encoded position, i.e. of three coordinates.
+    pub fn bytes_per_position(&self) -> usize {
+        match self {
+            Encoding::Plain => 3 * std::mem::size_of::<f64>(),
+            Encoding::ScaledToCube(_, _, pos) => 3 * pos.bytes_per_coordinate(),
+        }
+    }
+}
+
 /// Encode float as integer.
 pub fn fixpoint_encode<T>(value: f64, min: f64, edge_length: f64) -> T
 where
@@ -0,0 +1,307 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming access to input files that might be gzip compressed or members of zip or tar
+//! archives, without extracting anything to disk.
+
+use crate::errors::*;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use glob::Pattern;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Wraps 'reader' into a decompressor if it starts like a gzip stream.
+fn maybe_gunzip(reader: impl Read + Send + 'static) -> Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Opens 'path' for buffered reading, transparently decompressing gzip files.
+pub fn open_maybe_gzipped(path: impl AsRef<Path>) -> Result<Box<dyn BufRead + Send>> {
+    let path = path.as_ref();
+    maybe_gunzip(File::open(path).chain_err(|| format!("Could not open {}.", path.display()))?)
+}
+
+/// A file on disk, or a single member of a zip or tar archive on disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputSource {
+    path: PathBuf,
+    member: Option<String>,
+}
+
+impl InputSource {
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        InputSource {
+            path: path.into(),
+            member: None,
+        }
+    }
+
+    /// Selects the member of the archive at 'path' whose name matches 'member_glob'. Fails if
+    /// 'path' is no zip or tar archive, or if not exactly one member matches.
+    pub fn archive_member(path: impl Into<PathBuf>, member_glob: &str) -> Result<Self> {
+        let path = path.into();
+        let pattern = Pattern::new(member_glob)
+            .chain_err(|| ErrorKind::InvalidInput(format!("Invalid glob '{}'.", member_glob)))?;
+        let kind = ArchiveKind::from_path(&path).ok_or_else(|| {
+            ErrorKind::InvalidInput(format!("{} is no zip or tar archive.", path.display()))
+        })?;
+        let mut matches = member_names(&path, kind)?
+            .into_iter()
+            .filter(|name| pattern.matches(name))
+            .collect::<Vec<_>>();
+        match matches.len() {
+            1 => Ok(InputSource {
+                path,
+                member: matches.pop(),
+            }),
+            n => Err(ErrorKind::InvalidInput(format!(
+                "'{}' matches {} members of {}, expected exactly one.",
+                member_glob,
+                n,
+                path.display()
+            ))
+            .into()),
+        }
+    }
+
+    /// The file on disk, i.e. the archive for archive members.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The name that tells the format of the contained points, i.e. the archive member name or
+    /// the file name.
+    pub fn name(&self) -> String {
+        match &self.member {
+            Some(member) => member.clone(),
+            None => self.path.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Opens a new stream of the (decompressed) contents. This can be called repeatedly to read
+    /// the input more than once.
+    pub fn open(&self) -> Result<Box<dyn BufRead + Send>> {
+        let member = match &self.member {
+            Some(member) => member,
+            None => return open_maybe_gzipped(&self.path),
+        };
+        // Unwrap is safe, members can only be selected for archives.
+        let reader = match ArchiveKind::from_path(&self.path).unwrap() {
+            ArchiveKind::Zip => open_zip_member(&self.path, member)?,
+            kind => open_tar_member(&self.path, kind, member)?,
+        };
+        maybe_gunzip(reader)
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    File::open(path).chain_err(|| format!("Could not open {}.", path.display()))
+}
+
+fn open_tar_stream(path: &Path, kind: ArchiveKind) -> Result<Box<dyn Read + Send>> {
+    let file = open_file(path)?;
+    Ok(match kind {
+        ArchiveKind::TarGz => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        _ => Box::new(BufReader::new(file)),
+    })
+}
+
+fn member_names(path: &Path, kind: ArchiveKind) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if kind == ArchiveKind::Zip {
+        let mut archive = zip::ZipArchive::new(open_file(path)?)
+            .chain_err(|| format!("Could not read zip archive {}.", path.display()))?;
+        for i in 0..archive.len() {
+            let member = archive
+                .by_index(i)
+                .chain_err(|| "Could not read zip member.")?;
+            if !member.is_dir() {
+                names.push(member.name().to_string());
+            }
+        }
+    } else {
+        let mut archive = tar::Archive::new(open_tar_stream(path, kind)?);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type().is_file() {
+                names.push(entry.path()?.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Zip members are read straight from their offset in the archive, so that the returned reader
+/// does not borrow the archive.
+fn open_zip_member(path: &Path, member: &str) -> Result<Box<dyn Read + Send>> {
+    let (compression, data_start, compressed_size) = {
+        let mut archive = zip::ZipArchive::new(open_file(path)?)
+            .chain_err(|| format!("Could not read zip archive {}.", path.display()))?;
+        let member = archive
+            .by_name(member)
+            .chain_err(|| format!("Could not find {} in {}.", member, path.display()))?;
+        (
+            member.compression(),
+            member.data_start(),
+            member.compressed_size(),
+        )
+    };
+    let mut file = open_file(path)?;
+    file.seek(SeekFrom::Start(data_start))?;
+    let data = BufReader::new(file).take(compressed_size);
+    match compression {
+        zip::CompressionMethod::Stored => Ok(Box::new(data)),
+        zip::CompressionMethod::Deflated => Ok(Box::new(DeflateDecoder::new(data))),
+        other => Err(ErrorKind::InvalidInput(format!(
+            "Unsupported compression {:?} of {} in {}.",
+            other,
+            member,
+            path.display()
+        ))
+        .into()),
+    }
+}
+
+/// Tar archives can only be read sequentially, so we skip over everything before the member.
+fn open_tar_member(path: &Path, kind: ArchiveKind, member: &str) -> Result<Box<dyn Read + Send>> {
+    let (position, size) = {
+        let mut archive = tar::Archive::new(open_tar_stream(path, kind)?);
+        let mut found = None;
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if entry.path()?.to_string_lossy() == member {
+                found = Some((entry.raw_file_position(), entry.header().size()?));
+                break;
+            }
+        }
+        found.ok_or_else(|| {
+            ErrorKind::InvalidInput(format!("Could not find {} in {}.", member, path.display()))
+        })?
+    };
+    let mut stream = open_tar_stream(path, kind)?;
+    io::copy(&mut (&mut stream).take(position), &mut io::sink())?;
+    Ok(Box::new(stream.take(size)))
+}
+
+/// A reader that only returns less data than asked for at the end of the stream. Readers like
+/// decompressors return short reads at arbitrary positions, which breaks readers that rely on
+/// their buffer being filled completely.
+pub struct FullReader<R>(pub R);
+
+impl<R: Read> Read for FullReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            match self.0.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn read_all(source: &InputSource) -> String {
+        let mut contents = String::new();
+        source
+            .open()
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_zip_member() {
+        let tmp_dir = TempDir::new("input_source").unwrap();
+        let path = tmp_dir.path().join("delivery.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let deflated =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("readme.txt", deflated).unwrap();
+        zip.write_all(b"not points").unwrap();
+        zip.start_file("scans/points.xyz", deflated).unwrap();
+        zip.write_all(b"1 2 3\n4 5 6\n").unwrap();
+        zip.finish().unwrap();
+
+        let source = InputSource::archive_member(&path, "scans/*.xyz").unwrap();
+        assert_eq!(source.name(), "scans/points.xyz");
+        assert_eq!(read_all(&source), "1 2 3\n4 5 6\n");
+        // Sources can be read repeatedly.
+        assert_eq!(read_all(&source), "1 2 3\n4 5 6\n");
+        assert!(InputSource::archive_member(&path, "*").is_err());
+    }
+
+    #[test]
+    fn test_tar_gz_member() {
+        let tmp_dir = TempDir::new("input_source").unwrap();
+        let path = tmp_dir.path().join("delivery.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for (name, contents) in &[
+            ("a.pts", &b"1\n1 2 3 0 0 0 0\n"[..]),
+            ("b.txt", &b"skip"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let source = InputSource::archive_member(&path, "*.pts").unwrap();
+        assert_eq!(read_all(&source), "1\n1 2 3 0 0 0 0\n");
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Nodes that store several attributes in one file, point by point, so that they can be fetched
+//! with a single request.
+
+use crate::errors::*;
+
+/// The name under which data providers serve the interleaved file of a node. It is also the
+/// extension of the file.
+pub const INTERLEAVED: &str = "interleaved";
+
+/// Interleaves 'columns', which hold the raw values of the same points with the given number of
+/// bytes per point each.
+pub fn interleave(columns: &[(&[u8], usize)]) -> Result<Vec<u8>> {
+    let num_points = match columns.first() {
+        Some((data, bytes_per_point)) => data.len() / (*bytes_per_point).max(1),
+        None => return Ok(Vec::new()),
+    };
+    if let Some((data, bytes_per_point)) = columns
+        .iter()
+        .find(|(data, bytes_per_point)| data.len() != num_points * bytes_per_point)
+    {
+        return Err(ErrorKind::InvalidInput(format!(
+            "Cannot interleave {} bytes with {} per point into {} points.",
+            data.len(),
+            bytes_per_point,
+            num_points
+        ))
+        .into());
+    }
+    let mut interleaved = Vec::with_capacity(columns.iter().map(|(data, _)| data.len()).sum());
+    for index in 0..num_points {
+        for (data, bytes_per_point) in columns {
+            interleaved.extend_from_slice(&data[index * bytes_per_point..][..*bytes_per_point]);
+        }
+    }
+    Ok(interleaved)
+}
+
+/// Splits interleaved 'data' into one column per entry of 'bytes_per_point'.
+pub fn deinterleave(data: &[u8], bytes_per_point: &[usize]) -> Result<Vec<Vec<u8>>> {
+    let record_size: usize = bytes_per_point.iter().sum();
+    if record_size == 0 || data.len() % record_size != 0 {
+        return Err(ErrorKind::InvalidInput(format!(
+            "{} bytes of interleaved data do not consist of records of {} bytes.",
+            data.len(),
+            record_size
+        ))
+        .into());
+    }
+    let num_points = data.len() / record_size;
+    let mut columns: Vec<Vec<u8>> = bytes_per_point
+        .iter()
+        .map(|size| Vec::with_capacity(num_points * size))
+        .collect();
+    for record in data.chunks_exact(record_size) {
+        let mut offset = 0;
+        for (column, size) in columns.iter_mut().zip(bytes_per_point) {
+            column.extend_from_slice(&record[offset..offset + size]);
+            offset += size;
+        }
+    }
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave_roundtrip() {
+        let position = [1, 2, 3, 4, 5, 6];
+        let color = [10, 11, 12, 13, 14, 15];
+        let class = [20, 21];
+        let interleaved =
+            interleave(&[(&position[..], 3), (&color[..], 3), (&class[..], 1)]).unwrap();
+        assert_eq!(
+            interleaved,
+            vec![1, 2, 3, 10, 11, 12, 20, 4, 5, 6, 13, 14, 15, 21]
+        );
+        assert_eq!(
+            deinterleave(&interleaved, &[3, 3, 1]).unwrap(),
+            vec![position.to_vec(), color.to_vec(), class.to_vec()]
+        );
+        assert!(interleave(&[(&position[..], 3), (&class[..], 2)]).is_err());
+        assert!(deinterleave(&interleaved, &[3, 3]).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::read_write::{Encoding, NodeWriter, OpenMode};
+use crate::{match_1d_attr_data, AttributeData, PointsBatch};
+use las::point::{Classification, Format};
+use las::{Builder, Color, Transform, Vector, Write as _, Writer};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+/// The resolution of the positions in LAS files, which are stored as integers.
+const SCALE_M: f64 = 0.001;
+
+/// The offset of the positions is a multiple of this, around the first point.
+const OFFSET_GRANULARITY_M: f64 = 1000.;
+
+/// Point format 2 has 5 bits for the class. Larger classes are written as unclassified.
+const MAX_CLASS: u8 = 31;
+const UNCLASSIFIED: u8 = 1;
+
+fn las_error(error: las::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+/// Writes points to a LAS 1.2 file in point format 2, or to a LAZ file if the path ends in
+/// ".laz". The "color", "intensity" and "class" attributes are stored in the fields of the
+/// format, other attributes are dropped. Classes above 31 do not fit and are written as 1
+/// (unclassified). Positions are stored with a resolution of a millimeter, the encoding is
+/// ignored. LAS files cannot be appended to. The file is only complete after 'finish'.
+pub struct LasNodeWriter {
+    path: PathBuf,
+    open_mode: OpenMode,
+    // Created with the first batch, whose first point determines the offset.
+    writer: Option<Writer<BufWriter<File>>>,
+    num_unclassified: usize,
+}
+
+impl LasNodeWriter {
+    pub fn new(path: impl Into<PathBuf>, open_mode: OpenMode) -> Self {
+        LasNodeWriter {
+            path: path.into(),
+            open_mode,
+            writer: None,
+            num_unclassified: 0,
+        }
+    }
+
+    /// Writes the number of points and the bounds into the header and flushes the file. Warns
+    /// about classes that could not be stored.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.num_unclassified > 0 {
+            eprintln!(
+                "Wrote {} points with classes above {} to {} as unclassified.",
+                self.num_unclassified,
+                MAX_CLASS,
+                self.path.display()
+            );
+        }
+        match self.writer.take() {
+            Some(mut writer) => writer.close().map_err(las_error),
+            None => Ok(()),
+        }
+    }
+
+    fn create_writer(&self, batch: &PointsBatch) -> io::Result<Writer<BufWriter<File>>> {
+        if self.open_mode == OpenMode::Append {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "LAS files cannot be appended to.",
+            ));
+        }
+        let transform = |coordinate: f64| Transform {
+            scale: SCALE_M,
+            offset: (coordinate / OFFSET_GRANULARITY_M).round() * OFFSET_GRANULARITY_M,
+        };
+        let first = batch.position[0];
+        let mut builder = Builder::from((1, 2));
+        builder.point_format = Format::new(2).map_err(las_error)?;
+        builder.transforms = Vector {
+            x: transform(first.x),
+            y: transform(first.y),
+            z: transform(first.z),
+        };
+        let header = builder.into_header().map_err(las_error)?;
+        Writer::from_path(&self.path, header).map_err(las_error)
+    }
+}
+
+impl NodeWriter<PointsBatch> for LasNodeWriter {
+    fn new(path: impl Into<PathBuf>, _encoding: Encoding, open_mode: OpenMode) -> Self {
+        Self::new(path, open_mode)
+    }
+
+    fn write(&mut self, batch: &PointsBatch) -> io::Result<()> {
+        if batch.position.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            self.writer = Some(self.create_writer(batch)?);
+        }
+        let writer = self.writer.as_mut().unwrap();
+        let colors = match batch.attributes.get("color") {
+            Some(AttributeData::U8Vec3(colors)) => Some(colors),
+            _ => None,
+        };
+        let mut intensities = None;
+        if let Some(data) = batch.attributes.get("intensity").filter(|d| d.dim() == 1) {
+            macro_rules! rhs {
+                ($dtype:ident, $data:ident, $intensities:ident) => {
+                    $intensities = Some(
+                        $data
+                            .iter()
+                            .map(|value| (*value as f64).round().max(0.).min(65535.) as u16)
+                            .collect::<Vec<_>>(),
+                    )
+                };
+            }
+            match_1d_attr_data!(data, rhs, intensities)
+        }
+        let classes = match batch.attributes.get("class") {
+            Some(AttributeData::U8(classes)) => Some(classes),
+            _ => None,
+        };
+        for (i, position) in batch.position.iter().enumerate() {
+            let mut point = las::Point {
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                ..Default::default()
+            };
+            if let Some(colors) = colors {
+                // LAS colors have 16 bits per channel.
+                let color = colors[i].map(|c| u16::from(c) * 257);
+                point.color = Some(Color::new(color.x, color.y, color.z));
+            }
+            if let Some(intensities) = &intensities {
+                point.intensity = intensities[i];
+            }
+            if let Some(classes) = classes {
+                let class = if classes[i] <= MAX_CLASS {
+                    classes[i]
+                } else {
+                    self.num_unclassified += 1;
+                    UNCLASSIFIED
+                };
+                point.classification = Classification::new(class).map_err(las_error)?;
+            }
+            writer.write(point).map_err(las_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LasNodeWriter {
+    fn drop(&mut self) {
+        // Writers that were not finished, e.g. because of an earlier error, are closed without
+        // a way to report errors.
+        if let Some(writer) = &mut self.writer {
+            let _res = writer.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::Read as _;
+    use nalgebra::{Point3, Vector3};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_write_las() {
+        let tmp_dir = TempDir::new("test_write_las").unwrap();
+        let path = tmp_dir.path().join("points.las");
+        let batch = PointsBatch {
+            position: vec![
+                Point3::new(4_000_000.125, 500.25, -10.5),
+                Point3::new(4_000_010., 501., -9.),
+            ],
+            attributes: vec![
+                (
+                    "color".to_string(),
+                    AttributeData::U8Vec3(vec![Vector3::new(255, 0, 1), Vector3::new(0, 128, 0)]),
+                ),
+                ("intensity".to_string(), AttributeData::F32(vec![12.4, 1e6])),
+                ("class".to_string(), AttributeData::U8(vec![2, 40])),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let mut writer = LasNodeWriter::new(&path, OpenMode::Truncate);
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let points: Vec<las::Point> = las::Reader::from_path(&path)
+            .unwrap()
+            .points()
+            .map(|point| point.unwrap())
+            .collect();
+        assert_eq!(points.len(), 2);
+        let position = Point3::new(points[0].x, points[0].y, points[0].z);
+        assert!((position - batch.position[0]).norm() < 1e-6);
+        assert_eq!(points[0].color, Some(Color::new(65535, 0, 257)));
+        assert_eq!(points[0].intensity, 12);
+        assert_eq!(points[1].intensity, 65535);
+        assert_eq!(u8::from(points[0].classification), 2);
+        assert_eq!(u8::from(points[1].classification), UNCLASSIFIED);
+    }
+}
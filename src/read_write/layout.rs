@@ -0,0 +1,188 @@
+//! The header in front of the data of node files, which records how the data is encoded. Readers
+//! compare it with what the meta says, so that node files that do not match their meta, e.g.
+//! after copying the meta of another build next to them, are an error instead of garbage.
+//!
+//! The header is 8 bytes long:
+//!
+//! | Bytes | Content                                                        |
+//! |-------|----------------------------------------------------------------|
+//! | 0-3   | The magic bytes `0x89 'P' 'V' 'N'`.                            |
+//! | 4     | The layout version, currently 1.                               |
+//! | 5     | The layout: 1 for plain f64 positions, 2 to 5 for positions    |
+//! |       | scaled to the node with u8, u16, f32 or f64 coordinates, and   |
+//! |       | 64 plus the AttributeDataType of the proto for attributes.     |
+//! | 6-7   | Reserved, zero.                                                |
+//!
+//! Files written before the header was introduced start right with the data. They are still read,
+//! just without the check.
+
+use crate::errors::*;
+use crate::proto;
+use crate::read_write::{Encoding, PositionEncoding};
+use crate::AttributeDataType;
+use protobuf::ProtobufEnum;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+pub const LAYOUT_MAGIC: [u8; 4] = [0x89, b'P', b'V', b'N'];
+pub const LAYOUT_VERSION: u8 = 1;
+pub const LAYOUT_HEADER_LEN: usize = 8;
+
+const ATTRIBUTE_LAYOUT_OFFSET: u8 = 64;
+
+/// What the data of a node file is, as recorded in its header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeLayout {
+    /// Positions, None for plain f64 coordinates, otherwise scaled to the bounding cube of the node.
+    Position(Option<PositionEncoding>),
+    Attribute(AttributeDataType),
+}
+
+fn unknown_layout(byte: u8) -> Error {
+    ErrorKind::InvalidInput(format!("Unknown node layout {}.", byte)).into()
+}
+
+impl NodeLayout {
+    pub fn for_position(encoding: &Encoding) -> Self {
+        match encoding {
+            Encoding::Plain => NodeLayout::Position(None),
+            Encoding::ScaledToCube(_, _, position_encoding) => {
+                NodeLayout::Position(Some(position_encoding.clone()))
+            }
+        }
+    }
+
+    fn to_byte(&self) -> u8 {
+        match self {
+            NodeLayout::Position(None) => 1,
+            NodeLayout::Position(Some(PositionEncoding::Uint8)) => 2,
+            NodeLayout::Position(Some(PositionEncoding::Uint16)) => 3,
+            NodeLayout::Position(Some(PositionEncoding::Float32)) => 4,
+            NodeLayout::Position(Some(PositionEncoding::Float64)) => 5,
+            NodeLayout::Attribute(data_type) => {
+                ATTRIBUTE_LAYOUT_OFFSET + data_type.to_proto().value() as u8
+            }
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        let layout = match byte {
+            1 => NodeLayout::Position(None),
+            2 => NodeLayout::Position(Some(PositionEncoding::Uint8)),
+            3 => NodeLayout::Position(Some(PositionEncoding::Uint16)),
+            4 => NodeLayout::Position(Some(PositionEncoding::Float32)),
+            5 => NodeLayout::Position(Some(PositionEncoding::Float64)),
+            _ if byte >= ATTRIBUTE_LAYOUT_OFFSET => {
+                let data_type =
+                    proto::AttributeDataType::from_i32(i32::from(byte - ATTRIBUTE_LAYOUT_OFFSET))
+                        .ok_or_else(|| unknown_layout(byte))?;
+                NodeLayout::Attribute(AttributeDataType::from_proto(data_type)?)
+            }
+            _ => return Err(unknown_layout(byte)),
+        };
+        Ok(layout)
+    }
+}
+
+pub fn write_layout_header(writer: &mut impl Write, layout: &NodeLayout) -> io::Result<()> {
+    writer.write_all(&LAYOUT_MAGIC)?;
+    writer.write_all(&[LAYOUT_VERSION, layout.to_byte(), 0, 0])
+}
+
+/// Splits 'data' into the layout of its header and the data after it. Data without a header is
+/// returned as a whole with no layout.
+pub fn split_layout_header(data: &[u8]) -> Result<(Option<NodeLayout>, &[u8])> {
+    if data.len() < LAYOUT_HEADER_LEN || data[..LAYOUT_MAGIC.len()] != LAYOUT_MAGIC {
+        return Ok((None, data));
+    }
+    let version = data[LAYOUT_MAGIC.len()];
+    if version > LAYOUT_VERSION {
+        return Err(ErrorKind::InvalidInput(format!(
+            "Node data has layout version {}, but only versions up to {} are supported.",
+            version, LAYOUT_VERSION
+        ))
+        .into());
+    }
+    let layout = NodeLayout::from_byte(data[LAYOUT_MAGIC.len() + 1])?;
+    Ok((Some(layout), &data[LAYOUT_HEADER_LEN..]))
+}
+
+/// Fails if the data was written with another layout than 'expected'.
+pub fn check_layout(layout: &Option<NodeLayout>, expected: &NodeLayout) -> Result<()> {
+    match layout {
+        Some(layout) if layout != expected => Err(ErrorKind::InvalidInput(format!(
+            "Node data was written as {:?}, but the meta expects {:?}. The meta and the node \
+             files are out of sync.",
+            layout, expected
+        ))
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the header from 'reader', returning its layout and a reader for the data after it.
+pub fn read_layout_header(
+    mut reader: Box<dyn Read + Send>,
+) -> Result<(Option<NodeLayout>, Box<dyn Read + Send>)> {
+    let mut start = Vec::with_capacity(LAYOUT_HEADER_LEN);
+    (&mut reader)
+        .take(LAYOUT_HEADER_LEN as u64)
+        .read_to_end(&mut start)?;
+    let (layout, rest) = split_layout_header(&start)?;
+    let rest = rest.to_vec();
+    Ok((layout, Box::new(Cursor::new(rest).chain(reader))))
+}
+
+/// Like 'read_layout_header', failing if the data was written with another layout than
+/// 'expected'.
+pub fn strip_layout_header(
+    reader: Box<dyn Read + Send>,
+    expected: &NodeLayout,
+) -> Result<Box<dyn Read + Send>> {
+    let (layout, reader) = read_layout_header(reader)?;
+    check_layout(&layout, expected)?;
+    Ok(reader)
+}
+
+/// The layout of the node file at 'path', None if it has no header.
+pub fn read_layout_header_of_file(path: &Path) -> Result<Option<NodeLayout>> {
+    let file = File::open(path).chain_err(|| format!("Could not open {}", path.display()))?;
+    Ok(read_layout_header(Box::new(file))?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_header() {
+        let layouts = vec![
+            NodeLayout::Position(None),
+            NodeLayout::Position(Some(PositionEncoding::Uint16)),
+            NodeLayout::Attribute(AttributeDataType::U8Vec3),
+            NodeLayout::Attribute(AttributeDataType::F64Vec3),
+        ];
+        for layout in layouts {
+            let mut data = Vec::new();
+            write_layout_header(&mut data, &layout).unwrap();
+            data.extend_from_slice(&[1, 2, 3]);
+            let (read, rest) = split_layout_header(&data).unwrap();
+            assert_eq!(read, Some(layout.clone()));
+            assert_eq!(rest, &[1, 2, 3]);
+            assert!(check_layout(&read, &layout).is_ok());
+            assert!(check_layout(&read, &NodeLayout::Attribute(AttributeDataType::F32)).is_err());
+        }
+
+        // Data without a header is passed through unchanged.
+        let legacy = vec![7u8; 12];
+        let mut reader = strip_layout_header(
+            Box::new(Cursor::new(legacy.clone())),
+            &NodeLayout::Position(None),
+        )
+        .unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, legacy);
+    }
+}
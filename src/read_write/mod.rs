@@ -18,21 +18,58 @@ pub use self::codec::{
     PositionEncoding,
 };
 
+#[cfg(feature = "build")]
+mod input_source;
+#[cfg(feature = "build")]
+pub use self::input_source::{open_maybe_gzipped, FullReader, InputSource};
+
+#[cfg(feature = "build")]
+mod las;
+#[cfg(feature = "build")]
+pub use self::las::LasNodeWriter;
+
+mod interleaved;
+pub use self::interleaved::{deinterleave, interleave, INTERLEAVED};
+
+mod layout;
+pub use self::layout::{
+    check_layout, read_layout_header, read_layout_header_of_file, split_layout_header,
+    strip_layout_header, write_layout_header, NodeLayout, LAYOUT_HEADER_LEN, LAYOUT_MAGIC,
+    LAYOUT_VERSION,
+};
+
 mod node_iterator;
 pub use self::node_iterator::NodeIterator;
 
 mod node_writer;
 pub use self::node_writer::{DataWriter, NodeWriter, OpenMode, WriteEncoded, WriteLE, WriteLEPos};
 
+mod non_finite;
+pub use self::non_finite::{
+    apply_non_finite_policy, has_non_finite, NonFiniteFilter, NonFinitePolicy, NonFiniteStats,
+};
+
+#[cfg(feature = "build")]
 mod ply;
+#[cfg(feature = "build")]
 pub use self::ply::{PlyIterator, PlyNodeWriter};
 
+#[cfg(feature = "build")]
+mod pts;
+#[cfg(feature = "build")]
+pub use self::pts::{AsciiColumn, AsciiColumns, PtsIterator};
+
 mod raw;
 pub use self::raw::{RawNodeReader, RawNodeWriter};
 
+#[cfg(feature = "build")]
 mod s2;
+#[cfg(feature = "build")]
 pub use self::s2::S2Splitter;
 
+mod stream_error;
+pub use self::stream_error::StreamError;
+
 use std::io::{BufReader, Read};
 
 pub struct AttributeReader {
@@ -43,6 +80,7 @@ pub struct AttributeReader {
 /// We open a lot of files during our work. Sometimes users see errors with 'cannot open more
 /// files'. This utility function attempt to increase the rlimits for the number of open files per
 /// process here, but fails silently if we are not successful.
+#[cfg(feature = "build")]
 pub fn attempt_increasing_rlimit_to_max() {
     unsafe {
         let mut rl = libc::rlimit {
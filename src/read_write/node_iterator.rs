@@ -18,7 +18,7 @@ use crate::read_write::{AttributeReader, Encoding, RawNodeReader};
 use crate::{AttributeDataType, NumberOfPoints, PointsBatch};
 use num_integer::div_ceil;
 use std::collections::HashMap;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 
 /// Streams points from our data provider representation.
 pub struct NodeIterator {
@@ -66,20 +66,41 @@ impl NodeIterator {
         }
 
         let attributes: Vec<&str> = attribute_data_types.keys().map(String::as_str).collect();
-        let mut all_reads =
+        let all_reads =
             data_provider.data(&id.to_string(), &[&["position"], &attributes[..]].concat())?;
-        // Unwrapping all following removals is safe,
-        // as the data provider would already have errored on unavailability.
-        let position_reader = all_reads.remove("position").unwrap();
+        Self::from_readers(
+            all_reads,
+            attribute_data_types,
+            encoding,
+            num_points,
+            batch_size,
+        )
+    }
+
+    /// Like 'from_data_provider', with the readers of the position and all attributes in
+    /// 'attribute_data_types' already requested. Other readers are ignored.
+    pub fn from_readers(
+        mut all_reads: HashMap<String, Box<dyn Read + Send>>,
+        attribute_data_types: &HashMap<String, AttributeDataType>,
+        encoding: Encoding,
+        num_points: usize,
+        batch_size: usize,
+    ) -> Result<Self> {
+        if num_points == 0 {
+            return Ok(NodeIterator::default());
+        }
+
+        let missing = || ErrorKind::NodeNotFound;
+        let position_reader = all_reads.remove("position").ok_or_else(missing)?;
         let attribute_readers = attribute_data_types
             .iter()
             .map(|(attribute, data_type)| {
                 let data_type = *data_type;
-                let reader = BufReader::new(all_reads.remove(attribute).unwrap());
+                let reader = BufReader::new(all_reads.remove(attribute).ok_or_else(missing)?);
                 let attribute_reader = AttributeReader { data_type, reader };
-                (attribute.clone(), attribute_reader)
+                Ok((attribute.clone(), attribute_reader))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
         Ok(Self::new(
             RawNodeReader::new(position_reader, attribute_readers, encoding)?,
@@ -89,6 +110,31 @@ impl NodeIterator {
     }
 }
 
+impl NodeIterator {
+    /// Index of the first point of the next batch.
+    pub fn position(&self) -> usize {
+        self.point_count
+    }
+
+    /// Continues iterating at 'point_index', e.g. to resume an export in the middle of a node.
+    /// The points in between are skipped without being decoded. Since the underlying readers
+    /// are streams, it is only possible to seek forward.
+    pub fn seek(&mut self, point_index: usize) -> Result<()> {
+        if point_index < self.point_count || point_index > self.num_points {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Cannot seek to point {}, the iterator is at point {} of {}.",
+                point_index, self.point_count, self.num_points
+            ))
+            .into());
+        }
+        if let Some(reader) = &mut self.reader {
+            reader.skip(point_index - self.point_count)?;
+        }
+        self.point_count = point_index;
+        Ok(())
+    }
+}
+
 impl NumberOfPoints for NodeIterator {
     fn num_points(&self) -> usize {
         self.num_points
@@ -99,7 +145,7 @@ impl Iterator for NodeIterator {
     type Item = PointsBatch;
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let num_batches = div_ceil(self.num_points, self.batch_size);
+        let num_batches = div_ceil(self.num_points - self.point_count, self.batch_size);
         (num_batches, Some(num_batches))
     }
     fn next(&mut self) -> Option<PointsBatch> {
@@ -117,3 +163,54 @@ impl Iterator for NodeIterator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AttributeData;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_seek() {
+        let num_points = 10;
+        let mut xyz = Vec::new();
+        for i in 0..3 * num_points {
+            xyz.write_f64::<LittleEndian>(i as f64).unwrap();
+        }
+        let mut intensity = Vec::new();
+        for i in 0..num_points {
+            intensity.write_f32::<LittleEndian>(i as f32).unwrap();
+        }
+        let attribute_readers = vec![(
+            "intensity".to_string(),
+            AttributeReader {
+                data_type: AttributeDataType::F32,
+                reader: BufReader::new(Box::new(Cursor::new(intensity)) as Box<dyn Read + Send>),
+            },
+        )]
+        .into_iter()
+        .collect();
+        let reader = RawNodeReader::new(
+            Box::new(Cursor::new(xyz)),
+            attribute_readers,
+            Encoding::Plain,
+        )
+        .unwrap();
+        let mut iterator = NodeIterator::new(reader, num_points, 4);
+
+        iterator.seek(3).unwrap();
+        assert_eq!(iterator.size_hint(), (2, Some(2)));
+        let batch = iterator.next().unwrap();
+        assert_eq!(batch.position[0], nalgebra::Point3::new(9., 10., 11.));
+        match &batch.attributes["intensity"] {
+            AttributeData::F32(intensity) => assert_eq!(intensity, &[3., 4., 5., 6.]),
+            other => panic!("Unexpected intensity {:?}", other),
+        }
+        assert_eq!(iterator.position(), 7);
+        assert!(iterator.seek(5).is_err());
+        iterator.seek(9).unwrap();
+        assert_eq!(iterator.next().unwrap().position.len(), 1);
+        assert!(iterator.next().is_none());
+    }
+}
@@ -195,24 +195,41 @@ impl WriteLE for Color<u8> {
     }
 }
 
-impl WriteLE for Vec<Vector3<u8>> {
-    fn write_le(&self, writer: &mut DataWriter) -> Result<()> {
-        for elem in self {
-            elem.write_le(writer)?;
+macro_rules! derive_write_le_vec3 {
+    ($scalar:ty, $method:ident) => {
+        impl WriteLE for Vector3<$scalar> {
+            fn write_le(&self, writer: &mut DataWriter) -> Result<()> {
+                let mut bytes = [0; 3 * std::mem::size_of::<$scalar>()];
+                LittleEndian::$method(self.as_slice(), &mut bytes);
+                writer.write_all(&bytes)
+            }
         }
-        Ok(())
-    }
+    };
 }
 
-impl WriteLE for Vec<Vector3<f64>> {
-    fn write_le(&self, writer: &mut DataWriter) -> Result<()> {
-        for elem in self {
-            elem.write_le(writer)?;
+derive_write_le_vec3!(u32, write_u32_into);
+derive_write_le_vec3!(u64, write_u64_into);
+derive_write_le_vec3!(i32, write_i32_into);
+
+macro_rules! derive_write_le_vec_of_vec3 {
+    ($scalar:ty) => {
+        impl WriteLE for Vec<Vector3<$scalar>> {
+            fn write_le(&self, writer: &mut DataWriter) -> Result<()> {
+                for elem in self {
+                    elem.write_le(writer)?;
+                }
+                Ok(())
+            }
         }
-        Ok(())
-    }
+    };
 }
 
+derive_write_le_vec_of_vec3!(u8);
+derive_write_le_vec_of_vec3!(u32);
+derive_write_le_vec_of_vec3!(u64);
+derive_write_le_vec_of_vec3!(i32);
+derive_write_le_vec_of_vec3!(f64);
+
 impl WriteLE for Vec<Point3<f64>> {
     fn write_le(&self, writer: &mut DataWriter) -> Result<()> {
         for elem in self {
@@ -0,0 +1,320 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::read_write::StreamError;
+use crate::{AttributeData, NumberOfPoints, PointsBatch};
+use std::ops::AddAssign;
+use std::str::FromStr;
+
+/// What to do with NaN or infinite positions and attribute values during import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Drop points that have any non-finite position coordinate or attribute value.
+    Drop,
+    /// Fail on the first non-finite value.
+    Error,
+    /// Clamp non-finite attribute values to the finite range of their type, with NaN becoming 0.
+    /// A non-finite position cannot be clamped to anything meaningful, so such points are dropped.
+    Clamp,
+}
+
+impl FromStr for NonFinitePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(NonFinitePolicy::Drop),
+            "error" => Ok(NonFinitePolicy::Error),
+            "clamp" => Ok(NonFinitePolicy::Clamp),
+            _ => Err(format!(
+                "Invalid non-finite policy '{}', expected one of drop, error, clamp.",
+                s
+            )),
+        }
+    }
+}
+
+/// Counts what a 'NonFinitePolicy' did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NonFiniteStats {
+    pub points_dropped: usize,
+    pub values_clamped: usize,
+}
+
+impl NonFiniteStats {
+    pub fn is_empty(&self) -> bool {
+        self.points_dropped == 0 && self.values_clamped == 0
+    }
+
+    /// Prints the counts to stderr, if there is anything to report.
+    pub fn report(&self) {
+        if !self.is_empty() {
+            eprintln!(
+                "Non-finite values: dropped {} points, clamped {} attribute values.",
+                self.points_dropped, self.values_clamped
+            );
+        }
+    }
+}
+
+impl AddAssign for NonFiniteStats {
+    fn add_assign(&mut self, other: Self) {
+        self.points_dropped += other.points_dropped;
+        self.values_clamped += other.values_clamped;
+    }
+}
+
+fn clamp_f32(v: &mut f32) -> bool {
+    let finite = if v.is_nan() {
+        0.
+    } else {
+        v.max(f32::MIN).min(f32::MAX)
+    };
+    let clamped = finite.to_bits() != v.to_bits();
+    *v = finite;
+    clamped
+}
+
+fn clamp_f64(v: &mut f64) -> bool {
+    let finite = if v.is_nan() {
+        0.
+    } else {
+        v.max(f64::MIN).min(f64::MAX)
+    };
+    let clamped = finite.to_bits() != v.to_bits();
+    *v = finite;
+    clamped
+}
+
+/// Returns for each point whether any of its floating point attribute values is not finite.
+fn non_finite_attributes(batch: &PointsBatch) -> Vec<bool> {
+    let mut non_finite = vec![false; batch.position.len()];
+    for data in batch.attributes.values() {
+        match data {
+            AttributeData::F32(values) => {
+                for (flag, v) in non_finite.iter_mut().zip(values) {
+                    *flag |= !v.is_finite();
+                }
+            }
+            AttributeData::F64(values) => {
+                for (flag, v) in non_finite.iter_mut().zip(values) {
+                    *flag |= !v.is_finite();
+                }
+            }
+            AttributeData::F64Vec3(values) => {
+                for (flag, v) in non_finite.iter_mut().zip(values) {
+                    *flag |= !v.iter().all(|c| c.is_finite());
+                }
+            }
+            // Integer attributes are always finite.
+            _ => (),
+        }
+    }
+    non_finite
+}
+
+/// Returns whether 'batch' has any non-finite position coordinate or attribute value.
+pub fn has_non_finite(batch: &PointsBatch) -> bool {
+    batch
+        .position
+        .iter()
+        .any(|p| !p.coords.iter().all(|c| c.is_finite()))
+        || non_finite_attributes(batch).into_iter().any(|f| f)
+}
+
+/// Applies 'policy' to 'batch' in place. Returns an error describing the first offending point
+/// for 'NonFinitePolicy::Error', otherwise what was done.
+pub fn apply_non_finite_policy(
+    batch: &mut PointsBatch,
+    policy: NonFinitePolicy,
+) -> Result<NonFiniteStats, String> {
+    let mut stats = NonFiniteStats::default();
+    if policy == NonFinitePolicy::Clamp {
+        for data in batch.attributes.values_mut() {
+            match data {
+                AttributeData::F32(values) => {
+                    stats.values_clamped += values.iter_mut().map(clamp_f32).filter(|c| *c).count();
+                }
+                AttributeData::F64(values) => {
+                    stats.values_clamped += values.iter_mut().map(clamp_f64).filter(|c| *c).count();
+                }
+                AttributeData::F64Vec3(values) => {
+                    stats.values_clamped += values
+                        .iter_mut()
+                        .flat_map(|v| v.iter_mut())
+                        .map(clamp_f64)
+                        .filter(|c| *c)
+                        .count();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let non_finite_attributes = non_finite_attributes(batch);
+    let keep: Vec<bool> = batch
+        .position
+        .iter()
+        .zip(non_finite_attributes)
+        .map(|(p, non_finite_attribute)| {
+            p.coords.iter().all(|c| c.is_finite()) && !non_finite_attribute
+        })
+        .collect();
+    if let Some(i) = keep.iter().position(|k| !k) {
+        if policy == NonFinitePolicy::Error {
+            return Err(format!(
+                "Point {} at {:?} has a non-finite position or attribute value.",
+                i, batch.position[i]
+            ));
+        }
+        stats.points_dropped = keep.iter().filter(|k| !**k).count();
+        batch.retain(&keep);
+    }
+    Ok(stats)
+}
+
+/// Applies a 'NonFinitePolicy' to every batch of the wrapped iterator and reports the counts
+/// once it is exhausted. For 'NonFinitePolicy::Error', the first non-finite value ends the
+/// iteration, which then has to be checked with 'NonFiniteFilter::error'.
+pub struct NonFiniteFilter<I> {
+    inner: I,
+    policy: NonFinitePolicy,
+    stats: NonFiniteStats,
+    error: StreamError,
+    reported: bool,
+}
+
+impl<I> NonFiniteFilter<I> {
+    pub fn new(inner: I, policy: NonFinitePolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            stats: NonFiniteStats::default(),
+            error: StreamError::default(),
+            reported: false,
+        }
+    }
+
+    pub fn stats(&self) -> NonFiniteStats {
+        self.stats
+    }
+
+    /// The error that ended the iteration, if any. It can be checked after the filter was
+    /// consumed.
+    pub fn error(&self) -> StreamError {
+        self.error.clone()
+    }
+}
+
+impl<I> Iterator for NonFiniteFilter<I>
+where
+    I: Iterator<Item = PointsBatch>,
+{
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        if self.error.is_set() {
+            return None;
+        }
+        match self.inner.next() {
+            Some(mut batch) => match apply_non_finite_policy(&mut batch, self.policy) {
+                Ok(stats) => {
+                    self.stats += stats;
+                    Some(batch)
+                }
+                Err(msg) => {
+                    self.error.set(msg);
+                    None
+                }
+            },
+            None => {
+                if !self.reported {
+                    self.stats.report();
+                    self.reported = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// The number of points before filtering, i.e. an upper bound.
+impl<I> NumberOfPoints for NonFiniteFilter<I>
+where
+    I: NumberOfPoints,
+{
+    fn num_points(&self) -> usize {
+        self.inner.num_points()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    fn batch() -> PointsBatch {
+        PointsBatch {
+            position: vec![
+                Point3::new(1., 2., 3.),
+                Point3::new(std::f64::NAN, 2., 3.),
+                Point3::new(1., 2., 3.),
+            ],
+            attributes: vec![(
+                "intensity".to_string(),
+                AttributeData::F32(vec![1., 2., std::f32::INFINITY]),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_non_finite_policies() {
+        let mut dropped = batch();
+        assert!(has_non_finite(&dropped));
+        let stats = apply_non_finite_policy(&mut dropped, NonFinitePolicy::Drop).unwrap();
+        assert_eq!(stats.points_dropped, 2);
+        assert_eq!(dropped.position.len(), 1);
+        assert!(!has_non_finite(&dropped));
+
+        let mut clamped = batch();
+        let stats = apply_non_finite_policy(&mut clamped, NonFinitePolicy::Clamp).unwrap();
+        assert_eq!(stats.points_dropped, 1);
+        assert_eq!(stats.values_clamped, 1);
+        let intensity: &Vec<f32> = clamped.get_attribute_vec("intensity").unwrap();
+        assert_eq!(intensity, &vec![1., std::f32::MAX]);
+
+        assert!(apply_non_finite_policy(&mut batch(), NonFinitePolicy::Error).is_err());
+    }
+
+    #[test]
+    fn test_non_finite_filter_stops_on_error() {
+        let mut filter =
+            NonFiniteFilter::new(vec![batch(), batch()].into_iter(), NonFinitePolicy::Error);
+        let error = filter.error();
+        assert!(filter.next().is_none());
+        assert!(filter.next().is_none());
+        assert!(error.check().is_err());
+
+        let mut filter = NonFiniteFilter::new(vec![batch()].into_iter(), NonFinitePolicy::Drop);
+        assert_eq!(filter.next().unwrap().position.len(), 1);
+        assert!(filter.next().is_none());
+        assert!(filter.error().check().is_ok());
+    }
+}
@@ -14,13 +14,15 @@
 
 use crate::errors::*;
 use crate::read_write::{
-    DataWriter, Encoding, NodeWriter, OpenMode, PositionEncoding, WriteEncoded, WriteLE, WriteLEPos,
+    DataWriter, Encoding, FullReader, InputSource, NodeWriter, OpenMode, PositionEncoding,
+    StreamError, WriteEncoded, WriteLE, WriteLEPos,
 };
-use crate::{AttributeData, NumberOfPoints, Point, PointsBatch};
-use byteorder::{ByteOrder, LittleEndian};
+use crate::{AttributeData, NumberOfPoints, Point, PointsBatch, Schema};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
 use nalgebra::{Point3, Vector3};
 use num_integer::div_ceil;
 use num_traits::identities::Zero;
+use num_traits::NumCast;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fs::File;
@@ -90,7 +92,7 @@ impl<'a> Index<&'a str> for Header {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum Format {
     BinaryLittleEndianV1,
     BinaryBigEndianV1,
@@ -241,52 +243,66 @@ macro_rules! create_and_return_reading_fn {
 }
 
 macro_rules! read_casted_property {
-    ($data_type:expr, $assign:expr, &mut $size:ident) => {
+    ($data_type:expr, $assign:expr, &mut $size:ident, $byte_order:ident) => {
         match $data_type {
             DataType::Uint8 => {
                 create_and_return_reading_fn!($assign, $size, 1, |buf: &[u8]| buf[0])
             }
             DataType::Int8 => create_and_return_reading_fn!($assign, $size, 1, |buf: &[u8]| buf[0]),
             DataType::Uint16 => {
-                create_and_return_reading_fn!($assign, $size, 2, LittleEndian::read_u16)
+                create_and_return_reading_fn!($assign, $size, 2, $byte_order::read_u16)
             }
             DataType::Int16 => {
-                create_and_return_reading_fn!($assign, $size, 2, LittleEndian::read_i16)
+                create_and_return_reading_fn!($assign, $size, 2, $byte_order::read_i16)
             }
             DataType::Uint32 => {
-                create_and_return_reading_fn!($assign, $size, 4, LittleEndian::read_u32)
+                create_and_return_reading_fn!($assign, $size, 4, $byte_order::read_u32)
             }
             DataType::Int32 => {
-                create_and_return_reading_fn!($assign, $size, 4, LittleEndian::read_i32)
+                create_and_return_reading_fn!($assign, $size, 4, $byte_order::read_i32)
             }
             DataType::Uint64 => {
-                create_and_return_reading_fn!($assign, $size, 4, LittleEndian::read_u64)
+                create_and_return_reading_fn!($assign, $size, 8, $byte_order::read_u64)
             }
             DataType::Int64 => {
-                create_and_return_reading_fn!($assign, $size, 4, LittleEndian::read_i64)
+                create_and_return_reading_fn!($assign, $size, 8, $byte_order::read_i64)
             }
             DataType::Float32 => {
-                create_and_return_reading_fn!($assign, $size, 4, LittleEndian::read_f32)
+                create_and_return_reading_fn!($assign, $size, 4, $byte_order::read_f32)
             }
             DataType::Float64 => {
-                create_and_return_reading_fn!($assign, $size, 8, LittleEndian::read_f64)
+                create_and_return_reading_fn!($assign, $size, 8, $byte_order::read_f64)
             }
         }
     };
 }
 
+// ASCII points are converted to little endian before they are read, so only big endian files
+// need their own reading functions. The closure assigning the value is repeated in both arms, so
+// that the reading functions do not capture anything and remain plain function pointers.
 macro_rules! push_reader {
-    ($readers:ident, $prop:expr, $data:expr, &mut $num_bytes:ident, $dtype:ty) => {{
+    ($readers:ident, $format:expr, $prop:expr, $data:expr, &mut $num_bytes:ident, $dtype:ty) => {{
         $readers.push(PropertyReader {
             prop: $prop.clone(),
             data: $data,
-            func: read_casted_property!(
-                $prop.data_type,
-                |data: &mut AttributeData, val: $dtype| {
-                    <&mut Vec<$dtype>>::try_from(data).unwrap().push(val);
-                },
-                &mut $num_bytes
-            ),
+            func: match $format {
+                Format::BinaryBigEndianV1 => read_casted_property!(
+                    $prop.data_type,
+                    |data: &mut AttributeData, val: $dtype| {
+                        <&mut Vec<$dtype>>::try_from(data).unwrap().push(val);
+                    },
+                    &mut $num_bytes,
+                    BigEndian
+                ),
+                Format::BinaryLittleEndianV1 | Format::AsciiV1 => read_casted_property!(
+                    $prop.data_type,
+                    |data: &mut AttributeData, val: $dtype| {
+                        <&mut Vec<$dtype>>::try_from(data).unwrap().push(val);
+                    },
+                    &mut $num_bytes,
+                    LittleEndian
+                ),
+            },
         });
     }};
 }
@@ -314,32 +330,37 @@ struct PropertyReader {
     func: ReadingFn,
 }
 
-/// Abstraction to read binary points from ply files into points.
+/// Abstraction to read points from binary or ASCII ply files. An ASCII vertex that cannot be read
+/// or parsed ends the iteration, see 'PlyIterator::error'.
 pub struct PlyIterator {
-    reader: BufReader<File>,
+    reader: BufReader<FullReader<Box<dyn BufRead + Send>>>,
     readers: Vec<PropertyReader>,
+    format: Format,
+    // Only used for ASCII files: the current line and the point parsed from it, encoded like a
+    // little endian binary point.
+    line: String,
+    ascii_point: Vec<u8>,
     pub num_total_points: i64,
     batch_size: usize,
     offset: Vector3<f64>,
     point_count: usize,
+    error: StreamError,
 }
 
 impl PlyIterator {
     pub fn from_file<P: AsRef<Path>>(ply_file: P, batch_size: usize) -> Result<Self> {
-        let mut file = File::open(ply_file).chain_err(|| "Could not open input file.")?;
-        let mut reader = BufReader::new(file);
-        let (header, header_len) = parse_header(&mut reader)?;
-        file = reader.into_inner();
-        file.seek(SeekFrom::Start(header_len as u64))?;
+        Self::from_source(&InputSource::file(ply_file.as_ref()), batch_size)
+    }
+
+    /// Reads from files, gzip compressed files or archive members alike.
+    pub fn from_source(source: &InputSource, batch_size: usize) -> Result<Self> {
+        let mut reader = source.open().chain_err(|| "Could not open input file.")?;
+        let (header, _) = parse_header(&mut reader)?;
 
         if !header.has_element("vertex") {
             panic!("Header does not have element 'vertex'");
         }
 
-        if header.format != Format::BinaryLittleEndianV1 {
-            panic!("Unsupported PLY format: {:?}", header.format);
-        }
-
         let vertex = &header["vertex"];
         let mut seen_x = false;
         let mut seen_y = false;
@@ -353,6 +374,7 @@ impl PlyIterator {
                 "x" => {
                     push_reader!(
                         readers,
+                        header.format,
                         prop,
                         AttributeData::F64(Vec::with_capacity(batch_size)),
                         &mut num_bytes_per_point,
@@ -363,6 +385,7 @@ impl PlyIterator {
                 "y" => {
                     push_reader!(
                         readers,
+                        header.format,
                         prop,
                         AttributeData::F64(Vec::with_capacity(batch_size)),
                         &mut num_bytes_per_point,
@@ -373,6 +396,7 @@ impl PlyIterator {
                 "z" => {
                     push_reader!(
                         readers,
+                        header.format,
                         prop,
                         AttributeData::F64(Vec::with_capacity(batch_size)),
                         &mut num_bytes_per_point,
@@ -381,7 +405,15 @@ impl PlyIterator {
                     seen_z = true;
                 }
                 "a" | "alpha" => {
-                    readers.push(push_skip_reader!(prop, &mut num_bytes_per_point, 1));
+                    // Like the color channels, opacity is stored as a byte per point.
+                    push_reader!(
+                        readers,
+                        header.format,
+                        prop,
+                        AttributeData::U8(Vec::with_capacity(batch_size)),
+                        &mut num_bytes_per_point,
+                        u8
+                    );
                 }
                 other => {
                     // TODO(feuerste): We may need to support multidimensional attributes.
@@ -391,6 +423,7 @@ impl PlyIterator {
                     match prop.data_type {
                         Uint8 => push_reader!(
                             readers,
+                            header.format,
                             prop,
                             AttributeData::U8(Vec::with_capacity(batch_size)),
                             &mut num_bytes_per_point,
@@ -398,6 +431,7 @@ impl PlyIterator {
                         ),
                         Uint64 => push_reader!(
                             readers,
+                            header.format,
                             prop,
                             AttributeData::U64(Vec::with_capacity(batch_size)),
                             &mut num_bytes_per_point,
@@ -405,6 +439,7 @@ impl PlyIterator {
                         ),
                         Int64 => push_reader!(
                             readers,
+                            header.format,
                             prop,
                             AttributeData::I64(Vec::with_capacity(batch_size)),
                             &mut num_bytes_per_point,
@@ -412,6 +447,7 @@ impl PlyIterator {
                         ),
                         Float32 => push_reader!(
                             readers,
+                            header.format,
                             prop,
                             AttributeData::F32(Vec::with_capacity(batch_size)),
                             &mut num_bytes_per_point,
@@ -419,15 +455,22 @@ impl PlyIterator {
                         ),
                         Float64 => push_reader!(
                             readers,
+                            header.format,
                             prop,
                             AttributeData::F64(Vec::with_capacity(batch_size)),
                             &mut num_bytes_per_point,
                             f64
                         ),
+                        Uint16 => push_reader!(
+                            readers,
+                            header.format,
+                            prop,
+                            AttributeData::U16(Vec::with_capacity(batch_size)),
+                            &mut num_bytes_per_point,
+                            u16
+                        ),
                         Int8 => readers.push(push_skip_reader!(prop, &mut num_bytes_per_point, 1)),
-                        Uint16 | Int16 => {
-                            readers.push(push_skip_reader!(prop, &mut num_bytes_per_point, 2))
-                        }
+                        Int16 => readers.push(push_skip_reader!(prop, &mut num_bytes_per_point, 2)),
 
                         Uint32 | Int32 => {
                             readers.push(push_skip_reader!(prop, &mut num_bytes_per_point, 4))
@@ -444,14 +487,123 @@ impl PlyIterator {
         // We align the buffer of this 'BufReader' to points, so that we can index this buffer and know
         // that it will always contain full points to parse.
         Ok(PlyIterator {
-            reader: BufReader::with_capacity(num_bytes_per_point * 1024, file),
+            reader: BufReader::with_capacity(num_bytes_per_point * 1024, FullReader(reader)),
             readers,
+            format: header.format,
+            line: String::new(),
+            ascii_point: Vec::with_capacity(num_bytes_per_point),
             num_total_points: header["vertex"].count,
             batch_size,
             offset: header.offset,
             point_count: 0,
+            error: StreamError::default(),
         })
     }
+
+    /// The error that ended the iteration, if any. It can be checked after the iterator was
+    /// consumed.
+    pub fn error(&self) -> StreamError {
+        self.error.clone()
+    }
+
+    /// The attributes of the batches that octrees know how to store. Only files with color
+    /// channels have colors.
+    pub fn attributes(&self) -> Vec<&'static str> {
+        let mut attributes = Vec::new();
+        let has_color = self
+            .readers
+            .iter()
+            .any(|r| r.prop.name == "r" || r.prop.name == "red");
+        if has_color {
+            attributes.push("color");
+        }
+        // Octrees store intensity as f32, label ids as u16 and timestamps as f64, other types are
+        // not converted.
+        let has = |name: &str, data_type: DataType| {
+            self.readers
+                .iter()
+                .any(|r| r.prop.name == name && r.prop.data_type == data_type)
+        };
+        if has("intensity", DataType::Float32) {
+            attributes.push("intensity");
+        }
+        if has("label", DataType::Uint16) {
+            attributes.push("label");
+        }
+        if has("timestamp", DataType::Float64) {
+            attributes.push("timestamp");
+        }
+        attributes
+    }
+
+    /// Reads the next line of an ASCII file into 'ascii_point'. Blank lines are skipped and tokens
+    /// after the last property are ignored.
+    fn read_ascii_point(&mut self) -> Result<()> {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "PLY ended before all {} vertices were read",
+                    self.num_total_points
+                ))
+                .into());
+            }
+            if !self.line.trim().is_empty() {
+                break;
+            }
+        }
+        self.ascii_point.clear();
+        let mut tokens = self.line.split_whitespace();
+        for r in &self.readers {
+            let token = tokens.next().ok_or_else(|| {
+                ErrorKind::InvalidInput(format!("Missing '{}' in line: {}", r.prop.name, self.line))
+            })?;
+            encode_ascii_value(token, r.prop.data_type, &mut self.ascii_point)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number of an ASCII file. Integers written as floats are accepted, and so is a comma
+/// as decimal separator, which some tools write depending on the locale.
+fn parse_ascii_value<T: FromStr + NumCast>(token: &str) -> Option<T> {
+    token.parse().ok().or_else(|| {
+        let value: f64 = token.replace(',', ".").parse().ok()?;
+        num_traits::cast(value)
+    })
+}
+
+fn encode_ascii_value(token: &str, data_type: DataType, buf: &mut Vec<u8>) -> Result<()> {
+    let invalid = || ErrorKind::InvalidInput(format!("Invalid {:?} value: {}", data_type, token));
+    match data_type {
+        DataType::Int8 => buf.write_i8(parse_ascii_value(token).ok_or_else(invalid)?)?,
+        DataType::Uint8 => buf.write_u8(parse_ascii_value(token).ok_or_else(invalid)?)?,
+        DataType::Int16 => {
+            buf.write_i16::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Uint16 => {
+            buf.write_u16::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Int32 => {
+            buf.write_i32::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Uint32 => {
+            buf.write_u32::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Int64 => {
+            buf.write_i64::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Uint64 => {
+            buf.write_u64::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Float32 => {
+            buf.write_f32::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+        DataType::Float64 => {
+            buf.write_f64::<LittleEndian>(parse_ascii_value(token).ok_or_else(invalid)?)?
+        }
+    }
+    Ok(())
 }
 
 fn batch_from_readers(readers: &mut [PropertyReader], offset: &Vector3<f64>) -> PointsBatch {
@@ -467,19 +619,20 @@ fn batch_from_readers(readers: &mut [PropertyReader], offset: &Vector3<f64>) ->
             "r" | "red" => r_vec = <&mut Vec<u8>>::try_from(data).unwrap().split_off(0),
             "g" | "green" => g_vec = <&mut Vec<u8>>::try_from(data).unwrap().split_off(0),
             "b" | "blue" => b_vec = <&mut Vec<u8>>::try_from(data).unwrap().split_off(0),
-            "a" | "alpha" => {}
+            "a" | "alpha" => {
+                attributes.insert("alpha".to_string(), data.split_off(0));
+            }
             other => {
                 let other_data = match reader.prop.data_type {
                     DataType::Uint8
+                    | DataType::Uint16
                     | DataType::Uint64
                     | DataType::Int64
                     | DataType::Float32
                     | DataType::Float64 => data.split_off(0),
-                    DataType::Int8
-                    | DataType::Uint16
-                    | DataType::Int16
-                    | DataType::Uint32
-                    | DataType::Int32 => continue,
+                    DataType::Int8 | DataType::Int16 | DataType::Uint32 | DataType::Int32 => {
+                        continue
+                    }
                 };
                 attributes.insert(other.to_string(), other_data);
             }
@@ -525,7 +678,7 @@ impl Iterator for PlyIterator {
     }
 
     fn next(&mut self) -> Option<PointsBatch> {
-        if self.point_count == self.num_total_points as usize {
+        if self.point_count == self.num_total_points as usize || self.error.is_set() {
             return None;
         }
 
@@ -534,7 +687,24 @@ impl Iterator for PlyIterator {
             self.num_total_points as usize - self.point_count,
         );
 
-        for _ in 0..cur_batch_size {
+        for i in 0..cur_batch_size {
+            if self.format == Format::AsciiV1 {
+                if let Err(err) = self.read_ascii_point() {
+                    self.error.set(format!(
+                        "Could not read PLY vertex {}: {}",
+                        self.point_count + i,
+                        err
+                    ));
+                    return None;
+                }
+                let mut nread = 0;
+                for r in self.readers.iter_mut() {
+                    let cnread = nread;
+                    (r.func)(&mut nread, &self.ascii_point[cnread..], &mut r.data);
+                }
+                continue;
+            }
+
             let mut nread = 0;
 
             // We made sure before that the internal buffer of 'reader' is aligned to the number of
@@ -560,6 +730,8 @@ pub struct PlyNodeWriter {
     writer: DataWriter,
     point_count: usize,
     encoding: Encoding,
+    // Set by the first batch, all following batches must have the same attributes.
+    schema: Option<Schema>,
 }
 
 impl NodeWriter<PointsBatch> for PlyNodeWriter {
@@ -571,6 +743,10 @@ impl NodeWriter<PointsBatch> for PlyNodeWriter {
         if p.position.is_empty() {
             return Ok(());
         }
+        let schema = self.schema.get_or_insert_with(|| Schema::from_batch(p));
+        schema
+            .assert_matches(p)
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
         if self.point_count == 0 {
             self.create_header(
                 &p.attributes
@@ -590,6 +766,9 @@ impl NodeWriter<PointsBatch> for PlyNodeWriter {
                                 AttributeData::F32(_) => "float",
                                 AttributeData::F64(_) => "double",
                                 AttributeData::U8Vec3(_) => "uchar",
+                                AttributeData::U32Vec3(_) => "uint",
+                                AttributeData::U64Vec3(_) => "ulonglong",
+                                AttributeData::I32Vec3(_) => "int",
                                 AttributeData::F64Vec3(_) => "double",
                             },
                             data.dim(),
@@ -619,7 +798,10 @@ impl NodeWriter<Point> for PlyNodeWriter {
 
     fn write(&mut self, p: &Point) -> io::Result<()> {
         if self.point_count == 0 {
-            let mut attributes = vec![("color", "uchar", 3)];
+            let mut attributes = Vec::new();
+            if p.color.is_some() {
+                attributes.push(("color", "uchar", 3));
+            }
             if p.intensity.is_some() {
                 attributes.push(("intensity", "float", 1));
             }
@@ -627,7 +809,9 @@ impl NodeWriter<Point> for PlyNodeWriter {
         }
 
         p.position.write_encoded(&self.encoding, &mut self.writer)?;
-        p.color.write_le(&mut self.writer)?;
+        if let Some(color) = &p.color {
+            color.write_le(&mut self.writer)?;
+        }
         if let Some(i) = p.intensity {
             i.write_le(&mut self.writer)?;
         }
@@ -685,6 +869,7 @@ impl PlyNodeWriter {
             writer,
             point_count,
             encoding,
+            schema: None,
         }
     }
 
@@ -772,6 +957,10 @@ mod tests {
         let color_last: &Vec<Vector3<u8>> = batches[LAST_BATCH].get_attribute_vec("color").unwrap();
         assert_eq!(color_first[0].x, 255);
         assert_eq!(color_last.last().unwrap().x, 227);
+        let alpha_first: &Vec<u8> = batches[0].get_attribute_vec("alpha").unwrap();
+        let alpha_last: &Vec<u8> = batches[LAST_BATCH].get_attribute_vec("alpha").unwrap();
+        assert_eq!(alpha_first[0], 252);
+        assert_eq!(*alpha_last.last().unwrap(), 224);
     }
 
     #[test]
@@ -793,6 +982,97 @@ mod tests {
         assert_eq!(color_last.last().unwrap().x, 234);
     }
 
+    #[test]
+    fn test_xyz_rgb_ascii() {
+        let tmp_dir = TempDir::new("test_xyz_rgb_ascii").unwrap();
+        let path = tmp_dir.path().join("ascii.ply");
+        // Windows line endings, a comma as decimal separator and a trailing token.
+        let content = "ply\r\nformat ascii 1.0\r\nelement vertex 3\r\nproperty float x\r\n\
+                       property float y\r\nproperty double z\r\nproperty uchar red\r\n\
+                       property uchar green\r\nproperty uchar blue\r\nend_header\r\n\
+                       1 2 3 255 0 0\r\n4,5 5.5 -6 0 255 0 extra\r\n\r\n7e1 8 9 0 0 255\r\n";
+        std::fs::write(&path, content).unwrap();
+        let batches = batches_from_file(&path);
+        assert_eq!(2, batches.len());
+        assert_eq!(batches[0].position[1], Point3::new(4.5, 5.5, -6.));
+        assert_eq!(batches[1].position[0], Point3::new(70., 8., 9.));
+        let color: &Vec<Vector3<u8>> = batches[1].get_attribute_vec("color").unwrap();
+        assert_eq!(color[0], Vector3::new(0, 0, 255));
+    }
+
+    #[test]
+    fn test_malformed_ascii() {
+        let tmp_dir = TempDir::new("test_malformed_ascii").unwrap();
+        let read = |vertices: &str| {
+            let path = tmp_dir.path().join("ascii.ply");
+            let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                          property float y\nproperty float z\nend_header\n";
+            std::fs::write(&path, [header, vertices].concat()).unwrap();
+            let iterator = PlyIterator::from_file(&path, BATCH_SIZE).unwrap();
+            let error = iterator.error();
+            (iterator.count(), error)
+        };
+        let (num_batches, error) = read("1 2 3\n4 5 6\n7 8 9\n");
+        assert_eq!(num_batches, 2);
+        assert!(error.check().is_ok());
+        // Fewer vertices than the header promises, and a token that is not a number.
+        for vertices in &["1 2 3\n4 5 6\n", "1 2 3\n4 5 6\n7 eight 9\n"] {
+            let (num_batches, error) = read(vertices);
+            assert_eq!(num_batches, 1);
+            assert!(error.check().is_err());
+        }
+    }
+
+    #[test]
+    fn test_xyz_f64_intensity_u64_be() {
+        let tmp_dir = TempDir::new("test_xyz_f64_intensity_u64_be").unwrap();
+        let path = tmp_dir.path().join("be.ply");
+        let mut content = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+                            property double x\nproperty double y\nproperty double z\n\
+                            property uint64 intensity\nend_header\n"
+            .to_vec();
+        for i in 0..3 {
+            let x: f64 = i.into();
+            for v in &[x, 2. * x, -1.] {
+                content.extend_from_slice(&v.to_be_bytes());
+            }
+            content.extend_from_slice(&(u64::max_value() - i as u64).to_be_bytes());
+        }
+        std::fs::write(&path, content).unwrap();
+        let batches = batches_from_file(&path);
+        assert_eq!(2, batches.len());
+        assert_eq!(batches[1].position[0], Point3::new(2., 4., -1.));
+        let intensity: &Vec<u64> = batches[1].get_attribute_vec("intensity").unwrap();
+        assert_eq!(intensity[0], u64::max_value() - 2);
+    }
+
+    #[test]
+    fn test_xyz_f64_label_u16_le() {
+        let tmp_dir = TempDir::new("test_xyz_f64_label_u16_le").unwrap();
+        let path = tmp_dir.path().join("le.ply");
+        let mut content = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\n\
+                            property double x\nproperty double y\nproperty double z\n\
+                            property ushort label\nend_header\n"
+            .to_vec();
+        for i in 0..3u16 {
+            let x: f64 = i.into();
+            for v in &[x, 0., 0.] {
+                content.extend_from_slice(&v.to_le_bytes());
+            }
+            content.extend_from_slice(&(1000 + i).to_le_bytes());
+        }
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(
+            PlyIterator::from_file(&path, BATCH_SIZE)
+                .unwrap()
+                .attributes(),
+            vec!["label"]
+        );
+        let batches = batches_from_file(&path);
+        let label: &Vec<u16> = batches[1].get_attribute_vec("label").unwrap();
+        assert_eq!(label[0], 1002);
+    }
+
     #[test]
     fn test_ply_read_write() {
         let tmp_dir = TempDir::new("test_ply_read_write").unwrap();
@@ -0,0 +1,497 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reader for ASCII point formats like PTS, XYZ, TXT or CSV, optionally gzip compressed.
+
+use crate::errors::*;
+use crate::read_write::{InputSource, StreamError};
+use crate::{AttributeData, NumberOfPoints, PointsBatch};
+use nalgebra::{Point3, Vector3};
+use num_integer::div_ceil;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Lines};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The meaning of a column in an ASCII point file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsciiColumn {
+    X,
+    Y,
+    Z,
+    Intensity,
+    Red,
+    Green,
+    Blue,
+    Class,
+    /// A u16 label id, e.g. the class of a semantic segmentation.
+    Label,
+    /// A column that is not imported.
+    Skip,
+}
+
+/// The column layout of an ASCII point file, parsed from a spec like "x,y,z,i,r,g,b,class".
+/// Columns to be ignored are named "_". Columns beyond the spec are ignored as well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsciiColumns(Vec<AsciiColumn>);
+
+impl Default for AsciiColumns {
+    /// The layout of PTS files: position, intensity and color.
+    fn default() -> Self {
+        use AsciiColumn::*;
+        AsciiColumns(vec![X, Y, Z, Intensity, Red, Green, Blue])
+    }
+}
+
+impl FromStr for AsciiColumns {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|c| match c.trim() {
+                "x" => Ok(AsciiColumn::X),
+                "y" => Ok(AsciiColumn::Y),
+                "z" => Ok(AsciiColumn::Z),
+                "i" | "intensity" => Ok(AsciiColumn::Intensity),
+                "r" | "red" => Ok(AsciiColumn::Red),
+                "g" | "green" => Ok(AsciiColumn::Green),
+                "b" | "blue" => Ok(AsciiColumn::Blue),
+                "class" => Ok(AsciiColumn::Class),
+                "label" => Ok(AsciiColumn::Label),
+                "_" => Ok(AsciiColumn::Skip),
+                other => Err(format!("Unknown column '{}'.", other)),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let has = |column| columns.contains(&column);
+        if !has(AsciiColumn::X) || !has(AsciiColumn::Y) || !has(AsciiColumn::Z) {
+            return Err("Columns must contain 'x', 'y' and 'z'.".to_string());
+        }
+        let num_color_columns = [AsciiColumn::Red, AsciiColumn::Green, AsciiColumn::Blue]
+            .iter()
+            .filter(|c| has(**c))
+            .count();
+        if num_color_columns != 0 && num_color_columns != 3 {
+            return Err("Columns must contain all or none of 'r', 'g' and 'b'.".to_string());
+        }
+        Ok(AsciiColumns(columns))
+    }
+}
+
+impl fmt::Display for AsciiColumns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = self
+            .0
+            .iter()
+            .map(|c| match c {
+                AsciiColumn::X => "x",
+                AsciiColumn::Y => "y",
+                AsciiColumn::Z => "z",
+                AsciiColumn::Intensity => "i",
+                AsciiColumn::Red => "r",
+                AsciiColumn::Green => "g",
+                AsciiColumn::Blue => "b",
+                AsciiColumn::Class => "class",
+                AsciiColumn::Label => "label",
+                AsciiColumn::Skip => "_",
+            })
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl AsciiColumns {
+    fn has(&self, column: AsciiColumn) -> bool {
+        self.0.contains(&column)
+    }
+
+    /// Whether points read with these columns have colors.
+    fn has_color(&self) -> bool {
+        self.has(AsciiColumn::Red) || self.has(AsciiColumn::Green) || self.has(AsciiColumn::Blue)
+    }
+
+    /// The number of fields a line needs to have a value for every imported column.
+    fn num_required_fields(&self) -> usize {
+        self.0
+            .iter()
+            .rposition(|c| *c != AsciiColumn::Skip)
+            .map_or(0, |i| i + 1)
+    }
+
+    /// The names of the attributes of the batches read with these columns.
+    pub fn attributes(&self) -> Vec<&'static str> {
+        let mut attributes = Vec::new();
+        if self.has_color() {
+            attributes.push("color");
+        }
+        if self.has(AsciiColumn::Intensity) {
+            attributes.push("intensity");
+        }
+        if self.has(AsciiColumn::Class) {
+            attributes.push("class");
+        }
+        if self.has(AsciiColumn::Label) {
+            attributes.push("label");
+        }
+        attributes
+    }
+}
+
+/// Splits a line into its fields. Fields can be separated by whitespace, commas or semicolons.
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|f| !f.is_empty())
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#') || line.starts_with("//")
+}
+
+/// A line that only holds a single integer is the point count of a PTS file.
+fn parse_point_count(line: &str) -> Option<usize> {
+    let mut f = fields(line);
+    match (f.next(), f.next()) {
+        (Some(count), None) => count.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns whether 'line' looks like a column header rather than like data.
+fn is_header(line: &str) -> bool {
+    fields(line).any(|f| f.parse::<f64>().is_err())
+}
+
+/// The values of one data line.
+struct AsciiPoint {
+    position: Point3<f64>,
+    intensity: f32,
+    color: Vector3<u8>,
+    class: u8,
+    label: u16,
+}
+
+fn parse_line(line: &str, columns: &AsciiColumns) -> std::result::Result<AsciiPoint, String> {
+    let fields: Vec<&str> = fields(line).collect();
+    if fields.len() < columns.num_required_fields() {
+        return Err(format!(
+            "Expected columns {}, but found only {} fields.",
+            columns,
+            fields.len()
+        ));
+    }
+    let mut point = AsciiPoint {
+        position: Point3::origin(),
+        intensity: 0.,
+        color: Vector3::zeros(),
+        class: 0,
+        label: 0,
+    };
+    for (column, field) in columns.0.iter().zip(fields) {
+        if *column == AsciiColumn::Skip {
+            continue;
+        }
+        let value: f64 = field
+            .parse()
+            .map_err(|_| format!("Could not parse '{}' as {:?}.", field, column))?;
+        match column {
+            AsciiColumn::X => point.position.x = value,
+            AsciiColumn::Y => point.position.y = value,
+            AsciiColumn::Z => point.position.z = value,
+            AsciiColumn::Intensity => point.intensity = value as f32,
+            AsciiColumn::Red => point.color.x = value.round().max(0.).min(255.) as u8,
+            AsciiColumn::Green => point.color.y = value.round().max(0.).min(255.) as u8,
+            AsciiColumn::Blue => point.color.z = value.round().max(0.).min(255.) as u8,
+            AsciiColumn::Class => point.class = value as u8,
+            AsciiColumn::Label => point.label = value as u16,
+            AsciiColumn::Skip => unreachable!(),
+        }
+    }
+    Ok(point)
+}
+
+/// Abstraction to read points from ASCII files with a configurable column layout. Comment lines
+/// starting with '#' or '//', a PTS point count and a column header line are skipped. The first
+/// line that cannot be read or parsed ends the iteration, see 'PtsIterator::error'.
+pub struct PtsIterator {
+    lines: Lines<Box<dyn BufRead + Send>>,
+    columns: AsciiColumns,
+    num_total_points: usize,
+    batch_size: usize,
+    point_count: usize,
+    line_number: usize,
+    // A data line that was read while looking for headers.
+    first_line: Option<String>,
+    error: StreamError,
+}
+
+impl PtsIterator {
+    /// Reads PTS files, i.e. position, intensity and color.
+    pub fn from_file(path: impl AsRef<Path>, batch_size: usize) -> Result<Self> {
+        Self::from_file_with_columns(path, AsciiColumns::default(), batch_size)
+    }
+
+    pub fn from_file_with_columns(
+        path: impl AsRef<Path>,
+        columns: AsciiColumns,
+        batch_size: usize,
+    ) -> Result<Self> {
+        Self::from_source(&InputSource::file(path.as_ref()), columns, batch_size)
+    }
+
+    pub fn from_source(
+        source: &InputSource,
+        columns: AsciiColumns,
+        batch_size: usize,
+    ) -> Result<Self> {
+        let mut lines = source.open()?.lines();
+        let mut line_number = 0;
+        let mut num_header_points = None;
+        let mut first_line = None;
+        for line in &mut lines {
+            let line = line?;
+            line_number += 1;
+            if is_comment(&line) {
+                continue;
+            }
+            if num_header_points.is_none() {
+                if let Some(count) = parse_point_count(&line) {
+                    num_header_points = Some(count);
+                    continue;
+                }
+            }
+            if is_header(&line) {
+                continue;
+            }
+            first_line = Some(line);
+            break;
+        }
+
+        // Without a PTS point count, we need to count the data lines up front.
+        let num_total_points = match num_header_points {
+            Some(count) => count,
+            None => {
+                let mut count = 0;
+                for line in source.open()?.lines().skip(line_number) {
+                    if !is_comment(&line?) {
+                        count += 1;
+                    }
+                }
+                count + first_line.iter().count()
+            }
+        };
+
+        Ok(PtsIterator {
+            lines,
+            columns,
+            num_total_points,
+            batch_size,
+            point_count: 0,
+            line_number,
+            first_line,
+            error: StreamError::default(),
+        })
+    }
+
+    /// The error that ended the iteration, if any. It can be checked after the iterator was
+    /// consumed.
+    pub fn error(&self) -> StreamError {
+        self.error.clone()
+    }
+
+    fn next_data_line(&mut self) -> Option<std::io::Result<String>> {
+        if let Some(line) = self.first_line.take() {
+            return Some(Ok(line));
+        }
+        for line in &mut self.lines {
+            self.line_number += 1;
+            match line {
+                Ok(line) if is_comment(&line) => continue,
+                result => return Some(result),
+            }
+        }
+        None
+    }
+}
+
+impl NumberOfPoints for PtsIterator {
+    fn num_points(&self) -> usize {
+        self.num_total_points
+    }
+}
+
+impl Iterator for PtsIterator {
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let num_batches = div_ceil(self.num_total_points, self.batch_size);
+        (num_batches, Some(num_batches))
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        if self.error.is_set() {
+            return None;
+        }
+        let mut position = Vec::with_capacity(self.batch_size);
+        let mut intensity = Vec::new();
+        let mut color = Vec::new();
+        let mut class = Vec::new();
+        let mut label = Vec::new();
+        while position.len() < self.batch_size {
+            let point = match self.next_data_line() {
+                Some(Ok(line)) => parse_line(&line, &self.columns),
+                Some(Err(err)) => Err(format!("Could not read input file: {}", err)),
+                None => break,
+            };
+            let point = match point {
+                Ok(point) => point,
+                Err(msg) => {
+                    self.error
+                        .set(format!("Line {}: {}", self.line_number, msg));
+                    return None;
+                }
+            };
+            position.push(point.position);
+            intensity.push(point.intensity);
+            color.push(point.color);
+            class.push(point.class);
+            label.push(point.label);
+        }
+        if position.is_empty() {
+            return None;
+        }
+        self.point_count += position.len();
+
+        let mut attributes = BTreeMap::new();
+        if self.columns.has(AsciiColumn::Intensity) {
+            attributes.insert("intensity".to_string(), AttributeData::F32(intensity));
+        }
+        if self.columns.has_color() {
+            attributes.insert("color".to_string(), AttributeData::U8Vec3(color));
+        }
+        if self.columns.has(AsciiColumn::Class) {
+            attributes.insert("class".to_string(), AttributeData::U8(class));
+        }
+        if self.columns.has(AsciiColumn::Label) {
+            attributes.insert("label".to_string(), AttributeData::U16(label));
+        }
+        Some(PointsBatch {
+            position,
+            attributes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(
+            "x,y,z,i,r,g,b".parse::<AsciiColumns>().unwrap(),
+            AsciiColumns::default()
+        );
+        assert_eq!(
+            "_,x,y,z,class".parse::<AsciiColumns>().unwrap().to_string(),
+            "_,x,y,z,class"
+        );
+        assert_eq!(
+            "x,y,z,label".parse::<AsciiColumns>().unwrap().attributes(),
+            vec!["label"]
+        );
+        assert!("x,y,i".parse::<AsciiColumns>().is_err());
+        assert!("x,y,z,r,g".parse::<AsciiColumns>().is_err());
+        assert!("x,y,z,alpha".parse::<AsciiColumns>().is_err());
+    }
+
+    #[test]
+    fn test_read_with_header_and_comments() {
+        let tmp_dir = TempDir::new("pts").unwrap();
+        let path = tmp_dir.path().join("points.txt");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "# exported by some tool").unwrap();
+        writeln!(file, "class;x;y;z").unwrap();
+        writeln!(file, "2;1.5;2.5;3.5").unwrap();
+        writeln!(file, "// a comment in between").unwrap();
+        writeln!(file, "6;4;5;6").unwrap();
+        writeln!(file, "7;7;8;9").unwrap();
+        drop(file);
+
+        let columns = "class,x,y,z".parse().unwrap();
+        let iterator = PtsIterator::from_file_with_columns(&path, columns, 2).unwrap();
+        assert_eq!(iterator.num_points(), 3);
+        let batches: Vec<_> = iterator.collect();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].position[0], Point3::new(1.5, 2.5, 3.5));
+        assert_eq!(batches[1].position[0], Point3::new(7., 8., 9.));
+        let class: &Vec<u8> = batches[0].get_attribute_vec("class").unwrap();
+        assert_eq!(class, &vec![2, 6]);
+        assert!(!batches[0].attributes.contains_key("color"));
+    }
+
+    #[test]
+    fn test_read_stops_on_bad_lines() {
+        let tmp_dir = TempDir::new("pts").unwrap();
+        let read = |content: &str| {
+            let path = tmp_dir.path().join("points.xyz");
+            fs::write(&path, content).unwrap();
+            let columns = "x,y,z,i".parse().unwrap();
+            let iterator = PtsIterator::from_file_with_columns(&path, columns, 1).unwrap();
+            let error = iterator.error();
+            let num_batches = iterator.count();
+            (num_batches, error.check())
+        };
+
+        let (num_batches, result) = read("1 2 3 4\n5 6 7 8\n");
+        assert_eq!(num_batches, 2);
+        assert!(result.is_ok());
+
+        // A missing intensity is not read as 0.
+        let (num_batches, result) = read("1 2 3 4\n5 6 7\n9 10 11 12\n");
+        assert_eq!(num_batches, 1);
+        assert!(result.unwrap_err().to_string().contains("Line 2"));
+
+        let (num_batches, result) = read("1 2 3 4\n5 6 seven 8\n");
+        assert_eq!(num_batches, 1);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_read_pts_gzipped() {
+        let tmp_dir = TempDir::new("pts").unwrap();
+        let path = tmp_dir.path().join("points.pts.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        writeln!(encoder, "2").unwrap();
+        writeln!(encoder, "1 2 3 -100 255 128 0").unwrap();
+        writeln!(encoder, "4 5 6 200 0 0 255").unwrap();
+        encoder.finish().unwrap();
+
+        let iterator = PtsIterator::from_file(&path, 10).unwrap();
+        assert_eq!(iterator.num_points(), 2);
+        let batches: Vec<_> = iterator.collect();
+        assert_eq!(batches.len(), 1);
+        let intensity: &Vec<f32> = batches[0].get_attribute_vec("intensity").unwrap();
+        assert_eq!(intensity, &vec![-100., 200.]);
+        let color: &Vec<Vector3<u8>> = batches[0].get_attribute_vec("color").unwrap();
+        assert_eq!(color[0], Vector3::new(255, 128, 0));
+    }
+}
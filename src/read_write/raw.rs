@@ -15,15 +15,17 @@
 use crate::color;
 use crate::errors::*;
 use crate::read_write::{
-    decode, fixpoint_decode, AttributeReader, DataWriter, Encoding, NodeWriter, OpenMode,
-    PositionEncoding, WriteEncoded, WriteLE,
+    decode, fixpoint_decode, read_layout_header_of_file, strip_layout_header, write_layout_header,
+    AttributeReader, DataWriter, Encoding, NodeLayout, NodeWriter, OpenMode, PositionEncoding,
+    WriteEncoded, WriteLE, LAYOUT_HEADER_LEN,
 };
-use crate::{attribute_extension, AttributeData, AttributeDataType, Point, PointsBatch};
+use crate::{attribute_extension, AttributeData, AttributeDataType, Point, PointsBatch, Schema};
 use byteorder::{LittleEndian, ReadBytesExt};
 use nalgebra::{Point3, Vector3};
 use std::collections::{BTreeMap, HashMap};
+use std::fs;
 use std::io::{self, BufReader, ErrorKind, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct RawNodeReader {
     xyz_reader: BufReader<Box<dyn Read + Send>>,
@@ -35,7 +37,7 @@ impl RawNodeReader {
     pub fn read(&mut self) -> io::Result<Point> {
         let mut point = Point {
             position: Point3::origin(),
-            color: color::RED.to_u8(), // is overwritten
+            color: None,
             intensity: None,
         };
 
@@ -112,9 +114,12 @@ impl RawNodeReader {
         }
 
         if let Some(cr) = self.attribute_readers.get_mut("color") {
-            point.color.red = cr.reader.read_u8()?;
-            point.color.green = cr.reader.read_u8()?;
-            point.color.blue = cr.reader.read_u8()?;
+            point.color = Some(color::Color {
+                red: cr.reader.read_u8()?,
+                green: cr.reader.read_u8()?,
+                blue: cr.reader.read_u8()?,
+                alpha: 255,
+            });
         }
 
         if let Some(ir) = self.attribute_readers.get_mut("intensity") {
@@ -306,6 +311,51 @@ impl RawNodeReader {
                             .attributes
                             .insert(key.to_owned(), AttributeData::U8Vec3(attr));
                     }
+                    AttributeDataType::U32Vec3 => {
+                        let mut attr = Vec::with_capacity(num_points);
+                        let mut buffer = vec![0; 3 * num_points];
+                        reader.read_u32_into::<LittleEndian>(&mut buffer)?;
+                        for i in 0..num_points {
+                            attr.push(Vector3::new(
+                                buffer[3 * i],
+                                buffer[3 * i + 1],
+                                buffer[3 * i + 2],
+                            ));
+                        }
+                        batch
+                            .attributes
+                            .insert(key.to_owned(), AttributeData::U32Vec3(attr));
+                    }
+                    AttributeDataType::U64Vec3 => {
+                        let mut attr = Vec::with_capacity(num_points);
+                        let mut buffer = vec![0; 3 * num_points];
+                        reader.read_u64_into::<LittleEndian>(&mut buffer)?;
+                        for i in 0..num_points {
+                            attr.push(Vector3::new(
+                                buffer[3 * i],
+                                buffer[3 * i + 1],
+                                buffer[3 * i + 2],
+                            ));
+                        }
+                        batch
+                            .attributes
+                            .insert(key.to_owned(), AttributeData::U64Vec3(attr));
+                    }
+                    AttributeDataType::I32Vec3 => {
+                        let mut attr = Vec::with_capacity(num_points);
+                        let mut buffer = vec![0; 3 * num_points];
+                        reader.read_i32_into::<LittleEndian>(&mut buffer)?;
+                        for i in 0..num_points {
+                            attr.push(Vector3::new(
+                                buffer[3 * i],
+                                buffer[3 * i + 1],
+                                buffer[3 * i + 2],
+                            ));
+                        }
+                        batch
+                            .attributes
+                            .insert(key.to_owned(), AttributeData::I32Vec3(attr));
+                    }
                     AttributeDataType::F64Vec3 => {
                         let mut attr = Vec::with_capacity(num_points);
                         let mut buffer = vec![0.0; 3 * num_points];
@@ -343,12 +393,43 @@ impl RawNodeReader {
         }
     }
 
+    /// Skips the next 'num_points' points without decoding them. The byte offset of each
+    /// attribute is computed from its data type, so only the bytes in between are read.
+    pub fn skip(&mut self, num_points: usize) -> io::Result<()> {
+        discard(
+            &mut self.xyz_reader,
+            num_points * self.encoding.bytes_per_position(),
+        )?;
+        for AttributeReader { data_type, reader } in self.attribute_readers.values_mut() {
+            discard(reader, num_points * data_type.size_of())?;
+        }
+        Ok(())
+    }
+
+    /// Checks the layout headers of the readers, if the data has them, against 'encoding' and
+    /// the data types of the attributes.
     pub fn new(
         xyz_reader: Box<dyn Read + Send>,
         attribute_readers: HashMap<String, AttributeReader>,
         encoding: Encoding,
     ) -> Result<Self> {
-        let xyz_reader = BufReader::new(xyz_reader);
+        let xyz_reader = BufReader::new(
+            strip_layout_header(xyz_reader, &NodeLayout::for_position(&encoding))
+                .chain_err(|| "Could not read position")?,
+        );
+        let attribute_readers = attribute_readers
+            .into_iter()
+            .map(|(attribute, AttributeReader { data_type, reader })| {
+                // Keeps what the BufReader might have buffered already.
+                let buffered = reader.buffer().to_vec();
+                let reader: Box<dyn Read + Send> =
+                    Box::new(io::Cursor::new(buffered).chain(reader.into_inner()));
+                let reader = strip_layout_header(reader, &NodeLayout::Attribute(data_type))
+                    .chain_err(|| format!("Could not read {}", attribute))?;
+                let reader = BufReader::new(reader);
+                Ok((attribute, AttributeReader { data_type, reader }))
+            })
+            .collect::<Result<_>>()?;
 
         Ok(Self {
             xyz_reader,
@@ -358,9 +439,34 @@ impl RawNodeReader {
     }
 }
 
+/// Removes the file at 'path' if there is one.
+fn remove_stale_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Reads and drops 'num_bytes' bytes, since the readers of a data provider can't seek.
+fn discard(reader: &mut impl Read, num_bytes: usize) -> io::Result<()> {
+    let num_discarded = io::copy(&mut reader.take(num_bytes as u64), &mut io::sink())?;
+    if num_discarded != num_bytes as u64 {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Could only skip {} of {} bytes", num_discarded, num_bytes),
+        ));
+    }
+    Ok(())
+}
+
 pub struct RawNodeWriter {
     xyz_writer: DataWriter,
+    // The length of the layout header of the position file, which is 0 for files from before
+    // there were headers that are appended to.
+    xyz_header_len: u64,
     attribute_writers: Vec<DataWriter>,
+    // Set by the first batch, all following batches must have the same attributes.
+    schema: Option<Schema>,
     stem: PathBuf,
     encoding: Encoding,
     open_mode: OpenMode,
@@ -372,15 +478,30 @@ impl NodeWriter<PointsBatch> for RawNodeWriter {
     }
 
     fn write(&mut self, p: &PointsBatch) -> io::Result<()> {
+        let schema = self.schema.get_or_insert_with(|| Schema::from_batch(p));
+        schema
+            .assert_matches(p)
+            .map_err(|msg| io::Error::new(ErrorKind::InvalidInput, msg))?;
+        // Otherwise, files with nothing but a header would be left behind.
+        if p.position.is_empty() {
+            // The attribute files are only opened for the first points, so the old ones of a
+            // rewritten node have to go here, like its position file does when it stays empty.
+            if self.open_mode == OpenMode::Truncate && self.attribute_writers.is_empty() {
+                for name in p.attributes.keys() {
+                    remove_stale_file(&self.stem.with_extension(attribute_extension(name)))?;
+                }
+            }
+            return Ok(());
+        }
+
+        self.start_position_file()?;
         p.position
             .write_encoded(&self.encoding, &mut self.xyz_writer)?;
 
         if self.attribute_writers.is_empty() {
-            for name in p.attributes.keys() {
-                self.attribute_writers.push(DataWriter::new(
-                    &self.stem.with_extension(attribute_extension(&name)),
-                    self.open_mode,
-                )?)
+            for (name, data) in &p.attributes {
+                self.attribute_writers
+                    .push(self.open_attribute_file(name, NodeLayout::Attribute(data.data_type()))?);
             }
         }
 
@@ -398,24 +519,28 @@ impl NodeWriter<Point> for RawNodeWriter {
     }
 
     fn write(&mut self, p: &Point) -> io::Result<()> {
+        self.start_position_file()?;
         p.position
             .write_encoded(&self.encoding, &mut self.xyz_writer)?;
 
         if self.attribute_writers.is_empty() {
-            self.attribute_writers.push(DataWriter::new(
-                &self.stem.with_extension(attribute_extension("color")),
-                self.open_mode,
-            )?);
-            if p.intensity.is_some() {
-                self.attribute_writers.push(DataWriter::new(
-                    &self.stem.with_extension(attribute_extension("intensity")),
-                    self.open_mode,
-                )?);
+            let present = [
+                ("color", AttributeDataType::U8Vec3, p.color.is_some()),
+                ("intensity", AttributeDataType::F32, p.intensity.is_some()),
+            ];
+            for (attribute, data_type, _) in present.iter().filter(|(_, _, is_present)| *is_present)
+            {
+                self.attribute_writers
+                    .push(self.open_attribute_file(attribute, NodeLayout::Attribute(*data_type))?);
             }
         }
-        p.color.write_le(&mut self.attribute_writers[0])?;
+        // The writers were created in the order of the attributes that the first point has.
+        let mut writers = self.attribute_writers.iter_mut();
+        if let Some(color) = &p.color {
+            color.write_le(writers.next().unwrap())?;
+        }
         if let Some(i) = p.intensity {
-            i.write_le(&mut self.attribute_writers[1])?;
+            i.write_le(writers.next().unwrap())?;
         }
 
         Ok(())
@@ -425,15 +550,22 @@ impl NodeWriter<Point> for RawNodeWriter {
 impl RawNodeWriter {
     pub fn new(path: impl Into<PathBuf>, encoding: Encoding, open_mode: OpenMode) -> Self {
         let stem: PathBuf = path.into();
-        let xyz_writer = DataWriter::new(
-            &stem.with_extension(attribute_extension("position")),
-            open_mode,
-        )
-        .unwrap();
+        let xyz_path = stem.with_extension(attribute_extension("position"));
+        let xyz_writer = DataWriter::new(&xyz_path, open_mode).unwrap();
+        let xyz_header_len = if xyz_writer.bytes_written() > 0 {
+            match read_layout_header_of_file(&xyz_path) {
+                Ok(Some(_)) => LAYOUT_HEADER_LEN as u64,
+                _ => 0,
+            }
+        } else {
+            0
+        };
         let attribute_writers = Vec::new();
         Self {
             xyz_writer,
+            xyz_header_len,
             attribute_writers,
+            schema: None,
             stem,
             encoding,
             open_mode,
@@ -441,10 +573,126 @@ impl RawNodeWriter {
     }
 
     pub fn num_written(&self) -> i64 {
-        let bytes_per_coordinate = match &self.encoding {
-            Encoding::Plain => std::mem::size_of::<f64>(),
-            Encoding::ScaledToCube(_, _, pos_enc) => pos_enc.bytes_per_coordinate(),
-        } as i64;
-        self.xyz_writer.bytes_written() as i64 / bytes_per_coordinate / 3
+        ((self.xyz_writer.bytes_written() - self.xyz_header_len)
+            / self.encoding.bytes_per_position() as u64) as i64
+    }
+
+    /// Writes the layout header if the position file is new.
+    fn start_position_file(&mut self) -> io::Result<()> {
+        if self.xyz_writer.bytes_written() == 0 {
+            write_layout_header(
+                &mut self.xyz_writer,
+                &NodeLayout::for_position(&self.encoding),
+            )?;
+            self.xyz_header_len = LAYOUT_HEADER_LEN as u64;
+        }
+        Ok(())
+    }
+
+    /// Opens the file of an attribute, writing the layout header if it is new. Files that are
+    /// appended to keep the layout they were written with.
+    fn open_attribute_file(&self, attribute: &str, layout: NodeLayout) -> io::Result<DataWriter> {
+        let mut writer = DataWriter::new(
+            &self.stem.with_extension(attribute_extension(attribute)),
+            self.open_mode,
+        )?;
+        if writer.bytes_written() == 0 {
+            write_layout_header(&mut writer, &layout)?;
+        }
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_wide_integer_vec3_roundtrip() {
+        let batch = PointsBatch {
+            position: vec![Point3::new(1., 2., 3.), Point3::new(4., 5., 6.)],
+            attributes: vec![
+                (
+                    "gps_time".to_string(),
+                    AttributeData::U64Vec3(vec![
+                        Vector3::new(2100, 345_600, u64::max_value()),
+                        Vector3::new(0, 1, 2),
+                    ]),
+                ),
+                (
+                    "offset".to_string(),
+                    AttributeData::I32Vec3(vec![Vector3::new(-1, 0, 1), Vector3::new(-7, 8, -9)]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let tmp_dir = TempDir::new("raw").unwrap();
+        let stem = tmp_dir.path().join("r");
+        {
+            let mut writer = RawNodeWriter::new(&stem, Encoding::Plain, OpenMode::Truncate);
+            NodeWriter::<PointsBatch>::write(&mut writer, &batch).unwrap();
+        }
+
+        let open = |attribute: &str| -> BufReader<Box<dyn Read + Send>> {
+            let file = File::open(stem.with_extension(attribute_extension(attribute))).unwrap();
+            BufReader::new(Box::new(file))
+        };
+        let attribute_readers = vec![
+            (
+                "gps_time".to_string(),
+                AttributeReader {
+                    data_type: AttributeDataType::U64Vec3,
+                    reader: open("gps_time"),
+                },
+            ),
+            (
+                "offset".to_string(),
+                AttributeReader {
+                    data_type: AttributeDataType::I32Vec3,
+                    reader: open("offset"),
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let xyz = File::open(stem.with_extension("xyz")).unwrap();
+        let mut reader =
+            RawNodeReader::new(Box::new(xyz), attribute_readers, Encoding::Plain).unwrap();
+        let read = reader.read_batch(2).unwrap();
+
+        assert_eq!(read.position, batch.position);
+        assert_eq!(
+            read.get_attribute_vec::<Vector3<u64>>("gps_time"),
+            batch.get_attribute_vec::<Vector3<u64>>("gps_time")
+        );
+        assert_eq!(
+            read.get_attribute_vec::<Vector3<i32>>("offset"),
+            batch.get_attribute_vec::<Vector3<i32>>("offset")
+        );
+    }
+
+    #[test]
+    fn test_rewriting_without_points_removes_node_files() {
+        let batch = |num_points: usize| PointsBatch {
+            position: vec![Point3::new(1., 2., 3.); num_points],
+            attributes: vec![(
+                "intensity".to_string(),
+                AttributeData::F32(vec![1.; num_points]),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let tmp_dir = TempDir::new("raw").unwrap();
+        let stem = tmp_dir.path().join("r");
+        for num_points in &[2, 0] {
+            let mut writer = RawNodeWriter::new(&stem, Encoding::Plain, OpenMode::Truncate);
+            NodeWriter::<PointsBatch>::write(&mut writer, &batch(*num_points)).unwrap();
+        }
+        let exists = |attribute| stem.with_extension(attribute_extension(attribute)).exists();
+        assert!(!exists("position"));
+        assert!(!exists("intensity"));
     }
 }
@@ -1,6 +1,9 @@
 use crate::geometry::Aabb;
 use crate::math::{FromPoint3, EARTH_RADIUS_MAX_M, EARTH_RADIUS_MIN_M};
-use crate::read_write::{Encoding, NodeWriter, OpenMode};
+use crate::read_write::{
+    apply_non_finite_policy, has_non_finite, Encoding, NodeWriter, NonFinitePolicy, NonFiniteStats,
+    OpenMode,
+};
 use crate::s2_cells::{S2CellMeta, S2Meta};
 use crate::{AttributeData, AttributeDataType, PointsBatch};
 use fnv::FnvHashMap;
@@ -26,6 +29,8 @@ pub struct S2Splitter<W> {
     encoding: Encoding,
     open_mode: OpenMode,
     stem: PathBuf,
+    non_finite_policy: NonFinitePolicy,
+    non_finite_stats: NonFiniteStats,
 }
 
 impl<W> S2Splitter<W> {
@@ -45,8 +50,21 @@ impl<W> S2Splitter<W> {
             encoding,
             open_mode,
             stem: path.into(),
+            non_finite_policy: NonFinitePolicy::Drop,
+            non_finite_stats: NonFiniteStats::default(),
         }
     }
+
+    /// Sets how non-finite positions and attribute values are handled, the default is to drop
+    /// the affected points. S2 cells can not be computed for non-finite positions.
+    pub fn with_non_finite_policy(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite_policy = policy;
+        self
+    }
+
+    pub fn non_finite_stats(&self) -> NonFiniteStats {
+        self.non_finite_stats
+    }
 }
 
 impl<W> NodeWriter<PointsBatch> for S2Splitter<W>
@@ -58,6 +76,17 @@ where
     }
 
     fn write(&mut self, points_batch: &PointsBatch) -> Result<()> {
+        // Only copy the batch if something has to be changed.
+        let finite_batch;
+        let points_batch = if has_non_finite(points_batch) {
+            let mut batch = points_batch.clone();
+            self.non_finite_stats += apply_non_finite_policy(&mut batch, self.non_finite_policy)
+                .map_err(|msg| Error::new(ErrorKind::InvalidInput, msg))?;
+            finite_batch = batch;
+            &finite_batch
+        } else {
+            points_batch
+        };
         self.check_attributes(points_batch)?;
         let mut batches_by_s2_cell = HashMap::new();
         for (i, pos) in points_batch.position.iter().enumerate() {
@@ -100,6 +129,9 @@ where
                         (F32(in_vec), F32(out_vec)) => out_vec.push(in_vec[i]),
                         (F64(in_vec), F64(out_vec)) => out_vec.push(in_vec[i]),
                         (U8Vec3(in_vec), U8Vec3(out_vec)) => out_vec.push(in_vec[i]),
+                        (U32Vec3(in_vec), U32Vec3(out_vec)) => out_vec.push(in_vec[i]),
+                        (U64Vec3(in_vec), U64Vec3(out_vec)) => out_vec.push(in_vec[i]),
+                        (I32Vec3(in_vec), I32Vec3(out_vec)) => out_vec.push(in_vec[i]),
                         (F64Vec3(in_vec), F64Vec3(out_vec)) => out_vec.push(in_vec[i]),
                         _ => panic!("Input data type unequal output data type."),
                     })
@@ -164,6 +196,7 @@ where
     }
 
     pub fn get_meta(self) -> Option<S2Meta> {
+        self.non_finite_stats.report();
         let meta = S2Meta::new(
             self.cell_stats,
             self.attributes_seen.into_iter().collect(),
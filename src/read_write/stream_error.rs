@@ -0,0 +1,40 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use std::sync::{Arc, Mutex};
+
+/// The error that ended an iterator over batches early. Iterators cannot return it themselves,
+/// so it is shared with whoever consumes them, to be checked once the iterator is exhausted.
+#[derive(Clone, Debug, Default)]
+pub struct StreamError(Arc<Mutex<Option<String>>>);
+
+impl StreamError {
+    /// Records 'msg', unless an earlier error was recorded already.
+    pub fn set(&self, msg: String) {
+        self.0.lock().unwrap().get_or_insert(msg);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// Fails with the recorded error, if any.
+    pub fn check(&self) -> Result<()> {
+        match self.0.lock().unwrap().as_ref() {
+            Some(msg) => Err(ErrorKind::InvalidInput(msg.clone()).into()),
+            None => Ok(()),
+        }
+    }
+}
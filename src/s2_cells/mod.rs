@@ -1,7 +1,8 @@
+use crate::attributes::AttributeAliases;
 use crate::data_provider::DataProvider;
 use crate::errors::*;
-use crate::geometry::Aabb;
-use crate::iterator::{PointCloud, PointLocation};
+use crate::geometry::{cell_id_from_token, Aabb};
+use crate::iterator::{PointCloud, PointLocation, PointQuery, QueryPlan, QueryStrategy};
 use crate::math::{ConvexPolyhedron, FromPoint3};
 use crate::proto;
 use crate::read_write::{Encoding, NodeIterator};
@@ -10,14 +11,22 @@ use fnv::FnvHashMap;
 use s2::cell::Cell;
 use s2::cellid::CellID;
 use s2::cellunion::CellUnion;
-use s2::region::Region;
+use s2::region::{Region, RegionCoverer};
 use std::collections::HashMap;
 use std::iter;
 
+/// Point clouds with at most this many cells are scanned instead of looked up in a covering.
+const MAX_CELLS_FOR_SCAN: usize = 64;
+/// The maximum number of cells in the covering of a query.
+const MAX_COVERING_CELLS: usize = 16;
+
 pub struct S2Cells {
     data_provider: Box<dyn DataProvider>,
     cells: FnvHashMap<CellID, Cell>,
+    // The ids of 'cells', sorted, so that the cells within a covering cell are a range.
+    sorted_cell_ids: Vec<CellID>,
     meta: S2Meta,
+    attribute_aliases: AttributeAliases,
 }
 
 #[derive(Copy, Clone)]
@@ -29,11 +38,21 @@ impl S2CellMeta {
     pub fn to_proto(self, cell_id: u64) -> proto::S2Cell {
         let mut meta = proto::S2Cell::new();
         meta.set_id(cell_id);
+        meta.set_token(CellID(cell_id).to_token());
         meta.set_num_points(self.num_points);
         meta
     }
 }
 
+/// The cell id of a cell in the meta, from its token if it has no id.
+pub fn cell_id_from_proto(cell: &proto::S2Cell) -> Result<CellID> {
+    if cell.id == 0 && !cell.token.is_empty() {
+        cell_id_from_token(&cell.token)
+    } else {
+        Ok(CellID(cell.id))
+    }
+}
+
 pub struct S2Meta {
     cells: FnvHashMap<CellID, S2CellMeta>,
     attribute_data_types: HashMap<String, AttributeDataType>,
@@ -125,15 +144,14 @@ impl S2Meta {
         let s2_meta_proto = meta_proto.get_s2();
         // cells, num_points
         let mut cells = FnvHashMap::default();
-        s2_meta_proto.get_cells().iter().for_each(|cell| {
-            let cell_id = CellID(cell.id as u64);
+        for cell in s2_meta_proto.get_cells() {
             cells.insert(
-                cell_id,
+                cell_id_from_proto(cell)?,
                 S2CellMeta {
                     num_points: cell.num_points,
                 },
             );
-        });
+        }
 
         let mut attribute_data_types = HashMap::default();
         for attr in s2_meta_proto.attributes.iter() {
@@ -158,20 +176,30 @@ impl PointCloud for S2Cells {
     type Id = CellID;
 
     fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id> {
-        match location {
-            PointLocation::AllPoints => self.cells.keys().cloned().collect(),
-            PointLocation::Aabb(aabb) => self.cells_in_convex_polyhedron(aabb),
-            PointLocation::Obb(obb) => self.cells_in_convex_polyhedron(obb),
-            PointLocation::Frustum(frustum) => self.cells_in_convex_polyhedron(frustum),
-            PointLocation::S2Cells(cell_union) => self.cells_intersecting_region(cell_union),
-            PointLocation::WebMercatorRect(wmr) => self.cells_in_convex_polyhedron(wmr),
-        }
+        self.plan_location(location).node_ids
+    }
+
+    fn plan_query(&self, query: &PointQuery) -> QueryPlan<Self::Id> {
+        self.plan_location(&query.location)
+    }
+
+    fn attribute_aliases(&self) -> Option<&AttributeAliases> {
+        Some(&self.attribute_aliases)
+    }
+
+    fn stored_attribute_type(&self, attribute: &str) -> Result<Option<AttributeDataType>> {
+        let attribute = self.attribute_aliases.resolve(attribute);
+        Ok(self.meta.attribute_data_types().get(attribute).copied())
     }
 
     fn encoding_for_node(&self, _: Self::Id) -> Encoding {
         Encoding::Plain
     }
 
+    fn num_points_in_node(&self, node_id: Self::Id) -> usize {
+        self.meta.cells[&node_id].num_points as usize
+    }
+
     fn points_in_node(
         &self,
         attributes: &[&str],
@@ -190,6 +218,18 @@ impl PointCloud for S2Cells {
         Ok(node_iterator)
     }
 
+    fn prefetch(&self, node_ids: &[Self::Id], attributes: &[&str]) {
+        let files: Vec<&str> = std::iter::once("position")
+            .chain(
+                attributes
+                    .iter()
+                    .map(|attribute| self.attribute_aliases.resolve(attribute)),
+            )
+            .collect();
+        let node_names: Vec<String> = node_ids.iter().map(CellID::to_string).collect();
+        self.data_provider.prefetch(&node_names, &files);
+    }
+
     fn bounding_box(&self) -> &Aabb {
         &self.meta.bounding_box
     }
@@ -198,25 +238,53 @@ impl PointCloud for S2Cells {
 impl S2Cells {
     pub fn from_data_provider(data_provider: Box<dyn DataProvider>) -> Result<Self> {
         let meta_proto = data_provider.meta_proto()?;
+        let attribute_aliases = AttributeAliases::from_meta_proto(&meta_proto);
         let meta = S2Meta::from_proto(meta_proto)?;
         let cells: FnvHashMap<_, _> = meta
             .get_cells()
             .keys()
             .map(|id| (*id, Cell::from(id)))
             .collect();
+        let mut sorted_cell_ids: Vec<CellID> = cells.keys().copied().collect();
+        sorted_cell_ids.sort();
         Ok(S2Cells {
             data_provider,
             cells,
+            sorted_cell_ids,
             meta,
+            attribute_aliases,
         })
     }
 
     pub fn to_meta_proto(&self) -> proto::Meta {
-        self.meta.to_proto()
+        let mut meta = self.meta.to_proto();
+        meta.set_attribute_aliases(::protobuf::RepeatedField::from_vec(
+            self.attribute_aliases.to_proto(),
+        ));
+        meta
+    }
+
+    fn plan_location(&self, location: &PointLocation) -> QueryPlan<CellID> {
+        match location {
+            PointLocation::AllPoints => QueryPlan {
+                strategy: QueryStrategy::AllNodes,
+                node_ids: self.sorted_cell_ids.clone(),
+                num_nodes: self.cells.len(),
+                num_tested_nodes: 0,
+                approximated_regions: Vec::new(),
+            },
+            PointLocation::Aabb(aabb) => self.cells_in_convex_polyhedron(aabb),
+            PointLocation::Obb(obb) => self.cells_in_convex_polyhedron(obb),
+            PointLocation::Frustum(frustum) => self.cells_in_convex_polyhedron(frustum),
+            PointLocation::S2Cells(cell_union) => {
+                self.cells_intersecting_region(cell_union, || cell_union.clone())
+            }
+            PointLocation::WebMercatorRect(wmr) => self.cells_in_convex_polyhedron(wmr),
+        }
     }
 
     /// Returns all cells that intersect this convex polyhedron
-    fn cells_in_convex_polyhedron<T>(&self, poly: &T) -> Vec<CellID>
+    fn cells_in_convex_polyhedron<T>(&self, poly: &T) -> QueryPlan<CellID>
     where
         T: ConvexPolyhedron,
     {
@@ -229,14 +297,63 @@ impl S2Cells {
         let mut cell_union = CellUnion(point_cells);
         cell_union.normalize();
         let rect = cell_union.rect_bound();
-        self.cells_intersecting_region(&rect)
+        let coverer = RegionCoverer {
+            min_level: 0,
+            max_level: 30,
+            level_mod: 1,
+            max_cells: MAX_COVERING_CELLS,
+        };
+        self.cells_intersecting_region(&rect, || coverer.covering(&rect))
     }
 
-    fn cells_intersecting_region(&self, region: &impl Region) -> Vec<CellID> {
-        self.cells
-            .values()
-            .filter(|cell| region.intersects_cell(cell))
-            .map(|cell| cell.id)
-            .collect()
+    /// Returns all cells that intersect 'region'. With many cells, only the ones within or
+    /// containing a cell of the covering of the region are tested.
+    fn cells_intersecting_region(
+        &self,
+        region: &impl Region,
+        covering: impl FnOnce() -> CellUnion,
+    ) -> QueryPlan<CellID> {
+        let num_nodes = self.cells.len();
+        if num_nodes <= MAX_CELLS_FOR_SCAN {
+            return QueryPlan {
+                strategy: QueryStrategy::Scan,
+                node_ids: self
+                    .cells
+                    .values()
+                    .filter(|cell| region.intersects_cell(cell))
+                    .map(|cell| cell.id)
+                    .collect(),
+                num_nodes,
+                num_tested_nodes: num_nodes,
+                approximated_regions: Vec::new(),
+            };
+        }
+        let mut candidates = Vec::new();
+        for covering_cell_id in covering().0 {
+            let ids = &self.sorted_cell_ids;
+            let begin = ids
+                .binary_search(&covering_cell_id.range_min())
+                .unwrap_or_else(|index| index);
+            let end = ids
+                .binary_search(&covering_cell_id.range_max())
+                .map_or_else(|index| index, |index| index + 1);
+            candidates.extend_from_slice(&ids[begin..end]);
+            candidates.extend(
+                (0..covering_cell_id.level())
+                    .map(|level| covering_cell_id.parent(level))
+                    .filter(|cell_id| self.cells.contains_key(cell_id)),
+            );
+        }
+        candidates.sort();
+        candidates.dedup();
+        let num_tested_nodes = candidates.len();
+        candidates.retain(|cell_id| region.intersects_cell(&self.cells[cell_id]));
+        QueryPlan {
+            strategy: QueryStrategy::Covering,
+            node_ids: candidates,
+            num_nodes,
+            num_tested_nodes,
+            approximated_regions: Vec::new(),
+        }
     }
 }
@@ -0,0 +1,318 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Joins points with 2D polygons, e.g. parcels or zones from a GeoJSON file, by annotating each
+//! point with the polygon that contains it.
+
+use crate::errors::*;
+use crate::{AttributeData, PointsBatch};
+use nalgebra::{Point2, Point3};
+use nav_types::{ECEF, WGS84};
+use rstar::{RTree, RTreeObject, AABB};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// The I64 attribute that the join adds to batches: the index of the polygon containing the
+/// point, -1 for points outside of all polygons.
+pub const POLYGON_ATTRIBUTE: &str = "polygon";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolygonCoordinates {
+    /// Longitude and latitude in degrees, as in GeoJSON. The points are in ECEF.
+    Wgs84,
+    /// The x and y coordinates of the points themselves, for point clouds in a local frame.
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    /// The exterior ring and the holes. A point is inside if it is inside an odd number of them,
+    /// which also works for multi-polygons.
+    rings: Vec<Vec<Point2<f64>>>,
+    pub properties: Map<String, Value>,
+}
+
+impl Polygon {
+    pub fn new(rings: Vec<Vec<Point2<f64>>>, properties: Map<String, Value>) -> Self {
+        Polygon { rings, properties }
+    }
+
+    pub fn contains(&self, point: &Point2<f64>) -> bool {
+        let mut inside = false;
+        for ring in &self.rings {
+            let mut previous = match ring.last() {
+                Some(previous) => previous,
+                None => continue,
+            };
+            for current in ring {
+                if (current.y > point.y) != (previous.y > point.y)
+                    && point.x
+                        < (previous.x - current.x) * (point.y - current.y)
+                            / (previous.y - current.y)
+                            + current.x
+                {
+                    inside = !inside;
+                }
+                previous = current;
+            }
+        }
+        inside
+    }
+
+    fn envelope(&self) -> AABB<[f64; 2]> {
+        let corners: Vec<[f64; 2]> = self.rings.iter().flatten().map(|p| [p.x, p.y]).collect();
+        AABB::from_points(&corners)
+    }
+}
+
+/// A polygon in the R-tree, referring to the polygons of the join by index.
+struct IndexedEnvelope {
+    index: usize,
+    envelope: AABB<[f64; 2]>,
+}
+
+impl RTreeObject for IndexedEnvelope {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+pub struct SpatialJoin {
+    polygons: Vec<Polygon>,
+    rtree: RTree<IndexedEnvelope>,
+    coordinates: PolygonCoordinates,
+}
+
+fn invalid_geojson(msg: &str) -> Error {
+    ErrorKind::InvalidInput(format!("Invalid GeoJSON: {}", msg)).into()
+}
+
+fn parse_ring(ring: &Value) -> Result<Vec<Point2<f64>>> {
+    let positions = ring
+        .as_array()
+        .ok_or_else(|| invalid_geojson("a ring is not an array"))?;
+    positions
+        .iter()
+        .map(|position| match position.as_array().map(Vec::as_slice) {
+            Some([x, y, ..]) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) => Ok(Point2::new(x, y)),
+                _ => Err(invalid_geojson("a coordinate is not a number")),
+            },
+            _ => Err(invalid_geojson("a position has less than two coordinates")),
+        })
+        .collect()
+}
+
+fn parse_polygon_rings(coordinates: &Value) -> Result<Vec<Vec<Point2<f64>>>> {
+    coordinates
+        .as_array()
+        .ok_or_else(|| invalid_geojson("polygon coordinates are not an array"))?
+        .iter()
+        .map(parse_ring)
+        .collect()
+}
+
+/// The polygon of a Polygon or MultiPolygon feature, None for other geometries.
+fn parse_feature(feature: &Value) -> Result<Option<Polygon>> {
+    let geometry = &feature["geometry"];
+    let coordinates = &geometry["coordinates"];
+    let rings = match geometry["type"].as_str() {
+        Some("Polygon") => parse_polygon_rings(coordinates)?,
+        Some("MultiPolygon") => {
+            let mut rings = Vec::new();
+            for polygon in coordinates
+                .as_array()
+                .ok_or_else(|| invalid_geojson("multi-polygon coordinates are not an array"))?
+            {
+                rings.extend(parse_polygon_rings(polygon)?);
+            }
+            rings
+        }
+        _ => return Ok(None),
+    };
+    let properties = feature["properties"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    Ok(Some(Polygon::new(rings, properties)))
+}
+
+impl SpatialJoin {
+    pub fn new(polygons: Vec<Polygon>, coordinates: PolygonCoordinates) -> Self {
+        let envelopes = polygons
+            .iter()
+            .enumerate()
+            .map(|(index, polygon)| IndexedEnvelope {
+                index,
+                envelope: polygon.envelope(),
+            })
+            .collect();
+        SpatialJoin {
+            polygons,
+            rtree: RTree::bulk_load(envelopes),
+            coordinates,
+        }
+    }
+
+    /// Reads the Polygon and MultiPolygon features of a GeoJSON feature collection. Other
+    /// features are skipped.
+    pub fn from_geojson(geojson: &str, coordinates: PolygonCoordinates) -> Result<Self> {
+        let value: Value =
+            serde_json::from_str(geojson).map_err(|err| invalid_geojson(&err.to_string()))?;
+        let features = value["features"]
+            .as_array()
+            .ok_or_else(|| invalid_geojson("expected a feature collection"))?;
+        let mut polygons = Vec::new();
+        for feature in features {
+            polygons.extend(parse_feature(feature)?);
+        }
+        Ok(Self::new(polygons, coordinates))
+    }
+
+    pub fn from_geojson_file(
+        path: impl AsRef<Path>,
+        coordinates: PolygonCoordinates,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let geojson =
+            fs::read_to_string(path).chain_err(|| format!("Could not read {}.", path.display()))?;
+        Self::from_geojson(&geojson, coordinates)
+    }
+
+    pub fn polygons(&self) -> &[Polygon] {
+        &self.polygons
+    }
+
+    /// The index of the polygon containing 'position'. Where polygons overlap, the first one
+    /// wins.
+    pub fn polygon_containing(&self, position: &Point3<f64>) -> Option<usize> {
+        let point = match self.coordinates {
+            PolygonCoordinates::Wgs84 => {
+                let wgs84: WGS84<f64> = ECEF::new(position.x, position.y, position.z).into();
+                Point2::new(
+                    wgs84.longitude_radians().to_degrees(),
+                    wgs84.latitude_radians().to_degrees(),
+                )
+            }
+            PolygonCoordinates::Local => Point2::new(position.x, position.y),
+        };
+        self.rtree
+            .locate_in_envelope_intersecting(&AABB::from_point([point.x, point.y]))
+            .map(|candidate| candidate.index)
+            .filter(|index| self.polygons[*index].contains(&point))
+            .min()
+    }
+
+    /// Adds the index of the containing polygon to 'batch' as the POLYGON_ATTRIBUTE, and for
+    /// each of 'properties', its numeric value as an F64 attribute of the same name. Points
+    /// outside of all polygons, or in polygons without that numeric property, get NaN.
+    pub fn annotate(&self, batch: &mut PointsBatch, properties: &[&str]) {
+        let indices: Vec<Option<usize>> = batch
+            .position
+            .iter()
+            .map(|position| self.polygon_containing(position))
+            .collect();
+        for property in properties {
+            let values = indices
+                .iter()
+                .map(|index| {
+                    index
+                        .and_then(|index| self.polygons[index].properties.get(*property))
+                        .and_then(Value::as_f64)
+                        .unwrap_or(std::f64::NAN)
+                })
+                .collect();
+            batch
+                .attributes
+                .insert(property.to_string(), AttributeData::F64(values));
+        }
+        let indices = indices
+            .into_iter()
+            .map(|index| index.map_or(-1, |index| index as i64))
+            .collect();
+        batch
+            .attributes
+            .insert(POLYGON_ATTRIBUTE.to_string(), AttributeData::I64(indices));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_annotate_points_with_polygons() {
+        let geojson = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": {"name": "parcel", "zone": 3},
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [
+                            [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                            [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]]
+                        ]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "properties": {"zone": 7},
+                    "geometry": {
+                        "type": "MultiPolygon",
+                        "coordinates": [
+                            [[[20, 0], [30, 0], [30, 10], [20, 0]]],
+                            [[[5, 5], [15, 5], [15, 15], [5, 15], [5, 5]]]
+                        ]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "properties": {},
+                    "geometry": {"type": "Point", "coordinates": [1, 1]}
+                }
+            ]
+        }"#;
+        let join = SpatialJoin::from_geojson(geojson, PolygonCoordinates::Local).unwrap();
+        assert_eq!(join.polygons().len(), 2);
+
+        let mut batch = PointsBatch {
+            position: vec![
+                Point3::new(1., 1., 100.),
+                Point3::new(5., 5.5, 0.),
+                Point3::new(9., 9., 0.),
+                Point3::new(29., 5., 0.),
+                Point3::new(21., 5., 0.),
+            ],
+            attributes: BTreeMap::new(),
+        };
+        join.annotate(&mut batch, &["zone"]);
+        match &batch.attributes[POLYGON_ATTRIBUTE] {
+            AttributeData::I64(indices) => assert_eq!(indices, &[0, 1, 0, 1, -1]),
+            _ => panic!("Wrong data type."),
+        }
+        match &batch.attributes["zone"] {
+            AttributeData::F64(zones) => {
+                assert_eq!(&zones[..4], &[3., 7., 3., 7.]);
+                assert!(zones[4].is_nan());
+            }
+            _ => panic!("Wrong data type."),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Small preview images of whole point clouds, so that dataset browsers can show them without
+//! opening the point cloud. They are rendered from a coarse level of detail and stored as PNG
+//! files next to the meta, which lists them.
+
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::Aabb;
+use crate::iterator::{ParallelIterator, PointCloud, PointQuery};
+use crate::octree::Octree;
+use crate::proto;
+use crate::{AttributeData, NUM_POINTS_PER_BATCH};
+use image::{Rgba, RgbaImage};
+use nalgebra::{Point3, Vector3};
+use std::path::Path;
+
+/// The views that 'write_thumbnails' renders.
+pub const THUMBNAIL_VIEWS: [ThumbnailView; 2] = [ThumbnailView::Top, ThumbnailView::Oblique];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailView {
+    /// Straight down, with north up.
+    Top,
+    /// From the south-west at 45 degrees elevation.
+    Oblique,
+}
+
+impl ThumbnailView {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThumbnailView::Top => "top",
+            ThumbnailView::Oblique => "oblique",
+        }
+    }
+
+    /// Projects 'p' to the image plane, with x to the right and y up, and the distance towards
+    /// the viewer as z.
+    fn project(self, p: &Point3<f64>) -> Vector3<f64> {
+        match self {
+            ThumbnailView::Top => Vector3::new(p.x, p.y, p.z),
+            ThumbnailView::Oblique => {
+                let right = (p.x - p.y) / 2f64.sqrt();
+                let forward = (p.x + p.y) / 2f64.sqrt();
+                let elevation = std::f64::consts::FRAC_PI_4;
+                Vector3::new(
+                    right,
+                    p.z * elevation.cos() + forward * elevation.sin(),
+                    p.z * elevation.sin() - forward * elevation.cos(),
+                )
+            }
+        }
+    }
+}
+
+/// A preview image listed in the meta of a point cloud.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub view: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Thumbnail {
+    /// The name under which the data provider serves the image as its "png" attribute, i.e. the
+    /// stem of its file.
+    pub fn node_id(view: &str) -> String {
+        format!("thumbnail_{}", view)
+    }
+
+    pub fn from_proto(proto: &proto::Thumbnail) -> Self {
+        Thumbnail {
+            view: proto.view.clone(),
+            width: proto.width,
+            height: proto.height,
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::Thumbnail {
+        let mut proto = proto::Thumbnail::new();
+        proto.set_view(self.view.clone());
+        proto.set_width(self.width);
+        proto.set_height(self.height);
+        proto
+    }
+
+    pub fn from_meta_proto(meta: &proto::Meta) -> Vec<Self> {
+        meta.get_thumbnails().iter().map(Self::from_proto).collect()
+    }
+}
+
+/// Renders 'octree' from 'view' into an image whose longer side is 'size' pixels, with a
+/// transparent background. Only as many points are read as the resolution needs. Point clouds
+/// without colors are shaded by height.
+pub fn render_thumbnail(octree: &Octree, view: ThumbnailView, size: u32) -> Result<RgbaImage> {
+    if size == 0 {
+        return Err(
+            ErrorKind::InvalidInput("The thumbnail size must be positive.".to_string()).into(),
+        );
+    }
+    let bounding_box = octree.bounding_box();
+    let (min, max) = (bounding_box.min(), bounding_box.max());
+    let mut projected_box: Option<Aabb> = None;
+    for x in &[min.x, max.x] {
+        for y in &[min.y, max.y] {
+            for z in &[min.z, max.z] {
+                let corner = Point3::from(view.project(&Point3::new(*x, *y, *z)));
+                match &mut projected_box {
+                    Some(projected_box) => projected_box.grow(corner),
+                    None => projected_box = Some(Aabb::new(corner, corner)),
+                }
+            }
+        }
+    }
+    let projected_box = projected_box.unwrap();
+    let extent = projected_box.diag();
+    let meters_per_pixel = extent.x.max(extent.y) / f64::from(size);
+    if meters_per_pixel <= 0. {
+        return Err(ErrorKind::InvalidInput("The point cloud is empty.".to_string()).into());
+    }
+    let width = ((extent.x / meters_per_pixel).ceil() as u32)
+        .max(1)
+        .min(size);
+    let height = ((extent.y / meters_per_pixel).ceil() as u32)
+        .max(1)
+        .min(size);
+
+    let has_color = octree.stored_attribute_type("color")?.is_some();
+    let query = PointQuery {
+        attributes: if has_color { vec!["color"] } else { Vec::new() },
+        max_error_m: Some(meters_per_pixel),
+        ..Default::default()
+    };
+    let mut image = RgbaImage::new(width, height);
+    // The distance towards the viewer of the point drawn into each pixel.
+    let mut depth = vec![std::f64::NEG_INFINITY; width as usize * height as usize];
+    let mut parallel_iterator = ParallelIterator::new(
+        std::slice::from_ref(octree),
+        &query,
+        NUM_POINTS_PER_BATCH,
+        num_cpus::get(),
+        4, /* buffer_size */
+    );
+    parallel_iterator.try_for_each_batch(|batch| {
+        let colors = match batch.attributes.get("color") {
+            Some(AttributeData::U8Vec3(colors)) => Some(colors),
+            _ => None,
+        };
+        for (i, position) in batch.position.iter().enumerate() {
+            let p = view.project(position);
+            let column = ((p.x - projected_box.min().x) / meters_per_pixel) as u32;
+            let row = ((projected_box.max().y - p.y) / meters_per_pixel) as u32;
+            let (column, row) = (column.min(width - 1), row.min(height - 1));
+            let index = row as usize * width as usize + column as usize;
+            if p.z <= depth[index] {
+                continue;
+            }
+            depth[index] = p.z;
+            let color = match colors {
+                Some(colors) => [colors[i].x, colors[i].y, colors[i].z],
+                None => {
+                    let t = (position.z - min.z) / (max.z - min.z).max(std::f64::EPSILON);
+                    let gray = (55. + 200. * t.max(0.).min(1.)) as u8;
+                    [gray, gray, gray]
+                }
+            };
+            image.put_pixel(column, row, Rgba([color[0], color[1], color[2], 255]));
+        }
+        Ok(())
+    })?;
+    Ok(image)
+}
+
+/// Renders the THUMBNAIL_VIEWS of the octree in 'directory', stores them as PNG files in it and
+/// lists them in its meta, replacing previous thumbnails.
+pub fn write_thumbnails(directory: impl AsRef<Path>, size: u32) -> Result<Vec<Thumbnail>> {
+    let directory = directory.as_ref();
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    }))?;
+    let mut thumbnails = Vec::new();
+    for view in THUMBNAIL_VIEWS.iter() {
+        let image = render_thumbnail(&octree, *view, size)?;
+        let path = directory
+            .join(Thumbnail::node_id(view.name()))
+            .with_extension("png");
+        image
+            .save(&path)
+            .chain_err(|| format!("Could not write {}.", path.display()))?;
+        thumbnails.push(Thumbnail {
+            view: view.name().to_string(),
+            width: image.width(),
+            height: image.height(),
+        });
+    }
+    let data_provider = OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    };
+    data_provider.update_meta_proto(|meta| {
+        meta.set_thumbnails(::protobuf::RepeatedField::from_vec(
+            thumbnails.iter().map(Thumbnail::to_proto).collect(),
+        ))
+    })?;
+    Ok(thumbnails)
+}
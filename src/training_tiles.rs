@@ -0,0 +1,519 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Training data for semantic segmentation: a point cloud cut into square tiles in the x-y plane
+//! with at most a given number of points each. Every tile is written as a NumPy .npz file, and a
+//! JSON manifest describes all of them.
+
+use crate::errors::*;
+use crate::iterator::{ParallelIterator, PointCloud, PointLocation, PointQuery};
+use crate::utils::create_progress_bar;
+use crate::{AttributeData, AttributeDataType, PointsBatch, NUM_POINTS_PER_BATCH};
+use byteorder::{LittleEndian, WriteBytesExt};
+use nalgebra::Point3;
+use rand::seq::index;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// The name of the array with the positions in each tile file.
+const POSITION_ARRAY: &str = "xyz";
+/// The name of the array with the classes in each tile file.
+const LABEL_ARRAY: &str = "labels";
+
+#[derive(Debug, Clone)]
+pub struct TrainingTileOptions {
+    /// The edge length of the tiles. They are aligned to multiples of it, so tiles of different
+    /// exports of the same area line up.
+    pub tile_size_m: f64,
+    /// Tiles with more points are subsampled.
+    pub max_points_per_tile: usize,
+    /// Tiles with fewer points are skipped.
+    pub min_points_per_tile: usize,
+    /// The attributes to export besides the positions, e.g. "color" and "intensity".
+    pub attributes: Vec<String>,
+    /// The attribute with the class of each point, which is exported as "labels". It must have a
+    /// single value per point.
+    pub class_attribute: Option<String>,
+    /// When subsampling, keep equally many points of each class where possible instead of
+    /// keeping the class frequencies.
+    pub balance_classes: bool,
+    /// Scale the positions so that the tile spans [-1, 1] in x and y. Otherwise they are only
+    /// moved to the tile origin.
+    pub normalize: bool,
+}
+
+impl Default for TrainingTileOptions {
+    fn default() -> Self {
+        TrainingTileOptions {
+            tile_size_m: 50.,
+            max_points_per_tile: 65_536,
+            min_points_per_tile: 1024,
+            attributes: Vec::new(),
+            class_attribute: None,
+            balance_classes: false,
+            normalize: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainingTile {
+    /// The file name, relative to the manifest.
+    pub file: String,
+    /// The minimum x and y of the tile.
+    pub min: [f64; 2],
+    /// The positions in the file are (position - origin) / scale.
+    pub origin: [f64; 3],
+    pub scale: f64,
+    pub num_points: usize,
+    /// The number of points in the tile before subsampling.
+    pub num_points_in_tile: usize,
+    /// The number of exported points of each class, if there is a class attribute.
+    pub class_counts: BTreeMap<i64, usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainingManifest {
+    pub tile_size_m: f64,
+    pub max_points_per_tile: usize,
+    pub balance_classes: bool,
+    pub normalized: bool,
+    /// The arrays in each file, with their NumPy data types.
+    pub arrays: BTreeMap<String, String>,
+    pub tiles: Vec<TrainingTile>,
+}
+
+/// The NumPy type string of the values of 'data_type'.
+fn numpy_descr(data_type: AttributeDataType) -> &'static str {
+    match data_type {
+        AttributeDataType::U8 | AttributeDataType::U8Vec3 => "|u1",
+        AttributeDataType::U16 => "<u2",
+        AttributeDataType::U32 | AttributeDataType::U32Vec3 => "<u4",
+        AttributeDataType::U64 | AttributeDataType::U64Vec3 => "<u8",
+        AttributeDataType::I8 => "|i1",
+        AttributeDataType::I16 => "<i2",
+        AttributeDataType::I32 | AttributeDataType::I32Vec3 => "<i4",
+        AttributeDataType::I64 => "<i8",
+        AttributeDataType::F32 => "<f4",
+        AttributeDataType::F64 | AttributeDataType::F64Vec3 => "<f8",
+    }
+}
+
+macro_rules! le_bytes {
+    ($values:expr) => {{
+        let mut bytes = Vec::new();
+        for value in $values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }};
+}
+
+fn attribute_bytes(data: &AttributeData) -> Vec<u8> {
+    match data {
+        AttributeData::U8(d) => d.clone(),
+        AttributeData::U16(d) => le_bytes!(d),
+        AttributeData::U32(d) => le_bytes!(d),
+        AttributeData::U64(d) => le_bytes!(d),
+        AttributeData::I8(d) => le_bytes!(d),
+        AttributeData::I16(d) => le_bytes!(d),
+        AttributeData::I32(d) => le_bytes!(d),
+        AttributeData::I64(d) => le_bytes!(d),
+        AttributeData::F32(d) => le_bytes!(d),
+        AttributeData::F64(d) => le_bytes!(d),
+        AttributeData::U8Vec3(d) => d.iter().flat_map(|v| v.iter().copied()).collect(),
+        AttributeData::U32Vec3(d) => le_bytes!(d.iter().flat_map(|v| v.iter())),
+        AttributeData::U64Vec3(d) => le_bytes!(d.iter().flat_map(|v| v.iter())),
+        AttributeData::I32Vec3(d) => le_bytes!(d.iter().flat_map(|v| v.iter())),
+        AttributeData::F64Vec3(d) => le_bytes!(d.iter().flat_map(|v| v.iter())),
+    }
+}
+
+/// Writes an array in the .npy format, version 1.0. 'data' holds the little-endian values in
+/// row-major order.
+fn write_npy(writer: &mut impl Write, descr: &str, shape: &[usize], data: &[u8]) -> Result<()> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // The magic string, the version, the header length and the header, which ends with a
+    // newline, are padded to a multiple of 64 bytes.
+    let unpadded_len = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded_len % 64) % 64));
+    header.push('\n');
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_u16::<LittleEndian>(header.len() as u16)?;
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// The classes of 'data' as integers, if it has a single value per point.
+fn classes(data: &AttributeData) -> Result<Vec<i64>> {
+    macro_rules! rhs {
+        ($dtype:ident, $data:ident) => {
+            $data.iter().map(|v| *v as i64).collect()
+        };
+    }
+    if data.dim() != 1 {
+        return Err(ErrorKind::InvalidInput(format!(
+            "Classes must have a single value per point, not {:?}.",
+            data.data_type()
+        ))
+        .into());
+    }
+    Ok(match_1d_attr_data!(data, rhs))
+}
+
+/// Chooses 'max_points' of the points with the given 'classes' uniformly at random, or all of
+/// them if there are fewer. With 'balance_classes', each class gets an equal share, and the
+/// shares that small classes cannot fill go to the other classes.
+fn choose_points<R: Rng>(
+    num_points: usize,
+    classes: Option<&[i64]>,
+    max_points: usize,
+    balance_classes: bool,
+    rng: &mut R,
+) -> Vec<bool> {
+    if num_points <= max_points {
+        return vec![true; num_points];
+    }
+    let mut keep = vec![false; num_points];
+    match classes {
+        Some(classes) if balance_classes => {
+            let mut indices_per_class: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+            for (index, class) in classes.iter().enumerate() {
+                indices_per_class.entry(*class).or_default().push(index);
+            }
+            let mut indices_per_class: Vec<Vec<usize>> = indices_per_class.into_values().collect();
+            indices_per_class.sort_by_key(Vec::len);
+            let mut remaining = max_points;
+            let num_classes = indices_per_class.len();
+            for (i, indices) in indices_per_class.iter().enumerate() {
+                let share = (remaining / (num_classes - i)).min(indices.len());
+                for chosen in index::sample(rng, indices.len(), share).into_iter() {
+                    keep[indices[chosen]] = true;
+                }
+                remaining -= share;
+            }
+        }
+        _ => {
+            for chosen in index::sample(rng, num_points, max_points).into_iter() {
+                keep[chosen] = true;
+            }
+        }
+    }
+    keep
+}
+
+/// Queries all points of 'point_cloud' once with the attributes in 'query_attributes' and bins
+/// them into the tiles of 'tile_size_m', keyed by the tile index in x and y. The tiles contain
+/// their lower but not their upper borders, so no point is in two tiles.
+fn points_by_tile<C: PointCloud>(
+    point_cloud: &C,
+    query_attributes: &[&str],
+    tile_size_m: f64,
+) -> Result<BTreeMap<(i64, i64), PointsBatch>> {
+    let query = PointQuery {
+        attributes: query_attributes.to_vec(),
+        location: PointLocation::AllPoints,
+        filter_intervals: HashMap::new(),
+        max_error_m: None,
+    };
+    let tile_index = |v: f64| (v / tile_size_m).floor() as i64;
+    let mut tiles: BTreeMap<(i64, i64), PointsBatch> = BTreeMap::new();
+    let mut parallel_iterator = ParallelIterator::new(
+        std::slice::from_ref(point_cloud),
+        &query,
+        NUM_POINTS_PER_BATCH,
+        num_cpus::get(),
+        4, /* buffer_size */
+    );
+    parallel_iterator.try_for_each_batch(|batch| {
+        let keys: Vec<(i64, i64)> = batch
+            .position
+            .iter()
+            .map(|p| (tile_index(p.x), tile_index(p.y)))
+            .collect();
+        let mut distinct_keys = keys.clone();
+        distinct_keys.sort_unstable();
+        distinct_keys.dedup();
+        // The points of a batch come from one node, so they mostly fall into few tiles.
+        for key in distinct_keys {
+            let keep: Vec<bool> = keys.iter().map(|k| *k == key).collect();
+            let mut points = batch.clone();
+            points.retain(&keep);
+            match tiles.get_mut(&key) {
+                Some(tile) => tile.append(&mut points)?,
+                None => {
+                    tiles.insert(key, points);
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(tiles)
+}
+
+/// Writes the arrays of 'points' into the .npz file at 'path'. The positions are transformed
+/// into the frame given by 'origin' and 'scale'.
+fn write_tile(
+    path: &Path,
+    points: &PointsBatch,
+    origin: &Point3<f64>,
+    scale: f64,
+    options: &TrainingTileOptions,
+) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(path)?));
+    let file_options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let num_points = points.position.len();
+    let mut positions = Vec::with_capacity(3 * 4 * num_points);
+    for position in &points.position {
+        for v in ((position - origin) / scale).iter() {
+            positions.extend_from_slice(&(*v as f32).to_le_bytes());
+        }
+    }
+    let mut arrays = vec![(POSITION_ARRAY, "<f4", 3, positions)];
+    for name in &options.attributes {
+        let data = &points.attributes[name];
+        let data_type = data.data_type();
+        arrays.push((
+            name.as_str(),
+            numpy_descr(data_type),
+            data_type.dim(),
+            attribute_bytes(data),
+        ));
+    }
+    if let Some(class_attribute) = &options.class_attribute {
+        let data = &points.attributes[class_attribute];
+        arrays.push((
+            LABEL_ARRAY,
+            numpy_descr(data.data_type()),
+            1,
+            attribute_bytes(data),
+        ));
+    }
+    for (name, descr, dim, data) in arrays {
+        zip.start_file(format!("{}.npy", name), file_options)
+            .chain_err(|| format!("Could not write {}.", path.display()))?;
+        let shape = if dim == 1 {
+            vec![num_points]
+        } else {
+            vec![num_points, dim]
+        };
+        write_npy(&mut zip, descr, &shape, &data)?;
+    }
+    zip.finish()
+        .chain_err(|| format!("Could not write {}.", path.display()))?;
+    Ok(())
+}
+
+/// Cuts 'point_cloud' into tiles as described by 'options' and writes one .npz file per tile and
+/// the manifest into 'output_directory'. The point cloud is queried once, and all queried points
+/// are held in memory until the tiles are written. Returns the manifest.
+pub fn export_training_tiles<C: PointCloud>(
+    point_cloud: &C,
+    options: &TrainingTileOptions,
+    output_directory: &Path,
+) -> Result<TrainingManifest> {
+    if options.tile_size_m <= 0. || options.max_points_per_tile == 0 {
+        return Err(ErrorKind::InvalidInput(
+            "Tiles need a positive size and maximum number of points.".to_string(),
+        )
+        .into());
+    }
+    if options
+        .attributes
+        .iter()
+        .any(|a| a == POSITION_ARRAY || a == LABEL_ARRAY)
+    {
+        return Err(ErrorKind::InvalidInput(format!(
+            "The attributes '{}' and '{}' clash with the exported arrays.",
+            POSITION_ARRAY, LABEL_ARRAY
+        ))
+        .into());
+    }
+    fs::create_dir_all(output_directory)?;
+    let mut query_attributes: Vec<&str> = options.attributes.iter().map(String::as_str).collect();
+    if let Some(class_attribute) = &options.class_attribute {
+        if !query_attributes.contains(&class_attribute.as_str()) {
+            query_attributes.push(class_attribute);
+        }
+    }
+
+    let tiles = points_by_tile(point_cloud, &query_attributes, options.tile_size_m)?;
+    let mut progress_bar = create_progress_bar(tiles.len(), "Exporting tiles");
+    let mut rng = rand::thread_rng();
+    let mut manifest = TrainingManifest {
+        tile_size_m: options.tile_size_m,
+        max_points_per_tile: options.max_points_per_tile,
+        balance_classes: options.balance_classes,
+        normalized: options.normalize,
+        arrays: BTreeMap::new(),
+        tiles: Vec::new(),
+    };
+    for ((x, y), mut points) in tiles {
+        progress_bar.inc();
+        let min = [
+            x as f64 * options.tile_size_m,
+            y as f64 * options.tile_size_m,
+        ];
+        let num_points_in_tile = points.position.len();
+        if num_points_in_tile == 0 || num_points_in_tile < options.min_points_per_tile {
+            continue;
+        }
+        let tile_classes = match &options.class_attribute {
+            Some(class_attribute) => Some(classes(&points.attributes[class_attribute])?),
+            None => None,
+        };
+        let keep = choose_points(
+            num_points_in_tile,
+            tile_classes.as_deref(),
+            options.max_points_per_tile,
+            options.balance_classes,
+            &mut rng,
+        );
+        points.retain(&keep);
+        let mut class_counts = BTreeMap::new();
+        for (class, _) in tile_classes
+            .iter()
+            .flatten()
+            .zip(&keep)
+            .filter(|(_, keep)| **keep)
+        {
+            *class_counts.entry(*class).or_insert(0) += 1;
+        }
+
+        let min_z = points
+            .position
+            .iter()
+            .map(|p| p.z)
+            .fold(f64::INFINITY, f64::min);
+        let half_size = options.tile_size_m / 2.;
+        let origin = Point3::new(min[0] + half_size, min[1] + half_size, min_z);
+        let scale = if options.normalize { half_size } else { 1. };
+        let file = format!("tile_{}_{}.npz", x, y);
+        write_tile(
+            &output_directory.join(&file),
+            &points,
+            &origin,
+            scale,
+            options,
+        )?;
+        if manifest.arrays.is_empty() {
+            manifest
+                .arrays
+                .insert(POSITION_ARRAY.to_string(), "<f4".to_string());
+            for (name, data) in &points.attributes {
+                if options.attributes.contains(name) {
+                    manifest
+                        .arrays
+                        .insert(name.clone(), numpy_descr(data.data_type()).to_string());
+                }
+            }
+            if let Some(class_attribute) = &options.class_attribute {
+                let data_type = points.attributes[class_attribute].data_type();
+                manifest
+                    .arrays
+                    .insert(LABEL_ARRAY.to_string(), numpy_descr(data_type).to_string());
+            }
+        }
+        manifest.tiles.push(TrainingTile {
+            file,
+            min,
+            origin: [origin.x, origin.y, origin.z],
+            scale,
+            num_points: points.position.len(),
+            num_points_in_tile,
+            class_counts,
+        });
+    }
+    progress_bar.finish();
+
+    let manifest_path = output_directory.join(MANIFEST_FILENAME);
+    let mut writer = BufWriter::new(File::create(&manifest_path)?);
+    serde_json::to_writer_pretty(&mut writer, &manifest)
+        .chain_err(|| format!("Could not write {}.", manifest_path.display()))?;
+    writer.flush()?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_npy_header_is_aligned() {
+        let mut npy = Vec::new();
+        write_npy(&mut npy, "<f4", &[2, 3], &[0; 24]).unwrap();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = usize::from(npy[8]) + 256 * usize::from(npy[9]);
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(npy.len(), 10 + header_len + 24);
+    }
+
+    #[test]
+    fn test_choose_points_balances_classes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let classes: Vec<i64> = (0..100)
+            .map(|i| {
+                if i < 85 {
+                    0
+                } else if i < 95 {
+                    1
+                } else {
+                    2
+                }
+            })
+            .collect();
+        let count = |keep: &[bool], class: i64| {
+            keep.iter()
+                .zip(&classes)
+                .filter(|(keep, c)| **keep && **c == class)
+                .count()
+        };
+        let keep = choose_points(100, Some(&classes), 21, true, &mut rng);
+        assert_eq!(keep.iter().filter(|k| **k).count(), 21);
+        assert_eq!(
+            (count(&keep, 0), count(&keep, 1), count(&keep, 2)),
+            (8, 8, 5)
+        );
+        let keep = choose_points(100, Some(&classes), 21, false, &mut rng);
+        assert_eq!(keep.iter().filter(|k| **k).count(), 21);
+        assert_eq!(choose_points(10, None, 21, true, &mut rng), vec![true; 10]);
+    }
+}
@@ -1,11 +1,170 @@
-use pbr::ProgressBar;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use nalgebra::Point3;
+use serde_json::json;
 use std::error::Error;
-use std::io;
+use std::io::{self, Write};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-const PROGRESS_REFRESH_RATE: Duration = Duration::from_secs(2);
+const PROGRESS_REFRESH_HZ: u64 = 2;
+const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The environment variable that selects the progress mode if none was set explicitly.
+pub const PROGRESS_MODE_ENV: &str = "POINT_VIEWER_PROGRESS";
+
+/// How long-running tools report their progress on stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Interactive progress bars with speed and ETA.
+    Bar,
+    /// No progress output at all.
+    Quiet,
+    /// One JSON object per line, for consumption by other tools.
+    Json,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(ProgressMode::Bar),
+            "quiet" => Ok(ProgressMode::Quiet),
+            "json" => Ok(ProgressMode::Json),
+            _ => Err(format!(
+                "Invalid progress mode '{}', expected one of bar, quiet, json.",
+                s
+            )),
+        }
+    }
+}
+
+const PROGRESS_MODE_UNSET: u8 = u8::MAX;
+static PROGRESS_MODE: AtomicU8 = AtomicU8::new(PROGRESS_MODE_UNSET);
+
+/// Sets the progress mode for all progress reporters created afterwards.
+pub fn set_progress_mode(mode: ProgressMode) {
+    PROGRESS_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the progress mode, falling back to $POINT_VIEWER_PROGRESS and then to progress bars.
+pub fn progress_mode() -> ProgressMode {
+    match PROGRESS_MODE.load(Ordering::Relaxed) {
+        m if m == ProgressMode::Bar as u8 => ProgressMode::Bar,
+        m if m == ProgressMode::Quiet as u8 => ProgressMode::Quiet,
+        m if m == ProgressMode::Json as u8 => ProgressMode::Json,
+        _ => {
+            let mode = std::env::var(PROGRESS_MODE_ENV)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(ProgressMode::Bar);
+            set_progress_mode(mode);
+            mode
+        }
+    }
+}
+
+/// Reports the progress of a long-running operation according to the 'ProgressMode'.
+/// Progress bars show the rate and the ETA, the JSON mode prints them as fields.
+pub struct ProgressReporter {
+    bar: Option<ProgressBar>,
+    json: bool,
+    message: String,
+    current: u64,
+    total: u64,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl ProgressReporter {
+    pub fn new(total: usize, message: &str) -> Self {
+        Self::with_mode(total, message, progress_mode())
+    }
+
+    /// Like 'new', but ignores the global progress mode.
+    pub fn with_mode(total: usize, message: &str, mode: ProgressMode) -> Self {
+        let bar = if mode == ProgressMode::Bar {
+            let progress_bar = ProgressBar::with_draw_target(
+                total as u64,
+                ProgressDrawTarget::stderr_with_hz(PROGRESS_REFRESH_HZ),
+            );
+            progress_bar.set_style(ProgressStyle::default_bar().template(
+                "{msg}: {wide_bar} {pos}/{len} ({per_sec}, elapsed {elapsed_precise}, ETA {eta})",
+            ));
+            progress_bar.set_message(message);
+            Some(progress_bar)
+        } else {
+            None
+        };
+        let now = Instant::now();
+        Self {
+            bar,
+            json: mode == ProgressMode::Json,
+            message: message.to_string(),
+            current: 0,
+            total: total as u64,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    pub fn inc(&mut self) -> u64 {
+        self.add(1)
+    }
+
+    pub fn add(&mut self, delta: u64) -> u64 {
+        self.current += delta;
+        if let Some(bar) = &self.bar {
+            bar.inc(delta);
+        } else if self.json && self.last_report.elapsed() >= PROGRESS_REPORT_INTERVAL {
+            self.report_json(false);
+        }
+        self.current
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        } else if self.json {
+            self.report_json(true);
+        }
+    }
+
+    pub fn finish_println(&mut self, s: &str) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+            eprintln!("{}", s);
+        } else if self.json {
+            self.report_json(true);
+        }
+    }
+
+    fn report_json(&mut self, finished: bool) {
+        self.last_report = Instant::now();
+        let elapsed_s = self.start.elapsed().as_secs_f64();
+        let per_second = if elapsed_s > 0. {
+            self.current as f64 / elapsed_s
+        } else {
+            0.
+        };
+        let eta_s = if per_second > 0. {
+            self.total.saturating_sub(self.current) as f64 / per_second
+        } else {
+            0.
+        };
+        let report = json!({
+            "message": self.message,
+            "current": self.current,
+            "total": self.total,
+            "per_second": per_second,
+            "eta_seconds": eta_s.round(),
+            "finished": finished,
+        });
+        let _ = writeln!(io::stderr(), "{}", report);
+    }
+}
 
 pub fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error>>
 where
@@ -20,16 +179,22 @@ where
     Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
 }
 
-pub fn create_progress_bar(total: usize, message: &str) -> ProgressBar<io::Stderr> {
-    let mut progress_bar = ProgressBar::on(io::stderr(), total as u64);
-    progress_bar.set_max_refresh_rate(Some(PROGRESS_REFRESH_RATE));
-    progress_bar.message(&format!("{}: ", message));
-    progress_bar
+/// Parses a point given as 'x,y,z', e.g. for command line arguments.
+pub fn parse_point3(s: &str) -> Result<Point3<f64>, String> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if coords.len() != 3 {
+        return Err(format!("Expected 'x,y,z', got '{}'.", s));
+    }
+    Ok(Point3::new(coords[0], coords[1], coords[2]))
+}
+
+pub fn create_progress_bar(total: usize, message: &str) -> ProgressReporter {
+    ProgressReporter::new(total, message)
 }
 
-pub fn create_syncable_progress_bar(
-    total: usize,
-    message: &str,
-) -> Arc<Mutex<ProgressBar<io::Stderr>>> {
+pub fn create_syncable_progress_bar(total: usize, message: &str) -> Arc<Mutex<ProgressReporter>> {
     Arc::new(Mutex::new(create_progress_bar(total, message)))
 }
@@ -1,20 +1,33 @@
+use crate::footprints::FOOTPRINTS_FILE_EXTENSION;
 use crate::{BoundingRect, Meta, META_FILENAME};
+use fnv::FnvHasher;
+use iron::headers::{
+    AcceptRanges, ByteRangeSpec, CacheControl, CacheDirective, ContentRange, ContentRangeSpec,
+    ETag, EntityTag, IfNoneMatch, Range, RangeUnit,
+};
 use iron::mime::Mime;
 use iron::prelude::*;
 use iron::{self, itry};
 use router::Router;
 use serde_derive::Serialize;
 use std::fs;
+use std::hash::Hasher;
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
 use urlencoded::UrlEncodedQuery;
 
+/// How long clients may use a node image without asking again. After that, they revalidate it
+/// with its ETag, which is cheap if it did not change.
+const NODE_IMAGE_MAX_AGE_SECS: u32 = 3600;
+
 #[derive(Serialize, Debug)]
 struct MetaReply {
     bounding_rect: BoundingRect,
     tile_size: u32,
     deepest_level: u8,
+    /// The number of nodes on each level, starting with the root.
+    num_nodes_per_level: Vec<usize>,
 }
 
 pub trait XRay: Sync {
@@ -23,6 +36,28 @@ pub trait XRay: Sync {
 
     /// Returns the PNG blob of the node image for this 'image_id' or an Error.
     fn get_node_image(&self, node_id: &str) -> io::Result<Vec<u8>>;
+
+    /// Returns the GeoJSON of the point cloud footprints in this node, if they were generated.
+    fn get_node_footprints(&self, _node_id: &str) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No footprints available.",
+        ))
+    }
+}
+
+impl<T: XRay + Send> XRay for Arc<T> {
+    fn get_meta(&self) -> io::Result<Meta> {
+        (**self).get_meta()
+    }
+
+    fn get_node_image(&self, node_id: &str) -> io::Result<Vec<u8>> {
+        (**self).get_node_image(node_id)
+    }
+
+    fn get_node_footprints(&self, node_id: &str) -> io::Result<Vec<u8>> {
+        (**self).get_node_footprints(node_id)
+    }
 }
 
 pub struct OnDiskXRay {
@@ -50,6 +85,35 @@ impl XRay for OnDiskXRay {
         let data = fs::read(&filename)?;
         Ok(data)
     }
+
+    fn get_node_footprints(&self, node_id: &str) -> io::Result<Vec<u8>> {
+        let mut filename = self.directory.join(node_id);
+        filename.set_extension(FOOTPRINTS_FILE_EXTENSION);
+        fs::read(&filename)
+    }
+}
+
+/// A strong entity tag derived from the content, so that it changes whenever the content does.
+fn content_tag(data: &[u8]) -> EntityTag {
+    let mut hasher = FnvHasher::default();
+    hasher.write(data);
+    EntityTag::strong(format!("{:016x}-{:x}", hasher.finish(), data.len()))
+}
+
+/// Returns the inclusive byte range of 'spec' within content of the given length, or None if it
+/// is not satisfiable.
+fn satisfiable_range(spec: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    if length == 0 {
+        return None;
+    }
+    match *spec {
+        ByteRangeSpec::FromTo(from, to) if from <= to && from < length => {
+            Some((from, to.min(length - 1)))
+        }
+        ByteRangeSpec::AllFrom(from) if from < length => Some((from, length - 1)),
+        ByteRangeSpec::Last(last) if last > 0 => Some((length - last.min(length), length - 1)),
+        _ => None,
+    }
 }
 
 pub struct HandleNodeImage<T: XRay> {
@@ -67,7 +131,68 @@ impl<T: XRay + Send + 'static> iron::Handler for HandleNodeImage<T> {
             self.xray_provider.get_node_image(&id),
             iron::status::NotFound
         );
+        let tag = content_tag(&reply);
+
+        let mut response = Response::new();
+        response.headers.set(ETag(tag.clone()));
+        response.headers.set(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(NODE_IMAGE_MAX_AGE_SECS),
+        ]));
+        response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+
+        let not_modified = match req.headers.get::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&tag)),
+            None => false,
+        };
+        if not_modified {
+            return Ok(response.set(iron::status::NotModified));
+        }
+
         let content_type = "image/png".parse::<Mime>().unwrap();
+        let length = reply.len() as u64;
+        // Only single ranges are supported, multipart responses are not worth it for images.
+        match req.headers.get::<Range>() {
+            Some(Range::Bytes(specs)) if specs.len() == 1 => {
+                match satisfiable_range(&specs[0], length) {
+                    Some((from, to)) => {
+                        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                            range: Some((from, to)),
+                            instance_length: Some(length),
+                        }));
+                        let part = reply[from as usize..=to as usize].to_vec();
+                        Ok(response.set((content_type, iron::status::PartialContent, part)))
+                    }
+                    None => {
+                        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                            range: None,
+                            instance_length: Some(length),
+                        }));
+                        Ok(response.set(iron::status::RangeNotSatisfiable))
+                    }
+                }
+            }
+            _ => Ok(response.set((content_type, iron::status::Ok, reply))),
+        }
+    }
+}
+
+pub struct HandleNodeFootprints<T: XRay> {
+    pub xray_provider: T,
+}
+
+impl<T: XRay + Send + 'static> iron::Handler for HandleNodeFootprints<T> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let id = match req.extensions.get::<Router>().unwrap().find("id") {
+            Some(id) => id,
+            None => return Ok(Response::with(iron::status::NotFound)),
+        };
+        let reply = itry!(
+            self.xray_provider.get_node_footprints(&id),
+            iron::status::NotFound
+        );
+        let content_type = "application/geo+json".parse::<Mime>().unwrap();
         Ok(Response::with((content_type, iron::status::Ok, reply)))
     }
 }
@@ -86,6 +211,7 @@ impl iron::Handler for HandleMeta {
             },
             tile_size: self.meta.tile_size,
             deepest_level: self.meta.deepest_level,
+            num_nodes_per_level: self.meta.num_nodes_per_level(),
         };
         let reply = ::serde_json::to_string_pretty(&result).unwrap();
         let content_type = "application/json".parse::<Mime>().unwrap();
@@ -118,30 +244,77 @@ impl iron::Handler for HandleNodesForLevel {
     }
 }
 
+/// Adds the routes serving 'xray_provider' under 'prefix' to 'router'. Call this repeatedly with
+/// different prefixes to serve several quadtrees from one server.
 pub fn serve(
     prefix: &str,
     router: &mut Router,
     xray_provider: impl XRay + Send + 'static,
 ) -> io::Result<()> {
     let meta = Arc::new(xray_provider.get_meta()?);
+    let xray_provider = Arc::new(xray_provider);
+    // Route ids must be unique within the router.
+    let route_id = |name: &str| {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", prefix, name)
+        }
+    };
     router.get(
         format!("{}/meta", prefix),
         HandleMeta {
             meta: Arc::clone(&meta),
         },
-        "meta",
+        route_id("meta"),
     );
     router.get(
         format!("{}/nodes_for_level", prefix),
         HandleNodesForLevel {
             meta: Arc::clone(&meta),
         },
-        "nodes_for_level",
+        route_id("nodes_for_level"),
     );
     router.get(
         format!("{}/node_image/:id", prefix),
-        HandleNodeImage { xray_provider },
-        "node_image",
+        HandleNodeImage {
+            xray_provider: Arc::clone(&xray_provider),
+        },
+        route_id("node_image"),
+    );
+    router.get(
+        format!("{}/node_footprints/:id", prefix),
+        HandleNodeFootprints { xray_provider },
+        route_id("node_footprints"),
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_satisfiable_range() {
+        assert_eq!(
+            satisfiable_range(&ByteRangeSpec::FromTo(2, 5), 10),
+            Some((2, 5))
+        );
+        assert_eq!(
+            satisfiable_range(&ByteRangeSpec::FromTo(8, 20), 10),
+            Some((8, 9))
+        );
+        assert_eq!(satisfiable_range(&ByteRangeSpec::FromTo(10, 20), 10), None);
+        assert_eq!(
+            satisfiable_range(&ByteRangeSpec::AllFrom(3), 10),
+            Some((3, 9))
+        );
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(4), 10), Some((6, 9)));
+        assert_eq!(
+            satisfiable_range(&ByteRangeSpec::Last(40), 10),
+            Some((0, 9))
+        );
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(0), 10), None);
+        assert_eq!(satisfiable_range(&ByteRangeSpec::AllFrom(0), 0), None);
+    }
+}
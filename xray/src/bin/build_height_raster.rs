@@ -0,0 +1,82 @@
+use clap::Clap;
+use point_cloud_client::PointCloudClientBuilder;
+use point_viewer::read_write::attempt_increasing_rlimit_to_max;
+use std::path::PathBuf;
+use xray::height_raster::{build_height_raster, Ground, HeightRasterKind, HeightRasterParameters};
+
+#[derive(Clap, Debug)]
+#[clap(rename_all = "snake_case")]
+enum Mode {
+    /// The height of the ground.
+    Terrain,
+    /// The height of a percentile of all points above the ground, e.g. of the canopy.
+    HeightAboveGround,
+}
+
+#[derive(Clap, Debug)]
+#[clap(name = "build_height_raster")]
+/// Builds a GeoTIFF with the height of the terrain or of what stands on it, e.g. a canopy height
+/// model, from point clouds.
+struct CommandlineArguments {
+    /// Point cloud locations to build the raster from.
+    #[clap(required = true)]
+    point_cloud_locations: Vec<String>,
+    /// The GeoTIFF file to write.
+    #[clap(parse(from_os_str), long)]
+    output: PathBuf,
+    /// Edge length of the cells in meters.
+    #[clap(long, default_value = "1")]
+    resolution: f64,
+    #[clap(arg_enum, long, default_value = "height_above_ground")]
+    mode: Mode,
+    /// The percentile of the point heights in each cell that is compared with the ground.
+    #[clap(long, default_value = "99", validator = validate_percentile)]
+    percentile: f64,
+    /// The attribute that classifies points as ground, e.g. "class". Without it, the lowest
+    /// point of each cell is taken as the ground.
+    #[clap(long)]
+    ground_attribute: Option<String>,
+    /// The value of 'ground_attribute' for ground points, 2 in the ASPRS classification.
+    #[clap(long, default_value = "2")]
+    ground_value: f64,
+    /// The EPSG code of the projected coordinate system of the point clouds, if any.
+    #[clap(long)]
+    epsg: Option<u16>,
+}
+
+fn validate_percentile(percentile: &str) -> Result<(), String> {
+    match percentile.parse::<f64>() {
+        Ok(percentile) if (0. ..=100.).contains(&percentile) => Ok(()),
+        _ => Err("percentile must be a number in [0, 100].".to_string()),
+    }
+}
+
+fn main() {
+    attempt_increasing_rlimit_to_max();
+    let args = CommandlineArguments::parse();
+    let point_cloud_client = PointCloudClientBuilder::new(&args.point_cloud_locations)
+        // We do threading outside
+        .num_threads(1)
+        .build()
+        .expect("Could not create point cloud client.");
+    let ground_value = args.ground_value;
+    let parameters = HeightRasterParameters {
+        point_cloud_client,
+        query_from_global: None,
+        cell_size_m: args.resolution,
+        ground: args.ground_attribute.map(|attribute| Ground {
+            attribute,
+            value: ground_value,
+        }),
+        kind: match args.mode {
+            Mode::Terrain => HeightRasterKind::Terrain,
+            Mode::HeightAboveGround => HeightRasterKind::HeightAboveGround {
+                percentile: args.percentile,
+            },
+        },
+    };
+    let raster = build_height_raster(&parameters).expect("Could not query the point clouds.");
+    raster
+        .write_geotiff(&args.output, args.epsg)
+        .expect("Could not write GeoTIFF.");
+}
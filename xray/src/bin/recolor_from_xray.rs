@@ -0,0 +1,29 @@
+use clap::Clap;
+use std::path::PathBuf;
+use xray::recolor::{recolor_octree, XrayColorSampler};
+
+#[derive(Clap, Debug)]
+#[clap(name = "recolor_from_xray")]
+/// Colors the points of an octree by the X-Ray tiles above them, e.g. to give lidar point clouds
+/// without colors an orthophoto-like look. The colors are written as a new attribute, the original
+/// colors are kept.
+struct CommandlineArguments {
+    /// The octree directory to recolor.
+    #[clap(parse(from_os_str))]
+    octree_directory: PathBuf,
+    /// The X-Ray quadtree directory to take the colors from, built from the same octree.
+    #[clap(parse(from_os_str), long)]
+    xray_directory: PathBuf,
+    /// The attribute to write the colors to. Alias "color" to it to show it in the viewers.
+    #[clap(long, default_value = "xray_color")]
+    attribute: String,
+}
+
+fn main() {
+    let args = CommandlineArguments::parse();
+    let mut sampler =
+        XrayColorSampler::new(&args.xray_directory).expect("Could not open the X-Ray quadtree.");
+    let num_colored = recolor_octree(&args.octree_directory, &mut sampler, &args.attribute, None)
+        .expect("Could not recolor the octree.");
+    println!("Colored {} points from the X-Ray.", num_colored);
+}
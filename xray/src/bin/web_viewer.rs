@@ -51,6 +51,14 @@ fn main() {
                 .about("Input directory of the quadtree directory to serve.")
                 .index(1)
                 .required(true),
+            clap::Arg::new("mount")
+                .about(
+                    "Additional quadtree to serve under a URL path, given as <path>=<directory>, \
+                     e.g. /other=/data/other_quadtree. Can be given multiple times.",
+                )
+                .long("mount")
+                .takes_value(true)
+                .multiple_occurrences(true),
         ])
         .get_matches();
 
@@ -72,6 +80,21 @@ fn main() {
             .expect("Could not serve from directory. Not a xray directory?"),
     )
     .unwrap();
+    for mount in matches.values_of("mount").into_iter().flatten() {
+        let mut parts = mount.splitn(2, '=');
+        let (prefix, directory) = match (parts.next(), parts.next()) {
+            (Some(prefix), Some(directory)) if prefix.starts_with('/') => (prefix, directory),
+            _ => panic!("Invalid mount '{}', expected <path>=<directory>.", mount),
+        };
+        xray::backend::serve(
+            prefix.trim_end_matches('/'),
+            &mut router,
+            xray::backend::OnDiskXRay::from_directory(PathBuf::from(directory)).unwrap_or_else(
+                |_| panic!("Could not serve from {}. Not a xray directory?", directory),
+            ),
+        )
+        .unwrap();
+    }
 
     eprintln!("Listening on port {}.", port);
     Iron::new(router).http(("0.0.0.0", port)).unwrap();
@@ -1,3 +1,4 @@
+use crate::footprints::FootprintsArgument;
 use crate::generation::{
     build_xray_quadtree, ColoringStrategyArgument, ColoringStrategyKind, ColormapArgument,
     TileBackgroundColorArgument, XrayParameters,
@@ -8,7 +9,7 @@ use point_cloud_client::PointCloudClientBuilder;
 use point_viewer::data_provider::DataProviderFactory;
 use point_viewer::math::ClosedInterval;
 use point_viewer::read_write::attempt_increasing_rlimit_to_max;
-use point_viewer::utils::parse_key_val;
+use point_viewer::utils::{parse_key_val, set_progress_mode, ProgressMode};
 use quadtree::NodeId;
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -110,6 +111,27 @@ fn parse_arguments<T: Extension>() -> clap::ArgMatches {
                 .long("root-node-id")
                 .takes_value(true)
                 .default_value("r"),
+            clap::Arg::new("footprints")
+                .about(
+                    "Whether to show the outlines of the octree nodes of the point clouds, \
+                     colored by point density, by baking them into the tiles or writing them \
+                     as GeoJSON next to each tile.",
+                )
+                .long("footprints")
+                .takes_value(true)
+                .possible_values(&FootprintsArgument::VARIANTS)
+                .default_value("none"),
+            clap::Arg::new("footprint_level")
+                .about("The octree level whose nodes are shown as footprints.")
+                .long("footprint-level")
+                .takes_value(true)
+                .default_value("4"),
+            clap::Arg::new("progress")
+                .about("How to report progress.")
+                .long("progress")
+                .takes_value(true)
+                .possible_values(&["bar", "quiet", "json"])
+                .default_value("bar"),
         ]);
     app = T::pre_init(app);
     app.get_matches()
@@ -119,6 +141,10 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     attempt_increasing_rlimit_to_max();
 
     let args = parse_arguments::<T>();
+    set_progress_mode(
+        args.value_of_t::<ProgressMode>("progress")
+            .expect("progress could not be parsed."),
+    );
     let pixel_size_m = args
         .value_of("resolution")
         .unwrap()
@@ -206,6 +232,14 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
         .unwrap()
         .parse::<NodeId>()
         .expect("root_node_id could not be parsed.");
+    let footprints = FootprintsArgument::from_str(
+        args.value_of("footprints").expect("footprints is invalid"),
+        false,
+    )
+    .expect("footprints couldn't be parsed");
+    let footprint_level = args
+        .value_of_t("footprint_level")
+        .expect("footprint_level could not be parsed.");
     let parameters = XrayParameters {
         output_directory,
         point_cloud_client,
@@ -215,6 +249,8 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
         tile_size_px,
         pixel_size_m,
         root_node_id,
+        footprints,
+        footprint_level,
     };
     build_xray_quadtree(&coloring_strategy_kind, &parameters)
         .expect("Failed to build xray quadtree.");
@@ -0,0 +1,218 @@
+// Outlines of where the input point clouds have data, taken from their octree metas. They can be
+// baked into the X-Ray tiles or written next to them as GeoJSON, so that the overview shows gaps
+// in the acquisition and how dense the data is.
+
+use crate::colormap::{Colormap, Jet};
+use crate::utils::get_image_path;
+use clap::Clap;
+use fnv::FnvHashSet;
+use image::{ImageResult, Rgba};
+use imageproc::drawing::draw_hollow_rect_mut;
+use nalgebra::{Isometry3, Point2};
+use point_viewer::octree::{ChildIndex, Octree};
+use quadtree::{Node, NodeId, Rect};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FOOTPRINTS_FILE_EXTENSION: &str = "geojson";
+
+#[derive(Clap, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "snake_case")]
+pub enum FootprintsArgument {
+    None,
+    Baked,
+    Geojson,
+}
+
+/// The area covered by one octree node, projected onto the xy plane of the X-Ray.
+#[derive(Debug, Clone)]
+pub struct Footprint {
+    /// Index of the point cloud in the input locations.
+    pub dataset: usize,
+    pub min: Point2<f64>,
+    pub max: Point2<f64>,
+    pub num_points: i64,
+}
+
+impl Footprint {
+    /// Points per square meter.
+    pub fn density(&self) -> f64 {
+        let area = (self.max.x - self.min.x) * (self.max.y - self.min.y);
+        if area > 0. {
+            self.num_points as f64 / area
+        } else {
+            0.
+        }
+    }
+
+    fn intersects(&self, rect: &Rect) -> bool {
+        let (min, max) = (rect.min(), rect.max());
+        self.min.x < max.x && self.max.x > min.x && self.min.y < max.y && self.max.y > min.y
+    }
+}
+
+/// Collects the footprints of the nodes on 'level' of all octrees, and of the leaves above it.
+pub fn collect_footprints(
+    octrees: &[Octree],
+    level: u8,
+    query_from_global: &Option<Isometry3<f64>>,
+) -> Vec<Footprint> {
+    let mut footprints = Vec::new();
+    for (dataset, octree) in octrees.iter().enumerate() {
+        let node_ids = octree.node_ids_up_to_level(level);
+        let present: FnvHashSet<_> = node_ids.iter().copied().collect();
+        for node_id in node_ids {
+            let is_leaf = (0..8).all(|child_index| {
+                !present.contains(&node_id.get_child_id(ChildIndex::from_u8(child_index)))
+            });
+            if node_id.level() != level && !is_leaf {
+                continue;
+            }
+            let node_meta = match octree.node_meta(&node_id) {
+                Some(node_meta) => node_meta,
+                None => continue,
+            };
+            let mut aabb = node_meta.bounding_cube.to_aabb();
+            if let Some(query_from_global) = query_from_global {
+                aabb = aabb.transform(query_from_global);
+            }
+            footprints.push(Footprint {
+                dataset,
+                min: Point2::new(aabb.min().x, aabb.min().y),
+                max: Point2::new(aabb.max().x, aabb.max().y),
+                num_points: node_meta.num_points,
+            });
+        }
+    }
+    footprints
+}
+
+/// Maps densities to colors on a logarithmic scale between the lowest and highest density.
+struct DensityColors {
+    min_log_density: f64,
+    max_log_density: f64,
+}
+
+impl DensityColors {
+    fn new(footprints: &[Footprint]) -> Self {
+        let log_densities = footprints.iter().map(|f| f.density().max(1.).log10());
+        let min_log_density = log_densities.clone().fold(std::f64::INFINITY, f64::min);
+        let max_log_density = log_densities.fold(std::f64::NEG_INFINITY, f64::max);
+        Self {
+            min_log_density,
+            max_log_density,
+        }
+    }
+
+    fn color(&self, footprint: &Footprint) -> Rgba<u8> {
+        let range = self.max_log_density - self.min_log_density;
+        let value = if range > 0. {
+            (footprint.density().max(1.).log10() - self.min_log_density) / range
+        } else {
+            1.
+        };
+        Rgba::from(Jet.for_value(value.max(0.).min(1.) as f32))
+    }
+}
+
+/// Draws the outlines of the footprints into the images of the given nodes, colored by density.
+pub fn bake_footprints(
+    output_directory: &Path,
+    node_ids: &FnvHashSet<NodeId>,
+    root_bounding_rect: &Rect,
+    tile_size_px: u32,
+    footprints: &[Footprint],
+) -> ImageResult<()> {
+    let colors = DensityColors::new(footprints);
+    node_ids
+        .par_iter()
+        .try_for_each(|node_id| -> ImageResult<()> {
+            let rect =
+                Node::from_node_id_and_root_bounding_rect(*node_id, root_bounding_rect.clone())
+                    .bounding_rect;
+            let image_path = get_image_path(output_directory, *node_id);
+            let mut image = image::open(&image_path)?.to_rgba();
+            let px_per_m = f64::from(tile_size_px) / rect.edge_length();
+            // Images have their origin at the top left, so y is inverted.
+            let to_px = |p: &Point2<f64>| {
+                (
+                    ((p.x - rect.min().x) * px_per_m).round() as i32,
+                    ((rect.max().y - p.y) * px_per_m).round() as i32,
+                )
+            };
+            for footprint in footprints.iter().filter(|f| f.intersects(&rect)) {
+                let (min_x, max_y) = to_px(&footprint.min);
+                let (max_x, min_y) = to_px(&footprint.max);
+                // Nodes that are smaller than a pixel would only add noise.
+                if max_x - min_x < 2 || max_y - min_y < 2 {
+                    continue;
+                }
+                draw_hollow_rect_mut(
+                    &mut image,
+                    imageproc::rect::Rect::at(min_x, min_y)
+                        .of_size((max_x - min_x) as u32, (max_y - min_y) as u32),
+                    colors.color(footprint),
+                );
+            }
+            image.save(&image_path)?;
+            Ok(())
+        })
+}
+
+pub fn get_footprints_path(directory: &Path, id: NodeId) -> PathBuf {
+    directory
+        .join(id.to_string())
+        .with_extension(FOOTPRINTS_FILE_EXTENSION)
+}
+
+/// Writes the footprints that intersect each of the given nodes as a GeoJSON feature collection
+/// next to the node's image. The coordinates are in the frame of the X-Ray, not WGS84.
+pub fn write_footprints_geojson(
+    output_directory: &Path,
+    node_ids: &FnvHashSet<NodeId>,
+    root_bounding_rect: &Rect,
+    footprints: &[Footprint],
+) -> io::Result<()> {
+    node_ids
+        .par_iter()
+        .try_for_each(|node_id| -> io::Result<()> {
+            let rect =
+                Node::from_node_id_and_root_bounding_rect(*node_id, root_bounding_rect.clone())
+                    .bounding_rect;
+            let features: Vec<_> = footprints
+                .iter()
+                .filter(|f| f.intersects(&rect))
+                .map(|f| {
+                    json!({
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[
+                                [f.min.x, f.min.y],
+                                [f.max.x, f.min.y],
+                                [f.max.x, f.max.y],
+                                [f.min.x, f.max.y],
+                                [f.min.x, f.min.y],
+                            ]],
+                        },
+                        "properties": {
+                            "dataset": f.dataset,
+                            "num_points": f.num_points,
+                            "density": f.density(),
+                        },
+                    })
+                })
+                .collect();
+            let collection = json!({
+                "type": "FeatureCollection",
+                "features": features,
+            });
+            fs::write(
+                get_footprints_path(output_directory, *node_id),
+                serde_json::to_string(&collection)?,
+            )
+        })
+}
@@ -1,6 +1,9 @@
 // Code related to X-Ray generation.
 
 use crate::colormap::{Colormap, Jet, Monochrome, PURPLISH};
+use crate::footprints::{
+    bake_footprints, collect_footprints, write_footprints_geojson, FootprintsArgument,
+};
 use crate::utils::{get_image_path, get_meta_pb_path};
 use crate::Meta;
 use clap::Clap;
@@ -459,6 +462,9 @@ pub struct XrayParameters {
     pub tile_size_px: u32,
     pub pixel_size_m: f64,
     pub root_node_id: NodeId,
+    pub footprints: FootprintsArgument,
+    /// The octree level whose nodes are drawn as footprints.
+    pub footprint_level: u8,
 }
 
 pub fn xray_from_points(
@@ -485,6 +491,7 @@ pub fn xray_from_points(
             .iter()
             .map(|(k, v)| (&k[..], *v))
             .collect(),
+        max_error_m: None,
     };
     let _ = parameters
         .point_cloud_client
@@ -603,6 +610,34 @@ pub fn build_xray_quadtree(
         parameters.tile_size_px,
     );
 
+    if parameters.footprints != FootprintsArgument::None {
+        let octrees = parameters
+            .point_cloud_client
+            .octrees()
+            .ok_or("Footprints are only supported for octrees.")?;
+        let footprints = collect_footprints(
+            octrees,
+            parameters.footprint_level,
+            &parameters.query_from_global,
+        );
+        match parameters.footprints {
+            FootprintsArgument::Baked => bake_footprints(
+                &parameters.output_directory,
+                &all_node_ids,
+                &root_node.bounding_rect,
+                parameters.tile_size_px,
+                &footprints,
+            )?,
+            FootprintsArgument::Geojson => write_footprints_geojson(
+                &parameters.output_directory,
+                &all_node_ids,
+                &root_node.bounding_rect,
+                &footprints,
+            )?,
+            FootprintsArgument::None => (),
+        }
+    }
+
     let meta = Meta {
         nodes: all_node_ids,
         bounding_rect: root_node.bounding_rect,
@@ -0,0 +1,197 @@
+//! A minimal writer for single channel float GeoTIFFs, enough for elevation rasters.
+
+use nalgebra::Point2;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const ASCII: u16 = 2;
+const DOUBLE: u16 = 12;
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const SAMPLE_FORMAT: u16 = 339;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GDAL_NODATA: u16 = 42113;
+
+const GT_MODEL_TYPE_GEO_KEY: u16 = 1024;
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+const PROJECTED_CS_TYPE_GEO_KEY: u16 = 3072;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const MODEL_TYPE_USER_DEFINED: u16 = 32767;
+const RASTER_PIXEL_IS_AREA: u16 = 1;
+
+/// A georeferenced raster with one f32 per cell.
+pub struct GeoRaster<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row, starting with the row with the largest y coordinate, as in images.
+    pub values: &'a [f32],
+    /// The coordinates of the upper left corner of the upper left cell.
+    pub upper_left: Point2<f64>,
+    pub cell_size_m: f64,
+    /// The projected coordinate system the coordinates are in, if it has an EPSG code.
+    pub epsg: Option<u16>,
+    /// The value of cells without data.
+    pub no_data: f32,
+}
+
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+fn shorts(tag: u16, values: &[u16]) -> Entry {
+    Entry {
+        tag,
+        field_type: SHORT,
+        count: values.len() as u32,
+        data: values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect(),
+    }
+}
+
+fn long(tag: u16, value: u32) -> Entry {
+    Entry {
+        tag,
+        field_type: LONG,
+        count: 1,
+        data: value.to_le_bytes().to_vec(),
+    }
+}
+
+fn doubles(tag: u16, values: &[f64]) -> Entry {
+    Entry {
+        tag,
+        field_type: DOUBLE,
+        count: values.len() as u32,
+        data: values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect(),
+    }
+}
+
+fn ascii(tag: u16, value: &str) -> Entry {
+    let mut data = value.as_bytes().to_vec();
+    data.push(0);
+    Entry {
+        tag,
+        field_type: ASCII,
+        count: data.len() as u32,
+        data,
+    }
+}
+
+impl<'a> GeoRaster<'a> {
+    /// Writes an uncompressed, little endian GeoTIFF with a single strip.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        assert_eq!(self.values.len(), (self.width * self.height) as usize);
+        let mut geo_keys = vec![1, 1, 0, 0];
+        let mut add_key = |id: u16, value: u16| {
+            geo_keys.extend_from_slice(&[id, 0, 1, value]);
+            geo_keys[3] += 1;
+        };
+        match self.epsg {
+            Some(epsg) => {
+                add_key(GT_MODEL_TYPE_GEO_KEY, MODEL_TYPE_PROJECTED);
+                add_key(GT_RASTER_TYPE_GEO_KEY, RASTER_PIXEL_IS_AREA);
+                add_key(PROJECTED_CS_TYPE_GEO_KEY, epsg);
+            }
+            None => {
+                add_key(GT_MODEL_TYPE_GEO_KEY, MODEL_TYPE_USER_DEFINED);
+                add_key(GT_RASTER_TYPE_GEO_KEY, RASTER_PIXEL_IS_AREA);
+            }
+        }
+        let image_size = 4 * self.width * self.height;
+        // The strip offset is filled in once the size of everything before it is known.
+        let mut entries = vec![
+            long(IMAGE_WIDTH, self.width),
+            long(IMAGE_LENGTH, self.height),
+            shorts(BITS_PER_SAMPLE, &[32]),
+            shorts(COMPRESSION, &[1]),
+            // Black is zero.
+            shorts(PHOTOMETRIC_INTERPRETATION, &[1]),
+            long(STRIP_OFFSETS, 0),
+            shorts(SAMPLES_PER_PIXEL, &[1]),
+            long(ROWS_PER_STRIP, self.height),
+            long(STRIP_BYTE_COUNTS, image_size),
+            // IEEE floating point.
+            shorts(SAMPLE_FORMAT, &[3]),
+            doubles(MODEL_PIXEL_SCALE, &[self.cell_size_m, self.cell_size_m, 0.]),
+            doubles(
+                MODEL_TIEPOINT,
+                &[0., 0., 0., self.upper_left.x, self.upper_left.y, 0.],
+            ),
+            shorts(GEO_KEY_DIRECTORY, &geo_keys),
+            ascii(GDAL_NODATA, &self.no_data.to_string()),
+        ];
+
+        // Header, then the directory, then the values that do not fit into their entries, then
+        // the image.
+        let directory_offset = 8;
+        let directory_size = 2 + 12 * entries.len() as u32 + 4;
+        let mut extra_offset = directory_offset + directory_size;
+        let mut extra_data = Vec::new();
+        let mut offsets = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            if entry.data.len() > 4 {
+                offsets.push(Some(extra_offset + extra_data.len() as u32));
+                extra_data.extend_from_slice(&entry.data);
+                // Values must start on a word boundary.
+                if extra_data.len() % 2 == 1 {
+                    extra_data.push(0);
+                }
+            } else {
+                offsets.push(None);
+            }
+        }
+        extra_offset += extra_data.len() as u32;
+        for entry in &mut entries {
+            if entry.tag == STRIP_OFFSETS {
+                entry.data = extra_offset.to_le_bytes().to_vec();
+            }
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"II")?;
+        writer.write_all(&42u16.to_le_bytes())?;
+        writer.write_all(&directory_offset.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u16).to_le_bytes())?;
+        for (entry, offset) in entries.iter().zip(&offsets) {
+            writer.write_all(&entry.tag.to_le_bytes())?;
+            writer.write_all(&entry.field_type.to_le_bytes())?;
+            writer.write_all(&entry.count.to_le_bytes())?;
+            match offset {
+                Some(offset) => writer.write_all(&offset.to_le_bytes())?,
+                None => {
+                    let mut value = [0; 4];
+                    value[..entry.data.len()].copy_from_slice(&entry.data);
+                    writer.write_all(&value)?;
+                }
+            }
+        }
+        // There is no next directory.
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&extra_data)?;
+        for value in self.values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+}
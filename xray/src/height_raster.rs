@@ -0,0 +1,284 @@
+//! Height rasters from point clouds: a terrain model from the ground points, or the height of
+//! what stands on the ground, e.g. a canopy or building height model.
+
+use crate::generation::get_bounding_box;
+use crate::geotiff::GeoRaster;
+use nalgebra::{Isometry3, Point2, Point3};
+use num::ToPrimitive;
+use point_cloud_client::PointCloudClient;
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::geometry::{Aabb, Obb};
+use point_viewer::iterator::{PointLocation, PointQuery};
+use point_viewer::match_1d_attr_data;
+use rayon::prelude::*;
+use std::io;
+use std::path::Path;
+
+/// The raster is computed in square tiles of this many cells, one query each.
+const TILE_SIZE_CELLS: usize = 256;
+
+/// The value of cells without data in the written GeoTIFF.
+pub const NO_DATA: f32 = -9999.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeightRasterKind {
+    /// The mean height of the ground points in each cell.
+    Terrain,
+    /// The given percentile of the heights of the points in each cell, minus the terrain height.
+    /// With a high percentile, e.g. 99, this is a canopy height model for forests.
+    HeightAboveGround { percentile: f64 },
+}
+
+/// Which points are on the ground, e.g. those with class 2 in the ASPRS classification.
+#[derive(Debug, Clone)]
+pub struct Ground {
+    pub attribute: String,
+    pub value: f64,
+}
+
+pub struct HeightRasterParameters {
+    pub point_cloud_client: PointCloudClient,
+    pub query_from_global: Option<Isometry3<f64>>,
+    pub cell_size_m: f64,
+    /// If None, the lowest point in each cell is taken as the ground.
+    pub ground: Option<Ground>,
+    pub kind: HeightRasterKind,
+}
+
+pub struct HeightRaster {
+    pub width: usize,
+    pub height: usize,
+    /// Row by row, starting with the northernmost, i.e. the one with the largest y. NaN for cells
+    /// without data, which includes cells without ground points.
+    pub values: Vec<f32>,
+    pub upper_left: Point2<f64>,
+    pub cell_size_m: f64,
+}
+
+impl HeightRaster {
+    pub fn write_geotiff(&self, path: impl AsRef<Path>, epsg: Option<u16>) -> io::Result<()> {
+        let values: Vec<f32> = self
+            .values
+            .iter()
+            .map(|v| if v.is_nan() { NO_DATA } else { *v })
+            .collect();
+        GeoRaster {
+            width: self.width as u32,
+            height: self.height as u32,
+            values: &values,
+            upper_left: self.upper_left,
+            cell_size_m: self.cell_size_m,
+            epsg,
+            no_data: NO_DATA,
+        }
+        .write(path)
+    }
+}
+
+#[derive(Default)]
+struct Cell {
+    heights: Vec<f64>,
+    ground_sum: f64,
+    num_ground: usize,
+}
+
+impl Cell {
+    fn ground(&self, ground_from_attribute: bool) -> Option<f64> {
+        if ground_from_attribute {
+            if self.num_ground == 0 {
+                return None;
+            }
+            return Some(self.ground_sum / self.num_ground as f64);
+        }
+        if self.heights.is_empty() {
+            return None;
+        }
+        Some(
+            self.heights
+                .iter()
+                .cloned()
+                .fold(std::f64::INFINITY, f64::min),
+        )
+    }
+
+    fn value(&mut self, kind: HeightRasterKind, ground_from_attribute: bool) -> Option<f64> {
+        let ground = self.ground(ground_from_attribute)?;
+        match kind {
+            HeightRasterKind::Terrain => Some(ground),
+            HeightRasterKind::HeightAboveGround { percentile } => {
+                self.heights.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let index = (percentile / 100. * (self.heights.len() - 1) as f64).round() as usize;
+                Some((self.heights[index] - ground).max(0.))
+            }
+        }
+    }
+}
+
+fn to_f64<T: ToPrimitive>(data: &[T]) -> Vec<f64> {
+    data.iter()
+        .map(|v| v.to_f64().unwrap_or(std::f64::NAN))
+        .collect()
+}
+
+/// Computes the cells of the tile whose lower left cell is at ('x', 'y') in cells from the lower
+/// left corner of 'bbox'. Returns them row by row from the northernmost, or the error of the
+/// query.
+fn compute_tile(
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    bbox: &Aabb,
+    parameters: &HeightRasterParameters,
+) -> Result<Vec<f32>> {
+    let cell_size = parameters.cell_size_m;
+    let min = Point3::new(
+        bbox.min().x + x as f64 * cell_size,
+        bbox.min().y + y as f64 * cell_size,
+        bbox.min().z,
+    );
+    let max = Point3::new(
+        min.x + width as f64 * cell_size,
+        min.y + height as f64 * cell_size,
+        bbox.max().z,
+    );
+    let tile_bbox = Aabb::new(min, max);
+    let location = match &parameters.query_from_global {
+        Some(query_from_global) => {
+            let global_from_query = query_from_global.inverse();
+            PointLocation::Obb(Obb::from(&tile_bbox).transformed(&global_from_query))
+        }
+        None => PointLocation::Aabb(tile_bbox),
+    };
+    let point_query = PointQuery {
+        attributes: parameters
+            .ground
+            .iter()
+            .map(|ground| ground.attribute.as_str())
+            .collect(),
+        location,
+        ..Default::default()
+    };
+
+    let mut cells: Vec<Cell> = (0..width * height).map(|_| Cell::default()).collect();
+    parameters
+        .point_cloud_client
+        .for_each_point_data(&point_query, |points_batch| {
+            let ground_values = parameters.ground.as_ref().map(|ground| {
+                macro_rules! rhs {
+                    ($dtype:ident, $data:ident) => {
+                        to_f64($data)
+                    };
+                }
+                match_1d_attr_data!(&points_batch.attributes[&ground.attribute], rhs)
+            });
+            for (index, position) in points_batch.position.iter().enumerate() {
+                let p = match &parameters.query_from_global {
+                    Some(query_from_global) => query_from_global.transform_point(position),
+                    None => *position,
+                };
+                let cell_x = ((p.x - min.x) / cell_size).floor();
+                let cell_y = ((p.y - min.y) / cell_size).floor();
+                if cell_x < 0. || cell_y < 0. || cell_x >= width as f64 || cell_y >= height as f64 {
+                    continue;
+                }
+                let cell = &mut cells[(height - 1 - cell_y as usize) * width + cell_x as usize];
+                cell.heights.push(p.z);
+                if let (Some(ground), Some(values)) = (&parameters.ground, &ground_values) {
+                    if values[index] == ground.value {
+                        cell.ground_sum += p.z;
+                        cell.num_ground += 1;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+    let ground_from_attribute = parameters.ground.is_some();
+    Ok(cells
+        .iter_mut()
+        .map(|cell| {
+            cell.value(parameters.kind, ground_from_attribute)
+                .map_or(std::f32::NAN, |v| v as f32)
+        })
+        .collect())
+}
+
+pub fn build_height_raster(parameters: &HeightRasterParameters) -> Result<HeightRaster> {
+    if let Some(ground) = &parameters.ground {
+        let data_types = parameters
+            .point_cloud_client
+            .stored_attribute_types(&ground.attribute)?;
+        for data_type in data_types.into_iter().flatten() {
+            if data_type.dim() != 1 {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The ground attribute '{}' must have a single value per point, not {:?}.",
+                    ground.attribute, data_type
+                ))
+                .into());
+            }
+        }
+    }
+    let bbox = get_bounding_box(
+        &parameters.point_cloud_client.bounding_box(),
+        &parameters.query_from_global,
+    );
+    let cell_size = parameters.cell_size_m;
+    let diag = bbox.diag();
+    let width = ((diag.x / cell_size).ceil() as usize).max(1);
+    let height = ((diag.y / cell_size).ceil() as usize).max(1);
+
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE_CELLS) {
+        for x in (0..width).step_by(TILE_SIZE_CELLS) {
+            tiles.push((x, y));
+        }
+    }
+    let computed_tiles: Vec<_> = tiles
+        .into_par_iter()
+        .map(|(x, y)| {
+            let tile_width = TILE_SIZE_CELLS.min(width - x);
+            let tile_height = TILE_SIZE_CELLS.min(height - y);
+            let values = compute_tile(x, y, tile_width, tile_height, &bbox, parameters)?;
+            Ok((x, y, tile_width, tile_height, values))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut values = vec![std::f32::NAN; width * height];
+    for (x, y, tile_width, tile_height, tile_values) in computed_tiles {
+        for (row, tile_row) in tile_values.chunks_exact(tile_width).enumerate() {
+            // Rows count from the north, tiles from the south.
+            let raster_row = height - y - tile_height + row;
+            let start = raster_row * width + x;
+            values[start..start + tile_width].copy_from_slice(tile_row);
+        }
+    }
+
+    Ok(HeightRaster {
+        width,
+        height,
+        values,
+        upper_left: Point2::new(bbox.min().x, bbox.min().y + height as f64 * cell_size),
+        cell_size_m: cell_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_heights() {
+        let mut cell = Cell {
+            heights: vec![12., 10., 30., 11., 20.],
+            ground_sum: 21.,
+            num_ground: 2,
+        };
+        assert_eq!(cell.value(HeightRasterKind::Terrain, true), Some(10.5));
+        assert_eq!(cell.value(HeightRasterKind::Terrain, false), Some(10.));
+        let canopy = HeightRasterKind::HeightAboveGround { percentile: 75. };
+        assert_eq!(cell.value(canopy, true), Some(9.5));
+        assert_eq!(Cell::default().value(canopy, false), None);
+    }
+}
@@ -146,6 +146,31 @@ impl Meta {
             })
     }
 
+    /// The deepest node containing 'point', which has the most detailed image of it, or None if
+    /// the point is outside of the quadtree.
+    pub fn deepest_node_containing(&self, point: &Point2<f64>) -> Option<Node> {
+        let contains = |node: &Node| {
+            let (min, max) = (node.bounding_rect.min(), node.bounding_rect.max());
+            self.nodes.contains(&node.id)
+                && min.x <= point.x
+                && point.x <= max.x
+                && min.y <= point.y
+                && point.y <= max.y
+        };
+        let mut node =
+            Node::from_node_id_and_root_bounding_rect(NodeId::root(), self.bounding_rect.clone());
+        if !contains(&node) {
+            return None;
+        }
+        while let Some(child) = (0..4)
+            .map(|i| node.get_child(&ChildIndex::from_u8(i)))
+            .find(|child| contains(child))
+        {
+            node = child;
+        }
+        Some(node)
+    }
+
     pub fn iter_level(&self, level: u8) -> impl Iterator<Item = NodeId> + '_ {
         self.nodes
             .iter()
@@ -153,6 +178,17 @@ impl Meta {
             .filter(move |node_id| node_id.level() == level)
     }
 
+    /// The number of nodes on each level up to 'deepest_level', starting with the root.
+    pub fn num_nodes_per_level(&self) -> Vec<usize> {
+        let mut counts = vec![0; usize::from(self.deepest_level) + 1];
+        for node_id in &self.nodes {
+            if let Some(count) = counts.get_mut(usize::from(node_id.level())) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
     pub fn get_nodes_for_level(
         &self,
         level: u8,
@@ -223,8 +259,63 @@ impl Meta {
 pub mod backend;
 pub mod build_quadtree;
 pub mod colormap;
+pub mod footprints;
 pub mod generation;
+pub mod geotiff;
+pub mod height_raster;
 pub mod inpaint;
+pub mod recolor;
 pub mod utils;
 
 pub use xray_proto_rust::proto;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn golden_meta(version: i32) -> Meta {
+        Meta::from_disk(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata/golden")
+                .join(format!("xray_v{}", version))
+                .join(META_FILENAME),
+        )
+        .unwrap()
+    }
+
+    fn assert_meta_eq(a: &Meta, b: &Meta) {
+        assert_eq!(a.nodes, b.nodes);
+        assert_eq!(a.bounding_rect.min(), b.bounding_rect.min());
+        assert_eq!(a.bounding_rect.edge_length(), b.bounding_rect.edge_length());
+        assert_eq!(a.tile_size, b.tile_size);
+        assert_eq!(a.deepest_level, b.deepest_level);
+    }
+
+    #[test]
+    fn test_golden_metas() {
+        let current = golden_meta(CURRENT_VERSION);
+        assert_eq!(current.bounding_rect.min(), Point2::new(-1., -2.));
+        assert_eq!(current.bounding_rect.edge_length(), 4.);
+        assert_eq!(current.nodes.len(), 3);
+        assert_meta_eq(&golden_meta(2), &current);
+        assert_meta_eq(&Meta::from_proto(&current.to_proto()), &current);
+    }
+
+    #[test]
+    fn test_deepest_node_containing() {
+        let child = NodeId::root().get_child_id(&ChildIndex::from_u8(3));
+        let meta = Meta {
+            nodes: vec![NodeId::root(), child].into_iter().collect(),
+            bounding_rect: Rect::new(Point2::new(0., 0.), 4.),
+            tile_size: 256,
+            deepest_level: 1,
+        };
+        let deepest = |x, y| {
+            meta.deepest_node_containing(&Point2::new(x, y))
+                .map(|node| node.id)
+        };
+        assert_eq!(deepest(3., 3.), Some(child));
+        assert_eq!(deepest(1., 3.), Some(NodeId::root()));
+        assert_eq!(deepest(5., 3.), None);
+    }
+}
@@ -0,0 +1,192 @@
+//! Colors the points of an octree by the X-Ray tiles above them, e.g. to give lidar point clouds
+//! without colors a consistent, orthophoto-like look.
+
+use crate::utils::{get_image_path, image_from_path};
+use crate::{Meta, META_FILENAME};
+use fnv::FnvHashMap;
+use image::RgbaImage;
+use nalgebra::{Isometry3, Point2};
+use point_viewer::attribute_extension;
+use point_viewer::attributes::AttributeDataType;
+use point_viewer::data_provider::OnDiskDataProvider;
+use point_viewer::errors::*;
+use point_viewer::iterator::PointCloud;
+use point_viewer::octree::Octree;
+use point_viewer::proto;
+use point_viewer::read_write::{
+    split_layout_header, write_layout_header, NodeLayout, LAYOUT_HEADER_LEN,
+};
+use point_viewer::utils::create_progress_bar;
+use quadtree::NodeId;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many decoded tiles are kept in memory at most.
+const MAX_CACHED_TILES: usize = 256;
+
+/// The color of points that are not below any X-Ray pixel and had no color before.
+const NO_DATA_COLOR: [u8; 3] = [128, 128, 128];
+
+/// Looks up the color of the X-Ray pixel above points.
+pub struct XrayColorSampler {
+    directory: PathBuf,
+    meta: Meta,
+    /// None for tiles that could not be read.
+    tiles: FnvHashMap<NodeId, Option<RgbaImage>>,
+}
+
+impl XrayColorSampler {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        let meta = Meta::from_disk(directory.join(META_FILENAME))?;
+        Ok(XrayColorSampler {
+            directory,
+            meta,
+            tiles: FnvHashMap::default(),
+        })
+    }
+
+    /// The color of the pixel of the most detailed tile above 'point', or None if there is no
+    /// tile or the pixel is transparent.
+    pub fn color_at(&mut self, point: &Point2<f64>) -> Option<[u8; 3]> {
+        let node = self.meta.deepest_node_containing(point)?;
+        if self.tiles.len() >= MAX_CACHED_TILES && !self.tiles.contains_key(&node.id) {
+            self.tiles.clear();
+        }
+        let directory = &self.directory;
+        let tile = self
+            .tiles
+            .entry(node.id)
+            .or_insert_with(|| image_from_path(&get_image_path(directory, node.id))?.ok())
+            .as_ref()?;
+        let rect = &node.bounding_rect;
+        let (width, height) = tile.dimensions();
+        // Image rows start in the north.
+        let u = (point.x - rect.min().x) / rect.edge_length();
+        let v = (rect.max().y - point.y) / rect.edge_length();
+        let x = ((u * f64::from(width)) as u32).min(width - 1);
+        let y = ((v * f64::from(height)) as u32).min(height - 1);
+        let pixel = tile.get_pixel(x, y);
+        if pixel[3] == 0 {
+            return None;
+        }
+        Some([pixel[0], pixel[1], pixel[2]])
+    }
+}
+
+/// Writes the colors that 'sampler' finds above the points of the octree in 'octree_directory'
+/// as the new U8Vec3 attribute 'attribute' and adds it to the meta. The original colors are kept,
+/// so 'attribute' must not be "color"; the viewers can show the new one under the alias "color".
+/// The X-Ray must have been built with the same 'query_from_global' as is given here. Points
+/// outside of the X-Ray keep their color, or become gray if they had none. Returns the number of
+/// points that were colored from the X-Ray.
+pub fn recolor_octree(
+    octree_directory: &Path,
+    sampler: &mut XrayColorSampler,
+    attribute: &str,
+    query_from_global: Option<&Isometry3<f64>>,
+) -> Result<usize> {
+    if attribute == "color" || attribute == "position" {
+        return Err(ErrorKind::InvalidInput(format!(
+            "Cannot write the X-Ray colors as '{}', it would overwrite the original data.",
+            attribute
+        ))
+        .into());
+    }
+    let data_provider = OnDiskDataProvider {
+        directory: octree_directory.to_path_buf(),
+    };
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: octree_directory.to_path_buf(),
+    }))?;
+    let mut attributes = octree
+        .to_meta_proto()
+        .get_octree()
+        .get_attributes()
+        .to_vec();
+    match attributes.iter().find(|a| a.name == attribute) {
+        Some(existing) if existing.data_type != AttributeDataType::U8Vec3.to_proto() => {
+            return Err(ErrorKind::InvalidInput(format!(
+                "The octree already has an attribute '{}' that is not a color.",
+                attribute
+            ))
+            .into());
+        }
+        Some(_) => (),
+        None => {
+            let mut proto = proto::Attribute::new();
+            proto.set_name(attribute.to_string());
+            proto.set_data_type(AttributeDataType::U8Vec3.to_proto());
+            attributes.push(proto);
+            attributes.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+    }
+    if octree
+        .interleaved_attributes()
+        .iter()
+        .any(|a| a == attribute)
+    {
+        return Err(ErrorKind::InvalidInput(format!(
+            "The attribute '{}' of the octree is interleaved.",
+            attribute
+        ))
+        .into());
+    }
+    if octree.interleaved_attributes().iter().any(|a| a == "color") {
+        return Err(ErrorKind::InvalidInput(
+            "The colors of the octree are interleaved. Run `interleave_octree --separate` on it \
+             first."
+                .to_string(),
+        )
+        .into());
+    }
+    let node_ids = octree.node_ids_up_to_level(u8::MAX);
+    let mut progress_bar = create_progress_bar(node_ids.len(), "Recoloring nodes");
+    let mut num_colored = 0;
+    for node_id in node_ids {
+        let node_path = octree_directory.join(node_id.to_string());
+        let color_path = node_path.with_extension(attribute_extension("color"));
+        let output_path = node_path.with_extension(attribute_extension(attribute));
+        let old_colors = if color_path.exists() {
+            let data = fs::read(&color_path)?;
+            Some(split_layout_header(&data)?.1.to_vec())
+        } else {
+            None
+        };
+        let mut colors = Vec::new();
+        write_layout_header(
+            &mut colors,
+            &NodeLayout::Attribute(AttributeDataType::U8Vec3),
+        )?;
+        for batch in octree.points_in_node(&[], node_id, point_viewer::NUM_POINTS_PER_BATCH)? {
+            for position in &batch.position {
+                let position = match query_from_global {
+                    Some(query_from_global) => query_from_global * position,
+                    None => *position,
+                };
+                let index = colors.len() - LAYOUT_HEADER_LEN;
+                match sampler.color_at(&Point2::new(position.x, position.y)) {
+                    Some(color) => {
+                        colors.extend_from_slice(&color);
+                        num_colored += 1;
+                    }
+                    None => match &old_colors {
+                        Some(old_colors) if old_colors.len() >= index + 3 => {
+                            colors.extend_from_slice(&old_colors[index..index + 3])
+                        }
+                        _ => colors.extend_from_slice(&NO_DATA_COLOR),
+                    },
+                }
+            }
+        }
+        fs::write(&output_path, &colors)
+            .chain_err(|| format!("Could not write {}.", output_path.display()))?;
+        progress_bar.inc();
+    }
+    progress_bar.finish();
+    data_provider.update_meta_proto(|meta| {
+        meta.mut_octree()
+            .set_attributes(::protobuf::RepeatedField::from_vec(attributes))
+    })?;
+    Ok(num_colored)
+}